@@ -0,0 +1,81 @@
+//! Latency of small reads and writes while other clients scan a large value.
+//!
+//! ```shell
+//! cargo bench -p codecrafters-redis --bench mixed_workload
+//! ```
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use codecrafters_redis::ServerBuilder;
+use tokio::sync::watch;
+
+/// Count of elements in the large list.
+const LIST_LEN: usize = 1_000_000;
+
+/// Count of clients running LRANGE on the large list.
+const SCANNERS: usize = 2;
+
+/// Count of SET and GET pairs on small keys.
+const OPS: usize = 2_000;
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    sorted[((sorted.len() - 1) as f64 * p) as usize]
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() {
+    let handle = ServerBuilder::new().port(0).start().await.unwrap();
+    let client = handle.client();
+    for chunk in (0..LIST_LEN).collect::<Vec<_>>().chunks(10_000) {
+        let values = chunk.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        client.rpush("big", values).await.unwrap();
+    }
+
+    let mut scanners = vec![];
+    let scans = Arc::new(AtomicUsize::new(0));
+    let (stop, stopped) = watch::channel(false);
+    for _ in 0..SCANNERS {
+        let client = handle.client();
+        let scans = scans.clone();
+        let mut stopped = stopped.clone();
+        scanners.push(tokio::spawn(async move {
+            while !*stopped.borrow_and_update() {
+                client.lrange("big", 0, -1).await.unwrap();
+                scans.fetch_add(1, Ordering::Relaxed);
+            }
+        }));
+    }
+
+    let start = Instant::now();
+    let mut latencies = Vec::with_capacity(OPS);
+    for i in 0..OPS {
+        let t = Instant::now();
+        client.set(format!("k{}", i % 100), "v").await.unwrap();
+        client.get(format!("k{}", i % 100)).await.unwrap();
+        latencies.push(t.elapsed());
+    }
+    let elapsed = start.elapsed();
+    let _ = stop.send(true);
+    for s in scanners {
+        s.await.unwrap();
+    }
+
+    latencies.sort();
+    println!(
+        "{OPS} SET+GET with {SCANNERS} clients scanning a {LIST_LEN} elements list in {elapsed:?}"
+    );
+    println!(
+        "p50 {:?} p99 {:?} max {:?}, {} full scans",
+        percentile(&latencies, 0.5),
+        percentile(&latencies, 0.99),
+        latencies.last().unwrap(),
+        scans.load(Ordering::Relaxed),
+    );
+    handle.shutdown().await;
+}
@@ -0,0 +1,76 @@
+//! Throughput of pipelined SET and GET from many clients, each on its own keys.
+//!
+//! ```shell
+//! cargo bench -p codecrafters-redis --bench pipelined_load
+//! ```
+
+use std::time::Instant;
+
+use codecrafters_redis::ServerBuilder;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// Count of clients sending commands at the same time.
+const CLIENTS: usize = 16;
+
+/// Count of SET and GET pairs sent in one write.
+const PIPELINE: usize = 50;
+
+/// Count of pipelined writes by each client.
+const BATCHES: usize = 400;
+
+/// Count of distinct keys of each client.
+const KEYS: usize = 1_000;
+
+fn command(parts: &[&str]) -> Vec<u8> {
+    let mut data = format!("*{}\r\n", parts.len()).into_bytes();
+    for part in parts {
+        data.extend(format!("${}\r\n{part}\r\n", part.len()).into_bytes());
+    }
+    data
+}
+
+async fn run_client(mut stream: TcpStream, client: usize) {
+    let reply_len = b"+OK\r\n".len() + b"$5\r\nvalue\r\n".len();
+    let mut buf = vec![0u8; PIPELINE * reply_len];
+    for batch in 0..BATCHES {
+        let mut data = vec![];
+        for i in 0..PIPELINE {
+            let key = format!("key:{client}:{}", (batch * PIPELINE + i) % KEYS);
+            data.extend(command(&["SET", &key, "value"]));
+            data.extend(command(&["GET", &key]));
+        }
+        stream.write_all(&data).await.unwrap();
+        stream.read_exact(&mut buf).await.unwrap();
+    }
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() {
+    let handle = ServerBuilder::new().port(0).start().await.unwrap();
+    let addr = handle.local_addr();
+
+    let start = Instant::now();
+    let mut clients = vec![];
+    for client in 0..CLIENTS {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        clients.push(tokio::spawn(run_client(stream, client)));
+    }
+    for client in clients {
+        client.await.unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    let commands = CLIENTS * BATCHES * PIPELINE * 2;
+    println!(
+        "{commands} commands from {CLIENTS} clients pipelining {} at a time in {elapsed:?}",
+        PIPELINE * 2
+    );
+    println!(
+        "{:.0} commands per second",
+        commands as f64 / elapsed.as_secs_f64()
+    );
+    handle.shutdown().await;
+}
@@ -0,0 +1,569 @@
+//! Access control lists, set by ACL SETUSER.
+//!
+//! Each user has passwords, and rules on commands, keys and channels it can access.
+//! Connections run commands as the user they authenticated as, the "default" user if
+//! not authenticated, see `Conn::user`.
+//!
+//! Like redis, passwords are stored as SHA-256 hashes in hex, and command rules are
+//! applied in order, so later rules override earlier ones: `+@all -debug` allows all
+//! commands except DEBUG.
+//!
+//! Users can be saved to and loaded from the aclfile, one user per line in the same
+//! format as ACL LIST:
+//!
+//! ```text
+//! user default on nopass ~* &* +@all
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    command::{command_categories, is_known_command, CATEGORIES},
+    glob::glob_match,
+};
+
+/// Name of the user connections authenticate as by default.
+pub(crate) const DEFAULT_USER: &str = "default";
+
+/// A user in ACL.
+///
+/// New users created by ACL SETUSER are disabled and can do nothing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct User {
+    /// Whether the user can authenticate.
+    enabled: bool,
+
+    /// Accept any password.
+    nopass: bool,
+
+    /// SHA-256 hashes of passwords in lowercase hex.
+    passwords: Vec<String>,
+
+    /// Command rules in order, allowed or not, on command names in lowercase or
+    /// categories starting with "@".
+    commands: Vec<(bool, String)>,
+
+    /// Glob patterns of accessible keys.
+    keys: Vec<String>,
+
+    /// Glob patterns of accessible channels.
+    channels: Vec<String>,
+}
+
+impl User {
+    /// The default user, which can do everything without password.
+    fn new_default() -> Self {
+        Self {
+            enabled: true,
+            nopass: true,
+            passwords: vec![],
+            commands: vec![(true, "@all".to_string())],
+            keys: vec!["*".to_string()],
+            channels: vec!["*".to_string()],
+        }
+    }
+
+    /// Apply `rule` in ACL SETUSER.
+    ///
+    /// Return the error message without prefix if the rule is invalid.
+    fn apply_rule(&mut self, rule: &str) -> Result<(), String> {
+        let syntax_error = || format!("Error in ACL SETUSER modifier '{rule}': Syntax error");
+        match rule.to_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.keys = vec!["*".to_string()],
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => self.channels = vec!["*".to_string()],
+            "resetchannels" => self.channels.clear(),
+            "allcommands" => self.commands = vec![(true, "@all".to_string())],
+            "nocommands" => self.commands.clear(),
+            "reset" => *self = Self::default(),
+            _ => {
+                let (prefix, value) = rule.split_at(rule.chars().next().map_or(0, char::len_utf8));
+                match prefix {
+                    ">" => {
+                        let hash = sha256_hex(value.as_bytes());
+                        self.nopass = false;
+                        if !self.passwords.contains(&hash) {
+                            self.passwords.push(hash);
+                        }
+                    }
+                    "<" => {
+                        let hash = sha256_hex(value.as_bytes());
+                        if !self.passwords.contains(&hash) {
+                            return Err(format!(
+                                "Error in ACL SETUSER modifier '{rule}': no such password"
+                            ));
+                        }
+                        self.passwords.retain(|x| x != &hash);
+                    }
+                    "#" => {
+                        let hash = value.to_lowercase();
+                        if hash.len() != 64 || !hash.bytes().all(|x| x.is_ascii_hexdigit()) {
+                            return Err(format!("Error in ACL SETUSER modifier '{rule}': The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters"));
+                        }
+                        self.nopass = false;
+                        if !self.passwords.contains(&hash) {
+                            self.passwords.push(hash);
+                        }
+                    }
+                    "!" => {
+                        let hash = value.to_lowercase();
+                        if !self.passwords.contains(&hash) {
+                            return Err(format!(
+                                "Error in ACL SETUSER modifier '{rule}': no such password"
+                            ));
+                        }
+                        self.passwords.retain(|x| x != &hash);
+                    }
+                    "~" => {
+                        if !self.keys.iter().any(|x| x == "*") {
+                            self.keys.push(value.to_string());
+                        }
+                    }
+                    "&" => {
+                        if !self.channels.iter().any(|x| x == "*") {
+                            self.channels.push(value.to_string());
+                        }
+                    }
+                    "+" | "-" => {
+                        let allow = prefix == "+";
+                        let value = value.to_lowercase();
+                        if let Some(category) = value.strip_prefix('@') {
+                            if category != "all" && !CATEGORIES.contains(&category) {
+                                return Err(format!("Error in ACL SETUSER modifier '{rule}': Unknown command or category name in ACL"));
+                            }
+                            if category == "all" {
+                                self.commands.clear();
+                            }
+                        } else if !is_known_command(&value.to_uppercase()) {
+                            return Err(format!("Error in ACL SETUSER modifier '{rule}': Unknown command or category name in ACL"));
+                        }
+                        self.commands.retain(|(_, x)| x != &value);
+                        self.commands.push((allow, value));
+                    }
+                    _ => return Err(syntax_error()),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether `password` is accepted.
+    fn check_password(&self, password: &str) -> bool {
+        self.nopass || self.passwords.contains(&sha256_hex(password.as_bytes()))
+    }
+
+    /// Check whether the user can run command `cmd` in uppercase.
+    pub(crate) fn allows_command(&self, cmd: &str) -> bool {
+        let name = cmd.to_lowercase();
+        let categories = command_categories(cmd);
+        self.commands
+            .iter()
+            .rev()
+            .find(|(_, rule)| match rule.strip_prefix('@') {
+                Some("all") => true,
+                Some(category) => categories.contains(&category),
+                None => rule == &name,
+            })
+            .is_some_and(|(allow, _)| *allow)
+    }
+
+    /// Check whether the user can access `key`.
+    pub(crate) fn allows_key(&self, key: &[u8]) -> bool {
+        self.keys
+            .iter()
+            .any(|x| glob_match(x.as_bytes(), key, false))
+    }
+
+    /// Check whether the user can access `channel`.
+    ///
+    /// Patterns in PSUBSCRIBE are checked literally as redis does, `pattern` shall be
+    /// one of the allowed patterns.
+    pub(crate) fn allows_channel(&self, channel: &str, pattern: bool) -> bool {
+        self.channels.iter().any(|x| {
+            x == "*"
+                || x == channel
+                || (!pattern && glob_match(x.as_bytes(), channel.as_bytes(), false))
+        })
+    }
+
+    fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        flags
+    }
+
+    /// Command rules in ACL LIST format.
+    fn describe_commands(&self) -> String {
+        let mut rules = self
+            .commands
+            .iter()
+            .map(|(allow, rule)| format!("{}{}", if *allow { '+' } else { '-' }, rule))
+            .collect::<Vec<_>>();
+        // Commands are denied by default.
+        if !matches!(self.commands.first(), Some((_, x)) if x == "@all") {
+            rules.insert(0, "-@all".to_string());
+        }
+        rules.join(" ")
+    }
+
+    /// Rules of the user in ACL LIST format.
+    fn describe(&self) -> String {
+        let mut rules = self
+            .flags()
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        rules.extend(self.passwords.iter().map(|x| format!("#{x}")));
+        if self.keys.is_empty() {
+            rules.push("resetkeys".to_string());
+        } else {
+            rules.extend(self.keys.iter().map(|x| format!("~{x}")));
+        }
+        if self.channels.is_empty() {
+            rules.push("resetchannels".to_string());
+        } else {
+            rules.extend(self.channels.iter().map(|x| format!("&{x}")));
+        }
+        rules.push(self.describe_commands());
+        rules.join(" ")
+    }
+}
+
+/// Fields of a user in ACL GETUSER.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct UserInfo {
+    pub(crate) flags: Vec<&'static str>,
+    pub(crate) passwords: Vec<String>,
+    pub(crate) commands: String,
+    pub(crate) keys: String,
+    pub(crate) channels: String,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct AclState {
+    users: Arc<Mutex<BTreeMap<String, User>>>,
+}
+
+impl AclState {
+    pub(crate) fn new() -> Self {
+        let users = BTreeMap::from([(DEFAULT_USER.to_string(), User::new_default())]);
+        Self {
+            users: Arc::new(Mutex::new(users)),
+        }
+    }
+
+    /// Create user `name` if not exist, then apply `rules` in order.
+    ///
+    /// Rules are applied all or nothing, return the error message without prefix if
+    /// any rule is invalid.
+    pub(crate) fn set_user(&self, name: &str, rules: &[String]) -> Result<(), String> {
+        let mut lock = self.users.lock().unwrap();
+        let mut user = lock.get(name).cloned().unwrap_or_default();
+        for rule in rules {
+            user.apply_rule(rule)?;
+        }
+        lock.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    /// Set the only password of the default user to `password`, empty for nopass.
+    ///
+    /// Called when `requirepass` is set.
+    pub(crate) fn set_default_password(&self, password: &str) {
+        let rule = if password.is_empty() {
+            "nopass".to_string()
+        } else {
+            format!(">{password}")
+        };
+        let mut lock = self.users.lock().unwrap();
+        let user = lock.entry(DEFAULT_USER.to_string()).or_default();
+        user.apply_rule("resetpass").unwrap();
+        user.apply_rule(&rule).unwrap();
+    }
+
+    /// Delete users in `names` except the default user.
+    ///
+    /// Return the count of users deleted, or an error if deleting the default user.
+    pub(crate) fn delete_users(&self, names: &[String]) -> Result<usize, String> {
+        if names.iter().any(|x| x == DEFAULT_USER) {
+            return Err("The 'default' user cannot be removed".to_string());
+        }
+        let mut lock = self.users.lock().unwrap();
+        Ok(names.iter().filter(|x| lock.remove(*x).is_some()).count())
+    }
+
+    /// Get a copy of user `name`.
+    pub(crate) fn user(&self, name: &str) -> Option<User> {
+        self.users.lock().unwrap().get(name).cloned()
+    }
+
+    /// Get the fields of user `name` in ACL GETUSER.
+    pub(crate) fn user_info(&self, name: &str) -> Option<UserInfo> {
+        let lock = self.users.lock().unwrap();
+        let user = lock.get(name)?;
+        Some(UserInfo {
+            flags: user.flags(),
+            passwords: user.passwords.clone(),
+            commands: user.describe_commands(),
+            keys: user
+                .keys
+                .iter()
+                .map(|x| format!("~{x}"))
+                .collect::<Vec<_>>()
+                .join(" "),
+            channels: user
+                .channels
+                .iter()
+                .map(|x| format!("&{x}"))
+                .collect::<Vec<_>>()
+                .join(" "),
+        })
+    }
+
+    /// Names of all users in order.
+    pub(crate) fn usernames(&self) -> Vec<String> {
+        self.users.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// All users in ACL LIST format, also the content of aclfile.
+    pub(crate) fn list(&self) -> Vec<String> {
+        self.users
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, user)| format!("user {name} {}", user.describe()))
+            .collect()
+    }
+
+    /// Check whether `password` is correct for user `name`, and the user is enabled.
+    pub(crate) fn authenticate(&self, name: &str, password: &str) -> bool {
+        self.users
+            .lock()
+            .unwrap()
+            .get(name)
+            .is_some_and(|x| x.enabled && x.check_password(password))
+    }
+
+    /// Check whether new connections are authenticated as the default user without
+    /// AUTH, as the default user needs no password.
+    pub(crate) fn is_default_open(&self) -> bool {
+        self.users
+            .lock()
+            .unwrap()
+            .get(DEFAULT_USER)
+            .is_some_and(|x| x.enabled && x.nopass)
+    }
+
+    /// Check whether the default user needs no password, regardless of enabled.
+    pub(crate) fn is_default_nopass(&self) -> bool {
+        self.users
+            .lock()
+            .unwrap()
+            .get(DEFAULT_USER)
+            .is_some_and(|x| x.nopass)
+    }
+
+    /// Replace all users with those in aclfile `text`.
+    ///
+    /// The default user is created if not in the file. Return the error message
+    /// without prefix if any line is invalid, nothing is changed then.
+    pub(crate) fn load(&self, text: &str) -> Result<(), String> {
+        let mut users = BTreeMap::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut args = line.split_whitespace();
+            let (Some("user"), Some(name)) = (args.next(), args.next()) else {
+                return Err(format!(
+                    "/{}: line should start with user keyword",
+                    index + 1
+                ));
+            };
+            let mut user = User::default();
+            for rule in args {
+                user.apply_rule(rule)
+                    .map_err(|e| format!("/{}: {e}", index + 1))?;
+            }
+            users.insert(name.to_string(), user);
+        }
+        users
+            .entry(DEFAULT_USER.to_string())
+            .or_insert_with(User::new_default);
+        *self.users.lock().unwrap() = users;
+        Ok(())
+    }
+}
+
+/// SHA-256 digest of `data` in lowercase hex.
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    // Pad with 0x80, zeros and the bit length to a multiple of 64 bytes.
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (x, y) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *x = x.wrapping_add(y);
+        }
+    }
+
+    h.iter().map(|x| format!("{x:08x}")).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rules(rules: &str) -> Vec<String> {
+        rules.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // Two chunks after padding.
+        assert_eq!(
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn test_rules() {
+        let acl = AclState::new();
+        acl.set_user(
+            "alice",
+            &rules("on >pass ~cache:* &news.* +@read -type +set"),
+        )
+        .unwrap();
+        let user = acl.user("alice").unwrap();
+        assert!(user.allows_command("GET"));
+        assert!(user.allows_command("SET"));
+        assert!(!user.allows_command("TYPE"));
+        assert!(!user.allows_command("DEL"));
+        assert!(user.allows_key(b"cache:1"));
+        assert!(!user.allows_key(b"other"));
+        assert!(user.allows_channel("news.tech", false));
+        assert!(user.allows_channel("news.*", true));
+        assert!(!user.allows_channel("news.t*", true));
+        assert!(acl.authenticate("alice", "pass"));
+        assert!(!acl.authenticate("alice", "wrong"));
+
+        // Applied all or nothing.
+        assert!(acl.set_user("alice", &rules("off +foo")).is_err());
+        assert!(acl.authenticate("alice", "pass"));
+
+        acl.set_user("alice", &rules("+@all -@write")).unwrap();
+        let user = acl.user("alice").unwrap();
+        assert!(user.allows_command("PING"));
+        assert!(!user.allows_command("SET"));
+
+        acl.set_user("alice", &rules("off")).unwrap();
+        assert!(!acl.authenticate("alice", "pass"));
+        assert_eq!(acl.delete_users(&rules("alice bob")), Ok(1));
+        assert!(acl.delete_users(&rules("default")).is_err());
+    }
+
+    #[test]
+    fn test_list_and_load() {
+        let acl = AclState::new();
+        acl.set_user("bob", &rules("on >secret ~k* +get")).unwrap();
+        let list = acl.list();
+        assert_eq!(
+            list,
+            vec![
+                format!(
+                    "user bob on #{} ~k* resetchannels -@all +get",
+                    sha256_hex(b"secret")
+                ),
+                "user default on nopass ~* &* +@all".to_string(),
+            ]
+        );
+
+        let other = AclState::new();
+        other.load(&list.join("\n")).unwrap();
+        assert_eq!(other.list(), list);
+        assert!(other.authenticate("bob", "secret"));
+
+        assert!(other.load("user carol on +foo").is_err());
+        assert!(other.load("carol on").is_err());
+        assert_eq!(other.list(), list);
+    }
+}
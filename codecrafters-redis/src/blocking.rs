@@ -0,0 +1,369 @@
+//! Connections blocked by commands like BLPOP, XREAD and WAIT.
+//!
+//! A blocked command ends when served or timed out, or when unblocked from outside:
+//! one connection by CLIENT UNBLOCK, or all connections of some kind at once when the
+//! state they wait on is gone, like the server shutting down or stream groups removed
+//! by FLUSHALL. Unblocked commands reply as timed out, or with an UNBLOCKED error.
+//! A client closing the connection while blocked ends the command as timed out.
+//!
+//! Commands blocked on keys also leave a task in [`BlockedTasks`], fed by writes on
+//! the keys. The task is removed once the command ends for any reason, so writes never
+//! feed a command that is gone.
+//!
+//! Writes feed the tasks by [`feed_in_order`] while still holding the storage, so
+//! tasks are served in the order they blocked, and all elements written in one command
+//! are handed out before any other command sees them.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use serde_redis::{SimpleError, Value};
+use tokio::sync::oneshot;
+
+use crate::conn::Conn;
+
+/// How a blocked command is ended from outside.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Unblock {
+    /// Reply as if the command timed out.
+    Timeout,
+
+    /// Reply the error, see `Unblock::error`.
+    Error(Value),
+}
+
+impl Unblock {
+    /// Reply an UNBLOCKED error with `reason`.
+    pub(crate) fn error(reason: &str) -> Self {
+        Self::Error(Value::SimpleError(SimpleError::with_prefix(
+            "UNBLOCKED",
+            reason,
+        )))
+    }
+}
+
+/// What a blocked connection is waiting for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BlockKind {
+    /// Keys to have elements: lists, sorted sets and streams.
+    Keys,
+
+    /// New records delivered to a consumer group, XREADGROUP.
+    StreamGroup,
+
+    /// Replicas to acknowledge, WAIT.
+    Replicas,
+}
+
+impl BlockKind {
+    pub(crate) const ALL: &[BlockKind] = &[Self::Keys, Self::StreamGroup, Self::Replicas];
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BlockingState {
+    /// Blocked connections by id.
+    blocked: Arc<Mutex<HashMap<usize, Blocked>>>,
+}
+
+#[derive(Debug)]
+struct Blocked {
+    kind: BlockKind,
+
+    /// Channel to unblock the connection.
+    sender: oneshot::Sender<Unblock>,
+}
+
+/// Remove the blocked connection when its command ends, even if the command is dropped
+/// while blocked.
+struct BlockGuard<'a> {
+    state: &'a BlockingState,
+    id: usize,
+}
+
+impl Drop for BlockGuard<'_> {
+    fn drop(&mut self) {
+        self.state.blocked.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl BlockingState {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Block connection `conn` waiting for `kind` on `fut`, till `fut` completes or
+    /// the connection is unblocked.
+    ///
+    /// Return `Err` with how the connection is unblocked, `Unblock::Timeout` if the
+    /// client closed the connection. Timeouts of the command shall be part of `fut`.
+    pub(crate) async fn block_on<F: Future>(
+        &self,
+        conn: &mut Conn<'_>,
+        kind: BlockKind,
+        fut: F,
+    ) -> Result<F::Output, Unblock> {
+        let id = conn.id;
+        let (sender, receiver) = oneshot::channel();
+        self.blocked
+            .lock()
+            .unwrap()
+            .insert(id, Blocked { kind, sender });
+        let _guard = BlockGuard { state: self, id };
+        // Unblocking wins over `fut` completed at the same time, as the state `fut`
+        // waits on may be dropped by whoever unblocks.
+        tokio::select! {
+            biased;
+            Ok(unblock) = receiver => Err(unblock),
+            v = fut => Ok(v),
+            _ = conn.closed() => {
+                conn.log("connection closed while blocked");
+                Err(Unblock::Timeout)
+            }
+        }
+    }
+
+    /// Check whether connection `id` is blocked.
+    pub(crate) fn is_blocked(&self, id: usize) -> bool {
+        self.blocked.lock().unwrap().contains_key(&id)
+    }
+
+    /// Count of connections blocked now.
+    pub(crate) fn count(&self) -> usize {
+        self.blocked.lock().unwrap().len()
+    }
+
+    /// Unblock connection `id`.
+    ///
+    /// Return false if the connection is not blocked.
+    pub(crate) fn unblock(&self, id: usize, unblock: Unblock) -> bool {
+        match self.blocked.lock().unwrap().remove(&id) {
+            Some(blocked) => blocked.sender.send(unblock).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Unblock all connections blocked for kinds in `kinds`, return the count of them.
+    pub(crate) fn unblock_all(&self, kinds: &[BlockKind], unblock: Unblock) -> usize {
+        self.blocked
+            .lock()
+            .unwrap()
+            .extract_if(|_, blocked| kinds.contains(&blocked.kind))
+            .map(|(_, blocked)| blocked.sender.send(unblock.clone()).is_ok())
+            .filter(|sent| *sent)
+            .count()
+    }
+}
+
+/// Tasks of commands blocked on keys, by connection id in the order they blocked.
+///
+/// Writes feed the tasks under the lock, see [`BlockedTasks::lock`].
+pub(crate) struct BlockedTasks<T> {
+    tasks: Arc<Mutex<Vec<(usize, T)>>>,
+}
+
+// Not derived, tasks themselves are not cloned.
+impl<T> Clone for BlockedTasks<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tasks: self.tasks.clone(),
+        }
+    }
+}
+
+impl<T> BlockedTasks<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            tasks: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Add `task` of connection `id`, replacing the one it left if any.
+    ///
+    /// The task is removed once the returned guard is dropped, hold it till the
+    /// command ends.
+    pub(crate) fn add(&self, id: usize, task: T) -> TaskGuard<T> {
+        let mut tasks = self.lock();
+        tasks.retain(|(x, _)| *x != id);
+        tasks.push((id, task));
+        TaskGuard {
+            tasks: self.clone(),
+            id,
+        }
+    }
+
+    /// Lock all tasks, with the id of connection each belongs to.
+    pub(crate) fn lock(&self) -> MutexGuard<'_, Vec<(usize, T)>> {
+        self.tasks.lock().unwrap()
+    }
+}
+
+/// What happened to a blocked task offered what it waits for, see [`feed_in_order`].
+pub(crate) enum Fed<T> {
+    /// The task is done and removed, served or gone.
+    Done,
+
+    /// The task is not served and kept waiting, later tasks are still fed.
+    Skipped(T),
+
+    /// Nothing left to feed, the task is kept waiting and no more tasks are fed.
+    Exhausted(T),
+}
+
+/// Feed `tasks` waiting on something by `feed`, in the order they blocked.
+///
+/// Only tasks that `waits` on it are fed, till `feed` says nothing is left.
+pub(crate) fn feed_in_order<T>(
+    tasks: &mut Vec<(usize, T)>,
+    waits: impl Fn(&T) -> bool,
+    mut feed: impl FnMut(T) -> Fed<T>,
+) {
+    let mut pos = 0;
+    while let Some(offset) = tasks[pos..].iter().position(|(_, task)| waits(task)) {
+        pos += offset;
+        let (id, task) = tasks.remove(pos);
+        match feed(task) {
+            Fed::Done => {}
+            Fed::Skipped(task) => {
+                tasks.insert(pos, (id, task));
+                pos += 1;
+            }
+            Fed::Exhausted(task) => {
+                tasks.insert(pos, (id, task));
+                break;
+            }
+        }
+    }
+}
+
+/// Wait for the value fed to a blocked task by `recver`, forever if `timeout` is `None`.
+///
+/// Return `None` if timed out, or the task is dropped without being fed.
+pub(crate) async fn wait_fed<T>(
+    recver: oneshot::Receiver<T>,
+    timeout: Option<Duration>,
+) -> Option<T> {
+    match timeout {
+        Some(d) => tokio::time::timeout(d, recver).await.ok()?.ok(),
+        None => recver.await.ok(),
+    }
+}
+
+/// Remove the task of a connection from [`BlockedTasks`] when dropped.
+pub(crate) struct TaskGuard<T> {
+    tasks: BlockedTasks<T>,
+    id: usize,
+}
+
+impl<T> Drop for TaskGuard<T> {
+    fn drop(&mut self) {
+        self.tasks.lock().retain(|(id, _)| *id != self.id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unblock() {
+        let state = BlockingState::new();
+        let mut conn = Conn::new_local(1);
+        let result = state
+            .block_on(&mut conn, BlockKind::Keys, async { 1 })
+            .await;
+        assert_eq!(result, Ok(1));
+        assert!(!state.unblock(1, Unblock::Timeout));
+
+        let blocked = |id: usize, kind: BlockKind| {
+            let state = state.clone();
+            tokio::spawn(async move {
+                let mut conn = Conn::new_local(id);
+                let fut = std::future::pending::<()>();
+                state.block_on(&mut conn, kind, fut).await
+            })
+        };
+        let t1 = blocked(1, BlockKind::Keys);
+        let t2 = blocked(2, BlockKind::StreamGroup);
+        let t3 = blocked(3, BlockKind::Replicas);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        assert!(state.unblock(1, Unblock::Timeout));
+        assert_eq!(t1.await.unwrap(), Err(Unblock::Timeout));
+        assert!(!state.unblock(1, Unblock::Timeout));
+
+        let error = Unblock::error("gone");
+        assert_eq!(
+            state.unblock_all(&[BlockKind::StreamGroup], error.clone()),
+            1
+        );
+        assert_eq!(t2.await.unwrap(), Err(error.clone()));
+        assert!(!t3.is_finished());
+        assert_eq!(state.unblock_all(BlockKind::ALL, error.clone()), 1);
+        assert_eq!(t3.await.unwrap(), Err(error));
+
+        // Dropped while blocked.
+        let t4 = blocked(4, BlockKind::Keys);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        t4.abort();
+        let _ = t4.await;
+        assert!(!state.unblock(4, Unblock::Timeout));
+    }
+
+    #[test]
+    fn test_blocked_tasks() {
+        let tasks = BlockedTasks::new();
+        let g1 = tasks.add(1, "a");
+        let g2 = tasks.add(2, "b");
+        assert_eq!(*tasks.lock(), vec![(1, "a"), (2, "b")]);
+
+        drop(g1);
+        assert_eq!(*tasks.lock(), vec![(2, "b")]);
+
+        // Fed and removed already.
+        tasks.lock().clear();
+        drop(g2);
+        assert!(tasks.lock().is_empty());
+    }
+
+    #[test]
+    fn test_feed_in_order() {
+        let mut tasks = vec![(1, "a"), (2, "b"), (3, "a"), (4, "c"), (5, "a"), (6, "a")];
+        let mut fed = vec![];
+        let mut left = 3;
+        feed_in_order(
+            &mut tasks,
+            |x| *x != "b",
+            |x| match x {
+                _ if left == 0 => Fed::Exhausted(x),
+                "c" => Fed::Skipped(x),
+                x => {
+                    fed.push(x);
+                    left -= 1;
+                    Fed::Done
+                }
+            },
+        );
+        assert_eq!(fed, vec!["a", "a", "a"]);
+        assert_eq!(tasks, vec![(2, "b"), (4, "c"), (6, "a")]);
+    }
+
+    #[tokio::test]
+    async fn test_wait_fed() {
+        let (sender, recver) = oneshot::channel();
+        sender.send(1).unwrap();
+        assert_eq!(wait_fed(recver, None).await, Some(1));
+
+        let (sender, recver) = oneshot::channel::<i32>();
+        let timeout = Some(Duration::from_millis(10));
+        assert_eq!(wait_fed(recver, timeout).await, None);
+        drop(sender);
+
+        let (sender, recver) = oneshot::channel::<i32>();
+        drop(sender);
+        assert_eq!(wait_fed(recver, None).await, None);
+    }
+}
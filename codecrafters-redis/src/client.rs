@@ -0,0 +1,274 @@
+use serde_redis::{Array, BulkString, Null, Value};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    command::{dispatch_command, DispatchResult},
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    replication::ReplicationState,
+    server::propagate,
+    state::ServerState,
+};
+
+/// A command sent by `LocalClient`, with the channel to send reply back.
+type LocalRequest = (Array, oneshot::Sender<ServerResult<Value>>);
+
+/// A client talking to the server in the same process.
+///
+/// Commands are submitted to the dispatcher directly via channels, no socket
+/// involved. Each client holds its own connection state, so commands like
+/// `MULTI` and `EXEC` work across calls as they do on a tcp connection.
+///
+/// Create one with `Handle::client`.
+pub struct LocalClient {
+    sender: mpsc::UnboundedSender<LocalRequest>,
+}
+
+impl LocalClient {
+    /// Spawn the task owning the connection and return the client talking to it.
+    pub(crate) fn spawn(id: usize, state: ServerState, rep: ReplicationState) -> Self {
+        let (sender, mut recver) = mpsc::unbounded_channel::<LocalRequest>();
+        tokio::spawn(async move {
+            let mut state = state;
+            let mut conn = Conn::new_local(id);
+            // In-process clients can not be killed, the receiver is dropped.
+            drop(state.clients().register(id, String::new(), String::new()));
+            conn.log("new local client");
+            while let Some((message, reply)) = recver.recv().await {
+                let result =
+                    match dispatch_command(&mut conn, message, &mut state, rep.clone()).await {
+                        Ok(DispatchResult::ReplicaSyncEffects(effects)) => {
+                            propagate(&rep, &state, id, effects);
+                            Ok(())
+                        }
+                        Ok(
+                            DispatchResult::None
+                            | DispatchResult::Replica
+                            | DispatchResult::Monitor
+                            | DispatchResult::ReplicaSync,
+                        ) => Ok(()),
+                        Err(e) => Err(e),
+                    };
+                let values = conn.take_values();
+                let _ = reply
+                    .send(result.map(|_| values.into_iter().next().unwrap_or(Value::Null(Null))));
+            }
+            state.clients().unregister(id);
+            conn.log("local client closed");
+        });
+        Self { sender }
+    }
+
+    /// Run a command and return the raw reply.
+    ///
+    /// `cmd` is the command name followed by its args, e.g. `["SET", "foo", "bar"]`.
+    pub async fn command<I, S>(&self, cmd: I) -> ServerResult<Value>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<[u8]>,
+    {
+        let message = cmd
+            .into_iter()
+            .map(|x| Value::BulkString(BulkString::new(x.as_ref())))
+            .collect::<Array>();
+        let (sender, recver) = oneshot::channel();
+        self.sender
+            .send((message, sender))
+            .map_err(|_| ServerError::ConnectionClosed)?;
+        recver.await.map_err(|_| ServerError::ConnectionClosed)?
+    }
+
+    pub async fn ping(&self) -> ServerResult<String> {
+        match self.command(["PING"]).await? {
+            Value::SimpleString(s) => Ok(s.value().to_string()),
+            v => Err(unexpected_reply(v)),
+        }
+    }
+
+    pub async fn set(&self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> ServerResult<()> {
+        let value = self
+            .command([b"SET".as_slice(), key.as_ref(), value.as_ref()])
+            .await?;
+        expect_ok(value)
+    }
+
+    pub async fn get(&self, key: impl AsRef<[u8]>) -> ServerResult<Option<Vec<u8>>> {
+        let value = self.command([b"GET".as_slice(), key.as_ref()]).await?;
+        expect_bytes(value)
+    }
+
+    pub async fn incr(&self, key: impl AsRef<[u8]>) -> ServerResult<i64> {
+        let value = self.command([b"INCR".as_slice(), key.as_ref()]).await?;
+        expect_integer(value)
+    }
+
+    /// Append `values` to the tail of list `key`, return the length of list.
+    pub async fn rpush<I, S>(&self, key: impl AsRef<[u8]>, values: I) -> ServerResult<i64>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<[u8]>,
+    {
+        let mut cmd = vec![b"RPUSH".to_vec(), key.as_ref().to_vec()];
+        cmd.extend(values.into_iter().map(|x| x.as_ref().to_vec()));
+        expect_integer(self.command(cmd).await?)
+    }
+
+    /// Prepend `values` to the head of list `key`, return the length of list.
+    pub async fn lpush<I, S>(&self, key: impl AsRef<[u8]>, values: I) -> ServerResult<i64>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<[u8]>,
+    {
+        let mut cmd = vec![b"LPUSH".to_vec(), key.as_ref().to_vec()];
+        cmd.extend(values.into_iter().map(|x| x.as_ref().to_vec()));
+        expect_integer(self.command(cmd).await?)
+    }
+
+    pub async fn lrange(
+        &self,
+        key: impl AsRef<[u8]>,
+        start: i64,
+        end: i64,
+    ) -> ServerResult<Vec<Vec<u8>>> {
+        let value = self
+            .command([
+                b"LRANGE".to_vec(),
+                key.as_ref().to_vec(),
+                start.to_string().into_bytes(),
+                end.to_string().into_bytes(),
+            ])
+            .await?;
+        match value {
+            Value::Array(arr) => arr
+                .into_iter()
+                .map(|x| expect_bytes(x).map(Option::unwrap_or_default))
+                .collect(),
+            v => Err(unexpected_reply(v)),
+        }
+    }
+}
+
+/// Convert an unexpected reply into error.
+///
+/// Error replies are reported as `ServerError::ErrorReply`.
+fn unexpected_reply(value: Value) -> ServerError {
+    match value {
+        Value::SimpleError(e) => ServerError::ErrorReply(e),
+        v => ServerError::UnexpectedReply(v),
+    }
+}
+
+fn expect_ok(value: Value) -> ServerResult<()> {
+    match value {
+        Value::SimpleString(s) if s.value() == "OK" => Ok(()),
+        v => Err(unexpected_reply(v)),
+    }
+}
+
+fn expect_integer(value: Value) -> ServerResult<i64> {
+    match value {
+        Value::Integer(v) => Ok(v.value()),
+        v => Err(unexpected_reply(v)),
+    }
+}
+
+/// Expect a string reply, return `None` if it is null.
+fn expect_bytes(value: Value) -> ServerResult<Option<Vec<u8>>> {
+    match value {
+        Value::BulkString(mut s) => Ok(s.take()),
+        Value::SimpleString(s) => Ok(Some(s.value().as_bytes().to_vec())),
+        Value::Integer(v) => Ok(Some(v.value().to_string().into_bytes())),
+        Value::Null(..) => Ok(None),
+        v => Err(unexpected_reply(v)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_redis::{Integer, SimpleError, SimpleString};
+
+    use crate::ServerBuilder;
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_local_client() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let client = handle.client();
+
+        assert_eq!(client.ping().await.unwrap(), "PONG");
+        client.set("foo", "bar").await.unwrap();
+        assert_eq!(client.get("foo").await.unwrap(), Some(b"bar".to_vec()));
+        assert_eq!(client.get("absent").await.unwrap(), None);
+        assert_eq!(client.rpush("list", ["a", "b"]).await.unwrap(), 2);
+        assert_eq!(client.lpush("list", ["c"]).await.unwrap(), 3);
+        assert_eq!(
+            client.lrange("list", 0, -1).await.unwrap(),
+            vec![b"c".to_vec(), b"a".to_vec(), b"b".to_vec()]
+        );
+        assert!(matches!(
+            client.incr("list").await,
+            Err(ServerError::ErrorReply(..))
+        ));
+
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_local_client_transaction() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let client = handle.client();
+        let other = handle.client();
+
+        assert_eq!(
+            client.command(["MULTI"]).await.unwrap(),
+            Value::SimpleString(SimpleString::new("OK"))
+        );
+        assert_eq!(
+            client.command(["INCR", "counter"]).await.unwrap(),
+            Value::SimpleString(SimpleString::new("QUEUED"))
+        );
+        // Not visible to other clients before EXEC.
+        assert_eq!(other.get("counter").await.unwrap(), None);
+        assert_eq!(
+            client.command(["EXEC"]).await.unwrap(),
+            Value::Array(Array::with_values(vec![Value::Integer(Integer::new(1))]))
+        );
+        assert_eq!(other.incr("counter").await.unwrap(), 2);
+
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_local_client_transaction_abort() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let client = handle.client();
+        let queued = Value::SimpleString(SimpleString::new("QUEUED"));
+
+        client.command(["MULTI"]).await.unwrap();
+        assert_eq!(client.command(["INCR", "counter"]).await.unwrap(), queued);
+        assert_eq!(
+            client.command(["FOO", "bar"]).await.unwrap(),
+            Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                "unknown command 'FOO', with args beginning with: 'bar' "
+            ))
+        );
+        assert_eq!(client.command(["INCR", "counter"]).await.unwrap(), queued);
+        assert_eq!(
+            client.command(["EXEC"]).await.unwrap(),
+            Value::SimpleError(SimpleError::with_prefix(
+                "EXECABORT",
+                "Transaction discarded because of previous errors."
+            ))
+        );
+        // Nothing executed, and the transaction is over.
+        assert_eq!(client.get("counter").await.unwrap(), None);
+        assert_eq!(
+            client.command(["EXEC"]).await.unwrap(),
+            Value::SimpleError(SimpleError::with_prefix("ERR", "EXEC without MULTI"))
+        );
+
+        handle.shutdown().await;
+    }
+}
@@ -0,0 +1,158 @@
+//! Registry of connected clients, read by CLIENT LIST and CLIENT INFO.
+//!
+//! Each connection registers itself when connected, and updates its entry around
+//! every command it runs, so other connections can inspect it.
+//!
+//! Connections are closed by CLIENT KILL through the receiver returned when
+//! registering, which the connection selects on along with reading requests.
+
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::{sync::oneshot, time::Instant};
+
+/// States of a connected client.
+#[derive(Debug, Clone)]
+pub(crate) struct ClientInfo {
+    /// Address of the client, empty for in-process clients.
+    pub(crate) addr: String,
+
+    /// Address of the server the client connected to.
+    pub(crate) laddr: String,
+
+    /// Set by CLIENT SETNAME.
+    pub(crate) name: String,
+
+    /// When connected.
+    pub(crate) created: Instant,
+
+    /// When the last command started.
+    pub(crate) last_interaction: Instant,
+
+    /// Name of the last command in lowercase, "NULL" if none.
+    pub(crate) last_command: Cow<'static, str>,
+
+    /// Count of commands queued in transaction, `None` if not in transaction.
+    pub(crate) multi: Option<usize>,
+
+    /// Name of the user authenticated.
+    pub(crate) user: String,
+
+    /// Version of RESP.
+    pub(crate) resp: u8,
+
+    /// Set by CLIENT NO-EVICT.
+    pub(crate) no_evict: bool,
+
+    /// In MONITOR mode.
+    pub(crate) monitor: bool,
+}
+
+impl ClientInfo {
+    fn new(addr: String, laddr: String) -> Self {
+        let now = Instant::now();
+        Self {
+            addr,
+            laddr,
+            name: String::new(),
+            created: now,
+            last_interaction: now,
+            last_command: Cow::Borrowed("NULL"),
+            multi: None,
+            user: "default".to_string(),
+            resp: 2,
+            no_evict: false,
+            monitor: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Client {
+    info: ClientInfo,
+
+    /// Channel to close the connection.
+    kill: oneshot::Sender<()>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ClientRegistry {
+    clients: Arc<Mutex<BTreeMap<usize, Client>>>,
+}
+
+impl ClientRegistry {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register connection `id` from `addr` to `laddr`.
+    ///
+    /// Return the receiver notified when the connection is killed, dropping it makes
+    /// the connection unkillable.
+    pub(crate) fn register(&self, id: usize, addr: String, laddr: String) -> oneshot::Receiver<()> {
+        let (kill, killed) = oneshot::channel();
+        let client = Client {
+            info: ClientInfo::new(addr, laddr),
+            kill,
+        };
+        self.clients.lock().unwrap().insert(id, client);
+        killed
+    }
+
+    /// Remove connection `id`, called when the connection closed.
+    pub(crate) fn unregister(&self, id: usize) {
+        self.clients.lock().unwrap().remove(&id);
+    }
+
+    /// Update the states of connection `id` by `f`, nothing happens if not
+    /// registered.
+    pub(crate) fn update(&self, id: usize, f: impl FnOnce(&mut ClientInfo)) {
+        if let Some(client) = self.clients.lock().unwrap().get_mut(&id) {
+            f(&mut client.info);
+        }
+    }
+
+    /// Get a copy of the states of connection `id`.
+    pub(crate) fn get(&self, id: usize) -> Option<ClientInfo> {
+        self.clients
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|x| x.info.clone())
+    }
+
+    /// Count of connections registered.
+    pub(crate) fn count(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    /// Get a copy of the states of all connections, ordered by id.
+    pub(crate) fn list(&self) -> Vec<(usize, ClientInfo)> {
+        self.clients
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, client)| (*id, client.info.clone()))
+            .collect()
+    }
+
+    /// Kill connections in `ids`, return ids of those killed.
+    ///
+    /// Killed connections are removed from the registry at once, unkillable ones are
+    /// kept.
+    pub(crate) fn kill(&self, ids: &[usize]) -> Vec<usize> {
+        let mut lock = self.clients.lock().unwrap();
+        ids.iter()
+            .filter(|id| match lock.get(id) {
+                Some(client) if !client.kill.is_closed() => lock
+                    .remove(id)
+                    .is_some_and(|client| client.kill.send(()).is_ok()),
+                _ => false,
+            })
+            .copied()
+            .collect()
+    }
+}
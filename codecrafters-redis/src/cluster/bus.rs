@@ -0,0 +1,160 @@
+//! The cluster bus, where nodes exchange messages.
+//!
+//! Like redis, each node keeps an outbound link to every known node, sending PING and
+//! MEET on it and receiving PONG. Messages received on inbound connections from other
+//! nodes are answered on the same connection.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+
+use crate::{
+    cluster::{
+        message::{read_message, Message, MessageKind},
+        ClusterState,
+    },
+    lifecycle::Lifecycle,
+    log::log,
+};
+
+/// Period of the cluster cron.
+const CRON_PERIOD: Duration = Duration::from_millis(100);
+
+/// Senders of messages to the outbound link of each node, by bus address.
+type Links = Arc<Mutex<HashMap<SocketAddr, mpsc::UnboundedSender<Vec<u8>>>>>;
+
+/// Serve the cluster bus on `listener` and run the cluster cron, till the server
+/// shuts down.
+pub(crate) async fn run_bus(cluster: ClusterState, listener: TcpListener, lifecycle: Lifecycle) {
+    let links: Links = Arc::new(Mutex::new(HashMap::new()));
+    let mut interval = tokio::time::interval(CRON_PERIOD);
+    let mut tick = 0u64;
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, addr)) => {
+                    tokio::spawn(serve_inbound(cluster.clone(), stream, addr, lifecycle.clone()));
+                }
+                Err(e) => log!("[cluster] failed to accept bus connection: {e}"),
+            },
+            _ = interval.tick() => {
+                cron(&cluster, &links, &lifecycle, tick);
+                tick += 1;
+            }
+            _ = lifecycle.wait_shutting_down() => break,
+        }
+    }
+}
+
+/// Run the cluster cron, connect links to new nodes and send what it asks to.
+fn cron(cluster: &ClusterState, links: &Links, lifecycle: &Lifecycle, tick: u64) {
+    let actions = cluster.cron(tick);
+    let mut lock = links.lock().unwrap();
+    for peer in cluster.peers() {
+        if let Entry::Vacant(entry) = lock.entry(peer) {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            entry.insert(sender);
+            tokio::spawn(run_link(
+                cluster.clone(),
+                links.clone(),
+                peer,
+                receiver,
+                lifecycle.clone(),
+            ));
+        }
+    }
+    let send = |peer: &SocketAddr, message: &Message| {
+        if let Some(sender) = lock.get(peer) {
+            // Dropped links are replaced in the next cron run.
+            let _ = sender.send(message.encode());
+        }
+    };
+    for peer in &actions.pings {
+        send(peer, &cluster.message(MessageKind::Ping));
+    }
+    for id in &actions.fails {
+        let message = cluster.fail_message(id);
+        for peer in lock.keys() {
+            send(peer, &message);
+        }
+    }
+}
+
+/// Keep the outbound link to the node on bus address `peer`, sending messages from
+/// `receiver` and processing replies.
+///
+/// The link is dropped on any error, to be connected again by the cron.
+async fn run_link(
+    cluster: ClusterState,
+    links: Links,
+    peer: SocketAddr,
+    mut receiver: mpsc::UnboundedReceiver<Vec<u8>>,
+    lifecycle: Lifecycle,
+) {
+    let connected = tokio::time::timeout(cluster.node_timeout(), TcpStream::connect(peer)).await;
+    // Unreachable nodes are flagged by the cron, not worth logging on every retry.
+    if let Ok(Ok(stream)) = connected {
+        let (mut reader, mut writer) = stream.into_split();
+        let read = async {
+            loop {
+                let message = read_message(&mut reader).await?;
+                cluster.receive(message, peer.ip(), Some(peer));
+            }
+        };
+        let write = async {
+            let greeting = cluster.link_up(peer).map(|x| x.encode());
+            if let Some(greeting) = greeting {
+                writer.write_all(&greeting).await?;
+                while let Some(data) = receiver.recv().await {
+                    writer.write_all(&data).await?;
+                }
+            }
+            Ok::<_, std::io::Error>(())
+        };
+        let result: std::io::Result<()> = tokio::select! {
+            v = read => v,
+            v = write => v,
+            _ = lifecycle.wait_shutting_down() => Ok(()),
+        };
+        if let Err(e) = result {
+            log!("[cluster] link to {peer} lost: {e}");
+        }
+        cluster.link_down(peer);
+    }
+    links.lock().unwrap().remove(&peer);
+}
+
+/// Answer messages from another node connected from `addr`, till disconnected.
+async fn serve_inbound(
+    cluster: ClusterState,
+    mut stream: TcpStream,
+    addr: SocketAddr,
+    lifecycle: Lifecycle,
+) {
+    let serve = async {
+        loop {
+            let message = read_message(&mut stream).await?;
+            if let Some(reply) = cluster.receive(message, addr.ip(), None) {
+                stream.write_all(&reply.encode()).await?;
+            }
+        }
+    };
+    let result: std::io::Result<()> = tokio::select! {
+        v = serve => v,
+        _ = lifecycle.wait_shutting_down() => Ok(()),
+    };
+    if let Err(e) = result {
+        if e.kind() != std::io::ErrorKind::UnexpectedEof {
+            log!("[cluster] bus connection from {addr} dropped: {e}");
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! Binary messages on the cluster bus.
+//!
+//! Modeled on `clusterMsg` of redis, simplified. Every message starts with a fixed
+//! header describing the sender, integers are in big endian:
+//!
+//! ```text
+//! "RCmb" | totlen u32 | ver u16 | port u16 | type u16 | count u16
+//!        | currentEpoch u64 | configEpoch u64 | sender id [40]
+//!        | slots bitmap [2048] | ip [46] | cport u16 | flags u16
+//! ```
+//!
+//! followed by `count` gossip entries in PING, PONG and MEET, or the id of the failing
+//! node in FAIL. Each gossip entry describes a node known by the sender:
+//!
+//! ```text
+//! id [40] | ip [46] | port u16 | cport u16 | flags u16
+//! ```
+//!
+//! Ips are in text, padded with NUL.
+
+use std::net::IpAddr;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::cluster::{NODE_ID_SIZE, SLOTS};
+
+const SIGNATURE: &[u8; 4] = b"RCmb";
+const VERSION: u16 = 1;
+const IP_SIZE: usize = 46;
+const HEADER_SIZE: usize = 4 + 4 + 2 * 4 + 8 * 2 + NODE_ID_SIZE + SLOTS / 8 + IP_SIZE + 2 * 2;
+const GOSSIP_SIZE: usize = NODE_ID_SIZE + IP_SIZE + 2 * 3;
+
+/// Messages larger than this are rejected, far more than gossip of any sane cluster.
+const MAX_SIZE: usize = 1024 * 1024;
+
+/// The node is flagged "fail?" by the sender.
+pub(super) const FLAG_PFAIL: u16 = 1 << 2;
+
+/// The node is flagged "fail" by the sender.
+pub(super) const FLAG_FAIL: u16 = 1 << 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum MessageKind {
+    Ping,
+    Pong,
+
+    /// Like PING, but asks the receiver to add the sender to its nodes.
+    Meet,
+
+    /// Tells the receiver a node failed.
+    Fail,
+}
+
+impl MessageKind {
+    fn code(self) -> u16 {
+        match self {
+            MessageKind::Ping => 0,
+            MessageKind::Pong => 1,
+            MessageKind::Meet => 2,
+            MessageKind::Fail => 3,
+        }
+    }
+
+    fn from_code(code: u16) -> Option<Self> {
+        match code {
+            0 => Some(MessageKind::Ping),
+            1 => Some(MessageKind::Pong),
+            2 => Some(MessageKind::Meet),
+            3 => Some(MessageKind::Fail),
+            _ => None,
+        }
+    }
+}
+
+/// A node described in gossip.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Gossip {
+    pub(super) id: String,
+    pub(super) ip: IpAddr,
+    pub(super) port: u16,
+    pub(super) cport: u16,
+    pub(super) flags: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Message {
+    pub(super) kind: MessageKind,
+    pub(super) sender: String,
+
+    /// Port serving clients of the sender.
+    pub(super) port: u16,
+    pub(super) cport: u16,
+    pub(super) ip: IpAddr,
+    pub(super) current_epoch: u64,
+    pub(super) config_epoch: u64,
+    pub(super) flags: u16,
+
+    /// Bitmap of slots owned by the sender.
+    pub(super) slots: Vec<u8>,
+    pub(super) gossip: Vec<Gossip>,
+
+    /// Id of the failing node in FAIL.
+    pub(super) failing: Option<String>,
+}
+
+/// Append `v` padded with NUL to `size` bytes, truncated if longer.
+fn put_padded(buf: &mut Vec<u8>, v: &str, size: usize) {
+    let mut bytes = v.as_bytes().to_vec();
+    bytes.resize(size, 0);
+    buf.extend(bytes);
+}
+
+/// Reads fields from the front of a message.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < size {
+            return Err("message truncated".to_string());
+        }
+        let (head, rest) = self.0.split_at(size);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Text padded with NUL.
+    fn padded(&mut self, size: usize) -> Result<String, String> {
+        let bytes = self.take(size)?;
+        let end = bytes.iter().position(|x| *x == 0).unwrap_or(size);
+        String::from_utf8(bytes[..end].to_vec()).map_err(|_| "invalid text".to_string())
+    }
+
+    fn ip(&mut self) -> Result<IpAddr, String> {
+        self.padded(IP_SIZE)?
+            .parse()
+            .map_err(|_| "invalid ip".to_string())
+    }
+}
+
+impl Message {
+    pub(super) fn encode(&self) -> Vec<u8> {
+        let count = match self.kind {
+            MessageKind::Fail => 1,
+            _ => self.gossip.len(),
+        };
+        let body = match self.kind {
+            MessageKind::Fail => NODE_ID_SIZE,
+            _ => count * GOSSIP_SIZE,
+        };
+        let mut buf = Vec::with_capacity(HEADER_SIZE + body);
+        buf.extend(SIGNATURE);
+        buf.extend(((HEADER_SIZE + body) as u32).to_be_bytes());
+        buf.extend(VERSION.to_be_bytes());
+        buf.extend(self.port.to_be_bytes());
+        buf.extend(self.kind.code().to_be_bytes());
+        buf.extend((count as u16).to_be_bytes());
+        buf.extend(self.current_epoch.to_be_bytes());
+        buf.extend(self.config_epoch.to_be_bytes());
+        put_padded(&mut buf, &self.sender, NODE_ID_SIZE);
+        let mut slots = self.slots.clone();
+        slots.resize(SLOTS / 8, 0);
+        buf.extend(slots);
+        put_padded(&mut buf, &self.ip.to_string(), IP_SIZE);
+        buf.extend(self.cport.to_be_bytes());
+        buf.extend(self.flags.to_be_bytes());
+        match self.kind {
+            MessageKind::Fail => put_padded(
+                &mut buf,
+                self.failing.as_deref().unwrap_or(""),
+                NODE_ID_SIZE,
+            ),
+            _ => {
+                for gossip in &self.gossip {
+                    put_padded(&mut buf, &gossip.id, NODE_ID_SIZE);
+                    put_padded(&mut buf, &gossip.ip.to_string(), IP_SIZE);
+                    buf.extend(gossip.port.to_be_bytes());
+                    buf.extend(gossip.cport.to_be_bytes());
+                    buf.extend(gossip.flags.to_be_bytes());
+                }
+            }
+        }
+        buf
+    }
+
+    pub(super) fn decode(data: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader(data);
+        if reader.take(4)? != SIGNATURE {
+            return Err("invalid signature".to_string());
+        }
+        if reader.u32()? as usize != data.len() {
+            return Err("invalid length".to_string());
+        }
+        if reader.u16()? != VERSION {
+            return Err("unsupported version".to_string());
+        }
+        let port = reader.u16()?;
+        let kind = MessageKind::from_code(reader.u16()?).ok_or("unknown message type")?;
+        let count = reader.u16()? as usize;
+        let current_epoch = reader.u64()?;
+        let config_epoch = reader.u64()?;
+        let sender = reader.padded(NODE_ID_SIZE)?;
+        let slots = reader.take(SLOTS / 8)?.to_vec();
+        let ip = reader.ip()?;
+        let cport = reader.u16()?;
+        let flags = reader.u16()?;
+        let mut gossip = vec![];
+        let mut failing = None;
+        match kind {
+            MessageKind::Fail => failing = Some(reader.padded(NODE_ID_SIZE)?),
+            _ => {
+                for _ in 0..count {
+                    gossip.push(Gossip {
+                        id: reader.padded(NODE_ID_SIZE)?,
+                        ip: reader.ip()?,
+                        port: reader.u16()?,
+                        cport: reader.u16()?,
+                        flags: reader.u16()?,
+                    });
+                }
+            }
+        }
+        if !reader.0.is_empty() {
+            return Err("trailing bytes".to_string());
+        }
+        Ok(Self {
+            kind,
+            sender,
+            port,
+            cport,
+            ip,
+            current_epoch,
+            config_epoch,
+            flags,
+            slots,
+            gossip,
+            failing,
+        })
+    }
+
+    /// Check whether the sender owns `slot`.
+    pub(super) fn owns_slot(&self, slot: usize) -> bool {
+        self.slots
+            .get(slot / 8)
+            .is_some_and(|x| x & (1 << (slot % 8)) != 0)
+    }
+}
+
+/// Read a message from `stream`.
+pub(super) async fn read_message(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> std::io::Result<Message> {
+    let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+    let mut buf = vec![0; 8];
+    stream.read_exact(&mut buf).await?;
+    let len = u32::from_be_bytes(buf[4..8].try_into().unwrap()) as usize;
+    if !(HEADER_SIZE..=MAX_SIZE).contains(&len) {
+        return Err(invalid(format!("invalid message length {len}")));
+    }
+    buf.resize(len, 0);
+    stream.read_exact(&mut buf[8..]).await?;
+    Message::decode(&buf).map_err(invalid)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_message() {
+        let mut slots = vec![0; SLOTS / 8];
+        slots[0] = 0b101;
+        let ping = Message {
+            kind: MessageKind::Ping,
+            sender: "a".repeat(NODE_ID_SIZE),
+            port: 7000,
+            cport: 17000,
+            ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            current_epoch: 3,
+            config_epoch: 2,
+            flags: 0,
+            slots,
+            gossip: vec![Gossip {
+                id: "b".repeat(NODE_ID_SIZE),
+                ip: "::1".parse().unwrap(),
+                port: 7001,
+                cport: 17001,
+                flags: FLAG_PFAIL,
+            }],
+            failing: None,
+        };
+        let data = ping.encode();
+        assert_eq!(data.len(), HEADER_SIZE + GOSSIP_SIZE);
+        assert_eq!(Message::decode(&data), Ok(ping.clone()));
+        assert!(ping.owns_slot(0) && !ping.owns_slot(1) && ping.owns_slot(2));
+        assert_eq!(read_message(&mut data.as_slice()).await.unwrap(), ping);
+
+        let fail = Message {
+            kind: MessageKind::Fail,
+            gossip: vec![],
+            failing: Some("c".repeat(NODE_ID_SIZE)),
+            ..ping
+        };
+        let data = fail.encode();
+        assert_eq!(Message::decode(&data), Ok(fail));
+
+        assert!(Message::decode(&data[..data.len() - 1]).is_err());
+        let mut bad = data.clone();
+        bad[0] = b'X';
+        assert_eq!(Message::decode(&bad), Err("invalid signature".to_string()));
+    }
+}
@@ -0,0 +1,1255 @@
+//! Cluster mode, the keyspace is sharded across nodes by hash slots.
+//!
+//! Each node knows the owner of every slot. Commands on keys in slots served by other
+//! nodes are redirected like redis:
+//!
+//! * `-MOVED <slot> <ip>:<port>` if the slot is owned by another node.
+//! * `-ASK <slot> <ip>:<port>` if the slot is migrating to another node and the key is
+//!   not here anymore, the client shall ask the target node for this key only.
+//! * `-CLUSTERDOWN` if no node owns the slot.
+//!
+//! Slots are moved between nodes live, like redis:
+//!
+//! 1. CLUSTER SETSLOT IMPORTING on the target node, then MIGRATING on the source node.
+//! 2. MIGRATE on the source node moves keys of the slot to the target node. Meanwhile
+//!    commands on keys already moved are redirected by `-ASK`, the target node serves
+//!    them only if the client sent ASKING right before.
+//! 3. CLUSTER SETSLOT NODE on the target node takes the slot with a new config epoch,
+//!    which wins over the source node in gossip.
+//!
+//! The node table and slot ownership are kept in the nodes config file, in the same
+//! format as `nodes.conf` of redis, loaded at startup and saved on every change.
+//!
+//! Nodes talk on the cluster bus, see `bus`. CLUSTER MEET starts a handshake with a
+//! new node, then nodes ping each other and gossip about the nodes they know, so the
+//! whole cluster learns about it. Gossip also spreads the slots owned by each node,
+//! and failures:
+//!
+//! * A node not answering pings in the node timeout is flagged "fail?" (PFAIL), by the
+//!   pinging node alone.
+//! * Once a majority of nodes owning slots flag it in gossip, it is flagged "fail"
+//!   (FAIL) and the FAIL is broadcast to all nodes.
+//! * Both flags are cleared once the node answers again.
+
+mod bus;
+mod message;
+mod slot;
+
+use std::{
+    collections::{hash_map::RandomState, BTreeMap},
+    fmt::Write,
+    hash::{BuildHasher, Hasher},
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde_redis::{SimpleError, Value};
+
+pub(crate) use bus::run_bus;
+use message::{Gossip, Message, MessageKind, FLAG_FAIL, FLAG_PFAIL};
+pub(crate) use slot::{key_slot, SLOTS};
+
+use crate::{log::log, replication::random_hex};
+
+/// Length of node ids in hex chars.
+const NODE_ID_SIZE: usize = 40;
+
+/// Offset of the cluster bus port to the port serving clients, by default.
+pub(crate) const BUS_PORT_OFFSET: u16 = 10000;
+
+/// Least nodes described in the gossip of each message, if known.
+const GOSSIP_WANTED: usize = 3;
+
+/// A node in the cluster.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Node {
+    /// Random 40 hex chars, a temporary one during handshake.
+    pub(crate) id: String,
+
+    /// Address serving clients.
+    pub(crate) addr: SocketAddr,
+
+    /// Port of the cluster bus.
+    pub(crate) cport: u16,
+    config_epoch: u64,
+
+    /// Flagged "fail?", not answering pings of this node.
+    pfail: bool,
+
+    /// Flagged "fail" by a majority of nodes, since when.
+    failed_at: Option<Instant>,
+
+    /// Added by CLUSTER MEET or gossip, the real id is not known yet.
+    handshake: bool,
+
+    /// Added by CLUSTER MEET, greeted by MEET instead of PING.
+    meet: bool,
+
+    /// Whether the link to the node is connected.
+    connected: bool,
+
+    /// When the ping waiting for pong was sent.
+    ping_sent: Option<Instant>,
+    pong_received: Option<Instant>,
+
+    /// Nodes owning slots reporting this node failing in gossip, by id, with when.
+    fail_reports: BTreeMap<String, Instant>,
+    created: Instant,
+}
+
+impl Node {
+    fn new(id: String, addr: SocketAddr, cport: u16) -> Self {
+        Self {
+            id,
+            addr,
+            cport,
+            config_epoch: 0,
+            pfail: false,
+            failed_at: None,
+            handshake: false,
+            meet: false,
+            connected: false,
+            ping_sent: None,
+            pong_received: None,
+            fail_reports: BTreeMap::new(),
+            created: Instant::now(),
+        }
+    }
+
+    /// Address of the cluster bus.
+    fn bus_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.addr.ip(), self.cport)
+    }
+
+    /// Flags describing the node in gossip.
+    fn gossip_flags(&self) -> u16 {
+        let mut flags = 0;
+        if self.pfail {
+            flags |= FLAG_PFAIL;
+        }
+        if self.failed_at.is_some() {
+            flags |= FLAG_FAIL;
+        }
+        flags
+    }
+}
+
+/// Milliseconds since unix epoch at `instant`, 0 if `None`.
+fn unix_millis(instant: Option<Instant>) -> u128 {
+    let Some(instant) = instant else {
+        return 0;
+    };
+    (SystemTime::now() - instant.elapsed())
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+/// A random number, for picking nodes to ping and gossip about.
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Where to serve a command, if not by this node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Redirect {
+    /// The slot is owned by the node at the address.
+    Moved(u16, SocketAddr),
+
+    /// The slot is migrating to the node at the address, and the key is not here.
+    Ask(u16, SocketAddr),
+
+    /// No node owns the slot.
+    Down,
+}
+
+impl Redirect {
+    /// The error replied to clients.
+    pub(crate) fn to_error(&self) -> Value {
+        let (prefix, message) = match self {
+            Redirect::Moved(slot, addr) => {
+                ("MOVED", format!("{slot} {}:{}", addr.ip(), addr.port()))
+            }
+            Redirect::Ask(slot, addr) => ("ASK", format!("{slot} {}:{}", addr.ip(), addr.port())),
+            Redirect::Down => ("CLUSTERDOWN", "Hash slot not served".to_string()),
+        };
+        Value::SimpleError(SimpleError::with_prefix(prefix, message))
+    }
+}
+
+/// How CLUSTER SETSLOT changes a slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SlotState {
+    /// The slot is importing from the node of the id.
+    Importing(String),
+
+    /// The slot is migrating to the node of the id.
+    Migrating(String),
+
+    /// Neither importing nor migrating.
+    Stable,
+
+    /// The slot is owned by the node of the id.
+    Node(String),
+}
+
+/// Overview of the cluster, reported in CLUSTER INFO.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ClusterInfo {
+    /// Whether every slot is served by a node not failing.
+    pub(crate) ok: bool,
+    pub(crate) slots_assigned: usize,
+
+    /// Slots owned by nodes flagged "fail?".
+    pub(crate) slots_pfail: usize,
+
+    /// Slots owned by nodes flagged "fail".
+    pub(crate) slots_fail: usize,
+    pub(crate) known_nodes: usize,
+
+    /// Count of nodes serving at least one slot.
+    pub(crate) size: usize,
+    pub(crate) current_epoch: u64,
+}
+
+/// What the cluster bus shall send after a cron run.
+#[derive(Debug, Default)]
+struct CronActions {
+    /// Bus addresses of nodes to ping.
+    pings: Vec<SocketAddr>,
+
+    /// Ids of nodes just flagged "fail", broadcast to all nodes.
+    fails: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ClusterState {
+    inner: Arc<Mutex<ClusterInner>>,
+}
+
+#[derive(Debug)]
+struct ClusterInner {
+    /// Whether running in cluster mode, set at startup.
+    enabled: bool,
+    myid: String,
+
+    /// Known nodes by id, this node included.
+    nodes: BTreeMap<String, Node>,
+
+    /// Id of the node owning each slot.
+    slots: Vec<Option<String>>,
+
+    /// Slots migrating from this node, to the node of the id.
+    migrating: BTreeMap<u16, String>,
+
+    /// Slots importing to this node, from the node of the id.
+    importing: BTreeMap<u16, String>,
+    current_epoch: u64,
+
+    /// Time for nodes to answer pings before flagged "fail?".
+    node_timeout: Duration,
+
+    /// Whether changed since the nodes config file was saved.
+    dirty: bool,
+
+    /// Path of the nodes config file, empty if not enabled.
+    path: PathBuf,
+}
+
+impl ClusterState {
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(ClusterInner {
+                enabled: false,
+                myid: String::new(),
+                nodes: BTreeMap::new(),
+                slots: vec![None; SLOTS],
+                migrating: BTreeMap::new(),
+                importing: BTreeMap::new(),
+                current_epoch: 0,
+                node_timeout: Duration::from_secs(15),
+                dirty: false,
+                path: PathBuf::new(),
+            })),
+        }
+    }
+
+    /// Enter cluster mode as the node serving clients on `addr` and the cluster bus
+    /// on `cport`.
+    ///
+    /// The node table is loaded from the nodes config file at `path` if present,
+    /// otherwise this node starts alone with a new id and no slot. Either way the
+    /// file is saved, so the id survives restarts.
+    pub(crate) fn enable(
+        &self,
+        addr: SocketAddr,
+        cport: u16,
+        path: PathBuf,
+        node_timeout: Duration,
+    ) -> Result<(), String> {
+        let mut lock = self.inner.lock().unwrap();
+        match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                lock.load(&text)?;
+                log!("[cluster] loaded nodes config {}", path.display());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let id = random_hex(NODE_ID_SIZE);
+                log!("[cluster] no nodes config found, my id is {id}");
+                lock.myid = id;
+            }
+            Err(e) => return Err(format!("failed to read {}: {e}", path.display())),
+        }
+        // Addresses may change between restarts.
+        let myid = lock.myid.clone();
+        let config_epoch = lock.nodes.get(&myid).map_or(0, |x| x.config_epoch);
+        let mut node = Node::new(myid, addr, cport);
+        node.config_epoch = config_epoch;
+        node.connected = true;
+        lock.nodes.insert(node.id.clone(), node);
+        lock.enabled = true;
+        lock.node_timeout = node_timeout;
+        lock.path = path;
+        lock.save();
+        Ok(())
+    }
+
+    /// Check whether running in cluster mode.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.inner.lock().unwrap().enabled
+    }
+
+    /// Id of this node.
+    pub(crate) fn myid(&self) -> String {
+        self.inner.lock().unwrap().myid.clone()
+    }
+
+    /// Where to serve a command on a key in `slot`, `exists` here or not, `asking` if
+    /// the client sent ASKING right before.
+    ///
+    /// Return `None` if served by this node.
+    pub(crate) fn redirect(&self, slot: u16, exists: bool, asking: bool) -> Option<Redirect> {
+        let lock = self.inner.lock().unwrap();
+        let addr_of = |id: &str| lock.nodes.get(id).map(|x| x.addr);
+        match &lock.slots[slot as usize] {
+            None => Some(Redirect::Down),
+            Some(owner) if *owner == lock.myid => {
+                if exists {
+                    return None;
+                }
+                let target = lock.migrating.get(&slot)?;
+                addr_of(target).map(|x| Redirect::Ask(slot, x))
+            }
+            Some(_) if asking && lock.importing.contains_key(&slot) => None,
+            Some(owner) => {
+                Some(addr_of(owner).map_or(Redirect::Down, |x| Redirect::Moved(slot, x)))
+            }
+        }
+    }
+
+    /// Change `slot` to `state`, for CLUSTER SETSLOT. `has_keys` if any key in the
+    /// slot is here.
+    ///
+    /// Taking the slot from another node bumps the config epoch of this node, so the
+    /// other nodes learn the new owner in gossip.
+    pub(crate) fn set_slot(
+        &self,
+        slot: u16,
+        state: SlotState,
+        has_keys: bool,
+    ) -> Result<(), String> {
+        let mut lock = self.inner.lock().unwrap();
+        let myid = lock.myid.clone();
+        let owned = lock.slots[slot as usize].as_ref() == Some(&myid);
+        let known = |id: &str| {
+            if lock.nodes.get(id).is_some_and(|x| !x.handshake) {
+                Ok(id.to_string())
+            } else {
+                Err(format!("I don't know about node {id}"))
+            }
+        };
+        match state {
+            SlotState::Importing(id) => {
+                if owned {
+                    return Err(format!("I'm already the owner of hash slot {slot}"));
+                }
+                let id = known(&id)?;
+                lock.importing.insert(slot, id);
+            }
+            SlotState::Migrating(id) => {
+                if !owned {
+                    return Err(format!("I'm not the owner of hash slot {slot}"));
+                }
+                let id = known(&id)?;
+                lock.migrating.insert(slot, id);
+            }
+            SlotState::Stable => {
+                lock.migrating.remove(&slot);
+                lock.importing.remove(&slot);
+            }
+            SlotState::Node(id) => {
+                let id = known(&id)?;
+                if owned && id != myid && has_keys {
+                    return Err(format!(
+                        "Can't assign hashslot {slot} to a different node while I still hold keys for this hash slot."
+                    ));
+                }
+                if !has_keys {
+                    lock.migrating.remove(&slot);
+                }
+                if id == myid && lock.importing.remove(&slot).is_some() {
+                    lock.current_epoch += 1;
+                    let epoch = lock.current_epoch;
+                    lock.nodes.get_mut(&myid).unwrap().config_epoch = epoch;
+                    log!("[cluster] slot {slot} imported, config epoch bumped to {epoch}");
+                }
+                lock.slots[slot as usize] = Some(id);
+            }
+        }
+        lock.save();
+        Ok(())
+    }
+
+    /// Assign `slots` to this node.
+    ///
+    /// Fail without assigning any if one of them is already assigned.
+    pub(crate) fn add_slots(&self, slots: &[u16]) -> Result<(), String> {
+        let mut lock = self.inner.lock().unwrap();
+        if let Some(slot) = slots.iter().find(|x| lock.slots[**x as usize].is_some()) {
+            return Err(format!("Slot {slot} is already busy"));
+        }
+        let myid = lock.myid.clone();
+        for slot in slots {
+            lock.slots[*slot as usize] = Some(myid.clone());
+            lock.importing.remove(slot);
+        }
+        lock.save();
+        Ok(())
+    }
+
+    /// Unassign `slots` from whichever node owns them.
+    ///
+    /// Fail without unassigning any if one of them is not assigned.
+    pub(crate) fn del_slots(&self, slots: &[u16]) -> Result<(), String> {
+        let mut lock = self.inner.lock().unwrap();
+        if let Some(slot) = slots.iter().find(|x| lock.slots[**x as usize].is_none()) {
+            return Err(format!("Slot {slot} is already unassigned"));
+        }
+        for slot in slots {
+            lock.slots[*slot as usize] = None;
+            lock.migrating.remove(slot);
+        }
+        lock.save();
+        Ok(())
+    }
+
+    /// Start a handshake with the node serving clients on `addr` and the cluster bus
+    /// on `cport`, for CLUSTER MEET.
+    ///
+    /// Return false if the node is already known or in handshake.
+    pub(crate) fn meet(&self, addr: SocketAddr, cport: u16) -> bool {
+        let mut lock = self.inner.lock().unwrap();
+        let bus_addr = SocketAddr::new(addr.ip(), cport);
+        if lock.nodes.values().any(|x| x.bus_addr() == bus_addr) {
+            return false;
+        }
+        let mut node = Node::new(random_hex(NODE_ID_SIZE), addr, cport);
+        node.handshake = true;
+        node.meet = true;
+        lock.nodes.insert(node.id.clone(), node);
+        true
+    }
+
+    pub(crate) fn info(&self) -> ClusterInfo {
+        let lock = self.inner.lock().unwrap();
+        let mut info = ClusterInfo {
+            ok: false,
+            slots_assigned: 0,
+            slots_pfail: 0,
+            slots_fail: 0,
+            known_nodes: lock.nodes.len(),
+            size: lock.size(),
+            current_epoch: lock.current_epoch,
+        };
+        for owner in lock.slots.iter().flatten() {
+            info.slots_assigned += 1;
+            match lock.nodes.get(owner) {
+                Some(node) if node.failed_at.is_some() => info.slots_fail += 1,
+                Some(node) if node.pfail => info.slots_pfail += 1,
+                _ => {}
+            }
+        }
+        info.ok = info.slots_assigned == SLOTS && info.slots_fail == 0;
+        info
+    }
+
+    /// All known nodes, ordered by id.
+    pub(crate) fn nodes(&self) -> Vec<Node> {
+        self.inner.lock().unwrap().nodes.values().cloned().collect()
+    }
+
+    /// Describe all known nodes for CLUSTER NODES, one per line like the nodes config
+    /// file, nodes in handshake included.
+    pub(crate) fn describe_nodes(&self) -> String {
+        self.inner.lock().unwrap().render_nodes(true)
+    }
+
+    /// Ranges of consecutive slots owned by the same node, ordered by slot, with the
+    /// owner node.
+    pub(crate) fn slot_ranges(&self) -> Vec<(u16, u16, Node)> {
+        let lock = self.inner.lock().unwrap();
+        lock.ranges()
+            .into_iter()
+            .filter_map(|(start, end, id)| Some((start, end, lock.nodes.get(id)?.clone())))
+            .collect()
+    }
+
+    fn node_timeout(&self) -> Duration {
+        self.inner.lock().unwrap().node_timeout
+    }
+
+    /// Bus addresses of all nodes to keep links with.
+    fn peers(&self) -> Vec<SocketAddr> {
+        let lock = self.inner.lock().unwrap();
+        lock.nodes
+            .values()
+            .filter(|x| x.id != lock.myid)
+            .map(Node::bus_addr)
+            .collect()
+    }
+
+    /// Message of `kind` from this node, with gossip.
+    fn message(&self, kind: MessageKind) -> Message {
+        self.inner.lock().unwrap().message(kind, None)
+    }
+
+    /// Message telling node `id` failed.
+    fn fail_message(&self, id: &str) -> Message {
+        let mut message = self.inner.lock().unwrap().message(MessageKind::Fail, None);
+        message.failing = Some(id.to_string());
+        message
+    }
+
+    /// Record the link to the node on `bus_addr` is connected, return the greeting to
+    /// send on it, MEET or PING.
+    ///
+    /// Return `None` if the node is not known anymore.
+    fn link_up(&self, bus_addr: SocketAddr) -> Option<Message> {
+        let mut lock = self.inner.lock().unwrap();
+        let node = lock.nodes.values_mut().find(|x| x.bus_addr() == bus_addr)?;
+        node.connected = true;
+        node.ping_sent.get_or_insert_with(Instant::now);
+        let kind = if node.meet {
+            MessageKind::Meet
+        } else {
+            MessageKind::Ping
+        };
+        let receiver = node.id.clone();
+        Some(lock.message(kind, Some(&receiver)))
+    }
+
+    /// Record the link to the node on `bus_addr` is lost.
+    fn link_down(&self, bus_addr: SocketAddr) {
+        let mut lock = self.inner.lock().unwrap();
+        if let Some(node) = lock.nodes.values_mut().find(|x| x.bus_addr() == bus_addr) {
+            node.connected = false;
+        }
+    }
+
+    /// Process `message` from `peer`, received on the link to the node on `link`, or
+    /// on a connection from another node if `None`.
+    ///
+    /// Return the reply, PONG for PING and MEET.
+    fn receive(&self, message: Message, peer: IpAddr, link: Option<SocketAddr>) -> Option<Message> {
+        let mut lock = self.inner.lock().unwrap();
+        lock.receive(&message, peer, link);
+        match message.kind {
+            MessageKind::Ping | MessageKind::Meet => {
+                Some(lock.message(MessageKind::Pong, Some(&message.sender)))
+            }
+            MessageKind::Pong | MessageKind::Fail => None,
+        }
+    }
+
+    /// Run periodic tasks, `tick` counts the runs.
+    ///
+    /// Return the messages the cluster bus shall send.
+    fn cron(&self, tick: u64) -> CronActions {
+        let mut lock = self.inner.lock().unwrap();
+        let actions = lock.cron(tick);
+        if lock.dirty {
+            lock.save();
+        }
+        actions
+    }
+}
+
+impl ClusterInner {
+    /// Ranges of consecutive slots owned by the same node, with the owner id.
+    fn ranges(&self) -> Vec<(u16, u16, &str)> {
+        let mut ranges: Vec<(u16, u16, &str)> = vec![];
+        for (slot, owner) in self.slots.iter().enumerate() {
+            let Some(owner) = owner else {
+                continue;
+            };
+            let slot = slot as u16;
+            match ranges.last_mut() {
+                Some((_, end, id)) if *end + 1 == slot && id == owner => *end = slot,
+                _ => ranges.push((slot, slot, owner)),
+            }
+        }
+        ranges
+    }
+
+    /// Check whether node `id` owns any slot.
+    fn owns_slots(&self, id: &str) -> bool {
+        self.slots.iter().flatten().any(|x| x == id)
+    }
+
+    /// Count of nodes owning at least one slot.
+    fn size(&self) -> usize {
+        let mut owners = self
+            .ranges()
+            .into_iter()
+            .map(|(.., id)| id)
+            .collect::<Vec<_>>();
+        owners.sort();
+        owners.dedup();
+        owners.len()
+    }
+
+    /// Build a message of `kind` to node `receiver`, with gossip about other nodes.
+    fn message(&self, kind: MessageKind, receiver: Option<&str>) -> Message {
+        let myself = &self.nodes[&self.myid];
+        let mut slots = vec![0u8; SLOTS / 8];
+        for (slot, owner) in self.slots.iter().enumerate() {
+            if owner.as_ref() == Some(&self.myid) {
+                slots[slot / 8] |= 1 << (slot % 8);
+            }
+        }
+        // Like redis, a tenth of nodes at random but at least a few, and all nodes
+        // flagged "fail?" so failures are agreed on quickly.
+        let mut candidates = self
+            .nodes
+            .values()
+            .filter(|x| x.id != self.myid && Some(x.id.as_str()) != receiver && !x.handshake)
+            .collect::<Vec<_>>();
+        candidates.sort_by_cached_key(|_| random());
+        let wanted = GOSSIP_WANTED.max(self.nodes.len() / 10);
+        let gossip = candidates
+            .into_iter()
+            .enumerate()
+            .filter(|(index, x)| *index < wanted || x.pfail)
+            .map(|(_, x)| Gossip {
+                id: x.id.clone(),
+                ip: x.addr.ip(),
+                port: x.addr.port(),
+                cport: x.cport,
+                flags: x.gossip_flags(),
+            })
+            .collect();
+        Message {
+            kind,
+            sender: self.myid.clone(),
+            port: myself.addr.port(),
+            cport: myself.cport,
+            ip: myself.addr.ip(),
+            current_epoch: self.current_epoch,
+            config_epoch: myself.config_epoch,
+            flags: 0,
+            slots,
+            gossip,
+            failing: None,
+        }
+    }
+
+    fn receive(&mut self, message: &Message, peer: IpAddr, link: Option<SocketAddr>) {
+        let now = Instant::now();
+        self.current_epoch = self.current_epoch.max(message.current_epoch);
+
+        if message.kind == MessageKind::Meet && !self.nodes.contains_key(&message.sender) {
+            let addr = SocketAddr::new(peer, message.port);
+            log!("[cluster] met by node {} on {addr}", message.sender);
+            let node = Node::new(message.sender.clone(), addr, message.cport);
+            self.nodes.insert(node.id.clone(), node);
+            self.dirty = true;
+        }
+
+        // The first PONG on the link to a node in handshake tells its real id.
+        let handshake = link.and_then(|link| {
+            self.nodes
+                .values()
+                .find(|x| x.handshake && x.bus_addr() == link)
+                .map(|x| x.id.clone())
+        });
+        if let (MessageKind::Pong, Some(id)) = (message.kind, handshake) {
+            let mut node = self.nodes.remove(&id).unwrap();
+            // Already known by another address otherwise.
+            if !self.nodes.contains_key(&message.sender) {
+                log!(
+                    "[cluster] handshake with node {} on {} done",
+                    message.sender,
+                    node.addr
+                );
+                node.id = message.sender.clone();
+                node.handshake = false;
+                node.meet = false;
+                self.nodes.insert(node.id.clone(), node);
+                self.dirty = true;
+            }
+        }
+
+        let Some(sender) = self.nodes.get_mut(&message.sender) else {
+            // Nothing to learn from unknown nodes.
+            return;
+        };
+        sender.config_epoch = message.config_epoch;
+        if message.kind == MessageKind::Pong && link == Some(sender.bus_addr()) {
+            sender.ping_sent = None;
+            sender.pong_received = Some(now);
+            if sender.pfail {
+                log!("[cluster] node {} is reachable again", sender.id);
+                sender.pfail = false;
+            }
+        }
+        let sender_epoch = sender.config_epoch;
+        let failed_at = sender.failed_at;
+
+        // Nodes owning no slot are safe to rejoin at once, others only once the
+        // failure is known by the cluster for a while.
+        if message.kind == MessageKind::Pong
+            && failed_at.is_some_and(|x| {
+                !self.owns_slots(&message.sender) || x.elapsed() > self.node_timeout * 2
+            })
+        {
+            log!("[cluster] clear FAIL of node {}", message.sender);
+            self.nodes.get_mut(&message.sender).unwrap().failed_at = None;
+            self.dirty = true;
+        }
+
+        // Claims of slots win over owners with older config.
+        for slot in (0..SLOTS).filter(|x| message.owns_slot(*x)) {
+            let claimed = match &self.slots[slot] {
+                None => true,
+                Some(owner) if *owner == message.sender => false,
+                Some(owner) => self
+                    .nodes
+                    .get(owner)
+                    .is_none_or(|x| x.config_epoch < sender_epoch),
+            };
+            if claimed {
+                self.slots[slot] = Some(message.sender.clone());
+                self.migrating.remove(&(slot as u16));
+                self.dirty = true;
+            }
+        }
+
+        if let Some(failing) = &message.failing {
+            match self.nodes.get_mut(failing) {
+                Some(node) if node.id != self.myid && node.failed_at.is_none() => {
+                    log!(
+                        "[cluster] FAIL of node {failing} received from {}",
+                        message.sender
+                    );
+                    node.failed_at = Some(now);
+                    node.pfail = false;
+                    self.dirty = true;
+                }
+                _ => {}
+            }
+        }
+
+        // Only nodes owning slots have a say in failures.
+        let voter = self.owns_slots(&message.sender);
+        for gossip in &message.gossip {
+            if gossip.id == self.myid {
+                continue;
+            }
+            let failing = gossip.flags & (FLAG_PFAIL | FLAG_FAIL) != 0;
+            if let Some(node) = self.nodes.get_mut(&gossip.id) {
+                if failing && voter {
+                    node.fail_reports.insert(message.sender.clone(), now);
+                } else {
+                    node.fail_reports.remove(&message.sender);
+                }
+                continue;
+            }
+            let addr = SocketAddr::new(gossip.ip, gossip.port);
+            let bus_addr = SocketAddr::new(gossip.ip, gossip.cport);
+            if !failing && !self.nodes.values().any(|x| x.bus_addr() == bus_addr) {
+                log!("[cluster] start handshake with node {addr} in gossip");
+                let mut node = Node::new(random_hex(NODE_ID_SIZE), addr, gossip.cport);
+                node.handshake = true;
+                self.nodes.insert(node.id.clone(), node);
+            }
+        }
+    }
+
+    fn cron(&mut self, tick: u64) -> CronActions {
+        let now = Instant::now();
+        let timeout = self.node_timeout;
+        let mut actions = CronActions::default();
+
+        let handshake_timeout = timeout.max(Duration::from_secs(1));
+        self.nodes.retain(|_, x| {
+            let expired = x.handshake && x.created.elapsed() > handshake_timeout;
+            if expired {
+                log!("[cluster] handshake with node {} timed out", x.addr);
+            }
+            !expired
+        });
+
+        let myid = self.myid.clone();
+        let mut pinged = vec![];
+        // Every second, ping the one not heard of for the longest of a few random nodes.
+        if tick.is_multiple_of(10) {
+            let mut candidates = self
+                .nodes
+                .values()
+                .filter(|x| x.id != myid && !x.handshake && x.connected && x.ping_sent.is_none())
+                .collect::<Vec<_>>();
+            candidates.sort_by_cached_key(|_| random());
+            if let Some(node) = candidates
+                .into_iter()
+                .take(5)
+                .min_by_key(|x| x.pong_received)
+            {
+                pinged.push(node.id.clone());
+            }
+        }
+        for node in self.nodes.values_mut() {
+            if node.id == myid || node.handshake {
+                continue;
+            }
+            // Ping nodes not heard of for half of the timeout, so only nodes really
+            // failing reach the timeout.
+            let stale = node.pong_received.is_none_or(|x| now - x > timeout / 2);
+            if node.connected && node.ping_sent.is_none() && stale {
+                pinged.push(node.id.clone());
+            }
+            // The greeting is sent once the link is up, but the timeout starts now.
+            if !node.connected && node.ping_sent.is_none() {
+                node.ping_sent = Some(now);
+            }
+            let timed_out = node.ping_sent.is_some_and(|x| now - x > timeout);
+            if timed_out && !node.pfail && node.failed_at.is_none() {
+                log!("[cluster] node {} timed out, flagged fail?", node.id);
+                node.pfail = true;
+            }
+        }
+        pinged.sort();
+        pinged.dedup();
+        for id in pinged {
+            let node = self.nodes.get_mut(&id).unwrap();
+            node.ping_sent = Some(now);
+            actions.pings.push(node.bus_addr());
+        }
+
+        // A majority of nodes owning slots, this node included if owning any.
+        let needed = self.size() / 2 + 1;
+        let myself = usize::from(self.owns_slots(&myid));
+        for node in self.nodes.values_mut().filter(|x| x.pfail) {
+            node.fail_reports.retain(|_, x| now - *x < timeout * 2);
+            if node.fail_reports.len() + myself >= needed {
+                log!(
+                    "[cluster] node {} flagged fail by {} nodes",
+                    node.id,
+                    node.fail_reports.len() + myself
+                );
+                node.pfail = false;
+                node.failed_at = Some(now);
+                actions.fails.push(node.id.clone());
+                self.dirty = true;
+            }
+        }
+        actions
+    }
+
+    /// Describe nodes one per line, in the nodes config file format.
+    fn render_nodes(&self, handshake: bool) -> String {
+        let ranges = self.ranges();
+        let mut text = String::new();
+        for node in self.nodes.values() {
+            if node.handshake && !handshake {
+                continue;
+            }
+            let mut flags = vec![];
+            if node.id == self.myid {
+                flags.push("myself");
+            }
+            flags.push(if node.handshake {
+                "handshake"
+            } else {
+                "master"
+            });
+            if node.pfail {
+                flags.push("fail?");
+            }
+            if node.failed_at.is_some() {
+                flags.push("fail");
+            }
+            let link = if node.connected || node.id == self.myid {
+                "connected"
+            } else {
+                "disconnected"
+            };
+            let _ = write!(
+                text,
+                "{} {}:{}@{} {} - {} {} {} {link}",
+                node.id,
+                node.addr.ip(),
+                node.addr.port(),
+                node.cport,
+                flags.join(","),
+                unix_millis(node.ping_sent),
+                unix_millis(node.pong_received),
+                node.config_epoch,
+            );
+            for (start, end, _) in ranges.iter().filter(|(.., id)| *id == node.id) {
+                match start == end {
+                    true => write!(text, " {start}"),
+                    false => write!(text, " {start}-{end}"),
+                }
+                .unwrap();
+            }
+            if node.id == self.myid {
+                for (slot, id) in &self.migrating {
+                    let _ = write!(text, " [{slot}->-{id}]");
+                }
+                for (slot, id) in &self.importing {
+                    let _ = write!(text, " [{slot}-<-{id}]");
+                }
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Render the nodes config file, nodes in handshake are not saved.
+    fn render(&self) -> String {
+        let mut text = self.render_nodes(false);
+        let _ = writeln!(
+            text,
+            "vars currentEpoch {} lastVoteEpoch 0",
+            self.current_epoch
+        );
+        text
+    }
+
+    /// Load the nodes config file `text`, replacing all nodes and slots.
+    ///
+    /// Return the reason if invalid, or no node is flagged "myself".
+    fn load(&mut self, text: &str) -> Result<(), String> {
+        let invalid = |line: usize| format!("invalid nodes config at line {line}");
+        let mut myid = None;
+        let mut nodes = BTreeMap::new();
+        let mut slots = vec![None; SLOTS];
+        let mut migrating = BTreeMap::new();
+        let mut importing = BTreeMap::new();
+        let mut current_epoch = 0;
+        for (index, line) in text.lines().enumerate() {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            match fields.as_slice() {
+                [] => continue,
+                ["vars", vars @ ..] => {
+                    for pair in vars.chunks(2) {
+                        if let ["currentEpoch", v] = pair {
+                            current_epoch = v.parse().map_err(|_| invalid(index + 1))?;
+                        }
+                    }
+                    continue;
+                }
+                _ if fields.len() < 8 => return Err(invalid(index + 1)),
+                _ => {}
+            }
+            // <id> <ip:port@cport[,hostname]> <flags> <master> <ping-sent> <pong-recv>
+            // <config-epoch> <link-state> <slot> ...
+            let id = fields[0].to_string();
+            let (addr, cport) = fields[1]
+                .split(',')
+                .next()
+                .and_then(|x| x.split_once('@'))
+                .ok_or_else(|| invalid(index + 1))?;
+            let addr = addr.parse::<SocketAddr>().or_else(|_| {
+                // Unlike rust, redis does not bracket ipv6 addresses.
+                let (ip, port) = addr.rsplit_once(':').ok_or_else(|| invalid(index + 1))?;
+                Ok::<_, String>(SocketAddr::new(
+                    ip.parse().map_err(|_| invalid(index + 1))?,
+                    port.parse().map_err(|_| invalid(index + 1))?,
+                ))
+            })?;
+            let cport = cport.parse::<u16>().map_err(|_| invalid(index + 1))?;
+            let mut node = Node::new(id.clone(), addr, cport);
+            for flag in fields[2].split(',') {
+                match flag {
+                    "myself" => myid = Some(id.clone()),
+                    "fail?" => node.pfail = true,
+                    "fail" => node.failed_at = Some(Instant::now()),
+                    _ => {}
+                }
+            }
+            node.config_epoch = fields[6].parse().map_err(|_| invalid(index + 1))?;
+            for field in &fields[8..] {
+                if let Some(field) = field.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+                    let (slot, target, map) =
+                        match (field.split_once("->-"), field.split_once("-<-")) {
+                            (Some((slot, target)), _) => (slot, target, &mut migrating),
+                            (_, Some((slot, source))) => (slot, source, &mut importing),
+                            _ => return Err(invalid(index + 1)),
+                        };
+                    let slot = parse_slot(slot).ok_or_else(|| invalid(index + 1))?;
+                    map.insert(slot, target.to_string());
+                    continue;
+                }
+                let (start, end) = field.split_once('-').unwrap_or((field, field));
+                let (Some(start), Some(end)) = (parse_slot(start), parse_slot(end)) else {
+                    return Err(invalid(index + 1));
+                };
+                for slot in start..=end {
+                    slots[slot as usize] = Some(id.clone());
+                }
+            }
+            nodes.insert(id, node);
+        }
+        self.myid = myid.ok_or_else(|| "no myself node in nodes config".to_string())?;
+        self.nodes = nodes;
+        self.slots = slots;
+        self.migrating = migrating;
+        self.importing = importing;
+        self.current_epoch = current_epoch;
+        Ok(())
+    }
+
+    /// Save the nodes config file, failures are only logged as the state in memory
+    /// is still valid.
+    fn save(&mut self) {
+        self.dirty = false;
+        // Nowhere to save before enabled.
+        if self.path.as_os_str().is_empty() {
+            return;
+        }
+        if let Err(e) = std::fs::write(&self.path, self.render()) {
+            log!(
+                "[cluster] failed to save nodes config {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Parse slot `v`, return `None` if not a number in range.
+pub(crate) fn parse_slot(v: &str) -> Option<u16> {
+    v.parse::<u16>().ok().filter(|x| (*x as usize) < SLOTS)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nodes_config() {
+        let text = "\
+            e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 127.0.0.1:7001@17001 master - 0 0 2 disconnected 5461-10922\n\
+            67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1 127.0.0.1:7000@17000 myself,master - 0 0 1 connected 0-5460 10923 [5460->-e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca]\n\
+            vars currentEpoch 3 lastVoteEpoch 0\n";
+        let state = ClusterState::new();
+        let mut lock = state.inner.lock().unwrap();
+        lock.load(text).unwrap();
+        assert_eq!(lock.myid, "67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1");
+        assert_eq!(lock.current_epoch, 3);
+        assert_eq!(
+            lock.ranges(),
+            [
+                (0, 5460, "67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1"),
+                (5461, 10922, "e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca"),
+                (10923, 10923, "67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1"),
+            ]
+        );
+        // Nodes are ordered by id once saved.
+        let mut lines = text.lines().collect::<Vec<_>>();
+        lines.swap(0, 1);
+        assert_eq!(lock.render(), lines.join("\n") + "\n");
+        lock.enabled = true;
+        drop(lock);
+
+        let other: SocketAddr = "127.0.0.1:7001".parse().unwrap();
+        assert_eq!(state.redirect(0, false, false), None);
+        assert_eq!(state.redirect(5460, true, false), None);
+        assert_eq!(
+            state.redirect(5460, false, false),
+            Some(Redirect::Ask(5460, other))
+        );
+        assert_eq!(
+            state.redirect(5461, true, false),
+            Some(Redirect::Moved(5461, other))
+        );
+        assert_eq!(state.redirect(16383, true, false), Some(Redirect::Down));
+        assert_eq!(
+            Redirect::Moved(5461, other).to_error(),
+            Value::SimpleError(SimpleError::with_prefix("MOVED", "5461 127.0.0.1:7001"))
+        );
+
+        assert_eq!(
+            ClusterState::new().inner.lock().unwrap().load("a b c\n"),
+            Err("invalid nodes config at line 1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_set_slot() {
+        let text = "\
+            e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 127.0.0.1:7001@17001 master - 0 0 2 connected 5461-10922\n\
+            67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1 127.0.0.1:7000@17000 myself,master - 0 0 1 connected 0-5460\n\
+            vars currentEpoch 3 lastVoteEpoch 0\n";
+        let state = ClusterState::new();
+        state.inner.lock().unwrap().load(text).unwrap();
+        let myid = "67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1".to_string();
+        let other = "e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca".to_string();
+        let other_addr: SocketAddr = "127.0.0.1:7001".parse().unwrap();
+
+        // Importing slots are served only right after ASKING.
+        assert_eq!(
+            state.set_slot(0, SlotState::Importing(other.clone()), false),
+            Err("I'm already the owner of hash slot 0".to_string())
+        );
+        assert_eq!(
+            state.set_slot(5461, SlotState::Importing("x".to_string()), false),
+            Err("I don't know about node x".to_string())
+        );
+        state
+            .set_slot(5461, SlotState::Importing(other.clone()), false)
+            .unwrap();
+        assert_eq!(
+            state.redirect(5461, false, false),
+            Some(Redirect::Moved(5461, other_addr))
+        );
+        assert_eq!(state.redirect(5461, false, true), None);
+
+        // Migrating slots redirect keys not here to the target.
+        assert_eq!(
+            state.set_slot(5461, SlotState::Migrating(other.clone()), false),
+            Err("I'm not the owner of hash slot 5461".to_string())
+        );
+        state
+            .set_slot(0, SlotState::Migrating(other.clone()), false)
+            .unwrap();
+        assert_eq!(state.redirect(0, true, false), None);
+        assert_eq!(
+            state.redirect(0, false, false),
+            Some(Redirect::Ask(0, other_addr))
+        );
+        assert_eq!(
+            state.set_slot(0, SlotState::Node(other.clone()), true),
+            Err("Can't assign hashslot 0 to a different node while I still hold keys for this hash slot.".to_string())
+        );
+        state
+            .set_slot(0, SlotState::Node(other.clone()), false)
+            .unwrap();
+        assert_eq!(
+            state.redirect(0, false, false),
+            Some(Redirect::Moved(0, other_addr))
+        );
+
+        // Taking the imported slot bumps the config epoch.
+        state
+            .set_slot(5461, SlotState::Node(myid.clone()), false)
+            .unwrap();
+        assert_eq!(state.redirect(5461, false, false), None);
+        let lock = state.inner.lock().unwrap();
+        assert!(lock.migrating.is_empty() && lock.importing.is_empty());
+        assert_eq!(lock.current_epoch, 4);
+        assert_eq!(lock.nodes[&myid].config_epoch, 4);
+        drop(lock);
+
+        state
+            .set_slot(5462, SlotState::Importing(other.clone()), false)
+            .unwrap();
+        state.set_slot(5462, SlotState::Stable, false).unwrap();
+        assert_eq!(
+            state.redirect(5462, false, true),
+            Some(Redirect::Moved(5462, other_addr))
+        );
+    }
+
+    #[test]
+    fn test_gossip() {
+        let localhost = IpAddr::from([127, 0, 0, 1]);
+        let node = |name: &str, port: u16| {
+            let state = ClusterState::new();
+            let mut lock = state.inner.lock().unwrap();
+            lock.myid = name.repeat(NODE_ID_SIZE);
+            let mut myself = Node::new(lock.myid.clone(), (localhost, port).into(), port + 1);
+            myself.connected = true;
+            lock.nodes.insert(myself.id.clone(), myself);
+            lock.enabled = true;
+            lock.node_timeout = Duration::from_millis(100);
+            drop(lock);
+            state
+        };
+        let (a, b, c) = (node("a", 7000), node("b", 7010), node("c", 7020));
+        let (b_id, c_id) = ("b".repeat(NODE_ID_SIZE), "c".repeat(NODE_ID_SIZE));
+        a.add_slots(&[0]).unwrap();
+        b.add_slots(&[1]).unwrap();
+        c.add_slots(&[2]).unwrap();
+
+        // a meets b, b learns a by MEET, a learns the id of b by PONG.
+        assert!(a.meet((localhost, 7010).into(), 7011));
+        assert!(!a.meet((localhost, 7010).into(), 7011));
+        let meet = a.link_up((localhost, 7011).into()).unwrap();
+        assert_eq!(meet.kind, MessageKind::Meet);
+        let pong = b.receive(meet, localhost, None).unwrap();
+        assert_eq!(pong.kind, MessageKind::Pong);
+        assert_eq!(b.info().known_nodes, 2);
+        assert_eq!(
+            b.redirect(0, false, false),
+            Some(Redirect::Moved(0, (localhost, 7000).into()))
+        );
+        assert_eq!(
+            a.receive(pong, localhost, Some((localhost, 7011).into())),
+            None
+        );
+        let ids = a.nodes().into_iter().map(|x| x.id).collect::<Vec<_>>();
+        assert_eq!(ids, ["a".repeat(NODE_ID_SIZE), b_id.clone()]);
+        assert_eq!(a.info().size, 2);
+
+        // c learns b by gossip from a.
+        assert!(a.meet((localhost, 7020).into(), 7021));
+        let meet = a.link_up((localhost, 7021).into()).unwrap();
+        let pong = c.receive(meet, localhost, None).unwrap();
+        a.receive(pong, localhost, Some((localhost, 7021).into()));
+        assert!(a
+            .describe_nodes()
+            .contains(&format!("{c_id} 127.0.0.1:7020@7021 master ")));
+        assert!(c
+            .describe_nodes()
+            .contains(" 127.0.0.1:7010@7011 handshake "));
+
+        // b stops answering, flagged fail? by a and c, then fail once a knows c agrees.
+        c.inner.lock().unwrap().nodes.retain(|_, x| !x.handshake);
+        let timeout = Instant::now() - Duration::from_secs(1);
+        for state in [&a, &c] {
+            let mut lock = state.inner.lock().unwrap();
+            let node = lock
+                .nodes
+                .entry(b_id.clone())
+                .or_insert_with(|| Node::new(b_id.clone(), (localhost, 7010).into(), 7011));
+            node.ping_sent = Some(timeout);
+        }
+        assert!(a.cron(1).fails.is_empty());
+        assert!(c.cron(1).fails.is_empty());
+        assert!(c
+            .describe_nodes()
+            .contains(&format!("{b_id} 127.0.0.1:7010@7011 master,fail? ")));
+        assert_eq!(a.info().slots_pfail, 1);
+        a.receive(c.message(MessageKind::Ping), localhost, None);
+        assert_eq!(a.cron(2).fails, [b_id.as_str()]);
+        assert_eq!(a.info().slots_fail, 1);
+        assert!(!a.info().ok);
+        c.receive(a.fail_message(&b_id), localhost, None);
+        assert!(c
+            .describe_nodes()
+            .contains(&format!("{b_id} 127.0.0.1:7010@7011 master,fail ")));
+    }
+}
@@ -0,0 +1,65 @@
+//! Hash slots of keys.
+//!
+//! Like redis, the keyspace is split into 16384 slots by CRC16 of keys, each slot is
+//! served by one node in the cluster.
+//!
+//! Keys with a hash tag, a non-empty part between the first `{` and the first `}`
+//! after it, are hashed by the tag only. So `{user1}.name` and `{user1}.age` are in
+//! the same slot and can be used together in multi-key commands.
+
+/// Count of hash slots.
+pub(crate) const SLOTS: usize = 16384;
+
+/// CRC16 of `data` in the XMODEM variant, the one used by redis cluster.
+pub(crate) fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// The part of `key` hashed into slots, the hash tag if any, otherwise the whole key.
+fn hash_tag(key: &[u8]) -> &[u8] {
+    let Some(start) = key.iter().position(|x| *x == b'{') else {
+        return key;
+    };
+    match key[start + 1..].iter().position(|x| *x == b'}') {
+        // Empty tags like `{}` do not count.
+        Some(0) | None => key,
+        Some(len) => &key[start + 1..start + 1 + len],
+    }
+}
+
+/// Hash slot of `key`.
+pub(crate) fn key_slot(key: &[u8]) -> u16 {
+    crc16(hash_tag(key)) & (SLOTS as u16 - 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_key_slot() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_slot(b""), 0);
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"bar"), 5061);
+        assert_eq!(key_slot(b"hello"), 866);
+
+        assert_eq!(hash_tag(b"{user1}.name"), b"user1");
+        assert_eq!(hash_tag(b"a{b}{c}"), b"b");
+        assert_eq!(hash_tag(b"a{{b}}"), b"{b");
+        assert_eq!(hash_tag(b"{}.a{b}"), b"{}.a{b}");
+        assert_eq!(hash_tag(b"a{b"), b"a{b");
+        assert_eq!(key_slot(b"{foo}.bar"), key_slot(b"foo"));
+    }
+}
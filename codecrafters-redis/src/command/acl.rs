@@ -0,0 +1,165 @@
+use serde_redis::{Array, BulkString, Integer, Map, SimpleError, SimpleString, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    state::ServerState,
+};
+
+use super::{command_categories, table::COMMAND_TABLE, CATEGORIES};
+
+fn bulk_array(values: impl IntoIterator<Item = impl Into<Vec<u8>>>) -> Value {
+    Value::Array(
+        values
+            .into_iter()
+            .map(|x| Value::BulkString(BulkString::new(x)))
+            .collect(),
+    )
+}
+
+pub(super) async fn handle_acl_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    state: &mut ServerState,
+) -> ServerResult<()> {
+    conn.log("run command ACL");
+    let subcommand = args
+        .pop_front_bulk_string()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "ACL",
+            args: args.clone(),
+        })?;
+    let invalid_args = ServerError::InvalidArgs {
+        cmd: "ACL",
+        args: args.clone(),
+    };
+    let mut rest = std::iter::from_fn(|| args.pop_front_bulk_string()).collect::<Vec<_>>();
+
+    let value = match subcommand.to_uppercase().as_str() {
+        "SETUSER" => {
+            // ACL SETUSER username [rule [rule ...]]
+            if rest.is_empty() {
+                return Err(invalid_args);
+            }
+            let name = rest.remove(0);
+            match state.acl().set_user(&name, &rest) {
+                Ok(()) => Value::SimpleString(SimpleString::new("OK")),
+                Err(e) => Value::SimpleError(SimpleError::with_prefix("ERR", e)),
+            }
+        }
+        "GETUSER" => {
+            // ACL GETUSER username
+            if rest.len() != 1 {
+                return Err(invalid_args);
+            }
+            match state.acl().user_info(&rest[0]) {
+                Some(info) => Value::Map(Map::with_entries(vec![
+                    (
+                        Value::BulkString(BulkString::new("flags")),
+                        bulk_array(info.flags),
+                    ),
+                    (
+                        Value::BulkString(BulkString::new("passwords")),
+                        bulk_array(info.passwords),
+                    ),
+                    (
+                        Value::BulkString(BulkString::new("commands")),
+                        Value::BulkString(BulkString::new(info.commands)),
+                    ),
+                    (
+                        Value::BulkString(BulkString::new("keys")),
+                        Value::BulkString(BulkString::new(info.keys)),
+                    ),
+                    (
+                        Value::BulkString(BulkString::new("channels")),
+                        Value::BulkString(BulkString::new(info.channels)),
+                    ),
+                ])),
+                None => Value::BulkString(BulkString::null()),
+            }
+        }
+        "DELUSER" => {
+            // ACL DELUSER username [username ...]
+            if rest.is_empty() {
+                return Err(invalid_args);
+            }
+            match state.acl().delete_users(&rest) {
+                Ok(count) => Value::Integer(Integer::new(count as i64)),
+                Err(e) => Value::SimpleError(SimpleError::with_prefix("ERR", e)),
+            }
+        }
+        "LIST" => {
+            if !rest.is_empty() {
+                return Err(invalid_args);
+            }
+            bulk_array(state.acl().list())
+        }
+        "USERS" => {
+            if !rest.is_empty() {
+                return Err(invalid_args);
+            }
+            bulk_array(state.acl().usernames())
+        }
+        "WHOAMI" => {
+            if !rest.is_empty() {
+                return Err(invalid_args);
+            }
+            Value::BulkString(BulkString::new(conn.user()))
+        }
+        "CAT" => {
+            // ACL CAT [category]
+            match rest.as_slice() {
+                [] => bulk_array(CATEGORIES.iter().copied()),
+                [category] => {
+                    let category = category.to_lowercase();
+                    if CATEGORIES.contains(&category.as_str()) {
+                        bulk_array(
+                            COMMAND_TABLE
+                                .iter()
+                                .filter(|x| command_categories(x.name).contains(&category.as_str()))
+                                .map(|x| x.name.to_lowercase()),
+                        )
+                    } else {
+                        Value::SimpleError(SimpleError::with_prefix(
+                            "ERR",
+                            format!("Unknown category '{category}'"),
+                        ))
+                    }
+                }
+                _ => return Err(invalid_args),
+            }
+        }
+        v @ ("SAVE" | "LOAD") => {
+            if !rest.is_empty() {
+                return Err(invalid_args);
+            }
+            let path = state.config().read(|x| x.aclfile.clone());
+            if path.is_empty() {
+                let value = Value::SimpleError(SimpleError::with_prefix(
+                    "ERR",
+                    "This Redis instance is not configured to use an ACL file. You may want to specify users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming you have a Redis configuration file set) in order to store users in the Redis configuration.",
+                ));
+                return conn.write_value(value).await;
+            }
+            let result = if v == "SAVE" {
+                let mut text = state.acl().list().join("\n");
+                text.push('\n');
+                std::fs::write(&path, text).map_err(|e| format!("There was an error trying to save the ACLs. Please check the server logs for more information: {e}"))
+            } else {
+                std::fs::read_to_string(&path)
+                    .map_err(|e| format!("Error loading ACLs, opening file '{path}': {e}"))
+                    .and_then(|text| state.acl().load(&text).map_err(|e| format!("{path}{e}")))
+            };
+            match result {
+                Ok(()) => Value::SimpleString(SimpleString::new("OK")),
+                Err(e) => Value::SimpleError(SimpleError::with_prefix("ERR", e)),
+            }
+        }
+        v => Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            format!("unknown subcommand '{v}'"),
+        )),
+    };
+
+    conn.write_value(value).await
+}
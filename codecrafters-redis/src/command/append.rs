@@ -0,0 +1,35 @@
+use serde_redis::{Array, Integer, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+pub(super) async fn handle_append_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command APPEND");
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "APPEND",
+            args: args.clone(),
+        })?;
+
+    let bytes = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "APPEND",
+            args: args.clone(),
+        })?;
+
+    let value = match storage.string_append(key, bytes) {
+        Ok(v) => Value::Integer(Integer::new(v as i64)),
+        Err(e) => e.to_message(),
+    };
+
+    conn.write_value(value).await
+}
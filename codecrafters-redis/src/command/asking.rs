@@ -0,0 +1,22 @@
+use serde_redis::{SimpleError, SimpleString, Value};
+
+use crate::{conn::Conn, error::ServerResult, state::ServerState};
+
+/// Handle ASKING, allow the next command on a slot importing to this node.
+pub(super) async fn handle_asking_command(
+    conn: &mut Conn<'_>,
+    state: &mut ServerState,
+) -> ServerResult<()> {
+    conn.log("run command ASKING");
+
+    let value = if state.cluster().is_enabled() {
+        conn.set_asking(true);
+        Value::SimpleString(SimpleString::new("OK"))
+    } else {
+        Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            "This instance has cluster support disabled",
+        ))
+    };
+    conn.write_value(value).await
+}
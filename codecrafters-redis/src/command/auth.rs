@@ -0,0 +1,57 @@
+use serde_redis::{Array, SimpleError, SimpleString, Value};
+
+use crate::{
+    acl::DEFAULT_USER,
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    state::ServerState,
+};
+
+/// Check the credentials in AUTH and HELLO AUTH.
+///
+/// Users are set by ACL SETUSER, the password of the default user is also set by
+/// `requirepass`. Like redis, any password is accepted for users with nopass.
+pub(super) fn authenticate(state: &ServerState, username: &str, password: &str) -> bool {
+    state.acl().authenticate(username, password)
+}
+
+/// Handle AUTH, authenticate the connection as the user.
+pub(super) async fn handle_auth_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    state: &mut ServerState,
+) -> ServerResult<()> {
+    conn.log("run command AUTH");
+
+    // AUTH [username] password
+    let (username, password) = match (
+        args.pop_front_bulk_string(),
+        args.pop_front_bulk_string(),
+        args.is_empty(),
+    ) {
+        (Some(password), None, true) => {
+            if state.acl().is_default_nopass() {
+                let value = Value::SimpleError(SimpleError::with_prefix(
+                    "ERR",
+                    "AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?",
+                ));
+                return conn.write_value(value).await;
+            }
+            (DEFAULT_USER.to_string(), password)
+        }
+        (Some(username), Some(password), true) => (username, password),
+        _ => return Err(ServerError::InvalidArgs { cmd: "AUTH", args }),
+    };
+
+    let value = if authenticate(state, &username, &password) {
+        conn.set_user(username);
+        conn.set_authenticated(true);
+        Value::SimpleString(SimpleString::new("OK"))
+    } else {
+        Value::SimpleError(SimpleError::with_prefix(
+            "WRONGPASS",
+            "invalid username-password pair or user is disabled.",
+        ))
+    };
+    conn.write_value(value).await
+}
@@ -0,0 +1,35 @@
+use serde_redis::{Array, SimpleError, SimpleString, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    state::ServerState,
+};
+
+/// Handle BGREWRITEAOF, compact the AOF in background.
+pub(super) async fn handle_bgrewriteaof_command(
+    conn: &mut Conn<'_>,
+    args: Array,
+    state: &mut ServerState,
+) -> ServerResult<()> {
+    conn.log("run command BGREWRITEAOF");
+
+    if !args.is_empty() {
+        return Err(ServerError::InvalidArgs {
+            cmd: "BGREWRITEAOF",
+            args,
+        });
+    }
+
+    let value = if state.bgrewriteaof() {
+        Value::SimpleString(SimpleString::new(
+            "Background append only file rewriting started",
+        ))
+    } else {
+        Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            "Background append only file rewriting already in progress",
+        ))
+    };
+    conn.write_value(value).await
+}
@@ -0,0 +1,54 @@
+use serde_redis::{Array, Integer, Value};
+
+use crate::{
+    command::set::syntax_error,
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{BitUnit, OpError, Storage},
+};
+
+/// Parse the optional `BYTE` or `BIT` unit of bitmap ranges, `BYTE` by default.
+pub(super) fn parse_bit_unit(value: Option<String>) -> Option<BitUnit> {
+    match value {
+        None => Some(BitUnit::Byte),
+        Some(v) if v.eq_ignore_ascii_case("BYTE") => Some(BitUnit::Byte),
+        Some(v) if v.eq_ignore_ascii_case("BIT") => Some(BitUnit::Bit),
+        Some(..) => None,
+    }
+}
+
+pub(super) async fn handle_bitcount_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command BITCOUNT");
+
+    // BITCOUNT key [start end [BYTE | BIT]]
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "BITCOUNT",
+            args: args.clone(),
+        })?;
+    let range = match (args.pop_front_bulk_string(), args.pop_front_bulk_string()) {
+        (None, _) => None,
+        (Some(start), Some(end)) => {
+            let unit = match parse_bit_unit(args.pop_front_bulk_string()) {
+                Some(v) if args.is_empty() => v,
+                _ => return conn.write_value(syntax_error()).await,
+            };
+            match (start.parse::<i64>(), end.parse::<i64>()) {
+                (Ok(start), Ok(end)) => Some((start, end, unit)),
+                _ => return conn.write_value(OpError::InvalidInteger.to_message()).await,
+            }
+        }
+        (Some(..), None) => return conn.write_value(syntax_error()).await,
+    };
+
+    let value = match storage.string_bit_count(key, range) {
+        Ok(v) => Value::Integer(Integer::new(v as i64)),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
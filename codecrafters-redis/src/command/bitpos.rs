@@ -0,0 +1,61 @@
+use serde_redis::{Array, Integer, SimpleError, Value};
+
+use crate::{
+    command::{bitcount::parse_bit_unit, set::syntax_error},
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{OpError, Storage},
+};
+
+pub(super) async fn handle_bitpos_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command BITPOS");
+
+    // BITPOS key bit [start [end [BYTE | BIT]]]
+    let (key, bit) = match (
+        args.pop_front_bulk_string_bytes(),
+        args.pop_front_bulk_string(),
+    ) {
+        (Some(a), Some(b)) => (a, b),
+        _ => {
+            return Err(ServerError::InvalidArgs {
+                cmd: "BITPOS",
+                args: args.clone(),
+            })
+        }
+    };
+    let bit = match bit.as_str() {
+        "0" => false,
+        "1" => true,
+        _ => {
+            let value = Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                "The bit argument must be 1 or 0.",
+            ));
+            return conn.write_value(value).await;
+        }
+    };
+    let start = match args.pop_front_bulk_string().map(|x| x.parse::<i64>()) {
+        None => 0,
+        Some(Ok(v)) => v,
+        Some(Err(..)) => return conn.write_value(OpError::InvalidInteger.to_message()).await,
+    };
+    let end = match args.pop_front_bulk_string().map(|x| x.parse::<i64>()) {
+        None => None,
+        Some(Ok(v)) => Some(v),
+        Some(Err(..)) => return conn.write_value(OpError::InvalidInteger.to_message()).await,
+    };
+    let unit = match parse_bit_unit(args.pop_front_bulk_string()) {
+        Some(v) if args.is_empty() => v,
+        _ => return conn.write_value(syntax_error()).await,
+    };
+
+    let value = match storage.string_bit_pos(key, bit, start, end, unit) {
+        Ok(v) => Value::Integer(Integer::new(v)),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
@@ -1,98 +1,83 @@
-use std::time::Duration;
-
-use serde_redis::{Array, BulkString, SimpleError, Value};
+use serde_redis::{Array, BulkString, Value};
 
 use crate::{
+    blocking::{wait_fed, BlockKind, Unblock},
+    command::{bulk_reply, effect_command, lmove::parse_block_timeout},
     conn::Conn,
     error::{ServerError, ServerResult},
-    storage::{LpopBlockedTask, OpError, Storage},
+    state::ServerState,
+    storage::ListBlockedTask,
 };
 
+/// Handle BLPOP and BRPOP.
+///
+/// Set `tail` to true to pop from the tail of list (BRPOP).
 pub(super) async fn handle_blpop_command(
     conn: &mut Conn<'_>,
     mut args: Array,
-    storage: &mut Storage,
-) -> ServerResult<()> {
-    conn.log("run command BLPOP");
-    conn.log("BLPOP");
-
-    let key = args
-        .pop_front_bulk_string()
-        .ok_or_else(|| ServerError::InvalidArgs {
-            cmd: "BLPOP",
-            args: args.clone(),
-        })?;
+    state: &mut ServerState,
+    tail: bool,
+) -> ServerResult<Vec<Array>> {
+    let cmd = if tail { "BRPOP" } else { "BLPOP" };
+    conn.log(format!("run command {cmd}"));
 
-    if args.is_empty() {
-        let value = Value::SimpleError(SimpleError::with_prefix("EARG", "empty list args"));
-        conn.write_value(value).await?;
-        return Ok(());
-    }
-
-    let block_duration = match args.pop_front_bulk_string() {
-        Some(s) if s.as_str() == "0" => None,
-        Some(s) => match s.parse::<f64>() {
-            Ok(v) => Some(Duration::from_secs_f64(v)),
-            Err(e) => {
-                let value = Value::SimpleError(SimpleError::with_prefix(
-                    "EARG",
-                    format!("faied to parse timeout duration: {e}"),
-                ));
-                conn.write_value(value).await?;
-                return Ok(());
-            }
-        },
-        None => todo!(),
+    // BLPOP key [key ...] timeout
+    let mut keys = std::iter::from_fn(|| args.pop_front_bulk_string_bytes()).collect::<Vec<_>>();
+    let timeout = match keys.pop() {
+        Some(timeout) if !keys.is_empty() => timeout,
+        _ => return Err(ServerError::InvalidArgs { cmd, args }),
     };
-
-    args.pop_front_bulk_string().and_then(|s| {
-        if s == "0" {
-            None
-        } else {
-            s.parse::<f64>()
-                .ok()
-                .map(|d| Duration::from_secs((d * 1000.0) as u64))
+    let block_duration = match parse_block_timeout(&String::from_utf8_lossy(&timeout)) {
+        Ok(v) => v,
+        Err(e) => {
+            conn.write_value(e).await?;
+            return Ok(vec![]);
         }
-    });
+    };
 
-    let content = match storage.array_pop_front(key.clone(), None) {
-        Ok(Some(v)) => v,
-        Ok(None) | Err(OpError::KeyAbsent) => {
-            // No value in list, block here.
-            let (task, recver) = LpopBlockedTask::new(key.clone());
-            storage.lpop_add_block_task(task);
+    // Only sync the pop when the element is popped here.
+    //
+    // If the element is given directly by a push, the push command syncs the pop.
+    let mut effects = vec![];
+    let content = match state.storage().list_mpop(&keys, tail, 1) {
+        Ok(Some((key, mut values))) => {
+            let pop = if tail { "RPOP" } else { "LPOP" };
+            effects.push(effect_command([pop.as_bytes(), &key]));
+            Value::Array(Array::with_values(vec![
+                Value::BulkString(BulkString::new(key)),
+                bulk_reply(values.pop()),
+            ]))
+        }
+        Ok(None) => {
+            // No value in any list, block here until one of them is pushed.
+            let (task, recver) = ListBlockedTask::new_multi(keys, tail, None);
+            let _task = state.storage_mut().list_add_block_task(conn.id, task);
 
             conn.log(format!(
-                "BLPOP: value not present, blocking connection for {block_duration:?}"
+                "{cmd}: value not present, blocking connection for {block_duration:?}"
             ));
-            let wait_result = match block_duration {
-                Some(d) => {
-                    // Wait for some time.
-                    match tokio::time::timeout(d, async { recver.await }).await {
-                        Ok(Ok(v)) => Some(v),
-                        Ok(Err(..)) | Err(_) =>
-                        /* Timeout */
-                        {
-                            None
-                        }
-                    }
-                }
-                None => {
-                    // Wait forever.
-                    recver.await.map(Some).unwrap()
+            let wait = wait_fed(recver, block_duration);
+            let wait_result = match state.blocking().block_on(conn, BlockKind::Keys, wait).await {
+                Ok(v) => v,
+                Err(Unblock::Timeout) => None,
+                Err(Unblock::Error(e)) => {
+                    conn.write_value(e).await?;
+                    return Ok(effects);
                 }
             };
 
             match wait_result {
-                Some(v) => Value::Array(Array::with_values(vec![
+                Some((key, Ok(mut values))) => Value::Array(Array::with_values(vec![
                     Value::BulkString(BulkString::new(key)),
-                    v,
+                    bulk_reply(values.pop()),
                 ])),
+                Some((_, Err(e))) => e.to_message(),
                 None => Value::Array(Array::null()),
             }
         }
         Err(e) => e.to_message(),
     };
 
-    conn.write_value(content).await
+    conn.write_value(content).await?;
+    Ok(effects)
 }
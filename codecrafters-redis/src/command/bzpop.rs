@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+use serde_redis::{Array, BulkString, SimpleError, Value};
+
+use crate::{
+    blocking::{wait_fed, BlockKind, Unblock},
+    command::effect_command,
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    state::ServerState,
+    storage::{format_score, OpError, ZpopBlockedTask},
+};
+
+/// Handle BZPOPMIN, or BZPOPMAX if `max` is true.
+///
+/// Return the effects to sync to replica, a ZPOPMIN or ZPOPMAX if popped without
+/// blocking. Members fed while blocked are synced by the command adding them.
+pub(super) async fn handle_bzpop_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    state: &mut ServerState,
+    max: bool,
+) -> ServerResult<Vec<Array>> {
+    let (cmd, pop) = if max {
+        ("BZPOPMAX", "ZPOPMAX")
+    } else {
+        ("BZPOPMIN", "ZPOPMIN")
+    };
+    conn.log(format!("run command {cmd}"));
+
+    // The last argument is timeout.
+    let timeout = match args.pop() {
+        Some(Value::BulkString(mut s)) => s
+            .take()
+            .and_then(|x| String::from_utf8(x).ok())
+            .ok_or_else(|| ServerError::InvalidArgs {
+                cmd,
+                args: args.clone(),
+            })?,
+        _ => {
+            return Err(ServerError::InvalidArgs {
+                cmd,
+                args: args.clone(),
+            })
+        }
+    };
+
+    let mut keys = vec![];
+    while let Some(key) = args.pop_front_bulk_string_bytes() {
+        keys.push(key);
+    }
+    if keys.is_empty() {
+        return Err(ServerError::InvalidArgs {
+            cmd,
+            args: args.clone(),
+        });
+    }
+
+    let block_duration = match timeout.parse::<f64>() {
+        Ok(v) if v > 0.0 => Some(Duration::from_secs_f64(v)),
+        Ok(v) if v >= 0.0 => None,
+        _ => {
+            let value = Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                "timeout is not a float or out of range",
+            ));
+            conn.write_value(value).await?;
+            return Ok(vec![]);
+        }
+    };
+
+    // Check all keys in order, pop from the first non-empty one.
+    for key in keys.iter() {
+        match state.storage_mut().zset_pop(key, None, max) {
+            Ok(mut members) if !members.is_empty() => {
+                let (member, score) = members.pop().unwrap();
+                let value = Value::Array(Array::with_values(vec![
+                    Value::BulkString(BulkString::new(key.as_slice())),
+                    Value::BulkString(BulkString::new(member)),
+                    Value::BulkString(BulkString::new(format_score(score))),
+                ]));
+                conn.write_value(value).await?;
+                return Ok(vec![effect_command([pop.as_bytes(), key])]);
+            }
+            Ok(..) | Err(OpError::KeyAbsent) => continue,
+            Err(e) => {
+                conn.write_value(e.to_message()).await?;
+                return Ok(vec![]);
+            }
+        }
+    }
+
+    // No member in any sorted set, block here.
+    let (task, recver) = ZpopBlockedTask::new(keys, max);
+    let _task = state.storage_mut().zpop_add_block_task(conn.id, task);
+
+    conn.log(format!(
+        "{cmd}: value not present, blocking connection for {block_duration:?}"
+    ));
+    let wait = wait_fed(recver, block_duration);
+    let wait_result = match state.blocking().block_on(conn, BlockKind::Keys, wait).await {
+        Ok(v) => v,
+        Err(Unblock::Timeout) => None,
+        Err(Unblock::Error(e)) => {
+            conn.write_value(e).await?;
+            return Ok(vec![]);
+        }
+    };
+
+    let value = match wait_result {
+        Some((key, member, score)) => Value::Array(Array::with_values(vec![
+            Value::BulkString(BulkString::new(key)),
+            Value::BulkString(BulkString::new(member)),
+            Value::BulkString(BulkString::new(format_score(score))),
+        ])),
+        None => Value::Array(Array::null()),
+    };
+
+    conn.write_value(value).await?;
+    Ok(vec![])
+}
@@ -0,0 +1,461 @@
+use std::time::Duration;
+
+use serde_redis::{Array, BulkString, Integer, Map, SimpleError, SimpleString, Value};
+use tokio::time::Instant;
+
+use crate::{
+    blocking::Unblock,
+    clients::ClientInfo,
+    command::set::syntax_error,
+    conn::{Conn, ReplyMode},
+    error::{ServerError, ServerResult},
+    pause::PauseMode,
+    pubsub::SubscriptionKind,
+    state::ServerState,
+    tracking::TrackingOptions,
+};
+
+/// Parse the arguments of CLIENT TRACKING.
+///
+/// Return `Ok(None)` if turning tracking off, or the error replied.
+fn parse_tracking(mut args: Array, state: &ServerState) -> Result<Option<TrackingOptions>, Value> {
+    // CLIENT TRACKING <ON | OFF> [REDIRECT client-id] [PREFIX prefix [PREFIX prefix ...]]
+    //   [BCAST]
+    let on = match args.pop_front_bulk_string() {
+        Some(v) if v.eq_ignore_ascii_case("ON") => true,
+        Some(v) if v.eq_ignore_ascii_case("OFF") => false,
+        _ => return Err(syntax_error()),
+    };
+    let mut options = TrackingOptions::default();
+    while let Some(option) = args.pop_front_bulk_string() {
+        match option.to_uppercase().as_str() {
+            "REDIRECT" => {
+                let id = args
+                    .pop_front_bulk_string()
+                    .and_then(|x| x.parse::<usize>().ok())
+                    .ok_or_else(|| {
+                        Value::SimpleError(SimpleError::with_prefix("ERR", "Invalid client ID"))
+                    })?;
+                if !state.tracking().is_connected(id) {
+                    return Err(Value::SimpleError(SimpleError::with_prefix(
+                        "ERR",
+                        "The client ID you want redirect to does not exist",
+                    )));
+                }
+                options.redirect = Some(id);
+            }
+            "PREFIX" => options
+                .prefixes
+                .push(args.pop_front_bulk_string().ok_or_else(syntax_error)?),
+            "BCAST" => options.bcast = true,
+            _ => return Err(syntax_error()),
+        }
+    }
+    if !on {
+        return Ok(None);
+    }
+    if !options.prefixes.is_empty() && !options.bcast {
+        return Err(Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            "PREFIX option requires BCAST mode to be enabled",
+        )));
+    }
+    Ok(Some(options))
+}
+
+/// Build the reply of CLIENT TRACKINGINFO for connection `id`.
+fn tracking_info(id: usize, state: &ServerState) -> Value {
+    let options = state.tracking().options(id);
+    let mut flags = vec![];
+    let redirect = match &options {
+        Some(options) => {
+            flags.push("on");
+            if options.bcast {
+                flags.push("bcast");
+            }
+            match options.redirect {
+                Some(v) if !state.tracking().is_connected(v) => {
+                    flags.push("broken_redirect");
+                    v as i64
+                }
+                Some(v) => v as i64,
+                None => 0,
+            }
+        }
+        None => {
+            flags.push("off");
+            -1
+        }
+    };
+    let flags = flags
+        .into_iter()
+        .map(|x| Value::BulkString(BulkString::new(x)))
+        .collect::<Array>();
+    let prefixes = options
+        .map(|x| x.prefixes)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|x| Value::BulkString(BulkString::new(x)))
+        .collect::<Array>();
+    Value::Map(Map::with_entries(vec![
+        (
+            Value::BulkString(BulkString::new("flags")),
+            Value::Array(flags),
+        ),
+        (
+            Value::BulkString(BulkString::new("redirect")),
+            Value::Integer(Integer::new(redirect)),
+        ),
+        (
+            Value::BulkString(BulkString::new("prefixes")),
+            Value::Array(prefixes),
+        ),
+    ]))
+}
+
+/// Describe connection `id` in a line of CLIENT LIST, without the line ending.
+fn describe_client(id: usize, info: &ClientInfo, state: &ServerState) -> String {
+    let now = Instant::now();
+    let mut flags = String::new();
+    if info.monitor {
+        flags.push('O');
+    }
+    if state.pubsub().is_subscribed(id) {
+        flags.push('P');
+    }
+    if info.multi.is_some() {
+        flags.push('x');
+    }
+    if state.blocking().is_blocked(id) {
+        flags.push('b');
+    }
+    if info.no_evict {
+        flags.push('e');
+    }
+    if flags.is_empty() {
+        flags.push('N');
+    }
+    let pubsub = state.pubsub();
+    format!(
+        "id={id} addr={} laddr={} name={} age={} idle={} flags={flags} db=0 sub={} psub={} ssub={} multi={} user={} resp={} cmd={}",
+        info.addr,
+        info.laddr,
+        info.name,
+        (now - info.created).as_secs(),
+        (now - info.last_interaction).as_secs(),
+        pubsub.subscription_count(id, SubscriptionKind::Channel),
+        pubsub.subscription_count(id, SubscriptionKind::Pattern),
+        pubsub.subscription_count(id, SubscriptionKind::Shard),
+        info.multi.map_or(-1, |x| x as i64),
+        info.user,
+        info.resp,
+        info.last_command,
+    )
+}
+
+/// Keep connections of type `kind` in `ids`, return the error replied if the type is
+/// unknown.
+fn retain_type(ids: &mut Vec<usize>, kind: &str, state: &ServerState) -> Result<(), Value> {
+    match kind.to_lowercase().as_str() {
+        "normal" => ids.retain(|x| !state.pubsub().is_subscribed(*x)),
+        "pubsub" => ids.retain(|x| state.pubsub().is_subscribed(*x)),
+        // Master link and replicas are not registered as clients.
+        "master" | "replica" | "slave" => ids.clear(),
+        _ => {
+            return Err(Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                format!("Unknown client type '{kind}'"),
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// Parse the filters of CLIENT KILL sent by connection `id`, return the ids of
+/// connections to kill.
+///
+/// Return the error replied if filters are invalid.
+fn parse_kill_filters(
+    mut args: Array,
+    id: usize,
+    state: &ServerState,
+) -> Result<Vec<usize>, Value> {
+    // CLIENT KILL <ID client-id | TYPE <NORMAL | MASTER | REPLICA | PUBSUB> | USER username
+    //   | ADDR ip:port | LADDR ip:port | SKIPME <YES | NO>> [...]
+    let clients = state.clients().list();
+    let mut ids = clients.iter().map(|(id, _)| *id).collect::<Vec<_>>();
+    let mut skipme = true;
+    while let Some(option) = args.pop_front_bulk_string() {
+        let value = args.pop_front_bulk_string().ok_or_else(syntax_error)?;
+        let info = |x: &usize| clients.iter().find(|(id, _)| id == x).map(|(_, info)| info);
+        match option.to_uppercase().as_str() {
+            "ID" => {
+                let target = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|x| *x > 0)
+                    .ok_or_else(|| {
+                        Value::SimpleError(SimpleError::with_prefix(
+                            "ERR",
+                            "client-id should be greater than 0",
+                        ))
+                    })?;
+                ids.retain(|x| *x == target);
+            }
+            "TYPE" => retain_type(&mut ids, &value, state)?,
+            "USER" => ids.retain(|x| info(x).is_some_and(|x| x.user == value)),
+            "ADDR" => ids.retain(|x| info(x).is_some_and(|x| x.addr == value)),
+            "LADDR" => ids.retain(|x| info(x).is_some_and(|x| x.laddr == value)),
+            "SKIPME" => match value.to_lowercase().as_str() {
+                "yes" => skipme = true,
+                "no" => skipme = false,
+                _ => return Err(syntax_error()),
+            },
+            _ => return Err(syntax_error()),
+        }
+    }
+    if skipme {
+        ids.retain(|x| *x != id);
+    }
+    Ok(ids)
+}
+
+/// Parse the filters of CLIENT LIST, return the ids of connections to list.
+///
+/// Return the error replied if filters are invalid.
+fn parse_list_filters(mut args: Array, state: &ServerState) -> Result<Vec<usize>, Value> {
+    // CLIENT LIST [TYPE <NORMAL | MASTER | REPLICA | PUBSUB>] [ID client-id [client-id ...]]
+    let mut ids = state
+        .clients()
+        .list()
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect::<Vec<_>>();
+    while let Some(option) = args.pop_front_bulk_string() {
+        match option.to_uppercase().as_str() {
+            "TYPE" => {
+                let kind = args.pop_front_bulk_string().ok_or_else(syntax_error)?;
+                retain_type(&mut ids, &kind, state)?;
+            }
+            "ID" => {
+                let mut filter = vec![];
+                while let Some(id) = args.pop_front_bulk_string() {
+                    match id.parse::<usize>() {
+                        Ok(v) if v > 0 => filter.push(v),
+                        _ => {
+                            return Err(Value::SimpleError(SimpleError::with_prefix(
+                                "ERR",
+                                "Invalid client ID",
+                            )))
+                        }
+                    }
+                }
+                if filter.is_empty() {
+                    return Err(syntax_error());
+                }
+                ids.retain(|x| filter.contains(x));
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    Ok(ids)
+}
+
+pub(super) async fn handle_client_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    state: &mut ServerState,
+) -> ServerResult<()> {
+    conn.log("run command CLIENT");
+    let subcommand = args
+        .pop_front_bulk_string()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "CLIENT",
+            args: args.clone(),
+        })?;
+
+    let value = match subcommand.to_uppercase().as_str() {
+        "ID" => Value::Integer(Integer::new(conn.id as i64)),
+        "SETNAME" => {
+            // CLIENT SETNAME connection-name
+            let Some(name) = args.pop_front_bulk_string() else {
+                return Err(ServerError::InvalidArgs {
+                    cmd: "CLIENT",
+                    args,
+                });
+            };
+            // Like redis, names are shown in CLIENT LIST separated by spaces.
+            if name.chars().any(|x| !x.is_ascii_graphic()) {
+                Value::SimpleError(SimpleError::with_prefix(
+                    "ERR",
+                    "Client names cannot contain spaces, newlines or special characters.",
+                ))
+            } else {
+                state.clients().update(conn.id, |x| x.name = name);
+                Value::SimpleString(SimpleString::new("OK"))
+            }
+        }
+        "GETNAME" => match state.clients().get(conn.id) {
+            Some(info) if !info.name.is_empty() => Value::BulkString(BulkString::new(info.name)),
+            _ => Value::BulkString(BulkString::null()),
+        },
+        "LIST" => match parse_list_filters(args, state) {
+            Ok(ids) => {
+                let mut text = String::new();
+                for id in ids {
+                    if let Some(info) = state.clients().get(id) {
+                        text.push_str(&describe_client(id, &info, state));
+                        text.push('\n');
+                    }
+                }
+                Value::BulkString(BulkString::new(text))
+            }
+            Err(e) => e,
+        },
+        "INFO" => match state.clients().get(conn.id) {
+            Some(info) => {
+                let mut text = describe_client(conn.id, &info, state);
+                text.push('\n');
+                Value::BulkString(BulkString::new(text))
+            }
+            None => Value::BulkString(BulkString::new("")),
+        },
+        "KILL" => {
+            let (ids, legacy) = if args.len() == 1 {
+                // CLIENT KILL ip:port, the old form kills the client itself if matches.
+                let addr = args.pop_front_bulk_string().unwrap_or_default();
+                let ids = state
+                    .clients()
+                    .list()
+                    .into_iter()
+                    .filter(|(_, info)| info.addr == addr)
+                    .map(|(id, _)| id)
+                    .collect::<Vec<_>>();
+                (ids, true)
+            } else {
+                match parse_kill_filters(args, conn.id, state) {
+                    Ok(ids) => (ids, false),
+                    Err(e) => return conn.write_value(e).await,
+                }
+            };
+            let killed = state.clients().kill(&ids);
+            // Blocked connections only see the kill after the command ends.
+            for id in &killed {
+                state.blocking().unblock(*id, Unblock::Timeout);
+            }
+            conn.log(format!("CLIENT KILL {killed:?}"));
+            match (legacy, killed.is_empty()) {
+                (true, true) => {
+                    Value::SimpleError(SimpleError::with_prefix("ERR", "No such client"))
+                }
+                (true, false) => Value::SimpleString(SimpleString::new("OK")),
+                (false, _) => Value::Integer(Integer::new(killed.len() as i64)),
+            }
+        }
+        "REPLY" => {
+            // CLIENT REPLY <ON | OFF | SKIP>
+            let mode = match args.pop_front_bulk_string() {
+                Some(v) if v.eq_ignore_ascii_case("ON") => ReplyMode::On,
+                Some(v) if v.eq_ignore_ascii_case("OFF") => ReplyMode::Off,
+                Some(v) if v.eq_ignore_ascii_case("SKIP") => ReplyMode::SkipNext,
+                _ => return conn.write_value(syntax_error()).await,
+            };
+            conn.set_reply_mode(mode);
+            // Suppressed unless turning on.
+            Value::SimpleString(SimpleString::new("OK"))
+        }
+        "NO-EVICT" => {
+            // CLIENT NO-EVICT <ON | OFF>
+            let no_evict = match args.pop_front_bulk_string() {
+                Some(v) if v.eq_ignore_ascii_case("ON") => true,
+                Some(v) if v.eq_ignore_ascii_case("OFF") => false,
+                _ => return conn.write_value(syntax_error()).await,
+            };
+            state.clients().update(conn.id, |x| x.no_evict = no_evict);
+            Value::SimpleString(SimpleString::new("OK"))
+        }
+        "PAUSE" => {
+            // CLIENT PAUSE timeout [WRITE | ALL]
+            let timeout = match args
+                .pop_front_bulk_string()
+                .and_then(|x| x.parse::<u64>().ok())
+            {
+                Some(v) => Duration::from_millis(v),
+                None => {
+                    let value = Value::SimpleError(SimpleError::with_prefix(
+                        "ERR",
+                        "timeout is not an integer or out of range",
+                    ));
+                    return conn.write_value(value).await;
+                }
+            };
+            let mode = match args.pop_front_bulk_string() {
+                None => PauseMode::All,
+                Some(v) if v.eq_ignore_ascii_case("ALL") => PauseMode::All,
+                Some(v) if v.eq_ignore_ascii_case("WRITE") => PauseMode::Write,
+                Some(..) => {
+                    let value = Value::SimpleError(SimpleError::with_prefix("ERR", "syntax error"));
+                    return conn.write_value(value).await;
+                }
+            };
+            conn.log(format!("CLIENT PAUSE {timeout:?} {mode:?}"));
+            state.pause().pause(mode, Instant::now() + timeout);
+            Value::SimpleString(SimpleString::new("OK"))
+        }
+        "UNPAUSE" => {
+            state.pause().unpause();
+            Value::SimpleString(SimpleString::new("OK"))
+        }
+        "TRACKING" => match parse_tracking(args, state) {
+            Ok(options) => {
+                conn.log(format!("CLIENT TRACKING {options:?}"));
+                match options {
+                    Some(options) => state.tracking().enable(conn.id, options),
+                    None => state.tracking().disable(conn.id),
+                }
+                Value::SimpleString(SimpleString::new("OK"))
+            }
+            Err(e) => e,
+        },
+        "TRACKINGINFO" => tracking_info(conn.id, state),
+        "UNBLOCK" => {
+            // CLIENT UNBLOCK client-id [TIMEOUT | ERROR]
+            let id = match args
+                .pop_front_bulk_string()
+                .and_then(|x| x.parse::<usize>().ok())
+            {
+                Some(v) => v,
+                None => {
+                    let value = Value::SimpleError(SimpleError::with_prefix(
+                        "ERR",
+                        "value is not an integer or out of range",
+                    ));
+                    return conn.write_value(value).await;
+                }
+            };
+            let unblock = match args.pop_front_bulk_string() {
+                None => Unblock::Timeout,
+                Some(v) if v.eq_ignore_ascii_case("TIMEOUT") => Unblock::Timeout,
+                Some(v) if v.eq_ignore_ascii_case("ERROR") => {
+                    Unblock::error("client unblocked via CLIENT UNBLOCK")
+                }
+                Some(..) => {
+                    let value = Value::SimpleError(SimpleError::with_prefix(
+                        "ERR",
+                        "CLIENT UNBLOCK reason should be TIMEOUT or ERROR",
+                    ));
+                    return conn.write_value(value).await;
+                }
+            };
+            conn.log(format!("CLIENT UNBLOCK {id} {unblock:?}"));
+            let unblocked = state.blocking().unblock(id, unblock);
+            Value::Integer(Integer::new(unblocked as i64))
+        }
+        v => Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            format!("unknown subcommand '{v}'"),
+        )),
+    };
+
+    conn.write_value(value).await
+}
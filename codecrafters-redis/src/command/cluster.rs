@@ -0,0 +1,316 @@
+use std::net::{IpAddr, SocketAddr};
+
+use serde_redis::{Array, BulkString, Integer, Map, SimpleError, SimpleString, Value};
+
+use crate::{
+    cluster::{key_slot, parse_slot, Node, SlotState, BUS_PORT_OFFSET, SLOTS},
+    conn::Conn,
+    error::ServerResult,
+    replication::ReplicationState,
+    state::ServerState,
+};
+
+/// Parse slots in `args` for CLUSTER ADDSLOTS and DELSLOTS, or slot ranges for
+/// ADDSLOTSRANGE and DELSLOTSRANGE if `range`.
+///
+/// Return the error replied if any slot is invalid or specified more than once.
+fn parse_slots(args: Vec<String>, range: bool) -> Result<Vec<u16>, Value> {
+    let error = |message: String| Value::SimpleError(SimpleError::with_prefix("ERR", message));
+    let slots = args
+        .iter()
+        .map(|x| parse_slot(x).ok_or_else(|| error("Invalid or out of range slot".to_string())))
+        .collect::<Result<Vec<_>, _>>()?;
+    let slots = if range {
+        let mut all = vec![];
+        for pair in slots.chunks(2) {
+            let (start, end) = (pair[0], pair[1]);
+            if start > end {
+                return Err(error(format!(
+                    "start slot number {start} is greater than end slot number {end}"
+                )));
+            }
+            all.extend(start..=end);
+        }
+        all
+    } else {
+        slots
+    };
+    let mut seen = vec![false; SLOTS];
+    for slot in &slots {
+        if std::mem::replace(&mut seen[*slot as usize], true) {
+            return Err(error(format!("Slot {slot} specified multiple times")));
+        }
+    }
+    Ok(slots)
+}
+
+/// Parse the address of CLUSTER MEET, `args` are ip, port and the optional bus port.
+///
+/// Return the error replied if invalid.
+fn parse_meet(args: &[String]) -> Result<(SocketAddr, u16), Value> {
+    let error = |message: String| Value::SimpleError(SimpleError::with_prefix("ERR", message));
+    let port = args[1]
+        .parse::<u16>()
+        .map_err(|_| error(format!("Invalid base port specified: {}", args[1])))?;
+    let cport = match args.get(2) {
+        Some(v) => v
+            .parse::<u16>()
+            .map_err(|_| error(format!("Invalid bus port specified: {v}")))?,
+        None => port
+            .checked_add(BUS_PORT_OFFSET)
+            .ok_or_else(|| error(format!("Invalid base port specified: {port}")))?,
+    };
+    let ip = args[0].parse::<IpAddr>().map_err(|_| {
+        error(format!(
+            "Invalid node address specified: {}:{}",
+            args[0], port
+        ))
+    })?;
+    Ok((SocketAddr::new(ip, port), cport))
+}
+
+/// Parse the state of CLUSTER SETSLOT, `args` are the action and the node id.
+///
+/// Return `None` if the action is unknown or the node id is missing.
+fn parse_slot_state(args: &[String]) -> Option<SlotState> {
+    let action = args.first()?.to_uppercase();
+    let state = match (action.as_str(), args.get(1)) {
+        ("IMPORTING", Some(id)) => SlotState::Importing(id.clone()),
+        ("MIGRATING", Some(id)) => SlotState::Migrating(id.clone()),
+        ("NODE", Some(id)) => SlotState::Node(id.clone()),
+        ("STABLE", None) => SlotState::Stable,
+        _ => return None,
+    };
+    Some(state)
+}
+
+/// Describe `node` in a reply of CLUSTER SLOTS.
+fn slots_node(node: &Node) -> Value {
+    Value::Array(Array::with_values(vec![
+        Value::BulkString(BulkString::new(node.addr.ip().to_string())),
+        Value::Integer(Integer::new(node.addr.port() as i64)),
+        Value::BulkString(BulkString::new(node.id.clone())),
+    ]))
+}
+
+/// Describe `node` in a reply of CLUSTER SHARDS, `offset` is the replication offset.
+fn shards_node(node: &Node, offset: usize) -> Value {
+    let entry = |name: &str, value: Value| (Value::BulkString(BulkString::new(name)), value);
+    let bulk = |value: String| Value::BulkString(BulkString::new(value));
+    Value::Map(Map::with_entries(vec![
+        entry("id", bulk(node.id.clone())),
+        entry(
+            "port",
+            Value::Integer(Integer::new(node.addr.port() as i64)),
+        ),
+        entry("ip", bulk(node.addr.ip().to_string())),
+        entry("endpoint", bulk(node.addr.ip().to_string())),
+        entry("role", bulk("master".to_string())),
+        entry(
+            "replication-offset",
+            Value::Integer(Integer::new(offset as i64)),
+        ),
+        entry("health", bulk("online".to_string())),
+    ]))
+}
+
+/// Handle CLUSTER, inspect and configure the nodes and slots in cluster mode.
+pub(super) async fn handle_cluster_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    rep: ReplicationState,
+    state: &ServerState,
+) -> ServerResult<()> {
+    conn.log("run command CLUSTER");
+
+    let cluster = state.cluster();
+    if !cluster.is_enabled() {
+        let value = Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            "This instance has cluster support disabled",
+        ));
+        return conn.write_value(value).await;
+    }
+
+    // CLUSTER <subcommand> [argument ...]
+    let subcommand = args.pop_front_bulk_string().unwrap_or_default();
+    let subcommand = subcommand.to_uppercase();
+    let raw_args = std::iter::from_fn(|| args.pop_front_bulk_string_bytes()).collect::<Vec<_>>();
+    // Only the key of KEYSLOT is binary, other arguments are text.
+    let args = raw_args
+        .iter()
+        .map(|x| String::from_utf8_lossy(x).into_owned())
+        .collect::<Vec<_>>();
+    let arity_ok = match subcommand.as_str() {
+        "INFO" | "MYID" | "NODES" | "SLOTS" | "SHARDS" => args.is_empty(),
+        "KEYSLOT" | "COUNTKEYSINSLOT" => args.len() == 1,
+        "GETKEYSINSLOT" => args.len() == 2,
+        "SETSLOT" => args.len() == 2 || args.len() == 3,
+        "MEET" => args.len() == 2 || args.len() == 3,
+        "ADDSLOTS" | "DELSLOTS" => !args.is_empty(),
+        "ADDSLOTSRANGE" | "DELSLOTSRANGE" => !args.is_empty() && args.len() % 2 == 0,
+        _ => true,
+    };
+    if !arity_ok {
+        let value = Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            format!(
+                "wrong number of arguments for 'cluster|{}' command",
+                subcommand.to_lowercase()
+            ),
+        ));
+        return conn.write_value(value).await;
+    }
+
+    let value = match subcommand.as_str() {
+        "INFO" => {
+            let info = cluster.info();
+            let text = format!(
+                "cluster_state:{}\n\
+                 cluster_slots_assigned:{}\n\
+                 cluster_slots_ok:{}\n\
+                 cluster_slots_pfail:{}\n\
+                 cluster_slots_fail:{}\n\
+                 cluster_known_nodes:{}\n\
+                 cluster_size:{}\n\
+                 cluster_current_epoch:{}\n",
+                if info.ok { "ok" } else { "fail" },
+                info.slots_assigned,
+                info.slots_assigned - info.slots_pfail - info.slots_fail,
+                info.slots_pfail,
+                info.slots_fail,
+                info.known_nodes,
+                info.size,
+                info.current_epoch
+            );
+            Value::BulkString(BulkString::new(text))
+        }
+        "MYID" => Value::BulkString(BulkString::new(cluster.myid())),
+        "NODES" => Value::BulkString(BulkString::new(cluster.describe_nodes())),
+        "MEET" => {
+            let (addr, cport) = match parse_meet(&args) {
+                Ok(v) => v,
+                Err(e) => return conn.write_value(e).await,
+            };
+            // Meeting a known node again is not an error.
+            if cluster.meet(addr, cport) {
+                conn.log(format!("CLUSTER MEET {addr}@{cport}"));
+            }
+            Value::SimpleString(SimpleString::new("OK"))
+        }
+        "KEYSLOT" => Value::Integer(Integer::new(key_slot(&raw_args[0]) as i64)),
+        "COUNTKEYSINSLOT" => match parse_slot(&args[0]) {
+            Some(slot) => {
+                let count = state.storage().keys_in_slot(slot, usize::MAX).len();
+                Value::Integer(Integer::new(count as i64))
+            }
+            None => Value::SimpleError(SimpleError::with_prefix("ERR", "Invalid slot")),
+        },
+        "GETKEYSINSLOT" => match (parse_slot(&args[0]), args[1].parse::<usize>()) {
+            (Some(slot), Ok(count)) => {
+                let keys = state
+                    .storage()
+                    .keys_in_slot(slot, count)
+                    .into_iter()
+                    .map(|x| Value::BulkString(BulkString::new(x)))
+                    .collect::<Vec<_>>();
+                Value::Array(Array::with_values(keys))
+            }
+            _ => Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                "Invalid slot or number of keys",
+            )),
+        },
+        "SETSLOT" => {
+            let Some(slot) = parse_slot(&args[0]) else {
+                let value = Value::SimpleError(SimpleError::with_prefix(
+                    "ERR",
+                    "Invalid or out of range slot",
+                ));
+                return conn.write_value(value).await;
+            };
+            let Some(slot_state) = parse_slot_state(&args[1..]) else {
+                let value = Value::SimpleError(SimpleError::with_prefix(
+                    "ERR",
+                    "Invalid CLUSTER SETSLOT action or number of arguments. Try CLUSTER HELP",
+                ));
+                return conn.write_value(value).await;
+            };
+            let has_keys = !state.storage().keys_in_slot(slot, 1).is_empty();
+            match cluster.set_slot(slot, slot_state.clone(), has_keys) {
+                Ok(()) => {
+                    conn.log(format!("CLUSTER SETSLOT {slot} {slot_state:?}"));
+                    Value::SimpleString(SimpleString::new("OK"))
+                }
+                Err(e) => Value::SimpleError(SimpleError::with_prefix("ERR", e)),
+            }
+        }
+        "SLOTS" => {
+            // [start, end, [ip, port, id]]
+            let ranges = cluster
+                .slot_ranges()
+                .into_iter()
+                .map(|(start, end, node)| {
+                    Value::Array(Array::with_values(vec![
+                        Value::Integer(Integer::new(start as i64)),
+                        Value::Integer(Integer::new(end as i64)),
+                        slots_node(&node),
+                    ]))
+                })
+                .collect::<Vec<_>>();
+            Value::Array(Array::with_values(ranges))
+        }
+        "SHARDS" => {
+            // Each node is a shard of its own, replicas in cluster are not supported.
+            let ranges = cluster.slot_ranges();
+            let myid = cluster.myid();
+            let shards = cluster
+                .nodes()
+                .into_iter()
+                .map(|node| {
+                    let slots = ranges
+                        .iter()
+                        .filter(|(.., owner)| owner.id == node.id)
+                        .flat_map(|(start, end, _)| [*start, *end])
+                        .map(|x| Value::Integer(Integer::new(x as i64)))
+                        .collect::<Vec<_>>();
+                    let offset = if node.id == myid { rep.offset() } else { 0 };
+                    Value::Map(Map::with_entries(vec![
+                        (
+                            Value::BulkString(BulkString::new("slots")),
+                            Value::Array(Array::with_values(slots)),
+                        ),
+                        (
+                            Value::BulkString(BulkString::new("nodes")),
+                            Value::Array(Array::with_values(vec![shards_node(&node, offset)])),
+                        ),
+                    ]))
+                })
+                .collect::<Vec<_>>();
+            Value::Array(Array::with_values(shards))
+        }
+        "ADDSLOTS" | "ADDSLOTSRANGE" | "DELSLOTS" | "DELSLOTSRANGE" => {
+            let slots = match parse_slots(args, subcommand.ends_with("RANGE")) {
+                Ok(v) => v,
+                Err(e) => return conn.write_value(e).await,
+            };
+            let result = if subcommand.starts_with("ADD") {
+                cluster.add_slots(&slots)
+            } else {
+                cluster.del_slots(&slots)
+            };
+            match result {
+                Ok(()) => {
+                    conn.log(format!("CLUSTER {subcommand} {} slots", slots.len()));
+                    Value::SimpleString(SimpleString::new("OK"))
+                }
+                Err(e) => Value::SimpleError(SimpleError::with_prefix("ERR", e)),
+            }
+        }
+        v => Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            format!("unknown subcommand '{v}'"),
+        )),
+    };
+    conn.write_value(value).await
+}
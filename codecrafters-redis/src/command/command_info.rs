@@ -0,0 +1,198 @@
+use serde_redis::{Array, BulkString, Integer, Map, SimpleError, SimpleString, Value};
+
+use crate::{
+    command::{
+        command_categories, command_keys,
+        table::{lookup_command, CommandSpec, COMMAND_TABLE},
+    },
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    glob::glob_match,
+};
+
+/// Reply of COMMAND INFO for command `spec`.
+fn command_info(spec: &CommandSpec) -> Value {
+    let simple_strings = |values: Vec<String>| {
+        Value::Array(
+            values
+                .into_iter()
+                .map(|x| Value::SimpleString(SimpleString::new(x)))
+                .collect(),
+        )
+    };
+    let flags = spec.flags.iter().map(|x| x.to_string()).collect();
+    let categories = command_categories(spec.name)
+        .into_iter()
+        .map(|x| format!("@{x}"))
+        .collect();
+    Value::Array(Array::with_values(vec![
+        Value::BulkString(BulkString::new(spec.name.to_lowercase())),
+        Value::Integer(Integer::new(spec.arity)),
+        simple_strings(flags),
+        Value::Integer(Integer::new(spec.first_key)),
+        Value::Integer(Integer::new(spec.last_key)),
+        Value::Integer(Integer::new(spec.step)),
+        simple_strings(categories),
+        // Tips, key specifications and subcommands are not documented.
+        Value::Array(Array::new_empty()),
+        Value::Array(Array::new_empty()),
+        Value::Array(Array::new_empty()),
+    ]))
+}
+
+/// Reply of COMMAND DOCS for command `spec`.
+fn command_docs(spec: &CommandSpec) -> Value {
+    Value::Map(Map::with_entries(vec![
+        (
+            Value::BulkString(BulkString::new("summary")),
+            Value::BulkString(BulkString::new(spec.summary)),
+        ),
+        (
+            Value::BulkString(BulkString::new("since")),
+            Value::BulkString(BulkString::new(spec.since)),
+        ),
+        (
+            Value::BulkString(BulkString::new("group")),
+            Value::BulkString(BulkString::new(spec.group)),
+        ),
+    ]))
+}
+
+/// Handle COMMAND, introspect the command table.
+pub(super) async fn handle_command_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+) -> ServerResult<()> {
+    conn.log("run command COMMAND");
+
+    // COMMAND lists all commands.
+    let Some(subcommand) = args.pop_front_bulk_string() else {
+        let value = Value::Array(COMMAND_TABLE.iter().map(command_info).collect());
+        return conn.write_value(value).await;
+    };
+
+    let value = match subcommand.to_uppercase().as_str() {
+        "COUNT" => {
+            if !args.is_empty() {
+                return Err(ServerError::InvalidArgs {
+                    cmd: "COMMAND",
+                    args,
+                });
+            }
+            Value::Integer(Integer::new(COMMAND_TABLE.len() as i64))
+        }
+        "INFO" => {
+            // COMMAND INFO [command-name [command-name ...]]
+            if args.is_empty() {
+                Value::Array(COMMAND_TABLE.iter().map(command_info).collect())
+            } else {
+                let mut values = vec![];
+                while let Some(name) = args.pop_front_bulk_string() {
+                    values.push(
+                        lookup_command(&name)
+                            .map_or(Value::BulkString(BulkString::null()), command_info),
+                    );
+                }
+                Value::Array(Array::with_values(values))
+            }
+        }
+        "DOCS" => {
+            // COMMAND DOCS [command-name [command-name ...]]
+            let mut specs = vec![];
+            if args.is_empty() {
+                specs.extend(COMMAND_TABLE.iter());
+            }
+            // Unknown commands are skipped.
+            while let Some(name) = args.pop_front_bulk_string() {
+                specs.extend(lookup_command(&name));
+            }
+            Value::Map(Map::with_entries(
+                specs
+                    .into_iter()
+                    .map(|x| {
+                        (
+                            Value::BulkString(BulkString::new(x.name.to_lowercase())),
+                            command_docs(x),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            ))
+        }
+        "LIST" => {
+            // COMMAND LIST [FILTERBY <ACLCAT category | PATTERN pattern>]
+            let filter = match (
+                args.pop_front_bulk_string(),
+                args.pop_front_bulk_string(),
+                args.pop_front_bulk_string(),
+                args.is_empty(),
+            ) {
+                (None, ..) => None,
+                (Some(filterby), Some(kind), Some(value), true)
+                    if filterby.eq_ignore_ascii_case("FILTERBY") =>
+                {
+                    Some((kind.to_uppercase(), value))
+                }
+                _ => {
+                    let value = Value::SimpleError(SimpleError::with_prefix("ERR", "syntax error"));
+                    return conn.write_value(value).await;
+                }
+            };
+            let names = COMMAND_TABLE
+                .iter()
+                .filter(|x| match &filter {
+                    None => true,
+                    Some((kind, category)) if kind == "ACLCAT" => {
+                        command_categories(x.name).contains(&category.to_lowercase().as_str())
+                    }
+                    Some((kind, pattern)) if kind == "PATTERN" => {
+                        glob_match(pattern.as_bytes(), x.name.to_lowercase().as_bytes(), true)
+                    }
+                    Some(..) => false,
+                })
+                .map(|x| Value::BulkString(BulkString::new(x.name.to_lowercase())))
+                .collect();
+            Value::Array(names)
+        }
+        "GETKEYS" => {
+            // COMMAND GETKEYS command [arg [arg ...]]
+            let Some(name) = args.pop_front_bulk_string() else {
+                return Err(ServerError::InvalidArgs {
+                    cmd: "COMMAND",
+                    args,
+                });
+            };
+            match lookup_command(&name) {
+                None => {
+                    Value::SimpleError(SimpleError::with_prefix("ERR", "Invalid command specified"))
+                }
+                Some(spec) if !spec.check_arity(args.len() + 1) => {
+                    Value::SimpleError(SimpleError::with_prefix(
+                        "ERR",
+                        "Invalid number of arguments specified for command",
+                    ))
+                }
+                Some(spec) => {
+                    let keys = command_keys(spec.name, &args);
+                    if keys.is_empty() {
+                        Value::SimpleError(SimpleError::with_prefix(
+                            "ERR",
+                            "The command has no key arguments",
+                        ))
+                    } else {
+                        Value::Array(
+                            keys.into_iter()
+                                .map(|x| Value::BulkString(BulkString::new(x)))
+                                .collect(),
+                        )
+                    }
+                }
+            }
+        }
+        v => Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            format!("unknown subcommand '{v}'"),
+        )),
+    };
+
+    conn.write_value(value).await
+}
@@ -0,0 +1,88 @@
+use serde_redis::{Array, BulkString, Map, SimpleError, SimpleString, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    state::ServerState,
+};
+
+pub(super) async fn handle_config_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    state: &mut ServerState,
+) -> ServerResult<()> {
+    conn.log("run command CONFIG");
+    let subcommand = args
+        .pop_front_bulk_string()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "CONFIG",
+            args: args.clone(),
+        })?;
+
+    let value = match subcommand.to_uppercase().as_str() {
+        "GET" => {
+            // CONFIG GET parameter [parameter ...]
+            if args.is_empty() {
+                return Err(ServerError::InvalidArgs {
+                    cmd: "CONFIG",
+                    args,
+                });
+            }
+            let mut params = vec![];
+            while let Some(pattern) = args.pop_front_bulk_string() {
+                for param in state.config().get_params(&pattern) {
+                    if !params.contains(&param) {
+                        params.push(param);
+                    }
+                }
+            }
+            Value::Map(Map::with_entries(
+                params
+                    .into_iter()
+                    .map(|(name, value)| {
+                        (
+                            Value::BulkString(BulkString::new(name)),
+                            Value::BulkString(BulkString::new(value)),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            ))
+        }
+        "SET" => {
+            // CONFIG SET parameter value [parameter value ...]
+            if args.is_empty() || !args.len().is_multiple_of(2) {
+                return Err(ServerError::InvalidArgs {
+                    cmd: "CONFIG",
+                    args,
+                });
+            }
+            let mut params = vec![];
+            while let (Some(name), Some(value)) =
+                (args.pop_front_bulk_string(), args.pop_front_bulk_string())
+            {
+                params.push((name, value));
+            }
+            conn.log(format!("CONFIG SET {params:?}"));
+            match state.set_config(&params) {
+                Ok(()) => Value::SimpleString(SimpleString::new("OK")),
+                Err(e) => Value::SimpleError(SimpleError::with_prefix("ERR", e)),
+            }
+        }
+        "RESETSTAT" => {
+            if !args.is_empty() {
+                return Err(ServerError::InvalidArgs {
+                    cmd: "CONFIG",
+                    args,
+                });
+            }
+            state.reset_stats();
+            Value::SimpleString(SimpleString::new("OK"))
+        }
+        v => Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            format!("unknown subcommand '{v}'"),
+        )),
+    };
+
+    conn.write_value(value).await
+}
@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use serde_redis::{Array, BulkString, Integer, SimpleError, SimpleString, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+pub(super) async fn handle_debug_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command DEBUG");
+    let subcommand = args
+        .pop_front_bulk_string()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "DEBUG",
+            args: args.clone(),
+        })?;
+
+    let value = match subcommand.to_uppercase().as_str() {
+        "BIGKEYS" => {
+            // DEBUG BIGKEYS [count]
+            let count = match args.pop_front_bulk_string() {
+                Some(v) => v.parse::<usize>().map_err(|_| ServerError::InvalidArgs {
+                    cmd: "DEBUG",
+                    args: args.clone(),
+                })?,
+                None => 10,
+            };
+            storage
+                .biggest_keys(count)
+                .into_iter()
+                .map(|(key, ty, size)| {
+                    Value::Array(Array::with_values(vec![
+                        Value::BulkString(BulkString::new(key)),
+                        Value::BulkString(BulkString::new(ty)),
+                        Value::Integer(Integer::new(size as i64)),
+                    ]))
+                })
+                .collect::<Array>()
+        }
+        "DIGEST" => {
+            // DEBUG DIGEST
+            let value = Value::SimpleString(SimpleString::new(storage.digest()));
+            return conn.write_value(value).await;
+        }
+        "SLEEP" => {
+            // DEBUG SLEEP <seconds>
+            let seconds = match args
+                .pop_front_bulk_string()
+                .and_then(|x| x.parse::<f64>().ok())
+                .and_then(|x| Duration::try_from_secs_f64(x).ok())
+            {
+                Some(v) => v,
+                None => {
+                    let value = Value::SimpleError(SimpleError::with_prefix(
+                        "ERR",
+                        "value is not a valid float",
+                    ));
+                    return conn.write_value(value).await;
+                }
+            };
+            tokio::time::sleep(seconds).await;
+            return conn
+                .write_value(Value::SimpleString(SimpleString::new("OK")))
+                .await;
+        }
+        "OBJECT" => {
+            // DEBUG OBJECT <key>
+            let (Some(key), true) = (args.pop_front_bulk_string_bytes(), args.is_empty()) else {
+                return Err(ServerError::InvalidArgs { cmd: "DEBUG", args });
+            };
+            let value = match (storage.object(&key), storage.serialized_length(&key)) {
+                (Some(info), Some(len)) => Value::SimpleString(SimpleString::new(format!(
+                    "Value refcount:1 encoding:{} serializedlength:{len} lru_seconds_idle:{}",
+                    info.encoding, info.idle_time
+                ))),
+                _ => Value::SimpleError(SimpleError::with_prefix("ERR", "no such key")),
+            };
+            return conn.write_value(value).await;
+        }
+        "SET-ACTIVE-EXPIRE" => {
+            // DEBUG SET-ACTIVE-EXPIRE <0|1>
+            let enabled = match (args.pop_front_bulk_string().as_deref(), args.is_empty()) {
+                (Some("0"), true) => false,
+                (Some("1"), true) => true,
+                _ => {
+                    let value = Value::SimpleError(SimpleError::with_prefix("ERR", "syntax error"));
+                    return conn.write_value(value).await;
+                }
+            };
+            storage.set_active_expire(enabled);
+            return conn
+                .write_value(Value::SimpleString(SimpleString::new("OK")))
+                .await;
+        }
+        "JMAP" => {
+            // DEBUG JMAP
+            //
+            // Redis dumps the allocator heap map to log, here the memory used.
+            conn.log(format!("used_memory:{}", storage.used_memory()));
+            return conn
+                .write_value(Value::SimpleString(SimpleString::new("OK")))
+                .await;
+        }
+        #[cfg(debug_assertions)]
+        "OOM" => {
+            // DEBUG OOM <LIMIT bytes | FAIL count | OFF | STATUS>
+            let option = args.pop_front_bulk_string().unwrap_or_default();
+            let arg = args
+                .pop_front_bulk_string()
+                .and_then(|x| x.parse::<usize>().ok());
+            match (option.to_uppercase().as_str(), arg) {
+                ("LIMIT", Some(v)) => storage.set_oom_limit(Some(v)),
+                ("FAIL", Some(v)) => storage.inject_oom_failures(v),
+                ("OFF", None) => {
+                    storage.set_oom_limit(None);
+                    storage.inject_oom_failures(0);
+                }
+                ("STATUS", None) => {
+                    let status = format!(
+                        "used_memory:{} {}",
+                        storage.used_memory(),
+                        storage.oom_status()
+                    );
+                    return conn
+                        .write_value(Value::BulkString(BulkString::new(status)))
+                        .await;
+                }
+                _ => {
+                    let value = Value::SimpleError(SimpleError::with_prefix("ERR", "syntax error"));
+                    return conn.write_value(value).await;
+                }
+            }
+            return conn
+                .write_value(Value::SimpleString(SimpleString::new("OK")))
+                .await;
+        }
+        v => {
+            let value = Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                format!("unknown subcommand '{v}'"),
+            ));
+            return conn.write_value(value).await;
+        }
+    };
+
+    conn.write_value(Value::Array(value)).await
+}
@@ -0,0 +1,27 @@
+use serde_redis::{Array, Integer, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+/// Handle DEL, remove keys in any type and reply the count of keys removed.
+pub(super) async fn handle_del_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command DEL");
+    let mut keys = vec![];
+    while let Some(key) = args.pop_front_bulk_string_bytes() {
+        keys.push(key);
+    }
+    if keys.is_empty() {
+        return Err(ServerError::InvalidArgs { cmd: "DEL", args });
+    }
+
+    let count = storage.delete(&keys);
+    conn.write_value(Value::Integer(Integer::new(count as i64)))
+        .await
+}
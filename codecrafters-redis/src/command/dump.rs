@@ -0,0 +1,30 @@
+use serde_redis::{Array, BulkString, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+/// Handle DUMP, serialize the value of a key to be saved by RESTORE.
+pub(super) async fn handle_dump_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command DUMP");
+
+    // DUMP key
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "DUMP",
+            args: args.clone(),
+        })?;
+
+    let value = match storage.dump(&key) {
+        Some((payload, _)) => Value::BulkString(BulkString::new(payload)),
+        None => Value::BulkString(BulkString::null()),
+    };
+    conn.write_value(value).await
+}
@@ -1,14 +1,20 @@
 use serde_redis::{Array, SimpleError, Value};
 
-use crate::{conn::Conn, error::ServerResult, storage::Storage};
+use crate::{conn::Conn, error::ServerResult, state::ServerState};
 
 pub(super) async fn handle_exec_command(
     conn: &mut Conn<'_>,
-    storage: &mut Storage,
+    state: &mut ServerState,
 ) -> ServerResult<()> {
     conn.log("run command EXEC");
-    let value = if conn.in_transaction() {
-        let result = conn.commit_transaction(storage).await?;
+    let value = if conn.is_transaction_dirty() {
+        conn.abort_transaction();
+        Value::SimpleError(SimpleError::with_prefix(
+            "EXECABORT",
+            "Transaction discarded because of previous errors.",
+        ))
+    } else if conn.in_transaction() {
+        let result = conn.commit_transaction(state).await?;
         if result.is_empty() {
             // Return an empty array if the transaction is empty.
             Value::Array(Array::new_empty())
@@ -0,0 +1,34 @@
+use serde_redis::{Array, BulkString, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+/// Handle EXPORT, dump keys to a JSON document.
+///
+/// Keys not present are skipped.
+pub(super) async fn handle_export_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command EXPORT");
+
+    // EXPORT key [key ...]
+    let mut keys = vec![];
+    while let Some(key) = args.pop_front_bulk_string_bytes() {
+        keys.push(key);
+    }
+    if keys.is_empty() {
+        return Err(ServerError::InvalidArgs {
+            cmd: "EXPORT",
+            args,
+        });
+    }
+
+    let json = storage.export_json(&keys);
+    conn.write_value(Value::BulkString(BulkString::new(json)))
+        .await
+}
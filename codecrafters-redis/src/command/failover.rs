@@ -0,0 +1,103 @@
+use std::{net::Ipv4Addr, time::Duration};
+
+use serde_redis::{Array, SimpleError, SimpleString, Value};
+
+use crate::{
+    command::set::syntax_error, conn::Conn, error::ServerResult, replication::ReplicationState,
+    state::ServerState,
+};
+
+/// Handle FAILOVER, hand over the master role to one of the replicas.
+///
+/// Replies once the failover started, the progress is reported in INFO replication.
+pub(super) async fn handle_failover_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    rep: ReplicationState,
+    state: &ServerState,
+) -> ServerResult<()> {
+    conn.log("run command FAILOVER");
+
+    let error = |message: &str| Value::SimpleError(SimpleError::with_prefix("ERR", message));
+
+    let args = std::iter::from_fn(|| args.pop_front_bulk_string()).collect::<Vec<_>>();
+
+    // FAILOVER ABORT
+    if let [abort] = args.as_slice() {
+        if abort.eq_ignore_ascii_case("ABORT") {
+            let value = if rep.abort_failover(state) {
+                conn.log("failover aborted");
+                Value::SimpleString(SimpleString::new("OK"))
+            } else {
+                error("No failover in progress.")
+            };
+            return conn.write_value(value).await;
+        }
+    }
+
+    // FAILOVER [TO host port [FORCE]] [TIMEOUT milliseconds]
+    let mut target = None;
+    let mut timeout = None;
+    let mut force = false;
+    let mut args = args.into_iter();
+    while let Some(option) = args.next() {
+        match option.to_uppercase().as_str() {
+            "TO" if target.is_none() => {
+                let (Some(host), Some(port)) = (args.next(), args.next()) else {
+                    return conn.write_value(syntax_error()).await;
+                };
+                let Ok(port) = port.parse::<u16>() else {
+                    let value = error("value is not an integer or out of range");
+                    return conn.write_value(value).await;
+                };
+                target = Some((host, port));
+            }
+            "TIMEOUT" if timeout.is_none() => {
+                let value = match args.next().map(|x| x.parse::<i64>()) {
+                    Some(Ok(v)) if v > 0 => v,
+                    Some(Ok(..)) => {
+                        let value = error("FAILOVER timeout must be greater than 0");
+                        return conn.write_value(value).await;
+                    }
+                    Some(Err(..)) => {
+                        let value = error("value is not an integer or out of range");
+                        return conn.write_value(value).await;
+                    }
+                    None => return conn.write_value(syntax_error()).await,
+                };
+                timeout = Some(Duration::from_millis(value as u64));
+            }
+            "FORCE" if !force => force = true,
+            _ => return conn.write_value(syntax_error()).await,
+        }
+    }
+    if force && (target.is_none() || timeout.is_none()) {
+        let value =
+            error("FAILOVER with force option requires both a timeout and target HOST and IP.");
+        return conn.write_value(value).await;
+    }
+
+    // Hosts other than an ipv4 address never match a replica.
+    let target = match target {
+        Some((host, port)) if host.eq_ignore_ascii_case("localhost") => {
+            Some((Ipv4Addr::LOCALHOST, port))
+        }
+        Some((host, port)) => match host.parse::<Ipv4Addr>() {
+            Ok(ip) => Some((ip, port)),
+            Err(..) => {
+                let value = error("FAILOVER target HOST and PORT is not a replica.");
+                return conn.write_value(value).await;
+            }
+        },
+        None => None,
+    };
+
+    let value = match rep.failover(target, timeout, force, state.clone()) {
+        Ok(()) => {
+            conn.log(format!("failover started, target {target:?}"));
+            Value::SimpleString(SimpleString::new("OK"))
+        }
+        Err(e) => error(e),
+    };
+    conn.write_value(value).await
+}
@@ -0,0 +1,28 @@
+use serde_redis::{Array, SimpleString, Value};
+
+use crate::{command::set::syntax_error, conn::Conn, error::ServerResult, storage::Storage};
+
+/// Handle FLUSHALL, or FLUSHDB if `all` is false.
+///
+/// Only one database is supported, so both remove all keys.
+pub(super) async fn handle_flush_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+    all: bool,
+) -> ServerResult<()> {
+    let cmd = if all { "FLUSHALL" } else { "FLUSHDB" };
+    conn.log(format!("run command {cmd}"));
+
+    // FLUSHALL [ASYNC | SYNC]
+    let lazy = match args.pop_front_bulk_string() {
+        None => false,
+        Some(v) if v.eq_ignore_ascii_case("SYNC") && args.is_empty() => false,
+        Some(v) if v.eq_ignore_ascii_case("ASYNC") && args.is_empty() => true,
+        Some(..) => return conn.write_value(syntax_error()).await,
+    };
+    conn.log(format!("{cmd} lazy={lazy}"));
+    storage.flush(lazy);
+    conn.write_value(Value::SimpleString(SimpleString::new("OK")))
+        .await
+}
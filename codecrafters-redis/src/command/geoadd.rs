@@ -0,0 +1,114 @@
+use serde_redis::{Array, Integer, SimpleError, Value};
+
+use crate::{
+    command::{effect_command, set::syntax_error, zpop_feed_effects},
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{geo_is_valid, SetCondition, Storage},
+};
+
+/// Handle GEOADD.
+///
+/// Return the effects to sync to replica, the command itself followed by the pops of
+/// blocked tasks fed.
+pub(super) async fn handle_geoadd_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<Vec<Array>> {
+    conn.log("run command GEOADD");
+
+    // GEOADD key [NX | XX] [CH] longitude latitude member [longitude latitude member ...]
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "GEOADD",
+            args: args.clone(),
+        })?;
+    let mut effect = effect_command([b"GEOADD".as_slice(), &key]);
+    let mut options = vec![];
+    let mut condition = SetCondition::Always;
+    let mut changed = false;
+    let mut nx_xx = 0;
+    let first = loop {
+        let arg = args
+            .pop_front_bulk_string()
+            .ok_or_else(|| ServerError::InvalidArgs {
+                cmd: "GEOADD",
+                args: args.clone(),
+            })?;
+        let option = arg.to_uppercase();
+        match option.as_str() {
+            "NX" => {
+                condition = SetCondition::NotExists;
+                nx_xx += 1;
+            }
+            "XX" => {
+                condition = SetCondition::Exists;
+                nx_xx += 1;
+            }
+            "CH" => changed = true,
+            _ => break arg,
+        }
+        options.push(option);
+    };
+    if nx_xx > 1 {
+        let value = Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            "XX and NX options at the same time are not compatible",
+        ));
+        conn.write_value(value).await?;
+        return Ok(vec![]);
+    }
+
+    let rest = std::iter::once(first)
+        .chain(std::iter::from_fn(|| args.pop_front_bulk_string()))
+        .collect::<Vec<_>>();
+    if !rest.len().is_multiple_of(3) {
+        conn.write_value(syntax_error()).await?;
+        return Ok(vec![]);
+    }
+    let mut items = vec![];
+    for item in rest.chunks_exact(3) {
+        let (lon, lat) = match (item[0].parse::<f64>(), item[1].parse::<f64>()) {
+            (Ok(lon), Ok(lat)) => (lon, lat),
+            _ => {
+                let value = Value::SimpleError(SimpleError::with_prefix(
+                    "ERR",
+                    "value is not a valid float",
+                ));
+                conn.write_value(value).await?;
+                return Ok(vec![]);
+            }
+        };
+        if !geo_is_valid(lon, lat) {
+            let value = Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                format!("invalid longitude,latitude pair {lon:.6},{lat:.6}"),
+            ));
+            conn.write_value(value).await?;
+            return Ok(vec![]);
+        }
+        items.push((lon, lat, item[2].clone()));
+    }
+
+    conn.log(format!(
+        "GEOADD {:?} {} items",
+        String::from_utf8_lossy(&key),
+        items.len()
+    ));
+
+    options.extend(rest);
+    effect.append(effect_command(options));
+    let mut effects = vec![];
+    let value = match storage.geo_add(key, items, condition, changed) {
+        Ok((v, feeds)) => {
+            effects.push(effect);
+            effects.extend(zpop_feed_effects(feeds));
+            Value::Integer(Integer::new(v as i64))
+        }
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await?;
+    Ok(effects)
+}
@@ -0,0 +1,52 @@
+use serde_redis::{Array, BulkString, SimpleError, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{GeoUnit, Storage},
+};
+
+/// Error replied for unknown distance units.
+pub(super) fn unit_error() -> Value {
+    Value::SimpleError(SimpleError::with_prefix(
+        "ERR",
+        "unsupported unit provided. please use M, KM, FT, MI",
+    ))
+}
+
+pub(super) async fn handle_geodist_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command GEODIST");
+
+    // GEODIST key member1 member2 [M | KM | FT | MI]
+    let (key, member1, member2) = match (
+        args.pop_front_bulk_string_bytes(),
+        args.pop_front_bulk_string(),
+        args.pop_front_bulk_string(),
+    ) {
+        (Some(key), Some(member1), Some(member2)) => (key, member1, member2),
+        _ => {
+            return Err(ServerError::InvalidArgs {
+                cmd: "GEODIST",
+                args,
+            })
+        }
+    };
+    let unit = match args.pop_front_bulk_string() {
+        Some(v) => match GeoUnit::parse(&v) {
+            Some(v) => v,
+            None => return conn.write_value(unit_error()).await,
+        },
+        None => GeoUnit::Meter,
+    };
+
+    let value = match storage.geo_dist(&key, member1, member2) {
+        Ok(Some(v)) => Value::BulkString(BulkString::new(format!("{:.4}", v / unit.meters()))),
+        Ok(None) => Value::BulkString(BulkString::null()),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
@@ -0,0 +1,39 @@
+use serde_redis::{Array, BulkString, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+pub(super) async fn handle_geopos_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command GEOPOS");
+
+    // GEOPOS key [member [member ...]]
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "GEOPOS",
+            args: args.clone(),
+        })?;
+    let members = std::iter::from_fn(|| args.pop_front_bulk_string()).collect::<Vec<_>>();
+
+    let value = match storage.geo_pos(&key, &members) {
+        Ok(v) => v
+            .into_iter()
+            .map(|pos| match pos {
+                Some((lon, lat)) => Value::Array(Array::with_values(vec![
+                    Value::BulkString(BulkString::new(lon.to_string())),
+                    Value::BulkString(BulkString::new(lat.to_string())),
+                ])),
+                None => Value::Array(Array::null()),
+            })
+            .collect::<Array>(),
+        Err(e) => return conn.write_value(e.to_message()).await,
+    };
+    conn.write_value(Value::Array(value)).await
+}
@@ -0,0 +1,206 @@
+use serde_redis::{Array, Integer, Value};
+
+use crate::{
+    command::{
+        effect_command,
+        geodist::unit_error,
+        geosearch::{arrange_matches, error, float_error, matches_reply},
+        set::syntax_error,
+        zpop_feed_effects,
+    },
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{geo_is_valid, GeoCenter, GeoShape, GeoUnit, Storage},
+};
+
+/// A query of GEORADIUS or GEORADIUSBYMEMBER.
+struct RadiusQuery {
+    center: GeoCenter,
+
+    /// Radius in meters.
+    radius: f64,
+    unit: GeoUnit,
+    with_coord: bool,
+    with_dist: bool,
+    with_hash: bool,
+    descending: Option<bool>,
+    count: Option<usize>,
+    any: bool,
+
+    /// Key to store the result in, and whether to store distances instead of
+    /// geohashes, by STORE or STOREDIST.
+    store: Option<(Vec<u8>, bool)>,
+}
+
+/// Parse `args` of GEORADIUS after the key, or of GEORADIUSBYMEMBER if `by_member`
+/// is true. STORE and STOREDIST are not accepted if `readonly`.
+///
+/// Return the error replied if invalid.
+fn parse_query(args: &[Vec<u8>], by_member: bool, readonly: bool) -> Result<RadiusQuery, Value> {
+    let parse_float = |x: &[u8]| std::str::from_utf8(x).ok()?.parse::<f64>().ok();
+    let (center, radius, unit, options) = match (by_member, args) {
+        (true, [member, radius, unit, options @ ..]) => {
+            let member = String::from_utf8_lossy(member).into_owned();
+            (GeoCenter::Member(member), radius, unit, options)
+        }
+        (false, [lon, lat, radius, unit, options @ ..]) => {
+            let (Some(lon), Some(lat)) = (parse_float(lon), parse_float(lat)) else {
+                return Err(float_error());
+            };
+            if !geo_is_valid(lon, lat) {
+                return Err(error(format!(
+                    "invalid longitude,latitude pair {lon:.6},{lat:.6}"
+                )));
+            }
+            (GeoCenter::LonLat(lon, lat), radius, unit, options)
+        }
+        _ => return Err(syntax_error()),
+    };
+    let radius = match parse_float(radius) {
+        Some(v) if v >= 0.0 => v,
+        Some(_) => return Err(error("radius cannot be negative")),
+        None => return Err(error("need numeric radius")),
+    };
+    let unit = std::str::from_utf8(unit)
+        .ok()
+        .and_then(GeoUnit::parse)
+        .ok_or_else(unit_error)?;
+
+    let mut query = RadiusQuery {
+        center,
+        radius: radius * unit.meters(),
+        unit,
+        with_coord: false,
+        with_dist: false,
+        with_hash: false,
+        descending: None,
+        count: None,
+        any: false,
+        store: None,
+    };
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            b"WITHCOORD" => query.with_coord = true,
+            b"WITHDIST" => query.with_dist = true,
+            b"WITHHASH" => query.with_hash = true,
+            b"ASC" => query.descending = Some(false),
+            b"DESC" => query.descending = Some(true),
+            b"COUNT" => {
+                match options
+                    .next()
+                    .and_then(|x| std::str::from_utf8(x).ok()?.parse::<i64>().ok())
+                {
+                    Some(v) if v > 0 => query.count = Some(v as usize),
+                    Some(_) => return Err(error("COUNT must be > 0")),
+                    None => return Err(error("value is not an integer or out of range")),
+                }
+            }
+            b"ANY" => query.any = true,
+            b"STORE" | b"STOREDIST" if !readonly => {
+                let dist = option.eq_ignore_ascii_case(b"STOREDIST");
+                let key = options.next().ok_or_else(syntax_error)?;
+                query.store = Some((key.clone(), dist));
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    if query.any && query.count.is_none() {
+        return Err(error("the ANY argument requires COUNT argument"));
+    }
+    if query.store.is_some() && (query.with_coord || query.with_dist || query.with_hash) {
+        return Err(error(
+            "STORE option in GEORADIUS is not compatible with WITHDIST, WITHHASH and WITHCOORD options",
+        ));
+    }
+    Ok(query)
+}
+
+/// Handle GEORADIUS, or GEORADIUSBYMEMBER if `by_member` is true.
+///
+/// Their read only variants GEORADIUS_RO and GEORADIUSBYMEMBER_RO are handled if
+/// `readonly` is true, which do not accept STORE and STOREDIST.
+///
+/// Return the effects to sync to replica if the result is stored, the command itself
+/// followed by the pops of blocked tasks fed.
+pub(super) async fn handle_georadius_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+    by_member: bool,
+    readonly: bool,
+) -> ServerResult<Vec<Array>> {
+    let cmd = match (by_member, readonly) {
+        (false, false) => "GEORADIUS",
+        (false, true) => "GEORADIUS_RO",
+        (true, false) => "GEORADIUSBYMEMBER",
+        (true, true) => "GEORADIUSBYMEMBER_RO",
+    };
+    conn.log(format!("run command {cmd}"));
+
+    // GEORADIUS key longitude latitude radius <M | KM | FT | MI> [WITHCOORD] [WITHDIST]
+    //   [WITHHASH] [COUNT count [ANY]] [ASC | DESC] [STORE key | STOREDIST key]
+    // GEORADIUSBYMEMBER key member radius <M | KM | FT | MI> [options as above]
+    let all = std::iter::from_fn(|| args.pop_front_bulk_string_bytes()).collect::<Vec<_>>();
+    let [key, rest @ ..] = all.as_slice() else {
+        return Err(ServerError::InvalidArgs { cmd, args });
+    };
+    let query = match parse_query(rest, by_member, readonly) {
+        Ok(v) => v,
+        Err(e) => {
+            conn.write_value(e).await?;
+            return Ok(vec![]);
+        }
+    };
+
+    let shape = GeoShape::Radius(query.radius);
+    let mut matches = match storage.geo_search(key, query.center, shape) {
+        Ok(v) => v,
+        Err(e) => {
+            conn.write_value(e.to_message()).await?;
+            return Ok(vec![]);
+        }
+    };
+    arrange_matches(&mut matches, query.descending, query.count, query.any);
+
+    let Some((destination, dist)) = query.store else {
+        let value = matches_reply(
+            matches,
+            query.unit,
+            query.with_coord,
+            query.with_dist,
+            query.with_hash,
+        );
+        conn.write_value(value).await?;
+        return Ok(vec![]);
+    };
+    let members = matches
+        .into_iter()
+        .map(|m| {
+            let score = if dist {
+                m.distance / query.unit.meters()
+            } else {
+                m.hash as f64
+            };
+            (m.member, score)
+        })
+        .collect::<Vec<_>>();
+    let count = members.len();
+    conn.log(format!(
+        "{cmd} stored {count} members in {:?}",
+        String::from_utf8_lossy(&destination)
+    ));
+    // Replica finds the same members in the same dataset.
+    let mut effects = vec![effect_command(
+        [cmd.as_bytes()]
+            .into_iter()
+            .chain(all.iter().map(Vec::as_slice)),
+    )];
+    match storage.zset_replace(destination, members) {
+        Some(feeds) => effects.extend(zpop_feed_effects(feeds)),
+        None => effects.clear(),
+    }
+    conn.write_value(Value::Integer(Integer::new(count as i64)))
+        .await?;
+    Ok(effects)
+}
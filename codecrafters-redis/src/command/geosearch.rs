@@ -0,0 +1,222 @@
+use serde_redis::{Array, BulkString, Integer, SimpleError, Value};
+
+use crate::{
+    command::{geodist::unit_error, set::syntax_error},
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{geo_is_valid, GeoCenter, GeoMatch, GeoShape, GeoUnit, Storage},
+};
+
+pub(super) fn error(message: impl Into<String>) -> Value {
+    Value::SimpleError(SimpleError::with_prefix("ERR", message.into()))
+}
+
+pub(super) fn float_error() -> Value {
+    error("value is not a valid float")
+}
+
+/// Order `matches` by distance if `descending` is set, and keep at most `count` of
+/// them, shared by GEOSEARCH and GEORADIUS.
+///
+/// With `any`, the first matches found are kept before ordering.
+pub(super) fn arrange_matches(
+    matches: &mut Vec<GeoMatch>,
+    mut descending: Option<bool>,
+    count: Option<usize>,
+    any: bool,
+) {
+    // Like redis, COUNT without ANY returns the nearest ones.
+    if count.is_some() && !any && descending.is_none() {
+        descending = Some(false);
+    }
+    if let (Some(count), true) = (count, any) {
+        matches.truncate(count);
+    }
+    if let Some(descending) = descending {
+        matches.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        if descending {
+            matches.reverse();
+        }
+    }
+    if let Some(count) = count {
+        matches.truncate(count);
+    }
+}
+
+/// Build the reply of `matches`, with the fields asked by WITHCOORD, WITHDIST and
+/// WITHHASH. Distances are in `unit`.
+pub(super) fn matches_reply(
+    matches: Vec<GeoMatch>,
+    unit: GeoUnit,
+    with_coord: bool,
+    with_dist: bool,
+    with_hash: bool,
+) -> Value {
+    let value = matches
+        .into_iter()
+        .map(|m| {
+            let member = Value::BulkString(BulkString::new(m.member));
+            if !(with_coord || with_dist || with_hash) {
+                return member;
+            }
+            let mut values = vec![member];
+            if with_dist {
+                let distance = format!("{:.4}", m.distance / unit.meters());
+                values.push(Value::BulkString(BulkString::new(distance)));
+            }
+            if with_hash {
+                values.push(Value::Integer(Integer::new(m.hash as i64)));
+            }
+            if with_coord {
+                values.push(Value::Array(Array::with_values(vec![
+                    Value::BulkString(BulkString::new(m.lon.to_string())),
+                    Value::BulkString(BulkString::new(m.lat.to_string())),
+                ])));
+            }
+            Value::Array(Array::with_values(values))
+        })
+        .collect::<Array>();
+    Value::Array(value)
+}
+
+pub(super) async fn handle_geosearch_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command GEOSEARCH");
+
+    // GEOSEARCH key <FROMMEMBER member | FROMLONLAT longitude latitude>
+    //   <BYRADIUS radius <M | KM | FT | MI> | BYBOX width height <M | KM | FT | MI>>
+    //   [ASC | DESC] [COUNT count [ANY]] [WITHCOORD] [WITHDIST] [WITHHASH]
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "GEOSEARCH",
+            args: args.clone(),
+        })?;
+
+    let mut center = None;
+    let mut shape = None;
+    let mut unit = GeoUnit::Meter;
+    let mut descending = None;
+    let mut count = None;
+    let mut any = false;
+    let (mut with_coord, mut with_dist, mut with_hash) = (false, false, false);
+    let mut from_count = 0;
+    let mut by_count = 0;
+    while let Some(option) = args.pop_front_bulk_string() {
+        match option.to_uppercase().as_str() {
+            "FROMMEMBER" => {
+                let Some(member) = args.pop_front_bulk_string() else {
+                    return conn.write_value(syntax_error()).await;
+                };
+                center = Some(GeoCenter::Member(member));
+                from_count += 1;
+            }
+            "FROMLONLAT" => {
+                let (Some(lon), Some(lat)) = (
+                    args.pop_front_bulk_string()
+                        .and_then(|x| x.parse::<f64>().ok()),
+                    args.pop_front_bulk_string()
+                        .and_then(|x| x.parse::<f64>().ok()),
+                ) else {
+                    return conn.write_value(float_error()).await;
+                };
+                if !geo_is_valid(lon, lat) {
+                    return conn
+                        .write_value(error(format!(
+                            "invalid longitude,latitude pair {lon:.6},{lat:.6}"
+                        )))
+                        .await;
+                }
+                center = Some(GeoCenter::LonLat(lon, lat));
+                from_count += 1;
+            }
+            "BYRADIUS" | "BYBOX" => {
+                let n = if option.eq_ignore_ascii_case("BYRADIUS") {
+                    1
+                } else {
+                    2
+                };
+                let mut sizes = vec![];
+                for _ in 0..n {
+                    match args.pop_front_bulk_string().map(|x| x.parse::<f64>()) {
+                        Some(Ok(v)) if v >= 0.0 => sizes.push(v),
+                        Some(Ok(_)) if n == 1 => {
+                            return conn.write_value(error("radius cannot be negative")).await
+                        }
+                        Some(Ok(_)) => {
+                            return conn
+                                .write_value(error("height or width cannot be negative"))
+                                .await
+                        }
+                        _ => return conn.write_value(float_error()).await,
+                    }
+                }
+                unit = match args
+                    .pop_front_bulk_string()
+                    .and_then(|x| GeoUnit::parse(&x))
+                {
+                    Some(v) => v,
+                    None => return conn.write_value(unit_error()).await,
+                };
+                shape = Some(sizes);
+                by_count += 1;
+            }
+            "ASC" => descending = Some(false),
+            "DESC" => descending = Some(true),
+            "COUNT" => match args.pop_front_bulk_string().map(|x| x.parse::<i64>()) {
+                Some(Ok(v)) if v > 0 => count = Some(v as usize),
+                Some(Ok(_)) => return conn.write_value(error("COUNT must be > 0")).await,
+                _ => {
+                    return conn
+                        .write_value(error("value is not an integer or out of range"))
+                        .await
+                }
+            },
+            "ANY" => any = true,
+            "WITHCOORD" => with_coord = true,
+            "WITHDIST" => with_dist = true,
+            "WITHHASH" => with_hash = true,
+            _ => return conn.write_value(syntax_error()).await,
+        }
+    }
+
+    let center = match center {
+        Some(v) if from_count == 1 => v,
+        _ => {
+            return conn
+                .write_value(error(
+                    "exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH",
+                ))
+                .await
+        }
+    };
+    let shape = match shape.as_deref() {
+        Some(&[radius]) if by_count == 1 => GeoShape::Radius(radius * unit.meters()),
+        Some(&[width, height]) if by_count == 1 => {
+            GeoShape::Box(width * unit.meters(), height * unit.meters())
+        }
+        _ => {
+            return conn
+                .write_value(error(
+                    "exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH",
+                ))
+                .await
+        }
+    };
+    if any && count.is_none() {
+        return conn
+            .write_value(error("the ANY argument requires COUNT argument"))
+            .await;
+    }
+
+    let mut matches = match storage.geo_search(&key, center, shape) {
+        Ok(v) => v,
+        Err(e) => return conn.write_value(e.to_message()).await,
+    };
+    arrange_matches(&mut matches, descending, count, any);
+    let value = matches_reply(matches, unit, with_coord, with_dist, with_hash);
+    conn.write_value(value).await
+}
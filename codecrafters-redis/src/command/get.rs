@@ -13,19 +13,21 @@ pub(super) async fn handle_get_command(
 ) -> ServerResult<()> {
     conn.log("run command GET");
     let key = args
-        .pop_front_bulk_string()
+        .pop_front_bulk_string_bytes()
         .ok_or_else(|| ServerError::InvalidArgs {
             cmd: "GET",
             args: args.clone(),
         })?;
 
+    // The value is written from the storage, large ones are never copied.
     let value = match storage.get(&key) {
-        Some(value) => match value {
-            Value::Integer(i) => Value::BulkString(BulkString::new(i.value().to_string())),
-            _ => value,
-        },
-        None => Value::BulkString(BulkString::null()),
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            return conn
+                .write_value(Value::BulkString(BulkString::null()))
+                .await
+        }
+        Err(e) => return conn.write_value(e.to_message()).await,
     };
-    conn.log(format!("GET {key:?}={value:?}"));
-    conn.write_value(value).await
+    conn.write_stored(&value.bytes()).await
 }
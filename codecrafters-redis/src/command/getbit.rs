@@ -0,0 +1,34 @@
+use serde_redis::{Array, Integer, Value};
+
+use crate::{
+    command::setbit::parse_bit_offset,
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+pub(super) async fn handle_getbit_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command GETBIT");
+
+    // GETBIT key offset
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "GETBIT",
+            args: args.clone(),
+        })?;
+    let offset = match parse_bit_offset(args.pop_front_bulk_string()) {
+        Ok(v) => v,
+        Err(e) => return conn.write_value(e).await,
+    };
+
+    let value = match storage.string_get_bit(key, offset) {
+        Ok(v) => Value::Integer(Integer::new(v as i64)),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
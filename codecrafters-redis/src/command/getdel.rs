@@ -0,0 +1,28 @@
+use serde_redis::Array;
+
+use crate::{
+    command::bulk_reply,
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+pub(super) async fn handle_getdel_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command GETDEL");
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "GETDEL",
+            args: args.clone(),
+        })?;
+
+    let value = match storage.get_del(&key) {
+        Ok(v) => bulk_reply(v),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
@@ -0,0 +1,75 @@
+use serde_redis::Array;
+
+use crate::{
+    command::{
+        bulk_reply, effect_command,
+        set::{expire_at_effect, parse_expire_at, syntax_error},
+    },
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{SetExpire, Storage},
+};
+
+/// Handle GETEX.
+///
+/// Return the effects to sync to replica, a GETEX with the expiration in absolute time
+/// if the key exists and the expiration is changed.
+pub(super) async fn handle_getex_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<Vec<Array>> {
+    conn.log("run command GETEX");
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "GETEX",
+            args: args.clone(),
+        })?;
+
+    // GETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds | PXAT unix-time-milliseconds | PERSIST]
+    let expire = match args.pop_front_bulk_string() {
+        Some(option) => match option.to_uppercase().as_str() {
+            opt @ ("EX" | "PX" | "EXAT" | "PXAT") => {
+                match parse_expire_at("getex", opt, args.pop_front_bulk_string()) {
+                    Ok(at) => SetExpire::At(at),
+                    Err(e) => {
+                        conn.write_value(e).await?;
+                        return Ok(vec![]);
+                    }
+                }
+            }
+            "PERSIST" => SetExpire::Never,
+            _ => {
+                conn.write_value(syntax_error()).await?;
+                return Ok(vec![]);
+            }
+        },
+        None => SetExpire::Keep,
+    };
+    if !args.is_empty() {
+        conn.write_value(syntax_error()).await?;
+        return Ok(vec![]);
+    }
+
+    let (value, found) = match storage.get_ex(&key, expire) {
+        Ok(v) => (bulk_reply(v.clone()), v.is_some()),
+        Err(e) => (e.to_message(), false),
+    };
+    conn.write_value(value).await?;
+    let effects = match expire {
+        _ if !found => vec![],
+        SetExpire::Never => vec![effect_command([b"GETEX".as_slice(), &key, b"PERSIST"])],
+        SetExpire::At(at) => {
+            let [unit, millis] = expire_at_effect(at);
+            vec![effect_command([
+                b"GETEX".to_vec(),
+                key,
+                unit.into(),
+                millis.into(),
+            ])]
+        }
+        SetExpire::Keep => vec![],
+    };
+    Ok(effects)
+}
@@ -0,0 +1,45 @@
+use serde_redis::{Array, BulkString, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{OpError, Storage},
+};
+
+pub(super) async fn handle_getrange_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command GETRANGE");
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "GETRANGE",
+            args: args.clone(),
+        })?;
+
+    let start = args
+        .pop_front_bulk_string()
+        .and_then(|s| s.parse::<i64>().ok());
+    let end = args
+        .pop_front_bulk_string()
+        .and_then(|s| s.parse::<i64>().ok());
+    let (start, end) = match (start, end) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return conn.write_value(OpError::InvalidInteger.to_message()).await,
+    };
+
+    conn.log(format!(
+        "GETRANGE {:?} {start}..={end}",
+        String::from_utf8_lossy(&key)
+    ));
+
+    let value = match storage.string_get_range(key, start, end) {
+        Ok(v) => Value::BulkString(BulkString::new(v)),
+        Err(OpError::KeyAbsent) => Value::BulkString(BulkString::new("")),
+        Err(e) => e.to_message(),
+    };
+
+    conn.write_value(value).await
+}
@@ -0,0 +1,32 @@
+use serde_redis::Array;
+
+use crate::{
+    command::{bulk_reply, set::pop_front_value},
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{SetCondition, SetExpire, Storage},
+};
+
+pub(super) async fn handle_getset_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command GETSET");
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "GETSET",
+            args: args.clone(),
+        })?;
+    let value = pop_front_value(&mut args).ok_or_else(|| ServerError::InvalidArgs {
+        cmd: "GETSET",
+        args: args.clone(),
+    })?;
+
+    let value = match storage.set(key, value, SetExpire::Never, SetCondition::Always, true) {
+        Ok((_, old_value)) => bulk_reply(old_value),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
@@ -0,0 +1,109 @@
+use serde_redis::{Array, BulkString, Integer, Map, SimpleError, Value};
+
+use crate::{
+    command::auth::authenticate, conn::Conn, error::ServerResult, replication::ReplicationState,
+    state::ServerState,
+};
+
+/// Handle HELLO, switch the protocol of connection and reply server properties.
+///
+/// Replied as map in RESP3, or array of properties and values in RESP2. The
+/// authenticated user is included if authenticated by the AUTH option.
+pub(super) async fn handle_hello_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    rep: ReplicationState,
+    state: &mut ServerState,
+) -> ServerResult<()> {
+    conn.log("run command HELLO");
+
+    // HELLO [protover [AUTH username password]]
+    let protocol = match args.pop_front_bulk_string() {
+        Some(v) => match v.parse::<i64>() {
+            Ok(v @ (2 | 3)) => v as u8,
+            Ok(..) => {
+                let value = Value::SimpleError(SimpleError::with_prefix(
+                    "NOPROTO",
+                    "unsupported protocol version",
+                ));
+                return conn.write_value(value).await;
+            }
+            Err(..) => {
+                let value = Value::SimpleError(SimpleError::with_prefix(
+                    "ERR",
+                    "Protocol version is not an integer or out of range",
+                ));
+                return conn.write_value(value).await;
+            }
+        },
+        None => conn.protocol(),
+    };
+    let mut auth = None;
+    while let Some(option) = args.pop_front_bulk_string() {
+        match (
+            option.to_uppercase().as_str(),
+            args.pop_front_bulk_string(),
+            args.pop_front_bulk_string(),
+        ) {
+            ("AUTH", Some(username), Some(password)) => auth = Some((username, password)),
+            _ => {
+                let value = Value::SimpleError(SimpleError::with_prefix(
+                    "ERR",
+                    format!("Syntax error in HELLO option '{option}'"),
+                ));
+                return conn.write_value(value).await;
+            }
+        }
+    }
+
+    // Authenticate before switching protocol, nothing changes if failed.
+    if auth.is_none() && !conn.is_authenticated() {
+        let value = Value::SimpleError(SimpleError::with_prefix(
+            "NOAUTH",
+            "HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time",
+        ));
+        return conn.write_value(value).await;
+    }
+    if let Some((username, password)) = &auth {
+        if !authenticate(state, username, password) {
+            let value = Value::SimpleError(SimpleError::with_prefix(
+                "WRONGPASS",
+                "invalid username-password pair or user is disabled.",
+            ));
+            return conn.write_value(value).await;
+        }
+        conn.set_user(username.clone());
+        conn.set_authenticated(true);
+    }
+
+    conn.set_protocol(protocol);
+    let role = if rep.is_replica() {
+        "replica"
+    } else {
+        "master"
+    };
+    let props = [
+        ("server", Value::BulkString(BulkString::new("redis"))),
+        (
+            "version",
+            Value::BulkString(BulkString::new(env!("CARGO_PKG_VERSION"))),
+        ),
+        ("proto", Value::Integer(Integer::new(protocol as i64))),
+        ("id", Value::Integer(Integer::new(conn.id as i64))),
+        ("mode", Value::BulkString(BulkString::new("standalone"))),
+        ("role", Value::BulkString(BulkString::new(role))),
+        ("modules", Value::Array(Array::new_empty())),
+    ];
+    let mut props = props
+        .into_iter()
+        .map(|(key, value)| (Value::BulkString(BulkString::new(key)), value))
+        .collect::<Vec<_>>();
+    if auth.is_some() {
+        props.push((
+            Value::BulkString(BulkString::new("user")),
+            Value::BulkString(BulkString::new(conn.user())),
+        ));
+    }
+    let value = Value::Map(Map::with_entries(props));
+    conn.write_value(value).await
+}
@@ -0,0 +1,36 @@
+use serde_redis::{Array, Integer, Value};
+
+use crate::{
+    command::set::syntax_error,
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+/// Handle IMPORT, save all keys in the JSON document produced by EXPORT.
+pub(super) async fn handle_import_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command IMPORT");
+
+    // IMPORT json [REPLACE]
+    let json = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "IMPORT",
+            args: args.clone(),
+        })?;
+    let replace = match args.pop_front_bulk_string() {
+        None => false,
+        Some(v) if v.eq_ignore_ascii_case("REPLACE") && args.is_empty() => true,
+        Some(..) => return conn.write_value(syntax_error()).await,
+    };
+
+    let value = match storage.import_json(&json, replace) {
+        Ok(count) => Value::Integer(Integer::new(count as i64)),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
@@ -1,4 +1,4 @@
-use serde_redis::Array;
+use serde_redis::{Array, Integer, Value};
 
 use crate::{
     conn::Conn,
@@ -13,14 +13,14 @@ pub(super) async fn handle_incr_command(
 ) -> ServerResult<()> {
     conn.log("run command INCR");
     let key = args
-        .pop_front_bulk_string()
+        .pop_front_bulk_string_bytes()
         .ok_or_else(|| ServerError::InvalidArgs {
             cmd: "INCR",
             args: args.clone(),
         })?;
 
     let value = match storage.integer_increase(key) {
-        Ok(v) => v,
+        Ok(v) => Value::Integer(Integer::new(v)),
         Err(e) => e.to_message(),
     };
 
@@ -1,10 +1,68 @@
-use crate::{conn::Conn, error::ServerResult, replication::ReplicationState};
+use serde_redis::{Array, BulkString, Value, VerbatimString};
+
+use crate::{
+    conn::Conn,
+    error::ServerResult,
+    info::{bytes_to_human, ClientsInfo, InstanceInfo, MemoryInfo, ServerInfo},
+    replication::ReplicationState,
+    state::ServerState,
+};
 
 pub(super) async fn handle_info_command(
     conn: &mut Conn<'_>,
+    mut args: Array,
     rep: ReplicationState,
+    state: &mut ServerState,
 ) -> ServerResult<()> {
     conn.log("run command INFO");
-    let value = rep.info();
+
+    // INFO [section [section ...]] [JSON]
+    let mut sections = vec![];
+    while let Some(arg) = args.pop_front_bulk_string() {
+        sections.push(arg.to_lowercase());
+    }
+    let json = sections.last().is_some_and(|x| x == "json");
+    if json {
+        sections.pop();
+    }
+
+    let uptime = state.stats().uptime().as_secs();
+    let (maxmemory, policy) = state
+        .config()
+        .read(|x| (x.maxmemory, x.maxmemory_policy.name()));
+    let used_memory = state.storage().used_memory();
+    let mut info = ServerInfo {
+        server: Some(InstanceInfo {
+            redis_version: env!("CARGO_PKG_VERSION"),
+            redis_mode: "standalone",
+            process_id: std::process::id(),
+            run_id: rep.id(),
+            uptime_in_seconds: uptime,
+            uptime_in_days: uptime / 86400,
+        }),
+        clients: Some(ClientsInfo {
+            connected_clients: state.clients().count(),
+            blocked_clients: state.blocking().count(),
+        }),
+        memory: Some(MemoryInfo {
+            used_memory,
+            used_memory_human: bytes_to_human(used_memory as u64),
+            maxmemory,
+            maxmemory_human: bytes_to_human(maxmemory),
+            maxmemory_policy: policy,
+        }),
+        persistence: Some(state.persistence_info()),
+        stats: Some(state.stats_info()),
+        replication: Some(rep.info()),
+        commandstats: Some(state.stats().command_stats().into_iter().collect()),
+        keysizes: Some(state.storage().info()),
+        keyspace: Some(state.storage().keyspace_info()),
+    };
+    info.retain_sections(&sections);
+    let value = if json {
+        Value::BulkString(BulkString::new(info.to_json()))
+    } else {
+        Value::VerbatimString(VerbatimString::text(info.to_text()))
+    };
     conn.write_value(value).await
 }
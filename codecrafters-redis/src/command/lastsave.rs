@@ -0,0 +1,27 @@
+use serde_redis::{Array, Integer, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    state::ServerState,
+};
+
+/// Handle LASTSAVE, reply the unix time in seconds of the last successful save.
+pub(super) async fn handle_lastsave_command(
+    conn: &mut Conn<'_>,
+    args: Array,
+    state: &mut ServerState,
+) -> ServerResult<()> {
+    conn.log("run command LASTSAVE");
+
+    if !args.is_empty() {
+        return Err(ServerError::InvalidArgs {
+            cmd: "LASTSAVE",
+            args,
+        });
+    }
+
+    let time = state.persistence().last_save_time();
+    conn.write_value(Value::Integer(Integer::new(time as i64)))
+        .await
+}
@@ -0,0 +1,34 @@
+use serde_redis::Array;
+
+use crate::{
+    command::bulk_reply,
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{OpError, Storage},
+};
+
+pub(super) async fn handle_lindex_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command LINDEX");
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "LINDEX",
+            args: args.clone(),
+        })?;
+    let Some(index) = args
+        .pop_front_bulk_string()
+        .and_then(|s| s.parse::<i64>().ok())
+    else {
+        return conn.write_value(OpError::InvalidInteger.to_message()).await;
+    };
+
+    let value = match storage.list_index(&key, index) {
+        Ok(v) => bulk_reply(v),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
@@ -0,0 +1,57 @@
+use serde_redis::{Array, Integer, SimpleError, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{OpError, Storage},
+};
+
+pub(super) async fn handle_linsert_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command LINSERT");
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "LINSERT",
+            args: args.clone(),
+        })?;
+    let position = args
+        .pop_front_bulk_string()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "LINSERT",
+            args: args.clone(),
+        })?;
+    let pivot = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "LINSERT",
+            args: args.clone(),
+        })?;
+    let element = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "LINSERT",
+            args: args.clone(),
+        })?;
+
+    // LINSERT key <BEFORE | AFTER> pivot element
+    let after = match position.to_uppercase().as_str() {
+        "BEFORE" => false,
+        "AFTER" => true,
+        _ => {
+            let value = Value::SimpleError(SimpleError::with_prefix("ERR", "syntax error"));
+            return conn.write_value(value).await;
+        }
+    };
+
+    let value = match storage.list_insert(&key, after, &pivot, element) {
+        Ok(Some(v)) => Value::Integer(Integer::new(v as i64)),
+        Ok(None) => Value::Integer(Integer::new(-1)),
+        Err(OpError::KeyAbsent) => Value::Integer(Integer::new(0)),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
@@ -15,7 +15,7 @@ pub(super) async fn handle_llen_command(
     conn.log("LLEN");
 
     let key = args
-        .pop_front_bulk_string()
+        .pop_front_bulk_string_bytes()
         .ok_or_else(|| ServerError::InvalidArgs {
             cmd: "LLEN",
             args: args.clone(),
@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use serde_redis::{Array, BulkString, SimpleError, Value};
+
+use crate::{
+    blocking::{wait_fed, BlockKind, Unblock},
+    command::{bulk_reply, effect_command, list_end, list_feed_effects},
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    state::ServerState,
+    storage::ListBlockedTask,
+};
+
+/// Parse list end `LEFT` or `RIGHT`, return true if is `RIGHT`.
+pub(super) fn parse_list_end(value: Option<String>) -> Option<bool> {
+    match value?.to_uppercase().as_str() {
+        "LEFT" => Some(false),
+        "RIGHT" => Some(true),
+        _ => None,
+    }
+}
+
+/// Parse the timeout of blocking commands in seconds.
+///
+/// * `Ok(Some(v))` blocks for `v`.
+/// * `Ok(None)` blocks forever.
+/// * `Err(v)` is the error to reply.
+pub(super) fn parse_block_timeout(value: &str) -> Result<Option<Duration>, Value> {
+    match value.parse::<f64>() {
+        Ok(v) if v > 0.0 => Ok(Some(Duration::from_secs_f64(v))),
+        Ok(v) if v >= 0.0 => Ok(None),
+        _ => Err(Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            "timeout is not a float or out of range",
+        ))),
+    }
+}
+
+/// Handle LMOVE, or BLMOVE if `block` is true.
+pub(super) async fn handle_lmove_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    state: &mut ServerState,
+    block: bool,
+) -> ServerResult<Vec<Array>> {
+    let cmd = if block { "BLMOVE" } else { "LMOVE" };
+    conn.log(format!("run command {cmd}"));
+
+    // [B]LMOVE source destination <LEFT | RIGHT> <LEFT | RIGHT> [timeout]
+    let source = args.pop_front_bulk_string_bytes();
+    let destination = args.pop_front_bulk_string_bytes();
+    let from_tail = parse_list_end(args.pop_front_bulk_string());
+    let to_tail = parse_list_end(args.pop_front_bulk_string());
+    let (source, destination, from_tail, to_tail) = match (source, destination, from_tail, to_tail)
+    {
+        (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+        _ => {
+            return Err(ServerError::InvalidArgs {
+                cmd,
+                args: args.clone(),
+            })
+        }
+    };
+    let timeout = if block {
+        let timeout = args
+            .pop_front_bulk_string()
+            .ok_or_else(|| ServerError::InvalidArgs {
+                cmd,
+                args: args.clone(),
+            })?;
+        match parse_block_timeout(&timeout) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                conn.write_value(e).await?;
+                return Ok(vec![]);
+            }
+        }
+    } else {
+        None
+    };
+
+    move_element(
+        conn,
+        state,
+        source,
+        destination,
+        from_tail,
+        to_tail,
+        timeout,
+    )
+    .await
+}
+
+/// Move an element from list `source` to `destination` and reply the element.
+///
+/// Block for `timeout` if `source` is empty, `Some(None)` blocks forever and
+/// `None` does not block.
+///
+/// Return the effects to sync to replica.
+pub(super) async fn move_element(
+    conn: &mut Conn<'_>,
+    state: &mut ServerState,
+    source: Vec<u8>,
+    destination: Vec<u8>,
+    from_tail: bool,
+    to_tail: bool,
+    timeout: Option<Option<Duration>>,
+) -> ServerResult<Vec<Array>> {
+    let mut effects = vec![];
+    let value = match state
+        .storage()
+        .list_move(&source, &destination, from_tail, to_tail)
+    {
+        Ok((Some(v), feeds)) => {
+            effects.push(effect_command([
+                b"LMOVE".as_slice(),
+                &source,
+                &destination,
+                list_end(from_tail).as_bytes(),
+                list_end(to_tail).as_bytes(),
+            ]));
+            effects.extend(list_feed_effects(feeds));
+            bulk_reply(Some(v))
+        }
+        Ok((None, _)) => match timeout {
+            Some(timeout) => {
+                // Nothing to move, block here.
+                //
+                // The element is moved by the push that feeds us, which also syncs the move.
+                let (task, recver) =
+                    ListBlockedTask::new_move(source, from_tail, destination, to_tail);
+                let _task = state.storage_mut().list_add_block_task(conn.id, task);
+                conn.log(format!(
+                    "value not present, blocking connection for {timeout:?}"
+                ));
+                let wait = wait_fed(recver, timeout);
+                match state.blocking().block_on(conn, BlockKind::Keys, wait).await {
+                    Ok(Some((_, Ok(mut values)))) => bulk_reply(values.pop()),
+                    Ok(Some((_, Err(e)))) => e.to_message(),
+                    Ok(None) | Err(Unblock::Timeout) => Value::BulkString(BulkString::null()),
+                    Err(Unblock::Error(e)) => e,
+                }
+            }
+            None => Value::BulkString(BulkString::null()),
+        },
+        Err(e) => e.to_message(),
+    };
+
+    conn.write_value(value).await?;
+    Ok(effects)
+}
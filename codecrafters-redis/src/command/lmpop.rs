@@ -0,0 +1,155 @@
+use serde_redis::{Array, BulkString, SimpleError, Value};
+
+use crate::{
+    blocking::{wait_fed, BlockKind, Unblock},
+    command::{
+        effect_command, elements_reply,
+        lmove::{parse_block_timeout, parse_list_end},
+        set::syntax_error,
+    },
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    state::ServerState,
+    storage::ListBlockedTask,
+};
+
+/// Handle LMPOP, or BLMPOP if `block` is true.
+pub(super) async fn handle_lmpop_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    state: &mut ServerState,
+    block: bool,
+) -> ServerResult<Vec<Array>> {
+    let cmd = if block { "BLMPOP" } else { "LMPOP" };
+    conn.log(format!("run command {cmd}"));
+
+    // BLMPOP timeout numkeys key [key ...] <LEFT | RIGHT> [COUNT count]
+    // LMPOP numkeys key [key ...] <LEFT | RIGHT> [COUNT count]
+    let timeout = if block {
+        let timeout = args
+            .pop_front_bulk_string()
+            .ok_or_else(|| ServerError::InvalidArgs {
+                cmd,
+                args: args.clone(),
+            })?;
+        match parse_block_timeout(&timeout) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                conn.write_value(e).await?;
+                return Ok(vec![]);
+            }
+        }
+    } else {
+        None
+    };
+
+    let num_keys = args
+        .pop_front_bulk_string()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd,
+            args: args.clone(),
+        })?;
+    let num_keys = match num_keys.parse::<i64>() {
+        Ok(v) if v > 0 && v as usize <= args.len() => v as usize,
+        Ok(v) if v > 0 => {
+            conn.write_value(syntax_error()).await?;
+            return Ok(vec![]);
+        }
+        _ => {
+            let value = Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                "numkeys should be greater than 0",
+            ));
+            conn.write_value(value).await?;
+            return Ok(vec![]);
+        }
+    };
+    let mut keys = Vec::with_capacity(num_keys);
+    for _ in 0..num_keys {
+        keys.push(
+            args.pop_front_bulk_string_bytes()
+                .ok_or_else(|| ServerError::InvalidArgs {
+                    cmd,
+                    args: args.clone(),
+                })?,
+        );
+    }
+    let tail = match parse_list_end(args.pop_front_bulk_string()) {
+        Some(v) => v,
+        None => {
+            conn.write_value(syntax_error()).await?;
+            return Ok(vec![]);
+        }
+    };
+    let count = match (args.pop_front_bulk_string(), args.pop_front_bulk_string()) {
+        (None, _) => 1,
+        (Some(option), Some(count)) if option.eq_ignore_ascii_case("COUNT") && args.is_empty() => {
+            match count.parse::<i64>() {
+                Ok(v) if v > 0 => v as usize,
+                _ => {
+                    let value = Value::SimpleError(SimpleError::with_prefix(
+                        "ERR",
+                        "count should be greater than 0",
+                    ));
+                    conn.write_value(value).await?;
+                    return Ok(vec![]);
+                }
+            }
+        }
+        _ => {
+            conn.write_value(syntax_error()).await?;
+            return Ok(vec![]);
+        }
+    };
+
+    // Only sync the pop when elements are popped here.
+    //
+    // If elements are given directly by a push, the push command syncs the pop.
+    let mut effects = vec![];
+    let popped = match state.storage().list_mpop(&keys, tail, count) {
+        Ok(Some((key, values))) => {
+            let pop = if tail { "RPOP" } else { "LPOP" };
+            effects.push(effect_command([
+                pop.as_bytes(),
+                &key,
+                values.len().to_string().as_bytes(),
+            ]));
+            Some((key, Ok(values)))
+        }
+        Ok(None) => match timeout {
+            Some(timeout) => {
+                // No element in any list, block here.
+                let (task, recver) = ListBlockedTask::new_multi(keys, tail, Some(count));
+                let _task = state.storage_mut().list_add_block_task(conn.id, task);
+                conn.log(format!(
+                    "{cmd}: value not present, blocking connection for {timeout:?}"
+                ));
+                let wait = wait_fed(recver, timeout);
+                match state.blocking().block_on(conn, BlockKind::Keys, wait).await {
+                    Ok(v) => v,
+                    Err(Unblock::Timeout) => None,
+                    Err(Unblock::Error(e)) => {
+                        conn.write_value(e).await?;
+                        return Ok(vec![]);
+                    }
+                }
+            }
+            None => None,
+        },
+        Err(e) => {
+            conn.write_value(e.to_message()).await?;
+            return Ok(vec![]);
+        }
+    };
+
+    let value = match popped {
+        Some((key, Ok(elements))) => Value::Array(Array::with_values(vec![
+            Value::BulkString(BulkString::new(key)),
+            elements_reply(elements),
+        ])),
+        Some((_, Err(e))) => e.to_message(),
+        None => Value::Array(Array::null()),
+    };
+    conn.write_value(value).await?;
+    Ok(effects)
+}
@@ -0,0 +1,84 @@
+use serde_redis::{Array, SimpleError, Value, VerbatimString};
+
+use crate::{command::set::syntax_error, conn::Conn, error::ServerResult};
+
+/// Size of the art, in squares.
+const COLS: usize = 12;
+const ROWS: usize = 16;
+
+/// Width of a square in characters.
+const CELL: usize = 4;
+
+pub(super) async fn handle_lolwut_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+) -> ServerResult<()> {
+    conn.log("run command LOLWUT");
+
+    // LOLWUT [VERSION version]
+    let version = match args.pop_front_bulk_string() {
+        None => None,
+        Some(option) if option.to_uppercase() == "VERSION" && args.len() == 1 => {
+            match args
+                .pop_front_bulk_string()
+                .and_then(|x| x.parse::<i64>().ok())
+            {
+                Some(v) => Some(v),
+                None => {
+                    let value = Value::SimpleError(SimpleError::with_prefix(
+                        "ERR",
+                        "value is not an integer or out of range",
+                    ));
+                    return conn.write_value(value).await;
+                }
+            }
+        }
+        Some(_) => return conn.write_value(syntax_error()).await,
+    };
+    conn.log(format!("LOLWUT version={version:?}"));
+
+    let mut text = schotter();
+    text.push_str(concat!(
+        "Georg Nees - schotter, plotter on paper, 1968. Redis ver. ",
+        env!("CARGO_PKG_VERSION"),
+        "\n"
+    ));
+    conn.write_value(Value::VerbatimString(VerbatimString::text(text)))
+        .await
+}
+
+/// Draw "Schotter" by Georg Nees in characters: rows of squares, more displaced
+/// towards the bottom.
+///
+/// Same art for every version, and the same on every call.
+fn schotter() -> String {
+    let width = COLS * CELL + ROWS / 6;
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let mut random = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    let mut text = String::new();
+    for row in 0..ROWS {
+        // Squares never overlap, shifted at most the gap between them.
+        let max_shift = row / 6;
+        let mut line = vec![b' '; width];
+        for col in 0..COLS {
+            let shift = (random() % (max_shift as u64 + 1)) as usize;
+            let glyph: &[u8] = match random() % (row as u64 / 3 + 1) {
+                0 => b"[]",
+                1 => b"[/",
+                2 => b"\\]",
+                _ => b"<>",
+            };
+            let start = col * CELL + shift;
+            line[start..start + glyph.len()].copy_from_slice(glyph);
+        }
+        text.push_str(String::from_utf8_lossy(&line).trim_end());
+        text.push('\n');
+    }
+    text.push('\n');
+    text
+}
@@ -1,49 +1,70 @@
-use serde_redis::{Array, BulkString, Integer, Value};
+use serde_redis::{Array, BulkString, SimpleError, Value};
 
 use crate::{
+    command::{bulk_reply, effect_command, elements_reply},
     conn::Conn,
     error::{ServerError, ServerResult},
-    storage::{OpError, Storage},
+    storage::Storage,
 };
 
+/// Handle LPOP and RPOP.
+///
+/// Set `tail` to true to pop from the tail of list (RPOP).
 pub(super) async fn handle_lpop_command(
     conn: &mut Conn<'_>,
     mut args: Array,
     storage: &mut Storage,
-) -> ServerResult<()> {
-    conn.log("run command LPOP");
-    conn.log("LPOP");
+    tail: bool,
+) -> ServerResult<Vec<Array>> {
+    let cmd = if tail { "RPOP" } else { "LPOP" };
+    conn.log(format!("run command {cmd}"));
 
     let key = args
-        .pop_front_bulk_string()
+        .pop_front_bulk_string_bytes()
         .ok_or_else(|| ServerError::InvalidArgs {
-            cmd: "LPOP",
+            cmd,
             args: args.clone(),
         })?;
 
-    let count: Option<usize>;
-
-    if !args.is_empty() {
-        count = args
-            .pop_front_bulk_string()
-            .and_then(|s| s.parse::<usize>().ok())
-            .ok_or_else(|| ServerError::InvalidArgs {
-                cmd: "LRANGE",
-                args: args.clone(),
-            })
-            .map(Some)?;
-    } else {
-        count = None;
-    }
+    let count = match args.pop_front_bulk_string() {
+        Some(v) => match v.parse::<usize>() {
+            Ok(v) => Some(v),
+            Err(..) => {
+                let value = Value::SimpleError(SimpleError::with_prefix(
+                    "ERR",
+                    "value is out of range, must be positive",
+                ));
+                conn.write_value(value).await?;
+                return Ok(vec![]);
+            }
+        },
+        None => None,
+    };
 
-    let value = match storage.array_pop_front(key, count) {
-        Ok(Some(v)) => v,
-        Ok(None) => Value::BulkString(BulkString::null()),
-        Err(e) => match e {
-            OpError::KeyAbsent => Value::Integer(Integer::new(0)),
-            _ => e.to_message(),
+    // Sync the count of elements actually popped.
+    let mut effects = vec![];
+    let value = match storage.array_pop(&key, count.unwrap_or(1), tail) {
+        Ok(Some(mut values)) => match count {
+            Some(..) => {
+                if !values.is_empty() {
+                    effects.push(effect_command([
+                        cmd.as_bytes(),
+                        &key,
+                        values.len().to_string().as_bytes(),
+                    ]));
+                }
+                elements_reply(values)
+            }
+            None => {
+                effects.push(effect_command([cmd.as_bytes(), &key]));
+                bulk_reply(values.pop())
+            }
         },
+        Ok(None) if count.is_some() => Value::Array(Array::null()),
+        Ok(None) => Value::BulkString(BulkString::null()),
+        Err(e) => e.to_message(),
     };
 
-    conn.write_value(value).await
+    conn.write_value(value).await?;
+    Ok(effects)
 }
@@ -0,0 +1,94 @@
+use serde_redis::{Array, BulkString, Integer, SimpleError, Value};
+
+use crate::{
+    command::set::syntax_error,
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+pub(super) async fn handle_lpos_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command LPOS");
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "LPOS",
+            args: args.clone(),
+        })?;
+    let element = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "LPOS",
+            args: args.clone(),
+        })?;
+
+    // LPOS key element [RANK rank] [COUNT num-matches] [MAXLEN len]
+    let mut rank = 1;
+    let mut count = None;
+    let mut max_len = 0;
+    while let Some(option) = args.pop_front_bulk_string() {
+        let value = match args.pop_front_bulk_string().map(|x| x.parse::<i64>().ok()) {
+            Some(Some(v)) => v,
+            Some(None) => {
+                let value = Value::SimpleError(SimpleError::with_prefix(
+                    "ERR",
+                    "value is not an integer or out of range",
+                ));
+                return conn.write_value(value).await;
+            }
+            None => return conn.write_value(syntax_error()).await,
+        };
+        let error = match option.to_uppercase().as_str() {
+            "RANK" if value == 0 => Some(
+                "RANK can't be zero: use 1 to start from the first match, \
+                 2 from the second ... or use negative to start from the end of the list",
+            ),
+            "RANK" => {
+                rank = value;
+                None
+            }
+            "COUNT" if value < 0 => Some("COUNT can't be negative"),
+            "COUNT" => {
+                count = Some(value as usize);
+                None
+            }
+            "MAXLEN" if value < 0 => Some("MAXLEN can't be negative"),
+            "MAXLEN" => {
+                max_len = value as usize;
+                None
+            }
+            _ => return conn.write_value(syntax_error()).await,
+        };
+        if let Some(e) = error {
+            let value = Value::SimpleError(SimpleError::with_prefix("ERR", e));
+            return conn.write_value(value).await;
+        }
+    }
+
+    let positions = storage.list_positions(
+        &key,
+        &element,
+        rank,
+        count.unwrap_or(1),
+        max_len,
+        conn.budget(),
+    );
+    let value = match positions {
+        // Without COUNT, reply the first match only.
+        Ok(v) if count.is_none() => match v.first() {
+            Some(pos) => Value::Integer(Integer::new(*pos as i64)),
+            None => Value::BulkString(BulkString::null()),
+        },
+        Ok(v) => Value::Array(Array::with_values(
+            v.into_iter()
+                .map(|x| Value::Integer(Integer::new(x as i64)))
+                .collect::<Vec<_>>(),
+        )),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
@@ -13,14 +13,14 @@ pub(super) async fn handle_lrange_command(
 ) -> ServerResult<()> {
     conn.log("run command LRANGE");
     let key = args
-        .pop_front_bulk_string()
+        .pop_front_bulk_string_bytes()
         .ok_or_else(|| ServerError::InvalidArgs {
             cmd: "LRANGE",
             args: args.clone(),
         })?;
     let start = args
         .pop_front_bulk_string()
-        .and_then(|s| s.parse::<i32>().ok())
+        .and_then(|s| s.parse::<i64>().ok())
         .ok_or_else(|| ServerError::InvalidArgs {
             cmd: "LRANGE",
             args: args.clone(),
@@ -28,7 +28,7 @@ pub(super) async fn handle_lrange_command(
 
     let end = args
         .pop_front_bulk_string()
-        .and_then(|s| s.parse::<i32>().ok())
+        .and_then(|s| s.parse::<i64>().ok())
         .ok_or_else(|| ServerError::InvalidArgs {
             cmd: "LRANGE",
             args: args.clone(),
@@ -36,10 +36,8 @@ pub(super) async fn handle_lrange_command(
 
     conn.log(format!("LRANGE {start:?}..={end:?}"));
 
-    let value = storage
-        .lrange(key, start, end)
-        .map_err(|x| x.to_message())
-        .unwrap();
-
-    conn.write_value(value).await
+    match storage.lrange(&key, start, end) {
+        Ok(range) => conn.write_stored_elements(range.elements()).await,
+        Err(e) => conn.write_value(e.to_message()).await,
+    }
 }
@@ -0,0 +1,39 @@
+use serde_redis::{Array, SimpleString, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{OpError, Storage},
+};
+
+pub(super) async fn handle_lset_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command LSET");
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "LSET",
+            args: args.clone(),
+        })?;
+    let Some(index) = args
+        .pop_front_bulk_string()
+        .and_then(|s| s.parse::<i64>().ok())
+    else {
+        return conn.write_value(OpError::InvalidInteger.to_message()).await;
+    };
+    let element = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "LSET",
+            args: args.clone(),
+        })?;
+
+    let value = match storage.list_set(&key, index, element) {
+        Ok(()) => Value::SimpleString(SimpleString::new("OK")),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
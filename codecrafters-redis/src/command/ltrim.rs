@@ -0,0 +1,39 @@
+use serde_redis::{Array, SimpleString, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{OpError, Storage},
+};
+
+pub(super) async fn handle_ltrim_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command LTRIM");
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "LTRIM",
+            args: args.clone(),
+        })?;
+    let Some(start) = args
+        .pop_front_bulk_string()
+        .and_then(|s| s.parse::<i64>().ok())
+    else {
+        return conn.write_value(OpError::InvalidInteger.to_message()).await;
+    };
+    let Some(stop) = args
+        .pop_front_bulk_string()
+        .and_then(|s| s.parse::<i64>().ok())
+    else {
+        return conn.write_value(OpError::InvalidInteger.to_message()).await;
+    };
+
+    let value = match storage.list_trim(&key, start, stop) {
+        Ok(()) => Value::SimpleString(SimpleString::new("OK")),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
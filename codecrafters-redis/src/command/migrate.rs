@@ -0,0 +1,258 @@
+use std::time::{Duration, SystemTime};
+
+use serde_redis::{Array, SimpleError, SimpleString, Value};
+use tokio::{io::AsyncReadExt, net::TcpStream};
+
+use crate::{
+    command::{effect_command, set::syntax_error},
+    conn::{write_all, Conn},
+    error::{ServerError, ServerResult},
+    replication::frame_len,
+    state::ServerState,
+};
+
+/// Timeout of MIGRATE if not specified.
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Connection to the target node of MIGRATE.
+///
+/// Like other commands, the target node reads one command at a time, so each command
+/// is sent after the reply of the previous one.
+struct Target {
+    stream: TcpStream,
+    timeout: Duration,
+}
+
+impl Target {
+    /// Send command of `parts` and read the reply.
+    ///
+    /// Return the error replied to the client of MIGRATE if failed to send or read.
+    async fn request<T: Into<Vec<u8>>>(
+        &mut self,
+        parts: impl IntoIterator<Item = T>,
+    ) -> Result<Value, Value> {
+        let ioerr = |message: &str| Value::SimpleError(SimpleError::with_prefix("IOERR", message));
+        let data = serde_redis::to_vec(&Value::Array(effect_command(parts))).unwrap();
+        tokio::time::timeout(self.timeout, write_all(&mut self.stream, &data))
+            .await
+            .ok()
+            .and_then(|x| x.ok())
+            .ok_or_else(|| ioerr("error or timeout writing to target instance"))?;
+        let read = async {
+            let mut buf = vec![];
+            loop {
+                if let Some(len) = frame_len(&buf).ok()? {
+                    return serde_redis::from_bytes::<Value>(&buf[..len]).ok();
+                }
+                let mut chunk = [0u8; 1024];
+                match self.stream.read(&mut chunk).await {
+                    Ok(0) | Err(..) => return None,
+                    Ok(n) => buf.extend(&chunk[..n]),
+                }
+            }
+        };
+        tokio::time::timeout(self.timeout, read)
+            .await
+            .ok()
+            .flatten()
+            .ok_or_else(|| ioerr("error or timeout reading to target instance"))
+    }
+
+    /// Send command of `parts`, expecting OK.
+    ///
+    /// Return the error replied to the client of MIGRATE if failed.
+    async fn expect_ok<T: Into<Vec<u8>>>(
+        &mut self,
+        parts: impl IntoIterator<Item = T>,
+    ) -> Result<(), Value> {
+        match self.request(parts).await? {
+            Value::SimpleError(e) => Err(Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                format!(
+                    "Target instance replied with error: {}",
+                    match e.prefix() {
+                        Some(prefix) => format!("{prefix} {}", e.message()),
+                        None => e.message().to_string(),
+                    }
+                ),
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Handle MIGRATE, move keys to another node by DUMP and RESTORE.
+///
+/// Only database 0 is supported, like other commands.
+///
+/// Return the effects to sync to replica, DEL of the keys moved.
+pub(super) async fn handle_migrate_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    state: &mut ServerState,
+) -> ServerResult<Vec<Array>> {
+    conn.log("run command MIGRATE");
+
+    // MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE]
+    //   [AUTH password | AUTH2 username password] [KEYS key [key ...]]
+    let all = std::iter::from_fn(|| args.pop_front_bulk_string_bytes()).collect::<Vec<_>>();
+    let [host, port, key, db, timeout, options @ ..] = all.as_slice() else {
+        return Err(ServerError::InvalidArgs {
+            cmd: "MIGRATE",
+            args,
+        });
+    };
+    let (mut copy, mut replace, mut auth, mut keys) = (false, false, None, vec![]);
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            b"COPY" => copy = true,
+            b"REPLACE" => replace = true,
+            b"AUTH" => match options.next() {
+                Some(password) => auth = Some(vec![password.clone()]),
+                None => {
+                    conn.write_value(syntax_error()).await?;
+                    return Ok(vec![]);
+                }
+            },
+            b"AUTH2" => match (options.next(), options.next()) {
+                (Some(username), Some(password)) => {
+                    auth = Some(vec![username.clone(), password.clone()])
+                }
+                _ => {
+                    conn.write_value(syntax_error()).await?;
+                    return Ok(vec![]);
+                }
+            },
+            b"KEYS" if key.is_empty() => {
+                keys = options.by_ref().cloned().collect();
+            }
+            b"KEYS" => {
+                let value = Value::SimpleError(SimpleError::with_prefix(
+                    "ERR",
+                    "When using MIGRATE KEYS option, the key argument must be set to the empty string",
+                ));
+                conn.write_value(value).await?;
+                return Ok(vec![]);
+            }
+            _ => {
+                conn.write_value(syntax_error()).await?;
+                return Ok(vec![]);
+            }
+        }
+    }
+    if !key.is_empty() {
+        keys.push(key.clone());
+    }
+    let port = std::str::from_utf8(port)
+        .ok()
+        .and_then(|x| x.parse::<u16>().ok());
+    let timeout = std::str::from_utf8(timeout)
+        .ok()
+        .and_then(|x| x.parse::<i64>().ok());
+    let (Some(port), Some(timeout)) = (port, timeout) else {
+        let value = Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            "value is not an integer or out of range",
+        ));
+        conn.write_value(value).await?;
+        return Ok(vec![]);
+    };
+    if db != b"0" {
+        let value = Value::SimpleError(SimpleError::with_prefix("ERR", "DB index is out of range"));
+        conn.write_value(value).await?;
+        return Ok(vec![]);
+    }
+    let timeout = u64::try_from(timeout)
+        .ok()
+        .filter(|x| *x > 0)
+        .map_or(DEFAULT_TIMEOUT, Duration::from_millis);
+
+    // Keys not present or expired meanwhile are skipped.
+    let now = SystemTime::now();
+    let dumps = keys
+        .iter()
+        .filter_map(|key| {
+            let (payload, expiration) = state.storage().dump(key)?;
+            let ttl = match expiration {
+                Some(at) => at.duration_since(now).ok()?.as_millis().max(1),
+                None => 0,
+            };
+            Some((key.clone(), ttl, payload))
+        })
+        .collect::<Vec<_>>();
+    if dumps.is_empty() {
+        conn.write_value(Value::SimpleString(SimpleString::new("NOKEY")))
+            .await?;
+        return Ok(vec![]);
+    }
+
+    let connected = tokio::time::timeout(
+        timeout,
+        TcpStream::connect((String::from_utf8_lossy(host).as_ref(), port)),
+    )
+    .await;
+    let Ok(Ok(stream)) = connected else {
+        let value = Value::SimpleError(SimpleError::with_prefix(
+            "IOERR",
+            "error or timeout connecting to the client",
+        ));
+        conn.write_value(value).await?;
+        return Ok(vec![]);
+    };
+    let mut target = Target { stream, timeout };
+    let cluster = state.cluster().is_enabled();
+    let mut moved = vec![];
+    let mut result = match auth {
+        Some(auth) => {
+            target
+                .expect_ok([b"AUTH".to_vec()].into_iter().chain(auth))
+                .await
+        }
+        None => Ok(()),
+    };
+    for (key, ttl, payload) in dumps {
+        if result.is_err() {
+            break;
+        }
+        // The slot is importing on the target node in the middle of migration.
+        if cluster {
+            result = target.expect_ok(["ASKING"]).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        let mut parts = vec![
+            b"RESTORE".to_vec(),
+            key.clone(),
+            ttl.to_string().into_bytes(),
+            payload,
+        ];
+        if replace {
+            parts.push(b"REPLACE".to_vec());
+        }
+        result = target.expect_ok(parts).await;
+        if result.is_ok() {
+            moved.push(key);
+        }
+    }
+    conn.log(format!(
+        "MIGRATE moved {} keys to {}:{port}",
+        moved.len(),
+        String::from_utf8_lossy(host)
+    ));
+
+    // Keys moved before an error are still removed here.
+    let effects = if copy || moved.is_empty() {
+        vec![]
+    } else {
+        state.storage().delete(&moved);
+        vec![effect_command([b"DEL".to_vec()].into_iter().chain(moved))]
+    };
+    let value = match result {
+        Ok(()) => Value::SimpleString(SimpleString::new("OK")),
+        Err(e) => e,
+    };
+    conn.write_value(value).await?;
+    Ok(effects)
+}
@@ -1,44 +1,207 @@
-use serde_redis::{Array, SimpleError, SimpleString, Value};
+use std::borrow::Cow;
+
+use serde_redis::{Array, BulkString, SimpleError, SimpleString, Value};
+use tokio::time::Instant;
 
 use crate::{
+    cluster::key_slot,
     command::{
-        blpop::handle_blpop_command, discard::handle_discard_command, echo::handle_echo_command,
-        exec::handle_exec_command, get::handle_get_command, incr::handle_incr_command,
-        info::handle_info_command, llen::handle_llen_command, lpop::handle_lpop_command,
-        lpush::handle_lpush_command, lrange::handle_lrange_command, multi::handle_multi_command,
-        ping::handle_ping_command, psync::handle_psync_command, replconf::handle_replconf_command,
-        rpush::handle_rpush_command, set::handle_set_command, tipe::handle_type_command,
-        wait::handle_wait_command, xadd::handle_xadd_command, xrange::handle_xrange_command,
+        acl::handle_acl_command,
+        append::handle_append_command,
+        asking::handle_asking_command,
+        auth::handle_auth_command,
+        bgrewriteaof::handle_bgrewriteaof_command,
+        bitcount::handle_bitcount_command,
+        bitpos::handle_bitpos_command,
+        blpop::handle_blpop_command,
+        bzpop::handle_bzpop_command,
+        client::handle_client_command,
+        cluster::handle_cluster_command,
+        command_info::handle_command_command,
+        config::handle_config_command,
+        debug::handle_debug_command,
+        del::handle_del_command,
+        discard::handle_discard_command,
+        dump::handle_dump_command,
+        echo::handle_echo_command,
+        exec::handle_exec_command,
+        export::handle_export_command,
+        failover::handle_failover_command,
+        flush::handle_flush_command,
+        geoadd::handle_geoadd_command,
+        geodist::handle_geodist_command,
+        geopos::handle_geopos_command,
+        georadius::handle_georadius_command,
+        geosearch::handle_geosearch_command,
+        get::handle_get_command,
+        getbit::handle_getbit_command,
+        getdel::handle_getdel_command,
+        getex::handle_getex_command,
+        getrange::handle_getrange_command,
+        getset::handle_getset_command,
+        hello::handle_hello_command,
+        import::handle_import_command,
+        incr::handle_incr_command,
+        info::handle_info_command,
+        lastsave::handle_lastsave_command,
+        lindex::handle_lindex_command,
+        linsert::handle_linsert_command,
+        llen::handle_llen_command,
+        lmove::handle_lmove_command,
+        lmpop::handle_lmpop_command,
+        lolwut::handle_lolwut_command,
+        lpop::handle_lpop_command,
+        lpos::handle_lpos_command,
+        lpush::handle_lpush_command,
+        lrange::handle_lrange_command,
+        lrem::handle_lrem_command,
+        lset::handle_lset_command,
+        ltrim::handle_ltrim_command,
+        migrate::handle_migrate_command,
+        monitor::handle_monitor_command,
+        multi::handle_multi_command,
+        object::handle_object_command,
+        pfadd::handle_pfadd_command,
+        pfcount::handle_pfcount_command,
+        pfmerge::handle_pfmerge_command,
+        ping::handle_ping_command,
+        psync::handle_psync_command,
+        publish::handle_publish_command,
+        pubsub::handle_pubsub_command,
+        replconf::handle_replconf_command,
+        replicaof::handle_replicaof_command,
+        restore::handle_restore_command,
+        rpoplpush::handle_rpoplpush_command,
+        rpush::handle_rpush_command,
+        save::handle_save_command,
+        set::handle_set_command,
+        setbit::handle_setbit_command,
+        setex::handle_setex_command,
+        setnx::handle_setnx_command,
+        setrange::handle_setrange_command,
+        sort::handle_sort_command,
+        strlen::handle_strlen_command,
+        subscribe::handle_subscribe_command,
+        subscribe::handle_unsubscribe_command,
+        table::{lookup_command, lowercase_command_name, CommandSpec},
+        tipe::handle_type_command,
+        wait::handle_wait_command,
+        xack::handle_xack_command,
+        xadd::handle_xadd_command,
+        xautoclaim::handle_xautoclaim_command,
+        xclaim::handle_xclaim_command,
+        xgroup::handle_xgroup_command,
+        xinfo::handle_xinfo_command,
+        xpending::handle_xpending_command,
+        xrange::handle_xrange_command,
         xread::handle_xread_command,
+        xreadgroup::handle_xreadgroup_command,
+        xsetid::handle_xsetid_command,
+        zincrby::handle_zincrby_command,
+        zpop::handle_zpop_command,
     },
     conn::Conn,
     error::{ServerError, ServerResult},
+    load::Budget,
+    pubsub::SubscriptionKind,
     replication::ReplicationState,
-    storage::Storage,
+    server::propagate,
+    state::ServerState,
+    storage::{ListFeed, ZpopFeed},
 };
 
+mod acl;
+mod append;
+mod asking;
+mod auth;
+mod bgrewriteaof;
+mod bitcount;
+mod bitpos;
 mod blpop;
+mod bzpop;
+mod client;
+mod cluster;
+mod command_info;
+mod config;
+mod debug;
+mod del;
 mod discard;
+mod dump;
 mod echo;
 mod exec;
+mod export;
+mod failover;
+mod flush;
+mod geoadd;
+mod geodist;
+mod geopos;
+mod georadius;
+mod geosearch;
 mod get;
+mod getbit;
+mod getdel;
+mod getex;
+mod getrange;
+mod getset;
+mod hello;
+mod import;
 mod incr;
 mod info;
+mod lastsave;
+mod lindex;
+mod linsert;
 mod llen;
+mod lmove;
+mod lmpop;
+mod lolwut;
 mod lpop;
+mod lpos;
 mod lpush;
 mod lrange;
+mod lrem;
+mod lset;
+mod ltrim;
+mod migrate;
+mod monitor;
 mod multi;
+mod object;
+mod pfadd;
+mod pfcount;
+mod pfmerge;
 mod ping;
 mod psync;
+mod publish;
+mod pubsub;
 mod replconf;
+mod replicaof;
+mod restore;
+mod rpoplpush;
 mod rpush;
+mod save;
 mod set;
+mod setbit;
+mod setex;
+mod setnx;
+mod setrange;
+mod sort;
+mod strlen;
+mod subscribe;
+mod table;
 mod tipe;
 mod wait;
+mod xack;
 mod xadd;
+mod xautoclaim;
+mod xclaim;
+mod xgroup;
+mod xinfo;
+mod xpending;
 mod xrange;
 mod xread;
+mod xreadgroup;
+mod xsetid;
+mod zincrby;
+mod zpop;
 
 pub(crate) enum DispatchResult {
     /// Nothing special to do.
@@ -47,35 +210,882 @@ pub(crate) enum DispatchResult {
     /// Save the connection as replica connection.
     Replica,
 
+    /// Feed commands processed to the connection, see `monitor`.
+    Monitor,
+
     /// Current command need to be synced to replica.
     ///
     /// * If current redis instance is a replica node, apply that command on "myself",
     ///   now "myself" is the redis node that need need to be synced.
     /// * If current redis instance is a master node, record that this command should
     ///   send to all replica nodes that want to sync their data.
+    ///
+    /// Only returned by handlers, [`dispatch_command`] turns it into
+    /// [`DispatchResult::ReplicaSyncEffects`] carrying the command, or drops it if the
+    /// command wrote nothing to the dataset.
     ReplicaSync,
+
+    /// Sync the effects of current command to replica, instead of the command itself.
+    ///
+    /// Used when replaying the command on replica may produce a different result,
+    /// e.g. BLPOP that received an element directly from a push. Nothing to sync if
+    /// empty.
+    ReplicaSyncEffects(Vec<Array>),
+}
+
+/// Build a command to sync to replica from `parts`.
+fn effect_command<T: Into<Vec<u8>>>(parts: impl IntoIterator<Item = T>) -> Array {
+    parts
+        .into_iter()
+        .map(|x| Value::BulkString(BulkString::new(x)))
+        .collect()
+}
+
+/// Reply string `bytes` read from storage, null if `None`.
+fn bulk_reply(bytes: Option<Vec<u8>>) -> Value {
+    match bytes {
+        Some(v) => Value::BulkString(BulkString::new(v)),
+        None => Value::BulkString(BulkString::null()),
+    }
+}
+
+/// Reply list `elements` read from storage in an array.
+fn elements_reply(elements: Vec<Vec<u8>>) -> Value {
+    Value::Array(
+        elements
+            .into_iter()
+            .map(|x| Value::BulkString(BulkString::new(x)))
+            .collect(),
+    )
+}
+
+/// Name of the list end, used in LMOVE.
+fn list_end(tail: bool) -> &'static str {
+    if tail {
+        "RIGHT"
+    } else {
+        "LEFT"
+    }
+}
+
+/// Build the commands that take the elements taken by blocked tasks.
+fn list_feed_effects(feeds: Vec<ListFeed>) -> Vec<Array> {
+    feeds
+        .into_iter()
+        .map(|feed| match feed {
+            ListFeed::Pop { key, tail, count } => {
+                let cmd = if tail { "RPOP" } else { "LPOP" };
+                match count {
+                    Some(c) => effect_command([cmd.as_bytes(), &key, c.to_string().as_bytes()]),
+                    None => effect_command([cmd.as_bytes(), &key]),
+                }
+            }
+            ListFeed::Move {
+                source,
+                destination,
+                from_tail,
+                to_tail,
+            } => effect_command([
+                b"LMOVE".as_slice(),
+                &source,
+                &destination,
+                list_end(from_tail).as_bytes(),
+                list_end(to_tail).as_bytes(),
+            ]),
+        })
+        .collect()
+}
+
+/// Build the commands that take the members taken by blocked BZPOPMIN and BZPOPMAX
+/// tasks.
+fn zpop_feed_effects(feeds: Vec<ZpopFeed>) -> Vec<Array> {
+    feeds
+        .into_iter()
+        .map(|feed| {
+            let cmd = if feed.max { "ZPOPMAX" } else { "ZPOPMIN" };
+            effect_command([cmd.as_bytes(), &feed.key])
+        })
+        .collect()
+}
+
+/// Convert raw command name `cmd` into uppercase.
+///
+/// Reuses the buffer of `cmd` and converts in place, no allocation happens.
+fn canonical_command(cmd: Vec<u8>) -> ServerResult<String> {
+    let mut cmd =
+        String::from_utf8(cmd).map_err(|e| ServerError::InvalidCommand(format!("{e:?}")))?;
+    cmd.make_ascii_uppercase();
+    Ok(cmd)
+}
+
+/// Check whether command `cmd` is implemented.
+pub(crate) fn is_known_command(cmd: &str) -> bool {
+    lookup_command(cmd).is_some()
+}
+
+/// Reply of unknown command `cmd` with `args`, same as redis.
+///
+/// The command name and args are truncated to 128 bytes, and newlines are replaced
+/// with spaces so the reply stays in one line.
+fn unknown_command_error(cmd: &[u8], args: &Array) -> Value {
+    const LIMIT: usize = 128;
+    let cmd = String::from_utf8_lossy(&cmd[..cmd.len().min(LIMIT)]).to_string();
+    let mut text = String::new();
+    for arg in args.iter().filter_map(|x| match x {
+        Value::BulkString(v) => v.value(),
+        _ => None,
+    }) {
+        if text.len() >= LIMIT {
+            break;
+        }
+        let arg = &arg[..arg.len().min(LIMIT - text.len())];
+        text.push_str(&format!("'{}' ", String::from_utf8_lossy(arg)));
+    }
+    let message = format!("unknown command '{cmd}', with args beginning with: {text}")
+        .replace(['\r', '\n'], " ");
+    Value::SimpleError(SimpleError::with_prefix("ERR", message))
+}
+
+/// Reject command `cmd` not implemented, with the raw name as sent.
+///
+/// Return true if rejected. Unknown commands are replied with an error like redis,
+/// the connection is kept.
+async fn reject_unknown(conn: &mut Conn<'_>, cmd: &[u8], args: &Array) -> ServerResult<bool> {
+    if std::str::from_utf8(cmd).is_ok_and(is_known_command) {
+        return Ok(false);
+    }
+    conn.reject_command();
+    conn.write_value(unknown_command_error(cmd, args)).await?;
+    Ok(true)
+}
+
+/// Check whether command `cmd` may write the dataset.
+///
+/// Driven by the "write" flag in the command table, unknown commands never write.
+fn is_write_command(cmd: &str) -> bool {
+    lookup_command(cmd).is_some_and(CommandSpec::is_write)
+}
+
+/// Check whether command `cmd` shall be held by CLIENT PAUSE WRITE.
+///
+/// Besides write commands, WAIT is held because it waits for the writes before it,
+/// and EXEC is held because the transaction may contain writes.
+fn is_pausable_write(cmd: &str) -> bool {
+    is_write_command(cmd) || matches!(cmd, "WAIT" | "EXEC")
+}
+
+/// Hold command `cmd` till the client pause ends.
+///
+/// Commands from master node and CLIENT commands are never held.
+async fn wait_pause(
+    conn: &mut Conn<'_>,
+    state: &ServerState,
+    cmd: &str,
+    write: bool,
+) -> ServerResult<()> {
+    if conn.is_master_link() || cmd == "CLIENT" || !state.pause().is_paused(write) {
+        return Ok(());
+    }
+    conn.log(format!("{cmd} held by client pause"));
+    // Replies of commands pipelined before are not held.
+    conn.flush().await?;
+    state.pause().wait(write).await;
+    Ok(())
+}
+
+/// Evict keys by `maxmemory` before write command `cmd`, then reject it if storage is
+/// still out of memory.
+///
+/// Evicted keys are synced to replicas as DEL, replicas never evict by themselves.
+///
+/// Return true if rejected. Commands from master node are never rejected, nor DEL
+/// as it frees memory.
+async fn reject_oom(
+    conn: &mut Conn<'_>,
+    state: &ServerState,
+    rep: &ReplicationState,
+    cmd: &str,
+) -> ServerResult<bool> {
+    if conn.is_master_link() || !is_write_command(cmd) || cmd == "DEL" {
+        return Ok(false);
+    }
+    let evicted = state.evict();
+    if !evicted.is_empty() {
+        conn.log(format!("evicted {} keys before {cmd}", evicted.len()));
+        let effects = evicted
+            .iter()
+            .map(|x| effect_command([b"DEL".as_slice(), x]))
+            .collect();
+        propagate(rep, state, conn.id, effects);
+    }
+    match state.check_oom() {
+        Ok(()) => Ok(false),
+        Err(e) => {
+            conn.log(format!("{cmd} rejected by out of memory"));
+            conn.reject_command();
+            conn.write_value(e.to_message()).await?;
+            Ok(true)
+        }
+    }
+}
+
+/// Reject command `cmd` while loading the dataset at startup.
+///
+/// Return true if rejected. Like redis, commands inspecting or managing the connection
+/// and the server are allowed, so clients can wait for loading to finish. Commands from
+/// master node are never rejected.
+async fn reject_loading(conn: &mut Conn<'_>, state: &ServerState, cmd: &str) -> ServerResult<bool> {
+    if conn.is_master_link()
+        || matches!(
+            cmd,
+            "PING"
+                | "ECHO"
+                | "INFO"
+                | "AUTH"
+                | "HELLO"
+                | "CLIENT"
+                | "COMMAND"
+                | "CONFIG"
+                | "DEBUG"
+                | "REPLCONF"
+                | "MULTI"
+                | "EXEC"
+                | "DISCARD"
+        )
+        || !state.lifecycle().is_loading()
+    {
+        return Ok(false);
+    }
+    conn.log(format!("{cmd} rejected by loading"));
+    let value = Value::SimpleError(SimpleError::with_prefix(
+        "LOADING",
+        "Redis is loading the dataset in memory",
+    ));
+    conn.reject_command();
+    conn.write_value(value).await?;
+    Ok(true)
+}
+
+/// Keys read by command `cmd` with `args`, recorded for connections tracking keys.
+fn read_keys(cmd: &str, args: &Array) -> Vec<Vec<u8>> {
+    match cmd {
+        "GET"
+        | "STRLEN"
+        | "GETRANGE"
+        | "GETBIT"
+        | "BITCOUNT"
+        | "BITPOS"
+        | "LRANGE"
+        | "LLEN"
+        | "LINDEX"
+        | "LPOS"
+        | "TYPE"
+        | "XRANGE"
+        | "XREVRANGE"
+        | "GEOPOS"
+        | "GEODIST"
+        | "GEOSEARCH"
+        | "GEORADIUS_RO"
+        | "GEORADIUSBYMEMBER_RO"
+        | "SORT_RO"
+        | "DUMP" => args
+            .clone()
+            .pop_front_bulk_string_bytes()
+            .into_iter()
+            .collect(),
+        "EXPORT" | "PFCOUNT" => {
+            let mut args = args.clone();
+            std::iter::from_fn(|| args.pop_front_bulk_string_bytes()).collect()
+        }
+        _ => vec![],
+    }
+}
+
+/// Categories of commands in ACL rules, besides "all".
+pub(crate) const CATEGORIES: &[&str] = &[
+    "admin",
+    "blocking",
+    "connection",
+    "dangerous",
+    "keyspace",
+    "pubsub",
+    "read",
+    "transaction",
+    "write",
+];
+
+/// Categories of command `cmd` in ACL rules, a subset of those in redis.
+pub(crate) fn command_categories(cmd: &str) -> Vec<&'static str> {
+    let mut categories = vec![];
+    let admin = matches!(
+        cmd,
+        "ACL"
+            | "CONFIG"
+            | "DEBUG"
+            | "FAILOVER"
+            | "SAVE"
+            | "BGSAVE"
+            | "BGREWRITEAOF"
+            | "LASTSAVE"
+            | "MONITOR"
+            | "REPLCONF"
+            | "REPLICAOF"
+            | "SLAVEOF"
+            | "PSYNC"
+    );
+    if admin {
+        categories.push("admin");
+    }
+    if is_blocking_command(cmd) {
+        categories.push("blocking");
+    }
+    if matches!(
+        cmd,
+        "PING" | "ECHO" | "AUTH" | "HELLO" | "CLIENT" | "COMMAND"
+    ) {
+        categories.push("connection");
+    }
+    if admin || matches!(cmd, "FLUSHALL" | "FLUSHDB" | "MIGRATE" | "RESTORE") {
+        categories.push("dangerous");
+    }
+    if matches!(
+        cmd,
+        "DEL"
+            | "TYPE"
+            | "OBJECT"
+            | "EXPORT"
+            | "IMPORT"
+            | "DUMP"
+            | "RESTORE"
+            | "MIGRATE"
+            | "FLUSHALL"
+            | "FLUSHDB"
+    ) {
+        categories.push("keyspace");
+    }
+    if matches!(
+        cmd,
+        "SUBSCRIBE"
+            | "PSUBSCRIBE"
+            | "SSUBSCRIBE"
+            | "UNSUBSCRIBE"
+            | "PUNSUBSCRIBE"
+            | "SUNSUBSCRIBE"
+            | "PUBLISH"
+            | "SPUBLISH"
+            | "PUBSUB"
+    ) {
+        categories.push("pubsub");
+    }
+    let probe = Array::with_values(vec![Value::BulkString(BulkString::new("key"))]);
+    if !read_keys(cmd, &probe).is_empty()
+        || matches!(cmd, "XREAD" | "XINFO" | "XPENDING" | "OBJECT")
+    {
+        categories.push("read");
+    }
+    if matches!(cmd, "MULTI" | "EXEC" | "DISCARD") {
+        categories.push("transaction");
+    }
+    if is_write_command(cmd) {
+        categories.push("write");
+    }
+    categories
+}
+
+/// Keys accessed by command `cmd` with `args`, checked by ACL key patterns and routed
+/// by hash slots in cluster mode.
+///
+/// Keys are found at the positions in the command table, or by parsing `args` for
+/// commands with "movablekeys" and container commands.
+pub(crate) fn command_keys(cmd: &str, args: &Array) -> Vec<Vec<u8>> {
+    let mut args = args.clone();
+    let mut all = std::iter::from_fn(|| args.pop_front_bulk_string_bytes()).collect::<Vec<_>>();
+    match cmd {
+        "LMPOP" | "BLMPOP" => {
+            // [timeout] numkeys key [key ...]
+            let skip = (cmd == "BLMPOP") as usize;
+            let count = all
+                .get(skip)
+                .and_then(|x| std::str::from_utf8(x).ok()?.parse::<usize>().ok())
+                .unwrap_or_default();
+            all.into_iter().skip(skip + 1).take(count).collect()
+        }
+        "XREAD" | "XREADGROUP" => {
+            // Keys and ids follow STREAMS in two halves.
+            let Some(pos) = all.iter().position(|x| x.eq_ignore_ascii_case(b"STREAMS")) else {
+                return vec![];
+            };
+            let rest = all.split_off(pos + 1);
+            rest.iter().take(rest.len() / 2).cloned().collect()
+        }
+        "XINFO" | "XGROUP" | "OBJECT" => all.into_iter().skip(1).take(1).collect(),
+        "SORT" | "GEORADIUS" | "GEORADIUSBYMEMBER" => {
+            // key [arguments] [options], the destination key follows STORE or
+            // STOREDIST. Arguments of other options are skipped, as they may look
+            // like STORE.
+            let skip = match cmd {
+                "GEORADIUS" => 5,
+                "GEORADIUSBYMEMBER" => 4,
+                _ => 1,
+            };
+            let mut keys = all.iter().take(1).cloned().collect::<Vec<_>>();
+            let mut rest = all.iter().skip(skip);
+            while let Some(option) = rest.next() {
+                match option.to_ascii_uppercase().as_slice() {
+                    b"STORE" | b"STOREDIST" => keys.extend(rest.next().cloned()),
+                    b"BY" | b"GET" | b"COUNT" => {
+                        rest.next();
+                    }
+                    b"LIMIT" => {
+                        rest.nth(1);
+                    }
+                    _ => {}
+                }
+            }
+            keys
+        }
+        "MIGRATE" => {
+            // host port key|"" db timeout [options] [KEYS key [key ...]]
+            match all.get(2) {
+                Some(key) if !key.is_empty() => vec![key.clone()],
+                _ => match all.iter().position(|x| x.eq_ignore_ascii_case(b"KEYS")) {
+                    Some(pos) => all.split_off(pos + 1),
+                    None => vec![],
+                },
+            }
+        }
+        _ => {
+            let Some(spec) = lookup_command(cmd).filter(|x| x.first_key > 0) else {
+                return vec![];
+            };
+            // Positions count the command name at 0.
+            let argc = all.len() as i64 + 1;
+            let last = if spec.last_key < 0 {
+                argc + spec.last_key
+            } else {
+                spec.last_key.min(argc - 1)
+            };
+            (spec.first_key..=last)
+                .step_by(spec.step.max(1) as usize)
+                .filter_map(|x| all.get(x as usize - 1).cloned())
+                .collect()
+        }
+    }
+}
+
+/// Reject read command `cmd` on replica if too many commands are pending.
+///
+/// Return true if rejected. Writes and commands managing the connection or server are
+/// never rejected, so does commands from master node.
+async fn reject_load(
+    conn: &mut Conn<'_>,
+    state: &ServerState,
+    rep: &ReplicationState,
+    cmd: &str,
+) -> ServerResult<bool> {
+    if conn.is_master_link()
+        || is_write_command(cmd)
+        || matches!(
+            cmd,
+            "PING"
+                | "ECHO"
+                | "INFO"
+                | "CLIENT"
+                | "CONFIG"
+                | "DEBUG"
+                | "REPLCONF"
+                | "PSYNC"
+                | "MULTI"
+                | "EXEC"
+                | "DISCARD"
+        )
+        || !rep.is_replica()
+        || !state.load().shed()
+    {
+        return Ok(false);
+    }
+    conn.log(format!("{cmd} rejected by load"));
+    let value = Value::SimpleError(SimpleError::with_prefix(
+        "BUSY",
+        format!(
+            "too many pending commands ({}), try again later",
+            state.load().depth()
+        ),
+    ));
+    conn.reject_command();
+    conn.write_value(value).await?;
+    Ok(true)
+}
+
+/// Redirect command `cmd` with `args` to the node serving its keys in cluster mode.
+///
+/// Return true if redirected, or rejected as keys are in different slots. Commands
+/// without keys and commands from master node are always served here.
+async fn reject_redirect(
+    conn: &mut Conn<'_>,
+    state: &ServerState,
+    cmd: &str,
+    args: &Array,
+) -> ServerResult<bool> {
+    if conn.is_master_link() || !state.cluster().is_enabled() {
+        return Ok(false);
+    }
+    let keys = command_keys(cmd, args);
+    let Some(slot) = keys.first().map(|x| key_slot(x)) else {
+        return Ok(false);
+    };
+    if keys.iter().any(|x| key_slot(x) != slot) {
+        conn.log(format!("{cmd} rejected by keys in different slots"));
+        let value = Value::SimpleError(SimpleError::with_prefix(
+            "CROSSSLOT",
+            "Keys in request don't hash to the same slot",
+        ));
+        conn.reject_command();
+        conn.write_value(value).await?;
+        return Ok(true);
+    }
+    // Keys not here may be migrated already, ask the target node for them. Like
+    // redis, MIGRATE runs here in slots migrating or importing, to move keys freely.
+    let migrate = cmd == "MIGRATE";
+    let exists = migrate
+        || keys
+            .iter()
+            .all(|x| state.storage().get_value_type(x).is_ok());
+    let asking = migrate || conn.is_asking();
+    let Some(redirect) = state.cluster().redirect(slot, exists, asking) else {
+        return Ok(false);
+    };
+    conn.log(format!("{cmd} redirected by {redirect:?}"));
+    conn.reject_command();
+    conn.write_value(redirect.to_error()).await?;
+    Ok(true)
+}
+
+/// Reject write command `cmd` on replica.
+///
+/// Return true if rejected. Like redis, replicas are read only so the dataset stays
+/// the same as master node, writes from master node are still applied.
+async fn reject_readonly(
+    conn: &mut Conn<'_>,
+    rep: &ReplicationState,
+    cmd: &str,
+) -> ServerResult<bool> {
+    if conn.is_master_link() || !is_write_command(cmd) || !rep.is_replica() {
+        return Ok(false);
+    }
+    conn.log(format!("{cmd} rejected by read only replica"));
+    let value = Value::SimpleError(SimpleError::with_prefix(
+        "READONLY",
+        "You can't write against a read only replica.",
+    ));
+    conn.reject_command();
+    conn.write_value(value).await?;
+    Ok(true)
+}
+
+/// Reject command `cmd` before the client is authenticated.
+///
+/// Return true if rejected. Only commands authenticating the client are allowed.
+async fn reject_noauth(conn: &mut Conn<'_>, cmd: &str) -> ServerResult<bool> {
+    if conn.is_authenticated() || matches!(cmd, "AUTH" | "HELLO") {
+        return Ok(false);
+    }
+    let value = Value::SimpleError(SimpleError::with_prefix(
+        "NOAUTH",
+        "Authentication required.",
+    ));
+    conn.reject_command();
+    conn.write_value(value).await?;
+    Ok(true)
+}
+
+/// Reject command `cmd` called with `args` in the wrong number.
+///
+/// Return true if rejected. The arity is looked up in the command table before the
+/// handler runs, so handlers may assume arguments required are present. Unknown
+/// commands are left to report themselves.
+async fn reject_arity(conn: &mut Conn<'_>, cmd: &str, args: &Array) -> ServerResult<bool> {
+    match lookup_command(cmd) {
+        Some(spec) if !spec.check_arity(args.len() + 1) => {
+            let value = Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                format!(
+                    "wrong number of arguments for '{}' command",
+                    cmd.to_lowercase()
+                ),
+            ));
+            conn.reject_command();
+            conn.write_value(value).await?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Reject command `cmd` with `args` not permitted to the user of the connection.
+///
+/// Return true if rejected. The command, keys and channels it accesses are checked
+/// against ACL rules of the user, see `acl`. Commands from master node and
+/// authenticating the client are always permitted, unknown commands are left to
+/// report themselves.
+async fn reject_noperm(
+    conn: &mut Conn<'_>,
+    state: &ServerState,
+    cmd: &str,
+    args: &Array,
+) -> ServerResult<bool> {
+    if conn.is_master_link() || matches!(cmd, "AUTH" | "HELLO") || !is_known_command(cmd) {
+        return Ok(false);
+    }
+    // The user may be deleted after authentication, then nothing is permitted.
+    let user = state.acl().user(conn.user()).unwrap_or_default();
+    // Shard channels take key positions to be routed by slots, but are checked as
+    // channels.
+    let keys = match cmd {
+        "SPUBLISH" | "SSUBSCRIBE" | "SUNSUBSCRIBE" => vec![],
+        _ => command_keys(cmd, args),
+    };
+    let reason = if !user.allows_command(cmd) {
+        Some(format!(
+            "User {} has no permissions to run the '{}' command",
+            conn.user(),
+            cmd.to_lowercase()
+        ))
+    } else if !keys.iter().all(|x| user.allows_key(x)) {
+        Some("No permissions to access a key".to_string())
+    } else {
+        let mut args = args.clone();
+        let channels = match cmd {
+            "SUBSCRIBE" | "SSUBSCRIBE" | "PSUBSCRIBE" => {
+                std::iter::from_fn(|| args.pop_front_bulk_string()).collect()
+            }
+            "PUBLISH" | "SPUBLISH" => args.pop_front_bulk_string().into_iter().collect(),
+            _ => vec![],
+        };
+        (!channels
+            .iter()
+            .all(|x| user.allows_channel(x, cmd == "PSUBSCRIBE")))
+        .then(|| "No permissions to access a channel".to_string())
+    };
+    let Some(reason) = reason else {
+        return Ok(false);
+    };
+    let value = Value::SimpleError(SimpleError::with_prefix("NOPERM", reason));
+    conn.reject_command();
+    conn.write_value(value).await?;
+    Ok(true)
 }
 
+/// Reject command `cmd` on a RESP2 connection subscribed to channels.
+///
+/// Return true if rejected. In RESP2, replies can not be told apart from pub/sub
+/// messages, so only commands managing subscriptions are allowed. RESP3 connections
+/// read messages as push data, and are free to run any command.
+async fn reject_subscribed(
+    conn: &mut Conn<'_>,
+    state: &ServerState,
+    cmd: &str,
+) -> ServerResult<bool> {
+    if conn.protocol() != 2
+        || matches!(
+            cmd,
+            "SUBSCRIBE"
+                | "PSUBSCRIBE"
+                | "SSUBSCRIBE"
+                | "UNSUBSCRIBE"
+                | "PUNSUBSCRIBE"
+                | "SUNSUBSCRIBE"
+                | "PING"
+        )
+        || !state.pubsub().is_subscribed(conn.id)
+    {
+        return Ok(false);
+    }
+    let value = Value::SimpleError(SimpleError::with_prefix(
+        "ERR",
+        format!(
+            "Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING are allowed in this context",
+            cmd.to_lowercase()
+        ),
+    ));
+    conn.reject_command();
+    conn.write_value(value).await?;
+    Ok(true)
+}
+
+/// Name of command `cmd` with `args` reported by CLIENT LIST and INFO commandstats,
+/// in lowercase.
+///
+/// Like redis, subcommands of container commands are included, e.g. "client|list".
+///
+/// Names of known commands are borrowed from the command table, only unknown commands
+/// and subcommands allocate.
+pub(crate) fn command_fullname<'a>(
+    cmd: &[u8],
+    mut args: impl Iterator<Item = &'a Value>,
+) -> Cow<'static, str> {
+    let Some(name) = std::str::from_utf8(cmd)
+        .ok()
+        .and_then(lowercase_command_name)
+    else {
+        return Cow::Owned(String::from_utf8_lossy(cmd).to_lowercase());
+    };
+    if !matches!(
+        name,
+        "acl" | "client" | "cluster" | "config" | "object" | "pubsub" | "xgroup" | "xinfo"
+    ) {
+        return Cow::Borrowed(name);
+    }
+    match args.next() {
+        Some(Value::BulkString(v)) => match v.value() {
+            Some(sub) => Cow::Owned(format!(
+                "{name}|{}",
+                String::from_utf8_lossy(sub).to_lowercase()
+            )),
+            None => Cow::Borrowed(name),
+        },
+        _ => Cow::Borrowed(name),
+    }
+}
+
+/// Feed command `name` in `args` to monitors.
+///
+/// Like redis, admin commands are not fed, and arguments of commands carrying
+/// passwords are redacted.
+fn feed_monitor(conn: &Conn<'_>, state: &ServerState, name: &str, args: &Array) {
+    if !state.monitor().is_active() {
+        return;
+    }
+    let cmd = name.split('|').next().unwrap_or_default().to_uppercase();
+    if command_categories(&cmd).contains(&"admin") {
+        return;
+    }
+    let redacted = matches!(cmd.as_str(), "AUTH" | "HELLO");
+    let args = args
+        .iter()
+        .enumerate()
+        .map(|(index, x)| match x {
+            _ if redacted && index > 0 => b"(redacted)".to_vec(),
+            Value::BulkString(x) => x.value().cloned().unwrap_or_default(),
+            _ => vec![],
+        })
+        .collect::<Vec<_>>();
+    let addr = state
+        .clients()
+        .get(conn.id)
+        .map(|x| x.addr)
+        .unwrap_or_default();
+    state.monitor().feed(&addr, &args);
+}
+
+/// Dispatch command in `args` sent on `conn`, the states of the connection in client
+/// registry are updated around it.
 #[must_use]
 pub(crate) async fn dispatch_command(
+    conn: &mut Conn<'_>,
+    args: Array,
+    state: &mut ServerState,
+    rep: ReplicationState,
+) -> ServerResult<DispatchResult> {
+    conn.begin_command();
+    state.stats().count_command();
+    let mut iter = args.iter();
+    let name = match iter.next() {
+        Some(Value::BulkString(v)) => v.value().map(|cmd| {
+            let spec = std::str::from_utf8(cmd).ok().and_then(lookup_command);
+            (command_fullname(cmd, iter), spec)
+        }),
+        _ => None,
+    };
+    if let Some((name, _)) = &name {
+        feed_monitor(conn, state, name, &args);
+        state.clients().update(conn.id, |x| {
+            x.last_command = name.clone();
+            x.last_interaction = Instant::now();
+        });
+    }
+    // Handlers consume `args`, keep a copy only for commands that may be synced to
+    // replica as they are.
+    let synced = match &name {
+        Some((_, Some(spec))) if spec.is_write() && !conn.in_transaction() => Some(args.clone()),
+        _ => None,
+    };
+    let queued = conn.queued_commands();
+    let started = Instant::now();
+    let dirty = state.storage().handle_writes();
+    // Commands changing nothing are not synced, e.g. SET NX on an existing key or LSET
+    // on a missing one. Only writes through the handle of this connection are counted,
+    // so writes by other connections in the meantime do not matter.
+    let result = dispatch(conn, args, state, rep).await.map(|x| match x {
+        DispatchResult::ReplicaSync => match synced {
+            Some(command) if state.storage().handle_writes() != dirty => {
+                DispatchResult::ReplicaSyncEffects(vec![command])
+            }
+            _ => DispatchResult::None,
+        },
+        v => v,
+    });
+    if !matches!(&name, Some((name, _)) if name == "asking") {
+        conn.set_asking(false);
+    }
+    // Commands queued in transaction are recorded when EXEC runs them, unknown
+    // commands are not recorded at all.
+    let is_queued = matches!((queued, conn.queued_commands()), (Some(a), Some(b)) if b > a);
+    if let Some((name, Some(..))) = &name {
+        if !is_queued {
+            state
+                .stats()
+                .record_call(name, started.elapsed(), conn.call_outcome());
+        }
+    }
+    state.clients().update(conn.id, |x| {
+        x.multi = conn.queued_commands();
+        if x.user != conn.user() {
+            x.user = conn.user().to_string();
+        }
+        x.resp = conn.protocol();
+    });
+    result
+}
+
+async fn dispatch(
     conn: &mut Conn<'_>,
     mut args: Array,
-    storage: &mut Storage,
+    state: &mut ServerState,
     rep: ReplicationState,
 ) -> ServerResult<DispatchResult> {
     if args.is_null_or_empty() {
         return Err(ServerError::InvalidMessage("args is null or empty".into()));
     }
 
+    // Commands from master node are applied in order, not counted as load.
+    let _pending = (!conn.is_master_link()).then(|| state.load().enter());
+
     if conn.in_transaction() {
         // In Transcation, record commands and wait for the `EXEC` command to execute.
         let ele = args.pop_front();
         match ele {
             Some(Value::BulkString(mut cmd)) => match cmd.take() {
                 Some(cmd) => {
-                    let cmd = String::from_utf8(cmd)
-                        .map_err(|e| ServerError::InvalidCommand(format!("{e:?}")))?
-                        .to_uppercase();
+                    if reject_unknown(conn, &cmd, &args).await? {
+                        conn.flag_transaction_dirty();
+                        return Ok(DispatchResult::None);
+                    }
+                    let cmd = canonical_command(cmd)?;
+                    // Only EXEC runs commands, others are queued.
+                    wait_pause(conn, state, &cmd, cmd == "EXEC").await?;
+                    // Like redis, a command rejected when queueing fails the whole
+                    // transaction.
+                    if reject_arity(conn, &cmd, &args).await?
+                        || reject_noperm(conn, state, &cmd, &args).await?
+                        || reject_redirect(conn, state, &cmd, &args).await?
+                        || reject_readonly(conn, &rep, &cmd).await?
+                        || reject_oom(conn, state, &rep, &cmd).await?
+                        || reject_loading(conn, state, &cmd).await?
+                    {
+                        conn.flag_transaction_dirty();
+                        return Ok(DispatchResult::None);
+                    }
                     match cmd.as_str() {
                         "MULTI" => {
                             // Nested transaction is not allowed, `MULTI` can NOT be called
@@ -90,13 +1100,22 @@ pub(crate) async fn dispatch_command(
                         "EXEC" => {
                             // Execute all commands in transaction.
                             // This also leaves the transaction state for current connection.
-                            handle_exec_command(conn, storage).await?;
+                            handle_exec_command(conn, state).await?;
                             Ok(DispatchResult::None)
                         }
                         "DISCARD" => {
                             handle_discard_command(conn).await?;
                             Ok(DispatchResult::None)
                         }
+                        "MONITOR" => {
+                            conn.flag_transaction_dirty();
+                            let value = Value::SimpleError(SimpleError::with_prefix(
+                                "ERR",
+                                "Command not allowed inside a transaction",
+                            ));
+                            conn.write_value(value).await?;
+                            Ok(DispatchResult::None)
+                        }
                         _ => {
                             conn.add_to_transaction(cmd, args);
                             let value = Value::SimpleString(SimpleString::new("QUEUED"));
@@ -118,9 +1137,44 @@ pub(crate) async fn dispatch_command(
         match ele {
             Some(Value::BulkString(mut cmd)) => match cmd.take() {
                 Some(cmd) => {
-                    let cmd = String::from_utf8(cmd)
-                        .map_err(|e| ServerError::InvalidCommand(format!("{e:?}")))?
-                        .to_uppercase();
+                    if reject_unknown(conn, &cmd, &args).await? {
+                        return Ok(DispatchResult::None);
+                    }
+                    let cmd = canonical_command(cmd)?;
+                    if reject_noauth(conn, &cmd).await? {
+                        return Ok(DispatchResult::None);
+                    }
+                    if reject_arity(conn, &cmd, &args).await? {
+                        return Ok(DispatchResult::None);
+                    }
+                    if reject_noperm(conn, state, &cmd, &args).await? {
+                        return Ok(DispatchResult::None);
+                    }
+                    if reject_redirect(conn, state, &cmd, &args).await? {
+                        return Ok(DispatchResult::None);
+                    }
+                    if reject_readonly(conn, &rep, &cmd).await? {
+                        return Ok(DispatchResult::None);
+                    }
+                    wait_pause(conn, state, &cmd, is_pausable_write(&cmd)).await?;
+                    if reject_oom(conn, state, &rep, &cmd).await? {
+                        return Ok(DispatchResult::None);
+                    }
+                    if reject_loading(conn, state, &cmd).await? {
+                        return Ok(DispatchResult::None);
+                    }
+                    if reject_load(conn, state, &rep, &cmd).await? {
+                        return Ok(DispatchResult::None);
+                    }
+                    if reject_subscribed(conn, state, &cmd).await? {
+                        return Ok(DispatchResult::None);
+                    }
+                    // Replies of commands pipelined before are not held while waiting.
+                    if is_blocking_command(&cmd)
+                        || matches!(cmd.as_str(), "WAIT" | "FAILOVER" | "MIGRATE")
+                    {
+                        conn.flush().await?;
+                    }
                     match cmd.as_str() {
                         "MULTI" => {
                             if conn.in_transaction() {
@@ -131,12 +1185,12 @@ pub(crate) async fn dispatch_command(
                                 conn.write_value(value).await?;
                                 Ok(DispatchResult::None)
                             } else {
-                                handle_multi_command(conn, storage).await?;
+                                handle_multi_command(conn, state).await?;
                                 Ok(DispatchResult::None)
                             }
                         }
                         "EXEC" => {
-                            handle_exec_command(conn, storage).await?;
+                            handle_exec_command(conn, state).await?;
                             Ok(DispatchResult::None)
                         }
                         "DISCARD" => {
@@ -145,9 +1199,15 @@ pub(crate) async fn dispatch_command(
                         }
 
                         "INFO" => {
-                            // INFO command handles things more than about replication,
-                            // but we only implement them for now.
-                            handle_info_command(conn, rep).await?;
+                            handle_info_command(conn, args, rep, state).await?;
+                            Ok(DispatchResult::None)
+                        }
+                        "AUTH" => {
+                            handle_auth_command(conn, args, state).await?;
+                            Ok(DispatchResult::None)
+                        }
+                        "HELLO" => {
+                            handle_hello_command(conn, args, rep, state).await?;
                             Ok(DispatchResult::None)
                         }
                         "REPLCONF" => {
@@ -155,14 +1215,37 @@ pub(crate) async fn dispatch_command(
                             Ok(DispatchResult::None)
                         }
                         "PSYNC" => {
-                            handle_psync_command(conn, args, rep).await?;
-                            Ok(DispatchResult::Replica)
+                            if handle_psync_command(conn, args, rep, state).await? {
+                                Ok(DispatchResult::Replica)
+                            } else {
+                                Ok(DispatchResult::None)
+                            }
+                        }
+                        "CLUSTER" => {
+                            handle_cluster_command(conn, args, rep, state).await?;
+                            Ok(DispatchResult::None)
+                        }
+                        "FAILOVER" => {
+                            handle_failover_command(conn, args, rep, state).await?;
+                            Ok(DispatchResult::None)
+                        }
+                        "REPLICAOF" | "SLAVEOF" => {
+                            handle_replicaof_command(conn, args, rep, state).await?;
+                            Ok(DispatchResult::None)
+                        }
+                        "CLIENT" => {
+                            handle_client_command(conn, args, state).await?;
+                            Ok(DispatchResult::None)
                         }
                         "WAIT" => {
-                            handle_wait_command(conn, args, rep).await?;
+                            handle_wait_command(conn, args, rep, state).await?;
                             Ok(DispatchResult::None)
                         }
-                        v => dispatch_normal_command(conn, v, args, storage).await,
+                        "MONITOR" => {
+                            handle_monitor_command(conn, args).await?;
+                            Ok(DispatchResult::Monitor)
+                        }
+                        v => dispatch_with_timeout(conn, v, args, state).await,
                     }
                 }
                 None => Err(ServerError::InvalidCommand(
@@ -176,53 +1259,264 @@ pub(crate) async fn dispatch_command(
     }
 }
 
+/// Check whether command `cmd` may block on purpose, waiting for data or replicas.
+fn is_blocking_command(cmd: &str) -> bool {
+    matches!(
+        cmd,
+        "BLPOP"
+            | "BRPOP"
+            | "BLMOVE"
+            | "BLMPOP"
+            | "BRPOPLPUSH"
+            | "BZPOPMIN"
+            | "BZPOPMAX"
+            | "XREAD"
+            | "XREADGROUP"
+    )
+}
+
+/// Run command `cmd` within the command timeout.
+///
+/// Long loops of commands check the budget set here, and abort the command with the
+/// TIMEOUT error before changing the dataset or replying anything, e.g. LRANGE on a
+/// large list. Commands from master node are never aborted.
+async fn dispatch_with_timeout(
+    conn: &mut Conn<'_>,
+    cmd: &str,
+    args: Array,
+    state: &mut ServerState,
+) -> ServerResult<DispatchResult> {
+    match state.load().command_timeout() {
+        Some(v) if !conn.is_master_link() => conn.set_budget(Budget::new(v)),
+        _ => return dispatch_normal_command(conn, cmd, args, state).await,
+    }
+    let result = dispatch_normal_command(conn, cmd, args, state).await;
+    if conn.take_budget().is_exceeded() {
+        state.load().count_timed_out();
+        conn.log(format!("{cmd} aborted by command timeout"));
+    }
+    result
+}
+
+/// Run command `cmd` with `args`.
+///
+/// Args rejected by the handler are replied with a syntax error like redis, the
+/// connection is kept.
 #[must_use]
 pub(crate) async fn dispatch_normal_command(
     conn: &mut Conn<'_>,
     cmd: &str,
     args: Array,
-    storage: &mut Storage,
+    state: &mut ServerState,
 ) -> ServerResult<DispatchResult> {
+    match run_command(conn, cmd, args, state).await {
+        Err(ServerError::InvalidArgs { cmd, args }) => {
+            conn.log(format!("invalid args {args:?} for command {cmd}"));
+            conn.write_value(set::syntax_error()).await?;
+            Ok(DispatchResult::None)
+        }
+        v => v,
+    }
+}
+
+async fn run_command(
+    conn: &mut Conn<'_>,
+    cmd: &str,
+    args: Array,
+    state: &mut ServerState,
+) -> ServerResult<DispatchResult> {
+    // Recorded before reading, so modifications right after the read are notified.
+    let keys = read_keys(cmd, &args);
+    state.storage().touch_keys(&keys);
+    state.tracking().track_keys(conn.id, keys);
+    let storage = state.storage_mut();
     match cmd {
         "PING" => {
-            handle_ping_command(conn).await?;
+            handle_ping_command(conn, state).await?;
             Ok(DispatchResult::None)
         }
         "ECHO" => {
             handle_echo_command(conn, args).await?;
             Ok(DispatchResult::None)
         }
+        "SUBSCRIBE" => {
+            handle_subscribe_command(conn, args, state, SubscriptionKind::Channel).await?;
+            Ok(DispatchResult::None)
+        }
+        "PSUBSCRIBE" => {
+            handle_subscribe_command(conn, args, state, SubscriptionKind::Pattern).await?;
+            Ok(DispatchResult::None)
+        }
+        "UNSUBSCRIBE" => {
+            handle_unsubscribe_command(conn, args, state, SubscriptionKind::Channel).await?;
+            Ok(DispatchResult::None)
+        }
+        "PUNSUBSCRIBE" => {
+            handle_unsubscribe_command(conn, args, state, SubscriptionKind::Pattern).await?;
+            Ok(DispatchResult::None)
+        }
+        "SSUBSCRIBE" => {
+            handle_subscribe_command(conn, args, state, SubscriptionKind::Shard).await?;
+            Ok(DispatchResult::None)
+        }
+        "SUNSUBSCRIBE" => {
+            handle_unsubscribe_command(conn, args, state, SubscriptionKind::Shard).await?;
+            Ok(DispatchResult::None)
+        }
+        "PUBLISH" => {
+            handle_publish_command(conn, args, state, false).await?;
+            Ok(DispatchResult::None)
+        }
+        "SPUBLISH" => {
+            handle_publish_command(conn, args, state, true).await?;
+            Ok(DispatchResult::None)
+        }
+        "PUBSUB" => {
+            handle_pubsub_command(conn, args, state).await?;
+            Ok(DispatchResult::None)
+        }
+        "LOLWUT" => {
+            handle_lolwut_command(conn, args).await?;
+            Ok(DispatchResult::None)
+        }
         "SET" => {
-            handle_set_command(conn, args, storage).await?;
-            Ok(DispatchResult::ReplicaSync)
+            let effects = handle_set_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
         }
         "GET" => {
             handle_get_command(conn, args, storage).await?;
             Ok(DispatchResult::None)
         }
-        "RPUSH" => {
-            handle_rpush_command(conn, args, storage).await?;
-
+        "SETNX" => {
+            handle_setnx_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "SETEX" => {
+            let effects = handle_setex_command(conn, args, storage, false).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "PSETEX" => {
+            let effects = handle_setex_command(conn, args, storage, true).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "GETSET" => {
+            handle_getset_command(conn, args, storage).await?;
             Ok(DispatchResult::ReplicaSync)
         }
+        "GETDEL" => {
+            handle_getdel_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "DEL" => {
+            handle_del_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "GETEX" => {
+            let effects = handle_getex_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "RPUSH" => {
+            let effects = handle_rpush_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
         "LRANGE" => {
             handle_lrange_command(conn, args, storage).await?;
             Ok(DispatchResult::None)
         }
         "LPUSH" => {
-            handle_lpush_command(conn, args, storage).await?;
-            Ok(DispatchResult::ReplicaSync)
+            let effects = handle_lpush_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
         }
         "LLEN" => {
             handle_llen_command(conn, args, storage).await?;
             Ok(DispatchResult::None)
         }
         "LPOP" => {
-            handle_lpop_command(conn, args, storage).await?;
-            Ok(DispatchResult::ReplicaSync)
+            let effects = handle_lpop_command(conn, args, storage, false).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "RPOP" => {
+            let effects = handle_lpop_command(conn, args, storage, true).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
         }
         "BLPOP" => {
-            handle_blpop_command(conn, args, storage).await?;
+            let effects = handle_blpop_command(conn, args, state, false).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "BRPOP" => {
+            let effects = handle_blpop_command(conn, args, state, true).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "LMOVE" => {
+            let effects = handle_lmove_command(conn, args, state, false).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "BLMOVE" => {
+            let effects = handle_lmove_command(conn, args, state, true).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "LMPOP" => {
+            let effects = handle_lmpop_command(conn, args, state, false).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "BLMPOP" => {
+            let effects = handle_lmpop_command(conn, args, state, true).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "RPOPLPUSH" => {
+            let effects = handle_rpoplpush_command(conn, args, state, false).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "BRPOPLPUSH" => {
+            let effects = handle_rpoplpush_command(conn, args, state, true).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "EXPORT" => {
+            handle_export_command(conn, args, storage).await?;
+            Ok(DispatchResult::None)
+        }
+        "IMPORT" => {
+            handle_import_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "DUMP" => {
+            handle_dump_command(conn, args, storage).await?;
+            Ok(DispatchResult::None)
+        }
+        "RESTORE" => {
+            let effects = handle_restore_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "MIGRATE" => {
+            let effects = handle_migrate_command(conn, args, state).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "ASKING" => {
+            handle_asking_command(conn, state).await?;
+            Ok(DispatchResult::None)
+        }
+        "LINDEX" => {
+            handle_lindex_command(conn, args, storage).await?;
+            Ok(DispatchResult::None)
+        }
+        "LSET" => {
+            handle_lset_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "LPOS" => {
+            handle_lpos_command(conn, args, storage).await?;
+            Ok(DispatchResult::None)
+        }
+        "LREM" => {
+            handle_lrem_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "LINSERT" => {
+            handle_linsert_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "LTRIM" => {
+            handle_ltrim_command(conn, args, storage).await?;
             Ok(DispatchResult::ReplicaSync)
         }
         "TYPE" => {
@@ -230,21 +1524,205 @@ pub(crate) async fn dispatch_normal_command(
             Ok(DispatchResult::None)
         }
         "XADD" => {
-            handle_xadd_command(conn, args, storage).await?;
-            Ok(DispatchResult::ReplicaSync)
+            let effects = handle_xadd_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
         }
         "XRANGE" => {
-            handle_xrange_command(conn, args, storage).await?;
+            handle_xrange_command(conn, args, storage, false).await?;
+            Ok(DispatchResult::None)
+        }
+        "XREVRANGE" => {
+            handle_xrange_command(conn, args, storage, true).await?;
+            Ok(DispatchResult::None)
+        }
+        "XINFO" => {
+            handle_xinfo_command(conn, args, storage).await?;
+            Ok(DispatchResult::None)
+        }
+        "XGROUP" => {
+            handle_xgroup_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "XREADGROUP" => {
+            let effects = handle_xreadgroup_command(conn, args, state).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "XACK" => {
+            handle_xack_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "XCLAIM" => {
+            let effects = handle_xclaim_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "XAUTOCLAIM" => {
+            let effects = handle_xautoclaim_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "XSETID" => {
+            handle_xsetid_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "XPENDING" => {
+            handle_xpending_command(conn, args, storage).await?;
             Ok(DispatchResult::None)
         }
         "XREAD" => {
-            handle_xread_command(conn, args, storage).await?;
+            handle_xread_command(conn, args, state).await?;
             Ok(DispatchResult::None)
         }
         "INCR" => {
             handle_incr_command(conn, args, storage).await?;
             Ok(DispatchResult::ReplicaSync)
         }
+        "DEBUG" => {
+            handle_debug_command(conn, args, storage).await?;
+            Ok(DispatchResult::None)
+        }
+        "APPEND" => {
+            handle_append_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "STRLEN" => {
+            handle_strlen_command(conn, args, storage).await?;
+            Ok(DispatchResult::None)
+        }
+        "GETRANGE" => {
+            handle_getrange_command(conn, args, storage).await?;
+            Ok(DispatchResult::None)
+        }
+        "SETRANGE" => {
+            handle_setrange_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "SETBIT" => {
+            handle_setbit_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "GETBIT" => {
+            handle_getbit_command(conn, args, storage).await?;
+            Ok(DispatchResult::None)
+        }
+        "BITCOUNT" => {
+            handle_bitcount_command(conn, args, storage).await?;
+            Ok(DispatchResult::None)
+        }
+        "BITPOS" => {
+            handle_bitpos_command(conn, args, storage).await?;
+            Ok(DispatchResult::None)
+        }
+        "PFADD" => {
+            handle_pfadd_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "PFCOUNT" => {
+            handle_pfcount_command(conn, args, storage).await?;
+            Ok(DispatchResult::None)
+        }
+        "PFMERGE" => {
+            handle_pfmerge_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "OBJECT" => {
+            handle_object_command(conn, args, storage).await?;
+            Ok(DispatchResult::None)
+        }
+        "GEOADD" => {
+            let effects = handle_geoadd_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "GEOPOS" => {
+            handle_geopos_command(conn, args, storage).await?;
+            Ok(DispatchResult::None)
+        }
+        "GEODIST" => {
+            handle_geodist_command(conn, args, storage).await?;
+            Ok(DispatchResult::None)
+        }
+        "GEOSEARCH" => {
+            handle_geosearch_command(conn, args, storage).await?;
+            Ok(DispatchResult::None)
+        }
+        "GEORADIUS" => {
+            let effects = handle_georadius_command(conn, args, storage, false, false).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "GEORADIUS_RO" => {
+            handle_georadius_command(conn, args, storage, false, true).await?;
+            Ok(DispatchResult::None)
+        }
+        "GEORADIUSBYMEMBER" => {
+            let effects = handle_georadius_command(conn, args, storage, true, false).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "GEORADIUSBYMEMBER_RO" => {
+            handle_georadius_command(conn, args, storage, true, true).await?;
+            Ok(DispatchResult::None)
+        }
+        "SORT" => {
+            let effects = handle_sort_command(conn, args, storage, false).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "SORT_RO" => {
+            handle_sort_command(conn, args, storage, true).await?;
+            Ok(DispatchResult::None)
+        }
+        "ZINCRBY" => {
+            let effects = handle_zincrby_command(conn, args, storage).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "ZPOPMIN" => {
+            handle_zpop_command(conn, args, storage, false).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "ZPOPMAX" => {
+            handle_zpop_command(conn, args, storage, true).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "BZPOPMIN" => {
+            let effects = handle_bzpop_command(conn, args, state, false).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "BZPOPMAX" => {
+            let effects = handle_bzpop_command(conn, args, state, true).await?;
+            Ok(DispatchResult::ReplicaSyncEffects(effects))
+        }
+        "FLUSHALL" => {
+            handle_flush_command(conn, args, storage, true).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "FLUSHDB" => {
+            handle_flush_command(conn, args, storage, false).await?;
+            Ok(DispatchResult::ReplicaSync)
+        }
+        "SAVE" => {
+            handle_save_command(conn, args, state, false).await?;
+            Ok(DispatchResult::None)
+        }
+        "BGSAVE" => {
+            handle_save_command(conn, args, state, true).await?;
+            Ok(DispatchResult::None)
+        }
+        "BGREWRITEAOF" => {
+            handle_bgrewriteaof_command(conn, args, state).await?;
+            Ok(DispatchResult::None)
+        }
+        "LASTSAVE" => {
+            handle_lastsave_command(conn, args, state).await?;
+            Ok(DispatchResult::None)
+        }
+        "CONFIG" => {
+            handle_config_command(conn, args, state).await?;
+            Ok(DispatchResult::None)
+        }
+        "ACL" => {
+            handle_acl_command(conn, args, state).await?;
+            Ok(DispatchResult::None)
+        }
+        "COMMAND" => {
+            handle_command_command(conn, args).await?;
+            Ok(DispatchResult::None)
+        }
         v => Err(ServerError::InvalidCommand(v.to_string())),
     }
 }
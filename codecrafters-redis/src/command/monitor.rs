@@ -0,0 +1,22 @@
+use serde_redis::{Array, SimpleString, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+};
+
+/// Handle MONITOR, the server starts feeding commands to the connection once
+/// replied.
+pub(super) async fn handle_monitor_command(conn: &mut Conn<'_>, args: Array) -> ServerResult<()> {
+    conn.log("run command MONITOR");
+
+    if !args.is_empty() {
+        return Err(ServerError::InvalidArgs {
+            cmd: "MONITOR",
+            args,
+        });
+    }
+
+    conn.write_value(Value::SimpleString(SimpleString::new("OK")))
+        .await
+}
@@ -1,10 +1,10 @@
 use serde_redis::{SimpleError, SimpleString, Value};
 
-use crate::{conn::Conn, error::ServerResult, storage::Storage};
+use crate::{conn::Conn, error::ServerResult, state::ServerState};
 
 pub(super) async fn handle_multi_command(
     conn: &mut Conn<'_>,
-    _storage: &mut Storage,
+    _state: &mut ServerState,
 ) -> ServerResult<()> {
     conn.log("run command MULTI");
     let value = if conn.in_transaction() {
@@ -0,0 +1,63 @@
+use serde_redis::{Array, BulkString, Integer, SimpleError, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+pub(super) async fn handle_object_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command OBJECT");
+
+    // OBJECT <ENCODING | FREQ | IDLETIME> key
+    //
+    // Like redis, FREQ is only available under LFU policies and IDLETIME is not.
+    let (subcommand, key) = match (
+        args.pop_front_bulk_string(),
+        args.pop_front_bulk_string_bytes(),
+    ) {
+        (Some(subcommand), Some(key)) => (subcommand.to_uppercase(), key),
+        _ => {
+            return Err(ServerError::InvalidArgs {
+                cmd: "OBJECT",
+                args,
+            })
+        }
+    };
+
+    let meta = storage.object(&key);
+    let lfu = storage.maxmemory_policy().is_lfu();
+    let value = match (subcommand.as_str(), meta) {
+        (_, None) if matches!(subcommand.as_str(), "ENCODING" | "FREQ" | "IDLETIME") => {
+            Value::BulkString(BulkString::null())
+        }
+        ("ENCODING", Some(meta)) => Value::BulkString(BulkString::new(meta.encoding)),
+        ("FREQ", Some(_)) if !lfu => {
+            policy_error("An LFU maxmemory policy is not selected, access frequency not tracked.")
+        }
+        ("FREQ", Some(meta)) => Value::Integer(Integer::new(meta.freq as i64)),
+        ("IDLETIME", Some(_)) if lfu => {
+            policy_error("An LFU maxmemory policy is selected, idle time not tracked.")
+        }
+        ("IDLETIME", Some(meta)) => Value::Integer(Integer::new(meta.idle_time as i64)),
+        (v, _) => Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            format!("unknown subcommand '{v}'"),
+        )),
+    };
+    conn.write_value(value).await
+}
+
+fn policy_error(reason: &str) -> Value {
+    Value::SimpleError(SimpleError::with_prefix(
+        "ERR",
+        format!(
+            "{reason} Please note that when switching between policies at runtime \
+             LRU and LFU data will take some time to adjust."
+        ),
+    ))
+}
@@ -0,0 +1,30 @@
+use serde_redis::{Array, Integer, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+pub(super) async fn handle_pfadd_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command PFADD");
+
+    // PFADD key [element [element ...]]
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "PFADD",
+            args: args.clone(),
+        })?;
+    let elements = std::iter::from_fn(|| args.pop_front_bulk_string_bytes()).collect::<Vec<_>>();
+
+    let value = match storage.hll_add(&key, &elements) {
+        Ok(v) => Value::Integer(Integer::new(v as i64)),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
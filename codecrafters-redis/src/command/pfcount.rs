@@ -0,0 +1,30 @@
+use serde_redis::{Array, Integer, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+pub(super) async fn handle_pfcount_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command PFCOUNT");
+
+    // PFCOUNT key [key ...]
+    let keys = std::iter::from_fn(|| args.pop_front_bulk_string_bytes()).collect::<Vec<_>>();
+    if keys.is_empty() {
+        return Err(ServerError::InvalidArgs {
+            cmd: "PFCOUNT",
+            args,
+        });
+    }
+
+    let value = match storage.hll_count(&keys) {
+        Ok(v) => Value::Integer(Integer::new(v as i64)),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
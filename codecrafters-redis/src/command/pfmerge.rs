@@ -0,0 +1,30 @@
+use serde_redis::{Array, SimpleString, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+pub(super) async fn handle_pfmerge_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command PFMERGE");
+
+    // PFMERGE destkey [sourcekey [sourcekey ...]]
+    let dest = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "PFMERGE",
+            args: args.clone(),
+        })?;
+    let sources = std::iter::from_fn(|| args.pop_front_bulk_string_bytes()).collect::<Vec<_>>();
+
+    let value = match storage.hll_merge(&dest, &sources) {
+        Ok(()) => Value::SimpleString(SimpleString::new("OK")),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
@@ -1,9 +1,23 @@
-use serde_redis::{SimpleString, Value};
+use serde_redis::{Array, BulkString, SimpleString, Value};
 
-use crate::{conn::Conn, error::ServerResult};
+use crate::{conn::Conn, error::ServerResult, state::ServerState};
 
-pub(super) async fn handle_ping_command(conn: &mut Conn<'_>) -> ServerResult<()> {
+/// Handle PING.
+///
+/// Like redis, a RESP2 connection subscribed to channels can only read pub/sub
+/// messages, so the reply is in the same shape: `["pong", ""]`.
+pub(super) async fn handle_ping_command(
+    conn: &mut Conn<'_>,
+    state: &mut ServerState,
+) -> ServerResult<()> {
     conn.log("run command PONG");
-    let value = Value::SimpleString(SimpleString::new("PONG"));
+    let value = if conn.protocol() == 2 && state.pubsub().is_subscribed(conn.id) {
+        Value::Array(Array::with_values(vec![
+            Value::BulkString(BulkString::new("pong")),
+            Value::BulkString(BulkString::new("")),
+        ]))
+    } else {
+        Value::SimpleString(SimpleString::new("PONG"))
+    };
     conn.write_value(value).await
 }
@@ -1,25 +1,34 @@
-use serde_redis::{num_to_bytes, Array, SimpleString, Value};
-
-const EMPTY_RDB_FILE: [u8; 88] = [
-    0x52, 0x45, 0x44, 0x49, 0x53, 0x30, 0x30, 0x31, 0x31, 0xfa, 0x09, 0x72, 0x65, 0x64, 0x69, 0x73,
-    0x2d, 0x76, 0x65, 0x72, 0x05, 0x37, 0x2e, 0x32, 0x2e, 0x30, 0xfa, 0x0a, 0x72, 0x65, 0x64, 0x69,
-    0x73, 0x2d, 0x62, 0x69, 0x74, 0x73, 0xc0, 0x40, 0xfa, 0x05, 0x63, 0x74, 0x69, 0x6d, 0x65, 0xc2,
-    0x6d, 0x08, 0xbc, 0x65, 0xfa, 0x08, 0x75, 0x73, 0x65, 0x64, 0x2d, 0x6d, 0x65, 0x6d, 0xc2, 0xb0,
-    0xc4, 0x10, 0x00, 0xfa, 0x08, 0x61, 0x6f, 0x66, 0x2d, 0x62, 0x61, 0x73, 0x65, 0xc0, 0x00, 0xff,
-    0xf0, 0x6e, 0x3b, 0xfe, 0xc0, 0xff, 0x5a, 0xa2,
-];
+use serde_redis::{num_to_bytes, Array, SimpleError, SimpleString, Value};
 
 use crate::{
     conn::Conn,
     error::{ServerError, ServerResult},
-    replication::ReplicationState,
+    replication::{random_hex, ReplicationState},
+    state::ServerState,
 };
 
+/// Length of the delimiter marking the end of RDB in diskless sync.
+///
+/// Same as `RDB_EOF_MARK_SIZE` in redis.
+const EOF_MARK_SIZE: usize = 40;
+
+/// Generate a random delimiter of hex chars to mark the end of RDB.
+fn eof_mark() -> Vec<u8> {
+    random_hex(EOF_MARK_SIZE).into_bytes()
+}
+
+/// Handle PSYNC, sync the dataset and the replication stream to a replica.
+///
+/// With `FAILOVER`, the old master node asks current instance to promote itself
+/// before syncing, see FAILOVER.
+///
+/// Return false if rejected, the connection is not a replica.
 pub(super) async fn handle_psync_command(
     conn: &mut Conn<'_>,
     mut args: Array,
     rep: ReplicationState,
-) -> ServerResult<()> {
+    state: &ServerState,
+) -> ServerResult<bool> {
     conn.log("run command PSYNC");
     let master_id = args
         .pop_front_bulk_string()
@@ -35,19 +44,86 @@ pub(super) async fn handle_psync_command(
             args: args.clone(),
         })?;
 
+    // PSYNC replicationid offset [FAILOVER]
+    let failover = match (args.pop_front_bulk_string(), args.is_empty()) {
+        (None, _) => false,
+        (Some(v), true) if v.eq_ignore_ascii_case("FAILOVER") => true,
+        _ => {
+            return Err(ServerError::InvalidArgs {
+                cmd: "PSYNC",
+                args: args.clone(),
+            })
+        }
+    };
+
     conn.log(format!("PSYNC {master_id} {offset}"));
 
-    let value = Value::SimpleString(SimpleString::new(format!("FULLRESYNC {} 0", rep.id(),)));
+    // Only the master node followed, whose stream current instance shares, can hand
+    // over its role.
+    if failover {
+        if master_id != rep.id() {
+            let value = Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                "PSYNC FAILOVER replid must match my replid.",
+            ));
+            conn.write_value(value).await?;
+            return Ok(false);
+        }
+        if rep.is_replica() {
+            conn.log("promoted to master by failover");
+            rep.replicaof(None, state.clone());
+        }
+    }
+
+    // Continue from the backlog if the replica was synced with the same stream, and
+    // missed nothing dropped.
+    //
+    // +CONTINUE <REPL_ID>\r\n<missed commands>
+    let missed = offset
+        .parse::<usize>()
+        .ok()
+        .and_then(|x| rep.partial_sync(&master_id, x));
+    if let Some(missed) = missed {
+        conn.log(format!("partial resync with {} bytes missed", missed.len()));
+        let value = Value::SimpleString(SimpleString::new(format!("CONTINUE {}", rep.id())));
+        conn.write_value(value).await?;
+        conn.write_bytes(missed.as_slice()).await?;
+        return Ok(true);
+    }
+
+    let value = Value::SimpleString(SimpleString::new(format!(
+        "FULLRESYNC {} {}",
+        rep.id(),
+        rep.offset()
+    )));
 
     conn.write_value(value).await?;
 
+    let rdb = state.storage().rdb_snapshot();
+    conn.log(format!("full resync with RDB of {} bytes", rdb.len()));
+
+    if rep.diskless_sync() && conn.has_capa("eof") {
+        // Diskless sync, the length of RDB is unknown before generated.
+        //
+        // $EOF:<40 bytes delimiter>\r\n<RDB content><40 bytes delimiter>
+        conn.log("full resync with diskless RDB streaming");
+        let mark = eof_mark();
+        let mut header = b"$EOF:".to_vec();
+        header.extend(&mark);
+        header.extend(b"\r\n");
+        conn.write_bytes(header.as_slice()).await?;
+        conn.write_bytes(rdb.as_slice()).await?;
+        conn.write_bytes(mark.as_slice()).await?;
+        return Ok(true);
+    }
+
     let mut buf = vec![];
     buf.push(b'$');
-    buf.extend(num_to_bytes(EMPTY_RDB_FILE.len() as i64));
+    buf.extend(num_to_bytes(rdb.len() as i64));
     buf.extend(b"\r\n");
-    buf.extend(EMPTY_RDB_FILE);
+    buf.extend(rdb);
 
     conn.write_bytes(buf.as_slice()).await?;
 
-    Ok(())
+    Ok(true)
 }
@@ -0,0 +1,37 @@
+use serde_redis::{Array, Integer, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    state::ServerState,
+};
+
+/// Handle PUBLISH, or SPUBLISH to shard channel if `shard` is true.
+///
+/// Reply the count of subscribers received the message.
+pub(super) async fn handle_publish_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    state: &mut ServerState,
+    shard: bool,
+) -> ServerResult<()> {
+    let cmd = if shard { "SPUBLISH" } else { "PUBLISH" };
+    conn.log(format!("run command {cmd}"));
+    let (Some(channel), Some(message)) = (
+        args.pop_front_bulk_string(),
+        args.pop_front_bulk_string_bytes(),
+    ) else {
+        return Err(ServerError::InvalidArgs { cmd, args });
+    };
+    if !args.is_empty() {
+        return Err(ServerError::InvalidArgs { cmd, args });
+    }
+
+    let count = if shard {
+        state.pubsub().publish_shard(&channel, &message)
+    } else {
+        state.pubsub().publish(&channel, &message)
+    };
+    conn.write_value(Value::Integer(Integer::new(count as i64)))
+        .await
+}
@@ -0,0 +1,71 @@
+use serde_redis::{Array, BulkString, Integer, SimpleError, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    state::ServerState,
+};
+
+pub(super) async fn handle_pubsub_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    state: &mut ServerState,
+) -> ServerResult<()> {
+    conn.log("run command PUBSUB");
+    let subcommand = args
+        .pop_front_bulk_string()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "PUBSUB",
+            args: args.clone(),
+        })?;
+
+    let value = match subcommand.to_uppercase().as_str() {
+        v @ ("CHANNELS" | "SHARDCHANNELS") => {
+            // PUBSUB CHANNELS [pattern]
+            // PUBSUB SHARDCHANNELS [pattern]
+            let pattern = args.pop_front_bulk_string();
+            if !args.is_empty() {
+                return Err(ServerError::InvalidArgs {
+                    cmd: "PUBSUB",
+                    args,
+                });
+            }
+            Value::Array(
+                state
+                    .pubsub()
+                    .active_channels(v == "SHARDCHANNELS", pattern.as_deref())
+                    .into_iter()
+                    .map(|x| Value::BulkString(BulkString::new(x)))
+                    .collect(),
+            )
+        }
+        v @ ("NUMSUB" | "SHARDNUMSUB") => {
+            // PUBSUB NUMSUB [channel [channel ...]]
+            // PUBSUB SHARDNUMSUB [shardchannel [shardchannel ...]]
+            let mut values = vec![];
+            while let Some(channel) = args.pop_front_bulk_string() {
+                let count = state
+                    .pubsub()
+                    .subscriber_count(v == "SHARDNUMSUB", &channel);
+                values.push(Value::BulkString(BulkString::new(channel)));
+                values.push(Value::Integer(Integer::new(count as i64)));
+            }
+            Value::Array(Array::with_values(values))
+        }
+        "NUMPAT" => {
+            if !args.is_empty() {
+                return Err(ServerError::InvalidArgs {
+                    cmd: "PUBSUB",
+                    args,
+                });
+            }
+            Value::Integer(Integer::new(state.pubsub().pattern_count() as i64))
+        }
+        v => Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            format!("unknown subcommand '{v}'"),
+        )),
+    };
+
+    conn.write_value(value).await
+}
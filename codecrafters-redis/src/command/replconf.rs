@@ -1,4 +1,4 @@
-use serde_redis::{Array, BulkString, SimpleString, Value};
+use serde_redis::{Array, BulkString, SimpleError, SimpleString, Value};
 
 use crate::{
     conn::Conn,
@@ -20,7 +20,28 @@ pub(super) async fn handle_replconf_command(
         })?;
 
     let value = match key.to_lowercase().as_str() {
-        "listening-port" | "capa" => Value::SimpleString(SimpleString::new("OK")),
+        "listening-port" => {
+            // REPLCONF listening-port <port>
+            match args.pop_front_bulk_string().and_then(|x| x.parse().ok()) {
+                Some(port) => {
+                    conn.set_listening_port(port);
+                    Value::SimpleString(SimpleString::new("OK"))
+                }
+                None => Value::SimpleError(SimpleError::with_prefix("ERR", "Invalid port")),
+            }
+        }
+        "capa" => {
+            // REPLCONF capa <capability> [capa <capability> ...]
+            let mut capa = args.pop_front_bulk_string();
+            while let Some(v) = capa {
+                conn.add_capa(v);
+                capa = match args.pop_front_bulk_string() {
+                    Some(k) if k.eq_ignore_ascii_case("capa") => args.pop_front_bulk_string(),
+                    _ => None,
+                };
+            }
+            Value::SimpleString(SimpleString::new("OK"))
+        }
         "getack" => Value::Array(Array::with_values(vec![
             Value::BulkString(BulkString::new("REPLCONF")),
             Value::BulkString(BulkString::new("ACK")),
@@ -0,0 +1,75 @@
+use std::net::Ipv4Addr;
+
+use serde_redis::{Array, SimpleError, SimpleString, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    replication::ReplicationState,
+    state::ServerState,
+};
+
+/// Handle REPLICAOF, or SLAVEOF which is an alias of it.
+///
+/// Follow another master node at runtime, or stop following with `NO ONE` and act
+/// as a master node.
+pub(super) async fn handle_replicaof_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    rep: ReplicationState,
+    state: &mut ServerState,
+) -> ServerResult<()> {
+    conn.log("run command REPLICAOF");
+
+    // REPLICAOF <host port | NO ONE>
+    let (Some(host), Some(port), true) = (
+        args.pop_front_bulk_string(),
+        args.pop_front_bulk_string(),
+        args.is_empty(),
+    ) else {
+        return Err(ServerError::InvalidArgs {
+            cmd: "REPLICAOF",
+            args,
+        });
+    };
+
+    if host.eq_ignore_ascii_case("NO") && port.eq_ignore_ascii_case("ONE") {
+        if rep.is_replica() {
+            conn.log("promoted to master");
+            rep.replicaof(None, state.clone());
+        }
+        return conn
+            .write_value(Value::SimpleString(SimpleString::new("OK")))
+            .await;
+    }
+
+    let Ok(port) = port.parse::<u16>() else {
+        let value = Value::SimpleError(SimpleError::with_prefix("ERR", "Invalid master port"));
+        return conn.write_value(value).await;
+    };
+    let ip = if host.eq_ignore_ascii_case("localhost") {
+        Ipv4Addr::LOCALHOST
+    } else {
+        match host.parse::<Ipv4Addr>() {
+            Ok(v) => v,
+            Err(..) => {
+                let value = Value::SimpleError(SimpleError::with_prefix(
+                    "ERR",
+                    format!("Invalid master host '{host}'"),
+                ));
+                return conn.write_value(value).await;
+            }
+        }
+    };
+
+    if rep.master() == Some((ip, port)) {
+        let value = Value::SimpleString(SimpleString::new(
+            "OK Already connected to specified master",
+        ));
+        return conn.write_value(value).await;
+    }
+    conn.log(format!("follow master {ip}:{port}"));
+    rep.replicaof(Some((ip, port)), state.clone());
+    conn.write_value(Value::SimpleString(SimpleString::new("OK")))
+        .await
+}
@@ -0,0 +1,86 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_redis::{Array, SimpleError, SimpleString, Value};
+
+use crate::{
+    command::{effect_command, set::syntax_error},
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+/// Handle RESTORE, save the value serialized by DUMP.
+///
+/// Return the effects to sync to replica, a RESTORE with the expiration in absolute
+/// time if the value is saved.
+pub(super) async fn handle_restore_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<Vec<Array>> {
+    conn.log("run command RESTORE");
+
+    // RESTORE key ttl serialized-value [REPLACE] [ABSTTL]
+    let invalid_args = |args: &Array| ServerError::InvalidArgs {
+        cmd: "RESTORE",
+        args: args.clone(),
+    };
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| invalid_args(&args))?;
+    let ttl = args
+        .pop_front_bulk_string()
+        .ok_or_else(|| invalid_args(&args))?;
+    let payload = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| invalid_args(&args))?;
+    let (mut replace, mut absttl) = (false, false);
+    while let Some(option) = args.pop_front_bulk_string() {
+        match option.to_uppercase().as_str() {
+            "REPLACE" => replace = true,
+            "ABSTTL" => absttl = true,
+            _ => {
+                conn.write_value(syntax_error()).await?;
+                return Ok(vec![]);
+            }
+        }
+    }
+    let ttl = match ttl.parse::<u64>() {
+        Ok(v) => v,
+        Err(..) => {
+            let value = Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                "Invalid TTL value, must be >= 0",
+            ));
+            conn.write_value(value).await?;
+            return Ok(vec![]);
+        }
+    };
+    let expiration = match (ttl, absttl) {
+        (0, _) => None,
+        (v, true) => UNIX_EPOCH.checked_add(Duration::from_millis(v)),
+        (v, false) => SystemTime::now().checked_add(Duration::from_millis(v)),
+    };
+
+    let (value, effects) = match storage.restore(&key, &payload, expiration, replace) {
+        Ok(()) => {
+            let at = expiration.map_or(0, |x| {
+                x.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+            });
+            let mut effect = effect_command([
+                b"RESTORE".to_vec(),
+                key,
+                at.to_string().into_bytes(),
+                payload,
+                b"REPLACE".to_vec(),
+            ]);
+            if at > 0 {
+                effect.append(effect_command(["ABSTTL"]));
+            }
+            (Value::SimpleString(SimpleString::new("OK")), vec![effect])
+        }
+        Err(e) => (e.to_message(), vec![]),
+    };
+    conn.write_value(value).await?;
+    Ok(effects)
+}
@@ -0,0 +1,53 @@
+use serde_redis::Array;
+
+use crate::{
+    command::lmove::{move_element, parse_block_timeout},
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    state::ServerState,
+};
+
+/// Handle RPOPLPUSH, or BRPOPLPUSH if `block` is true.
+///
+/// Same as `[B]LMOVE source destination RIGHT LEFT`.
+pub(super) async fn handle_rpoplpush_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    state: &mut ServerState,
+    block: bool,
+) -> ServerResult<Vec<Array>> {
+    let cmd = if block { "BRPOPLPUSH" } else { "RPOPLPUSH" };
+    conn.log(format!("run command {cmd}"));
+
+    let source = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd,
+            args: args.clone(),
+        })?;
+    let destination =
+        args.pop_front_bulk_string_bytes()
+            .ok_or_else(|| ServerError::InvalidArgs {
+                cmd,
+                args: args.clone(),
+            })?;
+    let timeout = if block {
+        let timeout = args
+            .pop_front_bulk_string()
+            .ok_or_else(|| ServerError::InvalidArgs {
+                cmd,
+                args: args.clone(),
+            })?;
+        match parse_block_timeout(&timeout) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                conn.write_value(e).await?;
+                return Ok(vec![]);
+            }
+        }
+    } else {
+        None
+    };
+
+    move_element(conn, state, source, destination, true, false, timeout).await
+}
@@ -1,6 +1,7 @@
-use serde_redis::{Array, Integer, SimpleError, SimpleString, Value};
+use serde_redis::{Array, Integer, SimpleError, Value};
 
 use crate::{
+    command::{effect_command, list_feed_effects},
     conn::Conn,
     error::{ServerError, ServerResult},
     storage::Storage,
@@ -10,31 +11,43 @@ pub(super) async fn handle_rpush_command(
     conn: &mut Conn<'_>,
     mut args: Array,
     storage: &mut Storage,
-) -> ServerResult<()> {
+) -> ServerResult<Vec<Array>> {
     conn.log("run command RPUSH");
     let key = args
-        .pop_front_bulk_string()
+        .pop_front_bulk_string_bytes()
         .ok_or_else(|| ServerError::InvalidArgs {
             cmd: "RPUSH",
             args: args.clone(),
         })?;
 
-    let mut values = Array::new_empty();
+    let values = std::iter::from_fn(|| args.pop_front_bulk_string_bytes()).collect::<Vec<_>>();
+    let mut effect = effect_command([b"RPUSH".as_slice(), &key]);
+    effect.append(effect_command(values.clone()));
 
-    while let Some(v) = args.pop_front_bulk_string() {
-        values.push_back(Value::SimpleString(SimpleString::new(v)));
-    }
-
-    conn.log(format!("RPUSH {key:?}={values:?}"));
+    conn.log(format!(
+        "RPUSH {:?}={:?}",
+        String::from_utf8_lossy(&key),
+        values
+            .iter()
+            .map(|x| String::from_utf8_lossy(x))
+            .collect::<Vec<_>>()
+    ));
 
+    let mut effects = vec![];
     let value = if values.is_empty() {
         Value::SimpleError(SimpleError::with_prefix("EARG", "empty list args"))
     } else {
         match storage.insert_list(key, values, true, false) {
-            Ok(v) => Value::Integer(Integer::new(v as i64)),
+            Ok((count, feeds)) => {
+                // Elements taken by blocked tasks are popped on replica right after pushed.
+                effects.push(effect);
+                effects.extend(list_feed_effects(feeds));
+                Value::Integer(Integer::new(count as i64))
+            }
             Err(e) => e.to_message(),
         }
     };
 
-    conn.write_value(value).await
+    conn.write_value(value).await?;
+    Ok(effects)
 }
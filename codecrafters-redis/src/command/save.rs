@@ -0,0 +1,51 @@
+use serde_redis::{Array, SimpleError, SimpleString, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    state::ServerState,
+};
+
+fn in_progress_error() -> Value {
+    Value::SimpleError(SimpleError::with_prefix(
+        "ERR",
+        "Background save already in progress",
+    ))
+}
+
+/// Handle SAVE, or BGSAVE if `background` is true, write all keys to the RDB file.
+///
+/// SAVE replies after the file is written, BGSAVE replies once the snapshot is
+/// taken, check LASTSAVE or INFO persistence for the result.
+pub(super) async fn handle_save_command(
+    conn: &mut Conn<'_>,
+    args: Array,
+    state: &mut ServerState,
+    background: bool,
+) -> ServerResult<()> {
+    let cmd = if background { "BGSAVE" } else { "SAVE" };
+    conn.log(format!("run command {cmd}"));
+
+    if !args.is_empty() {
+        return Err(ServerError::InvalidArgs { cmd, args });
+    }
+
+    let value = if background {
+        if state.bgsave() {
+            Value::SimpleString(SimpleString::new("Background saving started"))
+        } else {
+            in_progress_error()
+        }
+    } else if state.persistence().bgsave_in_progress() {
+        in_progress_error()
+    } else {
+        match state.save() {
+            Ok(()) => Value::SimpleString(SimpleString::new("OK")),
+            Err(e) => {
+                conn.log(format!("failed to save RDB: {e}"));
+                Value::SimpleError(SimpleError::with_prefix("ERR", format!("{e}")))
+            }
+        }
+    };
+    conn.write_value(value).await
+}
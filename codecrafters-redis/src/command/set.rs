@@ -1,69 +1,162 @@
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use serde_redis::{Array, Integer, SimpleString, Value};
+use serde_redis::{Array, BulkString, SimpleError, SimpleString, Value};
 
 use crate::{
+    command::{bulk_reply, effect_command},
     conn::Conn,
     error::{ServerError, ServerResult},
-    storage::Storage,
+    storage::{SetCondition, SetExpire, Storage},
 };
 
+pub(super) fn syntax_error() -> Value {
+    Value::SimpleError(SimpleError::with_prefix("ERR", "syntax error"))
+}
+
+pub(super) fn invalid_expire_time(cmd: &str) -> Value {
+    Value::SimpleError(SimpleError::with_prefix(
+        "ERR",
+        format!("invalid expire time in '{cmd}' command"),
+    ))
+}
+
+/// Pop the bytes of value to save from `args`.
+pub(super) fn pop_front_value(args: &mut Array) -> Option<Vec<u8>> {
+    match args.pop_front()? {
+        Value::SimpleString(s) => Some(s.value().as_bytes().to_vec()),
+        Value::BulkString(mut b) => b.take(),
+        Value::Integer(i) => Some(i.value().to_string().into_bytes()),
+        _ => None,
+    }
+}
+
+/// Parse the expiration time `raw` in unit `unit`, one of "EX", "PX", "EXAT" and "PXAT".
+///
+/// Return the error message to reply if `raw` is invalid.
+pub(super) fn parse_expire_at(
+    cmd: &str,
+    unit: &str,
+    raw: Option<String>,
+) -> Result<SystemTime, Value> {
+    let time = match raw.and_then(|s| s.parse::<i64>().ok()) {
+        Some(v) if v > 0 => v as u64,
+        Some(..) => return Err(invalid_expire_time(cmd)),
+        None => return Err(syntax_error()),
+    };
+    let at = match unit {
+        "EX" => SystemTime::now().checked_add(Duration::from_secs(time)),
+        "PX" => SystemTime::now().checked_add(Duration::from_millis(time)),
+        "EXAT" => UNIX_EPOCH.checked_add(Duration::from_secs(time)),
+        _ => UNIX_EPOCH.checked_add(Duration::from_millis(time)),
+    };
+    at.ok_or_else(|| invalid_expire_time(cmd))
+}
+
+/// Build the options syncing expiration `at` to replica.
+///
+/// Relative times are converted to a unix time in milliseconds, so replicas expire the
+/// key at the same moment no matter when they apply the command.
+pub(super) fn expire_at_effect(at: SystemTime) -> [String; 2] {
+    let millis = at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    ["PXAT".to_string(), millis.to_string()]
+}
+
+/// Build the SET synced to replica, setting `key` to `value` as received with
+/// `expire`.
+pub(super) fn set_effect(key: &[u8], value: Value, expire: SetExpire) -> Array {
+    let mut effect = effect_command([b"SET".as_slice(), key]);
+    effect.push_back(value);
+    match expire {
+        SetExpire::Never => {}
+        SetExpire::At(at) => effect.append(effect_command(expire_at_effect(at))),
+        SetExpire::Keep => {
+            effect.push_back(Value::BulkString(BulkString::new("KEEPTTL")));
+        }
+    }
+    effect
+}
+
+/// Handle SET.
+///
+/// Return the effects to sync to replica, a SET with the expiration in absolute time
+/// if the value is set.
 pub(super) async fn handle_set_command(
     conn: &mut Conn<'_>,
     mut args: Array,
     storage: &mut Storage,
-) -> ServerResult<()> {
+) -> ServerResult<Vec<Array>> {
     conn.log("run command SET");
     let key = args
-        .pop_front_bulk_string()
+        .pop_front_bulk_string_bytes()
         .ok_or_else(|| ServerError::InvalidArgs {
             cmd: "SET",
             args: args.clone(),
         })?;
-    let value = match args.pop_front().unwrap() {
-        Value::SimpleString(s) => match s.value().parse::<i64>() {
-            Ok(v) => Value::Integer(Integer::new(v)),
-            _ => Value::SimpleString(s),
-        },
-        Value::BulkString(b) => match b
-            .clone()
-            .take()
-            .and_then(|x| String::from_utf8(x).ok())
-            .and_then(|x| x.parse::<i64>().ok())
-        {
-            Some(v) => Value::Integer(Integer::new(v)),
-            _ => Value::BulkString(b),
-        },
-        v => v,
-    };
-    conn.log(format!("SET {key:?}={value:?}"));
+    let raw_value = args.value().and_then(|x| x.first()).cloned();
+    let value = pop_front_value(&mut args).ok_or_else(|| ServerError::InvalidArgs {
+        cmd: "SET",
+        args: args.clone(),
+    })?;
+    conn.log(format!(
+        "SET {:?}={:?}",
+        String::from_utf8_lossy(&key),
+        String::from_utf8_lossy(&value)
+    ));
 
-    // Duration till expire. None value means never expire.
-    let mut duration = None;
-    match args.pop_front_bulk_string() {
-        Some(v) => match v.to_lowercase().as_str() {
-            "px" => {
-                duration = args
-                    .pop_front_bulk_string()
-                    .and_then(|s| s.parse::<u64>().ok())
-                    .ok_or_else(|| ServerError::InvalidArgs {
-                        cmd: "SET",
-                        args: args.clone(),
-                    })
-                    .map(|d| Some(Duration::from_millis(d)))?
+    // Expiration option. None value means no expiration option given.
+    let mut expire = None;
+    let mut condition = SetCondition::Always;
+    let mut get = false;
+    while let Some(option) = args.pop_front_bulk_string() {
+        match option.to_uppercase().as_str() {
+            opt @ ("EX" | "PX" | "EXAT" | "PXAT") => {
+                if expire.is_some() {
+                    conn.write_value(syntax_error()).await?;
+                    return Ok(vec![]);
+                }
+                match parse_expire_at("set", opt, args.pop_front_bulk_string()) {
+                    Ok(at) => expire = Some(SetExpire::At(at)),
+                    Err(e) => {
+                        conn.write_value(e).await?;
+                        return Ok(vec![]);
+                    }
+                }
             }
-
+            "KEEPTTL" => {
+                if expire.is_some() {
+                    conn.write_value(syntax_error()).await?;
+                    return Ok(vec![]);
+                }
+                expire = Some(SetExpire::Keep);
+            }
+            "NX" | "XX" if !matches!(condition, SetCondition::Always) => {
+                conn.write_value(syntax_error()).await?;
+                return Ok(vec![]);
+            }
+            "NX" => condition = SetCondition::NotExists,
+            "XX" => condition = SetCondition::Exists,
+            "GET" => get = true,
             _ => {
-                return Err(ServerError::InvalidArgs {
-                    cmd: "SET",
-                    args: args.clone(),
-                })
+                conn.write_value(syntax_error()).await?;
+                return Ok(vec![]);
             }
-        },
-        None => { /* No more args */ }
+        }
     }
 
-    storage.insert(key, value, duration);
-    let value = Value::SimpleString(SimpleString::new("OK"));
-    conn.write_value(value).await
+    let expire = expire.unwrap_or(SetExpire::Never);
+    let (value, set) = match storage.set(key.clone(), value, expire, condition, get) {
+        Ok((set, old_value)) if get => (bulk_reply(old_value), set),
+        Ok((true, _)) => (Value::SimpleString(SimpleString::new("OK")), true),
+        Ok((false, _)) => (Value::BulkString(BulkString::null()), false),
+        Err(e) => (e.to_message(), false),
+    };
+    conn.write_value(value).await?;
+    // The condition and GET are resolved, replicas only need to set the value.
+    Ok(match raw_value {
+        Some(raw_value) if set => vec![set_effect(&key, raw_value, expire)],
+        _ => vec![],
+    })
 }
@@ -0,0 +1,60 @@
+use serde_redis::{Array, Integer, SimpleError, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+/// Max bit offset, so the string never exceeds 512MB.
+const MAX_BIT_OFFSET: usize = 4 * 1024 * 1024 * 1024 - 1;
+
+/// Parse bit offset in SETBIT and GETBIT.
+pub(super) fn parse_bit_offset(value: Option<String>) -> Result<usize, Value> {
+    value
+        .and_then(|x| x.parse::<usize>().ok())
+        .filter(|x| *x <= MAX_BIT_OFFSET)
+        .ok_or_else(|| {
+            Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                "bit offset is not an integer or out of range",
+            ))
+        })
+}
+
+pub(super) async fn handle_setbit_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command SETBIT");
+
+    // SETBIT key offset value
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "SETBIT",
+            args: args.clone(),
+        })?;
+    let offset = match parse_bit_offset(args.pop_front_bulk_string()) {
+        Ok(v) => v,
+        Err(e) => return conn.write_value(e).await,
+    };
+    let bit = match args.pop_front_bulk_string().as_deref() {
+        Some("0") => false,
+        Some("1") => true,
+        _ => {
+            let value = Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                "bit is not an integer or out of range",
+            ));
+            return conn.write_value(value).await;
+        }
+    };
+
+    let value = match storage.string_set_bit(key, offset, bit) {
+        Ok(v) => Value::Integer(Integer::new(v as i64)),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
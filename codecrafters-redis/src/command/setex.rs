@@ -0,0 +1,61 @@
+use serde_redis::{Array, SimpleString, Value};
+
+use crate::{
+    command::set::{parse_expire_at, pop_front_value, set_effect},
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{SetCondition, SetExpire, Storage},
+};
+
+/// Handle SETEX and PSETEX.
+///
+/// Set `millis` to true if the expiration time is in milliseconds (PSETEX).
+///
+/// Return the effects to sync to replica, a SET with the expiration in absolute time.
+pub(super) async fn handle_setex_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+    millis: bool,
+) -> ServerResult<Vec<Array>> {
+    let (cmd, unit) = if millis {
+        ("PSETEX", "PX")
+    } else {
+        ("SETEX", "EX")
+    };
+    conn.log(format!("run command {cmd}"));
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd,
+            args: args.clone(),
+        })?;
+    let time = args.pop_front_bulk_string();
+    let raw_value = args.value().and_then(|x| x.first()).cloned();
+    let value = pop_front_value(&mut args).ok_or_else(|| ServerError::InvalidArgs {
+        cmd,
+        args: args.clone(),
+    })?;
+    let at = match parse_expire_at(&cmd.to_lowercase(), unit, time) {
+        Ok(v) => v,
+        Err(e) => {
+            conn.write_value(e).await?;
+            return Ok(vec![]);
+        }
+    };
+
+    let expire = SetExpire::At(at);
+    let (value, effects) =
+        match storage.set(key.clone(), value, expire, SetCondition::Always, false) {
+            Ok(..) => (
+                Value::SimpleString(SimpleString::new("OK")),
+                raw_value
+                    .map(|x| set_effect(&key, x, expire))
+                    .into_iter()
+                    .collect(),
+            ),
+            Err(e) => (e.to_message(), vec![]),
+        };
+    conn.write_value(value).await?;
+    Ok(effects)
+}
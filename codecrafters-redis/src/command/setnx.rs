@@ -0,0 +1,32 @@
+use serde_redis::{Array, Integer, Value};
+
+use crate::{
+    command::set::pop_front_value,
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{SetCondition, SetExpire, Storage},
+};
+
+pub(super) async fn handle_setnx_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command SETNX");
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "SETNX",
+            args: args.clone(),
+        })?;
+    let value = pop_front_value(&mut args).ok_or_else(|| ServerError::InvalidArgs {
+        cmd: "SETNX",
+        args: args.clone(),
+    })?;
+
+    let value = match storage.set(key, value, SetExpire::Never, SetCondition::NotExists, false) {
+        Ok((set, _)) => Value::Integer(Integer::new(set as i64)),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
@@ -0,0 +1,52 @@
+use serde_redis::{Array, Integer, SimpleError, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+pub(super) async fn handle_setrange_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command SETRANGE");
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "SETRANGE",
+            args: args.clone(),
+        })?;
+
+    let offset = match args
+        .pop_front_bulk_string()
+        .and_then(|s| s.parse::<usize>().ok())
+    {
+        Some(v) => v,
+        None => {
+            let value =
+                Value::SimpleError(SimpleError::with_prefix("ERR", "offset is out of range"));
+            return conn.write_value(value).await;
+        }
+    };
+
+    let bytes = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "SETRANGE",
+            args: args.clone(),
+        })?;
+
+    conn.log(format!(
+        "SETRANGE {:?} {offset}",
+        String::from_utf8_lossy(&key)
+    ));
+
+    let value = match storage.string_set_range(key, offset, bytes) {
+        Ok(v) => Value::Integer(Integer::new(v as i64)),
+        Err(e) => e.to_message(),
+    };
+
+    conn.write_value(value).await
+}
@@ -0,0 +1,222 @@
+use std::cmp::Ordering;
+
+use serde_redis::{Array, Integer, Value};
+
+use crate::{
+    command::{bulk_reply, effect_command, geosearch::error, list_feed_effects, set::syntax_error},
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+/// Options of SORT.
+#[derive(Default)]
+struct SortOptions {
+    /// Pattern of keys to sort by, see [`Storage::sort_lookup`].
+    by: Option<Vec<u8>>,
+
+    /// Offset and count of LIMIT, negative count for all.
+    limit: Option<(i64, i64)>,
+
+    /// Patterns of values to reply instead of the elements, `#` for the element itself.
+    get: Vec<Vec<u8>>,
+    descending: bool,
+    alpha: bool,
+    store: Option<Vec<u8>>,
+}
+
+/// Parse `args` of SORT after the key, STORE is not accepted if `readonly`.
+///
+/// Return the error replied if invalid.
+fn parse_options(args: &[Vec<u8>], readonly: bool) -> Result<SortOptions, Value> {
+    let parse_int = |x: &[u8]| {
+        std::str::from_utf8(x)
+            .ok()
+            .and_then(|x| x.parse::<i64>().ok())
+            .ok_or_else(|| error("value is not an integer or out of range"))
+    };
+    let mut options = SortOptions::default();
+    let mut args = args.iter();
+    while let Some(option) = args.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            b"ASC" => options.descending = false,
+            b"DESC" => options.descending = true,
+            b"ALPHA" => options.alpha = true,
+            b"LIMIT" => {
+                let (Some(offset), Some(count)) = (args.next(), args.next()) else {
+                    return Err(syntax_error());
+                };
+                options.limit = Some((parse_int(offset)?, parse_int(count)?));
+            }
+            b"BY" => options.by = Some(args.next().ok_or_else(syntax_error)?.clone()),
+            b"GET" => options
+                .get
+                .push(args.next().ok_or_else(syntax_error)?.clone()),
+            b"STORE" if !readonly => {
+                options.store = Some(args.next().ok_or_else(syntax_error)?.clone())
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    Ok(options)
+}
+
+/// Sort `elements` by `options`, then apply LIMIT and GET.
+///
+/// Return the values to reply or store, `None` for values looked up by GET not
+/// present, or the error replied if any weight is not a number.
+fn sort_values(
+    storage: &Storage,
+    mut elements: Vec<Vec<u8>>,
+    options: &SortOptions,
+) -> Result<Vec<Option<Vec<u8>>>, Value> {
+    // Like redis, BY a pattern without `*` keeps the order in storage.
+    let sort = options.by.as_ref().is_none_or(|x| x.contains(&b'*'));
+    if sort {
+        let weights = elements
+            .iter()
+            .map(|x| match &options.by {
+                Some(pattern) => storage.sort_lookup(pattern, x),
+                None => Some(x.clone()),
+            })
+            .collect::<Vec<_>>();
+        let mut weighted = if options.alpha {
+            elements
+                .into_iter()
+                .zip(weights)
+                .map(|(element, weight)| (element, weight, 0.0))
+                .collect::<Vec<_>>()
+        } else {
+            // Elements without weight are sorted as zero.
+            let mut weighted = vec![];
+            for (element, weight) in elements.into_iter().zip(weights) {
+                let score = match weight {
+                    Some(v) => std::str::from_utf8(&v)
+                        .ok()
+                        .and_then(|x| x.trim_start().parse::<f64>().ok())
+                        .filter(|x| !x.is_nan())
+                        .ok_or_else(|| {
+                            error("One or more scores can't be converted into double")
+                        })?,
+                    None => 0.0,
+                };
+                weighted.push((element, None, score));
+            }
+            weighted
+        };
+        weighted.sort_by(|a, b| {
+            let ordering = if options.alpha {
+                a.1.cmp(&b.1)
+            } else {
+                // Elements in the same score are ordered lexicographically.
+                a.2.partial_cmp(&b.2)
+                    .unwrap_or(Ordering::Equal)
+                    .then_with(|| a.0.cmp(&b.0))
+            };
+            if options.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        elements = weighted.into_iter().map(|(element, ..)| element).collect();
+    }
+
+    if let Some((offset, count)) = options.limit {
+        let count = usize::try_from(count).unwrap_or(usize::MAX);
+        elements = elements
+            .into_iter()
+            .skip(offset.max(0) as usize)
+            .take(count)
+            .collect();
+    }
+    if options.get.is_empty() {
+        return Ok(elements.into_iter().map(Some).collect());
+    }
+    Ok(elements
+        .iter()
+        .flat_map(|element| {
+            options
+                .get
+                .iter()
+                .map(move |pattern| match pattern.as_slice() {
+                    b"#" => Some(element.clone()),
+                    _ => storage.sort_lookup(pattern, element),
+                })
+        })
+        .collect())
+}
+
+/// Handle SORT, or SORT_RO if `readonly` is true, which does not accept STORE.
+///
+/// Return the effects to sync to replica if the result is stored, the destination
+/// replaced by DEL and RPUSH followed by the pops of blocked tasks fed.
+pub(super) async fn handle_sort_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+    readonly: bool,
+) -> ServerResult<Vec<Array>> {
+    let cmd = if readonly { "SORT_RO" } else { "SORT" };
+    conn.log(format!("run command {cmd}"));
+
+    // SORT key [BY pattern] [LIMIT offset count] [GET pattern [GET pattern ...]]
+    //   [ASC | DESC] [ALPHA] [STORE destination]
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd,
+            args: args.clone(),
+        })?;
+    let rest = std::iter::from_fn(|| args.pop_front_bulk_string_bytes()).collect::<Vec<_>>();
+    let options = match parse_options(&rest, readonly) {
+        Ok(v) => v,
+        Err(e) => {
+            conn.write_value(e).await?;
+            return Ok(vec![]);
+        }
+    };
+
+    let sorted = storage
+        .sort_elements(&key)
+        .map_err(|e| e.to_message())
+        .and_then(|elements| sort_values(storage, elements, &options));
+    let values = match sorted {
+        Ok(v) => v,
+        Err(e) => {
+            conn.write_value(e).await?;
+            return Ok(vec![]);
+        }
+    };
+
+    let Some(destination) = options.store else {
+        let value = values.into_iter().map(bulk_reply).collect::<Array>();
+        conn.write_value(Value::Array(value)).await?;
+        return Ok(vec![]);
+    };
+    // Values not present are stored as empty strings.
+    let values = values
+        .into_iter()
+        .map(Option::unwrap_or_default)
+        .collect::<Vec<_>>();
+    let count = values.len();
+    conn.log(format!(
+        "SORT stored {count} elements in {:?}",
+        String::from_utf8_lossy(&destination)
+    ));
+    // The result is synced instead of sorted again, lookups by BY and GET are not
+    // cheap.
+    let mut effects = vec![effect_command([b"DEL".as_slice(), &destination])];
+    if !values.is_empty() {
+        let mut effect = effect_command([b"RPUSH".as_slice(), &destination]);
+        effect.append(effect_command(values.clone()));
+        effects.push(effect);
+    }
+    match storage.list_replace(destination, values) {
+        Some(feeds) => effects.extend(list_feed_effects(feeds)),
+        None => effects.clear(),
+    }
+    conn.write_value(Value::Integer(Integer::new(count as i64)))
+        .await?;
+    Ok(effects)
+}
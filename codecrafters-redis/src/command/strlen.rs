@@ -0,0 +1,29 @@
+use serde_redis::{Array, Integer, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{OpError, Storage},
+};
+
+pub(super) async fn handle_strlen_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command STRLEN");
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "STRLEN",
+            args: args.clone(),
+        })?;
+
+    let value = match storage.string_len(key) {
+        Ok(v) => Value::Integer(Integer::new(v as i64)),
+        Err(OpError::KeyAbsent) => Value::Integer(Integer::new(0)),
+        Err(e) => e.to_message(),
+    };
+
+    conn.write_value(value).await
+}
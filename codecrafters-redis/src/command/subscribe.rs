@@ -0,0 +1,81 @@
+use serde_redis::{Array, BulkString, Integer, Push, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    pubsub::SubscriptionKind,
+    state::ServerState,
+};
+
+/// Reply of subscribing and unsubscribing for one channel or pattern, `None` if
+/// unsubscribing without any subscribed.
+fn subscription_reply(cmd: &str, channel: Option<&str>, count: usize) -> Value {
+    Value::Push(Push::new(vec![
+        Value::BulkString(BulkString::new(cmd.to_lowercase())),
+        channel.map_or(Value::BulkString(BulkString::null()), |x| {
+            Value::BulkString(BulkString::new(x))
+        }),
+        Value::Integer(Integer::new(count as i64)),
+    ]))
+}
+
+/// Handle SUBSCRIBE, PSUBSCRIBE or SSUBSCRIBE by `kind`.
+///
+/// Reply a message for each channel, with the count of subscriptions after it.
+pub(super) async fn handle_subscribe_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    state: &mut ServerState,
+    kind: SubscriptionKind,
+) -> ServerResult<()> {
+    let cmd = match kind {
+        SubscriptionKind::Channel => "SUBSCRIBE",
+        SubscriptionKind::Pattern => "PSUBSCRIBE",
+        SubscriptionKind::Shard => "SSUBSCRIBE",
+    };
+    conn.log(format!("run command {cmd}"));
+    if args.is_empty() {
+        return Err(ServerError::InvalidArgs { cmd, args });
+    }
+    while let Some(channel) = args.pop_front_bulk_string() {
+        let count = state.pubsub().subscribe(conn.id, &channel, kind);
+        conn.write_value(subscription_reply(cmd, Some(&channel), count))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Handle UNSUBSCRIBE, PUNSUBSCRIBE or SUNSUBSCRIBE by `kind`.
+///
+/// Unsubscribe from all channels, or patterns, of `kind` if none given. Reply a message
+/// for each channel, with the count of subscriptions still kept.
+pub(super) async fn handle_unsubscribe_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    state: &mut ServerState,
+    kind: SubscriptionKind,
+) -> ServerResult<()> {
+    let cmd = match kind {
+        SubscriptionKind::Channel => "UNSUBSCRIBE",
+        SubscriptionKind::Pattern => "PUNSUBSCRIBE",
+        SubscriptionKind::Shard => "SUNSUBSCRIBE",
+    };
+    conn.log(format!("run command {cmd}"));
+    let mut channels = vec![];
+    while let Some(channel) = args.pop_front_bulk_string() {
+        channels.push(channel);
+    }
+    if channels.is_empty() {
+        channels = state.pubsub().subscriptions(conn.id, kind);
+        if channels.is_empty() {
+            let count = state.pubsub().subscription_count(conn.id, kind);
+            return conn.write_value(subscription_reply(cmd, None, count)).await;
+        }
+    }
+    for channel in channels {
+        let count = state.pubsub().unsubscribe(conn.id, &channel, kind);
+        conn.write_value(subscription_reply(cmd, Some(&channel), count))
+            .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,1349 @@
+//! Static table of all commands, read by COMMAND and the dispatcher.
+//!
+//! Fields follow COMMAND INFO in redis: `arity` is the count of arguments including
+//! the command name, negative for at least that many. Keys are found at positions
+//! from `first_key` to `last_key` by `step`, where negative `last_key` counts from
+//! the end, all zero if no key or keys are found by parsing arguments, flagged with
+//! "movablekeys".
+
+use std::sync::LazyLock;
+
+/// A command in the command table.
+#[derive(Debug)]
+pub(crate) struct CommandSpec {
+    pub(crate) name: &'static str,
+    pub(crate) arity: i64,
+    pub(crate) flags: &'static [&'static str],
+    pub(crate) first_key: i64,
+    pub(crate) last_key: i64,
+    pub(crate) step: i64,
+
+    /// Group in COMMAND DOCS, like "string" and "list".
+    pub(crate) group: &'static str,
+
+    /// Version of redis the command is available since.
+    pub(crate) since: &'static str,
+    pub(crate) summary: &'static str,
+}
+
+impl CommandSpec {
+    /// Check whether `argc` arguments including the command name are accepted.
+    pub(crate) fn check_arity(&self, argc: usize) -> bool {
+        let argc = argc as i64;
+        if self.arity >= 0 {
+            argc == self.arity
+        } else {
+            argc >= -self.arity
+        }
+    }
+
+    /// Check whether the command may write the dataset, by the "write" flag.
+    pub(crate) fn is_write(&self) -> bool {
+        self.flags.contains(&"write")
+    }
+}
+
+/// All commands, ordered by name.
+pub(crate) static COMMAND_TABLE: &[CommandSpec] = &[
+    CommandSpec {
+        name: "ACL",
+        arity: -2,
+        flags: &[],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "6.0.0",
+        summary: "A container for Access List Control commands.",
+    },
+    CommandSpec {
+        name: "APPEND",
+        arity: 3,
+        flags: &["write", "denyoom", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "string",
+        since: "2.0.0",
+        summary: "Appends a string to the value of a key. Creates the key if it doesn't exist.",
+    },
+    CommandSpec {
+        name: "ASKING",
+        arity: 1,
+        flags: &["fast"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "cluster",
+        since: "3.0.0",
+        summary: "Signals that a cluster client is following an -ASK redirect.",
+    },
+    CommandSpec {
+        name: "AUTH",
+        arity: -2,
+        flags: &["noscript", "loading", "stale", "fast", "no_auth", "allow_busy"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "connection",
+        since: "1.0.0",
+        summary: "Authenticates the connection.",
+    },
+    CommandSpec {
+        name: "BGREWRITEAOF",
+        arity: 1,
+        flags: &["admin", "noscript", "no_async_loading"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "Asynchronously rewrites the append-only file to disk.",
+    },
+    CommandSpec {
+        name: "BGSAVE",
+        arity: -1,
+        flags: &["admin", "noscript", "no_async_loading"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "Asynchronously saves the database(s) to disk.",
+    },
+    CommandSpec {
+        name: "BITCOUNT",
+        arity: -2,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "bitmap",
+        since: "2.6.0",
+        summary: "Counts the number of set bits (population counting) in a string.",
+    },
+    CommandSpec {
+        name: "BITPOS",
+        arity: -3,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "bitmap",
+        since: "2.8.7",
+        summary: "Finds the first set (1) or clear (0) bit in a string.",
+    },
+    CommandSpec {
+        name: "BLMOVE",
+        arity: 6,
+        flags: &["write", "denyoom", "blocking"],
+        first_key: 1,
+        last_key: 2,
+        step: 1,
+        group: "list",
+        since: "6.2.0",
+        summary: "Pops an element from a list, pushes it to another list and returns it. Blocks until an element is available otherwise. Deletes the list if the last element was moved.",
+    },
+    CommandSpec {
+        name: "BLMPOP",
+        arity: -5,
+        flags: &["write", "blocking", "movablekeys"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "list",
+        since: "7.0.0",
+        summary: "Pops the first element from one of multiple lists. Blocks until an element is available otherwise. Deletes the list if the last element was popped.",
+    },
+    CommandSpec {
+        name: "BLPOP",
+        arity: -3,
+        flags: &["write", "blocking"],
+        first_key: 1,
+        last_key: -2,
+        step: 1,
+        group: "list",
+        since: "2.0.0",
+        summary: "Removes and returns the first element in a list. Blocks until an element is available otherwise. Deletes the list if the last element was popped.",
+    },
+    CommandSpec {
+        name: "BRPOP",
+        arity: -3,
+        flags: &["write", "blocking"],
+        first_key: 1,
+        last_key: -2,
+        step: 1,
+        group: "list",
+        since: "2.0.0",
+        summary: "Removes and returns the last element in a list. Blocks until an element is available otherwise. Deletes the list if the last element was popped.",
+    },
+    CommandSpec {
+        name: "BRPOPLPUSH",
+        arity: 4,
+        flags: &["write", "denyoom", "blocking"],
+        first_key: 1,
+        last_key: 2,
+        step: 1,
+        group: "list",
+        since: "2.2.0",
+        summary: "Pops an element from a list, pushes it to another list and returns it. Block until an element is available otherwise. Deletes the list if the last element was popped.",
+    },
+    CommandSpec {
+        name: "BZPOPMAX",
+        arity: -3,
+        flags: &["write", "fast", "blocking"],
+        first_key: 1,
+        last_key: -2,
+        step: 1,
+        group: "sorted_set",
+        since: "5.0.0",
+        summary: "Removes and returns the member with the highest score from one or more sorted sets. Blocks until a member available otherwise.  Deletes the sorted set if the last element was popped.",
+    },
+    CommandSpec {
+        name: "BZPOPMIN",
+        arity: -3,
+        flags: &["write", "fast", "blocking"],
+        first_key: 1,
+        last_key: -2,
+        step: 1,
+        group: "sorted_set",
+        since: "5.0.0",
+        summary: "Removes and returns the member with the lowest score from one or more sorted sets. Blocks until a member is available otherwise. Deletes the sorted set if the last element was popped.",
+    },
+    CommandSpec {
+        name: "CLIENT",
+        arity: -2,
+        flags: &[],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "connection",
+        since: "2.4.0",
+        summary: "A container for client connection commands.",
+    },
+    CommandSpec {
+        name: "CLUSTER",
+        arity: -2,
+        flags: &[],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "cluster",
+        since: "3.0.0",
+        summary: "A container for Redis Cluster commands.",
+    },
+    CommandSpec {
+        name: "COMMAND",
+        arity: -1,
+        flags: &["loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "2.8.13",
+        summary: "Returns detailed information about all commands.",
+    },
+    CommandSpec {
+        name: "CONFIG",
+        arity: -2,
+        flags: &[],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "2.0.0",
+        summary: "A container for server configuration commands.",
+    },
+    CommandSpec {
+        name: "DEBUG",
+        arity: -2,
+        flags: &["admin", "noscript", "loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "A container for debugging commands.",
+    },
+    CommandSpec {
+        name: "DEL",
+        arity: -2,
+        flags: &["write"],
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+        group: "generic",
+        since: "1.0.0",
+        summary: "Deletes one or more keys.",
+    },
+    CommandSpec {
+        name: "DISCARD",
+        arity: 1,
+        flags: &["noscript", "loading", "stale", "fast", "allow_busy"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "transactions",
+        since: "2.0.0",
+        summary: "Discards a transaction.",
+    },
+    CommandSpec {
+        name: "DUMP",
+        arity: 2,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "generic",
+        since: "2.6.0",
+        summary: "Returns a serialized representation of the value stored at a key.",
+    },
+    CommandSpec {
+        name: "ECHO",
+        arity: 2,
+        flags: &["fast"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "connection",
+        since: "1.0.0",
+        summary: "Returns the given string.",
+    },
+    CommandSpec {
+        name: "EXEC",
+        arity: 1,
+        flags: &["noscript", "loading", "stale", "skip_slowlog"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "transactions",
+        since: "1.2.0",
+        summary: "Executes all commands in a transaction.",
+    },
+    CommandSpec {
+        name: "EXPORT",
+        arity: -2,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+        group: "generic",
+        since: "0.1.0",
+        summary: "Dumps keys to a JSON document.",
+    },
+    CommandSpec {
+        name: "FAILOVER",
+        arity: -1,
+        flags: &["admin", "noscript", "stale"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "6.2.0",
+        summary: "Starts a coordinated failover from a server to one of its replicas.",
+    },
+    CommandSpec {
+        name: "FLUSHALL",
+        arity: -1,
+        flags: &["write"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "Removes all keys from all databases.",
+    },
+    CommandSpec {
+        name: "FLUSHDB",
+        arity: -1,
+        flags: &["write"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "Remove all keys from the current database.",
+    },
+    CommandSpec {
+        name: "GEOADD",
+        arity: -5,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "geo",
+        since: "3.2.0",
+        summary: "Adds one or more members to a geospatial index. The key is created if it doesn't exist.",
+    },
+    CommandSpec {
+        name: "GEODIST",
+        arity: -4,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "geo",
+        since: "3.2.0",
+        summary: "Returns the distance between two members of a geospatial index.",
+    },
+    CommandSpec {
+        name: "GEOPOS",
+        arity: -2,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "geo",
+        since: "3.2.0",
+        summary: "Returns the longitude and latitude of members from a geospatial index.",
+    },
+    CommandSpec {
+        name: "GEORADIUS",
+        arity: -6,
+        flags: &["write", "denyoom", "movablekeys"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "geo",
+        since: "3.2.0",
+        summary: "Queries a geospatial index for members within a distance from a coordinate, optionally stores the result.",
+    },
+    CommandSpec {
+        name: "GEORADIUSBYMEMBER",
+        arity: -5,
+        flags: &["write", "denyoom", "movablekeys"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "geo",
+        since: "3.2.0",
+        summary: "Queries a geospatial index for members within a distance from a member, optionally stores the result.",
+    },
+    CommandSpec {
+        name: "GEORADIUSBYMEMBER_RO",
+        arity: -5,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "geo",
+        since: "3.2.10",
+        summary: "Returns members from a geospatial index that are within a distance from a member.",
+    },
+    CommandSpec {
+        name: "GEORADIUS_RO",
+        arity: -6,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "geo",
+        since: "3.2.10",
+        summary: "Returns members from a geospatial index that are within a distance from a coordinate.",
+    },
+    CommandSpec {
+        name: "GEOSEARCH",
+        arity: -7,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "geo",
+        since: "6.2.0",
+        summary: "Queries a geospatial index for members inside an area of a box or a circle.",
+    },
+    CommandSpec {
+        name: "GET",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "string",
+        since: "1.0.0",
+        summary: "Returns the string value of a key.",
+    },
+    CommandSpec {
+        name: "GETBIT",
+        arity: 3,
+        flags: &["readonly", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "bitmap",
+        since: "2.2.0",
+        summary: "Returns a bit value by offset.",
+    },
+    CommandSpec {
+        name: "GETDEL",
+        arity: 2,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "string",
+        since: "6.2.0",
+        summary: "Returns the string value of a key after deleting the key.",
+    },
+    CommandSpec {
+        name: "GETEX",
+        arity: -2,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "string",
+        since: "6.2.0",
+        summary: "Returns the string value of a key after setting its expiration time.",
+    },
+    CommandSpec {
+        name: "GETRANGE",
+        arity: 4,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "string",
+        since: "2.4.0",
+        summary: "Returns a substring of the string stored at a key.",
+    },
+    CommandSpec {
+        name: "GETSET",
+        arity: 3,
+        flags: &["write", "denyoom", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "string",
+        since: "1.0.0",
+        summary: "Returns the previous string value of a key after setting it to a new value.",
+    },
+    CommandSpec {
+        name: "HELLO",
+        arity: -1,
+        flags: &["noscript", "loading", "stale", "fast", "no_auth", "allow_busy"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "connection",
+        since: "6.0.0",
+        summary: "Handshakes with the Redis server.",
+    },
+    CommandSpec {
+        name: "IMPORT",
+        arity: -2,
+        flags: &["write", "denyoom"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "generic",
+        since: "0.1.0",
+        summary: "Saves all keys in a JSON document produced by EXPORT.",
+    },
+    CommandSpec {
+        name: "INCR",
+        arity: 2,
+        flags: &["write", "denyoom", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "string",
+        since: "1.0.0",
+        summary: "Increments the integer value of a key by one. Uses 0 as initial value if the key doesn't exist.",
+    },
+    CommandSpec {
+        name: "INFO",
+        arity: -1,
+        flags: &["loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "Returns information and statistics about the server.",
+    },
+    CommandSpec {
+        name: "LASTSAVE",
+        arity: 1,
+        flags: &["loading", "stale", "fast"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "Returns the Unix timestamp of the last successful save to disk.",
+    },
+    CommandSpec {
+        name: "LINDEX",
+        arity: 3,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "list",
+        since: "1.0.0",
+        summary: "Returns an element from a list by its index.",
+    },
+    CommandSpec {
+        name: "LINSERT",
+        arity: 5,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "list",
+        since: "2.2.0",
+        summary: "Inserts an element before or after another element in a list.",
+    },
+    CommandSpec {
+        name: "LLEN",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "list",
+        since: "1.0.0",
+        summary: "Returns the length of a list.",
+    },
+    CommandSpec {
+        name: "LMOVE",
+        arity: 5,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 2,
+        step: 1,
+        group: "list",
+        since: "6.2.0",
+        summary: "Returns an element after popping it from one list and pushing it to another. Deletes the list if the last element was moved.",
+    },
+    CommandSpec {
+        name: "LMPOP",
+        arity: -4,
+        flags: &["write", "movablekeys"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "list",
+        since: "7.0.0",
+        summary: "Returns multiple elements from a list after removing them. Deletes the list if the last element was popped.",
+    },
+    CommandSpec {
+        name: "LOLWUT",
+        arity: -1,
+        flags: &["readonly", "fast"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "5.0.0",
+        summary: "Displays computer art and the Redis version",
+    },
+    CommandSpec {
+        name: "LPOP",
+        arity: -2,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "list",
+        since: "1.0.0",
+        summary: "Returns the first elements in a list after removing it. Deletes the list if the last element was popped.",
+    },
+    CommandSpec {
+        name: "LPOS",
+        arity: -3,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "list",
+        since: "6.0.6",
+        summary: "Returns the index of matching elements in a list.",
+    },
+    CommandSpec {
+        name: "LPUSH",
+        arity: -3,
+        flags: &["write", "denyoom", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "list",
+        since: "1.0.0",
+        summary: "Prepends one or more elements to a list. Creates the key if it doesn't exist.",
+    },
+    CommandSpec {
+        name: "LRANGE",
+        arity: 4,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "list",
+        since: "1.0.0",
+        summary: "Returns a range of elements from a list.",
+    },
+    CommandSpec {
+        name: "LREM",
+        arity: 4,
+        flags: &["write"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "list",
+        since: "1.0.0",
+        summary: "Removes elements from a list. Deletes the list if the last element was removed.",
+    },
+    CommandSpec {
+        name: "LSET",
+        arity: 4,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "list",
+        since: "1.0.0",
+        summary: "Sets the value of an element in a list by its index.",
+    },
+    CommandSpec {
+        name: "LTRIM",
+        arity: 4,
+        flags: &["write"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "list",
+        since: "1.0.0",
+        summary: "Removes elements from both ends a list. Deletes the list if all elements were trimmed.",
+    },
+    CommandSpec {
+        name: "MIGRATE",
+        arity: -6,
+        flags: &["write", "movablekeys"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "generic",
+        since: "2.6.0",
+        summary: "Atomically transfers a key from one Redis instance to another.",
+    },
+    CommandSpec {
+        name: "MONITOR",
+        arity: 1,
+        flags: &["admin", "noscript", "loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "Listens for all requests received by the server in real-time.",
+    },
+    CommandSpec {
+        name: "MULTI",
+        arity: 1,
+        flags: &["noscript", "loading", "stale", "fast", "allow_busy"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "transactions",
+        since: "1.2.0",
+        summary: "Starts a transaction.",
+    },
+    CommandSpec {
+        name: "OBJECT",
+        arity: -2,
+        flags: &[],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "generic",
+        since: "2.2.3",
+        summary: "A container for object introspection commands.",
+    },
+    CommandSpec {
+        name: "PFADD",
+        arity: -2,
+        flags: &["write", "denyoom", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "hyperloglog",
+        since: "2.8.9",
+        summary: "Adds elements to a HyperLogLog key. Creates the key if it doesn't exist.",
+    },
+    CommandSpec {
+        name: "PFCOUNT",
+        arity: -2,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+        group: "hyperloglog",
+        since: "2.8.9",
+        summary: "Returns the approximated cardinality of the set(s) observed by the HyperLogLog key(s).",
+    },
+    CommandSpec {
+        name: "PFMERGE",
+        arity: -2,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+        group: "hyperloglog",
+        since: "2.8.9",
+        summary: "Merges one or more HyperLogLog values into a single key.",
+    },
+    CommandSpec {
+        name: "PING",
+        arity: -1,
+        flags: &["fast"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "connection",
+        since: "1.0.0",
+        summary: "Returns the server's liveliness response.",
+    },
+    CommandSpec {
+        name: "PSETEX",
+        arity: 4,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "string",
+        since: "2.6.0",
+        summary: "Sets both string value and expiration time in milliseconds of a key. The key is created if it doesn't exist.",
+    },
+    CommandSpec {
+        name: "PSUBSCRIBE",
+        arity: -2,
+        flags: &["pubsub", "noscript", "loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "pubsub",
+        since: "2.0.0",
+        summary: "Listens for messages published to channels that match one or more patterns.",
+    },
+    CommandSpec {
+        name: "PSYNC",
+        arity: -3,
+        flags: &["admin", "noscript", "no_async_loading", "no_multi"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "2.8.0",
+        summary: "An internal command used in replication.",
+    },
+    CommandSpec {
+        name: "PUBLISH",
+        arity: 3,
+        flags: &["pubsub", "loading", "stale", "fast"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "pubsub",
+        since: "2.0.0",
+        summary: "Posts a message to a channel.",
+    },
+    CommandSpec {
+        name: "PUBSUB",
+        arity: -2,
+        flags: &[],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "pubsub",
+        since: "2.8.0",
+        summary: "A container for Pub/Sub commands.",
+    },
+    CommandSpec {
+        name: "PUNSUBSCRIBE",
+        arity: -1,
+        flags: &["pubsub", "noscript", "loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "pubsub",
+        since: "2.0.0",
+        summary: "Stops listening to messages published to channels that match one or more patterns.",
+    },
+    CommandSpec {
+        name: "REPLCONF",
+        arity: -1,
+        flags: &["admin", "noscript", "loading", "stale", "allow_busy"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "3.0.0",
+        summary: "An internal command for configuring the replication stream.",
+    },
+    CommandSpec {
+        name: "REPLICAOF",
+        arity: 3,
+        flags: &["admin", "noscript", "stale", "no_async_loading"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "5.0.0",
+        summary: "Configures a server as replica of another, or promotes it to a master.",
+    },
+    CommandSpec {
+        name: "RESTORE",
+        arity: -4,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "generic",
+        since: "2.6.0",
+        summary: "Creates a key from the serialized representation of a value.",
+    },
+    CommandSpec {
+        name: "RPOP",
+        arity: -2,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "list",
+        since: "1.0.0",
+        summary: "Returns and removes the last elements of a list. Deletes the list if the last element was popped.",
+    },
+    CommandSpec {
+        name: "RPOPLPUSH",
+        arity: 3,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 2,
+        step: 1,
+        group: "list",
+        since: "1.2.0",
+        summary: "Returns the last element of a list after removing and pushing it to another list. Deletes the list if the last element was popped.",
+    },
+    CommandSpec {
+        name: "RPUSH",
+        arity: -3,
+        flags: &["write", "denyoom", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "list",
+        since: "1.0.0",
+        summary: "Appends one or more elements to a list. Creates the key if it doesn't exist.",
+    },
+    CommandSpec {
+        name: "SAVE",
+        arity: 1,
+        flags: &["admin", "noscript", "no_async_loading", "no_multi"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "Synchronously saves the database(s) to disk.",
+    },
+    CommandSpec {
+        name: "SET",
+        arity: -3,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "string",
+        since: "1.0.0",
+        summary: "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist.",
+    },
+    CommandSpec {
+        name: "SETBIT",
+        arity: 4,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "bitmap",
+        since: "2.2.0",
+        summary: "Sets or clears the bit at offset of the string value. Creates the key if it doesn't exist.",
+    },
+    CommandSpec {
+        name: "SETEX",
+        arity: 4,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "string",
+        since: "2.0.0",
+        summary: "Sets the string value and expiration time of a key. Creates the key if it doesn't exist.",
+    },
+    CommandSpec {
+        name: "SETNX",
+        arity: 3,
+        flags: &["write", "denyoom", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "string",
+        since: "1.0.0",
+        summary: "Set the string value of a key only when the key doesn't exist.",
+    },
+    CommandSpec {
+        name: "SETRANGE",
+        arity: 4,
+        flags: &["write", "denyoom"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "string",
+        since: "2.2.0",
+        summary: "Overwrites a part of a string value with another by an offset. Creates the key if it doesn't exist.",
+    },
+    CommandSpec {
+        name: "SLAVEOF",
+        arity: 3,
+        flags: &["admin", "noscript", "stale", "no_async_loading"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "server",
+        since: "1.0.0",
+        summary: "Sets a Redis server as a replica of another, or promotes it to being a master.",
+    },
+    CommandSpec {
+        name: "SORT",
+        arity: -2,
+        flags: &["write", "denyoom", "movablekeys"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "generic",
+        since: "1.0.0",
+        summary: "Sorts the elements in a list, a set, or a sorted set, optionally storing the result.",
+    },
+    CommandSpec {
+        name: "SORT_RO",
+        arity: -2,
+        flags: &["readonly", "movablekeys"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "generic",
+        since: "7.0.0",
+        summary: "Returns the sorted elements of a list, a set, or a sorted set.",
+    },
+    CommandSpec {
+        name: "SPUBLISH",
+        arity: 3,
+        flags: &["pubsub", "loading", "stale", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "pubsub",
+        since: "7.0.0",
+        summary: "Post a message to a shard channel",
+    },
+    CommandSpec {
+        name: "SSUBSCRIBE",
+        arity: -2,
+        flags: &["pubsub", "noscript", "loading", "stale"],
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+        group: "pubsub",
+        since: "7.0.0",
+        summary: "Listens for messages published to shard channels.",
+    },
+    CommandSpec {
+        name: "STRLEN",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "string",
+        since: "2.2.0",
+        summary: "Returns the length of a string value.",
+    },
+    CommandSpec {
+        name: "SUBSCRIBE",
+        arity: -2,
+        flags: &["pubsub", "noscript", "loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "pubsub",
+        since: "2.0.0",
+        summary: "Listens for messages published to channels.",
+    },
+    CommandSpec {
+        name: "SUNSUBSCRIBE",
+        arity: -1,
+        flags: &["pubsub", "noscript", "loading", "stale"],
+        first_key: 1,
+        last_key: -1,
+        step: 1,
+        group: "pubsub",
+        since: "7.0.0",
+        summary: "Stops listening to messages posted to shard channels.",
+    },
+    CommandSpec {
+        name: "TYPE",
+        arity: 2,
+        flags: &["readonly", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "generic",
+        since: "1.0.0",
+        summary: "Determines the type of value stored at a key.",
+    },
+    CommandSpec {
+        name: "UNSUBSCRIBE",
+        arity: -1,
+        flags: &["pubsub", "noscript", "loading", "stale"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "pubsub",
+        since: "2.0.0",
+        summary: "Stops listening to messages posted to channels.",
+    },
+    CommandSpec {
+        name: "WAIT",
+        arity: 3,
+        flags: &[],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "generic",
+        since: "3.0.0",
+        summary: "Blocks until the asynchronous replication of all preceding write commands sent by the connection is completed.",
+    },
+    CommandSpec {
+        name: "XACK",
+        arity: -4,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "stream",
+        since: "5.0.0",
+        summary: "Returns the number of messages that were successfully acknowledged by the consumer group member of a stream.",
+    },
+    CommandSpec {
+        name: "XADD",
+        arity: -5,
+        flags: &["write", "denyoom", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "stream",
+        since: "5.0.0",
+        summary: "Appends a new message to a stream. Creates the key if it doesn't exist.",
+    },
+    CommandSpec {
+        name: "XAUTOCLAIM",
+        arity: -6,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "stream",
+        since: "6.2.0",
+        summary: "Changes, or acquires, ownership of messages in a consumer group, as if the messages were delivered to as consumer group member.",
+    },
+    CommandSpec {
+        name: "XCLAIM",
+        arity: -6,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "stream",
+        since: "5.0.0",
+        summary: "Changes, or acquires, ownership of a message in a consumer group, as if the message was delivered a consumer group member.",
+    },
+    CommandSpec {
+        name: "XGROUP",
+        arity: -2,
+        flags: &["write"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "stream",
+        since: "5.0.0",
+        summary: "A container for consumer groups commands.",
+    },
+    CommandSpec {
+        name: "XINFO",
+        arity: -2,
+        flags: &[],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "stream",
+        since: "5.0.0",
+        summary: "A container for stream introspection commands.",
+    },
+    CommandSpec {
+        name: "XPENDING",
+        arity: -3,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "stream",
+        since: "5.0.0",
+        summary: "Returns the information and entries from a stream consumer group's pending entries list.",
+    },
+    CommandSpec {
+        name: "XRANGE",
+        arity: -4,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "stream",
+        since: "5.0.0",
+        summary: "Returns the messages from a stream within a range of IDs.",
+    },
+    CommandSpec {
+        name: "XREAD",
+        arity: -4,
+        flags: &["readonly", "blocking", "movablekeys"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "stream",
+        since: "5.0.0",
+        summary: "Returns messages from multiple streams with IDs greater than the ones requested. Blocks until a message is available otherwise.",
+    },
+    CommandSpec {
+        name: "XREADGROUP",
+        arity: -7,
+        flags: &["write", "blocking", "movablekeys"],
+        first_key: 0,
+        last_key: 0,
+        step: 0,
+        group: "stream",
+        since: "5.0.0",
+        summary: "Returns new or historical messages from a stream for a consumer in a group. Blocks until a message is available otherwise.",
+    },
+    CommandSpec {
+        name: "XREVRANGE",
+        arity: -4,
+        flags: &["readonly"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "stream",
+        since: "5.0.0",
+        summary: "Returns the messages from a stream within a range of IDs in reverse order.",
+    },
+    CommandSpec {
+        name: "XSETID",
+        arity: -3,
+        flags: &["write", "denyoom", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "stream",
+        since: "5.0.0",
+        summary: "An internal command for replicating stream values.",
+    },
+    CommandSpec {
+        name: "ZINCRBY",
+        arity: 4,
+        flags: &["write", "denyoom", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "sorted_set",
+        since: "1.2.0",
+        summary: "Increments the score of a member in a sorted set.",
+    },
+    CommandSpec {
+        name: "ZPOPMAX",
+        arity: -2,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "sorted_set",
+        since: "5.0.0",
+        summary: "Returns the highest-scoring members from a sorted set after removing them. Deletes the sorted set if the last member was popped.",
+    },
+    CommandSpec {
+        name: "ZPOPMIN",
+        arity: -2,
+        flags: &["write", "fast"],
+        first_key: 1,
+        last_key: 1,
+        step: 1,
+        group: "sorted_set",
+        since: "5.0.0",
+        summary: "Returns the lowest-scoring members from a sorted set after removing them. Deletes the sorted set if the last member was popped.",
+    },
+];
+
+/// Names of commands in [`COMMAND_TABLE`] in lowercase, in the same order.
+///
+/// Commands are reported in lowercase by stats and the client list, kept here so
+/// that no name is lowercased per call.
+static LOWERCASE_NAMES: LazyLock<Vec<String>> = LazyLock::new(|| {
+    COMMAND_TABLE
+        .iter()
+        .map(|x| x.name.to_lowercase())
+        .collect()
+});
+
+/// Find the position of command `name` in the table, case insensitive.
+fn command_position(name: &str) -> Option<usize> {
+    COMMAND_TABLE
+        .binary_search_by(|x| {
+            x.name
+                .bytes()
+                .cmp(name.bytes().map(|x| x.to_ascii_uppercase()))
+        })
+        .ok()
+}
+
+/// Find command `name` in the table, case insensitive.
+pub(crate) fn lookup_command(name: &str) -> Option<&'static CommandSpec> {
+    command_position(name).map(|x| &COMMAND_TABLE[x])
+}
+
+/// Name of command `name` in lowercase, case insensitive.
+///
+/// Return `None` if the command is not in the table.
+pub(crate) fn lowercase_command_name(name: &str) -> Option<&'static str> {
+    command_position(name).map(|x| LOWERCASE_NAMES[x].as_str())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup_command() {
+        assert!(COMMAND_TABLE.windows(2).all(|x| x[0].name < x[1].name));
+        assert_eq!(lookup_command("get").unwrap().name, "GET");
+        assert_eq!(lookup_command("XREVRANGE").unwrap().arity, -4);
+        assert!(lookup_command("FOO").is_none());
+
+        let set = lookup_command("SET").unwrap();
+        assert!(!set.check_arity(2));
+        assert!(set.check_arity(3));
+        assert!(set.check_arity(5));
+        let get = lookup_command("GET").unwrap();
+        assert!(get.check_arity(2));
+        assert!(!get.check_arity(3));
+
+        assert_eq!(lowercase_command_name("ZPopMin"), Some("zpopmin"));
+        assert_eq!(lowercase_command_name("FOO"), None);
+    }
+}
@@ -15,7 +15,7 @@ pub(super) async fn handle_type_command(
     conn.log("TYPE");
 
     let key = args
-        .pop_front_bulk_string()
+        .pop_front_bulk_string_bytes()
         .ok_or_else(|| ServerError::InvalidArgs {
             cmd: "TYPE",
             args: args.clone(),
@@ -3,15 +3,18 @@ use std::time::Duration;
 use serde_redis::{Array, Integer, Value};
 
 use crate::{
+    blocking::{BlockKind, Unblock},
     conn::Conn,
     error::{ServerError, ServerResult},
     replication::ReplicationState,
+    state::ServerState,
 };
 
 pub(super) async fn handle_wait_command(
     conn: &mut Conn<'_>,
     mut args: Array,
-    mut rep: ReplicationState,
+    rep: ReplicationState,
+    state: &ServerState,
 ) -> ServerResult<()> {
     conn.log("run command WAIT");
 
@@ -23,32 +26,47 @@ pub(super) async fn handle_wait_command(
             args: args.clone(),
         })?;
 
-    let duration = args
+    let timeout = args
         .pop_front_bulk_string()
         .and_then(|s| s.parse::<u64>().ok())
         .ok_or_else(|| ServerError::InvalidArgs {
             cmd: "WAIT",
             args: args.clone(),
-        })
-        .map(|d| Duration::from_millis(d))?;
-
-    conn.log(format!("[wait] count={count}, duration={duration:?}"));
-
-    // rep.replica_notify().await;
-
-    let replica_count = rep.replica_count(conn.id);
-    let v = if replica_count >= count {
-        conn.log(format!("[wait] replica count is {replica_count}"));
-        let value = Value::Integer(Integer::new(replica_count as i64));
-        conn.sync_value(value).await
-    } else {
-        conn.log("[wait] wait for duration");
-        tokio::time::sleep(duration).await;
-        conn.log("[wait] wait for duration end");
-        let replica_count = rep.replica_count(conn.id);
-        let value = Value::Integer(Integer::new(replica_count as i64));
-        conn.sync_value(value).await
+        })?;
+
+    conn.log(format!("[wait] count={count}, timeout={timeout}ms"));
+
+    // Wait for replicas to acknowledge all commands synced so far.
+    let offset = rep.offset();
+    let acked = rep.acked_count(offset);
+    if acked >= count {
+        conn.log(format!(
+            "[wait] {acked} replicas acknowledged offset {offset}"
+        ));
+        return conn
+            .sync_value(Value::Integer(Integer::new(acked as i64)))
+            .await;
+    }
+
+    rep.request_acks();
+    let wait = async {
+        match timeout {
+            // Wait forever.
+            0 => rep.wait_acks(offset, count).await,
+            v => tokio::time::timeout(Duration::from_millis(v), rep.wait_acks(offset, count))
+                .await
+                .unwrap_or_else(|_| rep.acked_count(offset)),
+        }
+    };
+    let value = match state
+        .blocking()
+        .block_on(conn, BlockKind::Replicas, wait)
+        .await
+    {
+        Ok(v) => Value::Integer(Integer::new(v as i64)),
+        Err(Unblock::Timeout) => Value::Integer(Integer::new(rep.acked_count(offset) as i64)),
+        Err(Unblock::Error(e)) => e,
     };
-    rep.replica_reset(conn.id);
-    v
+    conn.log(format!("[wait] end with {value:?}"));
+    conn.sync_value(value).await
 }
@@ -0,0 +1,42 @@
+use serde_redis::{Array, Integer, Value};
+
+use crate::{
+    command::xgroup::{invalid_stream_id, parse_record_id},
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+pub(super) async fn handle_xack_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command XACK");
+
+    // XACK key group id [id ...]
+    let key = args.pop_front_bulk_string_bytes();
+    let group = args.pop_front_bulk_string();
+    let (key, group) = match (key, group) {
+        (Some(a), Some(b)) if !args.is_empty() => (a, b),
+        _ => {
+            return Err(ServerError::InvalidArgs {
+                cmd: "XACK",
+                args: args.clone(),
+            })
+        }
+    };
+    let mut ids = vec![];
+    while let Some(id) = args.pop_front_bulk_string() {
+        match parse_record_id(&id) {
+            Some(v) => ids.push(v),
+            None => return conn.write_value(invalid_stream_id()).await,
+        }
+    }
+
+    let value = match storage.stream_ack(&key, &group, &ids) {
+        Ok(v) => Value::Integer(Integer::new(v as i64)),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
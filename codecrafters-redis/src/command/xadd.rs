@@ -1,67 +1,224 @@
-use serde_redis::{Array, BulkString, Value};
+use serde_redis::{Array, BulkString, SimpleError, Value};
 
 use crate::{
+    command::{effect_command, xreadgroup::group_feed_effects},
     conn::Conn,
     error::{ServerError, ServerResult},
-    storage::{Storage, StreamId},
+    storage::{OpError, Storage, StreamId, StreamIdSpec, TrimOptions, TrimStrategy},
 };
 
+/// Max count of records removed in one approximate trimming if not specified.
+const DEFAULT_TRIM_LIMIT: usize = 10000;
+
+/// Parse the rest of trimming option `option` after MAXLEN or MINID:
+/// `[= | ~] threshold`, return the options and whether it's approximate.
+///
+/// Records are trimmed exactly even in approximate trimming, only the count removed
+/// at once is limited.
+fn parse_trim_options(option: &str, args: &mut Array) -> Result<(TrimOptions, bool), Value> {
+    let mut threshold = args.pop_front_bulk_string().unwrap_or_default();
+    let approximate = threshold == "~";
+    if approximate || threshold == "=" {
+        threshold = args.pop_front_bulk_string().unwrap_or_default();
+    }
+    let strategy = if option == "MAXLEN" {
+        match threshold.parse::<usize>() {
+            Ok(v) => TrimStrategy::MaxLen(v),
+            Err(..) => {
+                return Err(Value::SimpleError(SimpleError::with_prefix(
+                    "ERR",
+                    "The MAXLEN argument must be >= 0.",
+                )))
+            }
+        }
+    } else {
+        match StreamIdSpec::parse(&threshold)
+            .ok_or(OpError::MalformedStreamId)
+            .and_then(StreamIdSpec::record_id)
+        {
+            Ok(v) => TrimStrategy::MinId(v),
+            Err(e) => return Err(e.to_message()),
+        }
+    };
+
+    let limit = approximate.then_some(DEFAULT_TRIM_LIMIT);
+    Ok((TrimOptions { strategy, limit }, approximate))
+}
+
+/// Build the trimming option in XADD that trims the same records.
+fn trim_effect(trim: &TrimOptions) -> Vec<String> {
+    let mut effect = match trim.strategy {
+        TrimStrategy::MaxLen(v) => vec!["MAXLEN".to_string(), v.to_string()],
+        TrimStrategy::MinId((time_id, seq_id)) => {
+            vec!["MINID".to_string(), format!("{time_id}-{seq_id}")]
+        }
+    };
+    match trim.limit {
+        Some(limit) => {
+            effect.insert(1, "~".to_string());
+            effect.push("LIMIT".to_string());
+            effect.push(limit.to_string());
+        }
+        None => effect.insert(1, "=".to_string()),
+    }
+    effect
+}
+
 pub(super) async fn handle_xadd_command(
     conn: &mut Conn<'_>,
     mut args: Array,
     storage: &mut Storage,
-) -> ServerResult<()> {
+) -> ServerResult<Vec<Array>> {
     conn.log("run command XADD");
 
     let key = args
-        .pop_front_bulk_string()
+        .pop_front_bulk_string_bytes()
         .ok_or_else(|| ServerError::InvalidArgs {
             cmd: "XADD",
             args: args.clone(),
         })?;
 
-    let stream_id = args
-        .pop_front_bulk_string()
-        .and_then(|id| {
-            if id == "*" {
-                return Some(StreamId::Auto);
+    // XADD key [NOMKSTREAM] [CAP count] [MAXLEN | MINID [= | ~] threshold [LIMIT count]]
+    //   <* | id> field value [field value ...]
+    //
+    // `CAP count` sets a hard cap on the stream length, kept by the stream for all
+    // later additions.
+    let mut max_len = None;
+    let mut nomkstream = false;
+    let mut trim: Option<(TrimOptions, bool)> = None;
+    let mut limit = None;
+    let id = loop {
+        let arg = args
+            .pop_front_bulk_string()
+            .ok_or_else(|| ServerError::InvalidArgs {
+                cmd: "XADD",
+                args: args.clone(),
+            })?;
+        let error = match arg.to_uppercase().as_str() {
+            "NOMKSTREAM" => {
+                nomkstream = true;
+                continue;
             }
-            match id.split_once('-') {
-                Some((raw_time_id, raw_seq_id)) => {
-                    match (raw_time_id.parse::<u64>(), raw_seq_id.parse::<u64>()) {
-                        (Ok(time_id), Ok(seq_id)) => Some(StreamId::new(time_id, seq_id)),
-                        (Ok(time_id), Err(..)) if raw_seq_id == "*" => {
-                            Some(StreamId::PartialAuto(time_id))
-                        }
-                        _ => None,
-                    }
+            "LIMIT" => match args
+                .pop_front_bulk_string()
+                .and_then(|x| x.parse::<usize>().ok())
+            {
+                Some(v) => {
+                    limit = Some(v);
+                    continue;
+                }
+                None => "The LIMIT argument must be >= 0.",
+            },
+            "CAP" => match args
+                .pop_front_bulk_string()
+                .and_then(|x| x.parse::<usize>().ok())
+                .filter(|x| *x > 0)
+            {
+                Some(v) => {
+                    max_len = Some(v);
+                    continue;
                 }
-                None => None,
+                None => "The CAP argument must be > 0.",
+            },
+            "MAXLEN" | "MINID" if trim.is_some() => {
+                "syntax error, MAXLEN and MINID options at the same time are not compatible"
             }
-        })
-        .ok_or_else(|| ServerError::InvalidArgs {
-            cmd: "XADD",
-            args: args.clone(),
-        })?;
+            option @ ("MAXLEN" | "MINID") => match parse_trim_options(option, &mut args) {
+                Ok(v) => {
+                    trim = Some(v);
+                    continue;
+                }
+                Err(e) => {
+                    conn.write_value(e).await?;
+                    return Ok(vec![]);
+                }
+            },
+            _ => break arg,
+        };
+        conn.write_value(Value::SimpleError(SimpleError::with_prefix("ERR", error)))
+            .await?;
+        return Ok(vec![]);
+    };
+    let trim = match (trim, limit) {
+        // 0 means unlimited.
+        (Some((mut options, true)), Some(limit)) => {
+            options.limit = Some(limit).filter(|x| *x > 0);
+            Some(options)
+        }
+        (_, Some(..)) => {
+            conn.write_value(Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                "syntax error, LIMIT cannot be used without the special ~ option",
+            )))
+            .await?;
+            return Ok(vec![]);
+        }
+        (trim, None) => trim.map(|(options, _)| options),
+    };
 
-    let mut values = Array::new_empty();
+    let stream_id = match StreamIdSpec::parse(&id)
+        .ok_or(OpError::MalformedStreamId)
+        .and_then(StreamIdSpec::add_id)
+    {
+        Ok(v) => v,
+        Err(e) => {
+            conn.write_value(e.to_message()).await?;
+            return Ok(vec![]);
+        }
+    };
 
-    while let Some(v) = args.pop_front_bulk_string() {
-        values.push_back(Value::BulkString(BulkString::new(v)));
-    }
+    let values = std::iter::from_fn(|| args.pop_front_bulk_string_bytes())
+        .map(|x| Value::BulkString(BulkString::new(x)))
+        .collect::<Array>();
 
-    if values.is_empty() || values.len() % 2 != 0 {
+    if values.is_empty() || !values.len().is_multiple_of(2) {
         return Err(ServerError::InvalidArgs {
             cmd: "XADD",
             args: args.clone(),
         });
     }
 
-    conn.log(format!("XADD: key={key}, id={stream_id:?}"));
-    let value = match storage.stream_add_value(key, stream_id, values.take().unwrap()) {
-        Ok(v) => Value::BulkString(v.to_bulk_string()),
+    conn.log(format!(
+        "XADD: key={}, id={stream_id:?}, max_len={max_len:?}, trim={trim:?}",
+        String::from_utf8_lossy(&key)
+    ));
+    let mut effects = vec![];
+    let value = match storage.stream_add_value(
+        key.clone(),
+        stream_id,
+        values.value().cloned().unwrap_or_default(),
+        max_len,
+        nomkstream,
+        trim,
+    ) {
+        Ok((StreamId::Value { time_id, seq_id }, feeds, capped_len)) => {
+            // Sync the generated id, records delivered to blocked XREADGROUP tasks
+            // are read on replica right after added.
+            let id = format!("{time_id}-{seq_id}");
+            let mut effect = effect_command([b"XADD".as_slice(), &key]);
+            let mut options = vec![];
+            if let Some(max_len) = max_len {
+                options.push("CAP".to_string());
+                options.push(max_len.to_string());
+            }
+            // Records trimmed by the cap are synced as trimmed to the length left, the
+            // same records are removed as the oldest ones go first.
+            match capped_len {
+                Some(len) => options.extend(["MAXLEN".into(), "=".into(), len.to_string()]),
+                None => options.extend(trim.as_ref().map(trim_effect).unwrap_or_default()),
+            }
+            options.push(id.clone());
+            effect.append(effect_command(options));
+            effect.append(values);
+            effects.push(effect);
+            effects.extend(group_feed_effects(feeds));
+            Value::BulkString(BulkString::new(id))
+        }
+        Ok((v, ..)) => Value::BulkString(v.to_bulk_string()),
+        Err(OpError::KeyAbsent) => Value::BulkString(BulkString::null()),
         Err(e) => e.to_message(),
     };
 
-    conn.write_value(value).await
+    conn.write_value(value).await?;
+    Ok(effects)
 }
@@ -0,0 +1,112 @@
+use serde_redis::{Array, SimpleError, Value};
+
+use crate::{
+    command::{
+        set::syntax_error,
+        xclaim::{claim_effects, claimed_records},
+    },
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{OpError, Storage, StreamId, StreamIdSpec},
+};
+
+/// Default count of records to claim.
+const DEFAULT_COUNT: usize = 100;
+
+pub(super) async fn handle_xautoclaim_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<Vec<Array>> {
+    conn.log("run command XAUTOCLAIM");
+
+    // XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]
+    let (key, group, consumer, min_idle, start) = match (
+        args.pop_front_bulk_string_bytes(),
+        args.pop_front_bulk_string(),
+        args.pop_front_bulk_string(),
+        args.pop_front_bulk_string(),
+        args.pop_front_bulk_string(),
+    ) {
+        (Some(a), Some(b), Some(c), Some(d), Some(e)) => (a, b, c, d, e),
+        _ => {
+            return Err(ServerError::InvalidArgs {
+                cmd: "XAUTOCLAIM",
+                args: args.clone(),
+            })
+        }
+    };
+    let min_idle = match min_idle.parse::<u64>() {
+        Ok(v) => v,
+        Err(..) => {
+            let value = Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                "Invalid min-idle-time argument for XAUTOCLAIM",
+            ));
+            conn.write_value(value).await?;
+            return Ok(vec![]);
+        }
+    };
+    let start = match StreamIdSpec::parse(&start)
+        .ok_or(OpError::MalformedStreamId)
+        .and_then(StreamIdSpec::range_start)
+    {
+        Ok(v) => v,
+        Err(e) => {
+            conn.write_value(e.to_message()).await?;
+            return Ok(vec![]);
+        }
+    };
+
+    let mut count = DEFAULT_COUNT;
+    let mut justid = false;
+    while let Some(option) = args.pop_front_bulk_string() {
+        match option.to_uppercase().as_str() {
+            "COUNT" => {
+                match args
+                    .pop_front_bulk_string()
+                    .and_then(|x| x.parse::<usize>().ok())
+                {
+                    Some(v) if v > 0 => count = v,
+                    _ => {
+                        let value = Value::SimpleError(SimpleError::with_prefix(
+                            "ERR",
+                            "COUNT must be > 0",
+                        ));
+                        conn.write_value(value).await?;
+                        return Ok(vec![]);
+                    }
+                }
+            }
+            "JUSTID" => justid = true,
+            _ => {
+                conn.write_value(syntax_error()).await?;
+                return Ok(vec![]);
+            }
+        }
+    }
+
+    match storage.stream_auto_claim(&key, &group, &consumer, min_idle, start, count, justid) {
+        Ok((next, claimed, deleted)) => {
+            let effects = claim_effects(&key, &group, &consumer, &claimed, &deleted, None);
+            let value = Value::Array(Array::with_values(vec![
+                Value::BulkString(StreamId::new(next.0, next.1).to_bulk_string()),
+                claimed_records(claimed, justid),
+                Value::Array(
+                    deleted
+                        .into_iter()
+                        .map(|(time_id, seq_id)| {
+                            Value::BulkString(StreamId::new(time_id, seq_id).to_bulk_string())
+                        })
+                        .collect(),
+                ),
+            ]));
+            conn.write_value(value).await?;
+            Ok(effects)
+        }
+        Err(e) => {
+            conn.write_value(e.to_message()).await?;
+            Ok(vec![])
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use serde_redis::{Array, SimpleError, Value};
+
+use crate::{
+    command::{
+        effect_command,
+        xgroup::{invalid_stream_id, parse_record_id},
+    },
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{unix_millis, ClaimOptions, ClaimedRecord, RecordId, Storage, StreamId},
+};
+
+fn id_string((time_id, seq_id): RecordId) -> String {
+    format!("{time_id}-{seq_id}")
+}
+
+/// Build the reply of `claimed` records, only ids if `justid` is true.
+pub(super) fn claimed_records(claimed: Vec<ClaimedRecord>, justid: bool) -> Value {
+    Value::Array(
+        claimed
+            .into_iter()
+            .map(|record| {
+                let (time_id, seq_id) = record.id;
+                let id = Value::BulkString(StreamId::new(time_id, seq_id).to_bulk_string());
+                if justid {
+                    id
+                } else {
+                    Value::Array(Array::with_values(vec![
+                        id,
+                        Value::Array(Array::with_values(record.values)),
+                    ]))
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Build the commands that apply the same claiming on replicas.
+///
+/// Each claimed record is claimed again with its delivery time and count, records
+/// removed from the PEL are acknowledged. The consumer is created even if nothing
+/// claimed, so claim the nonexistent id 0-0 in that case.
+pub(super) fn claim_effects(
+    key: &[u8],
+    group: &str,
+    consumer: &str,
+    claimed: &[ClaimedRecord],
+    deleted: &[RecordId],
+    last_id: Option<RecordId>,
+) -> Vec<Array> {
+    let last_id = last_id.map(id_string);
+    let claim = |id: String, extra: Vec<String>| {
+        let mut parts = vec![group.to_string(), consumer.to_string(), "0".to_string(), id];
+        parts.extend(extra);
+        parts.push("JUSTID".to_string());
+        if let Some(last_id) = &last_id {
+            parts.push("LASTID".to_string());
+            parts.push(last_id.clone());
+        }
+        let mut effect = effect_command([b"XCLAIM".as_slice(), key]);
+        effect.append(effect_command(parts));
+        effect
+    };
+
+    let mut effects = claimed
+        .iter()
+        .map(|record| {
+            claim(
+                id_string(record.id),
+                vec![
+                    "TIME".to_string(),
+                    record.delivery_time.to_string(),
+                    "RETRYCOUNT".to_string(),
+                    record.delivery_count.to_string(),
+                    "FORCE".to_string(),
+                ],
+            )
+        })
+        .collect::<Vec<_>>();
+    if effects.is_empty() {
+        effects.push(claim(id_string((0, 0)), vec![]));
+    }
+    if !deleted.is_empty() {
+        let mut effect = effect_command([b"XACK".as_slice(), key, group.as_bytes()]);
+        effect.append(effect_command(deleted.iter().copied().map(id_string)));
+        effects.push(effect);
+    }
+    effects
+}
+
+fn invalid_argument(name: &str) -> Value {
+    Value::SimpleError(SimpleError::with_prefix(
+        "ERR",
+        format!("Invalid {name} argument for XCLAIM"),
+    ))
+}
+
+pub(super) async fn handle_xclaim_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<Vec<Array>> {
+    conn.log("run command XCLAIM");
+
+    // XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms]
+    //   [TIME unix-time-milliseconds] [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID lastid]
+    let (key, group, consumer, min_idle, first_id) = match (
+        args.pop_front_bulk_string_bytes(),
+        args.pop_front_bulk_string(),
+        args.pop_front_bulk_string(),
+        args.pop_front_bulk_string(),
+        args.pop_front_bulk_string(),
+    ) {
+        (Some(a), Some(b), Some(c), Some(d), Some(e)) => (a, b, c, d, e),
+        _ => {
+            return Err(ServerError::InvalidArgs {
+                cmd: "XCLAIM",
+                args: args.clone(),
+            })
+        }
+    };
+    let min_idle = match min_idle.parse::<u64>() {
+        Ok(v) => v,
+        Err(..) => {
+            conn.write_value(invalid_argument("min-idle-time")).await?;
+            return Ok(vec![]);
+        }
+    };
+    let mut ids = match parse_record_id(&first_id) {
+        Some(v) => vec![v],
+        None => {
+            conn.write_value(invalid_stream_id()).await?;
+            return Ok(vec![]);
+        }
+    };
+
+    // Ids end at the first argument not an id.
+    let mut option = None;
+    while let Some(arg) = args.pop_front_bulk_string() {
+        match parse_record_id(&arg) {
+            Some(v) => ids.push(v),
+            None => {
+                option = Some(arg);
+                break;
+            }
+        }
+    }
+
+    let mut options = ClaimOptions::default();
+    while let Some(name) = option.take().or_else(|| args.pop_front_bulk_string()) {
+        let name = name.to_uppercase();
+        match name.as_str() {
+            "FORCE" => options.force = true,
+            "JUSTID" => options.justid = true,
+            "IDLE" | "TIME" | "RETRYCOUNT" => {
+                let value = match args
+                    .pop_front_bulk_string()
+                    .and_then(|x| x.parse::<u64>().ok())
+                {
+                    Some(v) => v,
+                    None => {
+                        conn.write_value(invalid_argument(&format!("{name} option")))
+                            .await?;
+                        return Ok(vec![]);
+                    }
+                };
+                match name.as_str() {
+                    "IDLE" => options.delivery_time = Some(unix_millis().saturating_sub(value)),
+                    "TIME" => options.delivery_time = Some(value),
+                    _ => options.retry_count = Some(value),
+                }
+            }
+            "LASTID" => {
+                match args
+                    .pop_front_bulk_string()
+                    .and_then(|x| parse_record_id(&x))
+                {
+                    Some(v) => options.last_id = Some(v),
+                    None => {
+                        conn.write_value(invalid_stream_id()).await?;
+                        return Ok(vec![]);
+                    }
+                }
+            }
+            _ => {
+                let value = Value::SimpleError(SimpleError::with_prefix(
+                    "ERR",
+                    format!("Unrecognized XCLAIM option '{name}'"),
+                ));
+                conn.write_value(value).await?;
+                return Ok(vec![]);
+            }
+        }
+    }
+
+    match storage.stream_claim(&key, &group, &consumer, min_idle, &ids, &options) {
+        Ok((claimed, deleted)) => {
+            let effects =
+                claim_effects(&key, &group, &consumer, &claimed, &deleted, options.last_id);
+            conn.write_value(claimed_records(claimed, options.justid))
+                .await?;
+            Ok(effects)
+        }
+        Err(e) => {
+            conn.write_value(e.to_message()).await?;
+            Ok(vec![])
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use serde_redis::{Array, Integer, SimpleError, SimpleString, Value};
+
+use crate::{
+    command::set::syntax_error,
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{OpError, RecordId, Storage, StreamId, StreamIdSpec},
+};
+
+/// Parse complete stream id `<time>-<seq>`, or `<time>` with sequence 0.
+pub(super) fn parse_record_id(value: &str) -> Option<RecordId> {
+    StreamIdSpec::parse(value)?.record_id().ok()
+}
+
+pub(super) fn invalid_stream_id() -> Value {
+    OpError::MalformedStreamId.to_message()
+}
+
+pub(super) async fn handle_xgroup_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command XGROUP");
+    let subcommand = args.pop_front_bulk_string();
+    let key = args.pop_front_bulk_string_bytes();
+    let group = args.pop_front_bulk_string();
+    let (subcommand, key, group) = match (subcommand, key, group) {
+        (Some(a), Some(b), Some(c)) => (a, b, c),
+        _ => {
+            return Err(ServerError::InvalidArgs {
+                cmd: "XGROUP",
+                args: args.clone(),
+            })
+        }
+    };
+
+    let value = match subcommand.to_uppercase().as_str() {
+        "CREATE" => {
+            // XGROUP CREATE key group <id | $> [MKSTREAM]
+            let start = match args.pop_front_bulk_string() {
+                Some(v) if v == "$" => StreamId::Auto,
+                Some(v) => match parse_record_id(&v) {
+                    Some((time_id, seq_id)) => StreamId::new(time_id, seq_id),
+                    None => return conn.write_value(invalid_stream_id()).await,
+                },
+                None => return conn.write_value(syntax_error()).await,
+            };
+            let mkstream = match args.pop_front_bulk_string() {
+                None => false,
+                Some(v) if v.eq_ignore_ascii_case("MKSTREAM") && args.is_empty() => true,
+                Some(..) => return conn.write_value(syntax_error()).await,
+            };
+            match storage.stream_create_group(&key, group, start, mkstream) {
+                Ok(()) => Value::SimpleString(SimpleString::new("OK")),
+                Err(OpError::NoSuchKey) => Value::SimpleError(SimpleError::with_prefix(
+                    "ERR",
+                    "The XGROUP subcommand requires the key to exist. \
+                     Note that for CREATE you may want to use the MKSTREAM option \
+                     to create an empty stream automatically.",
+                )),
+                Err(e) => e.to_message(),
+            }
+        }
+        "DESTROY" => {
+            // XGROUP DESTROY key group
+            match storage.stream_destroy_group(&key, &group) {
+                Ok(v) => Value::Integer(Integer::new(v as i64)),
+                Err(e) => e.to_message(),
+            }
+        }
+        v => Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            format!("unknown subcommand '{v}'"),
+        )),
+    };
+    conn.write_value(value).await
+}
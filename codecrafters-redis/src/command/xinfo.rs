@@ -0,0 +1,56 @@
+use serde_redis::{Array, SimpleError, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+pub(super) async fn handle_xinfo_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command XINFO");
+    let subcommand = args
+        .pop_front_bulk_string()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "XINFO",
+            args: args.clone(),
+        })?;
+
+    let value = match subcommand.to_uppercase().as_str() {
+        "STREAM" => {
+            // XINFO STREAM key
+            let key =
+                args.pop_front_bulk_string_bytes()
+                    .ok_or_else(|| ServerError::InvalidArgs {
+                        cmd: "XINFO",
+                        args: args.clone(),
+                    })?;
+            match storage.stream_info(&key) {
+                Ok(v) => v,
+                Err(e) => e.to_message(),
+            }
+        }
+        "GROUPS" => {
+            // XINFO GROUPS key
+            let key =
+                args.pop_front_bulk_string_bytes()
+                    .ok_or_else(|| ServerError::InvalidArgs {
+                        cmd: "XINFO",
+                        args: args.clone(),
+                    })?;
+            match storage.stream_groups_info(&key) {
+                Ok(v) => Value::Array(v),
+                Err(e) => e.to_message(),
+            }
+        }
+        v => Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            format!("unknown subcommand '{v}'"),
+        )),
+    };
+
+    conn.write_value(value).await
+}
@@ -0,0 +1,130 @@
+use serde_redis::{Array, BulkString, Integer, SimpleError, Value};
+
+use crate::{
+    command::set::syntax_error,
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{OpError, RecordId, Storage, StreamId, StreamIdSpec},
+};
+
+fn id_string((time_id, seq_id): RecordId) -> Value {
+    Value::BulkString(StreamId::new(time_id, seq_id).to_bulk_string())
+}
+
+fn not_integer() -> Value {
+    Value::SimpleError(SimpleError::with_prefix(
+        "ERR",
+        "value is not an integer or out of range",
+    ))
+}
+
+pub(super) async fn handle_xpending_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command XPENDING");
+
+    // XPENDING key group [[IDLE min-idle-time] start end count [consumer]]
+    let key = args.pop_front_bulk_string_bytes();
+    let group = args.pop_front_bulk_string();
+    let (key, group) = match (key, group) {
+        (Some(a), Some(b)) => (a, b),
+        _ => {
+            return Err(ServerError::InvalidArgs {
+                cmd: "XPENDING",
+                args: args.clone(),
+            })
+        }
+    };
+
+    if args.is_empty() {
+        let value = match storage.stream_pending_summary(&key, &group) {
+            Ok((0, _, _)) => Value::Array(Array::with_values(vec![
+                Value::Integer(Integer::new(0)),
+                Value::BulkString(BulkString::null()),
+                Value::BulkString(BulkString::null()),
+                Value::Array(Array::null()),
+            ])),
+            Ok((count, bounds, consumers)) => {
+                let (first, last) = bounds.unwrap_or_default();
+                let consumers = consumers
+                    .into_iter()
+                    .map(|(name, count)| {
+                        Value::Array(Array::with_values(vec![
+                            Value::BulkString(BulkString::new(name)),
+                            Value::BulkString(BulkString::new(count.to_string())),
+                        ]))
+                    })
+                    .collect::<Array>();
+                Value::Array(Array::with_values(vec![
+                    Value::Integer(Integer::new(count as i64)),
+                    id_string(first),
+                    id_string(last),
+                    Value::Array(consumers),
+                ]))
+            }
+            Err(e) => e.to_message(),
+        };
+        return conn.write_value(value).await;
+    }
+
+    let mut min_idle = 0;
+    let mut start = args.pop_front_bulk_string().unwrap_or_default();
+    if start.eq_ignore_ascii_case("IDLE") {
+        min_idle = match args
+            .pop_front_bulk_string()
+            .and_then(|x| x.parse::<u64>().ok())
+        {
+            Some(v) => v,
+            None => return conn.write_value(not_integer()).await,
+        };
+        start = args.pop_front_bulk_string().unwrap_or_default();
+    }
+    let (end, count) = match (args.pop_front_bulk_string(), args.pop_front_bulk_string()) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return conn.write_value(syntax_error()).await,
+    };
+    let consumer = args.pop_front_bulk_string();
+    if !args.is_empty() {
+        return conn.write_value(syntax_error()).await;
+    }
+    let bounds = StreamIdSpec::parse(&start)
+        .zip(StreamIdSpec::parse(&end))
+        .ok_or(OpError::MalformedStreamId)
+        .and_then(|(start, end)| Ok((start.range_start()?, end.range_end()?)));
+    let (start, end) = match bounds {
+        Ok(v) => v,
+        Err(e) => return conn.write_value(e.to_message()).await,
+    };
+    let count = match count.parse::<i64>() {
+        Ok(v) => v.max(0) as usize,
+        Err(..) => return conn.write_value(not_integer()).await,
+    };
+
+    let value = match storage.stream_pending_range(
+        &key,
+        &group,
+        start,
+        end,
+        count,
+        min_idle,
+        consumer.as_deref(),
+    ) {
+        Ok(records) => Value::Array(
+            records
+                .into_iter()
+                .map(|(id, pending, idle)| {
+                    Value::Array(Array::with_values(vec![
+                        id_string(id),
+                        Value::BulkString(BulkString::new(pending.consumer)),
+                        Value::Integer(Integer::new(idle as i64)),
+                        Value::Integer(Integer::new(pending.delivery_count as i64)),
+                    ]))
+                })
+                .collect(),
+        ),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
@@ -1,73 +1,73 @@
-use serde_redis::Array;
+use serde_redis::{Array, SimpleError, Value};
 
 use crate::{
+    command::set::syntax_error,
     conn::Conn,
     error::{ServerError, ServerResult},
-    storage::{Storage, StreamId},
+    storage::{OpError, Storage, StreamIdSpec},
 };
 
-fn parse_stream_id(value: String) -> Option<StreamId> {
-    match value.split_once('-') {
-        Some((raw_time_id, raw_seq_id)) => {
-            match (raw_time_id.parse::<u64>(), raw_seq_id.parse::<u64>()) {
-                (Ok(time_id), Ok(seq_id)) => Some(StreamId::new(time_id, seq_id)),
-                (Ok(time_id), Err(..)) if raw_seq_id == "*" => Some(StreamId::PartialAuto(time_id)),
-                _ => None,
-            }
-        }
-        None => value
-            .parse::<u64>()
-            .ok()
-            .map(|id| StreamId::PartialAuto(id)),
-    }
-}
-
+/// Handle XRANGE, or XREVRANGE if `rev` is true.
 pub(super) async fn handle_xrange_command(
     conn: &mut Conn<'_>,
     mut args: Array,
     storage: &mut Storage,
+    rev: bool,
 ) -> ServerResult<()> {
-    conn.log("run command XRANGE");
-    let key = args
-        .pop_front_bulk_string()
-        .ok_or_else(|| ServerError::InvalidArgs {
-            cmd: "XRANGE",
-            args: args.clone(),
-        })?;
-    let start = args
-        .pop_front_bulk_string()
-        .and_then(|s| {
-            if s == "-" {
-                Some(StreamId::Auto)
-            } else {
-                parse_stream_id(s)
-            }
-        })
-        .ok_or_else(|| ServerError::InvalidArgs {
-            cmd: "XRANGE",
-            args: args.clone(),
-        })?;
+    let cmd = if rev { "XREVRANGE" } else { "XRANGE" };
+    conn.log(format!("run command {cmd}"));
 
-    let end = args
-        .pop_front_bulk_string()
-        .and_then(|s| {
-            if s == "+" {
-                Some(StreamId::Auto)
-            } else {
-                parse_stream_id(s)
-            }
-        })
-        .ok_or_else(|| ServerError::InvalidArgs {
-            cmd: "XRANGE",
-            args: args.clone(),
-        })?;
+    // XRANGE key start end [COUNT count]
+    // XREVRANGE key end start [COUNT count]
+    let (key, first, second) = match (
+        args.pop_front_bulk_string_bytes(),
+        args.pop_front_bulk_string(),
+        args.pop_front_bulk_string(),
+    ) {
+        (Some(a), Some(b), Some(c)) => (a, b, c),
+        _ => {
+            return Err(ServerError::InvalidArgs {
+                cmd,
+                args: args.clone(),
+            })
+        }
+    };
+    let (start, end) = if rev {
+        (second, first)
+    } else {
+        (first, second)
+    };
+    let bounds = StreamIdSpec::parse(&start)
+        .zip(StreamIdSpec::parse(&end))
+        .ok_or(OpError::MalformedStreamId)
+        .and_then(|(start, end)| Ok((start.range_start()?, end.range_end()?)));
+    let (start, end) = match bounds {
+        Ok(v) => v,
+        Err(e) => return conn.write_value(e.to_message()).await,
+    };
 
-    conn.log(format!("XRANGE {start:?}..={end:?}"));
+    let count = match args.pop_front_bulk_string() {
+        None => None,
+        Some(v) if v.eq_ignore_ascii_case("COUNT") && args.len() == 1 => {
+            match args.pop_front_bulk_string().map(|x| x.parse::<i64>()) {
+                Some(Ok(v)) => Some(v.max(0) as usize),
+                _ => {
+                    let value = Value::SimpleError(SimpleError::with_prefix(
+                        "ERR",
+                        "value is not an integer or out of range",
+                    ));
+                    return conn.write_value(value).await;
+                }
+            }
+        }
+        Some(..) => return conn.write_value(syntax_error()).await,
+    };
 
-    let value = storage
-        .stream_get_range(key, start, end)
-        .map_err(|x| x.to_message())
-        .unwrap();
+    conn.log(format!("{cmd} {start:?}..={end:?} count={count:?}"));
 
+    let value = match storage.stream_get_range(&key, start, end, count, rev) {
+        Ok(v) => v,
+        Err(e) => e.to_message(),
+    };
     conn.write_value(value).await
 }
@@ -4,173 +4,158 @@ use serde_redis::{Array, BulkString, SimpleError, Value};
 use tokio::sync::oneshot;
 
 use crate::{
+    blocking::{wait_fed, BlockKind, Unblock},
+    command::set::syntax_error,
     conn::Conn,
     error::{ServerError, ServerResult},
-    storage::{Storage, StreamId, XreadBlockedTarget, XreadBlockedTask},
+    state::ServerState,
+    storage::{
+        OpError, OpResult, RecordId, Storage, StreamIdSpec, XreadBlockedTarget, XreadBlockedTask,
+    },
 };
 
-fn parse_stream_id(value: String) -> Option<StreamId> {
-    match value.split_once('-') {
-        Some((raw_time_id, raw_seq_id)) => {
-            match (raw_time_id.parse::<u64>(), raw_seq_id.parse::<u64>()) {
-                (Ok(time_id), Ok(seq_id)) => Some(StreamId::new(time_id, seq_id + 1)),
-                _ => None,
-            }
+/// Read at most `count` records after id in each of `queries`.
+///
+/// Streams have no record after the id are omitted.
+fn read_streams(
+    storage: &Storage,
+    queries: &[(Vec<u8>, RecordId)],
+    count: Option<usize>,
+) -> OpResult<Vec<Value>> {
+    let mut result = vec![];
+    for (key, (time_id, seq_id)) in queries {
+        let start = StreamIdSpec::Exclusive(*time_id, Some(*seq_id)).range_start()?;
+        let v = storage.stream_get_range(key, start, (u64::MAX, u64::MAX), count, false)?;
+        if matches!(&v, Value::Array(arr) if arr.is_empty()) {
+            continue;
         }
-        None => None,
+        result.push(Value::Array(Array::with_values(vec![
+            Value::BulkString(BulkString::new(key.as_slice())),
+            v,
+        ])));
     }
+    Ok(result)
 }
 
 pub(super) async fn handle_xread_command(
     conn: &mut Conn<'_>,
     mut args: Array,
-    storage: &mut Storage,
+    state: &mut ServerState,
 ) -> ServerResult<()> {
     conn.log("run command XREAD");
-    let subcommand = args
-        .pop_front_bulk_string()
-        .ok_or_else(|| ServerError::InvalidArgs {
-            cmd: "XREAD",
-            args: args.clone(),
-        })?;
 
+    // XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]
+    let mut count = None;
     let mut block_duration = None;
-
-    if subcommand == "block" {
-        // Run in block mode.
-        let d = args
+    loop {
+        let option = args
             .pop_front_bulk_string()
-            .and_then(|x| x.parse::<u64>().ok())
             .ok_or_else(|| ServerError::InvalidArgs {
                 cmd: "XREAD",
                 args: args.clone(),
-            })?;
-        block_duration = Some(d);
-
-        // Read the "streams" argument after "XREAD".
-        let _stream = args
-            .pop_front_bulk_string()
-            .ok_or_else(|| ServerError::InvalidArgs {
-                cmd: "XREAD",
-                args: args.clone(),
-            })?;
-    }
-
-    let mut stream_names = vec![];
-    let mut stream_ids = vec![];
-
-    while !args.is_empty() {
-        let s = args
-            .pop_front_bulk_string()
-            .ok_or_else(|| ServerError::InvalidArgs {
-                cmd: "XREAD",
-                args: args.clone(),
-            })?;
-
-        // Simple distinguish stream names and stream keys by the delimiter.
-        if s.contains("-") {
-            let id = parse_stream_id(s).ok_or_else(|| ServerError::InvalidArgs {
-                cmd: "XREAD",
-                args: args.clone(),
-            })?;
-            stream_ids.push(id);
-        } else if s == "$" {
-            // Use auto to represent only waiting for new entries for BLOCKING xread commands.
-            stream_ids.push(StreamId::Auto);
-        } else {
-            stream_names.push(s);
+            })?
+            .to_uppercase();
+        match option.as_str() {
+            "COUNT" | "BLOCK" => {
+                let value = match args
+                    .pop_front_bulk_string()
+                    .and_then(|x| x.parse::<u64>().ok())
+                {
+                    Some(v) => v,
+                    None => {
+                        let value = Value::SimpleError(SimpleError::with_prefix(
+                            "ERR",
+                            "value is not an integer or out of range",
+                        ));
+                        return conn.write_value(value).await;
+                    }
+                };
+                if option == "COUNT" {
+                    count = Some(value as usize).filter(|x| *x > 0);
+                } else {
+                    block_duration = Some(value);
+                }
+            }
+            "STREAMS" => break,
+            _ => return conn.write_value(syntax_error()).await,
         }
     }
 
-    if stream_ids.len() != stream_names.len() {
-        let content = Value::SimpleError(SimpleError::with_prefix(
-            "EARGS",
-            "stream name and stream keys have different count",
+    let rest = args.take().unwrap_or_default();
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        let value = Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            "Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.",
         ));
-        return conn.write_value(content).await;
+        return conn.write_value(value).await;
+    }
+    let mut rest = Array::with_values(rest);
+    let mut stream_names = vec![];
+    for _ in 0..rest.len() / 2 {
+        stream_names.push(rest.pop_front_bulk_string_bytes().unwrap_or_default());
     }
 
-    let end = StreamId::Auto;
-
-    let queries = stream_names.into_iter().zip(stream_ids).collect::<Vec<_>>();
-
-    let mut query_result = vec![];
-
-    match block_duration {
-        Some(v) => {
-            // Block forever till notify.
-            let block_targets = queries
-                .iter()
-                .map(|q| match q.1 {
-                    StreamId::Value { time_id, seq_id } => {
-                        XreadBlockedTarget::with_id(q.0.to_owned(), time_id, seq_id)
-                    }
-                    StreamId::Auto => XreadBlockedTarget::with_new_entry(q.0.to_owned()),
-                    StreamId::PartialAuto(_) => {
-                        unreachable!("partial auto id shall not happen here")
-                    }
-                })
-                .collect::<Vec<_>>();
-            let (sender, recver) = oneshot::channel::<(Vec<String>, Value)>();
-            let block_task = XreadBlockedTask::new(block_targets, sender);
-            storage.xread_add_block_task(block_task);
-
-            let r = if v > 0 {
-                // Wait for some time.
-                match tokio::time::timeout(Duration::from_millis(v), async { recver.await }).await {
-                    Ok(v) => Some(v),
-                    Err(..) => {
-                        // Timeout
-                        None
-                    }
-                }
-            } else {
-                Some(recver.await)
-            };
-
-            match r {
-                Some(Ok((keys, value))) => {
-                    conn.log(format!(
-                        "XREAD [block forever] received value for keys: {keys:?} = {value:?}"
-                    ));
-                    for key in keys.into_iter() {
-                        let arr = Value::Array(Array::with_values(vec![
-                            Value::BulkString(BulkString::new(key)),
-                            Value::Array(Array::with_values(vec![value.clone()])),
-                        ]));
-                        query_result.push(arr);
-                    }
-                }
-                Some(Err(e)) => {
-                    conn.log(format!(
-                        "failed to receive the result for forever blocking task: {e:?}"
-                    ));
-                    return Ok(());
-                }
-                None => {
-                    // No value received.
-                }
+    // Read records after each id, `$` is the last id in stream when blocking starts.
+    let mut queries = vec![];
+    let mut block_targets = vec![];
+    for key in stream_names {
+        let id = rest.pop_front_bulk_string().unwrap_or_default();
+        let after = match StreamIdSpec::parse(&id).ok_or(OpError::MalformedStreamId) {
+            Ok(StreamIdSpec::Last) => {
+                block_targets.push(XreadBlockedTarget::with_new_entry(key.clone()));
+                state.storage().stream_last_id(&key)
             }
+            Ok(v) => v.record_id().and_then(|(time_id, seq_id)| {
+                let start = StreamIdSpec::Exclusive(time_id, Some(seq_id)).range_start()?;
+                block_targets.push(XreadBlockedTarget::with_id(key.clone(), start.0, start.1));
+                Ok((time_id, seq_id))
+            }),
+            Err(e) => Err(e),
+        };
+        match after {
+            Ok(v) => queries.push((key, v)),
+            Err(e) => return conn.write_value(e.to_message()).await,
         }
-        _ => {
-            for query in queries {
-                conn.log(format!("XREAD key={}, {:?}..={:?}", query.0, query.1, end));
-                let v = storage
-                    .stream_get_range(query.0.clone(), query.1, end.clone())
-                    .map_err(|x| x.to_message())
-                    .unwrap();
+    }
 
-                if let Value::Array(arr) = &v {
-                    if arr.is_empty() {
-                        continue;
-                    }
-                }
+    let mut query_result = match read_streams(state.storage(), &queries, count) {
+        Ok(v) => v,
+        Err(e) => return conn.write_value(e.to_message()).await,
+    };
 
-                let arr = Value::Array(Array::with_values(vec![
-                    Value::BulkString(BulkString::new(query.0)),
-                    v,
-                ]));
-                query_result.push(arr);
+    if let (true, Some(v)) = (query_result.is_empty(), block_duration) {
+        let (sender, recver) = oneshot::channel::<(Vec<Vec<u8>>, Value)>();
+        let block_task = XreadBlockedTask::new(block_targets, sender);
+        let _task = state
+            .storage_mut()
+            .xread_add_block_task(conn.id, block_task);
+
+        let timeout = Some(v).filter(|x| *x > 0).map(Duration::from_millis);
+        let wait = wait_fed(recver, timeout);
+        let r = match state.blocking().block_on(conn, BlockKind::Keys, wait).await {
+            Ok(v) => v,
+            Err(Unblock::Timeout) => None,
+            Err(Unblock::Error(e)) => return conn.write_value(e).await,
+        };
+
+        match r {
+            Some((keys, value)) => {
+                conn.log(format!(
+                    "XREAD [block] received value for keys: {:?} = {value:?}",
+                    keys.iter()
+                        .map(|x| String::from_utf8_lossy(x))
+                        .collect::<Vec<_>>()
+                ));
+                for key in keys.into_iter() {
+                    let arr = Value::Array(Array::with_values(vec![
+                        Value::BulkString(BulkString::new(key)),
+                        Value::Array(Array::with_values(vec![value.clone()])),
+                    ]));
+                    query_result.push(arr);
+                }
+            }
+            None => {
+                // No value received.
             }
         }
     }
@@ -0,0 +1,273 @@
+use std::time::Duration;
+
+use serde_redis::{Array, BulkString, SimpleError, Value};
+
+use crate::{
+    blocking::{wait_fed, BlockKind, Unblock},
+    command::{
+        effect_command,
+        set::syntax_error,
+        xgroup::{invalid_stream_id, parse_record_id},
+    },
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    state::ServerState,
+    storage::{
+        GroupRecord, OpResult, RecordId, Storage, StreamGroupFeed, StreamId, XreadGroupBlockedTask,
+    },
+};
+
+/// Build the reply of `records` read from stream `key`.
+fn stream_records(key: &[u8], records: Vec<GroupRecord>) -> Value {
+    let records = records
+        .into_iter()
+        .map(|((time_id, seq_id), values)| {
+            let values = match values {
+                Some(v) => Value::Array(Array::with_values(v)),
+                None => Value::BulkString(BulkString::null()),
+            };
+            Value::Array(Array::with_values(vec![
+                Value::BulkString(StreamId::new(time_id, seq_id).to_bulk_string()),
+                values,
+            ]))
+        })
+        .collect::<Array>();
+    Value::Array(Array::with_values(vec![
+        Value::BulkString(BulkString::new(key)),
+        Value::Array(records),
+    ]))
+}
+
+/// Read records of all `queries` in consumer group.
+///
+/// Each query is the key of stream and the id to read after, `None` for `>`.
+///
+/// Streams have no new record are omitted.
+fn read_group(
+    storage: &Storage,
+    group: &str,
+    consumer: &str,
+    queries: &[(Vec<u8>, Option<RecordId>)],
+    count: Option<usize>,
+    noack: bool,
+) -> OpResult<Vec<Value>> {
+    let mut result = vec![];
+    for (key, after) in queries {
+        let records = storage.stream_read_group(key, group, consumer, *after, count, noack)?;
+        if after.is_none() && records.is_empty() {
+            continue;
+        }
+        result.push(stream_records(key, records));
+    }
+    Ok(result)
+}
+
+/// Build the XREADGROUP command that reads the same records without blocking.
+fn group_read_effect<K: AsRef<[u8]>, T: AsRef<str>>(
+    group: &str,
+    consumer: &str,
+    count: Option<usize>,
+    noack: bool,
+    keys: &[K],
+    ids: &[T],
+) -> Array {
+    let mut effect = vec!["XREADGROUP", "GROUP", group, consumer];
+    let count = count.map(|x| x.to_string());
+    if let Some(count) = &count {
+        effect.push("COUNT");
+        effect.push(count);
+    }
+    if noack {
+        effect.push("NOACK");
+    }
+    effect.push("STREAMS");
+    let mut effect = effect_command(effect);
+    effect.append(effect_command(keys.iter().map(|x| x.as_ref())));
+    effect.append(effect_command(ids.iter().map(|x| x.as_ref())));
+    effect
+}
+
+/// Build the commands that deliver the records to blocked XREADGROUP tasks.
+pub(super) fn group_feed_effects(feeds: Vec<StreamGroupFeed>) -> Vec<Array> {
+    feeds
+        .into_iter()
+        .map(|feed| {
+            group_read_effect(
+                &feed.group,
+                &feed.consumer,
+                feed.count,
+                feed.noack,
+                &[feed.key],
+                &[">"],
+            )
+        })
+        .collect()
+}
+
+pub(super) async fn handle_xreadgroup_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    state: &mut ServerState,
+) -> ServerResult<Vec<Array>> {
+    conn.log("run command XREADGROUP");
+
+    // XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK]
+    //   STREAMS key [key ...] id [id ...]
+    let (group, consumer) = match (
+        args.pop_front_bulk_string(),
+        args.pop_front_bulk_string(),
+        args.pop_front_bulk_string(),
+    ) {
+        (Some(option), Some(group), Some(consumer)) if option.eq_ignore_ascii_case("GROUP") => {
+            (group, consumer)
+        }
+        _ => {
+            return Err(ServerError::InvalidArgs {
+                cmd: "XREADGROUP",
+                args: args.clone(),
+            })
+        }
+    };
+
+    let mut count = None;
+    let mut block = None;
+    let mut noack = false;
+    loop {
+        let option = match args.pop_front_bulk_string() {
+            Some(v) => v.to_uppercase(),
+            None => {
+                conn.write_value(syntax_error()).await?;
+                return Ok(vec![]);
+            }
+        };
+        match option.as_str() {
+            "COUNT" | "BLOCK" => {
+                let value = match args
+                    .pop_front_bulk_string()
+                    .and_then(|x| x.parse::<u64>().ok())
+                {
+                    Some(v) => v,
+                    None => {
+                        let value = Value::SimpleError(SimpleError::with_prefix(
+                            "ERR",
+                            "value is not an integer or out of range",
+                        ));
+                        conn.write_value(value).await?;
+                        return Ok(vec![]);
+                    }
+                };
+                if option == "COUNT" {
+                    count = Some(value as usize).filter(|x| *x > 0);
+                } else {
+                    block = Some(value);
+                }
+            }
+            "NOACK" => noack = true,
+            "STREAMS" => break,
+            _ => {
+                conn.write_value(syntax_error()).await?;
+                return Ok(vec![]);
+            }
+        }
+    }
+
+    let rest = args.take().unwrap_or_default();
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        let value = Value::SimpleError(SimpleError::with_prefix(
+            "ERR",
+            "Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified.",
+        ));
+        conn.write_value(value).await?;
+        return Ok(vec![]);
+    }
+    let mut rest = Array::with_values(rest);
+    let mut keys = vec![];
+    let mut ids = vec![];
+    for _ in 0..rest.len() / 2 {
+        keys.push(rest.pop_front_bulk_string_bytes().unwrap_or_default());
+    }
+    while let Some(id) = rest.pop_front_bulk_string() {
+        ids.push(id);
+    }
+    let mut queries = vec![];
+    for (key, id) in keys.iter().zip(ids.iter()) {
+        let after = if id == ">" {
+            None
+        } else {
+            match parse_record_id(id) {
+                Some(v) => Some(v),
+                None => {
+                    conn.write_value(invalid_stream_id()).await?;
+                    return Ok(vec![]);
+                }
+            }
+        };
+        queries.push((key.clone(), after));
+    }
+
+    let mut result = match read_group(state.storage(), &group, &consumer, &queries, count, noack) {
+        Ok(v) => v,
+        Err(e) => {
+            conn.write_value(e.to_message()).await?;
+            return Ok(vec![]);
+        }
+    };
+
+    // Block only when waiting for new records.
+    let new_keys = queries
+        .iter()
+        .filter(|(_, after)| after.is_none())
+        .map(|(key, _)| key.clone())
+        .collect::<Vec<_>>();
+    let blocking = result.is_empty() && !new_keys.is_empty() && block.is_some();
+    let mut unblocked = None;
+    if let (true, Some(block)) = (blocking, block) {
+        // Records are delivered by XADD, which also syncs the delivery.
+        let (task, recver) =
+            XreadGroupBlockedTask::new(new_keys, group.clone(), consumer.clone(), count, noack);
+        let _task = state.storage_mut().xreadgroup_add_block_task(conn.id, task);
+        conn.log(format!(
+            "XREADGROUP: no new record, blocking connection for {block} milliseconds"
+        ));
+        let timeout = Some(block).filter(|x| *x > 0).map(Duration::from_millis);
+        let wait = wait_fed(recver, timeout);
+        let fed = match state
+            .blocking()
+            .block_on(conn, BlockKind::StreamGroup, wait)
+            .await
+        {
+            Ok(v) => v,
+            Err(Unblock::Timeout) => None,
+            Err(Unblock::Error(e)) => {
+                unblocked = Some(e);
+                None
+            }
+        };
+        if let Some((key, records)) = fed {
+            result.push(stream_records(&key, records));
+        }
+    }
+
+    let value = if let Some(e) = unblocked {
+        e
+    } else if result.is_empty() {
+        Value::Array(Array::null())
+    } else {
+        Value::Array(Array::with_values(result))
+    };
+    conn.write_value(value).await?;
+
+    // Sync the read without blocking, replica delivers the same records as the
+    // records added before are synced first.
+    //
+    // When blocked, only the consumer is created here as records delivered later are
+    // synced by XADD, read pending records instead of new records.
+    if blocking {
+        ids.iter_mut()
+            .filter(|x| *x == ">")
+            .for_each(|x| *x = "0".to_string());
+    }
+    Ok(vec![group_read_effect(
+        &group, &consumer, count, noack, &keys, &ids,
+    )])
+}
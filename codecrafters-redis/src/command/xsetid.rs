@@ -0,0 +1,69 @@
+use serde_redis::{Array, SimpleError, SimpleString, Value};
+
+use crate::{
+    command::{
+        set::syntax_error,
+        xgroup::{invalid_stream_id, parse_record_id},
+    },
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::Storage,
+};
+
+pub(super) async fn handle_xsetid_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<()> {
+    conn.log("run command XSETID");
+
+    // XSETID key last-id [ENTRIESADDED entries-added] [MAXDELETEDID max-deleted-id]
+    let (key, last_id) = match (
+        args.pop_front_bulk_string_bytes(),
+        args.pop_front_bulk_string(),
+    ) {
+        (Some(a), Some(b)) => (a, b),
+        _ => {
+            return Err(ServerError::InvalidArgs {
+                cmd: "XSETID",
+                args: args.clone(),
+            })
+        }
+    };
+    let last_id = match parse_record_id(&last_id) {
+        Some(v) => v,
+        None => return conn.write_value(invalid_stream_id()).await,
+    };
+
+    let mut entries_added = None;
+    let mut max_deleted_id = None;
+    while let Some(option) = args.pop_front_bulk_string() {
+        let value = match args.pop_front_bulk_string() {
+            Some(v) => v,
+            None => return conn.write_value(syntax_error()).await,
+        };
+        match option.to_uppercase().as_str() {
+            "ENTRIESADDED" => match value.parse::<u64>() {
+                Ok(v) => entries_added = Some(v),
+                Err(..) => {
+                    let value = Value::SimpleError(SimpleError::with_prefix(
+                        "ERR",
+                        "entries_added must be positive",
+                    ));
+                    return conn.write_value(value).await;
+                }
+            },
+            "MAXDELETEDID" => match parse_record_id(&value) {
+                Some(v) => max_deleted_id = Some(v),
+                None => return conn.write_value(invalid_stream_id()).await,
+            },
+            _ => return conn.write_value(syntax_error()).await,
+        }
+    }
+
+    let value = match storage.stream_set_id(&key, last_id, entries_added, max_deleted_id) {
+        Ok(()) => Value::SimpleString(SimpleString::new("OK")),
+        Err(e) => e.to_message(),
+    };
+    conn.write_value(value).await
+}
@@ -0,0 +1,77 @@
+use serde_redis::{Array, BulkString, SimpleError, Value};
+
+use crate::{
+    command::{effect_command, zpop_feed_effects},
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{format_score, Storage},
+};
+
+/// Handle ZINCRBY.
+///
+/// Return the effects to sync to replica, the command itself followed by the pops of
+/// blocked tasks fed.
+pub(super) async fn handle_zincrby_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+) -> ServerResult<Vec<Array>> {
+    conn.log("run command ZINCRBY");
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "ZINCRBY",
+            args: args.clone(),
+        })?;
+
+    let increment = args
+        .pop_front_bulk_string()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "ZINCRBY",
+            args: args.clone(),
+        })?;
+
+    let member = args
+        .pop_front_bulk_string()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd: "ZINCRBY",
+            args: args.clone(),
+        })?;
+
+    let effect = effect_command([
+        b"ZINCRBY".as_slice(),
+        &key,
+        increment.as_bytes(),
+        member.as_bytes(),
+    ]);
+    let increment = match increment.parse::<f64>() {
+        Ok(v) if !v.is_nan() => v,
+        _ => {
+            let value = Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                "value is not a valid float",
+            ));
+            conn.write_value(value).await?;
+            return Ok(vec![]);
+        }
+    };
+
+    conn.log(format!(
+        "ZINCRBY {:?} {increment} {member:?}",
+        String::from_utf8_lossy(&key)
+    ));
+
+    let mut effects = vec![];
+    let value = match storage.zset_incr(key, member, increment) {
+        Ok((v, feeds)) => {
+            // Members taken by blocked tasks are popped on replica right after added.
+            effects.push(effect);
+            effects.extend(zpop_feed_effects(feeds));
+            Value::BulkString(BulkString::new(format_score(v)))
+        }
+        Err(e) => e.to_message(),
+    };
+
+    conn.write_value(value).await?;
+    Ok(effects)
+}
@@ -0,0 +1,57 @@
+use serde_redis::{Array, BulkString, SimpleError, Value};
+
+use crate::{
+    conn::Conn,
+    error::{ServerError, ServerResult},
+    storage::{format_score, OpError, Storage},
+};
+
+/// Handle ZPOPMIN, or ZPOPMAX if `max` is true.
+pub(super) async fn handle_zpop_command(
+    conn: &mut Conn<'_>,
+    mut args: Array,
+    storage: &mut Storage,
+    max: bool,
+) -> ServerResult<()> {
+    let cmd = if max { "ZPOPMAX" } else { "ZPOPMIN" };
+    conn.log(format!("run command {cmd}"));
+
+    let key = args
+        .pop_front_bulk_string_bytes()
+        .ok_or_else(|| ServerError::InvalidArgs {
+            cmd,
+            args: args.clone(),
+        })?;
+
+    let count = match args.pop_front_bulk_string() {
+        Some(v) => match v.parse::<usize>() {
+            Ok(v) => Some(v),
+            Err(..) => {
+                let value = Value::SimpleError(SimpleError::with_prefix(
+                    "ERR",
+                    "value is out of range, must be positive",
+                ));
+                return conn.write_value(value).await;
+            }
+        },
+        None => None,
+    };
+
+    let value = match storage.zset_pop(key, count, max) {
+        Ok(members) => Value::Array(
+            members
+                .into_iter()
+                .flat_map(|(member, score)| {
+                    [
+                        Value::BulkString(BulkString::new(member)),
+                        Value::BulkString(BulkString::new(format_score(score))),
+                    ]
+                })
+                .collect(),
+        ),
+        Err(OpError::KeyAbsent) => Value::Array(Array::new_empty()),
+        Err(e) => e.to_message(),
+    };
+
+    conn.write_value(value).await
+}
@@ -0,0 +1,846 @@
+//! Server configuration.
+//!
+//! The configuration is shared by the server and storage in [`SharedConfig`], so
+//! parameters set by CONFIG SET take effect at runtime. Each parameter has a redis
+//! name used in CONFIG GET and CONFIG SET, see `PARAMS`.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::TcpStream;
+
+use crate::{
+    glob::glob_match,
+    storage::{LfuConfig, MaxMemoryPolicy},
+};
+
+/// Save the RDB file in background once `changes` writes are made in `seconds`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SaveRule {
+    pub(crate) seconds: u64,
+    pub(crate) changes: u64,
+}
+
+/// Configuration of the server.
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    /// Seconds to wait before sending TCP keepalive probes to an idle peer.
+    ///
+    /// Zero disables keepalive. Same as `tcp-keepalive` in redis.
+    pub(crate) tcp_keepalive: u64,
+
+    /// Disable Nagle's algorithm on sockets, so small replies like PONG are sent
+    /// immediately instead of being delayed to batch with later writes.
+    pub(crate) tcp_nodelay: bool,
+
+    /// Seconds before closing a client connection idle, 0 disables it. Same as
+    /// `timeout` in redis.
+    ///
+    /// Like redis, subscribers and monitors are never closed as idle, nor clients
+    /// blocked by commands.
+    pub(crate) timeout: u64,
+
+    /// Max bytes of commands kept for replicas to continue after reconnecting, same
+    /// as `repl-backlog-size` in redis.
+    pub(crate) repl_backlog_size: u64,
+
+    /// Seconds before disconnecting a replica acknowledging nothing, 0 disables it.
+    /// Same as `repl-timeout` in redis.
+    pub(crate) repl_timeout: u64,
+
+    /// Seconds between PINGs sent to replicas, 0 disables it. Same as
+    /// `repl-ping-replica-period` in redis.
+    pub(crate) repl_ping_replica_period: u64,
+
+    /// Stream the RDB snapshot directly to replicas in full resynchronization,
+    /// for replicas support it.
+    ///
+    /// Same as `repl-diskless-sync` in redis.
+    pub(crate) repl_diskless_sync: bool,
+
+    /// Max bytes of commands queued for a replica before disconnecting it, 0 for no
+    /// limit. Same as the hard limit of `client-output-buffer-limit replica` in redis.
+    pub(crate) replica_output_buffer_limit: u64,
+
+    /// Max bytes of replies pending to a client before disconnecting it, 0 for no
+    /// limit. Same as the hard limit of `client-output-buffer-limit normal` in redis.
+    ///
+    /// Taken when clients connect, clients connected before CONFIG SET keep the old
+    /// limit.
+    pub(crate) client_output_buffer_limit: u64,
+
+    /// Max count of pending commands on replica before rejecting read commands
+    /// with BUSY error, 0 disables it.
+    pub(crate) replica_max_pending: usize,
+
+    /// Microseconds to sleep after loading each key at startup, 0 disables it.
+    ///
+    /// Same as `key-load-delay` in redis, to observe the loading state in tests.
+    pub(crate) key_load_delay: u64,
+
+    /// Max execution time of read commands in milliseconds, 0 disables it.
+    pub(crate) command_timeout: u64,
+
+    /// Max count of clients connected at the same time, new connections beyond it
+    /// are rejected. Same as `maxclients` in redis.
+    pub(crate) maxclients: usize,
+
+    /// Max bytes of memory used by the dataset, 0 for no limit. Same as `maxmemory`
+    /// in redis.
+    ///
+    /// Keys are evicted by `maxmemory_policy` before writes once exceeded.
+    pub(crate) maxmemory: u64,
+
+    /// Same as `maxmemory-policy` in redis.
+    pub(crate) maxmemory_policy: MaxMemoryPolicy,
+
+    /// Same as `lfu-log-factor` and `lfu-decay-time` in redis.
+    pub(crate) lfu: LfuConfig,
+
+    /// Directory of the RDB file, same as `dir` in redis.
+    pub(crate) dir: PathBuf,
+
+    /// Name of the RDB file, same as `dbfilename` in redis.
+    pub(crate) dbfilename: String,
+
+    /// Rules to save the RDB file in background, same as `save` in redis.
+    ///
+    /// Empty by default unlike redis, as the RDB file is not loaded at startup.
+    pub(crate) save: Vec<SaveRule>,
+
+    /// Append write commands to the AOF and replay it at startup, same as
+    /// `appendonly` in redis.
+    pub(crate) appendonly: bool,
+
+    /// Name of the AOF in `dir`, same as `appendfilename` in redis.
+    pub(crate) appendfilename: String,
+
+    /// Path of the log file, empty to log to stdout. Same as `logfile` in redis.
+    pub(crate) logfile: String,
+
+    /// Password of the default user, empty if no password required. Same as
+    /// `requirepass` in redis.
+    pub(crate) requirepass: String,
+
+    /// Path of the file to load users from at startup and save users to by ACL SAVE,
+    /// empty if not used. Same as `aclfile` in redis.
+    pub(crate) aclfile: String,
+
+    /// Run in cluster mode, same as `cluster-enabled` in redis.
+    pub(crate) cluster_enabled: bool,
+
+    /// Name of the nodes config file in `dir`, same as `cluster-config-file` in redis.
+    pub(crate) cluster_config_file: String,
+
+    /// Milliseconds for nodes to answer pings before flagged failing, same as
+    /// `cluster-node-timeout` in redis.
+    pub(crate) cluster_node_timeout: u64,
+
+    /// Port of the cluster bus, the client port plus 10000 if 0. Same as
+    /// `cluster-port` in redis.
+    pub(crate) cluster_port: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            tcp_keepalive: 300,
+            tcp_nodelay: true,
+            timeout: 0,
+            repl_backlog_size: 1024 * 1024,
+            repl_timeout: 60,
+            repl_ping_replica_period: 10,
+            repl_diskless_sync: true,
+            replica_output_buffer_limit: 256 * 1024 * 1024,
+            client_output_buffer_limit: 0,
+            replica_max_pending: 0,
+            key_load_delay: 0,
+            command_timeout: 0,
+            maxclients: 10000,
+            maxmemory: 0,
+            maxmemory_policy: MaxMemoryPolicy::default(),
+            lfu: LfuConfig::default(),
+            dir: PathBuf::from("."),
+            dbfilename: "dump.rdb".to_string(),
+            save: vec![],
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            logfile: String::new(),
+            requirepass: String::new(),
+            aclfile: String::new(),
+            cluster_enabled: false,
+            cluster_config_file: "nodes.conf".to_string(),
+            cluster_node_timeout: 15000,
+            cluster_port: 0,
+        }
+    }
+}
+
+impl Config {
+    /// Path of the RDB file.
+    pub(crate) fn rdb_path(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+
+    /// Path of the AOF.
+    pub(crate) fn aof_path(&self) -> PathBuf {
+        self.dir.join(&self.appendfilename)
+    }
+
+    /// Path of the nodes config file in cluster mode.
+    pub(crate) fn cluster_config_path(&self) -> PathBuf {
+        self.dir.join(&self.cluster_config_file)
+    }
+
+    /// Apply socket options in config to `stream`.
+    ///
+    /// Used on accepted client connections and the connection with master node.
+    pub(crate) fn tune_socket(&self, stream: &TcpStream) -> std::io::Result<()> {
+        stream.set_nodelay(self.tcp_nodelay)?;
+        let socket = SockRef::from(stream);
+        if self.tcp_keepalive > 0 {
+            let keepalive = TcpKeepalive::new().with_time(Duration::from_secs(self.tcp_keepalive));
+            socket.set_tcp_keepalive(&keepalive)
+        } else {
+            socket.set_keepalive(false)
+        }
+    }
+}
+
+fn format_bool(v: bool) -> String {
+    if v { "yes" } else { "no" }.to_string()
+}
+
+fn parse_bool(v: &str) -> Result<bool, String> {
+    match v.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".to_string()),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(v: &str) -> Result<T, String> {
+    v.parse::<T>()
+        .map_err(|_| "argument couldn't be parsed into an integer".to_string())
+}
+
+/// Parse memory value `v` like "100mb", units are case insensitive.
+fn parse_memory(v: &str) -> Result<u64, String> {
+    let v = v.to_lowercase();
+    let digits = v.find(|c: char| !c.is_ascii_digit()).unwrap_or(v.len());
+    let unit = match &v[digits..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err("argument must be a memory value".to_string()),
+    };
+    v[..digits]
+        .parse::<u64>()
+        .ok()
+        .and_then(|x| x.checked_mul(unit))
+        .ok_or_else(|| "argument must be a memory value".to_string())
+}
+
+/// Parse save rules `v` like "3600 1 300 100", pairs of seconds and changes.
+fn parse_save(v: &str) -> Result<Vec<SaveRule>, String> {
+    let parts = v
+        .split_whitespace()
+        .map(|x| x.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| "Invalid save parameters".to_string())?;
+    if !parts.len().is_multiple_of(2) {
+        return Err("Invalid save parameters".to_string());
+    }
+    Ok(parts
+        .chunks(2)
+        .map(|x| SaveRule {
+            seconds: x[0],
+            changes: x[1],
+        })
+        .collect())
+}
+
+/// Parse and set a parameter, return the reason if the value is invalid.
+type Setter = fn(&mut Config, &str) -> Result<(), String>;
+
+/// A parameter in CONFIG GET and CONFIG SET.
+struct Param {
+    name: &'static str,
+    get: fn(&Config) -> String,
+
+    /// Whether the parameter can be set at runtime, or only at startup.
+    mutable: bool,
+    set: Setter,
+}
+
+/// All parameters, ordered by name.
+static PARAMS: &[Param] = &[
+    Param {
+        name: "aclfile",
+        get: |c| c.aclfile.clone(),
+        mutable: false,
+        set: |c, v| {
+            c.aclfile = v.to_string();
+            Ok(())
+        },
+    },
+    Param {
+        name: "appendfilename",
+        get: |c| c.appendfilename.clone(),
+        mutable: false,
+        set: |c, v| {
+            if Path::new(v).file_name() != Some(v.as_ref()) {
+                return Err("appendfilename can't be a path, just a filename".to_string());
+            }
+            c.appendfilename = v.to_string();
+            Ok(())
+        },
+    },
+    Param {
+        name: "appendonly",
+        get: |c| format_bool(c.appendonly),
+        mutable: true,
+        set: |c, v| {
+            c.appendonly = parse_bool(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "client-output-buffer-limit",
+        get: |c| c.client_output_buffer_limit.to_string(),
+        mutable: true,
+        set: |c, v| {
+            c.client_output_buffer_limit = parse_memory(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "cluster-config-file",
+        get: |c| c.cluster_config_file.clone(),
+        mutable: false,
+        set: |c, v| {
+            c.cluster_config_file = v.to_string();
+            Ok(())
+        },
+    },
+    Param {
+        name: "cluster-enabled",
+        get: |c| format_bool(c.cluster_enabled),
+        mutable: false,
+        set: |c, v| {
+            c.cluster_enabled = parse_bool(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "cluster-node-timeout",
+        get: |c| c.cluster_node_timeout.to_string(),
+        mutable: false,
+        set: |c, v| {
+            c.cluster_node_timeout = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "cluster-port",
+        get: |c| c.cluster_port.to_string(),
+        mutable: false,
+        set: |c, v| {
+            c.cluster_port = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "command-timeout",
+        get: |c| c.command_timeout.to_string(),
+        mutable: true,
+        set: |c, v| {
+            c.command_timeout = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "dbfilename",
+        get: |c| c.dbfilename.clone(),
+        mutable: true,
+        set: |c, v| {
+            if Path::new(v).file_name() != Some(v.as_ref()) {
+                return Err("dbfilename can't be a path, just a filename".to_string());
+            }
+            c.dbfilename = v.to_string();
+            Ok(())
+        },
+    },
+    Param {
+        name: "dir",
+        get: |c| c.dir.display().to_string(),
+        mutable: true,
+        set: |c, v| {
+            if !Path::new(v).is_dir() {
+                return Err("No such file or directory".to_string());
+            }
+            c.dir = PathBuf::from(v);
+            Ok(())
+        },
+    },
+    Param {
+        name: "key-load-delay",
+        get: |c| c.key_load_delay.to_string(),
+        mutable: true,
+        set: |c, v| {
+            c.key_load_delay = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "lfu-decay-time",
+        get: |c| c.lfu.decay_time.to_string(),
+        mutable: true,
+        set: |c, v| {
+            c.lfu.decay_time = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "lfu-log-factor",
+        get: |c| c.lfu.log_factor.to_string(),
+        mutable: true,
+        set: |c, v| {
+            c.lfu.log_factor = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "logfile",
+        get: |c| c.logfile.clone(),
+        mutable: false,
+        set: |c, v| {
+            c.logfile = v.to_string();
+            Ok(())
+        },
+    },
+    Param {
+        name: "maxclients",
+        get: |c| c.maxclients.to_string(),
+        mutable: true,
+        set: |c, v| {
+            c.maxclients = parse_number(v)?;
+            if c.maxclients == 0 {
+                return Err("argument must be between 1 and 4294967295 inclusive".to_string());
+            }
+            Ok(())
+        },
+    },
+    Param {
+        name: "maxmemory",
+        get: |c| c.maxmemory.to_string(),
+        mutable: true,
+        set: |c, v| {
+            c.maxmemory = parse_memory(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "maxmemory-policy",
+        get: |c| c.maxmemory_policy.name().to_string(),
+        mutable: true,
+        set: |c, v| {
+            c.maxmemory_policy = MaxMemoryPolicy::parse(v).ok_or_else(|| {
+                let names = MaxMemoryPolicy::ALL.iter().map(|x| x.name());
+                format!(
+                    "argument(s) must be one of the following: {}",
+                    names.collect::<Vec<_>>().join(", ")
+                )
+            })?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "repl-backlog-size",
+        get: |c| c.repl_backlog_size.to_string(),
+        mutable: false,
+        set: |c, v| {
+            c.repl_backlog_size = parse_memory(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "repl-diskless-sync",
+        get: |c| format_bool(c.repl_diskless_sync),
+        mutable: false,
+        set: |c, v| {
+            c.repl_diskless_sync = parse_bool(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "repl-ping-replica-period",
+        get: |c| c.repl_ping_replica_period.to_string(),
+        mutable: false,
+        set: |c, v| {
+            c.repl_ping_replica_period = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "repl-timeout",
+        get: |c| c.repl_timeout.to_string(),
+        mutable: false,
+        set: |c, v| {
+            c.repl_timeout = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "replica-max-pending",
+        get: |c| c.replica_max_pending.to_string(),
+        mutable: true,
+        set: |c, v| {
+            c.replica_max_pending = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "replica-output-buffer-limit",
+        get: |c| c.replica_output_buffer_limit.to_string(),
+        mutable: false,
+        set: |c, v| {
+            c.replica_output_buffer_limit = parse_memory(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "requirepass",
+        get: |c| c.requirepass.clone(),
+        mutable: true,
+        set: |c, v| {
+            c.requirepass = v.to_string();
+            Ok(())
+        },
+    },
+    Param {
+        name: "save",
+        get: |c| {
+            c.save
+                .iter()
+                .map(|x| format!("{} {}", x.seconds, x.changes))
+                .collect::<Vec<_>>()
+                .join(" ")
+        },
+        mutable: true,
+        set: |c, v| {
+            c.save = parse_save(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "tcp-keepalive",
+        get: |c| c.tcp_keepalive.to_string(),
+        mutable: true,
+        set: |c, v| {
+            c.tcp_keepalive = parse_number(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "tcp-nodelay",
+        get: |c| format_bool(c.tcp_nodelay),
+        mutable: true,
+        set: |c, v| {
+            c.tcp_nodelay = parse_bool(v)?;
+            Ok(())
+        },
+    },
+    Param {
+        name: "timeout",
+        get: |c| c.timeout.to_string(),
+        mutable: true,
+        set: |c, v| {
+            c.timeout = parse_number(v)?;
+            Ok(())
+        },
+    },
+];
+
+fn find_param(name: &str) -> Option<&'static Param> {
+    PARAMS.iter().find(|x| x.name.eq_ignore_ascii_case(name))
+}
+
+/// Set parameter `name` in `config` at startup, immutable parameters included.
+///
+/// Return the reason if the name or value is invalid.
+pub(crate) fn set_param(config: &mut Config, name: &str, value: &str) -> Result<(), String> {
+    let param = find_param(name).ok_or_else(|| format!("unknown parameter '{name}'"))?;
+    (param.set)(config, value)
+}
+
+/// Split config file `line` into arguments by whitespace, like redis.
+///
+/// Arguments can be quoted in double quotes with escapes like `\n` and `\"`, or in
+/// single quotes with only `\'` escaped. Return `None` if quotes are unbalanced.
+fn split_args(line: &str) -> Option<Vec<String>> {
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|x| x.is_whitespace()).is_some() {}
+        let Some(c) = chars.next() else {
+            return Some(args);
+        };
+        let mut arg = String::new();
+        match c {
+            '"' => loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => match chars.next()? {
+                        'n' => arg.push('\n'),
+                        'r' => arg.push('\r'),
+                        't' => arg.push('\t'),
+                        c => arg.push(c),
+                    },
+                    c => arg.push(c),
+                }
+            },
+            '\'' => loop {
+                match chars.next()? {
+                    '\'' => break,
+                    '\\' if chars.peek() == Some(&'\'') => arg.push(chars.next()?),
+                    c => arg.push(c),
+                }
+            },
+            c => {
+                arg.push(c);
+                while let Some(c) = chars.next_if(|x| !x.is_whitespace()) {
+                    arg.push(c);
+                }
+            }
+        }
+        // A closing quote must be followed by whitespace.
+        if matches!(c, '"' | '\'') && chars.peek().is_some_and(|x| !x.is_whitespace()) {
+            return None;
+        }
+        args.push(arg);
+    }
+}
+
+/// Parse config file `text` in the format of redis.conf into pairs of name and value,
+/// in the order they appear.
+///
+/// Each line is a name followed by its arguments, joined by spaces into the value, like
+/// `save 3600 1 300 100`. Empty lines and lines starting with `#` are skipped.
+///
+/// Return the line number and reason if any line is invalid.
+pub(crate) fn parse_config_file(text: &str) -> Result<Vec<(String, String)>, (usize, String)> {
+    let mut params = vec![];
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut args = split_args(line).ok_or((index + 1, "unbalanced quotes".to_string()))?;
+        if args.len() < 2 {
+            return Err((index + 1, "wrong number of arguments".to_string()));
+        }
+        let name = args.remove(0).to_lowercase();
+        params.push((name, args.join(" ")));
+    }
+    Ok(params)
+}
+
+/// The configuration shared by the server and storage, for CONFIG GET and CONFIG SET.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SharedConfig {
+    inner: Arc<RwLock<Config>>,
+}
+
+impl SharedConfig {
+    /// A copy of the current configuration.
+    pub(crate) fn get(&self) -> Config {
+        self.inner.read().unwrap().clone()
+    }
+
+    /// Read the current configuration with `f`, without copying it.
+    pub(crate) fn read<T>(&self, f: impl FnOnce(&Config) -> T) -> T {
+        f(&self.inner.read().unwrap())
+    }
+
+    /// Update the configuration with `f`, bypassing validation of CONFIG SET.
+    pub(crate) fn update(&self, f: impl FnOnce(&mut Config)) {
+        f(&mut self.inner.write().unwrap())
+    }
+
+    /// Names and values of parameters matching glob `pattern`, case insensitive.
+    pub(crate) fn get_params(&self, pattern: &str) -> Vec<(&'static str, String)> {
+        let config = self.inner.read().unwrap();
+        PARAMS
+            .iter()
+            .filter(|x| glob_match(pattern.as_bytes(), x.name.as_bytes(), true))
+            .map(|x| (x.name, (x.get)(&config)))
+            .collect()
+    }
+
+    /// Set all parameters in `params`, pairs of name and value, as CONFIG SET.
+    ///
+    /// Parameters are all set or none of them is. Return the error message without
+    /// prefix if any name or value is invalid.
+    pub(crate) fn set_params(&self, params: &[(String, String)]) -> Result<(), String> {
+        let mut config = self.inner.write().unwrap();
+        let mut updated = config.clone();
+        let mut names = vec![];
+        for (name, value) in params {
+            let Some(param) = find_param(name) else {
+                return Err(format!(
+                    "Unknown option or number of arguments for CONFIG SET - '{name}'"
+                ));
+            };
+            let failed = |reason: &str| {
+                format!(
+                    "CONFIG SET failed (possibly related to argument '{}') - {reason}",
+                    param.name
+                )
+            };
+            if names.contains(&param.name) {
+                return Err(failed("duplicate parameter"));
+            }
+            names.push(param.name);
+            if !param.mutable {
+                return Err(failed("can't set immutable config"));
+            }
+            (param.set)(&mut updated, value).map_err(|e| failed(&e))?;
+        }
+        *config = updated;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tune_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+
+        let config = Config::default();
+        config.tune_socket(&stream).unwrap();
+        assert!(stream.nodelay().unwrap());
+        assert!(SockRef::from(&stream).keepalive().unwrap());
+
+        let config = Config {
+            tcp_keepalive: 0,
+            tcp_nodelay: false,
+            ..Default::default()
+        };
+        config.tune_socket(&stream).unwrap();
+        assert!(!stream.nodelay().unwrap());
+        assert!(!SockRef::from(&stream).keepalive().unwrap());
+    }
+
+    #[test]
+    fn test_params() {
+        let config = SharedConfig::default();
+        assert_eq!(
+            config.get_params("MAXMEMORY*"),
+            vec![
+                ("maxmemory", "0".to_string()),
+                ("maxmemory-policy", "noeviction".to_string())
+            ]
+        );
+        assert_eq!(config.get_params("*fsync*"), vec![]);
+
+        let pairs = |x: &[(&str, &str)]| {
+            x.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+        };
+        config
+            .set_params(&pairs(&[
+                ("maxmemory", "1mb"),
+                ("SAVE", "3600 1 60 100"),
+                ("appendonly", "yes"),
+            ]))
+            .unwrap();
+        let updated = config.get();
+        assert_eq!(updated.maxmemory, 1024 * 1024);
+        assert_eq!(
+            updated.save,
+            vec![
+                SaveRule {
+                    seconds: 3600,
+                    changes: 1
+                },
+                SaveRule {
+                    seconds: 60,
+                    changes: 100
+                }
+            ]
+        );
+        assert!(updated.appendonly);
+        assert_eq!(config.get_params("save")[0].1, "3600 1 60 100");
+
+        // Nothing is set if any parameter is invalid.
+        assert_eq!(
+            config.set_params(&pairs(&[("maxmemory", "0"), ("tcp-nodelay", "on")])),
+            Err("CONFIG SET failed (possibly related to argument 'tcp-nodelay') - argument must be 'yes' or 'no'".to_string())
+        );
+        assert_eq!(config.get().maxmemory, 1024 * 1024);
+        assert!(config.set_params(&pairs(&[("maxmemory", "1x")])).is_err());
+        assert!(config
+            .set_params(&pairs(&[("dbfilename", "a/dump.rdb")]))
+            .is_err());
+        assert!(config
+            .set_params(&pairs(&[("appendfilename", "a.aof")]))
+            .is_err());
+        assert!(config.set_params(&pairs(&[("save", "60")])).is_err());
+        assert!(config.set_params(&pairs(&[("foo", "1")])).is_err());
+        assert!(config
+            .set_params(&pairs(&[("save", ""), ("save", "")]))
+            .is_err());
+        config.set_params(&pairs(&[("save", "")])).unwrap();
+        assert!(config.get().save.is_empty());
+
+        // Immutable parameters are set at startup.
+        let mut updated = config.get();
+        set_param(&mut updated, "appendfilename", "a.aof").unwrap();
+        assert_eq!(updated.appendfilename, "a.aof");
+        assert!(set_param(&mut updated, "foo", "1").is_err());
+    }
+
+    #[test]
+    fn test_parse_config_file() {
+        let text = r#"
+# comment
+port 6380
+save 3600 1 300 100
+SAVE ""
+dir "/tmp/redis data"
+dbfilename 'it\'s.rdb'
+"#;
+        assert_eq!(
+            parse_config_file(text).unwrap(),
+            [
+                ("port", "6380"),
+                ("save", "3600 1 300 100"),
+                ("save", ""),
+                ("dir", "/tmp/redis data"),
+                ("dbfilename", "it's.rdb")
+            ]
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+        );
+        assert_eq!(parse_config_file("port 6380\ndir \"/tmp").unwrap_err().0, 2);
+        assert_eq!(parse_config_file("port").unwrap_err().0, 1);
+        assert_eq!(parse_config_file("dir \"a\"b").unwrap_err().0, 1);
+    }
+}
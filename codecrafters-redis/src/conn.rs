@@ -1,87 +1,521 @@
-use std::io::{stdout, Write};
-
-use serde_redis::{Array, Value};
+use serde_redis::{Array, BulkString, Push, SimpleString, Value};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::TcpStream,
+    time::Instant,
 };
 
 use crate::{
-    command::dispatch_normal_command,
+    command::{command_fullname, dispatch_normal_command},
     error::{ServerError, ServerResult},
-    storage::Storage,
+    load::Budget,
+    log::log,
+    state::ServerState,
+    stats::CallOutcome,
     transaction::{Transaction, TransactionEvent},
 };
 
+/// Write all of `data` to `writer`.
+///
+/// All data written to sockets goes through here, replies, RDB transfer and the
+/// replication stream, so short writes are always continued. Errors of the peer gone
+/// are reported as [`ServerError::Disconnected`].
+pub(crate) async fn write_all<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    data: &[u8],
+) -> ServerResult<()> {
+    writer.write_all(data).await.map_err(ServerError::from_io)
+}
+
+/// The underlying stream a connection reads from and writes to.
+#[derive(Debug)]
+enum ConnStream<'a> {
+    /// A real tcp connection.
+    Tcp(&'a mut TcpStream),
+
+    /// In-process connection, used when executing commands directly
+    /// through the library API.
+    ///
+    /// Nothing to read from, all values written are collected in the buffer.
+    Local(Vec<Value>),
+}
+
+/// Whether replies are sent to client, set by CLIENT REPLY.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReplyMode {
+    On,
+
+    /// No reply till turned on.
+    Off,
+
+    /// Skip the reply of the next command, CLIENT REPLY SKIP itself is not replied
+    /// either.
+    SkipNext,
+
+    /// Skip the reply of the current command, then turn on.
+    Skip,
+}
+
 /// A connection between redis client instance.
 #[derive(Debug)]
 pub(crate) struct Conn<'a> {
     pub id: usize,
-    stream: &'a mut TcpStream,
+    stream: ConnStream<'a>,
     transaction: Transaction,
     in_sync: bool,
+
+    /// Capabilities declared by replica with REPLCONF capa.
+    capa: Vec<String>,
+
+    /// Port the replica listens on, declared with REPLCONF listening-port.
+    listening_port: Option<u16>,
+
+    /// Version of RESP used by the client, set by HELLO.
+    protocol: u8,
+
+    /// Name of the user authenticated on the connection.
+    user: String,
+
+    /// Whether the client is authenticated, by AUTH or without password required.
+    authenticated: bool,
+
+    /// Set by CLIENT REPLY.
+    reply_mode: ReplyMode,
+
+    /// The current command is rejected before running.
+    rejected: bool,
+
+    /// The current command replied an error.
+    failed: bool,
+
+    /// Set by ASKING, for the next command on a slot importing to this node.
+    asking: bool,
+
+    /// Replies not sent to the tcp stream yet.
+    ///
+    /// Replies of pipelined commands are coalesced here, and sent while waiting for
+    /// the next read or by [`Conn::flush`].
+    output: Vec<u8>,
+
+    /// Max bytes of `output` before the client shall be disconnected, 0 for no limit.
+    output_limit: usize,
+
+    /// Execution time left for the current command, see [`Conn::set_budget`].
+    budget: Budget,
 }
 
 impl<'a> Conn<'a> {
     pub(crate) fn new(id: usize, stream: &'a mut TcpStream) -> Self {
         Self {
             id,
-            stream,
+            stream: ConnStream::Tcp(stream),
             transaction: Transaction::new(),
             in_sync: false,
+            capa: vec![],
+            listening_port: None,
+            protocol: 2,
+            user: "default".to_string(),
+            authenticated: false,
+            reply_mode: ReplyMode::On,
+            rejected: false,
+            failed: false,
+            asking: false,
+            output: vec![],
+            output_limit: 0,
+            budget: Budget::default(),
         }
     }
 
     pub(crate) fn new_sync(id: usize, stream: &'a mut TcpStream) -> Self {
         Self {
             id,
-            stream,
+            stream: ConnStream::Tcp(stream),
             transaction: Transaction::new(),
             in_sync: true,
+            capa: vec![],
+            listening_port: None,
+            protocol: 2,
+            user: "default".to_string(),
+            authenticated: true,
+            reply_mode: ReplyMode::On,
+            rejected: false,
+            failed: false,
+            asking: false,
+            output: vec![],
+            output_limit: 0,
+            budget: Budget::default(),
+        }
+    }
+
+    /// Build an in-process connection that is not backed by any socket.
+    ///
+    /// Values written to the connection are kept and can be retrieved by
+    /// `take_values`.
+    pub(crate) fn new_local(id: usize) -> Self {
+        Self {
+            id,
+            stream: ConnStream::Local(vec![]),
+            transaction: Transaction::new(),
+            in_sync: false,
+            capa: vec![],
+            listening_port: None,
+            protocol: 2,
+            user: "default".to_string(),
+            authenticated: true,
+            reply_mode: ReplyMode::On,
+            rejected: false,
+            failed: false,
+            asking: false,
+            output: vec![],
+            output_limit: 0,
+            budget: Budget::default(),
+        }
+    }
+
+    /// Check whether the connection is the link to master node.
+    pub(crate) fn is_master_link(&self) -> bool {
+        self.in_sync
+    }
+
+    /// Record the capability `capa` declared by replica.
+    pub(crate) fn add_capa(&mut self, capa: String) {
+        self.capa.push(capa);
+    }
+
+    /// Check whether the replica on the connection declared capability `capa`.
+    pub(crate) fn has_capa(&self, capa: &str) -> bool {
+        self.capa.iter().any(|x| x.eq_ignore_ascii_case(capa))
+    }
+
+    pub(crate) fn set_listening_port(&mut self, port: u16) {
+        self.listening_port = Some(port);
+    }
+
+    /// Port the replica on the connection listens on, if declared.
+    pub(crate) fn listening_port(&self) -> Option<u16> {
+        self.listening_port
+    }
+
+    /// Version of RESP used by the client, 2 or 3.
+    pub(crate) fn protocol(&self) -> u8 {
+        self.protocol
+    }
+
+    pub(crate) fn set_protocol(&mut self, protocol: u8) {
+        self.protocol = protocol;
+    }
+
+    /// Name of the user authenticated on the connection, "default" if not
+    /// authenticated.
+    pub(crate) fn user(&self) -> &str {
+        &self.user
+    }
+
+    pub(crate) fn set_user(&mut self, user: String) {
+        self.user = user;
+    }
+
+    /// Check whether the client is authenticated.
+    ///
+    /// Master link and in-process connections are always authenticated, tcp clients
+    /// are not until set by the server.
+    pub(crate) fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    pub(crate) fn set_authenticated(&mut self, authenticated: bool) {
+        self.authenticated = authenticated;
+    }
+
+    /// Take all values written to a local connection.
+    ///
+    /// Always empty for tcp connections.
+    pub(crate) fn take_values(&mut self) -> Vec<Value> {
+        match &mut self.stream {
+            ConnStream::Tcp(..) => vec![],
+            ConnStream::Local(values) => std::mem::take(values),
         }
     }
 
     pub(crate) fn log(&self, data: impl AsRef<str>) {
-        println!("[{}] {}", self.id, data.as_ref());
-        stdout().flush().unwrap();
+        log!("[{}] {}", self.id, data.as_ref());
+    }
+
+    /// Read from the stream into `buf`.
+    ///
+    /// Pending replies are sent meanwhile, as much as the client accepts, so a client
+    /// not reading replies does not stop the connection from receiving other data.
+    pub(crate) async fn read(&mut self, buf: &'_ mut [u8]) -> ServerResult<usize> {
+        match &mut self.stream {
+            ConnStream::Tcp(stream) => {
+                let (mut reader, mut writer) = stream.split();
+                while !self.output.is_empty() {
+                    // Unlike `write_all`, a single write is cancelled safely once
+                    // there is something to read, the rest is sent later.
+                    tokio::select! {
+                        n = reader.read(buf) => return n.map_err(ServerError::from_io),
+                        n = writer.write(&self.output) => match n.map_err(ServerError::from_io)? {
+                            0 => return Err(ServerError::from_io(std::io::ErrorKind::WriteZero.into())),
+                            n => drop(self.output.drain(..n)),
+                        },
+                    }
+                }
+                reader.read(buf).await.map_err(ServerError::from_io)
+            }
+            ConnStream::Local(..) => Ok(0),
+        }
+    }
+
+    /// Wait till the client closes the connection, for commands blocked.
+    ///
+    /// Never completes for in-process connections, or once the client sends more data:
+    /// the data is kept for later reads.
+    pub(crate) async fn closed(&mut self) {
+        if let ConnStream::Tcp(stream) = &mut self.stream {
+            let mut buf = [0u8; 1];
+            if !matches!(stream.peek(&mut buf).await, Ok(0) | Err(..)) {
+                std::future::pending::<()>().await;
+            }
+        } else {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Send all pending replies to the stream.
+    ///
+    /// Call before waiting for anything other than the client, and before leaving the
+    /// stream to others.
+    pub(crate) async fn flush(&mut self) -> ServerResult<()> {
+        if let ConnStream::Tcp(stream) = &mut self.stream {
+            if !self.output.is_empty() {
+                write_all(stream, &self.output).await?;
+                self.output.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the max bytes of pending replies, 0 for no limit.
+    pub(crate) fn set_output_limit(&mut self, limit: usize) {
+        self.output_limit = limit;
     }
 
-    pub(crate) async fn read(&mut self, buf: &'_ mut [u8]) -> Result<usize, std::io::Error> {
-        self.stream.read(buf).await
+    /// Bytes of pending replies, if more than the limit set by
+    /// [`Conn::set_output_limit`].
+    pub(crate) fn output_overflow(&self) -> Option<usize> {
+        let len = self.output.len();
+        (self.output_limit > 0 && len > self.output_limit).then_some(len)
     }
 
+    /// Write raw `buf` to the stream directly, after all pending replies.
     pub(crate) async fn write_bytes(&mut self, buf: &[u8]) -> ServerResult<()> {
-        self.stream.write(buf).await.map_err(ServerError::IoError)?;
+        self.flush().await?;
+        match &mut self.stream {
+            ConnStream::Tcp(stream) => write_all(stream, buf).await?,
+            ConnStream::Local(..) => { /* Raw bytes are not values, drop them */ }
+        }
         Ok(())
     }
 
+    async fn write_value_to_stream(&mut self, value: Value) -> ServerResult<()> {
+        let value = if self.protocol == 2 {
+            value.into_resp2()
+        } else {
+            value
+        };
+        match &mut self.stream {
+            ConnStream::Tcp(..) => {
+                let content = serde_redis::to_vec(&value).map_err(ServerError::SerdeError)?;
+                self.output.extend_from_slice(&content);
+            }
+            ConnStream::Local(values) => values.push(value),
+        }
+        Ok(())
+    }
+
+    /// Write out of band push data to client.
+    ///
+    /// Dropped if the client is not using RESP3.
+    pub(crate) async fn write_push(&mut self, push: Push) -> ServerResult<()> {
+        if self.protocol != 3 {
+            return Ok(());
+        }
+        self.write_value_to_stream(Value::Push(push)).await
+    }
+
+    /// Write pub/sub message to client.
+    ///
+    /// Unlike other push data, written as array if the client is using RESP2.
+    pub(crate) async fn write_message(&mut self, message: Push) -> ServerResult<()> {
+        self.write_value_to_stream(Value::Push(message)).await
+    }
+
+    /// Write `line` fed by MONITOR to client.
+    ///
+    /// Sent as push data `monitor` if the client is using RESP3, otherwise as simple
+    /// string.
+    pub(crate) async fn write_monitor(&mut self, line: String) -> ServerResult<()> {
+        if self.protocol != 3 {
+            return self
+                .write_value(Value::SimpleString(SimpleString::new(line)))
+                .await;
+        }
+        let push = Push::new(vec![
+            Value::BulkString(BulkString::new("monitor")),
+            Value::SimpleString(SimpleString::new(line)),
+        ]);
+        self.write_value_to_stream(Value::Push(push)).await
+    }
+
+    /// Set how replies are sent by CLIENT REPLY.
+    pub(crate) fn set_reply_mode(&mut self, mode: ReplyMode) {
+        self.reply_mode = mode;
+    }
+
+    /// Called before running each command, so CLIENT REPLY SKIP applies to the next
+    /// command only.
+    pub(crate) fn begin_command(&mut self) {
+        self.rejected = false;
+        self.failed = false;
+        self.reply_mode = match self.reply_mode {
+            ReplyMode::SkipNext => ReplyMode::Skip,
+            ReplyMode::Skip => ReplyMode::On,
+            v => v,
+        };
+    }
+
+    /// Check whether the previous command is ASKING, see [`Conn::set_asking`].
+    pub(crate) fn is_asking(&self) -> bool {
+        self.asking
+    }
+
+    /// Allow the next command on a slot importing to this node, for ASKING.
+    ///
+    /// Cleared after any command other than ASKING.
+    pub(crate) fn set_asking(&mut self, asking: bool) {
+        self.asking = asking;
+    }
+
+    /// Limit the execution time of the current command by `budget`.
+    ///
+    /// Unlimited until set, and reset by [`Conn::take_budget`].
+    pub(crate) fn set_budget(&mut self, budget: Budget) {
+        self.budget = budget;
+    }
+
+    /// Budget of the current command, for long loops to check.
+    pub(crate) fn budget(&mut self) -> &mut Budget {
+        &mut self.budget
+    }
+
+    /// Take the budget of the command finished, leaving it unlimited.
+    pub(crate) fn take_budget(&mut self) -> Budget {
+        std::mem::take(&mut self.budget)
+    }
+
+    /// Mark the current command as rejected before running, call before replying the
+    /// error.
+    pub(crate) fn reject_command(&mut self) {
+        self.rejected = true;
+    }
+
+    /// Check how the current command ended, for INFO commandstats.
+    pub(crate) fn call_outcome(&self) -> CallOutcome {
+        if self.rejected {
+            CallOutcome::Rejected
+        } else if self.failed {
+            CallOutcome::Failed
+        } else {
+            CallOutcome::Ok
+        }
+    }
+
     pub(crate) async fn write_value(&mut self, value: Value) -> ServerResult<()> {
+        if matches!(value, Value::SimpleError(..)) {
+            self.failed = true;
+        }
         if self.is_executing_transaction() {
             self.transaction.record_result(value);
             Ok(())
-        } else if !self.in_sync {
-            let content = serde_redis::to_vec(&value).map_err(ServerError::SerdeError)?;
-            self.stream
-                .write(&content)
-                .await
-                .map_err(ServerError::IoError)?;
+        } else if self.reply_mode != ReplyMode::On {
+            self.log("skip response by CLIENT REPLY");
             Ok(())
+        } else if !self.in_sync {
+            self.write_value_to_stream(value).await
         } else {
             self.log("skip response in sync");
             Ok(())
         }
     }
 
+    /// Write `value` read from storage as a bulk string, from the borrowed bytes instead
+    /// of a copy.
+    ///
+    /// Bulk strings are the same in RESP2 and RESP3. They are only copied if not
+    /// written to the socket, e.g. recorded as a result of transaction.
+    pub(crate) async fn write_stored(&mut self, value: &[u8]) -> ServerResult<()> {
+        if !self.writes_output() {
+            let value = Value::BulkString(BulkString::new(value.to_vec()));
+            return self.write_value(value).await;
+        }
+        self.write_stored_bulk(value);
+        Ok(())
+    }
+
+    /// Write `elements` read from storage as an array of bulk strings, see
+    /// [`Conn::write_stored`].
+    ///
+    /// Checks the budget for each element. Once exceeded, the elements written so far
+    /// are dropped and the TIMEOUT error is replied instead.
+    pub(crate) async fn write_stored_elements(
+        &mut self,
+        elements: impl ExactSizeIterator<Item = &[u8]>,
+    ) -> ServerResult<()> {
+        if !self.writes_output() {
+            let mut values = Vec::with_capacity(elements.len());
+            for element in elements {
+                if let Err(e) = self.budget.check() {
+                    return self.write_value(e.to_message()).await;
+                }
+                values.push(Value::BulkString(BulkString::new(element.to_vec())));
+            }
+            return self
+                .write_value(Value::Array(Array::with_values(values)))
+                .await;
+        }
+        let start = self.output.len();
+        self.output
+            .extend_from_slice(format!("*{}\r\n", elements.len()).as_bytes());
+        for element in elements {
+            if let Err(e) = self.budget.check() {
+                self.output.truncate(start);
+                return self.write_value(e.to_message()).await;
+            }
+            self.write_stored_bulk(element);
+        }
+        Ok(())
+    }
+
+    fn write_stored_bulk(&mut self, value: &[u8]) {
+        self.output
+            .extend_from_slice(format!("${}\r\n", value.len()).as_bytes());
+        self.output.extend_from_slice(value);
+        self.output.extend_from_slice(b"\r\n");
+    }
+
+    /// Whether replies are appended to the output buffer of socket as is.
+    fn writes_output(&self) -> bool {
+        matches!(self.stream, ConnStream::Tcp(..))
+            && !self.is_executing_transaction()
+            && self.reply_mode == ReplyMode::On
+            && !self.in_sync
+    }
+
     /// Still write value back to server even flagged in sync.
     ///
     /// For replconf command only.
     pub(crate) async fn sync_value(&mut self, value: Value) -> ServerResult<()> {
-        let content = serde_redis::to_vec(&value).map_err(ServerError::SerdeError)?;
-        self.stream
-            .write(&content)
-            .await
-            .map_err(ServerError::IoError)?;
-        Ok(())
+        self.write_value_to_stream(value).await
     }
 
     /// Record command in transaction.
@@ -93,7 +527,7 @@ impl<'a> Conn<'a> {
     pub(crate) fn add_to_transaction(&mut self, cmd: String, args: Array) -> bool {
         match &mut self.transaction {
             Transaction::None => false,
-            Transaction::Pending(events) => {
+            Transaction::Pending { events, .. } => {
                 events.push(TransactionEvent::new(cmd, args));
                 true
             }
@@ -101,6 +535,21 @@ impl<'a> Conn<'a> {
         }
     }
 
+    /// Mark the pending transaction as failed, `EXEC` discards it.
+    pub(crate) fn flag_transaction_dirty(&mut self) {
+        self.transaction.flag_dirty();
+    }
+
+    /// Check whether a command failed when queueing in the pending transaction.
+    pub(crate) fn is_transaction_dirty(&self) -> bool {
+        self.transaction.is_dirty()
+    }
+
+    /// Count of commands queued in the pending transaction, `None` if not in one.
+    pub(crate) fn queued_commands(&self) -> Option<usize> {
+        self.transaction.queued_count()
+    }
+
     pub(crate) fn in_transaction(&self) -> bool {
         self.transaction.is_pending() || self.transaction.is_executing()
     }
@@ -121,14 +570,23 @@ impl<'a> Conn<'a> {
     /// Get the results of transaction.
     pub(crate) async fn commit_transaction(
         &mut self,
-        storage: &mut Storage,
+        state: &mut ServerState,
     ) -> ServerResult<Vec<Value>> {
         let events = self.transaction.commit();
         // Transaction convert into executing state.
 
         for event in events {
-            dispatch_normal_command(self, &event.cmd, event.args, storage).await?;
+            state.stats().count_command();
+            let name = command_fullname(event.cmd.as_bytes(), event.args.iter());
+            let started = Instant::now();
+            self.failed = false;
+            dispatch_normal_command(self, &event.cmd, event.args, state).await?;
+            state
+                .stats()
+                .record_call(&name, started.elapsed(), self.call_outcome());
         }
+        // Errors of commands are replied in the result of EXEC, not by EXEC itself.
+        self.failed = false;
         Ok(self.transaction.finish())
     }
 
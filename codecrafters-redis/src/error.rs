@@ -1,15 +1,19 @@
 use std::{error::Error, fmt::Display};
 
-use serde_redis::{Array, RdError};
+use serde_redis::{Array, RdError, SimpleError, Value};
 
 pub type ServerResult<T> = Result<T, ServerError>;
 
 /// All errors as a redis server may respond.
 #[derive(Debug)]
 pub enum ServerError {
-    /// Forwarding `std::io::Error`
+    /// Forwarding `std::io::Error`, other than disconnection.
     IoError(std::io::Error),
 
+    /// The peer is gone in the middle of reading or writing, e.g. the connection is
+    /// reset.
+    Disconnected(std::io::Error),
+
     /// The message is invalid, not following the correct structure.
     ///
     /// That is, the message should be an array with command as the
@@ -30,12 +34,22 @@ pub enum ServerError {
 
     /// Custom anyhow error.
     Custom(anyhow::Error),
+
+    /// The connection is closed.
+    ConnectionClosed,
+
+    /// Command replied with an error.
+    ErrorReply(SimpleError),
+
+    /// Command replied with a value not in the expected type.
+    UnexpectedReply(Value),
 }
 
 impl Display for ServerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ServerError::IoError(e) => f.write_fmt(format_args!("io error: {e}")),
+            ServerError::Disconnected(e) => f.write_fmt(format_args!("disconnected: {e}")),
             ServerError::InvalidMessage(msg) => f.write_fmt(format_args!("invalid message: {msg}")),
             ServerError::InvalidCommand(cmd) => {
                 f.write_fmt(format_args!("invalid command \"{cmd}\""))
@@ -48,8 +62,40 @@ impl Display for ServerError {
             }
             ServerError::ReplicaConfigNotSet => f.write_str("replica master config not set"),
             ServerError::Custom(error) => f.write_fmt(format_args!("{error}")),
+            ServerError::ConnectionClosed => f.write_str("connection closed"),
+            ServerError::ErrorReply(e) => match e.prefix() {
+                Some(prefix) => f.write_fmt(format_args!("{prefix} {}", e.message())),
+                None => f.write_str(e.message()),
+            },
+            ServerError::UnexpectedReply(v) => f.write_fmt(format_args!("unexpected reply {v:?}")),
         }
     }
 }
 
 impl Error for ServerError {}
+
+impl ServerError {
+    /// Wrap io error `e`, as [`ServerError::Disconnected`] if the peer is gone.
+    pub(crate) fn from_io(e: std::io::Error) -> Self {
+        use std::io::ErrorKind;
+
+        match e.kind() {
+            ErrorKind::BrokenPipe
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::UnexpectedEof
+            | ErrorKind::WriteZero => ServerError::Disconnected(e),
+            _ => ServerError::IoError(e),
+        }
+    }
+
+    /// Check whether the error is caused by the peer disconnected, not worth
+    /// reporting as a failure.
+    pub(crate) fn is_disconnect(&self) -> bool {
+        matches!(
+            self,
+            ServerError::Disconnected(..) | ServerError::ConnectionClosed
+        )
+    }
+}
@@ -0,0 +1,135 @@
+//! Glob-style pattern matching, same as patterns in CONFIG GET and KEYS of redis.
+//!
+//! Supported patterns:
+//!
+//! * `?` matches any single byte.
+//! * `*` matches any bytes, including none.
+//! * `[abc]`, `[^abc]` and `[a-z]` match one byte in, or not in, the set.
+//! * `\x` matches `x` literally.
+
+/// Check whether `s` matches glob `pattern`, ignoring ASCII case if `nocase`.
+pub(crate) fn glob_match(pattern: &[u8], s: &[u8], nocase: bool) -> bool {
+    let eq = |a: u8, b: u8| {
+        if nocase {
+            a.eq_ignore_ascii_case(&b)
+        } else {
+            a == b
+        }
+    };
+    let (mut p, mut s) = (pattern, s);
+    while let Some(&c) = p.first() {
+        match c {
+            b'*' => {
+                while p.first() == Some(&b'*') {
+                    p = &p[1..];
+                }
+                if p.is_empty() {
+                    return true;
+                }
+                return (0..=s.len()).any(|i| glob_match(p, &s[i..], nocase));
+            }
+            b'?' => {
+                if s.is_empty() {
+                    return false;
+                }
+                s = &s[1..];
+                p = &p[1..];
+            }
+            b'[' => {
+                let Some(&b) = s.first() else {
+                    return false;
+                };
+                let (matched, rest) = match_class(&p[1..], b, nocase);
+                if !matched {
+                    return false;
+                }
+                s = &s[1..];
+                p = rest;
+            }
+            _ => {
+                let c = if c == b'\\' && p.len() >= 2 {
+                    p = &p[1..];
+                    p[0]
+                } else {
+                    c
+                };
+                match s.first() {
+                    Some(&b) if eq(b, c) => {}
+                    _ => return false,
+                }
+                s = &s[1..];
+                p = &p[1..];
+            }
+        }
+    }
+    s.is_empty()
+}
+
+/// Match byte `b` against the set starting right after `[`.
+///
+/// Return whether matched and the pattern after the closing `]`. An unclosed set
+/// extends to the end of pattern, like redis.
+fn match_class(mut p: &[u8], b: u8, nocase: bool) -> (bool, &[u8]) {
+    let fold = |x: u8| if nocase { x.to_ascii_lowercase() } else { x };
+    let negate = p.first() == Some(&b'^');
+    if negate {
+        p = &p[1..];
+    }
+    let mut matched = false;
+    loop {
+        match p {
+            [] => break,
+            [b']', rest @ ..] => {
+                p = rest;
+                break;
+            }
+            [b'\\', c, rest @ ..] => {
+                matched |= fold(*c) == fold(b);
+                p = rest;
+            }
+            [start, b'-', end, rest @ ..] if *end != b']' => {
+                let (lo, hi) = if start <= end {
+                    (fold(*start), fold(*end))
+                } else {
+                    (fold(*end), fold(*start))
+                };
+                matched |= (lo..=hi).contains(&fold(b));
+                p = rest;
+            }
+            [c, rest @ ..] => {
+                matched |= fold(*c) == fold(b);
+                p = rest;
+            }
+        }
+    }
+    (matched != negate, p)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        let m = |p: &str, s: &str| glob_match(p.as_bytes(), s.as_bytes(), false);
+        assert!(m("*", ""));
+        assert!(m("*", "maxmemory"));
+        assert!(m("max*", "maxmemory-policy"));
+        assert!(m("*memory*", "maxmemory"));
+        assert!(!m("max*z", "maxmemory-policy"));
+        assert!(m("h?llo", "hello"));
+        assert!(!m("h?llo", "hllo"));
+        assert!(m("h[ae]llo", "hallo"));
+        assert!(!m("h[ae]llo", "hillo"));
+        assert!(m("h[^e]llo", "hallo"));
+        assert!(!m("h[^e]llo", "hello"));
+        assert!(m("h[a-c]llo", "hbllo"));
+        assert!(!m("h[a-c]llo", "hdllo"));
+        assert!(m("h\\*llo", "h*llo"));
+        assert!(!m("h\\*llo", "hello"));
+        assert!(m("h[a", "ha"));
+        assert!(!m("dir", "DIR"));
+        assert!(glob_match(b"DIR", b"dir", true));
+        assert!(glob_match(b"[A-Z]ir", b"dir", true));
+    }
+}
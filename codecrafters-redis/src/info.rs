@@ -0,0 +1,557 @@
+//! Server information reported by INFO.
+//!
+//! Each part of the server fills its own section of [`ServerInfo`], which is
+//! rendered either as the plain text of redis:
+//!
+//! ```text
+//! # Server
+//! redis_version:0.1.0
+//! ...
+//!
+//! # Clients
+//! ...
+//! ```
+//!
+//! or as a JSON object with one member per section, for tools that prefer not to
+//! parse the text:
+//!
+//! ```json
+//! {
+//!   "server": { "redis_version": "0.1.0", "redis_mode": "standalone", "process_id": 1, ... },
+//!   "clients": { "connected_clients": 1, "blocked_clients": 0 },
+//!   "memory": { "used_memory": 1024, "used_memory_human": "1.00K", ... },
+//!   "replication": { "role": "master", "master_replid": "...", "master_repl_offset": 0, ... },
+//!   "keysizes": { "keys": { "list": 0, "string": 1 }, "biggest_key": { "key": "k", "type": "string", "size": 12 } },
+//!   "stats": { "total_connections_received": 1, "total_commands_processed": 3, ..., "evicted_keys": 0 },
+//!   "persistence": { "loading": 0, "rdb_changes_since_last_save": 0, "rdb_bgsave_in_progress": 0, ... },
+//!   "keyspace": { "db0": { "keys": 1, "expires": 0, "avg_ttl": 0 } }
+//! }
+//! ```
+//!
+//! Sections not requested are omitted in both forms.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::stats::{CommandStats, StatsSnapshot};
+
+/// Sections in INFO, in the order rendered.
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct ServerInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) server: Option<InstanceInfo>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) clients: Option<ClientsInfo>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) memory: Option<MemoryInfo>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) persistence: Option<PersistenceInfo>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) stats: Option<StatsInfo>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) replication: Option<ReplicationInfo>,
+
+    /// Stats of commands by name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) commandstats: Option<BTreeMap<String, CommandStats>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) keysizes: Option<KeysizesInfo>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) keyspace: Option<KeyspaceInfo>,
+}
+
+/// The server section, named after the instance as the whole info is `ServerInfo`.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct InstanceInfo {
+    pub(crate) redis_version: &'static str,
+    pub(crate) redis_mode: &'static str,
+    pub(crate) process_id: u32,
+    pub(crate) run_id: String,
+    pub(crate) uptime_in_seconds: u64,
+    pub(crate) uptime_in_days: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ClientsInfo {
+    pub(crate) connected_clients: usize,
+    pub(crate) blocked_clients: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct MemoryInfo {
+    /// Estimated bytes used by the dataset.
+    pub(crate) used_memory: usize,
+    pub(crate) used_memory_human: String,
+    pub(crate) maxmemory: u64,
+    pub(crate) maxmemory_human: String,
+    pub(crate) maxmemory_policy: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct KeyspaceInfo {
+    /// The only database, omitted if empty like redis.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) db0: Option<DbInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct DbInfo {
+    pub(crate) keys: usize,
+
+    /// Count of keys with expiration.
+    pub(crate) expires: usize,
+
+    /// Average time to live of keys with expiration in milliseconds.
+    pub(crate) avg_ttl: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ReplicationInfo {
+    /// "master" or "slave".
+    pub(crate) role: &'static str,
+    pub(crate) connected_slaves: usize,
+
+    /// Replicas connected, rendered as `slave<n>` lines.
+    pub(crate) slaves: Vec<SlaveInfo>,
+
+    /// Progress of FAILOVER, "no-failover" if not running.
+    pub(crate) master_failover_state: &'static str,
+    pub(crate) master_replid: String,
+    pub(crate) master_repl_offset: usize,
+    pub(crate) repl_backlog_size: usize,
+    pub(crate) repl_backlog_first_byte_offset: usize,
+    pub(crate) repl_backlog_histlen: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct SlaveInfo {
+    pub(crate) ip: String,
+
+    /// Port the replica listens on.
+    pub(crate) port: u16,
+
+    /// Always "online", the RDB is sent before a replica is registered.
+    pub(crate) state: &'static str,
+
+    /// The offset last acknowledged.
+    pub(crate) offset: usize,
+
+    /// Seconds since the last acknowledgement.
+    pub(crate) lag: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct KeysizesInfo {
+    /// Count of keys by type.
+    pub(crate) keys: BTreeMap<&'static str, usize>,
+
+    /// The key with the largest estimated memory usage.
+    pub(crate) biggest_key: Option<BiggestKey>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct BiggestKey {
+    pub(crate) key: String,
+    #[serde(rename = "type")]
+    pub(crate) ty: &'static str,
+
+    /// Estimated size in bytes.
+    pub(crate) size: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct StatsInfo {
+    /// Counters of the server, filled by storage.
+    #[serde(flatten)]
+    pub(crate) counters: Option<StatsSnapshot>,
+
+    pub(crate) pending_commands: usize,
+    pub(crate) max_pending_commands: usize,
+    pub(crate) rejected_reads_by_load: u64,
+
+    /// Count of commands aborted by the command timeout.
+    pub(crate) timed_out_commands: u64,
+
+    /// Count of keys evicted by `maxmemory`, filled by storage.
+    pub(crate) evicted_keys: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct PersistenceInfo {
+    /// 1 if loading the dataset at startup.
+    pub(crate) loading: u8,
+
+    /// Progress of loading, only present while loading.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub(crate) progress: Option<LoadingInfo>,
+
+    /// State of saving the RDB file, filled by storage.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub(crate) rdb: Option<RdbInfo>,
+
+    /// State of the AOF, filled by storage.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub(crate) aof: Option<AofInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct RdbInfo {
+    pub(crate) rdb_changes_since_last_save: u64,
+    pub(crate) rdb_bgsave_in_progress: u8,
+
+    /// Unix time in seconds.
+    pub(crate) rdb_last_save_time: u64,
+
+    /// "ok" or "err".
+    pub(crate) rdb_last_bgsave_status: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct AofInfo {
+    pub(crate) aof_enabled: u8,
+    pub(crate) aof_rewrite_in_progress: u8,
+
+    /// "ok" or "err".
+    pub(crate) aof_last_bgrewrite_status: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct LoadingInfo {
+    /// Unix time in seconds.
+    pub(crate) loading_start_time: u64,
+    pub(crate) loading_total_bytes: u64,
+    pub(crate) loading_total_keys: usize,
+    pub(crate) loading_loaded_keys: usize,
+
+    /// Percent of keys loaded, with two decimals.
+    pub(crate) loading_loaded_perc: String,
+    pub(crate) loading_eta_seconds: u64,
+}
+
+/// Format `bytes` for human like redis, e.g. "1.50K" and "2.00M".
+pub(crate) fn bytes_to_human(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["K", "M", "G", "T", "P"];
+    if bytes < 1024 {
+        return format!("{bytes}B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.2}{}", UNITS[unit])
+}
+
+impl ServerInfo {
+    /// Keep only `sections`, all sections are kept if containing "all" or
+    /// "everything".
+    ///
+    /// Like redis, commandstats is verbose and only kept if asked explicitly, other
+    /// sections are kept if `sections` is empty or containing "default".
+    pub(crate) fn retain_sections(&mut self, sections: &[String]) {
+        let wanted = |name: &str| sections.iter().any(|x| x == name);
+        if wanted("all") || wanted("everything") {
+            return;
+        }
+        if !wanted("commandstats") {
+            self.commandstats = None;
+        }
+        if sections.is_empty() || wanted("default") {
+            return;
+        }
+        if !wanted("server") {
+            self.server = None;
+        }
+        if !wanted("clients") {
+            self.clients = None;
+        }
+        if !wanted("memory") {
+            self.memory = None;
+        }
+        if !wanted("persistence") {
+            self.persistence = None;
+        }
+        if !wanted("stats") {
+            self.stats = None;
+        }
+        if !wanted("replication") {
+            self.replication = None;
+        }
+        if !wanted("keysizes") {
+            self.keysizes = None;
+        }
+        if !wanted("keyspace") {
+            self.keyspace = None;
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Render in the plain text format, sections are separated by an empty line.
+    pub(crate) fn to_text(&self) -> Vec<u8> {
+        let mut sections = vec![];
+        if let Some(info) = &self.server {
+            let mut buf = b"# Server\n".to_vec();
+            buf.extend(format!("redis_version:{}\n", info.redis_version).as_bytes());
+            buf.extend(format!("redis_mode:{}\n", info.redis_mode).as_bytes());
+            buf.extend(format!("process_id:{}\n", info.process_id).as_bytes());
+            buf.extend(format!("run_id:{}\n", info.run_id).as_bytes());
+            buf.extend(format!("uptime_in_seconds:{}\n", info.uptime_in_seconds).as_bytes());
+            buf.extend(format!("uptime_in_days:{}\n", info.uptime_in_days).as_bytes());
+            sections.push(buf);
+        }
+        if let Some(info) = &self.clients {
+            let mut buf = b"# Clients\n".to_vec();
+            buf.extend(format!("connected_clients:{}\n", info.connected_clients).as_bytes());
+            buf.extend(format!("blocked_clients:{}\n", info.blocked_clients).as_bytes());
+            sections.push(buf);
+        }
+        if let Some(info) = &self.memory {
+            let mut buf = b"# Memory\n".to_vec();
+            buf.extend(format!("used_memory:{}\n", info.used_memory).as_bytes());
+            buf.extend(format!("used_memory_human:{}\n", info.used_memory_human).as_bytes());
+            buf.extend(format!("maxmemory:{}\n", info.maxmemory).as_bytes());
+            buf.extend(format!("maxmemory_human:{}\n", info.maxmemory_human).as_bytes());
+            buf.extend(format!("maxmemory_policy:{}\n", info.maxmemory_policy).as_bytes());
+            sections.push(buf);
+        }
+        if let Some(info) = &self.persistence {
+            let mut buf = b"# Persistence\n".to_vec();
+            buf.extend(format!("loading:{}\n", info.loading).as_bytes());
+            if let Some(v) = &info.progress {
+                buf.extend(format!("loading_start_time:{}\n", v.loading_start_time).as_bytes());
+                buf.extend(format!("loading_total_bytes:{}\n", v.loading_total_bytes).as_bytes());
+                buf.extend(format!("loading_total_keys:{}\n", v.loading_total_keys).as_bytes());
+                buf.extend(format!("loading_loaded_keys:{}\n", v.loading_loaded_keys).as_bytes());
+                buf.extend(format!("loading_loaded_perc:{}\n", v.loading_loaded_perc).as_bytes());
+                buf.extend(format!("loading_eta_seconds:{}\n", v.loading_eta_seconds).as_bytes());
+            }
+            if let Some(v) = &info.rdb {
+                buf.extend(
+                    format!(
+                        "rdb_changes_since_last_save:{}\n",
+                        v.rdb_changes_since_last_save
+                    )
+                    .as_bytes(),
+                );
+                buf.extend(
+                    format!("rdb_bgsave_in_progress:{}\n", v.rdb_bgsave_in_progress).as_bytes(),
+                );
+                buf.extend(format!("rdb_last_save_time:{}\n", v.rdb_last_save_time).as_bytes());
+                buf.extend(
+                    format!("rdb_last_bgsave_status:{}\n", v.rdb_last_bgsave_status).as_bytes(),
+                );
+            }
+            if let Some(v) = &info.aof {
+                buf.extend(format!("aof_enabled:{}\n", v.aof_enabled).as_bytes());
+                buf.extend(
+                    format!("aof_rewrite_in_progress:{}\n", v.aof_rewrite_in_progress).as_bytes(),
+                );
+                buf.extend(
+                    format!(
+                        "aof_last_bgrewrite_status:{}\n",
+                        v.aof_last_bgrewrite_status
+                    )
+                    .as_bytes(),
+                );
+            }
+            sections.push(buf);
+        }
+        if let Some(info) = &self.stats {
+            let mut buf = b"# Stats\n".to_vec();
+            if let Some(v) = &info.counters {
+                buf.extend(
+                    format!(
+                        "total_connections_received:{}\n",
+                        v.total_connections_received
+                    )
+                    .as_bytes(),
+                );
+                buf.extend(
+                    format!("total_commands_processed:{}\n", v.total_commands_processed).as_bytes(),
+                );
+                buf.extend(format!("expired_keys:{}\n", v.expired_keys).as_bytes());
+                buf.extend(format!("keyspace_hits:{}\n", v.keyspace_hits).as_bytes());
+                buf.extend(format!("keyspace_misses:{}\n", v.keyspace_misses).as_bytes());
+                buf.extend(format!("rejected_connections:{}\n", v.rejected_connections).as_bytes());
+            }
+            buf.extend(format!("pending_commands:{}\n", info.pending_commands).as_bytes());
+            buf.extend(format!("max_pending_commands:{}\n", info.max_pending_commands).as_bytes());
+            buf.extend(
+                format!("rejected_reads_by_load:{}\n", info.rejected_reads_by_load).as_bytes(),
+            );
+            buf.extend(format!("timed_out_commands:{}\n", info.timed_out_commands).as_bytes());
+            buf.extend(format!("evicted_keys:{}\n", info.evicted_keys).as_bytes());
+            sections.push(buf);
+        }
+        if let Some(info) = &self.replication {
+            let mut buf = b"# Replication\n".to_vec();
+            buf.extend(format!("role:{}\n", info.role).as_bytes());
+            buf.extend(format!("connected_slaves:{}\n", info.connected_slaves).as_bytes());
+            for (i, x) in info.slaves.iter().enumerate() {
+                buf.extend(
+                    format!(
+                        "slave{i}:ip={},port={},state={},offset={},lag={}\n",
+                        x.ip, x.port, x.state, x.offset, x.lag
+                    )
+                    .as_bytes(),
+                );
+            }
+            buf.extend(
+                format!("master_failover_state:{}\n", info.master_failover_state).as_bytes(),
+            );
+            buf.extend(format!("master_replid:{}\n", info.master_replid).as_bytes());
+            buf.extend(format!("master_repl_offset:{}\n", info.master_repl_offset).as_bytes());
+            buf.extend(format!("repl_backlog_size:{}\n", info.repl_backlog_size).as_bytes());
+            buf.extend(
+                format!(
+                    "repl_backlog_first_byte_offset:{}\n",
+                    info.repl_backlog_first_byte_offset
+                )
+                .as_bytes(),
+            );
+            buf.extend(format!("repl_backlog_histlen:{}\n", info.repl_backlog_histlen).as_bytes());
+            sections.push(buf);
+        }
+        if let Some(info) = &self.commandstats {
+            let mut buf = b"# Commandstats\n".to_vec();
+            for (name, v) in info.iter() {
+                let per_call = match v.calls {
+                    0 => 0.0,
+                    n => v.usec as f64 / n as f64,
+                };
+                buf.extend(
+                    format!(
+                        "cmdstat_{name}:calls={},usec={},usec_per_call={per_call:.2},rejected_calls={},failed_calls={}\n",
+                        v.calls, v.usec, v.rejected_calls, v.failed_calls
+                    )
+                    .as_bytes(),
+                );
+            }
+            sections.push(buf);
+        }
+        if let Some(info) = &self.keysizes {
+            let mut buf = b"# Keysizes\n".to_vec();
+            for (ty, count) in info.keys.iter() {
+                buf.extend(format!("{ty}_keys:{count}\n").as_bytes());
+            }
+            if let Some(v) = &info.biggest_key {
+                buf.extend(format!("biggest_key:{},{},{}\n", v.key, v.ty, v.size).as_bytes());
+            }
+            sections.push(buf);
+        }
+        if let Some(info) = &self.keyspace {
+            let mut buf = b"# Keyspace\n".to_vec();
+            if let Some(v) = &info.db0 {
+                buf.extend(
+                    format!(
+                        "db0:keys={},expires={},avg_ttl={}\n",
+                        v.keys, v.expires, v.avg_ttl
+                    )
+                    .as_bytes(),
+                );
+            }
+            sections.push(buf);
+        }
+        sections.join(&b'\n')
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut info = ServerInfo {
+            replication: Some(ReplicationInfo {
+                role: "master",
+                connected_slaves: 1,
+                slaves: vec![SlaveInfo {
+                    ip: "127.0.0.1".into(),
+                    port: 6380,
+                    state: "online",
+                    offset: 5,
+                    lag: 1,
+                }],
+                master_failover_state: "no-failover",
+                master_replid: "abc".into(),
+                master_repl_offset: 7,
+                repl_backlog_size: 1024,
+                repl_backlog_first_byte_offset: 3,
+                repl_backlog_histlen: 5,
+            }),
+            keysizes: Some(KeysizesInfo {
+                keys: BTreeMap::from([("list", 0), ("string", 1)]),
+                biggest_key: Some(BiggestKey {
+                    key: "k".into(),
+                    ty: "string",
+                    size: 12,
+                }),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            String::from_utf8(info.to_text()).unwrap(),
+            "# Replication\nrole:master\nconnected_slaves:1\n\
+             slave0:ip=127.0.0.1,port=6380,state=online,offset=5,lag=1\nmaster_failover_state:no-failover\n\
+             master_replid:abc\nmaster_repl_offset:7\n\
+             repl_backlog_size:1024\nrepl_backlog_first_byte_offset:3\nrepl_backlog_histlen:5\n\n\
+             # Keysizes\nlist_keys:0\nstring_keys:1\nbiggest_key:k,string,12\n"
+        );
+
+        info.retain_sections(&["keysizes".into()]);
+        assert_eq!(
+            info.to_json(),
+            r#"{"keysizes":{"keys":{"list":0,"string":1},"biggest_key":{"key":"k","type":"string","size":12}}}"#
+        );
+
+        let mut info = ServerInfo {
+            memory: Some(MemoryInfo {
+                used_memory: 1536,
+                used_memory_human: bytes_to_human(1536),
+                maxmemory: 0,
+                maxmemory_human: bytes_to_human(0),
+                maxmemory_policy: "noeviction",
+            }),
+            keyspace: Some(KeyspaceInfo {
+                db0: Some(DbInfo {
+                    keys: 2,
+                    expires: 1,
+                    avg_ttl: 500,
+                }),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            String::from_utf8(info.to_text()).unwrap(),
+            "# Memory\nused_memory:1536\nused_memory_human:1.50K\nmaxmemory:0\n\
+             maxmemory_human:0B\nmaxmemory_policy:noeviction\n\n\
+             # Keyspace\ndb0:keys=2,expires=1,avg_ttl=500\n"
+        );
+        info.retain_sections(&["keyspace".into()]);
+        assert_eq!(
+            info.to_json(),
+            r#"{"keyspace":{"db0":{"keys":2,"expires":1,"avg_ttl":500}}}"#
+        );
+    }
+
+    #[test]
+    fn test_bytes_to_human() {
+        assert_eq!(bytes_to_human(1023), "1023B");
+        assert_eq!(bytes_to_human(1024), "1.00K");
+        assert_eq!(bytes_to_human(3 * 1024 * 1024 / 2), "1.50M");
+        assert_eq!(bytes_to_human(1 << 30), "1.00G");
+    }
+}
@@ -0,0 +1,38 @@
+//! A toy redis server.
+//!
+//! Besides running as a standalone binary, the server can be embedded in other
+//! rust programs: configure it with `ServerBuilder`, then use the returned `Handle`
+//! to execute commands in process or shut it down. `LocalClient` talks to the
+//! embedded server without any socket, useful in tests or as an in-memory cache.
+
+mod acl;
+mod blocking;
+mod client;
+mod clients;
+mod cluster;
+mod command;
+mod config;
+mod conn;
+mod error;
+mod glob;
+mod info;
+mod lifecycle;
+mod load;
+mod log;
+mod monitor;
+mod pause;
+mod persistence;
+mod pubsub;
+mod replication;
+mod server;
+mod state;
+mod stats;
+mod storage;
+mod tracking;
+mod transaction;
+
+pub use client::LocalClient;
+pub use error::{ServerError, ServerResult};
+pub use serde_redis::{Array, Value};
+pub use server::{Handle, ServerBuilder};
+pub use storage::{MaxMemoryPolicy, StorageHook};
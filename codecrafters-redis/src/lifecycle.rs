@@ -0,0 +1,204 @@
+//! Lifecycle of the server.
+//!
+//! ```text
+//! Loading ---> Running ---> ShuttingDown
+//!    |                           ^
+//!    +---------------------------+
+//! ```
+//!
+//! A server started with a dataset to load stays in `Loading` till all keys are
+//! loaded. Connections are accepted meanwhile, but most commands are answered with
+//! a LOADING error. Servers without anything to load start in `Running`.
+//! `ShuttingDown` is final, the server stops accepting connections once entered.
+
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{sync::watch, time::Instant};
+
+use crate::info::{LoadingInfo, PersistenceInfo};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Phase {
+    Loading(LoadingProgress),
+    Running,
+    ShuttingDown,
+}
+
+/// Progress of loading the dataset at startup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct LoadingProgress {
+    /// When loading started, reported in INFO.
+    start_time: SystemTime,
+
+    /// Same as `start_time`, used to estimate the remaining time.
+    started: Instant,
+
+    /// Size of the file being loaded.
+    total_bytes: u64,
+
+    /// Count of keys in the file, 0 till the file is parsed.
+    total_keys: usize,
+    loaded_keys: usize,
+}
+
+impl LoadingProgress {
+    fn info(&self) -> LoadingInfo {
+        let perc = if self.total_keys == 0 {
+            0.0
+        } else {
+            self.loaded_keys as f64 * 100.0 / self.total_keys as f64
+        };
+        // Like redis, assume the remaining keys load as fast as the loaded ones.
+        let eta = if self.loaded_keys == 0 {
+            1
+        } else {
+            let elapsed = self.started.elapsed().as_secs_f64();
+            let remaining = (self.total_keys - self.loaded_keys) as f64;
+            (elapsed * remaining / self.loaded_keys as f64).round() as u64
+        };
+        LoadingInfo {
+            loading_start_time: self
+                .start_time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            loading_total_bytes: self.total_bytes,
+            loading_total_keys: self.total_keys,
+            loading_loaded_keys: self.loaded_keys,
+            loading_loaded_perc: format!("{perc:.2}"),
+            loading_eta_seconds: eta,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Lifecycle {
+    sender: Arc<watch::Sender<Phase>>,
+}
+
+impl Lifecycle {
+    pub(crate) fn new() -> Self {
+        let (sender, _) = watch::channel(Phase::Running);
+        Self {
+            sender: Arc::new(sender),
+        }
+    }
+
+    /// Enter `Loading` to load a file of `total_bytes`.
+    pub(crate) fn start_loading(&self, total_bytes: u64) {
+        self.sender.send_if_modified(|phase| {
+            debug_assert_eq!(*phase, Phase::Running, "loading after started");
+            if *phase != Phase::Running {
+                return false;
+            }
+            *phase = Phase::Loading(LoadingProgress {
+                start_time: SystemTime::now(),
+                started: Instant::now(),
+                total_bytes,
+                total_keys: 0,
+                loaded_keys: 0,
+            });
+            true
+        });
+    }
+
+    /// Update the progress of loading, `loaded_keys` out of `total_keys` keys are
+    /// loaded now.
+    pub(crate) fn set_loaded(&self, loaded_keys: usize, total_keys: usize) {
+        self.sender.send_if_modified(|phase| match phase {
+            Phase::Loading(progress) => {
+                progress.loaded_keys = loaded_keys;
+                progress.total_keys = total_keys;
+                false
+            }
+            _ => false,
+        });
+    }
+
+    /// Leave `Loading` and start serving all commands.
+    pub(crate) fn finish_loading(&self) {
+        self.sender.send_if_modified(|phase| match phase {
+            Phase::Loading(_) => {
+                *phase = Phase::Running;
+                true
+            }
+            _ => false,
+        });
+    }
+
+    /// Enter `ShuttingDown`, from any phase.
+    pub(crate) fn shut_down(&self) {
+        self.sender.send_if_modified(|phase| {
+            let changed = *phase != Phase::ShuttingDown;
+            *phase = Phase::ShuttingDown;
+            changed
+        });
+    }
+
+    pub(crate) fn is_loading(&self) -> bool {
+        matches!(*self.sender.borrow(), Phase::Loading(_))
+    }
+
+    /// Wait till the server leaves `Loading`.
+    pub(crate) async fn wait_loaded(&self) {
+        let mut receiver = self.sender.subscribe();
+        let _ = receiver.wait_for(|x| !matches!(x, Phase::Loading(_))).await;
+    }
+
+    /// Wait till the server enters `ShuttingDown`.
+    pub(crate) async fn wait_shutting_down(&self) {
+        let mut receiver = self.sender.subscribe();
+        let _ = receiver.wait_for(|x| *x == Phase::ShuttingDown).await;
+    }
+
+    pub(crate) fn info(&self) -> PersistenceInfo {
+        match &*self.sender.borrow() {
+            Phase::Loading(progress) => PersistenceInfo {
+                loading: 1,
+                progress: Some(progress.info()),
+                rdb: None,
+                aof: None,
+            },
+            _ => PersistenceInfo {
+                loading: 0,
+                progress: None,
+                rdb: None,
+                aof: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lifecycle() {
+        let lifecycle = Lifecycle::new();
+        assert!(!lifecycle.is_loading());
+        lifecycle.wait_loaded().await;
+
+        lifecycle.start_loading(100);
+        assert!(lifecycle.is_loading());
+        lifecycle.set_loaded(1, 4);
+        let info = lifecycle.info().progress.unwrap();
+        assert_eq!(info.loading_total_bytes, 100);
+        assert_eq!(info.loading_loaded_perc, "25.00");
+
+        let waiter = tokio::spawn({
+            let lifecycle = lifecycle.clone();
+            async move { lifecycle.wait_loaded().await }
+        });
+        lifecycle.finish_loading();
+        waiter.await.unwrap();
+        assert_eq!(lifecycle.info().loading, 0);
+
+        lifecycle.shut_down();
+        lifecycle.finish_loading();
+        lifecycle.wait_shutting_down().await;
+    }
+}
@@ -0,0 +1,222 @@
+//! Load of the server, tracks commands pending on connections.
+//!
+//! Each connection runs its commands in its own task, so the queue depth is the count
+//! of commands received from clients and not finished yet, including commands held by
+//! client pause or blocked. When running as replica and the depth exceeds the limit,
+//! read commands are rejected with a BUSY error instead of adding more latency.
+//!
+//! Commands taking longer than the command timeout are aborted, so one pathological
+//! command does not hold its connection forever. Commands run synchronously between
+//! awaits, so long loops check their [`Budget`] instead of being interrupted.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    info::StatsInfo,
+    storage::{OpError, OpResult},
+};
+
+/// Count of steps between two reads of the clock in [`Budget::check`].
+const CHECK_INTERVAL: u32 = 1024;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LoadState {
+    inner: Arc<LoadInner>,
+}
+
+#[derive(Debug, Default)]
+struct LoadInner {
+    /// Count of commands pending.
+    pending: AtomicUsize,
+
+    /// Max count of pending commands before shedding reads, 0 disables shedding.
+    max_pending: AtomicUsize,
+
+    /// Count of read commands rejected.
+    rejected: AtomicU64,
+
+    /// Max execution time of commands in milliseconds, 0 disables it.
+    command_timeout: AtomicU64,
+
+    /// Count of commands aborted by the command timeout.
+    timed_out: AtomicU64,
+}
+
+/// Counts a command as pending till dropped.
+pub(crate) struct PendingGuard {
+    inner: Arc<LoadInner>,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.inner.pending.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl LoadState {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn set_max_pending(&self, max_pending: usize) {
+        self.inner.max_pending.store(max_pending, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_command_timeout(&self, timeout: Duration) {
+        self.inner
+            .command_timeout
+            .store(timeout.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Max execution time of commands, `None` if disabled.
+    pub(crate) fn command_timeout(&self) -> Option<Duration> {
+        match self.inner.command_timeout.load(Ordering::Relaxed) {
+            0 => None,
+            v => Some(Duration::from_millis(v)),
+        }
+    }
+
+    /// Count a command aborted by the command timeout.
+    pub(crate) fn count_timed_out(&self) {
+        self.inner.timed_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a command as pending till the returned guard is dropped.
+    pub(crate) fn enter(&self) -> PendingGuard {
+        self.inner.pending.fetch_add(1, Ordering::Relaxed);
+        PendingGuard {
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Count of commands pending now.
+    pub(crate) fn depth(&self) -> usize {
+        self.inner.pending.load(Ordering::Relaxed)
+    }
+
+    /// Check whether a read command shall be rejected for too many pending commands.
+    ///
+    /// The rejection is counted if true.
+    pub(crate) fn shed(&self) -> bool {
+        let max_pending = self.inner.max_pending.load(Ordering::Relaxed);
+        if max_pending == 0 || self.depth() <= max_pending {
+            return false;
+        }
+        self.inner.rejected.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Zero counters reported in INFO, for CONFIG RESETSTAT.
+    pub(crate) fn reset_counters(&self) {
+        self.inner.rejected.store(0, Ordering::Relaxed);
+        self.inner.timed_out.store(0, Ordering::Relaxed);
+    }
+
+    /// Build the stats section of INFO.
+    pub(crate) fn info(&self) -> StatsInfo {
+        StatsInfo {
+            counters: None,
+            pending_commands: self.depth(),
+            max_pending_commands: self.inner.max_pending.load(Ordering::Relaxed),
+            rejected_reads_by_load: self.inner.rejected.load(Ordering::Relaxed),
+            timed_out_commands: self.inner.timed_out.load(Ordering::Relaxed),
+            evicted_keys: 0,
+        }
+    }
+}
+
+/// Execution time left for the command running on a connection.
+///
+/// Long loops call [`Budget::check`] for each step, and stop with the TIMEOUT error
+/// once the command timeout is exceeded. Checks happen before any change to the
+/// dataset or any reply is kept, so an aborted command leaves nothing behind.
+#[derive(Debug, Default)]
+pub(crate) struct Budget {
+    /// When the command started and its max execution time, `None` if unlimited.
+    limit: Option<(Instant, Duration)>,
+
+    /// Count of steps checked.
+    steps: u32,
+
+    /// Set once a check failed.
+    exceeded: bool,
+}
+
+impl Budget {
+    /// Budget of a command starting now, allowed to run for `timeout`.
+    pub(crate) fn new(timeout: Duration) -> Self {
+        Self {
+            limit: Some((Instant::now(), timeout)),
+            ..Self::default()
+        }
+    }
+
+    /// Count one step, return `Err(OpError::Timeout)` if the time is used up.
+    ///
+    /// The clock is only read every [`CHECK_INTERVAL`] steps.
+    pub(crate) fn check(&mut self) -> OpResult<()> {
+        let Some((started, timeout)) = self.limit else {
+            return Ok(());
+        };
+        self.steps = self.steps.wrapping_add(1);
+        if self.exceeded
+            || (self.steps.is_multiple_of(CHECK_INTERVAL) && started.elapsed() > timeout)
+        {
+            self.exceeded = true;
+            return Err(OpError::Timeout(timeout));
+        }
+        Ok(())
+    }
+
+    /// Check whether the command was aborted by a failed check.
+    pub(crate) fn is_exceeded(&self) -> bool {
+        self.exceeded
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        let mut budget = Budget::default();
+        assert!((0..CHECK_INTERVAL * 2).all(|_| budget.check().is_ok()));
+        assert!(!budget.is_exceeded());
+
+        let mut budget = Budget::new(Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(1));
+        assert!((1..CHECK_INTERVAL).all(|_| budget.check().is_ok()));
+        assert!(matches!(budget.check(), Err(OpError::Timeout(..))));
+        assert!(budget.is_exceeded());
+        // Stays exceeded without reading the clock again.
+        assert!(budget.check().is_err());
+    }
+
+    #[test]
+    fn test_shed() {
+        let load = LoadState::new();
+        let guards = (0..3).map(|_| load.enter()).collect::<Vec<_>>();
+        assert_eq!(load.depth(), 3);
+        assert!(!load.shed());
+
+        load.set_max_pending(2);
+        assert!(load.shed());
+        drop(guards);
+        assert_eq!(load.depth(), 0);
+        assert!(!load.shed());
+        assert_eq!(load.info().rejected_reads_by_load, 1);
+
+        assert_eq!(load.command_timeout(), None);
+        load.set_command_timeout(Duration::from_millis(20));
+        assert_eq!(load.command_timeout(), Some(Duration::from_millis(20)));
+        load.count_timed_out();
+        assert_eq!(load.info().timed_out_commands, 1);
+    }
+}
@@ -0,0 +1,39 @@
+//! Server logs, written to stdout or the file set by `logfile`.
+//!
+//! The log file is shared by all servers in the process, like the stdout.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+};
+
+/// The log file, `None` to log to stdout.
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Append logs to the file at `path` from now on.
+pub(crate) fn set_log_file(path: &Path) -> std::io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    *LOG_FILE.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+pub(crate) fn write(args: std::fmt::Arguments) {
+    let mut file = LOG_FILE.lock().unwrap();
+    let result = match file.as_mut() {
+        Some(file) => writeln!(file, "{args}"),
+        None => writeln!(std::io::stdout().lock(), "{args}"),
+    };
+    // Nowhere to report it.
+    let _ = result;
+}
+
+/// Write a line of log, same arguments as `println!`.
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::log::write(format_args!($($arg)*))
+    };
+}
+
+pub(crate) use log;
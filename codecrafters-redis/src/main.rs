@@ -1,209 +1,284 @@
-use std::{net::Ipv4Addr, str::FromStr};
+use anyhow::{Context, Result};
+use codecrafters_redis::ServerBuilder;
 
-use anyhow::{bail, Context, Result};
-use serde_redis::Array;
-use tokio::{io::AsyncReadExt, net::TcpStream};
+/// Flags accepted on command line: name, value and description.
+///
+/// Besides `config`, each flag is also a parameter in the config file.
+const FLAGS: &[(&str, &str, &str)] = &[
+    (
+        "config",
+        "<path>",
+        "Load parameters from the config file, flags override it",
+    ),
+    ("port", "<port>", "Port to listen on, default 6379"),
+    (
+        "bind",
+        "\"<ip> ...\"",
+        "IPv4 or IPv6 addresses to listen on, default 127.0.0.1",
+    ),
+    (
+        "replicaof",
+        "\"<ip> <port>\"",
+        "Run as replica of the master node",
+    ),
+    (
+        "dir",
+        "<path>",
+        "Directory of the RDB file and AOF, default .",
+    ),
+    (
+        "dbfilename",
+        "<name>",
+        "Name of the RDB file, default dump.rdb",
+    ),
+    (
+        "save",
+        "\"<seconds> <changes> ...\"",
+        "Rules to save the RDB file in background",
+    ),
+    (
+        "appendonly",
+        "<yes|no>",
+        "Append writes to the AOF and replay it at startup",
+    ),
+    (
+        "appendfilename",
+        "<name>",
+        "Name of the AOF, default appendonly.aof",
+    ),
+    (
+        "logfile",
+        "<path>",
+        "Write logs to the file instead of stdout",
+    ),
+    (
+        "requirepass",
+        "<password>",
+        "Require clients to AUTH with the password",
+    ),
+    (
+        "aclfile",
+        "<path>",
+        "Load users from the file, and save them by ACL SAVE",
+    ),
+    (
+        "maxclients",
+        "<count>",
+        "Max clients connected at the same time, default 10000",
+    ),
+    (
+        "maxmemory",
+        "<bytes>",
+        "Max memory used by the dataset, 0 for no limit",
+    ),
+    (
+        "maxmemory-policy",
+        "<policy>",
+        "noeviction, allkeys-lru, allkeys-lfu or allkeys-random",
+    ),
+    (
+        "lfu-log-factor",
+        "<factor>",
+        "How slow the LFU counter grows, default 10",
+    ),
+    (
+        "lfu-decay-time",
+        "<minutes>",
+        "Idle minutes to decay the LFU counter, default 1",
+    ),
+    (
+        "tcp-keepalive",
+        "<seconds>",
+        "TCP keepalive time, 0 disables it, default 300",
+    ),
+    (
+        "tcp-nodelay",
+        "<yes|no>",
+        "Disable Nagle's algorithm on sockets, default yes",
+    ),
+    (
+        "repl-diskless-sync",
+        "<yes|no>",
+        "Stream RDB snapshots to replicas, default yes",
+    ),
+    (
+        "replica-max-pending",
+        "<count>",
+        "Pending commands on replica to reject reads",
+    ),
+    (
+        "load-dump",
+        "<path>",
+        "Load the JSON dump written by EXPORT at startup",
+    ),
+    (
+        "key-load-delay",
+        "<micros>",
+        "Sleep after loading each key, for tests",
+    ),
+    (
+        "cluster-enabled",
+        "<yes|no>",
+        "Run in cluster mode, default no",
+    ),
+    (
+        "cluster-node-timeout",
+        "<millis>",
+        "Time to flag nodes not answering as failing",
+    ),
+    (
+        "cluster-port",
+        "<port>",
+        "Port of the cluster bus, default port + 10000",
+    ),
+    (
+        "command-timeout",
+        "<millis>",
+        "Max execution time of read commands",
+    ),
+    (
+        "timeout",
+        "<seconds>",
+        "Close clients idle for this long, default 0 never",
+    ),
+];
 
-use crate::{
-    command::{dispatch_command, DispatchResult},
-    conn::Conn,
-    replication::ReplicationState,
-    server::RedisServer,
-    storage::Storage,
-};
+/// What to do, parsed from command line.
+enum Cli {
+    Help,
+    Run {
+        config_file: Option<String>,
 
-mod command;
-mod conn;
-mod error;
-mod replication;
-mod server;
-mod storage;
-mod transaction;
+        /// Parameters in flags other than `config`, in order.
+        params: Vec<(String, String)>,
+    },
+}
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = std::env::args().collect::<Vec<_>>();
-    let mut port = 6379;
-    let mut master_config = None;
-    for w in args.windows(2) {
-        match w[0].as_str() {
-            "--port" => port = w[1].parse::<u16>().context("invalid port")?,
-            "--replicaof" => {
-                match w[1].split_once(" ").map(|(ip, port)| {
-                    (
-                        if ip == "localhost" {
-                            Ipv4Addr::new(127, 0, 0, 1)
-                        } else {
-                            Ipv4Addr::from_str(ip).unwrap()
-                        },
-                        port.parse::<u16>().unwrap(),
-                    )
-                }) {
-                    Some((ip, port)) => master_config = Some((ip, port)),
-                    None => continue,
-                }
-            }
-            _ => continue,
+/// Parse command line `args` without the program name, each flag is followed by
+/// its value: `--port 6380 --dir /tmp`.
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Cli> {
+    let mut args = args.into_iter();
+    let mut config_file = None;
+    let mut params = vec![];
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            return Ok(Cli::Help);
+        }
+        let name = arg
+            .strip_prefix("--")
+            .with_context(|| format!("unexpected argument {arg:?}, see --help"))?;
+        if !FLAGS.iter().any(|(flag, ..)| *flag == name) {
+            anyhow::bail!("unknown flag --{name}, see --help");
+        }
+        let value = args
+            .next()
+            .with_context(|| format!("missing value of --{name}"))?;
+        if name == "config" {
+            config_file = Some(value);
+        } else {
+            params.push((name.to_string(), value));
         }
     }
+    Ok(Cli::Run {
+        config_file,
+        params,
+    })
+}
 
-    let server = RedisServer::new(Ipv4Addr::new(127, 0, 0, 1), port);
-
-    let replication = ReplicationState::new(master_config);
+fn print_help() {
+    println!("A toy redis server.\n");
+    println!("Usage: codecrafters-redis [--<flag> <value>]...\n");
+    println!("Flags:");
+    let width = FLAGS
+        .iter()
+        .map(|(name, value, _)| name.len() + value.len())
+        .max()
+        .unwrap_or_default();
+    for (name, value, description) in FLAGS {
+        let flag = format!("--{name} {value}");
+        println!("  {flag:<0$}  {description}", width + 3);
+    }
+    println!("  {:<1$}  Print this help", "-h, --help", width + 3);
+}
 
-    // The connection with master node, if current instance started with `--repliconf` config.
-    // Master node may send commands via the connection, these connection shall be applied on current instance.
-    let rep_master_conn = match replication.handshake(port).await {
-        Ok(v) => Some(v),
-        Err(e) => {
-            println!("[main][replica] handshake failed: {e}");
-            None
+#[tokio::main]
+async fn main() -> Result<()> {
+    let (config_file, params) = match parse_args(std::env::args().skip(1))? {
+        Cli::Help => {
+            print_help();
+            return Ok(());
         }
+        Cli::Run {
+            config_file,
+            params,
+        } => (config_file, params),
     };
 
-    // Run the loop where we act like replica node: receive commands provided
-    // by master node and apply those commands. This loop keeps current instance
-    // sync with master node.
-    let storage2 = server.clone_storage();
-    let rep = replication.clone();
-
-    tokio::spawn(async move {
-        if let Err(e) = run_replica(rep, rep_master_conn, storage2).await {
-            println!("[main][replica] failed to run replica task: {e}");
-        }
-    });
+    // Flags override the config file.
+    let mut builder = ServerBuilder::new();
+    if let Some(path) = config_file {
+        builder = builder.config_file(path)?;
+    }
+    for (name, value) in params {
+        builder = builder.set_param(&name, &value)?;
+    }
+    let handle = builder.start().await?;
 
-    server.serve(replication).await?;
+    handle.wait().await;
 
     Ok(())
 }
 
-async fn run_replica(
-    mut rep: ReplicationState,
-    rep_master_conn: Option<TcpStream>,
-    mut storage: Storage,
-) -> Result<()> {
-    println!("[main][replica] spawning replica task");
-    let mut rep_master_conn = match rep_master_conn {
-        Some(v) => v,
-        None => {
-            println!("[main][replica]: connection not available, skip replica task");
-            return Ok::<(), anyhow::Error>(());
-        }
-    };
-    println!("[main][replica] reading RDB file");
-    // Read and skip the RDB file.
-    // The master node will send a RDB file once connection is setup.
-    // RDB file in this format:
-    // `$<length_of_file>\r\n<binary_contents_of_file>`
-    let mut ch_buf = [0u8; 1];
-    rep_master_conn
-        .read_exact(&mut ch_buf)
-        .await
-        .context("failed to read header doller sign in RDB file transfer")?;
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    if ch_buf[0] != b'$' {
-        bail!(
-            "expected dollar sign as the header of RDB file transfer, got '{}'",
-            ch_buf[0]
-        )
+    fn parse(args: &[&str]) -> Result<Cli> {
+        parse_args(args.iter().map(|x| x.to_string()))
     }
 
-    println!("[main][replica]: reading RDB file length");
-
-    let mut length_buf = vec![];
-
-    // Read the length of RDB file content.
-    loop {
-        rep_master_conn
-            .read_exact(&mut ch_buf)
-            .await
-            .context("failed to read length in RDB file transfer")?;
-        if ch_buf[0] == b'\r' {
-            break;
+    fn error(args: &[&str]) -> String {
+        match parse(args) {
+            Ok(..) => panic!("{args:?} parsed"),
+            Err(e) => e.to_string(),
         }
-        length_buf.push(ch_buf[0]);
-    }
-
-    // The next char shall be '\n'
-    rep_master_conn
-        .read_exact(&mut ch_buf)
-        .await
-        .context("failed to read length in RDB file transfer")?;
-    if ch_buf[0] != b'\n' {
-        bail!("expected LF after CR after length in RDB file transfer")
     }
 
-    let length = length_buf
-        .into_iter()
-        .rev()
-        .enumerate()
-        .fold(0, |acc, (idx, ch)| {
-            (ch as usize - 48) * 10_usize.pow(idx as u32) + acc
-        });
-
-    println!("[main][replica]: reading RDB file content, length is {length}");
-
-    let mut rdb_content_buf = vec![0u8; length];
-
-    rep_master_conn
-        .read_exact(&mut rdb_content_buf)
-        .await
-        .context("failed to read RDB content")?;
-
-    println!(
-        "[main][replica] receive RDB file from master node, size is {}",
-        length
-    );
-
-    let mut buf = [0u8; 1024];
-    // Receving commands from master node.
-    loop {
-        println!("[main][replica] waiting for commands to sync");
-        let n = rep_master_conn
-            .read(&mut buf)
-            .await
-            .context("failed to get read replica master connection")?;
-
-        println!(
-            "[main][replica] read {n} bytes as command to sync, from master node: {:?}",
-            String::from_utf8(buf[0..n].to_vec()).unwrap()
+    #[test]
+    fn test_parse_args() {
+        let Cli::Run {
+            config_file,
+            params,
+        } = parse(&["--port", "6380", "--config", "a.conf", "--save", "60 1"]).unwrap()
+        else {
+            panic!("expected to run");
+        };
+        assert_eq!(config_file.as_deref(), Some("a.conf"));
+        assert_eq!(
+            params,
+            [
+                ("port".to_string(), "6380".to_string()),
+                ("save".to_string(), "60 1".to_string())
+            ]
         );
+        assert!(matches!(parse(&["--port", "1", "-h"]).unwrap(), Cli::Help));
+        assert!(matches!(parse(&["--help"]).unwrap(), Cli::Help));
 
-        // Record where we are executing commands in the parsed data.
-        let mut exec_pos = 0;
-        loop {
-            let (message, len): (Array, usize) = serde_redis::from_bytes_len(&buf[exec_pos..n])
-                .context("failed to deserialize replia master message")?;
-            println!("[main][replica] parsed {len} bytes command, total is {n}");
-            let rep2 = rep.clone();
-            let mut conn = Conn::new_sync(30000, &mut rep_master_conn);
-            match dispatch_command(&mut conn, message.clone(), &mut storage, rep2)
-                .await
-                .context("failed to dispatch replica command from master")?
-            {
-                DispatchResult::None | DispatchResult::Replica => { /* Do nothing */ }
-                DispatchResult::ReplicaSync => {
-                    // Here in this async task we are acting like replica node.
-                    // So every command that need to be synced should be applied on current
-                    // instance, because we are the replica node, the node need to be synced.
-                    println!("[main][replica] sync command from master node: {message:?}");
-                }
-            }
-            rep.add_offset(len);
-
-            if len == 0 {
-                // I think this is unreachable.
-                unreachable!("something shall be produced when parsing synced commands")
-            }
-            exec_pos += len;
+        assert_eq!(
+            error(&["--nosuch", "1"]),
+            "unknown flag --nosuch, see --help"
+        );
+        assert_eq!(error(&["--port"]), "missing value of --port");
+        assert_eq!(error(&["6380"]), "unexpected argument \"6380\", see --help");
+        assert_eq!(
+            error(&["-p", "6380"]),
+            "unexpected argument \"-p\", see --help"
+        );
 
-            if exec_pos == n {
-                // All produced.
-                break;
-            } else if exec_pos > n {
-                unreachable!("munched command bytes size not matched, exec_pos={exec_pos}, n={n}")
-            }
-        }
+        // Values are checked when applied as parameters.
+        assert!(ServerBuilder::new().set_param("port", "abc").is_err());
+        assert!(ServerBuilder::new()
+            .set_param("appendonly", "maybe")
+            .is_err());
     }
 }
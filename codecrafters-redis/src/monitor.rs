@@ -0,0 +1,103 @@
+//! Feed of commands to connections in MONITOR mode.
+//!
+//! Every command processed is formatted as a line like redis:
+//!
+//! ```text
+//! 1339518083.107412 [0 127.0.0.1:60866] "set" "foo" "bar"
+//! ```
+//!
+//! and sent through a broadcast channel, monitoring connections subscribe to it and
+//! write lines as simple strings. Lines are only formatted if anyone is monitoring.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::sync::broadcast;
+
+/// Lines kept for slow monitors, older ones are dropped.
+const CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub(crate) struct MonitorState {
+    sender: broadcast::Sender<String>,
+}
+
+impl MonitorState {
+    pub(crate) fn new() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Self { sender }
+    }
+
+    /// Start monitoring, return the receiver of lines.
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.sender.subscribe()
+    }
+
+    /// Check whether any connection is monitoring.
+    pub(crate) fn is_active(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    /// Send command `args` from client `addr` to monitors.
+    pub(crate) fn feed(&self, addr: &str, args: &[Vec<u8>]) {
+        if !self.is_active() {
+            return;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut line = format!("{}.{:06} [0 {addr}]", now.as_secs(), now.subsec_micros());
+        for arg in args {
+            line.push(' ');
+            line.push_str(&repr(arg));
+        }
+        // Nobody receives if all monitors left meanwhile.
+        let _ = self.sender.send(line);
+    }
+}
+
+/// Quote `arg` in double quotes, escape special and non-printable characters, same
+/// as `sdscatrepr` in redis.
+fn repr(arg: &[u8]) -> String {
+    let mut s = String::from('"');
+    for &c in arg {
+        match c {
+            b'\\' => s.push_str("\\\\"),
+            b'"' => s.push_str("\\\""),
+            b'\n' => s.push_str("\\n"),
+            b'\r' => s.push_str("\\r"),
+            b'\t' => s.push_str("\\t"),
+            0x07 => s.push_str("\\a"),
+            0x08 => s.push_str("\\b"),
+            c if c.is_ascii_graphic() || c == b' ' => s.push(c as char),
+            c => s.push_str(&format!("\\x{c:02x}")),
+        }
+    }
+    s.push('"');
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_feed() {
+        let monitor = MonitorState::new();
+        assert!(!monitor.is_active());
+        monitor.feed("127.0.0.1:1", &[b"ping".to_vec()]);
+
+        let mut recver = monitor.subscribe();
+        assert!(monitor.is_active());
+        monitor.feed(
+            "127.0.0.1:1",
+            &[b"set".to_vec(), b"k".to_vec(), b"a \"b\"\r\n\x01".to_vec()],
+        );
+        let line = recver.try_recv().unwrap();
+        let (time, rest) = line.split_once(' ').unwrap();
+        let (secs, micros) = time.split_once('.').unwrap();
+        assert!(secs.parse::<u64>().is_ok());
+        assert_eq!(micros.len(), 6);
+        assert_eq!(rest, r#"[0 127.0.0.1:1] "set" "k" "a \"b\"\r\n\x01""#);
+        assert!(recver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,133 @@
+//! Client pause state, set by CLIENT PAUSE and cleared by CLIENT UNPAUSE.
+//!
+//! While paused, commands from clients are held before execution until the pause
+//! ends. Commands from the master link are never paused.
+
+use std::sync::Arc;
+
+use tokio::{sync::watch, time::Instant};
+
+/// Which kind of commands to pause.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PauseMode {
+    /// Pause all commands.
+    All,
+
+    /// Only pause commands that may write the dataset, include WAIT.
+    Write,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct PauseState {
+    /// Current pause mode and when the pause ends, `None` if not paused.
+    sender: Arc<watch::Sender<Option<(PauseMode, Instant)>>>,
+}
+
+impl PauseState {
+    pub(crate) fn new() -> Self {
+        let (sender, _) = watch::channel(None);
+        Self {
+            sender: Arc::new(sender),
+        }
+    }
+
+    /// Pause commands in `mode` till `until`.
+    ///
+    /// Like redis, pausing again while already paused only extends the pause,
+    /// and `PauseMode::All` always wins over `PauseMode::Write`.
+    pub(crate) fn pause(&self, mode: PauseMode, until: Instant) {
+        self.sender.send_modify(|state| {
+            *state = match *state {
+                Some((old_mode, old_until)) if old_until > Instant::now() => {
+                    let mode = if old_mode == PauseMode::All {
+                        PauseMode::All
+                    } else {
+                        mode
+                    };
+                    Some((mode, old_until.max(until)))
+                }
+                _ => Some((mode, until)),
+            };
+        });
+    }
+
+    /// End the pause and wake up all held commands.
+    pub(crate) fn unpause(&self) {
+        self.sender.send_replace(None);
+    }
+
+    /// Check whether a command is paused now.
+    ///
+    /// Set `write` to true if the command may write the dataset.
+    pub(crate) fn is_paused(&self, write: bool) -> bool {
+        self.paused_until(write).is_some()
+    }
+
+    fn paused_until(&self, write: bool) -> Option<Instant> {
+        match *self.sender.borrow() {
+            Some((mode, until)) if until > Instant::now() && (write || mode == PauseMode::All) => {
+                Some(until)
+            }
+            _ => None,
+        }
+    }
+
+    /// Wait till a command is not paused.
+    ///
+    /// Set `write` to true if the command may write the dataset.
+    pub(crate) async fn wait(&self, write: bool) {
+        let mut receiver = self.sender.subscribe();
+        while let Some(until) = self.paused_until(write) {
+            tokio::select! {
+                _ = tokio::time::sleep_until(until) => {}
+                _ = receiver.changed() => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pause_write() {
+        let pause = PauseState::new();
+        pause.pause(PauseMode::Write, Instant::now() + Duration::from_secs(10));
+        assert!(pause.is_paused(true));
+        assert!(!pause.is_paused(false));
+
+        // Read commands are not held.
+        pause.wait(false).await;
+
+        let task = {
+            let pause = pause.clone();
+            tokio::spawn(async move { pause.wait(true).await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!task.is_finished());
+        pause.unpause();
+        tokio::time::timeout(Duration::from_secs(1), task)
+            .await
+            .expect("write command not resumed after unpause")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pause_expire() {
+        let pause = PauseState::new();
+        pause.pause(PauseMode::All, Instant::now() + Duration::from_millis(50));
+        // Extending with a write pause keeps all commands paused.
+        pause.pause(
+            PauseMode::Write,
+            Instant::now() + Duration::from_millis(100),
+        );
+        assert!(pause.is_paused(false));
+        tokio::time::timeout(Duration::from_secs(1), pause.wait(false))
+            .await
+            .expect("pause not expired");
+        assert!(!pause.is_paused(true));
+    }
+}
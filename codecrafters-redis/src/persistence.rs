@@ -0,0 +1,224 @@
+//! State of saving the dataset to the RDB file, by SAVE and BGSAVE.
+//!
+//! Background saves are also started by the `save` rules in config, checked every
+//! second.
+//!
+//! Only one background save runs at a time. Writes made after the snapshot of a save
+//! is taken still count as changes since the last save once it finishes.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{config::SaveRule, info::RdbInfo};
+
+/// Seconds to wait before retrying a failed background save started by save rules,
+/// same as redis.
+const BGSAVE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+struct SaveStatus {
+    /// Count of writes since the server started.
+    writes: u64,
+
+    /// `writes` when the last successful save took its snapshot.
+    saved_writes: u64,
+
+    bgsave_in_progress: bool,
+
+    /// When the last successful save took its snapshot, the start time of server
+    /// if never saved.
+    last_save_time: SystemTime,
+
+    /// Whether the last background save succeeded, true if never run.
+    last_bgsave_ok: bool,
+
+    /// When the last background save started, `None` if never run.
+    last_bgsave_try: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct PersistenceState {
+    status: Arc<Mutex<SaveStatus>>,
+}
+
+/// A save in progress, taken when the snapshot is taken.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SaveTicket {
+    writes: u64,
+    time: SystemTime,
+}
+
+impl PersistenceState {
+    pub(crate) fn new() -> Self {
+        Self {
+            status: Arc::new(Mutex::new(SaveStatus {
+                writes: 0,
+                saved_writes: 0,
+                bgsave_in_progress: false,
+                last_save_time: SystemTime::now(),
+                last_bgsave_ok: true,
+                last_bgsave_try: None,
+            })),
+        }
+    }
+
+    /// Count `count` writes to the dataset.
+    pub(crate) fn record_writes(&self, count: u64) {
+        self.status.lock().unwrap().writes += count;
+    }
+
+    /// Start a foreground save, call when taking the snapshot.
+    pub(crate) fn start_save(&self) -> SaveTicket {
+        SaveTicket {
+            writes: self.status.lock().unwrap().writes,
+            time: SystemTime::now(),
+        }
+    }
+
+    /// Start a background save, call when taking the snapshot.
+    ///
+    /// Return `None` if another background save is in progress.
+    pub(crate) fn start_bgsave(&self) -> Option<SaveTicket> {
+        let mut status = self.status.lock().unwrap();
+        if status.bgsave_in_progress {
+            return None;
+        }
+        let time = SystemTime::now();
+        status.bgsave_in_progress = true;
+        status.last_bgsave_try = Some(time);
+        Some(SaveTicket {
+            writes: status.writes,
+            time,
+        })
+    }
+
+    /// Finish the save started with `ticket`, `ok` is whether the file is written.
+    pub(crate) fn finish_save(&self, ticket: SaveTicket, ok: bool) {
+        let mut status = self.status.lock().unwrap();
+        if ok {
+            status.saved_writes = ticket.writes;
+            status.last_save_time = ticket.time;
+        }
+    }
+
+    /// Finish the background save started with `ticket`.
+    pub(crate) fn finish_bgsave(&self, ticket: SaveTicket, ok: bool) {
+        self.finish_save(ticket, ok);
+        let mut status = self.status.lock().unwrap();
+        status.bgsave_in_progress = false;
+        status.last_bgsave_ok = ok;
+    }
+
+    /// Whether any of save `rules` is met, so a background save shall start.
+    ///
+    /// A rule is met once enough changes are made since the last successful save and
+    /// enough time passed. After a failed background save, wait a few seconds before
+    /// trying again.
+    pub(crate) fn save_due(&self, rules: &[SaveRule]) -> bool {
+        let status = self.status.lock().unwrap();
+        if status.bgsave_in_progress {
+            return false;
+        }
+        let elapsed = |time: SystemTime| time.elapsed().unwrap_or_default();
+        if !status.last_bgsave_ok
+            && status
+                .last_bgsave_try
+                .is_some_and(|x| elapsed(x) < BGSAVE_RETRY_DELAY)
+        {
+            return false;
+        }
+        let changes = status.writes - status.saved_writes;
+        let since_save = elapsed(status.last_save_time);
+        rules
+            .iter()
+            .any(|rule| changes >= rule.changes && since_save >= Duration::from_secs(rule.seconds))
+    }
+
+    pub(crate) fn bgsave_in_progress(&self) -> bool {
+        self.status.lock().unwrap().bgsave_in_progress
+    }
+
+    /// Unix time in seconds of the last successful save, as LASTSAVE.
+    pub(crate) fn last_save_time(&self) -> u64 {
+        self.info().rdb_last_save_time
+    }
+
+    pub(crate) fn info(&self) -> RdbInfo {
+        let status = self.status.lock().unwrap();
+        RdbInfo {
+            rdb_changes_since_last_save: status.writes - status.saved_writes,
+            rdb_bgsave_in_progress: status.bgsave_in_progress as u8,
+            rdb_last_save_time: status
+                .last_save_time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            rdb_last_bgsave_status: if status.last_bgsave_ok { "ok" } else { "err" },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bgsave() {
+        let state = PersistenceState::new();
+        state.record_writes(1);
+        let ticket = state.start_bgsave().unwrap();
+        assert!(state.bgsave_in_progress());
+        assert!(state.start_bgsave().is_none());
+
+        // Written during the save.
+        state.record_writes(1);
+        state.finish_bgsave(ticket, true);
+        let info = state.info();
+        assert_eq!(info.rdb_bgsave_in_progress, 0);
+        assert_eq!(info.rdb_changes_since_last_save, 1);
+        assert_eq!(info.rdb_last_bgsave_status, "ok");
+
+        let ticket = state.start_bgsave().unwrap();
+        state.finish_bgsave(ticket, false);
+        let info = state.info();
+        assert_eq!(info.rdb_changes_since_last_save, 1);
+        assert_eq!(info.rdb_last_bgsave_status, "err");
+
+        let ticket = state.start_save();
+        state.finish_save(ticket, true);
+        assert_eq!(state.info().rdb_changes_since_last_save, 0);
+        assert_eq!(state.last_save_time(), state.info().rdb_last_save_time);
+    }
+
+    #[test]
+    fn test_save_due() {
+        let state = PersistenceState::new();
+        let rules = [
+            SaveRule {
+                seconds: 3600,
+                changes: 1,
+            },
+            SaveRule {
+                seconds: 0,
+                changes: 2,
+            },
+        ];
+        assert!(!state.save_due(&[]));
+        state.record_writes(1);
+        assert!(!state.save_due(&rules));
+        state.record_writes(1);
+        assert!(state.save_due(&rules));
+
+        let ticket = state.start_bgsave().unwrap();
+        assert!(!state.save_due(&rules));
+        state.finish_bgsave(ticket, false);
+        // Retried later.
+        assert!(!state.save_due(&rules));
+
+        let ticket = state.start_save();
+        state.finish_save(ticket, true);
+        assert!(!state.save_due(&rules));
+    }
+}
@@ -0,0 +1,413 @@
+//! Publish/subscribe messaging, set by SUBSCRIBE, PSUBSCRIBE, SSUBSCRIBE and PUBLISH.
+//!
+//! Each connection registers a message channel when connected. Messages published to
+//! a channel are sent to the message channels of all connections subscribed to it or
+//! to any glob pattern matching it, and written to the clients by the connection tasks.
+//! A connection subscribed to both the channel and a matching pattern receives the
+//! message once for each.
+//!
+//! Shard channels, added in redis 7 for cluster mode, are kept apart from regular
+//! channels: SPUBLISH only reaches SSUBSCRIBE subscribers, and the other way round.
+//! There is no pattern of shard channels.
+//!
+//! Unlike tracking invalidations, messages are delivered in both RESP2 and RESP3: as
+//! arrays in RESP2 and push data in RESP3.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use serde_redis::{BulkString, Push, Value};
+use tokio::sync::mpsc;
+
+use crate::glob::glob_match;
+
+/// Subscribers by channel name or pattern.
+type Subscribers = HashMap<String, HashMap<usize, mpsc::UnboundedSender<Push>>>;
+
+/// What a subscription is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SubscriptionKind {
+    /// A regular channel, by SUBSCRIBE.
+    Channel,
+
+    /// A glob pattern of regular channels, by PSUBSCRIBE.
+    Pattern,
+
+    /// A shard channel, by SSUBSCRIBE.
+    Shard,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PubSubState {
+    inner: Arc<Mutex<PubSubInner>>,
+}
+
+#[derive(Debug, Default)]
+struct PubSubInner {
+    /// Message channels of connections, by connection id.
+    senders: HashMap<usize, mpsc::UnboundedSender<Push>>,
+
+    /// Subscribers of channels, by channel name.
+    channels: Subscribers,
+
+    /// Subscribers of patterns, by pattern.
+    patterns: Subscribers,
+
+    /// Subscribers of shard channels, by channel name.
+    shard_channels: Subscribers,
+
+    /// Subscriptions of connections, by connection id.
+    clients: HashMap<usize, Subscriptions>,
+}
+
+/// Channels and patterns subscribed by a connection.
+///
+/// Ordered so unsubscribing from all replies in a stable order.
+#[derive(Debug, Default)]
+struct Subscriptions {
+    channels: BTreeSet<String>,
+    patterns: BTreeSet<String>,
+    shard_channels: BTreeSet<String>,
+}
+
+impl Subscriptions {
+    fn get_mut(&mut self, kind: SubscriptionKind) -> &mut BTreeSet<String> {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::Shard => &mut self.shard_channels,
+        }
+    }
+
+    /// Count replied when subscribing to `kind`: like redis, shard channels are
+    /// counted apart from regular channels and patterns.
+    fn count(&self, kind: SubscriptionKind) -> usize {
+        match kind {
+            SubscriptionKind::Channel | SubscriptionKind::Pattern => {
+                self.channels.len() + self.patterns.len()
+            }
+            SubscriptionKind::Shard => self.shard_channels.len(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.channels.is_empty() && self.patterns.is_empty() && self.shard_channels.is_empty()
+    }
+}
+
+impl PubSubState {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the message channel of connection `id`.
+    ///
+    /// Return the receiver of all messages published to channels it subscribes.
+    pub(crate) fn register(&self, id: usize) -> mpsc::UnboundedReceiver<Push> {
+        let (sender, recver) = mpsc::unbounded_channel();
+        self.inner.lock().unwrap().senders.insert(id, sender);
+        recver
+    }
+
+    /// Remove all subscriptions of connection `id`, called when the connection closed.
+    pub(crate) fn unregister(&self, id: usize) {
+        let mut lock = self.inner.lock().unwrap();
+        lock.senders.remove(&id);
+        let mut subscriptions = lock.clients.remove(&id).unwrap_or_default();
+        for kind in [
+            SubscriptionKind::Channel,
+            SubscriptionKind::Pattern,
+            SubscriptionKind::Shard,
+        ] {
+            for channel in std::mem::take(subscriptions.get_mut(kind)) {
+                remove_subscriber(lock.subscribers_mut(kind), &channel, id);
+            }
+        }
+    }
+
+    /// Subscribe connection `id` to `channel` of `kind`.
+    ///
+    /// Return the count of subscriptions of the connection after it, shard channels
+    /// are counted apart. Connections without message channel, like in-process ones,
+    /// are counted but receive nothing.
+    pub(crate) fn subscribe(&self, id: usize, channel: &str, kind: SubscriptionKind) -> usize {
+        let mut lock = self.inner.lock().unwrap();
+        if let Some(sender) = lock.senders.get(&id).cloned() {
+            lock.subscribers_mut(kind)
+                .entry(channel.to_string())
+                .or_default()
+                .insert(id, sender);
+        }
+        let subscriptions = lock.clients.entry(id).or_default();
+        subscriptions.get_mut(kind).insert(channel.to_string());
+        subscriptions.count(kind)
+    }
+
+    /// Unsubscribe connection `id` from `channel` of `kind`.
+    ///
+    /// Return the count of subscriptions still kept by the connection, shard channels
+    /// are counted apart.
+    pub(crate) fn unsubscribe(&self, id: usize, channel: &str, kind: SubscriptionKind) -> usize {
+        let mut lock = self.inner.lock().unwrap();
+        remove_subscriber(lock.subscribers_mut(kind), channel, id);
+        let Some(subscriptions) = lock.clients.get_mut(&id) else {
+            return 0;
+        };
+        subscriptions.get_mut(kind).remove(channel);
+        let count = subscriptions.count(kind);
+        if subscriptions.is_empty() {
+            lock.clients.remove(&id);
+        }
+        count
+    }
+
+    /// Channels, or patterns, of `kind` subscribed by connection `id`.
+    pub(crate) fn subscriptions(&self, id: usize, kind: SubscriptionKind) -> Vec<String> {
+        self.inner
+            .lock()
+            .unwrap()
+            .clients
+            .get_mut(&id)
+            .map(|x| x.get_mut(kind).iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Count of subscriptions of `kind` by connection `id`, same as replied when
+    /// subscribing.
+    pub(crate) fn subscription_count(&self, id: usize, kind: SubscriptionKind) -> usize {
+        self.inner
+            .lock()
+            .unwrap()
+            .clients
+            .get(&id)
+            .map_or(0, |x| x.count(kind))
+    }
+
+    /// Check whether connection `id` subscribes to anything.
+    pub(crate) fn is_subscribed(&self, id: usize) -> bool {
+        self.inner.lock().unwrap().clients.contains_key(&id)
+    }
+
+    /// Regular channels, or shard channels if `shard`, with at least one subscriber.
+    /// Only those matching glob `pattern` if set.
+    ///
+    /// Pattern subscribers are not counted.
+    pub(crate) fn active_channels(&self, shard: bool, pattern: Option<&str>) -> Vec<String> {
+        let lock = self.inner.lock().unwrap();
+        let subscribers = if shard {
+            &lock.shard_channels
+        } else {
+            &lock.channels
+        };
+        let mut channels = subscribers
+            .keys()
+            .filter(|x| pattern.is_none_or(|p| glob_match(p.as_bytes(), x.as_bytes(), false)))
+            .cloned()
+            .collect::<Vec<_>>();
+        channels.sort();
+        channels
+    }
+
+    /// Count of subscribers of regular `channel`, or shard channel if `shard`.
+    ///
+    /// Pattern subscribers are not counted.
+    pub(crate) fn subscriber_count(&self, shard: bool, channel: &str) -> usize {
+        let lock = self.inner.lock().unwrap();
+        let subscribers = if shard {
+            &lock.shard_channels
+        } else {
+            &lock.channels
+        };
+        subscribers.get(channel).map_or(0, |x| x.len())
+    }
+
+    /// Count of unique patterns subscribed by all connections.
+    pub(crate) fn pattern_count(&self) -> usize {
+        self.inner.lock().unwrap().patterns.len()
+    }
+
+    /// Send `message` to all subscribers of `channel` and of patterns matching it.
+    ///
+    /// Return the count of messages sent.
+    pub(crate) fn publish(&self, channel: &str, message: &[u8]) -> usize {
+        let bulk = |x: &str| Value::BulkString(BulkString::new(x));
+        let message = Value::BulkString(BulkString::new(message));
+        let lock = self.inner.lock().unwrap();
+        let mut count = 0;
+        for sender in lock
+            .channels
+            .get(channel)
+            .into_iter()
+            .flat_map(|x| x.values())
+        {
+            let message = Push::new(vec![bulk("message"), bulk(channel), message.clone()]);
+            count += sender.send(message).is_ok() as usize;
+        }
+        for (pattern, subscribers) in lock.patterns.iter() {
+            if !glob_match(pattern.as_bytes(), channel.as_bytes(), false) {
+                continue;
+            }
+            for sender in subscribers.values() {
+                let message = Push::new(vec![
+                    bulk("pmessage"),
+                    bulk(pattern),
+                    bulk(channel),
+                    message.clone(),
+                ]);
+                count += sender.send(message).is_ok() as usize;
+            }
+        }
+        count
+    }
+
+    /// Send `message` to all subscribers of shard channel `channel`.
+    ///
+    /// Return the count of messages sent.
+    pub(crate) fn publish_shard(&self, channel: &str, message: &[u8]) -> usize {
+        let bulk = |x: &str| Value::BulkString(BulkString::new(x));
+        let message = Value::BulkString(BulkString::new(message));
+        let lock = self.inner.lock().unwrap();
+        lock.shard_channels
+            .get(channel)
+            .into_iter()
+            .flat_map(|x| x.values())
+            .filter(|sender| {
+                let message = Push::new(vec![bulk("smessage"), bulk(channel), message.clone()]);
+                sender.send(message).is_ok()
+            })
+            .count()
+    }
+}
+
+impl PubSubInner {
+    fn subscribers_mut(&mut self, kind: SubscriptionKind) -> &mut Subscribers {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::Shard => &mut self.shard_channels,
+        }
+    }
+}
+
+fn remove_subscriber(subscribers: &mut Subscribers, channel: &str, id: usize) {
+    if let Some(ids) = subscribers.get_mut(channel) {
+        ids.remove(&id);
+        if ids.is_empty() {
+            subscribers.remove(channel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SubscriptionKind::*, *};
+
+    fn received(recver: &mut mpsc::UnboundedReceiver<Push>) -> Vec<Vec<String>> {
+        let mut messages = vec![];
+        while let Ok(push) = recver.try_recv() {
+            let values = push.into_values().into_iter().map(|x| match x {
+                Value::BulkString(mut v) => String::from_utf8(v.take().unwrap()).unwrap(),
+                v => panic!("unexpected message part {v:?}"),
+            });
+            messages.push(values.collect());
+        }
+        messages
+    }
+
+    #[test]
+    fn test_publish() {
+        let pubsub = PubSubState::new();
+        let mut recver1 = pubsub.register(1);
+        let mut recver2 = pubsub.register(2);
+
+        assert_eq!(pubsub.subscribe(1, "a", Channel), 1);
+        assert_eq!(pubsub.subscribe(1, "b", Channel), 2);
+        assert_eq!(pubsub.subscribe(1, "a", Channel), 2);
+        assert_eq!(pubsub.subscribe(2, "a", Channel), 1);
+        assert_eq!(pubsub.subscriptions(1, Channel), ["a", "b"]);
+        assert_eq!(pubsub.active_channels(false, None), ["a", "b"]);
+        assert_eq!(pubsub.subscriber_count(false, "a"), 2);
+
+        assert_eq!(pubsub.publish("a", b"x"), 2);
+        assert_eq!(pubsub.publish("b", b"y"), 1);
+        assert_eq!(pubsub.publish("c", b"z"), 0);
+        assert_eq!(
+            received(&mut recver1),
+            [["message", "a", "x"], ["message", "b", "y"]]
+        );
+        assert_eq!(received(&mut recver2), [["message", "a", "x"]]);
+
+        assert_eq!(pubsub.unsubscribe(1, "a", Channel), 1);
+        assert_eq!(pubsub.unsubscribe(1, "c", Channel), 1);
+        assert_eq!(pubsub.publish("a", b"x"), 1);
+        assert!(received(&mut recver1).is_empty());
+
+        pubsub.unregister(2);
+        assert_eq!(pubsub.publish("a", b"x"), 0);
+        assert_eq!(pubsub.subscription_count(2, Channel), 0);
+        assert_eq!(pubsub.subscription_count(1, Channel), 1);
+    }
+
+    #[test]
+    fn test_publish_pattern() {
+        let pubsub = PubSubState::new();
+        let mut recver = pubsub.register(1);
+
+        assert_eq!(pubsub.subscribe(1, "news.*", Pattern), 1);
+        assert_eq!(pubsub.subscribe(1, "news.tech", Channel), 2);
+        assert_eq!(pubsub.subscribe(1, "*", Pattern), 3);
+        assert_eq!(pubsub.pattern_count(), 2);
+        assert_eq!(pubsub.active_channels(false, Some("news.*")), ["news.tech"]);
+        assert!(pubsub.active_channels(false, Some("sport.*")).is_empty());
+
+        // Once for the channel and once for each pattern.
+        assert_eq!(pubsub.publish("news.tech", b"x"), 3);
+        let mut messages = received(&mut recver);
+        messages.sort();
+        assert_eq!(
+            messages,
+            [
+                vec!["message", "news.tech", "x"],
+                vec!["pmessage", "*", "news.tech", "x"],
+                vec!["pmessage", "news.*", "news.tech", "x"],
+            ]
+        );
+
+        assert_eq!(pubsub.unsubscribe(1, "*", Pattern), 2);
+        assert_eq!(pubsub.publish("sport", b"y"), 0);
+        assert_eq!(pubsub.subscriptions(1, Pattern), ["news.*"]);
+        pubsub.unregister(1);
+        assert_eq!(pubsub.pattern_count(), 0);
+    }
+
+    #[test]
+    fn test_publish_shard() {
+        let pubsub = PubSubState::new();
+        let mut recver = pubsub.register(1);
+
+        assert_eq!(pubsub.subscribe(1, "a", Channel), 1);
+        assert_eq!(pubsub.subscribe(1, "a", Shard), 1);
+        assert_eq!(pubsub.subscribe(1, "b", Shard), 2);
+        assert_eq!(pubsub.subscription_count(1, Channel), 1);
+        assert_eq!(pubsub.active_channels(true, None), ["a", "b"]);
+        assert_eq!(pubsub.subscriber_count(true, "b"), 1);
+
+        // Regular and shard channels of the same name are apart.
+        assert_eq!(pubsub.publish_shard("b", b"x"), 1);
+        assert_eq!(pubsub.publish("b", b"y"), 0);
+        assert_eq!(pubsub.publish_shard("a", b"z"), 1);
+        assert_eq!(
+            received(&mut recver),
+            [["smessage", "b", "x"], ["smessage", "a", "z"]]
+        );
+
+        assert_eq!(pubsub.unsubscribe(1, "a", Channel), 0);
+        assert!(pubsub.is_subscribed(1));
+        assert_eq!(pubsub.unsubscribe(1, "a", Shard), 1);
+        pubsub.unregister(1);
+        assert!(!pubsub.is_subscribed(1));
+        assert!(pubsub.active_channels(true, None).is_empty());
+    }
+}
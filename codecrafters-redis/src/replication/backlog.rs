@@ -0,0 +1,108 @@
+//! Replication backlog, the latest bytes of commands synced to replicas.
+//!
+//! Every byte sent to replicas has an offset in the replication stream, the first
+//! byte is at offset 1 and the offset of master is the offset of the last byte. The
+//! backlog keeps the latest bytes till its size is reached, older bytes are dropped.
+//!
+//! A replica reconnecting asks for bytes after the offset it processed in PSYNC, if
+//! they are still in the backlog the master continues the stream from there instead
+//! of a full resynchronization.
+
+use std::collections::VecDeque;
+
+#[derive(Debug)]
+pub(super) struct Backlog {
+    buf: VecDeque<u8>,
+
+    /// Max count of bytes kept.
+    size: usize,
+
+    /// Offset of the last byte in the replication stream.
+    offset: usize,
+}
+
+impl Backlog {
+    pub(super) fn new(size: usize) -> Self {
+        Self {
+            buf: VecDeque::new(),
+            size,
+            offset: 0,
+        }
+    }
+
+    /// Offset of the last byte in the replication stream.
+    pub(super) fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Offset of the first byte kept, `offset + 1` if empty.
+    pub(super) fn first_byte_offset(&self) -> usize {
+        self.offset + 1 - self.buf.len()
+    }
+
+    /// Count of bytes kept.
+    pub(super) fn histlen(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub(super) fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Append `data` to the replication stream.
+    pub(super) fn feed(&mut self, data: &[u8]) {
+        self.offset += data.len();
+        self.buf.extend(data);
+        let excess = self.buf.len().saturating_sub(self.size);
+        self.buf.drain(..excess);
+    }
+
+    /// Drop all bytes and continue the replication stream from `offset`, when
+    /// synced with another stream.
+    pub(super) fn reset(&mut self, offset: usize) {
+        self.buf.clear();
+        self.offset = offset;
+    }
+
+    /// Get the bytes from `offset` to the end of the stream.
+    ///
+    /// Return `None` if bytes at `offset` are dropped or not written yet.
+    pub(super) fn since(&self, offset: usize) -> Option<Vec<u8>> {
+        let first = self.first_byte_offset();
+        if offset < first || offset > self.offset + 1 {
+            return None;
+        }
+        Some(self.buf.range(offset - first..).copied().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_backlog() {
+        let mut backlog = Backlog::new(8);
+        assert_eq!(backlog.since(1), Some(vec![]));
+        assert_eq!(backlog.since(2), None);
+
+        backlog.feed(b"abcde");
+        assert_eq!(backlog.offset(), 5);
+        assert_eq!(backlog.since(1), Some(b"abcde".to_vec()));
+        assert_eq!(backlog.since(4), Some(b"de".to_vec()));
+        assert_eq!(backlog.since(6), Some(vec![]));
+
+        // Oldest bytes are dropped once full.
+        backlog.feed(b"fghij");
+        assert_eq!(backlog.offset(), 10);
+        assert_eq!(backlog.first_byte_offset(), 3);
+        assert_eq!(backlog.histlen(), 8);
+        assert_eq!(backlog.since(2), None);
+        assert_eq!(backlog.since(3), Some(b"cdefghij".to_vec()));
+
+        backlog.reset(100);
+        assert_eq!(backlog.offset(), 100);
+        assert_eq!(backlog.since(10), None);
+        assert_eq!(backlog.since(101), Some(vec![]));
+    }
+}
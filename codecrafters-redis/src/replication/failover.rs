@@ -0,0 +1,80 @@
+//! Coordinated failover by FAILOVER, switching roles with one of the replicas.
+//!
+//! Like redis, the master node pauses writes and waits till the target replica
+//! acknowledged the whole replication stream, then follows the target and asks it to
+//! promote itself by `PSYNC <replid> <offset> FAILOVER`. The target continues the
+//! stream under a new id, so the old master node needs no full resynchronization.
+//!
+//! Writes are paused till the target accepted the PSYNC, or the failover is aborted.
+
+use std::{net::Ipv4Addr, time::Duration};
+
+use crate::{log::log, replication::ReplicationState, state::ServerState};
+
+/// Progress of FAILOVER, reported as `master_failover_state` in INFO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FailoverState {
+    /// No failover running.
+    None,
+
+    /// Writes are paused, waiting for the target replica to catch up.
+    WaitingForSync,
+
+    /// Following the target replica, asking it to promote itself in PSYNC.
+    InProgress,
+}
+
+impl FailoverState {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            FailoverState::None => "no-failover",
+            FailoverState::WaitingForSync => "waiting-for-sync",
+            FailoverState::InProgress => "failover-in-progress",
+        }
+    }
+}
+
+/// Wait for replica `target` to catch up, or any replica if `None`, then follow it.
+///
+/// Give up after `timeout` unless `force`, which failovers to `target` anyway.
+pub(super) async fn run_failover(
+    rep: ReplicationState,
+    state: ServerState,
+    target: Option<(Ipv4Addr, u16)>,
+    timeout: Option<Duration>,
+    force: bool,
+) {
+    // Subscribe before asking, so ACKs in between are not missed.
+    let mut acked = rep.acked.subscribe();
+    rep.request_acks();
+    let offset = rep.offset();
+    let wait = async {
+        loop {
+            if rep.failover_state() != FailoverState::WaitingForSync {
+                return None;
+            }
+            if let Some(addr) = rep.synced_replica(target, offset) {
+                return Some(addr);
+            }
+            // The sender lives as long as the state.
+            let _ = acked.changed().await;
+        }
+    };
+    let synced = match timeout {
+        Some(v) => tokio::time::timeout(v, wait).await,
+        None => Ok(wait.await),
+    };
+    let addr = match (synced, target) {
+        (Ok(Some(addr)), _) => addr,
+        // Aborted by FAILOVER ABORT.
+        (Ok(None), _) => return,
+        (Err(..), Some(addr)) if force => addr,
+        (Err(..), _) => {
+            log!("[failover] replica not synced in {timeout:?}, aborted");
+            rep.abort_failover(&state);
+            return;
+        }
+    };
+    log!("[failover] following {}:{} as new master", addr.0, addr.1);
+    rep.handoff(addr, state);
+}
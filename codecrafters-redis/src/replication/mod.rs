@@ -1,21 +1,64 @@
 use std::{
-    collections::HashMap,
-    net::{Ipv4Addr, SocketAddr},
-    sync::{Arc, Mutex},
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, Context};
+use bytes::Bytes;
 use serde_redis::{Array, BulkString, Value};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpSocket, TcpStream},
+    io::AsyncReadExt,
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpSocket, TcpStream,
+    },
+    sync::{mpsc, watch},
+    task::JoinHandle,
 };
 
 use crate::{
-    conn::Conn,
+    config::Config,
+    conn::write_all,
     error::{ServerError, ServerResult},
+    info::{ReplicationInfo, SlaveInfo},
+    log::log,
+    pause::PauseMode,
+    state::ServerState,
 };
 
+mod backlog;
+mod failover;
+mod replica;
+
+use backlog::Backlog;
+use failover::run_failover;
+pub(crate) use failover::FailoverState;
+pub(crate) use replica::{follow_master, frame_len};
+
+/// Length of the replication id.
+const REPLID_SIZE: usize = 40;
+
+/// How long writes are paused by FAILOVER at most, it ends the pause once done.
+const FAILOVER_PAUSE: Duration = Duration::from_secs(365 * 24 * 3600);
+
+/// Generate a random string of `len` hex chars.
+pub(crate) fn random_hex(len: usize) -> String {
+    let state = RandomState::new();
+    (0..len)
+        .map(|i| {
+            let mut hasher = state.build_hasher();
+            hasher.write_usize(i);
+            b"0123456789abcdef"[(hasher.finish() % 16) as usize] as char
+        })
+        .collect()
+}
+
 /// Replication state stores info and states about replication feature in redis.
 ///
 /// In replication, there are two kinds of redis instance:
@@ -28,6 +71,59 @@ use crate::{
 #[derive(Debug, Clone)]
 pub(crate) struct ReplicationState {
     inner: Arc<Mutex<ReplicationInner>>,
+
+    /// Notified when any replica acknowledges an offset, for WAIT.
+    acked: Arc<watch::Sender<()>>,
+}
+
+/// A replica connected to current instance.
+///
+/// Commands are written to the connection by a task of its own, so that a slow
+/// replica does not hold others. Dropping it closes the connection.
+#[derive(Debug)]
+struct Replica {
+    /// Id of the connection.
+    id: usize,
+
+    /// Ip of the replica.
+    ip: IpAddr,
+
+    /// Port the replica listens on, 0 if not declared.
+    port: u16,
+
+    /// Commands to write, received by the writer task.
+    sender: mpsc::UnboundedSender<Bytes>,
+
+    /// Bytes sent to the writer task but not written yet.
+    queued: Arc<AtomicUsize>,
+
+    /// The offset last acknowledged by REPLCONF ACK.
+    ack_offset: usize,
+
+    /// When the last REPLCONF ACK received, or when connected if none yet.
+    ack_time: Instant,
+
+    /// Tasks writing commands and reading ACKs.
+    tasks: [JoinHandle<()>; 2],
+}
+
+impl Replica {
+    /// Address the replica listens on, `None` if not declared.
+    fn addr(&self) -> Option<(Ipv4Addr, u16)> {
+        let ip = match self.ip {
+            IpAddr::V4(v) => v,
+            IpAddr::V6(v) => v.to_ipv4_mapped()?,
+        };
+        (self.port != 0).then_some((ip, self.port))
+    }
+}
+
+impl Drop for Replica {
+    fn drop(&mut self) {
+        for task in self.tasks.iter() {
+            task.abort();
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -37,13 +133,28 @@ struct ReplicationInner {
     /// Current instance will act like replica node if this field is not `None`.
     master: Option<(Ipv4Addr, u16)>,
 
-    /// Id of current node.
+    /// Id of the replication stream, random 40 hex chars.
     ///
-    /// In this challenge we use a fixed string instead of random string.
-    id: &'static str,
+    /// Generated at startup, replaced by the id of master node once synced with it.
+    id: String,
+
+    /// Id and the offset where it ends, of the replication stream followed before
+    /// promoted to master node.
+    ///
+    /// Replicas of the old master node can continue with it till the offset.
+    replid2: Option<(String, usize)>,
+
+    /// Port current instance listens on, told to master node in handshake.
+    port: u16,
 
-    /// The offset between server? not used yet.
-    offset: usize,
+    /// Task syncing with master node, if following one.
+    link: Option<JoinHandle<()>>,
+
+    /// Latest commands synced, also tracks the offset in the replication stream.
+    ///
+    /// On master node, commands sent to replicas. On replica node, commands received
+    /// from master node.
+    backlog: Backlog,
 
     /// All connections with replicas.
     ///
@@ -51,40 +162,234 @@ struct ReplicationInner {
     /// keep sync with current instance.
     ///
     /// If this field is not empty, current instance acts like a master node.
-    replica: Vec<TcpStream>,
+    replica: Vec<Replica>,
 
-    /// Record for each connection specified by connection id, how many replicas
-    /// have received the last command when WAIT.
-    ///
-    /// * The key is connection id that start WAIT, and value is the count of replicas
-    ///   have recived last command WAIT for.
-    /// * The value shall be reset to zero if a new command come in for the same id.
-    ///   Because WAIT only wait for last command that came in.
-    replica_recv: HashMap<usize, usize>,
+    /// Stream the RDB snapshot to replicas with EOF marker in full resynchronization.
+    diskless_sync: bool,
+
+    /// Max bytes queued for a replica, the replica is disconnected once exceeded.
+    output_buffer_limit: usize,
+
+    /// Replicas acknowledging nothing for this long are disconnected, zero for never.
+    timeout: Duration,
+
+    failover: FailoverState,
 }
 
 impl ReplicationState {
-    pub(crate) fn new(master: Option<(Ipv4Addr, u16)>) -> Self {
+    pub(crate) fn new(master: Option<(Ipv4Addr, u16)>, port: u16, config: &Config) -> Self {
         let inner = ReplicationInner {
             master,
-            id: "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb",
-            offset: 0,
+            id: random_hex(REPLID_SIZE),
+            replid2: None,
+            port,
+            link: None,
+            backlog: Backlog::new(config.repl_backlog_size as usize),
             replica: vec![],
-            replica_recv: HashMap::new(),
+            diskless_sync: config.repl_diskless_sync,
+            output_buffer_limit: config.replica_output_buffer_limit as usize,
+            timeout: Duration::from_secs(config.repl_timeout),
+            failover: FailoverState::None,
         };
+        let (acked, _) = watch::channel(());
         Self {
             inner: Arc::new(Mutex::new(inner)),
+            acked: Arc::new(acked),
         }
     }
 
-    pub(crate) fn info(&self) -> Value {
+    /// Build the replication section in INFO.
+    pub(crate) fn info(&self) -> ReplicationInfo {
         let lock = self.inner.lock().unwrap();
         lock.info()
     }
 
-    pub(crate) async fn handshake(&self, port: u16) -> ServerResult<TcpStream> {
+    /// Connect to master node and finish the handshake, the replication stream
+    /// continues from master node since.
+    ///
+    /// Like redis, ask to continue the replication stream followed so far, so a
+    /// replica reconnecting to the same master node only receives what it missed.
+    ///
+    /// Return the connection, and whether master node sends the RDB to fully
+    /// resynchronize. During FAILOVER, master node is asked to promote itself and
+    /// continue with the stream of current instance.
+    pub(crate) async fn handshake(&self) -> ServerResult<(TcpStream, bool)> {
+        let (master, port, psync, failover) = {
+            let lock = self.inner.lock().unwrap();
+            let psync = (lock.id.clone(), lock.backlog.offset() + 1);
+            let failover = lock.failover == FailoverState::InProgress;
+            (lock.master, lock.port, psync, failover)
+        };
+        let master = master.ok_or(ServerError::ReplicaConfigNotSet)?;
+        let (conn, reply) = handshake(master, port, psync, failover).await?;
+        let mut lock = self.inner.lock().unwrap();
+        lock.replid2 = None;
+        match reply {
+            PsyncReply::FullResync(id, offset) => {
+                lock.id = id;
+                lock.backlog.reset(offset);
+                Ok((conn, true))
+            }
+            PsyncReply::Continue(id) => {
+                lock.id = id;
+                Ok((conn, false))
+            }
+        }
+    }
+
+    /// Set the task syncing with master node, aborted when master node changes.
+    pub(crate) fn set_link(&self, task: JoinHandle<()>) {
+        let mut lock = self.inner.lock().unwrap();
+        if let Some(link) = lock.link.replace(task) {
+            link.abort();
+        }
+    }
+
+    /// Stop syncing with master node.
+    pub(crate) fn close_link(&self) {
+        let mut lock = self.inner.lock().unwrap();
+        if let Some(link) = lock.link.take() {
+            link.abort();
+        }
+    }
+
+    /// Close the connections with all replicas, they reconnect once current instance
+    /// is back.
+    pub(crate) fn disconnect_replicas(&self) {
+        let mut lock = self.inner.lock().unwrap();
+        lock.replica.clear();
+    }
+
+    /// Get the address of master node, `None` if current instance is a master.
+    pub(crate) fn master(&self) -> Option<(Ipv4Addr, u16)> {
         let lock = self.inner.lock().unwrap();
-        lock.handshake(port).await
+        lock.master
+    }
+
+    /// Follow master node at `master` at runtime, for REPLICAOF, or stop following and
+    /// act like a master node if `None`.
+    ///
+    /// Replicas of current instance are disconnected when following a new master, to
+    /// resync with the new replication stream. When promoted, the replication
+    /// stream continues under a new id, and replicas of the old master can still
+    /// continue with the old id, same as redis.
+    pub(crate) fn replicaof(&self, master: Option<(Ipv4Addr, u16)>, state: ServerState) {
+        self.close_link();
+        {
+            let mut lock = self.inner.lock().unwrap();
+            let old = std::mem::replace(&mut lock.master, master);
+            match master {
+                Some(_) => lock.replica.clear(),
+                None if old.is_some() => {
+                    let id = std::mem::replace(&mut lock.id, random_hex(REPLID_SIZE));
+                    lock.replid2 = Some((id, lock.backlog.offset() + 1));
+                }
+                None => { /* Already a master */ }
+            }
+        }
+        if master.is_some() {
+            self.set_link(tokio::spawn(follow_master(self.clone(), state, None)));
+        }
+    }
+
+    pub(crate) fn failover_state(&self) -> FailoverState {
+        let lock = self.inner.lock().unwrap();
+        lock.failover
+    }
+
+    /// Start FAILOVER to the replica listening on `target`, or the first replica
+    /// caught up if `None`.
+    ///
+    /// Writes are paused till the failover ends. Wait at most `timeout` for the
+    /// replica to catch up, then give up, or failover anyway if `force`.
+    ///
+    /// Return the error message if the failover can not start.
+    pub(crate) fn failover(
+        &self,
+        target: Option<(Ipv4Addr, u16)>,
+        timeout: Option<Duration>,
+        force: bool,
+        state: ServerState,
+    ) -> Result<(), &'static str> {
+        let mut lock = self.inner.lock().unwrap();
+        if lock.failover != FailoverState::None {
+            return Err("FAILOVER already in progress.");
+        }
+        if lock.master.is_some() {
+            return Err("FAILOVER is not valid when server is a replica.");
+        }
+        if lock.replica.is_empty() {
+            return Err("FAILOVER requires connected replicas.");
+        }
+        if target.is_some_and(|addr| !lock.replica.iter().any(|x| x.addr() == Some(addr))) {
+            return Err("FAILOVER target HOST and PORT is not a replica.");
+        }
+        lock.failover = FailoverState::WaitingForSync;
+        drop(lock);
+        state.pause().pause(
+            PauseMode::Write,
+            tokio::time::Instant::now() + FAILOVER_PAUSE,
+        );
+        tokio::spawn(run_failover(self.clone(), state, target, timeout, force));
+        Ok(())
+    }
+
+    /// Abort the failover in progress, for FAILOVER ABORT or when it failed.
+    ///
+    /// If already following the target replica, act like a master node again. Return
+    /// false if no failover is in progress.
+    pub(crate) fn abort_failover(&self, state: &ServerState) -> bool {
+        let failover = {
+            let mut lock = self.inner.lock().unwrap();
+            std::mem::replace(&mut lock.failover, FailoverState::None)
+        };
+        match failover {
+            FailoverState::None => return false,
+            FailoverState::WaitingForSync => {}
+            FailoverState::InProgress => self.replicaof(None, state.clone()),
+        }
+        state.pause().unpause();
+        // Wake up the failover task waiting for ACKs.
+        self.acked.send_replace(());
+        true
+    }
+
+    /// Follow replica `addr` as the new master node, if the failover is not aborted.
+    fn handoff(&self, addr: (Ipv4Addr, u16), state: ServerState) {
+        {
+            let mut lock = self.inner.lock().unwrap();
+            if lock.failover != FailoverState::WaitingForSync {
+                return;
+            }
+            lock.failover = FailoverState::InProgress;
+        }
+        self.replicaof(Some(addr), state);
+    }
+
+    /// End the failover once the new master node accepted PSYNC, return false if no
+    /// failover is in progress.
+    pub(crate) fn finish_failover(&self) -> bool {
+        let mut lock = self.inner.lock().unwrap();
+        let in_progress = lock.failover == FailoverState::InProgress;
+        if in_progress {
+            lock.failover = FailoverState::None;
+        }
+        in_progress
+    }
+
+    /// Address of a replica that acknowledged `offset`, the one listening on `target`
+    /// if specified.
+    fn synced_replica(
+        &self,
+        target: Option<(Ipv4Addr, u16)>,
+        offset: usize,
+    ) -> Option<(Ipv4Addr, u16)> {
+        let lock = self.inner.lock().unwrap();
+        lock.replica
+            .iter()
+            .filter(|x| x.ack_offset >= offset)
+            .filter_map(Replica::addr)
+            .find(|addr| target.is_none_or(|x| x == *addr))
     }
 
     pub(crate) fn id(&self) -> String {
@@ -92,283 +397,499 @@ impl ReplicationState {
         lock.id()
     }
 
-    pub(crate) async fn sync_command(&mut self, args: Array) -> usize {
+    /// Check whether current instance is a replica of some master node.
+    pub(crate) fn is_replica(&self) -> bool {
+        let lock = self.inner.lock().unwrap();
+        lock.master.is_some()
+    }
+
+    pub(crate) fn diskless_sync(&self) -> bool {
+        let lock = self.inner.lock().unwrap();
+        lock.diskless_sync
+    }
+
+    pub(crate) fn sync_command(&mut self, args: Array) -> usize {
         let mut lock = self.inner.lock().unwrap();
-        lock.sync_command(args).await
+        lock.sync_command(args)
     }
 
-    pub(crate) fn set_replica(&mut self, socket: TcpStream) {
+    /// Add connection `id` on `socket` as a replica listening on `port`, after PSYNC.
+    ///
+    /// Commands are synced to it from now on, and ACKs it sends are read in
+    /// background. The replica is removed once the connection is broken.
+    pub(crate) fn set_replica(&mut self, id: usize, socket: TcpStream, port: Option<u16>) {
+        let ip = socket
+            .peer_addr()
+            .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |x| x.ip());
+        let (reader, writer) = socket.into_split();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let queued = Arc::new(AtomicUsize::new(0));
         let mut lock = self.inner.lock().unwrap();
-        lock.set_replica(socket)
+        let timeout = lock.timeout;
+        let rep = self.clone();
+        let write_task = tokio::spawn({
+            let queued = queued.clone();
+            async move {
+                if let Err(e) = write_commands(writer, receiver, &queued).await {
+                    rep.remove_replica(id, &format!("failed to write: {e}"));
+                }
+            }
+        });
+        let rep = self.clone();
+        let read_task = tokio::spawn(async move {
+            let reason = read_acks(&rep, id, reader, timeout).await;
+            rep.remove_replica(id, &reason);
+        });
+        let tasks = [write_task, read_task];
+        lock.replica.push(Replica {
+            id,
+            ip,
+            port: port.unwrap_or_default(),
+            sender,
+            queued,
+            ack_offset: 0,
+            ack_time: Instant::now(),
+            tasks,
+        });
     }
 
-    pub(crate) fn add_offset(&mut self, len: usize) {
+    /// Append `data` received from master node to the replication stream.
+    pub(crate) fn feed(&mut self, data: &[u8]) {
         let mut lock = self.inner.lock().unwrap();
-        lock.offset += len
+        lock.backlog.feed(data)
     }
 
+    /// Offset of the last byte in the replication stream.
     pub(crate) fn offset(&self) -> usize {
         let lock = self.inner.lock().unwrap();
-        lock.offset
+        lock.backlog.offset()
     }
 
-    /// Get the count of replicas that received last command if connection
-    /// starts WAIT.
+    /// Get the bytes a replica missed for PSYNC `replid` `offset`, to continue the
+    /// replication stream instead of a full resynchronization.
     ///
-    /// 1. Several replicas connected.
-    /// 2. A connection (not replica) came in, id is `conn_id`.
-    /// 3. The connection (id is `conn_id`) sent a command.
-    /// 4. Several replicas received the command in step 2.
-    /// 5. The connection (id is `conn_id`) starts WAIT.
-    /// 6. (Here) Return the count of replicas that received command in step 2.
-    pub(crate) fn replica_count(&self, conn_id: usize) -> usize {
-        // How to check if a connection is closed by peer?
-        // we should drop those ones.
+    /// Return `None` if `replid` is not the current stream or the bytes since
+    /// `offset` are not in the backlog.
+    pub(crate) fn partial_sync(&self, replid: &str, offset: usize) -> Option<Vec<u8>> {
         let lock = self.inner.lock().unwrap();
-        lock.replica_recv
-            .get(&conn_id)
-            .map(|x| x.to_owned())
-            .unwrap_or_default()
+        let same_stream = match &lock.replid2 {
+            _ if replid == lock.id => true,
+            Some((id, end)) => replid == id && offset <= *end,
+            None => false,
+        };
+        if !same_stream {
+            return None;
+        }
+        lock.backlog.since(offset)
     }
 
-    pub(crate) fn replica_reset(&mut self, conn_id: usize) {
+    /// Record replica `id` acknowledged `offset`.
+    fn ack(&self, id: usize, offset: usize) {
         let mut lock = self.inner.lock().unwrap();
-        match lock.replica_recv.get_mut(&conn_id) {
-            Some(v) => *v = 0,
-            None => {
-                lock.replica_recv.insert(conn_id, 0);
-            }
+        if let Some(replica) = lock.replica.iter_mut().find(|x| x.id == id) {
+            replica.ack_offset = offset;
+            replica.ack_time = Instant::now();
         }
+        drop(lock);
+        self.acked.send_replace(());
     }
 
-    /// Increase the count of replicas received command by last command.
-    pub(crate) fn replica_increase(&mut self, conn_id: usize, count: usize) {
+    /// Remove replica `id` and close the connection, for `reason`.
+    fn remove_replica(&self, id: usize, reason: &str) {
         let mut lock = self.inner.lock().unwrap();
-        match lock.replica_recv.get_mut(&conn_id) {
-            Some(v) => *v += count,
-            None => {
-                // Unreachable.
-                lock.replica_recv.insert(conn_id, 1);
-            }
-        }
+        let Some(pos) = lock.replica.iter().position(|x| x.id == id) else {
+            return;
+        };
+        log!("[replica {id}] disconnected: {reason}");
+        // Tasks of the replica are aborted when dropped, the caller included, do it
+        // last.
+        let replica = lock.replica.remove(pos);
+        drop(lock);
+        drop(replica);
     }
 
-    pub(crate) async fn replica_notify(&mut self) {
-        let mut lock = self.inner.lock().unwrap();
+    /// Count of replicas acknowledged `offset`.
+    pub(crate) fn acked_count(&self, offset: usize) -> usize {
+        let lock = self.inner.lock().unwrap();
+        lock.replica
+            .iter()
+            .filter(|x| x.ack_offset >= offset)
+            .count()
+    }
 
-        let ack = serde_redis::to_vec(&Value::Array(Array::with_values(vec![
+    /// Ask all replicas to acknowledge the offset they processed, by sending
+    /// `REPLCONF GETACK *` in the replication stream.
+    pub(crate) fn request_acks(&self) {
+        let getack = Array::with_values(vec![
             Value::BulkString(BulkString::new("REPLCONF")),
             Value::BulkString(BulkString::new("GETACK")),
             Value::BulkString(BulkString::new("*")),
-        ])))
-        .unwrap();
-
-        tokio::task::block_in_place(move || {
-            tokio::runtime::Handle::current().block_on(async move {
-                for x in lock.replica.iter_mut() {
-                    let _ = x.write(&ack).await;
-                }
-            })
-        });
+        ]);
+        self.clone().sync_command(getack);
     }
-}
 
-impl ReplicationInner {
-    fn info(&self) -> Value {
-        let mut buf = vec![];
-        buf.extend(b"# Replication\n");
-        if self.master.is_some() {
-            buf.extend(b"role:slave\n");
-        } else {
-            buf.extend(b"role:master\n");
+    /// Send PING to replicas in the replication stream, so that they know the master
+    /// node is alive even if no command is written.
+    ///
+    /// Nothing is sent on replica nodes, they proxy the stream of their master node.
+    pub(crate) fn ping_replicas(&self) {
+        let mut lock = self.inner.lock().unwrap();
+        if lock.master.is_some() || lock.replica.is_empty() {
+            return;
         }
+        let ping = Array::with_values(vec![Value::BulkString(BulkString::new("PING"))]);
+        lock.sync_command(ping);
+    }
 
-        buf.extend(b"master_replid:");
-        buf.extend(self.id.as_bytes());
-        buf.push(b'\n');
-
-        buf.extend(b"master_repl_offset:");
-        buf.extend(self.offset.to_string().as_bytes());
-        buf.push(b'\n');
+    /// Wait till `count` replicas acknowledged `offset`, return the count of them.
+    pub(crate) async fn wait_acks(&self, offset: usize, count: usize) -> usize {
+        // Subscribe before counting, so ACKs in between are not missed.
+        let mut acked = self.acked.subscribe();
+        loop {
+            let n = self.acked_count(offset);
+            if n >= count {
+                return n;
+            }
+            // The sender lives as long as the state.
+            let _ = acked.changed().await;
+        }
+    }
+}
 
-        Value::BulkString(BulkString::new(buf))
+/// Write commands from `receiver` to a replica on `writer`, till the replica is
+/// removed.
+///
+/// `queued` is decreased by bytes written. Return the error if failed to write.
+async fn write_commands(
+    mut writer: OwnedWriteHalf,
+    mut receiver: mpsc::UnboundedReceiver<Bytes>,
+    queued: &AtomicUsize,
+) -> ServerResult<()> {
+    while let Some(data) = receiver.recv().await {
+        write_all(&mut writer, &data).await?;
+        queued.fetch_sub(data.len(), Ordering::Relaxed);
     }
+    Ok(())
+}
 
-    async fn handshake(&self, port: u16) -> ServerResult<TcpStream> {
-        let master_addr = match self.master {
-            Some(v) => v,
-            None => return Err(ServerError::ReplicaConfigNotSet),
+/// Read messages from replica `id` on `reader`, record offsets acknowledged by
+/// `REPLCONF ACK <offset>`.
+///
+/// Like redis, replicas send ACK every second, those acknowledging nothing in
+/// `timeout` are considered dead. Return why the connection is broken.
+async fn read_acks(
+    rep: &ReplicationState,
+    id: usize,
+    mut reader: OwnedReadHalf,
+    timeout: Duration,
+) -> String {
+    let mut buf = [0u8; 1024];
+    // Bytes received but not parsed yet, as a message may be split into reads.
+    let mut pending = vec![];
+    let mut deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let read = reader.read(&mut buf);
+        let n = if timeout.is_zero() {
+            read.await
+        } else {
+            match tokio::time::timeout_at(deadline, read).await {
+                Ok(v) => v,
+                Err(..) => return format!("timeout, no ACK received in {timeout:?}"),
+            }
         };
-        let socket = TcpSocket::new_v4()
-            .context("[replica] failed to instaniate the socket")
-            .map_err(ServerError::Custom)?;
-        let mut conn = socket
-            .connect(SocketAddr::new(
-                std::net::IpAddr::V4(master_addr.0),
-                master_addr.1,
-            ))
-            .await
-            .context("[replica] failed to connect to master")
-            .map_err(ServerError::Custom)?;
+        let n = match n {
+            Ok(0) => return "connection closed".to_string(),
+            Ok(n) => n,
+            Err(e) => return format!("failed to read: {e}"),
+        };
+        pending.extend_from_slice(&buf[0..n]);
+        let mut pos = 0;
+        loop {
+            let len = match frame_len(&pending[pos..]) {
+                Ok(Some(v)) => v,
+                Ok(None) => break,
+                Err(e) => return format!("malformed message: {e}"),
+            };
+            let message = serde_redis::from_bytes::<Array>(&pending[pos..pos + len]);
+            pos += len;
+            let mut args = match message {
+                Ok(v) => v,
+                Err(e) => {
+                    log!("[replica {id}] invalid message: {e}");
+                    continue;
+                }
+            };
+            let args = std::iter::from_fn(|| args.pop_front_bulk_string()).collect::<Vec<_>>();
+            match args.as_slice() {
+                [cmd, sub, offset]
+                    if cmd.eq_ignore_ascii_case("REPLCONF") && sub.eq_ignore_ascii_case("ACK") =>
+                {
+                    match offset.parse() {
+                        Ok(offset) => {
+                            rep.ack(id, offset);
+                            deadline = tokio::time::Instant::now() + timeout;
+                        }
+                        Err(..) => log!("[replica {id}] invalid ACK offset {offset}"),
+                    }
+                }
+                _ => log!("[replica {id}] unexpected message {args:?}"),
+            }
+        }
+        pending.drain(..pos);
+    }
+}
 
-        let mut buf = [0u8; 1024];
+impl ReplicationInner {
+    fn info(&self) -> ReplicationInfo {
+        ReplicationInfo {
+            role: if self.master.is_some() {
+                "slave"
+            } else {
+                "master"
+            },
+            connected_slaves: self.replica.len(),
+            slaves: self
+                .replica
+                .iter()
+                .map(|x| SlaveInfo {
+                    ip: x.ip.to_string(),
+                    port: x.port,
+                    state: "online",
+                    offset: x.ack_offset,
+                    lag: x.ack_time.elapsed().as_secs(),
+                })
+                .collect(),
+            master_failover_state: self.failover.name(),
+            master_replid: self.id.clone(),
+            master_repl_offset: self.backlog.offset(),
+            repl_backlog_size: self.backlog.size(),
+            repl_backlog_first_byte_offset: self.backlog.first_byte_offset(),
+            repl_backlog_histlen: self.backlog.histlen(),
+        }
+    }
 
-        // Send PING
+    fn id(&self) -> String {
+        self.id.clone()
+    }
 
-        let ping = Value::Array(Array::with_values(vec![Value::BulkString(
-            BulkString::new("PING"),
-        )]));
-        let n = conn
-            .write(serde_redis::to_vec(&ping).unwrap().as_slice())
-            .await
-            .context("[replica] failed to send PING message")
-            .map_err(ServerError::Custom)?;
-        println!("[replica] PING: sent {n} bytes");
-        let n = conn
-            .read(&mut buf)
-            .await
-            .context("failed to read PING reply")
-            .map_err(ServerError::Custom)?;
-        match serde_redis::from_bytes(&buf[0..n])
-            .context("failed to read PING response:")
-            .map_err(ServerError::Custom)?
-        {
-            Value::SimpleString(s) if s.value() == "PONG" => { /* Correct response */ }
-            v => {
-                return Err(ServerError::Custom(anyhow!(
-                    "[replica] invalid PING response: {v:?}"
-                )))
+    /// Sync command `args` to all replicas, and append it to the backlog.
+    ///
+    /// Commands are queued to the writer task of each replica. Replicas with more than
+    /// `output_buffer_limit` bytes queued are disconnected, they shall resync.
+    ///
+    /// Return the count of replicas intend to receive the command.
+    fn sync_command(&mut self, args: Array) -> usize {
+        let data = Bytes::from(serde_redis::to_vec(&Value::Array(args)).unwrap());
+        self.backlog.feed(&data);
+        let limit = self.output_buffer_limit;
+        self.replica.retain(|replica| {
+            let queued = replica.queued.fetch_add(data.len(), Ordering::Relaxed) + data.len();
+            if limit > 0 && queued > limit {
+                log!(
+                    "[replica {}] output buffer of {queued} bytes exceeds limit, disconnected",
+                    replica.id
+                );
+                return false;
             }
-        }
+            // Fails if the writer task ended, the connection is broken.
+            let _ = replica.sender.send(data.clone());
+            true
+        });
+        self.replica.len()
+    }
+}
 
-        // Send REPLCONF listening-port
+/// Reply of master node to PSYNC.
+enum PsyncReply {
+    /// Full resynchronization with the replication id and offset of master node, the
+    /// RDB follows.
+    FullResync(String, usize),
 
-        let replconf = Value::Array(Array::with_values(vec![
-            Value::BulkString(BulkString::new("REPLCONF")),
-            Value::BulkString(BulkString::new("listening-port")),
-            Value::BulkString(BulkString::new(port.to_string())),
-        ]));
-        let n = conn
-            .write(serde_redis::to_vec(&replconf).unwrap().as_slice())
-            .await
-            .context("failed to send REPLCONF listening-port")
-            .map_err(ServerError::Custom)?;
-        println!("[replica] REPLCONF listening-port: sent {n} bytes");
-        let n = conn
-            .read(&mut buf)
-            .await
-            .context("failed to read REPLCONF listening-port reply")
-            .map_err(ServerError::Custom)?;
-        match serde_redis::from_bytes(&buf[0..n])
-            .context("failed to read REPLCONF listening-port response:")
-            .map_err(ServerError::Custom)?
-        {
-            Value::SimpleString(s) if s.value() == "OK" => { /* Correct response */ }
-            v => {
-                return Err(ServerError::Custom(anyhow!(
-                    "[replica] invalid REPLCONF listening-port response: {v:?}"
-                )))
-            }
+    /// Continue the replication stream under the id of master node.
+    Continue(String),
+}
+
+/// Connect to master node at `master_addr` and finish the handshake, telling it we
+/// listen on `port`.
+///
+/// `psync` is the replication id and the next offset of current instance, to
+/// continue with. Set `failover` to ask master node to promote itself in FAILOVER.
+async fn handshake(
+    master_addr: (Ipv4Addr, u16),
+    port: u16,
+    psync: (String, usize),
+    failover: bool,
+) -> ServerResult<(TcpStream, PsyncReply)> {
+    let socket = TcpSocket::new_v4()
+        .context("[replica] failed to instaniate the socket")
+        .map_err(ServerError::Custom)?;
+    let mut conn = socket
+        .connect(SocketAddr::new(
+            std::net::IpAddr::V4(master_addr.0),
+            master_addr.1,
+        ))
+        .await
+        .context("[replica] failed to connect to master")
+        .map_err(ServerError::Custom)?;
+
+    let mut buf = [0u8; 1024];
+
+    // Send PING
+
+    let ping = Value::Array(Array::with_values(vec![Value::BulkString(
+        BulkString::new("PING"),
+    )]));
+    let data = serde_redis::to_vec(&ping).unwrap();
+    write_all(&mut conn, &data)
+        .await
+        .context("[replica] failed to send PING message")
+        .map_err(ServerError::Custom)?;
+    log!("[replica] PING: sent {} bytes", data.len());
+    let n = conn
+        .read(&mut buf)
+        .await
+        .context("failed to read PING reply")
+        .map_err(ServerError::Custom)?;
+    match serde_redis::from_bytes(&buf[0..n])
+        .context("failed to read PING response:")
+        .map_err(ServerError::Custom)?
+    {
+        Value::SimpleString(s) if s.value() == "PONG" => { /* Correct response */ }
+        v => {
+            return Err(ServerError::Custom(anyhow!(
+                "[replica] invalid PING response: {v:?}"
+            )))
         }
+    }
 
-        // Send REPLCONF listening-port
+    // Send REPLCONF listening-port
+
+    let replconf = Value::Array(Array::with_values(vec![
+        Value::BulkString(BulkString::new("REPLCONF")),
+        Value::BulkString(BulkString::new("listening-port")),
+        Value::BulkString(BulkString::new(port.to_string())),
+    ]));
+    let data = serde_redis::to_vec(&replconf).unwrap();
+    write_all(&mut conn, &data)
+        .await
+        .context("failed to send REPLCONF listening-port")
+        .map_err(ServerError::Custom)?;
+    log!(
+        "[replica] REPLCONF listening-port: sent {} bytes",
+        data.len()
+    );
+    let n = conn
+        .read(&mut buf)
+        .await
+        .context("failed to read REPLCONF listening-port reply")
+        .map_err(ServerError::Custom)?;
+    match serde_redis::from_bytes(&buf[0..n])
+        .context("failed to read REPLCONF listening-port response:")
+        .map_err(ServerError::Custom)?
+    {
+        Value::SimpleString(s) if s.value() == "OK" => { /* Correct response */ }
+        v => {
+            return Err(ServerError::Custom(anyhow!(
+                "[replica] invalid REPLCONF listening-port response: {v:?}"
+            )))
+        }
+    }
 
-        let replconf = Value::Array(Array::with_values(vec![
-            Value::BulkString(BulkString::new("REPLCONF")),
-            Value::BulkString(BulkString::new("capa")),
-            Value::BulkString(BulkString::new("psync2")),
-        ]));
-        let n = conn
-            .write(serde_redis::to_vec(&replconf).unwrap().as_slice())
-            .await
-            .context("failed to send REPLCONF capa")
-            .map_err(ServerError::Custom)?;
-        println!("[replica] REPLCONF capa: sent {n} bytes");
-        let n = conn
-            .read(&mut buf)
-            .await
-            .context("failed to read REPLCONF capa reply")
-            .map_err(ServerError::Custom)?;
-        match serde_redis::from_bytes(&buf[0..n])
-            .context("failed to read REPLCONF capa response:")
-            .map_err(ServerError::Custom)?
-        {
-            Value::SimpleString(s) if s.value() == "OK" => { /* Correct response */ }
-            v => {
-                return Err(ServerError::Custom(anyhow!(
-                    "[replica] invalid REPLCONF capa response: {v:?}"
-                )))
-            }
+    // Send REPLCONF listening-port
+
+    let replconf = Value::Array(Array::with_values(vec![
+        Value::BulkString(BulkString::new("REPLCONF")),
+        Value::BulkString(BulkString::new("capa")),
+        Value::BulkString(BulkString::new("psync2")),
+    ]));
+    let data = serde_redis::to_vec(&replconf).unwrap();
+    write_all(&mut conn, &data)
+        .await
+        .context("failed to send REPLCONF capa")
+        .map_err(ServerError::Custom)?;
+    log!("[replica] REPLCONF capa: sent {} bytes", data.len());
+    let n = conn
+        .read(&mut buf)
+        .await
+        .context("failed to read REPLCONF capa reply")
+        .map_err(ServerError::Custom)?;
+    match serde_redis::from_bytes(&buf[0..n])
+        .context("failed to read REPLCONF capa response:")
+        .map_err(ServerError::Custom)?
+    {
+        Value::SimpleString(s) if s.value() == "OK" => { /* Correct response */ }
+        v => {
+            return Err(ServerError::Custom(anyhow!(
+                "[replica] invalid REPLCONF capa response: {v:?}"
+            )))
         }
+    }
 
-        // Send PSYNC
+    // Send PSYNC
 
-        let psync = Value::Array(Array::with_values(vec![
-            Value::BulkString(BulkString::new("PSYNC")),
-            Value::BulkString(BulkString::new("?")),
-            Value::BulkString(BulkString::new("-1")),
-        ]));
-        let n = conn
-            .write(serde_redis::to_vec(&psync).unwrap().as_slice())
-            .await
-            .context("failed to send psync")
-            .map_err(ServerError::Custom)?;
-        println!("[replica] psync: sent {n} bytes");
-        // +FULLRESYNC <REPL_ID> 0\r\n
-        // 1    +     10          +  1  + 40 +  1  +  1  + 2
-        // ' '     'FULLRESYNC'     ' '   ID   ' '   '0'   '\r\n'
-        // 56bytes
-        let mut psync_resp_buf = [0u8; 56];
-        conn.read_exact(&mut psync_resp_buf)
+    let (id, offset) = psync;
+    let mut psync = vec!["PSYNC".to_string(), id, offset.to_string()];
+    if failover {
+        psync.push("FAILOVER".into());
+    }
+    let psync = Value::Array(
+        psync
+            .into_iter()
+            .map(|x| Value::BulkString(BulkString::new(x)))
+            .collect(),
+    );
+    let data = serde_redis::to_vec(&psync).unwrap();
+    write_all(&mut conn, &data)
+        .await
+        .context("failed to send psync")
+        .map_err(ServerError::Custom)?;
+    log!("[replica] psync: sent {} bytes", data.len());
+    // +FULLRESYNC <REPL_ID> <OFFSET>\r\n, or +CONTINUE <REPL_ID>\r\n
+    //
+    // Read byte by byte as the RDB file follows right after it.
+    let mut psync_resp_buf = vec![];
+    while !psync_resp_buf.ends_with(b"\r\n") {
+        let mut ch_buf = [0u8; 1];
+        conn.read_exact(&mut ch_buf)
             .await
             .context("failed to read psync reply")
             .map_err(ServerError::Custom)?;
-        let master_id = match serde_redis::from_bytes(&psync_resp_buf)
-            .context("failed to read psync response:")
-            .map_err(ServerError::Custom)?
-        {
-            Value::SimpleString(s) => {
-                let segs = s.value().split(' ').collect::<Vec<_>>();
-                if segs.len() == 3 && segs[0] == "FULLRESYNC" && segs[2] == "0" {
-                    segs[1].to_string()
-                } else {
+        psync_resp_buf.push(ch_buf[0]);
+    }
+    let reply = match serde_redis::from_bytes(&psync_resp_buf)
+        .context("failed to read psync response:")
+        .map_err(ServerError::Custom)?
+    {
+        Value::SimpleString(s) => {
+            let segs = s.value().split(' ').collect::<Vec<_>>();
+            let offset = segs.get(2).and_then(|x| x.parse::<usize>().ok());
+            match (segs.len(), segs[0], offset) {
+                (3, "FULLRESYNC", Some(offset)) => {
+                    PsyncReply::FullResync(segs[1].to_string(), offset)
+                }
+                (2, "CONTINUE", _) => PsyncReply::Continue(segs[1].to_string()),
+                _ => {
                     return Err(ServerError::Custom(anyhow!(
                         "invalid psync response: {s:?}"
                     )));
                 }
             }
-            v => {
-                return Err(ServerError::Custom(anyhow!(
-                    "[replica] invalid REPLCONF capa response: {v:?}"
-                )))
-            }
-        };
-
-        println!("[replica] handshake success, master id is {master_id}");
-
-        Ok(conn)
-    }
-
-    fn id(&self) -> String {
-        self.id.into()
-    }
+        }
+        v => {
+            return Err(ServerError::Custom(anyhow!(
+                "[replica] invalid psync response: {v:?}"
+            )))
+        }
+    };
 
-    /// Sync command `args` to all replicas.
-    ///
-    /// Return the count of replicas intend to receive the command.
-    async fn sync_command(&mut self, args: Array) -> usize {
-        let mut synced_replica_count = 0;
-        for conn in self.replica.iter_mut() {
-            let mut conn = Conn::new(10000, conn);
-            if let Err(e) = conn.write_value(Value::Array(args.clone())).await {
-                conn.log(format!("failed to replica sync: {e}"));
-            }
-            synced_replica_count += 1;
+    match &reply {
+        PsyncReply::FullResync(id, offset) => {
+            log!("[replica] handshake success, master id is {id}, offset is {offset}")
+        }
+        PsyncReply::Continue(id) => {
+            log!("[replica] handshake success, continue with master id {id}")
         }
-        synced_replica_count
     }
 
-    fn set_replica(&mut self, socket: TcpStream) {
-        self.replica.push(socket);
-    }
+    Ok((conn, reply))
 }
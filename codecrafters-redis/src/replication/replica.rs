@@ -0,0 +1,338 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde_redis::{Array, BulkString, Value};
+use tokio::{io::AsyncReadExt, net::TcpStream, time::Instant};
+
+use crate::{
+    command::{dispatch_command, DispatchResult},
+    conn::{write_all, Conn},
+    log::log,
+    replication::{FailoverState, ReplicationState},
+    state::ServerState,
+    storage::Storage,
+};
+
+/// Interval of sending REPLCONF ACK to master node.
+const ACK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Follow master node, till the link is closed by REPLICAOF.
+///
+/// `link` is the connection already handshaked at startup and whether master node
+/// sends the RDB, if any. Like redis, reconnect once the connection is lost, and
+/// continue the replication stream where it stopped if master node still has it.
+pub(crate) async fn follow_master(
+    rep: ReplicationState,
+    state: ServerState,
+    mut link: Option<(TcpStream, bool)>,
+) {
+    loop {
+        let (conn, full_sync) = match link.take() {
+            Some(v) => v,
+            None => match connect_master(&rep, &state).await {
+                Some(v) => v,
+                None => return,
+            },
+        };
+        if let Err(e) = run_replica(rep.clone(), conn, full_sync, state.clone()).await {
+            log!("[replica] lost the link with master node, reconnecting: {e}");
+        }
+    }
+}
+
+/// Connect to master node and finish the handshake.
+///
+/// Like redis, retry every second till connected. During FAILOVER, the failover is
+/// aborted instead if the new master node rejects to promote itself, and `None` is
+/// returned.
+async fn connect_master(rep: &ReplicationState, state: &ServerState) -> Option<(TcpStream, bool)> {
+    let (conn, full_sync) = loop {
+        match rep.handshake().await {
+            Ok(v) => break v,
+            Err(e) if rep.failover_state() == FailoverState::InProgress => {
+                log!("[replica] failover handshake failed, act as master again: {e}");
+                rep.abort_failover(state);
+                return None;
+            }
+            Err(e) => log!("[replica] handshake failed, retry in 1 second: {e}"),
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    };
+    if rep.finish_failover() {
+        log!("[replica] failover finished");
+        state.pause().unpause();
+    }
+    if let Err(e) = state.config().get().tune_socket(&conn) {
+        log!("[replica] failed to set socket options: {e:?}");
+    }
+    Some((conn, full_sync))
+}
+
+/// Run the loop where we act like replica node: receive commands provided
+/// by master node and apply those commands. This loop keeps current instance
+/// sync with master node.
+///
+/// Set `full_sync` to true if master node sends the RDB first, otherwise the
+/// replication stream continues on the current dataset.
+async fn run_replica(
+    mut rep: ReplicationState,
+    mut rep_master_conn: TcpStream,
+    full_sync: bool,
+    mut state: ServerState,
+) -> Result<()> {
+    log!("[main][replica] spawning replica task");
+    if full_sync {
+        receive_rdb(&mut rep_master_conn, state.storage()).await?;
+    }
+
+    let mut buf = [0u8; 1024];
+    // Bytes received from master node but not executed yet.
+    //
+    // A command may be split into multiple reads, keep the incomplete part till
+    // the rest arrives.
+    let mut pending = vec![];
+    // Like redis, acknowledge the offset processed every second, so master node
+    // knows the connection is alive. The offset is also acknowledged after applying
+    // commands, so that master node knows the progress without waiting.
+    let mut ack_interval = tokio::time::interval_at(Instant::now() + ACK_INTERVAL, ACK_INTERVAL);
+    let mut acked = rep.offset();
+    // Receving commands from master node.
+    loop {
+        log!("[main][replica] waiting for commands to sync");
+        let n = tokio::select! {
+            n = rep_master_conn.read(&mut buf) => {
+                n.context("failed to get read replica master connection")?
+            }
+            _ = ack_interval.tick() => {
+                acked = rep.offset();
+                send_ack(&mut rep_master_conn, acked).await?;
+                continue;
+            }
+        };
+        if n == 0 {
+            bail!("connection closed by master node");
+        }
+
+        log!(
+            "[main][replica] read {n} bytes as command to sync, from master node: {:?}",
+            String::from_utf8_lossy(&buf[0..n])
+        );
+        pending.extend_from_slice(&buf[0..n]);
+
+        // Record where we are executing commands in the parsed data.
+        let mut exec_pos = 0;
+        while let Some(frame) = frame_len(&pending[exec_pos..]).with_context(|| {
+            format!(
+                "malformed command from master node after offset {}",
+                rep.offset()
+            )
+        })? {
+            let (message, len): (Array, usize) =
+                serde_redis::from_bytes_len(&pending[exec_pos..exec_pos + frame])
+                    .context("failed to deserialize replia master message")?;
+            if len != frame {
+                bail!("parsed {len} bytes command from master node, expected {frame} bytes");
+            }
+            log!(
+                "[main][replica] parsed {len} bytes command, total is {}",
+                pending.len()
+            );
+            let rep2 = rep.clone();
+            let mut conn = Conn::new_sync(30000, &mut rep_master_conn);
+            match dispatch_command(&mut conn, message, &mut state, rep2)
+                .await
+                .context("failed to dispatch replica command from master")?
+            {
+                DispatchResult::None
+                | DispatchResult::Replica
+                | DispatchResult::Monitor
+                | DispatchResult::ReplicaSync => { /* Do nothing */ }
+                DispatchResult::ReplicaSyncEffects(effects) => {
+                    // Here in this async task we are acting like replica node.
+                    // So every command that need to be synced should be applied on current
+                    // instance, because we are the replica node, the node need to be synced.
+                    log!("[main][replica] sync command from master node: {effects:?}");
+                    state.aof().append(&effects);
+                }
+            }
+            // Only REPLCONF GETACK is answered to master node.
+            conn.flush()
+                .await
+                .context("failed to reply to master node")?;
+            rep.feed(&pending[exec_pos..exec_pos + len]);
+            exec_pos += len;
+        }
+        pending.drain(..exec_pos);
+        if rep.offset() != acked {
+            acked = rep.offset();
+            send_ack(&mut rep_master_conn, acked).await?;
+        }
+    }
+}
+
+/// Receive the RDB sent by master node in full resynchronization, and replace the
+/// dataset in `storage` with it.
+async fn receive_rdb(rep_master_conn: &mut TcpStream, storage: &Storage) -> Result<()> {
+    log!("[main][replica] reading RDB file");
+    // Read the RDB file and load it.
+    // The master node will send a RDB file once connection is setup.
+    // RDB file in this format:
+    // `$<length_of_file>\r\n<binary_contents_of_file>`
+    let mut ch_buf = [0u8; 1];
+    rep_master_conn
+        .read_exact(&mut ch_buf)
+        .await
+        .context("failed to read header doller sign in RDB file transfer")?;
+
+    if ch_buf[0] != b'$' {
+        bail!(
+            "expected dollar sign as the header of RDB file transfer, got '{}'",
+            ch_buf[0]
+        )
+    }
+
+    log!("[main][replica]: reading RDB file length");
+
+    let mut length_buf = vec![];
+
+    // Read the length of RDB file content.
+    loop {
+        rep_master_conn
+            .read_exact(&mut ch_buf)
+            .await
+            .context("failed to read length in RDB file transfer")?;
+        if ch_buf[0] == b'\r' {
+            break;
+        }
+        length_buf.push(ch_buf[0]);
+    }
+
+    // The next char shall be '\n'
+    rep_master_conn
+        .read_exact(&mut ch_buf)
+        .await
+        .context("failed to read length in RDB file transfer")?;
+    if ch_buf[0] != b'\n' {
+        bail!("expected LF after CR after length in RDB file transfer")
+    }
+
+    let length = length_buf
+        .into_iter()
+        .rev()
+        .enumerate()
+        .fold(0, |acc, (idx, ch)| {
+            (ch as usize - 48) * 10_usize.pow(idx as u32) + acc
+        });
+
+    log!("[main][replica]: reading RDB file content, length is {length}");
+
+    let mut rdb_content_buf = vec![0u8; length];
+
+    rep_master_conn
+        .read_exact(&mut rdb_content_buf)
+        .await
+        .context("failed to read RDB content")?;
+
+    log!(
+        "[main][replica] receive RDB file from master node, size is {}",
+        length
+    );
+    // The dataset is replaced by the one of master node.
+    let keys = storage
+        .load_rdb(&rdb_content_buf)
+        .map_err(|e| anyhow::anyhow!("failed to load RDB from master node: {e:?}"))?;
+    log!("[main][replica] loaded {keys} keys from RDB");
+    Ok(())
+}
+
+/// Send `REPLCONF ACK <offset>` to master node, telling it the offset processed.
+async fn send_ack(conn: &mut TcpStream, offset: usize) -> Result<()> {
+    let ack = Value::Array(Array::with_values(vec![
+        Value::BulkString(BulkString::new("REPLCONF")),
+        Value::BulkString(BulkString::new("ACK")),
+        Value::BulkString(BulkString::new(offset.to_string())),
+    ]));
+    write_all(conn, &serde_redis::to_vec(&ack).unwrap())
+        .await
+        .context("failed to send ACK to master node")
+}
+
+/// Find the length of the first complete RESP value in `buf`.
+///
+/// * `Ok(Some(len))` if the value is complete, `len` is the count of bytes it takes.
+/// * `Ok(None)` if more bytes are needed.
+/// * `Err(..)` if `buf` is not valid RESP data.
+pub(crate) fn frame_len(buf: &[u8]) -> Result<Option<usize>> {
+    value_end(buf, 0)
+}
+
+/// Find where the RESP value starting at `pos` in `buf` ends.
+fn value_end(buf: &[u8], pos: usize) -> Result<Option<usize>> {
+    let line_end = match buf[pos..].windows(2).position(|x| x == b"\r\n") {
+        Some(v) => pos + v,
+        None => return Ok(None),
+    };
+    let header = &buf[pos + 1..line_end];
+    let parse_length = || {
+        std::str::from_utf8(header)
+            .ok()
+            .and_then(|x| x.parse::<i64>().ok())
+            .with_context(|| {
+                let header = String::from_utf8_lossy(header);
+                format!("invalid length {header:?} at {pos}")
+            })
+    };
+    match buf[pos] {
+        b'+' | b'-' | b':' => Ok(Some(line_end + 2)),
+        b'$' => {
+            let length = parse_length()?;
+            if length < 0 {
+                return Ok(Some(line_end + 2));
+            }
+            let end = line_end + 2 + length as usize + 2;
+            if buf.len() < end {
+                Ok(None)
+            } else if &buf[end - 2..end] != b"\r\n" {
+                bail!("unterminated bulk string at {pos}")
+            } else {
+                Ok(Some(end))
+            }
+        }
+        b'*' => {
+            let mut end = line_end + 2;
+            for _ in 0..parse_length()?.max(0) {
+                end = match value_end(buf, end)? {
+                    Some(v) => v,
+                    None => return Ok(None),
+                };
+            }
+            Ok(Some(end))
+        }
+        v => bail!("unknown type prefix {v} at {pos}"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame_len() {
+        let set = b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$4\r\n\r\nxy\r\n";
+        for i in 0..set.len() {
+            assert_eq!(frame_len(&set[..i]).unwrap(), None, "prefix length {i}");
+        }
+        assert_eq!(frame_len(set).unwrap(), Some(set.len()));
+
+        let mut two = set.to_vec();
+        two.extend_from_slice(b"*1\r\n$4\r\nPI");
+        assert_eq!(frame_len(&two).unwrap(), Some(set.len()));
+        assert_eq!(frame_len(&two[set.len()..]).unwrap(), None);
+
+        assert_eq!(frame_len(b"+OK\r\n:1\r\n").unwrap(), Some(5));
+        assert_eq!(frame_len(b"$-1\r\n").unwrap(), Some(5));
+        assert!(frame_len(b"?\r\n").is_err());
+        assert!(frame_len(b"$3\r\nfooxx").is_err());
+        assert!(frame_len(b"*x\r\n").is_err());
+    }
+}
@@ -1,103 +1,4853 @@
-use std::net::{Ipv4Addr, SocketAddr};
+use std::{
+    fs::File,
+    io::{ErrorKind, Read},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::Poll,
+    time::Duration,
+};
 
 use anyhow::{Context, Result};
-use serde_redis::Array;
-use tokio::net::{TcpListener, TcpStream};
+use serde_redis::{Array, BulkString, Null, SimpleError, Value};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, oneshot},
+    task::JoinHandle,
+    time::Instant,
+};
 
 use crate::{
+    blocking::{BlockKind, Unblock},
+    client::LocalClient,
+    cluster::{run_bus, BUS_PORT_OFFSET},
     command::{dispatch_command, DispatchResult},
-    conn::Conn,
-    error::ServerError,
-    replication::ReplicationState,
-    storage::Storage,
+    config::{self, Config, SaveRule},
+    conn::{write_all, Conn},
+    error::{ServerError, ServerResult},
+    log::{self, log},
+    replication::{follow_master, frame_len, ReplicationState},
+    state::ServerState,
+    storage::{MaxMemoryPolicy, StorageHook},
 };
 
-pub struct RedisServer {
-    ip: Ipv4Addr,
+/// Most keys removed by one cycle of active expiration.
+const EXPIRE_CYCLE_KEYS: usize = 200;
+
+pub(crate) struct RedisServer {
+    /// Addresses to listen on, at least one.
+    ips: Vec<IpAddr>,
     port: u16,
-    storage: Storage,
+    state: ServerState,
+
+    /// Id for the next connection.
+    ///
+    /// Shared between tcp connections and in-process connections. Starts from 1 like
+    /// redis, 0 means no connection in replies like CLIENT TRACKINGINFO.
+    next_id: Arc<AtomicUsize>,
 }
 
 impl RedisServer {
-    pub fn new(ip: Ipv4Addr, port: u16) -> Self {
+    pub fn new(ips: Vec<IpAddr>, port: u16, state: ServerState) -> Self {
         Self {
-            ip,
+            ips,
             port,
-            storage: Storage::new(),
+            state,
+            next_id: Arc::new(AtomicUsize::new(1)),
+        }
+    }
+
+    /// Bind the tcp sockets the server listens on, one for each address.
+    ///
+    /// If port is 0, the port picked by the OS for the first address is used for
+    /// the others.
+    pub async fn bind(&self) -> Result<Vec<TcpListener>> {
+        let mut port = self.port;
+        let mut listeners = vec![];
+        for ip in &self.ips {
+            let listener = TcpListener::bind((*ip, port))
+                .await
+                .with_context(|| format!("failed to bind tcp socket on {ip}:{port}"))?;
+            port = listener
+                .local_addr()
+                .context("failed to get local address")?
+                .port();
+            listeners.push(listener);
         }
+        Ok(listeners)
     }
 
     /// Run the server.
     ///
     /// Hold a replication settings to act like master node, sync commands to replicas connected.
-    pub async fn serve(&self, rep: ReplicationState) -> Result<()> {
-        let listener = TcpListener::bind((self.ip, self.port))
-            .await
-            .context("failed to bind tcp socket")?;
-        println!("[server] server started");
-        let mut id = 0;
+    ///
+    /// The server stops accepting new connections once shutting down.
+    pub async fn serve(&self, listeners: Vec<TcpListener>, rep: ReplicationState) -> Result<()> {
+        log!("[server] server started");
+        let lifecycle = self.state.lifecycle().clone();
         loop {
-            let (socket, addr) = listener
-                .accept()
-                .await
-                .context("failed to accept new tcp connection")?;
-            let mut s = self.storage.clone();
+            let (socket, addr) = tokio::select! {
+                accepted = accept_any(&listeners) => {
+                    accepted.context("failed to accept new tcp connection")?
+                }
+                _ = lifecycle.wait_shutting_down() => {
+                    log!("[server] server shutdown");
+                    // Blocked commands would never be served.
+                    self.state
+                        .blocking()
+                        .unblock_all(BlockKind::ALL, Unblock::error("server is shutting down"));
+                    break;
+                }
+            };
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            self.state.stats().count_connection();
+            let maxclients = self.state.config().read(|x| x.maxclients);
+            if self.state.clients().count() >= maxclients {
+                log!("[{id}] max number of clients reached, connection from {addr} rejected");
+                self.state.stats().count_rejected_connection();
+                tokio::spawn(reject_connection(socket));
+                continue;
+            }
+            if let Err(e) = self.state.config().get().tune_socket(&socket) {
+                log!("[{id}] failed to set socket options: {e:?}");
+            }
+            // Registered before spawning, so connections accepted next see the count.
+            let laddr = socket.local_addr().map_or(String::new(), |x| x.to_string());
+            let killed = self.state.clients().register(id, addr.to_string(), laddr);
+            let mut s = self.state.clone();
             let rep = rep.clone();
             tokio::spawn(async move {
-                if let Err(e) = Self::handle_task(&mut s, id, socket, addr, rep).await {
-                    println!("[{id}] failed to handle task: {e:?}");
+                match Self::handle_task(&mut s, id, socket, addr, killed, rep).await {
+                    Ok(()) => {}
+                    Err(e) if e.downcast_ref().is_some_and(ServerError::is_disconnect) => {
+                        log!("[{id}] connection lost: {e:#}");
+                    }
+                    Err(e) => log!("[{id}] failed to handle task: {e:?}"),
                 }
+                s.tracking().unregister(id);
+                s.pubsub().unregister(id);
+                s.clients().unregister(id);
             });
-            id += 1;
         }
+        Ok(())
     }
 
-    pub(crate) fn clone_storage(&self) -> Storage {
-        self.storage.clone()
+    pub(crate) fn clone_state(&self) -> ServerState {
+        self.state.clone()
     }
 
     async fn handle_task(
-        storage: &mut Storage,
+        state: &mut ServerState,
         id: usize,
         mut stream: TcpStream,
         addr: SocketAddr,
+        mut killed: oneshot::Receiver<()>,
         mut rep: ReplicationState,
     ) -> Result<()> {
+        let mut pushes = state.tracking().register(id);
+        let mut messages = state.pubsub().register(id);
+        // Set by MONITOR.
+        let mut monitor: Option<broadcast::Receiver<String>> = None;
         let mut conn = Conn::new(id, &mut stream);
+        conn.set_output_limit(state.config().get().client_output_buffer_limit as usize);
+        // Like redis, connected before the password is set are not asked for it.
+        conn.set_authenticated(state.acl().is_default_open());
         conn.log(format!("new connection with client {addr:?}"));
-        loop {
+        // Received bytes not forming a complete command yet.
+        let mut pending = vec![];
+        // When the client sent anything or finished a command, for the idle timeout.
+        let mut last_interaction = Instant::now();
+        'task: loop {
+            if let Some(len) = conn.output_overflow() {
+                conn.log(format!(
+                    "output buffer of {len} bytes exceeds limit, disconnected"
+                ));
+                break;
+            }
+            let idle = match state.config().read(|x| x.timeout) {
+                0 => None,
+                _ if monitor.is_some() || state.pubsub().is_subscribed(id) => None,
+                v => Some(Duration::from_secs(v)),
+            };
             let mut buf = [0u8; 1024];
-            let n = conn
-                .read(&mut buf)
-                .await
-                .with_context(|| format!("[{id}] failed to read from stream"))?;
+            let n = tokio::select! {
+                n = async {
+                    match idle {
+                        Some(idle) => {
+                            tokio::time::timeout_at(last_interaction + idle, conn.read(&mut buf))
+                                .await
+                                .ok()
+                        }
+                        None => Some(conn.read(&mut buf).await),
+                    }
+                } => match n {
+                    Some(n) => n.with_context(|| format!("[{id}] failed to read from stream"))?,
+                    None => {
+                        conn.log(format!("idle for {:?}, closed", idle.unwrap()));
+                        break;
+                    }
+                },
+                Some(push) = pushes.recv() => {
+                    conn.write_push(push).await?;
+                    continue;
+                }
+                Some(message) = messages.recv() => {
+                    conn.write_message(message).await?;
+                    continue;
+                }
+                Ok(()) = &mut killed => {
+                    conn.log("killed by CLIENT KILL");
+                    break;
+                }
+                line = async { monitor.as_mut().unwrap().recv().await }, if monitor.is_some() => {
+                    match line {
+                        Ok(line) => conn.write_monitor(line).await?,
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            conn.log(format!("monitor lagged, {n} lines dropped"));
+                        }
+                        Err(broadcast::error::RecvError::Closed) => monitor = None,
+                    }
+                    continue;
+                }
+            };
             if n == 0 {
                 conn.log("connection closed");
+                // Replies to commands before the client shut down writing are still
+                // expected.
+                conn.flush().await?;
                 break;
             }
             conn.log(format!("receive message {n} bytes"));
-            let message: Array =
-                serde_redis::from_bytes(&buf[0..n]).map_err(ServerError::SerdeError)?;
-            let rep2 = rep.clone();
-            match dispatch_command(&mut conn, message.clone(), storage, rep2).await? {
-                DispatchResult::None => { /* Do nothing */ }
-                DispatchResult::Replica => {
-                    rep.set_replica(stream);
-                    break;
-                }
-                DispatchResult::ReplicaSync => {
-                    let conn_id = conn.id;
-                    let mut rep = rep.clone();
-                    tokio::task::block_in_place(move || {
-                        tokio::runtime::Handle::current().block_on(async move {
-                            let synced_replica_count = rep.sync_command(message.clone()).await;
-                            rep.replica_increase(conn_id, synced_replica_count);
-                            println!("[{conn_id}][replica sync] {synced_replica_count} replicas received command");
-                        })
-                    });
+            pending.extend_from_slice(&buf[0..n]);
+
+            // Run all pipelined commands received, their replies are sent together.
+            let mut pos = 0;
+            loop {
+                let (message, len) = match next_message(&pending[pos..]) {
+                    Ok(Some(v)) => v,
+                    Ok(None) => break,
+                    Err(e) => {
+                        conn.log(format!("invalid message from client: {e}"));
+                        // Like redis, replies to the commands before are still sent.
+                        let message = format!("Protocol error: {e}").replace(['\r', '\n'], " ");
+                        conn.write_value(Value::SimpleError(SimpleError::with_prefix(
+                            "ERR", message,
+                        )))
+                        .await?;
+                        conn.flush().await?;
+                        break 'task;
+                    }
+                };
+                pos += len;
+                let rep2 = rep.clone();
+                match dispatch_command(&mut conn, message, state, rep2).await? {
+                    DispatchResult::None | DispatchResult::ReplicaSync => { /* Do nothing */ }
+                    DispatchResult::Replica => {
+                        conn.flush().await?;
+                        let port = conn.listening_port();
+                        rep.set_replica(id, stream, port);
+                        break 'task;
+                    }
+                    DispatchResult::Monitor => {
+                        monitor = Some(state.monitor().subscribe());
+                        state.clients().update(id, |x| x.monitor = true);
+                    }
+                    DispatchResult::ReplicaSyncEffects(effects) => {
+                        propagate(&rep, state, conn.id, effects)
+                    }
                 }
             }
+            pending.drain(..pos);
+            last_interaction = Instant::now();
+            // Killed by the commands just run, e.g. CLIENT KILL on itself, the replies
+            // are still sent.
+            if let Ok(()) = killed.try_recv() {
+                conn.log("killed by CLIENT KILL");
+                conn.flush().await?;
+                break;
+            }
         }
         Ok(())
     }
 }
+
+/// Parse the command at the beginning of `buf`, return it with its length in bytes.
+///
+/// Return `None` if the command is not complete yet.
+fn next_message(buf: &[u8]) -> Result<Option<(Array, usize)>> {
+    let Some(len) = frame_len(buf)? else {
+        return Ok(None);
+    };
+    let message = serde_redis::from_bytes(&buf[..len]).context("command is not an array")?;
+    Ok(Some((message, len)))
+}
+
+/// Send `messages` to all replicas connected, for the command sent by connection `conn_id`.
+/// Also append them to the AOF.
+///
+/// `messages` are sent in order.
+pub(crate) fn propagate(
+    rep: &ReplicationState,
+    state: &ServerState,
+    conn_id: usize,
+    messages: Vec<Array>,
+) {
+    if messages.is_empty() {
+        return;
+    }
+    state.aof().append(&messages);
+    let mut rep = rep.clone();
+    let mut synced_replica_count = 0;
+    for message in messages {
+        synced_replica_count = rep.sync_command(message);
+    }
+    log!("[{conn_id}][replica sync] {synced_replica_count} replicas received command");
+}
+
+/// Replay the AOF at `path` into the storage, return the count of commands replayed.
+///
+/// Nothing to replay if the file does not exist. Like redis refusing to start with a
+/// truncated AOF, fail if the file is not a complete list of commands.
+async fn load_aof(state: &mut ServerState, rep: &ReplicationState, path: &Path) -> Result<usize> {
+    let bytes = match std::fs::read(path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e).with_context(|| format!("failed to read AOF {}", path.display())),
+    };
+    // Connection ids start from 1, 0 is never taken by clients.
+    let mut conn = Conn::new_local(0);
+    let mut pos = 0;
+    let mut count = 0;
+    while pos < bytes.len() {
+        let (message, len): (Array, usize) = serde_redis::from_bytes_len(&bytes[pos..])
+            .with_context(|| format!("invalid command in AOF at offset {pos}"))?;
+        dispatch_command(&mut conn, message, state, rep.clone())
+            .await
+            .with_context(|| format!("failed to replay command in AOF at offset {pos}"))?;
+        conn.take_values();
+        pos += len;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Tell the client on `socket` that it is rejected by `maxclients`, then close it.
+async fn reject_connection(mut socket: TcpStream) {
+    // The client may be gone already, nothing to do then.
+    let _ = write_all(&mut socket, b"-ERR max number of clients reached\r\n").await;
+}
+
+/// Accept a new connection on any of `listeners`.
+///
+/// Listeners are polled in order, so earlier ones win when several are ready.
+async fn accept_any(listeners: &[TcpListener]) -> std::io::Result<(TcpStream, SocketAddr)> {
+    std::future::poll_fn(|cx| {
+        listeners
+            .iter()
+            .find_map(|x| match x.poll_accept(cx) {
+                Poll::Ready(v) => Some(Poll::Ready(v)),
+                Poll::Pending => None,
+            })
+            .unwrap_or(Poll::Pending)
+    })
+    .await
+}
+
+/// Start background saves once any of `save` rules in config is met, till the server
+/// shuts down.
+async fn save_cron(state: ServerState) {
+    let lifecycle = state.lifecycle().clone();
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = lifecycle.wait_shutting_down() => break,
+        }
+        let rules = state.config().get().save;
+        if state.persistence().save_due(&rules) && state.bgsave() {
+            log!("[server] background saving started by save rules");
+        }
+    }
+}
+
+/// Remove expired keys in background every 100 milliseconds, till the server shuts
+/// down.
+///
+/// Like redis, each cycle removes a bounded count of keys so that the storage is not
+/// held for long, the rest are left to later cycles.
+async fn expire_cron(state: ServerState) {
+    let lifecycle = state.lifecycle().clone();
+    let mut interval = tokio::time::interval(Duration::from_millis(100));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = lifecycle.wait_shutting_down() => break,
+        }
+        state.storage().expire_cycle(EXPIRE_CYCLE_KEYS);
+    }
+}
+
+/// Send PING to replicas every `period`, till the server shuts down.
+async fn repl_ping_cron(state: ServerState, rep: ReplicationState, period: Duration) {
+    let lifecycle = state.lifecycle().clone();
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = lifecycle.wait_shutting_down() => break,
+        }
+        rep.ping_replicas();
+    }
+}
+
+/// Load the dump in `file` into the storage at startup, then start serving all commands.
+///
+/// Like redis refusing to start with a corrupted RDB file, the server is shut down if
+/// the dump is invalid.
+fn load_dump(state: ServerState, mut file: File, key_load_delay: Duration) {
+    let lifecycle = state.lifecycle();
+    let mut json = vec![];
+    if let Err(e) = file.read_to_end(&mut json) {
+        log!("[server] failed to read dump: {e}");
+        lifecycle.shut_down();
+        return;
+    }
+    let mut last = 0;
+    let result = state.storage().load_json(&json, |loaded, total| {
+        std::thread::sleep(key_load_delay * (loaded - last) as u32);
+        last = loaded;
+        lifecycle.set_loaded(loaded, total);
+    });
+    match result {
+        Ok(n) => {
+            log!("[server] loaded {n} keys from dump");
+            lifecycle.finish_loading();
+        }
+        Err(e) => {
+            log!("[server] failed to load dump: {e:?}");
+            lifecycle.shut_down();
+        }
+    }
+}
+
+/// Builder to configure and start a redis server in current process.
+///
+/// ```no_run
+/// # async fn run() -> anyhow::Result<()> {
+/// use codecrafters_redis::ServerBuilder;
+///
+/// let handle = ServerBuilder::new().port(6380).start().await?;
+/// let value = handle.execute(["SET", "foo", "bar"]).await?;
+/// handle.shutdown().await;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ServerBuilder {
+    ips: Vec<IpAddr>,
+    port: u16,
+    master: Option<(Ipv4Addr, u16)>,
+    hooks: Vec<Arc<dyn StorageHook>>,
+    config: Config,
+    dump: Option<PathBuf>,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self {
+            ips: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            port: 6379,
+            master: None,
+            hooks: vec![],
+            config: Config::default(),
+            dump: None,
+        }
+    }
+
+    /// Set the ip address to listen on.
+    ///
+    /// Default is 127.0.0.1.
+    pub fn ip(self, ip: impl Into<IpAddr>) -> Self {
+        self.bind([ip.into()])
+    }
+
+    /// Set the ip addresses to listen on, IPv4 or IPv6, on the same port.
+    ///
+    /// An empty list is ignored. Default is 127.0.0.1.
+    pub fn bind(mut self, ips: impl IntoIterator<Item = IpAddr>) -> Self {
+        let ips = ips.into_iter().collect::<Vec<_>>();
+        if !ips.is_empty() {
+            self.ips = ips;
+        }
+        self
+    }
+
+    /// Set the port to listen on.
+    ///
+    /// Use port 0 to let the OS pick an unused one, check `Handle::local_addr`
+    /// for the actual port.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Act like a replica of the master node at `master`.
+    pub fn replicaof(mut self, master: Option<(Ipv4Addr, u16)>) -> Self {
+        self.master = master;
+        self
+    }
+
+    /// Set the seconds to wait before sending TCP keepalive probes to idle peers,
+    /// 0 disables keepalive.
+    ///
+    /// Default is 300.
+    pub fn tcp_keepalive(mut self, seconds: u64) -> Self {
+        self.config.tcp_keepalive = seconds;
+        self
+    }
+
+    /// Set whether to disable Nagle's algorithm on sockets.
+    ///
+    /// Default is true.
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.config.tcp_nodelay = nodelay;
+        self
+    }
+
+    /// Set the seconds before closing client connections idle, 0 disables it.
+    ///
+    /// Subscribers, monitors and clients blocked by commands are never closed as
+    /// idle. Default is 0.
+    pub fn timeout(mut self, seconds: u64) -> Self {
+        self.config.timeout = seconds;
+        self
+    }
+
+    /// Set the max bytes of commands kept for replicas to continue after
+    /// reconnecting, instead of a full resynchronization.
+    ///
+    /// Default is 1MB.
+    pub fn repl_backlog_size(mut self, size: u64) -> Self {
+        self.config.repl_backlog_size = size;
+        self
+    }
+
+    /// Set the seconds before disconnecting a replica acknowledging nothing, 0
+    /// disables it.
+    ///
+    /// Default is 60.
+    pub fn repl_timeout(mut self, seconds: u64) -> Self {
+        self.config.repl_timeout = seconds;
+        self
+    }
+
+    /// Set the seconds between PINGs sent to replicas.
+    ///
+    /// Default is 10.
+    pub fn repl_ping_replica_period(mut self, seconds: u64) -> Self {
+        self.config.repl_ping_replica_period = seconds;
+        self
+    }
+
+    /// Set the max bytes of commands queued for a replica, the replica is
+    /// disconnected once exceeded, 0 for no limit.
+    ///
+    /// Default is 256MB.
+    pub fn replica_output_buffer_limit(mut self, limit: u64) -> Self {
+        self.config.replica_output_buffer_limit = limit;
+        self
+    }
+
+    /// Set the max bytes of replies pending to a client, the client is disconnected
+    /// once exceeded, 0 for no limit.
+    ///
+    /// Default is 0.
+    pub fn client_output_buffer_limit(mut self, limit: u64) -> Self {
+        self.config.client_output_buffer_limit = limit;
+        self
+    }
+
+    /// Set whether to stream the RDB snapshot directly to replicas that support it
+    /// in full resynchronization.
+    ///
+    /// Default is true.
+    pub fn repl_diskless_sync(mut self, diskless: bool) -> Self {
+        self.config.repl_diskless_sync = diskless;
+        self
+    }
+
+    /// Set the max count of pending commands when running as replica, read commands
+    /// are rejected with BUSY error once exceeded, 0 disables it.
+    ///
+    /// Default is 0.
+    pub fn replica_max_pending(mut self, max_pending: usize) -> Self {
+        self.config.replica_max_pending = max_pending;
+        self
+    }
+
+    /// Load the dump document at `path` on startup, in the format written by EXPORT.
+    ///
+    /// Connections are accepted while loading, but most commands are answered with
+    /// a LOADING error till all keys are loaded.
+    pub fn load_dump(mut self, path: impl Into<PathBuf>) -> Self {
+        self.dump = Some(path.into());
+        self
+    }
+
+    /// Set the microseconds to sleep after loading each key from the dump, 0 disables it.
+    ///
+    /// Only useful to observe the loading state in tests. Default is 0.
+    pub fn key_load_delay(mut self, micros: u64) -> Self {
+        self.config.key_load_delay = micros;
+        self
+    }
+
+    /// Set the max execution time of commands in milliseconds, 0 disables it.
+    ///
+    /// Commands walking large values, e.g. LRANGE and LREM, are aborted once exceeding
+    /// it and answered with a TIMEOUT error, the dataset is left as is. Default is 0.
+    pub fn command_timeout(mut self, millis: u64) -> Self {
+        self.config.command_timeout = millis;
+        self
+    }
+
+    /// Set the max count of clients connected at the same time, new connections
+    /// beyond it are answered with an error and closed.
+    ///
+    /// Default is 10000.
+    pub fn maxclients(mut self, count: usize) -> Self {
+        self.config.maxclients = count;
+        self
+    }
+
+    /// Set the max bytes of memory used by the dataset, 0 for no limit.
+    ///
+    /// Once exceeded, keys are evicted before writes by the policy set with
+    /// `maxmemory_policy`, writes fail if nothing can be evicted. Default is 0.
+    pub fn maxmemory(mut self, bytes: u64) -> Self {
+        self.config.maxmemory = bytes;
+        self
+    }
+
+    /// Set the policy to pick keys to evict when out of memory.
+    ///
+    /// OBJECT FREQ is only available under LFU policies, OBJECT IDLETIME under the
+    /// others. Default is noeviction.
+    pub fn maxmemory_policy(mut self, policy: MaxMemoryPolicy) -> Self {
+        self.config.maxmemory_policy = policy;
+        self
+    }
+
+    /// Set how slow the LFU access counter grows, the greater the more accesses are
+    /// needed to saturate it.
+    ///
+    /// Default is 10.
+    pub fn lfu_log_factor(mut self, factor: u32) -> Self {
+        self.config.lfu.log_factor = factor;
+        self
+    }
+
+    /// Set the minutes of idle time to decay the LFU access counter by one, 0 never
+    /// decays.
+    ///
+    /// Default is 1.
+    pub fn lfu_decay_time(mut self, minutes: u64) -> Self {
+        self.config.lfu.decay_time = minutes;
+        self
+    }
+
+    /// Set the directory to write the RDB file in.
+    ///
+    /// Default is the current directory.
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.dir = dir.into();
+        self
+    }
+
+    /// Set the name of the RDB file written by SAVE.
+    ///
+    /// Default is "dump.rdb".
+    pub fn dbfilename(mut self, name: impl Into<String>) -> Self {
+        self.config.dbfilename = name.into();
+        self
+    }
+
+    /// Set the rules to save the RDB file in background, pairs of seconds and changes:
+    /// save once that many changes are made in that many seconds.
+    ///
+    /// Default is empty, never save in background.
+    pub fn save(mut self, rules: &[(u64, u64)]) -> Self {
+        self.config.save = rules
+            .iter()
+            .map(|&(seconds, changes)| SaveRule { seconds, changes })
+            .collect();
+        self
+    }
+
+    /// Append write commands to the AOF, and replay it at startup.
+    ///
+    /// Default is false.
+    pub fn appendonly(mut self, enabled: bool) -> Self {
+        self.config.appendonly = enabled;
+        self
+    }
+
+    /// Set the name of the AOF in the directory set by `dir`.
+    ///
+    /// Default is "appendonly.aof".
+    pub fn appendfilename(mut self, name: impl Into<String>) -> Self {
+        self.config.appendfilename = name.into();
+        self
+    }
+
+    /// Set parameter `name` to `value`, by its name in redis config file like
+    /// "maxmemory-policy" and "save".
+    ///
+    /// Besides parameters of CONFIG SET, "port", "bind", "replicaof" and "load-dump"
+    /// are supported too. Fail if the name is unknown or the value is invalid.
+    pub fn set_param(mut self, name: &str, value: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "port" => self.port = value.parse().context("invalid port")?,
+            "bind" => {
+                let ips = value
+                    .split_whitespace()
+                    .map(|x| x.parse::<IpAddr>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .context("invalid bind")?;
+                if ips.is_empty() {
+                    anyhow::bail!("invalid bind: no address");
+                }
+                self = self.bind(ips);
+            }
+            "replicaof" => {
+                let (ip, port) = value
+                    .split_once(' ')
+                    .context("invalid replicaof, expected \"<ip> <port>\"")?;
+                let ip = if ip == "localhost" {
+                    Ipv4Addr::LOCALHOST
+                } else {
+                    ip.parse().context("invalid replicaof ip")?
+                };
+                let port = port.trim().parse().context("invalid replicaof port")?;
+                self.master = Some((ip, port));
+            }
+            "load-dump" => self.dump = Some(value.into()),
+            v => config::set_param(&mut self.config, v, value)
+                .map_err(|e| anyhow::anyhow!("invalid {v}: {e}"))?,
+        }
+        Ok(self)
+    }
+
+    /// Set parameters in config file at `path`, in the format of redis.conf.
+    ///
+    /// Each line is a parameter name followed by its value, as `set_param`. Later
+    /// lines and calls override earlier ones.
+    pub fn config_file(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        let params = config::parse_config_file(&text).map_err(|(line, e)| {
+            anyhow::anyhow!("invalid config file {} at line {line}: {e}", path.display())
+        })?;
+        for (name, value) in params {
+            self = self
+                .set_param(&name, &value)
+                .with_context(|| format!("invalid config file {}", path.display()))?;
+        }
+        Ok(self)
+    }
+
+    /// Write logs to the file at `path` instead of stdout, empty for stdout.
+    ///
+    /// Logs of all servers in the process go to the same file. Default is empty.
+    pub fn logfile(mut self, path: impl Into<String>) -> Self {
+        self.config.logfile = path.into();
+        self
+    }
+
+    /// Require clients to authenticate with `password` by AUTH, empty for no password.
+    ///
+    /// In-process clients are always authenticated. Default is empty.
+    pub fn requirepass(mut self, password: impl Into<String>) -> Self {
+        self.config.requirepass = password.into();
+        self
+    }
+
+    /// Load users from ACL file `path` at startup, and save users to it by ACL SAVE.
+    ///
+    /// Default is empty, no ACL file used.
+    pub fn aclfile(mut self, path: impl Into<String>) -> Self {
+        self.config.aclfile = path.into();
+        self
+    }
+
+    /// Run in cluster mode, serving only keys in slots assigned to this node.
+    ///
+    /// Default is false.
+    pub fn cluster_enabled(mut self, enabled: bool) -> Self {
+        self.config.cluster_enabled = enabled;
+        self
+    }
+
+    /// Set the name of the nodes config file in the directory set by `dir`, used in
+    /// cluster mode.
+    ///
+    /// Default is "nodes.conf".
+    pub fn cluster_config_file(mut self, name: impl Into<String>) -> Self {
+        self.config.cluster_config_file = name.into();
+        self
+    }
+
+    /// Set the milliseconds for nodes to answer pings on the cluster bus before they
+    /// are flagged failing, used in cluster mode.
+    ///
+    /// Default is 15000.
+    pub fn cluster_node_timeout(mut self, millis: u64) -> Self {
+        self.config.cluster_node_timeout = millis;
+        self
+    }
+
+    /// Set the port of the cluster bus, used in cluster mode.
+    ///
+    /// Default is 0, the port serving clients plus 10000, or a random one if that port
+    /// is 0 too.
+    pub fn cluster_port(mut self, port: u16) -> Self {
+        self.config.cluster_port = port;
+        self
+    }
+
+    /// Register a hook notified on every change in storage.
+    pub fn storage_hook(mut self, hook: impl StorageHook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Start the server in background.
+    ///
+    /// Check the configuration before starting, so mistakes fail the start instead of
+    /// showing up later in logs.
+    ///
+    /// Return the opened dump file and its size, if any.
+    fn sanity_check(&self) -> Result<Option<(File, u64)>> {
+        if let Some((ip, port)) = self.master {
+            let is_self_ip = self
+                .ips
+                .iter()
+                .any(|x| x.is_unspecified() || *x == ip || (x.is_loopback() && ip.is_loopback()));
+            if is_self_ip && port == self.port && port != 0 {
+                anyhow::bail!("replicaof {ip}:{port} is the server itself");
+            }
+        }
+        let Some(path) = &self.dump else {
+            return Ok(None);
+        };
+        let file = File::open(path)
+            .with_context(|| format!("failed to open dump file {}", path.display()))?;
+        let metadata = file
+            .metadata()
+            .with_context(|| format!("failed to stat dump file {}", path.display()))?;
+        if !metadata.is_file() {
+            anyhow::bail!("dump file {} is not a regular file", path.display());
+        }
+        Ok(Some((file, metadata.len())))
+    }
+
+    /// The listening socket is bound before returning, so the server is ready
+    /// to accept connections once this function returns. If a dump is configured,
+    /// it is loaded in background after that, see `Handle::wait_loaded`.
+    pub async fn start(self) -> Result<Handle> {
+        let dump = self.sanity_check()?;
+        let config = self.config;
+        if !config.logfile.is_empty() {
+            log::set_log_file(Path::new(&config.logfile))
+                .with_context(|| format!("failed to open log file {}", config.logfile))?;
+        }
+        let server = RedisServer::new(self.ips, self.port, ServerState::new(self.hooks));
+        let state = server.clone_state();
+        state.config().update(|x| *x = config.clone());
+        state.apply_config();
+        if !config.requirepass.is_empty() {
+            state.acl().set_default_password(&config.requirepass);
+        }
+        if !config.aclfile.is_empty() {
+            let text = std::fs::read_to_string(&config.aclfile)
+                .with_context(|| format!("failed to read ACL file {}", config.aclfile))?;
+            state
+                .acl()
+                .load(&text)
+                .map_err(|e| anyhow::anyhow!("failed to load ACL file {}{e}", config.aclfile))?;
+        }
+        let listeners = server.bind().await?;
+        let local_addrs = listeners
+            .iter()
+            .map(|x| x.local_addr())
+            .collect::<std::io::Result<Vec<_>>>()
+            .context("failed to get local address")?;
+        let local_addr = local_addrs[0];
+
+        let replication = ReplicationState::new(self.master, local_addr.port(), &config);
+
+        // Replayed before serving, writes from now on are appended.
+        let aof_path = config.aof_path();
+        if config.appendonly {
+            let count = load_aof(&mut state.clone(), &replication, &aof_path).await?;
+            log!("[server] replayed {count} commands from AOF");
+            state
+                .aof()
+                .open(aof_path)
+                .with_context(|| format!("failed to open AOF {}", config.appendfilename))?;
+        } else {
+            state.aof().set_path(aof_path);
+        }
+
+        // Enabled after replaying, so commands in AOF are never redirected.
+        if config.cluster_enabled {
+            let cport = match (config.cluster_port, self.port) {
+                (0, 0) => 0,
+                (0, port) => port
+                    .checked_add(BUS_PORT_OFFSET)
+                    .context("port too large to derive the cluster bus port")?,
+                (cport, _) => cport,
+            };
+            let bus_listener = TcpListener::bind((local_addr.ip(), cport))
+                .await
+                .with_context(|| format!("failed to bind cluster bus port {cport}"))?;
+            let cport = bus_listener
+                .local_addr()
+                .context("failed to get cluster bus address")?
+                .port();
+            state
+                .cluster()
+                .enable(
+                    local_addr,
+                    cport,
+                    config.cluster_config_path(),
+                    Duration::from_millis(config.cluster_node_timeout),
+                )
+                .map_err(|e| anyhow::anyhow!("failed to enable cluster mode: {e}"))?;
+            tokio::spawn(run_bus(
+                state.cluster().clone(),
+                bus_listener,
+                state.lifecycle().clone(),
+            ));
+        }
+
+        // The connection with master node, if current instance started with `--repliconf` config.
+        // Master node may send commands via the connection, these connection shall be applied on current instance.
+        let rep_master_link = match replication.handshake().await {
+            Ok((v, full_sync)) => {
+                if let Err(e) = config.tune_socket(&v) {
+                    log!("[main][replica] failed to set socket options: {e:?}");
+                }
+                Some((v, full_sync))
+            }
+            Err(e) => {
+                log!("[main][replica] handshake failed: {e}");
+                None
+            }
+        };
+
+        // Enter loading before serving, so no command runs on a partial dataset.
+        if let Some((file, total_bytes)) = dump {
+            let state = server.clone_state();
+            state.lifecycle().start_loading(total_bytes);
+            let delay = Duration::from_micros(config.key_load_delay);
+            tokio::task::spawn_blocking(move || load_dump(state, file, delay));
+        }
+
+        tokio::spawn(save_cron(server.clone_state()));
+        tokio::spawn(expire_cron(server.clone_state()));
+        if config.repl_ping_replica_period > 0 {
+            tokio::spawn(repl_ping_cron(
+                server.clone_state(),
+                replication.clone(),
+                Duration::from_secs(config.repl_ping_replica_period),
+            ));
+        }
+
+        if replication.is_replica() {
+            let state2 = server.clone_state();
+            let rep = replication.clone();
+            replication.set_link(tokio::spawn(async move {
+                // Commands from master node apply on top of the loaded dataset.
+                state2.lifecycle().wait_loaded().await;
+                follow_master(rep, state2, rep_master_link).await;
+            }));
+        }
+
+        let state = server.clone_state();
+        let next_id = server.next_id.clone();
+        let rep = replication.clone();
+        let serve_task = tokio::spawn(async move {
+            if let Err(e) = server.serve(listeners, rep).await {
+                log!("[server] failed to serve: {e:?}");
+            }
+        });
+
+        Ok(Handle {
+            local_addrs,
+            state,
+            replication,
+            next_id,
+            serve_task,
+        })
+    }
+}
+
+/// Handle of a running server started by `ServerBuilder`.
+pub struct Handle {
+    /// Addresses listening on, in the order of `ServerBuilder::bind`.
+    local_addrs: Vec<SocketAddr>,
+    state: ServerState,
+    replication: ReplicationState,
+    next_id: Arc<AtomicUsize>,
+    serve_task: JoinHandle<()>,
+}
+
+impl Handle {
+    /// The first address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addrs[0]
+    }
+
+    /// All addresses the server is listening on.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Execute a command in process, without going through any socket.
+    ///
+    /// `cmd` is the command name followed by its args, e.g. `["SET", "foo", "bar"]`.
+    ///
+    /// Return the reply of the command.
+    pub async fn execute<I, S>(&self, cmd: I) -> ServerResult<Value>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<[u8]>,
+    {
+        let message = cmd
+            .into_iter()
+            .map(|x| Value::BulkString(BulkString::new(x.as_ref())))
+            .collect::<Array>();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut conn = Conn::new_local(id);
+        let mut state = self.state.clone();
+        match dispatch_command(&mut conn, message, &mut state, self.replication.clone()).await? {
+            DispatchResult::None
+            | DispatchResult::Replica
+            | DispatchResult::Monitor
+            | DispatchResult::ReplicaSync => { /* Do nothing */ }
+            DispatchResult::ReplicaSyncEffects(effects) => {
+                propagate(&self.replication, &state, id, effects)
+            }
+        }
+        Ok(conn
+            .take_values()
+            .into_iter()
+            .next()
+            .unwrap_or(Value::Null(Null)))
+    }
+
+    /// Create a client connected to the server in process.
+    pub fn client(&self) -> LocalClient {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        LocalClient::spawn(id, self.state.clone(), self.replication.clone())
+    }
+
+    /// Wait till the server finishes loading the dump configured by
+    /// `ServerBuilder::load_dump`, return immediately if nothing to load.
+    pub async fn wait_loaded(&self) {
+        self.state.lifecycle().wait_loaded().await;
+    }
+
+    /// Wait till the server stops.
+    pub async fn wait(self) {
+        let _ = self.serve_task.await;
+        self.replication.close_link();
+        self.replication.disconnect_replicas();
+    }
+
+    /// Stop the server.
+    ///
+    /// Stop accepting new connections, stop syncing with master node and disconnect
+    /// replicas.
+    pub async fn shutdown(self) {
+        self.state.lifecycle().shut_down();
+        self.wait().await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use serde_redis::{Integer, SimpleString};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct RecordHook(Arc<Mutex<Vec<Vec<u8>>>>);
+
+    impl StorageHook for RecordHook {
+        fn on_write(&self, key: &[u8]) {
+            self.0.lock().unwrap().push(key.to_vec());
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_execute_in_process() {
+        let hook = RecordHook::default();
+        let handle = ServerBuilder::new()
+            .port(0)
+            .storage_hook(hook.clone())
+            .start()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            handle.execute(["SET", "foo", "bar"]).await.unwrap(),
+            Value::SimpleString(SimpleString::new("OK"))
+        );
+        assert_eq!(
+            handle.execute(["GET", "foo"]).await.unwrap(),
+            Value::BulkString(BulkString::new("bar"))
+        );
+        assert_eq!(hook.0.lock().unwrap().as_slice(), [b"foo"]);
+
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_active_expire_hook() {
+        let hook = RecordHook::default();
+        let handle = ServerBuilder::new()
+            .port(0)
+            .storage_hook(hook.clone())
+            .start()
+            .await
+            .unwrap();
+
+        handle
+            .execute(["SET", "foo", "bar", "PX", "50"])
+            .await
+            .unwrap();
+        // Removed in background without being accessed.
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(hook.0.lock().unwrap().as_slice(), [b"foo", b"foo"]);
+
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_bind() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let handle = ServerBuilder::new()
+            .port(0)
+            .set_param("bind", "127.0.0.1 ::1")
+            .unwrap()
+            .start()
+            .await
+            .unwrap();
+        let addrs = handle.local_addrs().to_vec();
+        assert_eq!(addrs.len(), 2);
+        assert!(addrs[1].is_ipv6());
+        assert_eq!(addrs[0].port(), addrs[1].port());
+        for addr in addrs {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+            let mut buf = [0; 7];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"+PONG\r\n");
+        }
+        handle.shutdown().await;
+
+        assert!(ServerBuilder::new().set_param("bind", "localhost").is_err());
+    }
+
+    /// Connect to `handle` as a replica and finish the full resynchronization.
+    ///
+    /// Return the connection, the replication id and offset of master.
+    async fn full_sync(handle: &Handle) -> (TcpStream, String, usize) {
+        use tokio::io::AsyncReadExt;
+
+        async fn read_line(stream: &mut TcpStream) -> String {
+            let mut line = vec![];
+            while !line.ends_with(b"\r\n") {
+                line.push(stream.read_u8().await.unwrap());
+            }
+            String::from_utf8(line).unwrap()
+        }
+
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        roundtrip(&mut stream, &["PSYNC", "?", "-1"], b"+FULLRESYNC ").await;
+        let line = read_line(&mut stream).await;
+        let (id, offset) = line.trim_end().split_once(' ').unwrap();
+        let len = read_line(&mut stream).await;
+        let mut rdb = vec![0; len[1..len.len() - 2].parse().unwrap()];
+        stream.read_exact(&mut rdb).await.unwrap();
+        // Wait till registered as replica.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        (stream, id.to_string(), offset.parse().unwrap())
+    }
+
+    /// Send `cmd` to the server on `stream` and check the reply is `expected`.
+    ///
+    /// Nothing sent if `cmd` is empty, to read pushed messages.
+    async fn roundtrip(stream: &mut TcpStream, cmd: &[&str], expected: &[u8]) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let cmd = cmd
+            .iter()
+            .map(|x| Value::BulkString(BulkString::new(*x)))
+            .collect::<Array>();
+        if !cmd.is_empty() {
+            stream
+                .write_all(&serde_redis::to_vec(&cmd).unwrap())
+                .await
+                .unwrap();
+        }
+        let mut buf = vec![0; expected.len()];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buf),
+            String::from_utf8_lossy(expected)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pubsub() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+
+        roundtrip(
+            stream,
+            &["SUBSCRIBE", "ch"],
+            b"*3\r\n$9\r\nsubscribe\r\n$2\r\nch\r\n:1\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["PSUBSCRIBE", "c*"],
+            b"*3\r\n$10\r\npsubscribe\r\n$2\r\nc*\r\n:2\r\n",
+        )
+        .await;
+        let bulk = |x: &str| Value::BulkString(BulkString::new(x));
+        assert_eq!(
+            handle
+                .execute(["PUBSUB", "NUMSUB", "ch", "other"])
+                .await
+                .unwrap(),
+            Value::Array(Array::with_values(vec![
+                bulk("ch"),
+                Value::Integer(Integer::new(1)),
+                bulk("other"),
+                Value::Integer(Integer::new(0)),
+            ]))
+        );
+        assert_eq!(
+            handle.execute(["PUBSUB", "CHANNELS", "c?"]).await.unwrap(),
+            Value::Array(Array::with_values(vec![bulk("ch")]))
+        );
+        assert_eq!(
+            handle.execute(["PUBSUB", "NUMPAT"]).await.unwrap(),
+            Value::Integer(Integer::new(1))
+        );
+        assert_eq!(
+            handle.execute(["PUBLISH", "ch", "hi"]).await.unwrap(),
+            Value::Integer(Integer::new(2))
+        );
+        assert_eq!(
+            handle.execute(["PUBLISH", "other", "hi"]).await.unwrap(),
+            Value::Integer(Integer::new(0))
+        );
+        roundtrip(
+            stream,
+            &[],
+            b"*3\r\n$7\r\nmessage\r\n$2\r\nch\r\n$2\r\nhi\r\n\
+              *4\r\n$8\r\npmessage\r\n$2\r\nc*\r\n$2\r\nch\r\n$2\r\nhi\r\n",
+        )
+        .await;
+        roundtrip(stream, &["PING"], b"*2\r\n$4\r\npong\r\n$0\r\n\r\n").await;
+        roundtrip(
+            stream,
+            &["GET", "k"],
+            b"-ERR Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING are allowed in this context\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["UNSUBSCRIBE"],
+            b"*3\r\n$11\r\nunsubscribe\r\n$2\r\nch\r\n:1\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["PUNSUBSCRIBE"],
+            b"*3\r\n$12\r\npunsubscribe\r\n$2\r\nc*\r\n:0\r\n",
+        )
+        .await;
+        roundtrip(stream, &["GET", "k"], b"$-1\r\n").await;
+
+        // Shard channels are apart from regular channels.
+        roundtrip(
+            stream,
+            &["SSUBSCRIBE", "ch"],
+            b"*3\r\n$10\r\nssubscribe\r\n$2\r\nch\r\n:1\r\n",
+        )
+        .await;
+        assert_eq!(
+            handle.execute(["PUBLISH", "ch", "hi"]).await.unwrap(),
+            Value::Integer(Integer::new(0))
+        );
+        assert_eq!(
+            handle.execute(["SPUBLISH", "ch", "hi"]).await.unwrap(),
+            Value::Integer(Integer::new(1))
+        );
+        assert_eq!(
+            handle
+                .execute(["PUBSUB", "SHARDNUMSUB", "ch"])
+                .await
+                .unwrap(),
+            Value::Array(Array::with_values(vec![
+                bulk("ch"),
+                Value::Integer(Integer::new(1))
+            ]))
+        );
+        roundtrip(
+            stream,
+            &[],
+            b"*3\r\n$8\r\nsmessage\r\n$2\r\nch\r\n$2\r\nhi\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["SUNSUBSCRIBE"],
+            b"*3\r\n$12\r\nsunsubscribe\r\n$2\r\nch\r\n:0\r\n",
+        )
+        .await;
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_auth() {
+        let handle = ServerBuilder::new()
+            .port(0)
+            .requirepass("secret")
+            .start()
+            .await
+            .unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+        let wrongpass = b"-WRONGPASS invalid username-password pair or user is disabled.\r\n";
+
+        roundtrip(stream, &["PING"], b"-NOAUTH Authentication required.\r\n").await;
+        roundtrip(
+            stream,
+            &["HELLO", "3"],
+            b"-NOAUTH HELLO must be called with the client already authenticated, \
+              otherwise the HELLO <proto> AUTH <user> <pass> option can be used to \
+              authenticate the client and select the RESP protocol version at the same time\r\n",
+        )
+        .await;
+        roundtrip(stream, &["HELLO", "3", "AUTH", "default", "x"], wrongpass).await;
+        roundtrip(stream, &["AUTH", "x"], wrongpass).await;
+        roundtrip(stream, &["AUTH", "nobody", "secret"], wrongpass).await;
+        roundtrip(stream, &["PING"], b"-NOAUTH Authentication required.\r\n").await;
+        roundtrip(stream, &["AUTH", "secret"], b"+OK\r\n").await;
+        roundtrip(stream, &["PING"], b"+PONG\r\n").await;
+
+        // In-process clients need no password.
+        assert_eq!(
+            handle.execute(["PING"]).await.unwrap(),
+            Value::SimpleString(SimpleString::new("PONG"))
+        );
+        assert_eq!(
+            handle.execute(["AUTH", "default", "secret"]).await.unwrap(),
+            Value::SimpleString(SimpleString::new("OK"))
+        );
+
+        // Without password, clients are authenticated once connected.
+        handle
+            .execute(["CONFIG", "SET", "requirepass", ""])
+            .await
+            .unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        roundtrip(&mut stream, &["PING"], b"+PONG\r\n").await;
+        roundtrip(
+            &mut stream,
+            &["AUTH", "x"],
+            b"-ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?\r\n",
+        )
+        .await;
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_hello_auth() {
+        use tokio::io::AsyncReadExt;
+
+        /// Read the reply of HELLO till the user it ends with.
+        async fn read_hello(stream: &mut TcpStream, user: &str) -> String {
+            let end = format!("$4\r\nuser\r\n${}\r\n{user}\r\n", user.len());
+            let mut reply = vec![];
+            while !reply.ends_with(end.as_bytes()) {
+                reply.push(stream.read_u8().await.unwrap());
+            }
+            String::from_utf8(reply).unwrap()
+        }
+
+        let handle = ServerBuilder::new()
+            .port(0)
+            .requirepass("secret")
+            .start()
+            .await
+            .unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+
+        roundtrip(
+            stream,
+            &["HELLO", "3", "AUTH", "default"],
+            b"-ERR Syntax error in HELLO option 'AUTH'\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["HELLO", "4", "AUTH", "default", "secret"],
+            b"-NOPROTO unsupported protocol version\r\n",
+        )
+        .await;
+        roundtrip(stream, &["PING"], b"-NOAUTH Authentication required.\r\n").await;
+
+        // Authenticated and switched to RESP3 at once.
+        let cmd = ["HELLO", "3", "AUTH", "default", "secret"];
+        roundtrip(stream, &cmd, b"%8\r\n$6\r\nserver\r\n$5\r\nredis\r\n").await;
+        let reply = read_hello(stream, "default").await;
+        assert!(reply.contains("$5\r\nproto\r\n:3\r\n"), "reply {reply:?}");
+        roundtrip(stream, &["PING"], b"+PONG\r\n").await;
+
+        // Other users by ACL, replied in RESP2 as array.
+        roundtrip(
+            stream,
+            &["ACL", "SETUSER", "alice", "on", ">pw", "+@all", "~*"],
+            b"+OK\r\n",
+        )
+        .await;
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let cmd = ["HELLO", "2", "AUTH", "alice", "pw"];
+        roundtrip(&mut stream, &cmd, b"*16\r\n$6\r\nserver\r\n").await;
+        let reply = read_hello(&mut stream, "alice").await;
+        assert!(reply.contains("$5\r\nproto\r\n:2\r\n"), "reply {reply:?}");
+        roundtrip(&mut stream, &["ACL", "WHOAMI"], b"$5\r\nalice\r\n").await;
+        roundtrip(&mut stream, &["GET", "missing"], b"$-1\r\n").await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_command() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let bulk = |x: &str| Value::BulkString(BulkString::new(x));
+
+        let Value::Integer(count) = handle.execute(["COMMAND", "COUNT"]).await.unwrap() else {
+            panic!("COMMAND COUNT replies integer");
+        };
+        let Value::Array(list) = handle.execute(["COMMAND", "LIST"]).await.unwrap() else {
+            panic!("COMMAND LIST replies array");
+        };
+        assert_eq!(count.value(), list.len() as i64);
+
+        let Value::Array(info) = handle
+            .execute(["COMMAND", "INFO", "get", "foo"])
+            .await
+            .unwrap()
+        else {
+            panic!("COMMAND INFO replies array");
+        };
+        let mut info = info.iter();
+        let Some(Value::Array(get)) = info.next() else {
+            panic!("GET is found");
+        };
+        assert_eq!(
+            get.iter().take(6).cloned().collect::<Vec<_>>(),
+            vec![
+                bulk("get"),
+                Value::Integer(Integer::new(2)),
+                Value::Array(Array::with_values(vec![
+                    Value::SimpleString(SimpleString::new("readonly")),
+                    Value::SimpleString(SimpleString::new("fast")),
+                ])),
+                Value::Integer(Integer::new(1)),
+                Value::Integer(Integer::new(1)),
+                Value::Integer(Integer::new(1)),
+            ]
+        );
+        assert_eq!(info.next(), Some(&Value::BulkString(BulkString::null())));
+
+        assert_eq!(
+            handle
+                .execute(["COMMAND", "LIST", "FILTERBY", "PATTERN", "xre*"])
+                .await
+                .unwrap(),
+            Value::Array(Array::with_values(vec![
+                bulk("xread"),
+                bulk("xreadgroup"),
+                bulk("xrevrange"),
+            ]))
+        );
+        assert_eq!(
+            handle
+                .execute(["COMMAND", "GETKEYS", "LMOVE", "a", "b", "LEFT", "RIGHT"])
+                .await
+                .unwrap(),
+            Value::Array(Array::with_values(vec![bulk("a"), bulk("b")]))
+        );
+        assert_eq!(
+            handle.execute(["COMMAND", "GETKEYS", "GET"]).await.unwrap(),
+            Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                "Invalid number of arguments specified for command"
+            ))
+        );
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_arity() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+
+        roundtrip(
+            stream,
+            &["SET", "a"],
+            b"-ERR wrong number of arguments for 'set' command\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["get", "a", "b"],
+            b"-ERR wrong number of arguments for 'get' command\r\n",
+        )
+        .await;
+        roundtrip(stream, &["SET", "a", "1"], b"+OK\r\n").await;
+
+        // Rejected when queueing, fails the transaction.
+        roundtrip(stream, &["MULTI"], b"+OK\r\n").await;
+        roundtrip(
+            stream,
+            &["INCR"],
+            b"-ERR wrong number of arguments for 'incr' command\r\n",
+        )
+        .await;
+        roundtrip(stream, &["INCR", "a"], b"+QUEUED\r\n").await;
+        roundtrip(
+            stream,
+            &["EXEC"],
+            b"-EXECABORT Transaction discarded because of previous errors.\r\n",
+        )
+        .await;
+        roundtrip(stream, &["GET", "a"], b"$1\r\n1\r\n").await;
+
+        // Args rejected by the handler keep the connection too.
+        roundtrip(
+            stream,
+            &["DEBUG", "BIGKEYS", "abc"],
+            b"-ERR syntax error\r\n",
+        )
+        .await;
+        roundtrip(stream, &["MULTI"], b"+OK\r\n").await;
+        roundtrip(stream, &["DEBUG", "BIGKEYS", "abc"], b"+QUEUED\r\n").await;
+        roundtrip(stream, &["INCR", "a"], b"+QUEUED\r\n").await;
+        roundtrip(stream, &["EXEC"], b"*2\r\n-ERR syntax error\r\n:2\r\n").await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_unknown_command() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+
+        roundtrip(
+            stream,
+            &["foo", "a", "b\r\nc"],
+            b"-ERR unknown command 'foo', with args beginning with: 'a' 'b  c' \r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["bar"],
+            b"-ERR unknown command 'bar', with args beginning with: \r\n",
+        )
+        .await;
+        let long = "x".repeat(200);
+        let expected = format!(
+            "-ERR unknown command 'foo', with args beginning with: '{}' \r\n",
+            "x".repeat(128)
+        );
+        roundtrip(stream, &["foo", &long, "y"], expected.as_bytes()).await;
+
+        // The connection is kept.
+        roundtrip(stream, &["PING"], b"+PONG\r\n").await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_info() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+        let info = |section: &'static str| {
+            let handle = &handle;
+            async move {
+                let Value::BulkString(v) = handle.execute(["INFO", section]).await.unwrap() else {
+                    panic!("INFO replies bulk string");
+                };
+                String::from_utf8_lossy(v.value().unwrap()).to_string()
+            }
+        };
+
+        roundtrip(stream, &["SET", "a", "1"], b"+OK\r\n").await;
+        roundtrip(stream, &["SET", "b", "2", "PX", "100000"], b"+OK\r\n").await;
+        roundtrip(stream, &["GET", "a"], b"$1\r\n1\r\n").await;
+        roundtrip(stream, &["GET", "c"], b"$-1\r\n").await;
+
+        let stats = info("stats").await;
+        assert!(stats.starts_with("# Stats\n"));
+        assert!(stats.contains("total_connections_received:1\n"));
+        assert!(stats.contains("keyspace_hits:1\n"));
+        assert!(stats.contains("keyspace_misses:1\n"));
+        assert!(!stats.contains("# Server"));
+
+        assert!(!info("default").await.contains("# Commandstats"));
+
+        let keyspace = info("keyspace").await;
+        assert!(keyspace.contains("db0:keys=2,expires=1,avg_ttl="));
+
+        let clients = info("clients").await;
+        assert!(clients.contains("connected_clients:1\n"));
+
+        let all = info("all").await;
+        let headers = all
+            .lines()
+            .filter(|x| x.starts_with('#'))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            headers,
+            [
+                "# Server",
+                "# Clients",
+                "# Memory",
+                "# Persistence",
+                "# Stats",
+                "# Replication",
+                "# Commandstats",
+                "# Keysizes",
+                "# Keyspace"
+            ]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_commandstats() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+        let info = || async {
+            let Value::BulkString(v) = handle.execute(["INFO", "commandstats"]).await.unwrap()
+            else {
+                panic!("INFO replies bulk string");
+            };
+            String::from_utf8_lossy(v.value().unwrap()).to_string()
+        };
+        // Time spent varies, only check counts around it.
+        let has_stat = |info: &str, name: &str, calls: u64, rejected: u64, failed: u64| {
+            info.lines().any(|x| {
+                x.starts_with(&format!("cmdstat_{name}:calls={calls},usec="))
+                    && x.ends_with(&format!(",rejected_calls={rejected},failed_calls={failed}"))
+            })
+        };
+
+        roundtrip(stream, &["SET", "a", "1"], b"+OK\r\n").await;
+        roundtrip(stream, &["GET", "a"], b"$1\r\n1\r\n").await;
+        roundtrip(
+            stream,
+            &["GET"],
+            b"-ERR wrong number of arguments for 'get' command\r\n",
+        )
+        .await;
+        roundtrip(stream, &["SET", "s", "x"], b"+OK\r\n").await;
+        roundtrip(
+            stream,
+            &["INCR", "s"],
+            b"-ERR value is not an integer or out of range\r\n",
+        )
+        .await;
+        roundtrip(stream, &["MULTI"], b"+OK\r\n").await;
+        roundtrip(stream, &["GET", "a"], b"+QUEUED\r\n").await;
+        roundtrip(stream, &["EXEC"], b"*1\r\n$1\r\n1\r\n").await;
+        roundtrip(stream, &["CLIENT", "GETNAME"], b"$-1\r\n").await;
+        roundtrip(
+            stream,
+            &["FOO"],
+            b"-ERR unknown command 'FOO', with args beginning with: \r\n",
+        )
+        .await;
+
+        let stats = info().await;
+        assert!(has_stat(&stats, "set", 2, 0, 0));
+        assert!(has_stat(&stats, "get", 2, 1, 0));
+        assert!(has_stat(&stats, "incr", 1, 0, 1));
+        assert!(has_stat(&stats, "multi", 1, 0, 0));
+        assert!(has_stat(&stats, "exec", 1, 0, 0));
+        assert!(has_stat(&stats, "client|getname", 1, 0, 0));
+        assert!(!stats.contains("cmdstat_foo"));
+
+        roundtrip(stream, &["CONFIG", "RESETSTAT"], b"+OK\r\n").await;
+        let stats = info().await;
+        assert!(!stats.contains("cmdstat_get"));
+        assert!(has_stat(&stats, "config|resetstat", 1, 0, 0));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_debug() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+        let expired_keys = || async {
+            let Value::BulkString(v) = handle.execute(["INFO", "stats"]).await.unwrap() else {
+                panic!("INFO replies bulk string");
+            };
+            let info = String::from_utf8_lossy(v.value().unwrap()).to_string();
+            info.lines()
+                .find_map(|x| x.strip_prefix("expired_keys:"))
+                .unwrap()
+                .to_string()
+        };
+
+        roundtrip(stream, &["SET", "a", "12345"], b"+OK\r\n").await;
+        let Value::SimpleString(v) = handle.execute(["DEBUG", "OBJECT", "a"]).await.unwrap() else {
+            panic!("DEBUG OBJECT replies simple string");
+        };
+        assert!(v
+            .value()
+            .starts_with("Value refcount:1 encoding:int serializedlength:"));
+        assert!(v.value().ends_with(" lru_seconds_idle:0"));
+        roundtrip(stream, &["DEBUG", "OBJECT", "b"], b"-ERR no such key\r\n").await;
+
+        // Other clients are served while sleeping.
+        let addr = handle.local_addr();
+        let sleep = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            roundtrip(&mut stream, &["DEBUG", "SLEEP", "0.5"], b"+OK\r\n").await;
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let started = tokio::time::Instant::now();
+        roundtrip(stream, &["PING"], b"+PONG\r\n").await;
+        assert!(started.elapsed() < Duration::from_millis(300));
+        assert!(!sleep.is_finished());
+        sleep.await.unwrap();
+
+        roundtrip(stream, &["DEBUG", "SET-ACTIVE-EXPIRE", "0"], b"+OK\r\n").await;
+        roundtrip(stream, &["SET", "b", "1", "PX", "50"], b"+OK\r\n").await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(expired_keys().await, "0");
+
+        roundtrip(stream, &["DEBUG", "SET-ACTIVE-EXPIRE", "1"], b"+OK\r\n").await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(expired_keys().await, "1");
+        roundtrip(
+            stream,
+            &["DEBUG", "SET-ACTIVE-EXPIRE", "2"],
+            b"-ERR syntax error\r\n",
+        )
+        .await;
+        roundtrip(stream, &["DEBUG", "JMAP"], b"+OK\r\n").await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_psync() {
+        let handle = ServerBuilder::new()
+            .port(0)
+            .repl_backlog_size(32)
+            .start()
+            .await
+            .unwrap();
+        let set = |key: &str| format!("*3\r\n$3\r\nSET\r\n$1\r\n{key}\r\n$1\r\n1\r\n");
+
+        let (mut replica, id, offset) = full_sync(&handle).await;
+        let id = id.as_str();
+        assert_eq!(id.len(), 40);
+        assert_eq!(offset, 0);
+
+        handle.execute(["SET", "a", "1"]).await.unwrap();
+        roundtrip(&mut replica, &[], set("a").as_bytes()).await;
+        drop(replica);
+        handle.execute(["SET", "b", "1"]).await.unwrap();
+
+        // Continue after the last byte received, 27 bytes of SET.
+        let mut replica = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let expected = format!("+CONTINUE {id}\r\n{}", set("b"));
+        roundtrip(&mut replica, &["PSYNC", id, "28"], expected.as_bytes()).await;
+
+        // Dropped from the backlog, or another stream.
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let expected = format!("+FULLRESYNC {id} 54\r\n");
+        roundtrip(&mut stream, &["PSYNC", id, "1"], expected.as_bytes()).await;
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let other = "0".repeat(40);
+        roundtrip(&mut stream, &["PSYNC", &other, "28"], expected.as_bytes()).await;
+
+        let Value::BulkString(v) = handle.execute(["INFO", "replication"]).await.unwrap() else {
+            panic!("INFO replies bulk string");
+        };
+        let info = String::from_utf8_lossy(v.value().unwrap()).to_string();
+        assert!(info.contains(&format!("master_replid:{id}\n")));
+        assert!(info.contains("master_repl_offset:54\n"));
+        assert!(info.contains("repl_backlog_first_byte_offset:23\n"));
+        assert!(info.contains("repl_backlog_histlen:32\n"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_diskless_sync() {
+        use tokio::io::AsyncReadExt;
+
+        async fn read_line(stream: &mut TcpStream) -> String {
+            let mut line = vec![];
+            while !line.ends_with(b"\r\n") {
+                line.push(stream.read_u8().await.unwrap());
+            }
+            String::from_utf8(line).unwrap()
+        }
+
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        handle.execute(["SET", "key", "value"]).await.unwrap();
+
+        // $EOF:<40 bytes mark>\r\n<RDB><40 bytes mark>
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        roundtrip(&mut stream, &["REPLCONF", "capa", "eof"], b"+OK\r\n").await;
+        roundtrip(&mut stream, &["PSYNC", "?", "-1"], b"+FULLRESYNC ").await;
+        read_line(&mut stream).await;
+        let header = read_line(&mut stream).await;
+        assert_eq!(header.len(), 5 + 40 + 2, "header {header:?}");
+        let mark = header.strip_prefix("$EOF:").unwrap().trim_end().as_bytes();
+        let mut rdb = vec![];
+        while !rdb.ends_with(mark) {
+            rdb.push(stream.read_u8().await.unwrap());
+        }
+        let rdb = &rdb[..rdb.len() - mark.len()];
+        assert!(rdb.starts_with(b"REDIS"));
+        assert!(rdb.windows(5).any(|x| x == b"value"));
+
+        // The replication stream follows right after the mark.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        handle.execute(["SET", "a", "1"]).await.unwrap();
+        roundtrip(
+            &mut stream,
+            &[],
+            b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n",
+        )
+        .await;
+
+        // Sent with the length if the replica can not parse the mark, or turned off.
+        full_sync(&handle).await;
+        let handle = ServerBuilder::new()
+            .port(0)
+            .repl_diskless_sync(false)
+            .start()
+            .await
+            .unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        roundtrip(&mut stream, &["REPLCONF", "capa", "eof"], b"+OK\r\n").await;
+        roundtrip(&mut stream, &["PSYNC", "?", "-1"], b"+FULLRESYNC ").await;
+        read_line(&mut stream).await;
+        let header = read_line(&mut stream).await;
+        assert!(header[1..header.len() - 2].parse::<usize>().is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_wait() {
+        use tokio::io::AsyncWriteExt;
+
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let getack = b"*3\r\n$8\r\nREPLCONF\r\n$6\r\nGETACK\r\n$1\r\n*\r\n";
+        let ack = |offset: usize| {
+            let offset = offset.to_string();
+            format!(
+                "*3\r\n$8\r\nREPLCONF\r\n$3\r\nACK\r\n${}\r\n{offset}\r\n",
+                offset.len()
+            )
+        };
+        let wait = |args: [&'static str; 3], expected: &'static [u8]| {
+            let addr = handle.local_addr();
+            tokio::spawn(async move {
+                let mut stream = TcpStream::connect(addr).await.unwrap();
+                roundtrip(&mut stream, &args, expected).await;
+            })
+        };
+
+        // Nothing to acknowledge.
+        wait(["WAIT", "0", "0"], b":0\r\n").await.unwrap();
+        let (mut replica, ..) = full_sync(&handle).await;
+        wait(["WAIT", "1", "0"], b":1\r\n").await.unwrap();
+
+        // Acknowledged the 27 bytes of SET, before processing GETACK.
+        handle.execute(["SET", "a", "1"]).await.unwrap();
+        let waiting = wait(["WAIT", "1", "0"], b":1\r\n");
+        roundtrip(
+            &mut replica,
+            &[],
+            b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n",
+        )
+        .await;
+        roundtrip(&mut replica, &[], getack).await;
+        replica.write_all(ack(27).as_bytes()).await.unwrap();
+        waiting.await.unwrap();
+
+        // Timed out with fewer replicas acknowledged.
+        let waiting = wait(["WAIT", "2", "200"], b":1\r\n");
+        roundtrip(&mut replica, &[], getack).await;
+        replica.write_all(ack(64).as_bytes()).await.unwrap();
+        waiting.await.unwrap();
+        let waiting = wait(["WAIT", "1", "100"], b":0\r\n");
+        roundtrip(&mut replica, &[], getack).await;
+        waiting.await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replica_output_buffer_limit() {
+        use tokio::io::AsyncReadExt;
+
+        let handle = ServerBuilder::new()
+            .port(0)
+            .replica_output_buffer_limit(2 * 1024 * 1024)
+            .start()
+            .await
+            .unwrap();
+        let (mut slow, ..) = full_sync(&handle).await;
+        let (mut fast, ..) = full_sync(&handle).await;
+
+        // Beyond what socket buffers take, so commands queue up for the replica not
+        // reading.
+        let value = "x".repeat(1024 * 1024);
+        let set = format!("*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1048576\r\n{value}\r\n");
+        let mut buf = vec![0; set.len()];
+        for _ in 0..32 {
+            handle.execute(["SET", "k", &value]).await.unwrap();
+            fast.read_exact(&mut buf).await.unwrap();
+            assert!(buf == set.as_bytes());
+        }
+
+        // Disconnected, the rest is dropped.
+        let read_all = async {
+            let mut total = 0;
+            loop {
+                match slow.read(&mut buf).await {
+                    Ok(0) | Err(..) => break total,
+                    Ok(n) => total += n,
+                }
+            }
+        };
+        let total = tokio::time::timeout(Duration::from_secs(5), read_all)
+            .await
+            .unwrap();
+        assert!(total < 32 * set.len());
+
+        // Others are kept.
+        handle.execute(["SET", "k", "1"]).await.unwrap();
+        roundtrip(&mut fast, &[], b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\n1\r\n").await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replica_disconnect() {
+        let handle = ServerBuilder::new()
+            .port(0)
+            .repl_timeout(2)
+            .start()
+            .await
+            .unwrap();
+        let connected_slaves = || async {
+            let Value::BulkString(v) = handle.execute(["INFO", "replication"]).await.unwrap()
+            else {
+                panic!("INFO replies bulk string");
+            };
+            let info = String::from_utf8_lossy(v.value().unwrap()).to_string();
+            info.lines()
+                .find_map(|x| x.strip_prefix("connected_slaves:"))
+                .unwrap()
+                .parse::<usize>()
+                .unwrap()
+        };
+
+        // Sends ACK every second.
+        let replica = ServerBuilder::new()
+            .port(0)
+            .replicaof(Some((Ipv4Addr::LOCALHOST, handle.local_addr().port())))
+            .start()
+            .await
+            .unwrap();
+        let (closed, ..) = full_sync(&handle).await;
+        let (_silent, ..) = full_sync(&handle).await;
+        assert_eq!(connected_slaves().await, 3);
+
+        drop(closed);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(connected_slaves().await, 2);
+
+        tokio::time::sleep(Duration::from_millis(3000)).await;
+        assert_eq!(connected_slaves().await, 1);
+        replica.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replicaof() {
+        let master = ServerBuilder::new().port(0).start().await.unwrap();
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        async fn replication(handle: &Handle) -> String {
+            let Value::BulkString(v) = handle.execute(["INFO", "replication"]).await.unwrap()
+            else {
+                panic!("INFO replies bulk string");
+            };
+            String::from_utf8_lossy(v.value().unwrap()).to_string()
+        }
+        let field = |info: &str, name: &str| {
+            info.lines()
+                .find_map(|x| x.strip_prefix(&format!("{name}:")))
+                .unwrap()
+                .to_string()
+        };
+        let port = master.local_addr().port().to_string();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+
+        roundtrip(stream, &["SET", "stale", "1"], b"+OK\r\n").await;
+        master.execute(["SET", "synced", "v"]).await.unwrap();
+        master
+            .execute(["XADD", "s", "1-1", "f", "v"])
+            .await
+            .unwrap();
+        roundtrip(
+            stream,
+            &["REPLICAOF", "localhost", "x"],
+            b"-ERR Invalid master port\r\n",
+        )
+        .await;
+        roundtrip(stream, &["REPLICAOF", "localhost", &port], b"+OK\r\n").await;
+        roundtrip(
+            stream,
+            &["SLAVEOF", "127.0.0.1", &port],
+            b"+OK Already connected to specified master\r\n",
+        )
+        .await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let info = replication(&handle).await;
+        assert_eq!(field(&info, "role"), "slave");
+        let master_id = field(&replication(&master).await, "master_replid");
+        assert_eq!(field(&info, "master_replid"), master_id);
+        // Dataset is replaced by the RDB of master node in full resynchronization.
+        roundtrip(stream, &["GET", "stale"], b"$-1\r\n").await;
+        roundtrip(stream, &["GET", "synced"], b"$1\r\nv\r\n").await;
+        roundtrip(
+            stream,
+            &["XRANGE", "s", "-", "+"],
+            b"*1\r\n*2\r\n+1-1\r\n*2\r\n$1\r\nf\r\n$1\r\nv\r\n",
+        )
+        .await;
+
+        master.execute(["SET", "a", "1"]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        roundtrip(stream, &["GET", "a"], b"$1\r\n1\r\n").await;
+
+        roundtrip(stream, &["REPLICAOF", "NO", "ONE"], b"+OK\r\n").await;
+        let info = replication(&handle).await;
+        assert_eq!(field(&info, "role"), "master");
+        assert_ne!(field(&info, "master_replid"), master_id);
+        master.execute(["SET", "a", "2"]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        roundtrip(stream, &["GET", "a"], b"$1\r\n1\r\n").await;
+
+        // Replicas of the old master continue with the promoted one.
+        let offset = field(&info, "master_repl_offset").parse::<usize>().unwrap();
+        let mut replica = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let expected = format!("+CONTINUE {}\r\n", field(&info, "master_replid"));
+        roundtrip(
+            &mut replica,
+            &["PSYNC", &master_id, &(offset + 1).to_string()],
+            expected.as_bytes(),
+        )
+        .await;
+        master.shutdown().await;
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replica_read_only() {
+        let master = ServerBuilder::new().port(0).start().await.unwrap();
+        let handle = ServerBuilder::new()
+            .port(0)
+            .replicaof(Some((Ipv4Addr::LOCALHOST, master.local_addr().port())))
+            .start()
+            .await
+            .unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+
+        roundtrip(
+            stream,
+            &["SET", "a", "1"],
+            b"-READONLY You can't write against a read only replica.\r\n",
+        )
+        .await;
+        roundtrip(stream, &["MULTI"], b"+OK\r\n").await;
+        roundtrip(
+            stream,
+            &["XGROUP", "CREATE", "s", "g", "$"],
+            b"-READONLY You can't write against a read only replica.\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["EXEC"],
+            b"-EXECABORT Transaction discarded because of previous errors.\r\n",
+        )
+        .await;
+
+        // Writes from master node are applied.
+        master.execute(["SET", "a", "2"]).await.unwrap();
+        master.execute(["RPUSH", "l", "2", "1"]).await.unwrap();
+        master
+            .execute(["GEOADD", "geo", "13.36", "38.11", "p"])
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        roundtrip(stream, &["GET", "a"], b"$1\r\n2\r\n").await;
+
+        // Commands that may store their results are rejected even without STORE, the
+        // read only variants are served.
+        roundtrip(
+            stream,
+            &["SORT", "l"],
+            b"-READONLY You can't write against a read only replica.\r\n",
+        )
+        .await;
+        roundtrip(stream, &["SORT_RO", "l"], b"*2\r\n$1\r\n1\r\n$1\r\n2\r\n").await;
+        roundtrip(
+            stream,
+            &["SORT_RO", "l", "STORE", "d"],
+            b"-ERR syntax error\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["GEORADIUS", "geo", "13.36", "38.11", "1", "km"],
+            b"-READONLY You can't write against a read only replica.\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["GEORADIUS_RO", "geo", "13.36", "38.11", "1", "km"],
+            b"*1\r\n$1\r\np\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["GEORADIUSBYMEMBER_RO", "geo", "p", "1", "km"],
+            b"*1\r\n$1\r\np\r\n",
+        )
+        .await;
+        handle.shutdown().await;
+        master.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_georadius() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+        let add = [
+            "GEOADD",
+            "geo",
+            "13.361389",
+            "38.115556",
+            "Palermo",
+            "15.087269",
+            "37.502669",
+            "Catania",
+        ];
+        roundtrip(stream, &add, b":2\r\n").await;
+
+        roundtrip(
+            stream,
+            &["GEORADIUS", "geo", "15", "37", "200", "km", "WITHDIST", "ASC"],
+            b"*2\r\n*2\r\n$7\r\nCatania\r\n$7\r\n56.4413\r\n*2\r\n$7\r\nPalermo\r\n$8\r\n190.4424\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["GEORADIUSBYMEMBER", "geo", "Palermo", "100", "km"],
+            b"*1\r\n$7\r\nPalermo\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["GEORADIUSBYMEMBER_RO", "geo", "Rome", "100", "km"],
+            b"-ERR could not decode requested zset member\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["GEORADIUS", "geo", "15", "37", "200", "km", "WITHHASH", "STORE", "d"],
+            b"-ERR STORE option in GEORADIUS is not compatible with WITHDIST, WITHHASH and WITHCOORD options\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["GEORADIUS_RO", "geo", "15", "37", "200", "km", "STORE", "d"],
+            b"-ERR syntax error\r\n",
+        )
+        .await;
+
+        // Distances are stored in the unit of the query.
+        roundtrip(
+            stream,
+            &[
+                "GEORADIUS",
+                "geo",
+                "15",
+                "37",
+                "200",
+                "km",
+                "COUNT",
+                "1",
+                "STOREDIST",
+                "d",
+            ],
+            b":1\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["ZPOPMIN", "d"],
+            b"*2\r\n$7\r\nCatania\r\n$16\r\n56.4412",
+        )
+        .await;
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sort() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+        for cmd in [
+            vec!["RPUSH", "l", "3", "1", "2"],
+            vec!["SET", "w_1", "30"],
+            vec!["SET", "w_2", "10"],
+            vec!["SET", "v_3", "c"],
+        ] {
+            handle.execute(cmd).await.unwrap();
+        }
+        let (mut replica, ..) = full_sync(&handle).await;
+
+        roundtrip(
+            stream,
+            &["SORT", "l", "DESC", "LIMIT", "1", "5"],
+            b"*2\r\n$1\r\n2\r\n$1\r\n1\r\n",
+        )
+        .await;
+        // Elements without weight sort as zero.
+        roundtrip(
+            stream,
+            &["SORT_RO", "l", "BY", "w_*", "GET", "#", "GET", "v_*"],
+            b"*6\r\n$1\r\n3\r\n$1\r\nc\r\n$1\r\n2\r\n$-1\r\n$1\r\n1\r\n$-1\r\n",
+        )
+        .await;
+        // BY a pattern without `*` skips sorting.
+        roundtrip(
+            stream,
+            &["SORT", "l", "BY", "nosort"],
+            b"*3\r\n$1\r\n3\r\n$1\r\n1\r\n$1\r\n2\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["SORT", "w_1"],
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["SORT", "v_3", "BY", "v_*"],
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+        )
+        .await;
+        roundtrip(stream, &["RPUSH", "a", "x"], b":1\r\n").await;
+        roundtrip(
+            stream,
+            &["SORT", "a"],
+            b"-ERR One or more scores can't be converted into double\r\n",
+        )
+        .await;
+
+        // The result replaces the destination, synced as is.
+        roundtrip(stream, &["SORT", "l", "ALPHA", "STORE", "w_1"], b":3\r\n").await;
+        roundtrip(
+            stream,
+            &["LRANGE", "w_1", "0", "-1"],
+            b"*3\r\n$1\r\n1\r\n$1\r\n2\r\n$1\r\n3\r\n",
+        )
+        .await;
+        roundtrip(stream, &["SORT", "none", "STORE", "w_2"], b":0\r\n").await;
+        roundtrip(stream, &["TYPE", "w_2"], b"+none\r\n").await;
+        // Nothing synced if the destination stays absent.
+        roundtrip(stream, &["SORT", "none", "STORE", "w_3"], b":0\r\n").await;
+        roundtrip(stream, &["SET", "end", "1"], b"+OK\r\n").await;
+        let synced = [
+            vec!["RPUSH", "a", "x"],
+            vec!["DEL", "w_1"],
+            vec!["RPUSH", "w_1", "1", "2", "3"],
+            vec!["DEL", "w_2"],
+            vec!["SET", "end", "1"],
+        ]
+        .into_iter()
+        .flat_map(|cmd| {
+            let cmd = cmd
+                .into_iter()
+                .map(|x| Value::BulkString(BulkString::new(x)))
+                .collect::<Array>();
+            serde_redis::to_vec(&cmd).unwrap()
+        })
+        .collect::<Vec<_>>();
+        roundtrip(&mut replica, &[], &synced).await;
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replica_ack() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // A master node only replying the handshake.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let master = tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            for reply in ["+PONG\r\n", "+OK\r\n", "+OK\r\n"] {
+                let _ = conn.read(&mut buf).await.unwrap();
+                conn.write_all(reply.as_bytes()).await.unwrap();
+            }
+            let _ = conn.read(&mut buf).await.unwrap();
+            let rdb = b"REDIS0011\xFF\0\0\0\0\0\0\0\0";
+            let reply = format!("+FULLRESYNC {} 0\r\n${}\r\n", "0".repeat(40), rdb.len());
+            conn.write_all(reply.as_bytes()).await.unwrap();
+            conn.write_all(rdb).await.unwrap();
+            conn
+        });
+        let handle = ServerBuilder::new()
+            .port(0)
+            .replicaof(Some((Ipv4Addr::LOCALHOST, port)))
+            .start()
+            .await
+            .unwrap();
+        let mut conn = master.await.unwrap();
+
+        // Acknowledged right after applied, before the periodic one.
+        let set = b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n";
+        conn.write_all(set).await.unwrap();
+        let expected = b"*3\r\n$8\r\nREPLCONF\r\n$3\r\nACK\r\n$2\r\n27\r\n";
+        let mut buf = vec![0; expected.len()];
+        tokio::time::timeout(Duration::from_millis(500), conn.read_exact(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(buf, expected);
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replica_ping() {
+        let handle = ServerBuilder::new()
+            .port(0)
+            .repl_ping_replica_period(1)
+            .start()
+            .await
+            .unwrap();
+        let replica = ServerBuilder::new()
+            .port(0)
+            .replicaof(Some((Ipv4Addr::LOCALHOST, handle.local_addr().port())))
+            .start()
+            .await
+            .unwrap();
+        let (mut stream, ..) = full_sync(&handle).await;
+        handle.execute(["SET", "a", "1"]).await.unwrap();
+
+        // PING follows the command in the replication stream.
+        let set = b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n";
+        roundtrip(&mut stream, &[], set).await;
+        roundtrip(&mut stream, &[], b"*1\r\n$4\r\nPING\r\n").await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let Value::BulkString(v) = handle.execute(["INFO", "replication"]).await.unwrap() else {
+            panic!("INFO replies bulk string");
+        };
+        let info = String::from_utf8_lossy(v.value().unwrap()).to_string();
+        let expected = format!(
+            "slave0:ip=127.0.0.1,port={},state=online,offset={},lag=0",
+            replica.local_addr().port(),
+            set.len() + 14
+        );
+        assert!(info.contains(&expected), "{info}");
+        // The fake replica never acknowledged.
+        assert!(info.contains("slave1:ip=127.0.0.1,port=0,state=online,offset=0,lag="));
+        replica.shutdown().await;
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_propagate_effects() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        use tokio::io::AsyncReadExt;
+
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let (mut replica, ..) = full_sync(&handle).await;
+
+        // Relative expiration is synced as absolute time, options resolved are dropped.
+        handle
+            .execute(["SET", "a", "1", "NX", "EXAT", "4102444800"])
+            .await
+            .unwrap();
+        roundtrip(
+            &mut replica,
+            &[],
+            b"*5\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n$4\r\nPXAT\r\n$13\r\n4102444800000\r\n",
+        )
+        .await;
+
+        // Nothing synced if not set.
+        handle.execute(["SET", "a", "2", "NX"]).await.unwrap();
+        handle.execute(["GETEX", "b", "PERSIST"]).await.unwrap();
+        handle.execute(["GETEX", "a", "PERSIST"]).await.unwrap();
+        roundtrip(
+            &mut replica,
+            &[],
+            b"*3\r\n$5\r\nGETEX\r\n$1\r\na\r\n$7\r\nPERSIST\r\n",
+        )
+        .await;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        handle.execute(["SETEX", "c", "100", "3"]).await.unwrap();
+        roundtrip(
+            &mut replica,
+            &[],
+            b"*5\r\n$3\r\nSET\r\n$1\r\nc\r\n$1\r\n3\r\n$4\r\nPXAT\r\n$13\r\n",
+        )
+        .await;
+        let mut millis = [0; 15];
+        replica.read_exact(&mut millis).await.unwrap();
+        let millis: u128 = std::str::from_utf8(&millis[..13]).unwrap().parse().unwrap();
+        let expected = (now + Duration::from_secs(100)).as_millis();
+        assert!(millis >= expected && millis < expected + 1000);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_propagate_changes_only() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let (mut replica, ..) = full_sync(&handle).await;
+
+        // Commands changing nothing are not synced.
+        handle.execute(["DEL", "a"]).await.unwrap();
+        handle.execute(["LPOP", "a"]).await.unwrap();
+        handle.execute(["SET", "a", "1"]).await.unwrap();
+        handle.execute(["SET", "a", "2", "NX"]).await.unwrap();
+        handle.execute(["LSET", "b", "0", "v"]).await.unwrap();
+        handle.execute(["INCR", "a"]).await.unwrap();
+        roundtrip(
+            &mut replica,
+            &[],
+            b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n*2\r\n$4\r\nINCR\r\n$1\r\na\r\n",
+        )
+        .await;
+        match handle.execute(["INFO", "persistence"]).await.unwrap() {
+            Value::BulkString(s) => {
+                let text = String::from_utf8(s.value().unwrap().to_vec()).unwrap();
+                assert!(text.contains("rdb_changes_since_last_save:2\n"));
+            }
+            v => panic!("unexpected INFO reply {v:?}"),
+        }
+
+        // Members fed to blocked BZPOPMIN are popped right after added.
+        let mut blocked = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let waiting = tokio::spawn(async move {
+            roundtrip(
+                &mut blocked,
+                &["BZPOPMIN", "z", "0"],
+                b"*3\r\n$1\r\nz\r\n$1\r\nm\r\n$1\r\n1\r\n",
+            )
+            .await;
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        handle.execute(["ZINCRBY", "z", "1", "m"]).await.unwrap();
+        waiting.await.unwrap();
+        roundtrip(
+            &mut replica,
+            &[],
+            b"*4\r\n$7\r\nZINCRBY\r\n$1\r\nz\r\n$1\r\n1\r\n$1\r\nm\r\n*2\r\n$7\r\nZPOPMIN\r\n$1\r\nz\r\n",
+        )
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_stream_cap() {
+        async fn xinfo(handle: &Handle, key: &str, field: &str) -> Value {
+            let Value::Array(info) = handle.execute(["XINFO", "STREAM", key]).await.unwrap() else {
+                panic!("XINFO STREAM replies array");
+            };
+            let info = info.iter().collect::<Vec<_>>();
+            let pos = info
+                .iter()
+                .position(|x| **x == Value::BulkString(BulkString::new(field)))
+                .unwrap();
+            info[pos + 1].clone()
+        }
+
+        let master = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(master.local_addr()).await.unwrap();
+        let stream = &mut stream;
+        roundtrip(
+            stream,
+            &["XADD", "s", "CAP", "0", "*", "f", "v"],
+            b"-ERR The CAP argument must be > 0.\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["XADD", "s", "LIMIT", "5", "*", "f", "v"],
+            b"-ERR syntax error, LIMIT cannot be used without the special ~ option\r\n",
+        )
+        .await;
+        for id in ["1-0", "2-0", "3-0"] {
+            master
+                .execute(["XADD", "s", "CAP", "2", id, "f", "v"])
+                .await
+                .unwrap();
+        }
+
+        // Trims by the cap are synced as the length left.
+        let (mut replica, ..) = full_sync(&master).await;
+        master
+            .execute(["XADD", "s", "4-0", "f", "v"])
+            .await
+            .unwrap();
+        roundtrip(
+            &mut replica,
+            &[],
+            b"*8\r\n$4\r\nXADD\r\n$1\r\ns\r\n$6\r\nMAXLEN\r\n$1\r\n=\r\n$1\r\n2\r\n\
+              $3\r\n4-0\r\n$1\r\nf\r\n$1\r\nv\r\n",
+        )
+        .await;
+
+        // The cap is kept by full resynchronization.
+        let handle = ServerBuilder::new()
+            .port(0)
+            .replicaof(Some((Ipv4Addr::LOCALHOST, master.local_addr().port())))
+            .start()
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        master
+            .execute(["XADD", "s", "5-0", "f", "v"])
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        for server in [&master, &handle] {
+            assert_eq!(
+                xinfo(server, "s", "max-len").await,
+                Value::Integer(Integer::new(2))
+            );
+            assert_eq!(
+                server.execute(["XRANGE", "s", "-", "+"]).await.unwrap(),
+                master.execute(["XRANGE", "s", "4-0", "+"]).await.unwrap()
+            );
+        }
+
+        // And by DUMP and EXPORT.
+        let payload = match master.execute(["DUMP", "s"]).await.unwrap() {
+            Value::BulkString(v) => v.value().unwrap().clone(),
+            v => panic!("unexpected DUMP reply {v:?}"),
+        };
+        master
+            .execute([b"RESTORE".as_slice(), b"d", b"0", &payload])
+            .await
+            .unwrap();
+        let json = match master.execute(["EXPORT", "s"]).await.unwrap() {
+            Value::BulkString(v) => v.value().unwrap().clone(),
+            v => panic!("unexpected EXPORT reply {v:?}"),
+        };
+        let json = String::from_utf8(json).unwrap().replace("\"s\"", "\"e\"");
+        master.execute(["IMPORT", &json]).await.unwrap();
+        for key in ["d", "e"] {
+            assert_eq!(
+                xinfo(&master, key, "max-len").await,
+                Value::Integer(Integer::new(2))
+            );
+        }
+        handle.shutdown().await;
+        master.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_failover() {
+        let master = ServerBuilder::new().port(0).start().await.unwrap();
+        let replica = ServerBuilder::new()
+            .port(0)
+            .replicaof(Some((Ipv4Addr::LOCALHOST, master.local_addr().port())))
+            .start()
+            .await
+            .unwrap();
+        async fn replication(handle: &Handle) -> String {
+            let Value::BulkString(v) = handle.execute(["INFO", "replication"]).await.unwrap()
+            else {
+                panic!("INFO replies bulk string");
+            };
+            String::from_utf8_lossy(v.value().unwrap()).to_string()
+        }
+        let error = |message: &str| Value::SimpleError(SimpleError::with_prefix("ERR", message));
+        let port = replica.local_addr().port().to_string();
+        master.execute(["SET", "a", "1"]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(
+            replica.execute(["FAILOVER"]).await.unwrap(),
+            error("FAILOVER is not valid when server is a replica.")
+        );
+        assert_eq!(
+            master
+                .execute(["FAILOVER", "TO", "127.0.0.1", "1"])
+                .await
+                .unwrap(),
+            error("FAILOVER target HOST and PORT is not a replica.")
+        );
+        assert_eq!(
+            master.execute(["FAILOVER", "FORCE"]).await.unwrap(),
+            error("FAILOVER with force option requires both a timeout and target HOST and IP.")
+        );
+        assert_eq!(
+            master.execute(["FAILOVER", "ABORT"]).await.unwrap(),
+            error("No failover in progress.")
+        );
+
+        assert_eq!(
+            master
+                .execute(["FAILOVER", "TO", "localhost", &port, "TIMEOUT", "5000"])
+                .await
+                .unwrap(),
+            Value::SimpleString(SimpleString::new("OK"))
+        );
+        for _ in 0..50 {
+            let info = replication(&master).await;
+            if info.contains("role:slave\n") && info.contains("master_failover_state:no-failover\n")
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let info = replication(&master).await;
+        assert!(
+            info.contains("master_failover_state:no-failover\n"),
+            "{info}"
+        );
+        assert!(replication(&replica).await.contains("role:master\n"));
+
+        // Roles are switched, the old master follows the new one.
+        replica.execute(["SET", "b", "2"]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            master.execute(["GET", "b"]).await.unwrap(),
+            Value::BulkString(BulkString::new("2"))
+        );
+        assert_eq!(
+            replica.execute(["GET", "a"]).await.unwrap(),
+            Value::BulkString(BulkString::new("1"))
+        );
+        replica.shutdown().await;
+        master.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cluster() {
+        let dir = std::env::temp_dir();
+        let name_a = format!("test-cluster-a-{}.conf", std::process::id());
+        let name_b = format!("test-cluster-b-{}.conf", std::process::id());
+        let _ = std::fs::remove_file(dir.join(&name_a));
+        let a = ServerBuilder::new()
+            .port(0)
+            .dir(&dir)
+            .cluster_enabled(true)
+            .cluster_config_file(&name_a)
+            .start()
+            .await
+            .unwrap();
+        let ok = Value::SimpleString(SimpleString::new("OK"));
+        let error = |prefix: &str, message: &str| {
+            Value::SimpleError(SimpleError::with_prefix(prefix, message))
+        };
+        let text = |value: Value| match value {
+            Value::BulkString(v) => String::from_utf8(v.value().unwrap().to_vec()).unwrap(),
+            v => panic!("unexpected reply {v:?}"),
+        };
+
+        let info = text(a.execute(["CLUSTER", "INFO"]).await.unwrap());
+        assert!(info.starts_with("cluster_state:fail\ncluster_slots_assigned:0\n"));
+        assert_eq!(
+            a.execute(["GET", "foo"]).await.unwrap(),
+            error("CLUSTERDOWN", "Hash slot not served")
+        );
+        assert_eq!(
+            a.execute(["CLUSTER", "ADDSLOTSRANGE", "0", "16383"])
+                .await
+                .unwrap(),
+            ok
+        );
+        assert_eq!(
+            a.execute(["CLUSTER", "ADDSLOTS", "0"]).await.unwrap(),
+            error("ERR", "Slot 0 is already busy")
+        );
+        assert_eq!(
+            a.execute(["CLUSTER", "DELSLOTS", "1", "1"]).await.unwrap(),
+            error("ERR", "Slot 1 specified multiple times")
+        );
+        let info = text(a.execute(["CLUSTER", "INFO"]).await.unwrap());
+        assert!(info.contains("cluster_state:ok\n"), "{info}");
+        assert!(info.contains("cluster_size:1\n"), "{info}");
+        assert_eq!(a.execute(["SET", "foo", "1"]).await.unwrap(), ok);
+        assert_eq!(
+            a.execute(["CLUSTER", "KEYSLOT", "foo"]).await.unwrap(),
+            Value::Integer(Integer::new(12182))
+        );
+        assert_eq!(
+            a.execute(["CLUSTER", "KEYSLOT", "{foo}.bar"])
+                .await
+                .unwrap(),
+            Value::Integer(Integer::new(12182))
+        );
+        assert_eq!(
+            a.execute(["DEL", "foo", "bar"]).await.unwrap(),
+            error("CROSSSLOT", "Keys in request don't hash to the same slot")
+        );
+        assert_eq!(
+            a.execute(["DEL", "{foo}.a", "{foo}.b"]).await.unwrap(),
+            Value::Integer(Integer::new(0))
+        );
+        let id_a = text(a.execute(["CLUSTER", "MYID"]).await.unwrap());
+        let port_a = a.local_addr().port();
+        assert_eq!(
+            a.execute(["CLUSTER", "SLOTS"]).await.unwrap(),
+            Value::Array(Array::with_values(vec![Value::Array(Array::with_values(
+                vec![
+                    Value::Integer(Integer::new(0)),
+                    Value::Integer(Integer::new(16383)),
+                    Value::Array(Array::with_values(vec![
+                        Value::BulkString(BulkString::new("127.0.0.1")),
+                        Value::Integer(Integer::new(port_a as i64)),
+                        Value::BulkString(BulkString::new(id_a.clone())),
+                    ])),
+                ]
+            ))]))
+        );
+        // Slots are saved in the nodes config file.
+        let saved = std::fs::read_to_string(dir.join(&name_a)).unwrap();
+        assert!(saved.contains(&format!("{id_a} 127.0.0.1:{port_a}@")));
+        assert!(saved.contains("myself,master - 0 0 0 connected 0-16383\n"));
+
+        // Node b serves half of the slots, and is migrating slot 5061 of "bar" to a.
+        let id_b = "67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1";
+        std::fs::write(
+            dir.join(&name_b),
+            format!(
+                "{id_b} 127.0.0.1:0@10000 myself,master - 0 0 0 connected 0-8191 [5061->-{id_a}]\n\
+                 {id_a} 127.0.0.1:{port_a}@1 master - 0 0 0 connected 8192-16383\n"
+            ),
+        )
+        .unwrap();
+        let b = ServerBuilder::new()
+            .port(0)
+            .dir(&dir)
+            .cluster_enabled(true)
+            .cluster_config_file(&name_b)
+            .start()
+            .await
+            .unwrap();
+        assert_eq!(text(b.execute(["CLUSTER", "MYID"]).await.unwrap()), id_b);
+        assert_eq!(
+            b.execute(["GET", "foo"]).await.unwrap(),
+            error("MOVED", &format!("12182 127.0.0.1:{port_a}"))
+        );
+        assert_eq!(
+            b.execute(["GET", "bar"]).await.unwrap(),
+            error("ASK", &format!("5061 127.0.0.1:{port_a}"))
+        );
+        assert_eq!(
+            b.execute(["GET", "hello"]).await.unwrap(),
+            Value::BulkString(BulkString::null())
+        );
+        assert_eq!(
+            b.execute(["PING"]).await.unwrap(),
+            Value::SimpleString(SimpleString::new("PONG"))
+        );
+        b.shutdown().await;
+        a.shutdown().await;
+        let _ = std::fs::remove_file(dir.join(&name_a));
+        let _ = std::fs::remove_file(dir.join(&name_b));
+
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        assert_eq!(
+            handle.execute(["CLUSTER", "INFO"]).await.unwrap(),
+            error("ERR", "This instance has cluster support disabled")
+        );
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cluster_bus() {
+        let dir = std::env::temp_dir();
+        let names = ["a", "b", "c"].map(|x| format!("test-bus-{x}-{}.conf", std::process::id()));
+        let mut nodes = vec![];
+        for name in &names {
+            let _ = std::fs::remove_file(dir.join(name));
+            let handle = ServerBuilder::new()
+                .port(0)
+                .dir(&dir)
+                .cluster_enabled(true)
+                .cluster_config_file(name)
+                .cluster_node_timeout(500)
+                .start()
+                .await
+                .unwrap();
+            nodes.push(handle);
+        }
+        let [a, b, c] = <[Handle; 3]>::try_from(nodes).ok().unwrap();
+        fn text(value: Value) -> String {
+            match value {
+                Value::BulkString(v) => String::from_utf8(v.value().unwrap().to_vec()).unwrap(),
+                v => panic!("unexpected reply {v:?}"),
+            }
+        }
+        // Poll CLUSTER INFO of `handle` till it contains all of `want`.
+        async fn wait_for(handle: &Handle, want: &[&str]) {
+            for _ in 0..100 {
+                let info = text(handle.execute(["CLUSTER", "INFO"]).await.unwrap());
+                if want.iter().all(|x| info.contains(x)) {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            panic!("{want:?} not found in cluster info");
+        }
+        let ok = Value::SimpleString(SimpleString::new("OK"));
+
+        // A majority of nodes owning slots is needed to flag failures.
+        for (node, start, end) in [
+            (&a, "0", "4095"),
+            (&b, "4096", "12287"),
+            (&c, "12288", "16383"),
+        ] {
+            let reply = node.execute(["CLUSTER", "ADDSLOTSRANGE", start, end]);
+            assert_eq!(reply.await.unwrap(), ok);
+        }
+        assert_eq!(
+            a.execute(["CLUSTER", "MEET", "127.0.0.1", "x"])
+                .await
+                .unwrap(),
+            Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                "Invalid base port specified: x"
+            ))
+        );
+        // a meets b and c, c learns b by gossip.
+        for node in [&b, &c] {
+            let nodes = text(node.execute(["CLUSTER", "NODES"]).await.unwrap());
+            let cport = nodes.split_once('@').unwrap().1.split_once(' ').unwrap().0;
+            let port = node.local_addr().port().to_string();
+            let reply = a
+                .execute(["CLUSTER", "MEET", "127.0.0.1", &port, cport])
+                .await
+                .unwrap();
+            assert_eq!(reply, ok);
+        }
+        let info = [
+            "cluster_state:ok\n",
+            "cluster_known_nodes:3\n",
+            "cluster_size:3\n",
+        ];
+        wait_for(&c, &info).await;
+        let port_b = b.local_addr().port();
+        assert_eq!(
+            c.execute(["GET", "foo"]).await.unwrap(),
+            Value::SimpleError(SimpleError::with_prefix(
+                "MOVED",
+                format!("12182 127.0.0.1:{port_b}")
+            ))
+        );
+
+        // b is flagged failing by a and c, once not answering.
+        let id_b = text(b.execute(["CLUSTER", "MYID"]).await.unwrap());
+        b.shutdown().await;
+        for node in [&a, &c] {
+            wait_for(node, &["cluster_state:fail\n", "cluster_slots_fail:8192\n"]).await;
+            let nodes = text(node.execute(["CLUSTER", "NODES"]).await.unwrap());
+            let line = nodes.lines().find(|x| x.starts_with(&id_b)).unwrap();
+            assert!(line.contains(" master,fail - "), "{line}");
+        }
+        a.shutdown().await;
+        c.shutdown().await;
+        for name in &names {
+            let _ = std::fs::remove_file(dir.join(name));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_crossslot() {
+        let dir = std::env::temp_dir();
+        let name = format!("test-crossslot-{}.conf", std::process::id());
+        let _ = std::fs::remove_file(dir.join(&name));
+        let handle = ServerBuilder::new()
+            .port(0)
+            .dir(&dir)
+            .cluster_enabled(true)
+            .cluster_config_file(&name)
+            .start()
+            .await
+            .unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+        let crossslot = b"-CROSSSLOT Keys in request don't hash to the same slot\r\n";
+
+        roundtrip(
+            stream,
+            &["CLUSTER", "ADDSLOTSRANGE", "0", "16383"],
+            b"+OK\r\n",
+        )
+        .await;
+        roundtrip(stream, &["RPUSH", "foo", "a"], b":1\r\n").await;
+        // Keys at the positions in the command table, or parsed for movable keys.
+        roundtrip(stream, &["RPOPLPUSH", "foo", "bar"], crossslot).await;
+        roundtrip(stream, &["LMOVE", "foo", "bar", "LEFT", "LEFT"], crossslot).await;
+        roundtrip(stream, &["BLPOP", "foo", "bar", "1"], crossslot).await;
+        roundtrip(stream, &["LMPOP", "2", "foo", "bar", "LEFT"], crossslot).await;
+        roundtrip(
+            stream,
+            &["BLMPOP", "1", "2", "foo", "bar", "LEFT"],
+            crossslot,
+        )
+        .await;
+        roundtrip(stream, &["SORT", "foo", "ALPHA", "STORE", "bar"], crossslot).await;
+        roundtrip(stream, &["PFMERGE", "foo", "bar"], crossslot).await;
+        roundtrip(
+            stream,
+            &["XREAD", "STREAMS", "foo", "bar", "0", "0"],
+            crossslot,
+        )
+        .await;
+        roundtrip(stream, &["LLEN", "foo"], b":1\r\n").await;
+
+        // Keys sharing a hash tag are in the same slot.
+        roundtrip(stream, &["RPOPLPUSH", "foo", "{foo}.bar"], b"$1\r\na\r\n").await;
+        roundtrip(
+            stream,
+            &["LMPOP", "2", "{foo}.a", "{foo}.bar", "LEFT"],
+            b"*2\r\n$9\r\n{foo}.bar\r\n*1\r\n$1\r\na\r\n",
+        )
+        .await;
+        // Only the keys count, not the other arguments.
+        roundtrip(
+            stream,
+            &["LMPOP", "1", "foo", "LEFT", "COUNT", "1"],
+            b"*-1\r\n",
+        )
+        .await;
+
+        handle.shutdown().await;
+        let _ = std::fs::remove_file(dir.join(&name));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cluster_migration() {
+        let dir = std::env::temp_dir();
+        let names = ["a", "b"].map(|x| format!("test-migrate-{x}-{}.conf", std::process::id()));
+        let mut nodes = vec![];
+        for name in &names {
+            let _ = std::fs::remove_file(dir.join(name));
+            let handle = ServerBuilder::new()
+                .port(0)
+                .dir(&dir)
+                .cluster_enabled(true)
+                .cluster_config_file(name)
+                .cluster_node_timeout(500)
+                .start()
+                .await
+                .unwrap();
+            nodes.push(handle);
+        }
+        let [a, b] = <[Handle; 2]>::try_from(nodes).ok().unwrap();
+        fn text(value: Value) -> String {
+            match value {
+                Value::BulkString(v) => String::from_utf8(v.value().unwrap().to_vec()).unwrap(),
+                v => panic!("unexpected reply {v:?}"),
+            }
+        }
+        let ok = Value::SimpleString(SimpleString::new("OK"));
+        let error = |prefix: &str, message: String| {
+            Value::SimpleError(SimpleError::with_prefix(prefix, message))
+        };
+        let (port_a, port_b) = (a.local_addr().port(), b.local_addr().port());
+        let (id_a, id_b) = (
+            text(a.execute(["CLUSTER", "MYID"]).await.unwrap()),
+            text(b.execute(["CLUSTER", "MYID"]).await.unwrap()),
+        );
+
+        // a owns all slots, b joins owning none.
+        let reply = a.execute(["CLUSTER", "ADDSLOTSRANGE", "0", "16383"]);
+        assert_eq!(reply.await.unwrap(), ok);
+        let nodes = text(b.execute(["CLUSTER", "NODES"]).await.unwrap());
+        let cport = nodes.split_once('@').unwrap().1.split_once(' ').unwrap().0;
+        let port = port_b.to_string();
+        let reply = a.execute(["CLUSTER", "MEET", "127.0.0.1", &port, cport]);
+        assert_eq!(reply.await.unwrap(), ok);
+        for _ in 0..100 {
+            let nodes = text(b.execute(["CLUSTER", "NODES"]).await.unwrap());
+            if nodes.contains(&id_a) && !nodes.contains("handshake") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(a.execute(["SET", "foo", "1"]).await.unwrap(), ok);
+        let reply = a.execute(["RPUSH", "{foo}.list", "x", "y"]).await.unwrap();
+        assert_eq!(reply, Value::Integer(Integer::new(2)));
+        assert_eq!(
+            a.execute(["CLUSTER", "COUNTKEYSINSLOT", "12182"])
+                .await
+                .unwrap(),
+            Value::Integer(Integer::new(2))
+        );
+
+        // Slot 12182 of "foo" moves from a to b.
+        let reply = b.execute(["CLUSTER", "SETSLOT", "12182", "IMPORTING", &id_a]);
+        assert_eq!(reply.await.unwrap(), ok);
+        let reply = a.execute(["CLUSTER", "SETSLOT", "12182", "MIGRATING", &id_b]);
+        assert_eq!(reply.await.unwrap(), ok);
+        assert_eq!(
+            a.execute(["CLUSTER", "SETSLOT", "12182", "NODE", &id_b])
+                .await
+                .unwrap(),
+            error(
+                "ERR",
+                "Can't assign hashslot 12182 to a different node while I still hold keys for this hash slot.".to_string()
+            )
+        );
+        assert_eq!(
+            a.execute(["GET", "{foo}.new"]).await.unwrap(),
+            error("ASK", format!("12182 127.0.0.1:{port_b}"))
+        );
+        let moved = error("MOVED", format!("12182 127.0.0.1:{port_a}"));
+        assert_eq!(b.execute(["GET", "foo"]).await.unwrap(), moved);
+
+        let reply = a.execute(["MIGRATE", "127.0.0.1", &port, "foo", "0", "1000"]);
+        assert_eq!(reply.await.unwrap(), ok);
+        let reply = a.execute([
+            "MIGRATE",
+            "127.0.0.1",
+            &port,
+            "",
+            "0",
+            "1000",
+            "KEYS",
+            "foo",
+            "{foo}.list",
+        ]);
+        assert_eq!(reply.await.unwrap(), ok);
+        let reply = a.execute(["MIGRATE", "127.0.0.1", &port, "foo", "0", "1000"]);
+        assert_eq!(
+            reply.await.unwrap(),
+            Value::SimpleString(SimpleString::new("NOKEY"))
+        );
+        assert_eq!(
+            a.execute(["CLUSTER", "GETKEYSINSLOT", "12182", "10"])
+                .await
+                .unwrap(),
+            Value::Array(Array::with_values(vec![]))
+        );
+        assert_eq!(
+            a.execute(["GET", "foo"]).await.unwrap(),
+            error("ASK", format!("12182 127.0.0.1:{port_b}"))
+        );
+
+        // b serves the slot only right after ASKING.
+        let mut stream = TcpStream::connect(b.local_addr()).await.unwrap();
+        let stream = &mut stream;
+        roundtrip(stream, &["ASKING"], b"+OK\r\n").await;
+        roundtrip(stream, &["GET", "foo"], b"$1\r\n1\r\n").await;
+        roundtrip(
+            stream,
+            &["GET", "foo"],
+            format!("-MOVED 12182 127.0.0.1:{port_a}\r\n").as_bytes(),
+        )
+        .await;
+        roundtrip(stream, &["ASKING"], b"+OK\r\n").await;
+        roundtrip(
+            stream,
+            &["LRANGE", "{foo}.list", "0", "-1"],
+            b"*2\r\n$1\r\nx\r\n$1\r\ny\r\n",
+        )
+        .await;
+
+        // b takes the slot, a learns it by gossip.
+        let reply = b.execute(["CLUSTER", "SETSLOT", "12182", "NODE", &id_b]);
+        assert_eq!(reply.await.unwrap(), ok);
+        assert_eq!(
+            text(b.execute(["GET", "foo"]).await.unwrap()),
+            "1".to_string()
+        );
+        let moved = error("MOVED", format!("12182 127.0.0.1:{port_b}"));
+        for _ in 0..100 {
+            if a.execute(["GET", "foo"]).await.unwrap() == moved {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(a.execute(["GET", "foo"]).await.unwrap(), moved);
+
+        // DUMP and RESTORE.
+        let Value::BulkString(payload) = b.execute(["DUMP", "foo"]).await.unwrap() else {
+            panic!("DUMP replies bulk string");
+        };
+        let payload = payload.value().unwrap().to_vec();
+        assert_eq!(
+            b.execute([b"RESTORE".as_slice(), b"foo", b"0", &payload])
+                .await
+                .unwrap(),
+            error("BUSYKEY", "Target key name already exists.".to_string())
+        );
+        let reply = b.execute([b"RESTORE".as_slice(), b"{foo}.copy", b"0", &payload]);
+        assert_eq!(reply.await.unwrap(), ok);
+        assert_eq!(
+            text(b.execute(["GET", "{foo}.copy"]).await.unwrap()),
+            "1".to_string()
+        );
+        assert_eq!(
+            b.execute(["RESTORE", "foo", "0", "bad", "REPLACE"])
+                .await
+                .unwrap(),
+            error(
+                "ERR",
+                "DUMP payload version or checksum are wrong".to_string()
+            )
+        );
+
+        a.shutdown().await;
+        b.shutdown().await;
+        for name in &names {
+            let _ = std::fs::remove_file(dir.join(name));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_pipeline() {
+        use tokio::io::AsyncWriteExt;
+
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+
+        // Commands in one write, and a command split across writes.
+        let commands = "*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\n1\r\n\
+                        *2\r\n$4\r\nINCR\r\n$1\r\nk\r\n\
+                        *2\r\n$3\r\nGET\r\n$1\r\nk\r\n*2\r\n$3\r\nGE";
+        stream.write_all(commands.as_bytes()).await.unwrap();
+        roundtrip(&mut stream, &[], b"+OK\r\n:2\r\n$1\r\n2\r\n").await;
+        stream.write_all(b"T\r\n$1\r\nk\r\n").await.unwrap();
+        roundtrip(&mut stream, &[], b"$1\r\n2\r\n").await;
+
+        // Replies before a blocking command are not held by it.
+        let commands = "*3\r\n$5\r\nRPUSH\r\n$1\r\nl\r\n$1\r\na\r\n\
+                        *3\r\n$5\r\nBLPOP\r\n$1\r\nm\r\n$1\r\n2\r\n";
+        stream.write_all(commands.as_bytes()).await.unwrap();
+        let replied = roundtrip(&mut stream, &[], b":1\r\n");
+        tokio::time::timeout(Duration::from_secs(1), replied)
+            .await
+            .unwrap();
+        roundtrip(&mut stream, &[], b"*-1\r\n").await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_protocol_error() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        for commands in [
+            "*1\r\n$4\r\nPING\r\n*1\r\n$x\r\n",
+            "*1\r\n$4\r\nPING\r\n+OK\r\n",
+        ] {
+            let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+            stream.write_all(commands.as_bytes()).await.unwrap();
+            // Replies queued before are sent, then the connection is closed.
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).await.unwrap();
+            assert!(
+                reply.starts_with("+PONG\r\n-ERR Protocol error: "),
+                "unexpected reply {reply:?}"
+            );
+            assert!(reply.ends_with("\r\n") && reply.matches("\r\n").count() == 2);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_large_reply() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+
+        // Far beyond what socket buffers take in a single write.
+        let value = "x".repeat(4 * 1024 * 1024);
+        handle.execute(["SET", "k", &value]).await.unwrap();
+        let expected = format!("${}\r\n{value}\r\n", value.len());
+        roundtrip(&mut stream, &["GET", "k"], expected.as_bytes()).await;
+        roundtrip(&mut stream, &["PING"], b"+PONG\r\n").await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_binary_values() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+
+        // Compared as is, invalid UTF-8 bytes all look the same when lossy.
+        let mut roundtrip = async |cmd: &[&[u8]], expected: &[u8]| {
+            let cmd = cmd
+                .iter()
+                .map(|x| Value::BulkString(BulkString::new(*x)))
+                .collect::<Array>();
+            stream
+                .write_all(&serde_redis::to_vec(&cmd).unwrap())
+                .await
+                .unwrap();
+            let mut buf = vec![0; expected.len()];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, expected);
+        };
+        roundtrip(&[b"SET", b"k", b"\xff\x00\r\n"], b"+OK\r\n").await;
+        roundtrip(&[b"GET", b"k"], b"$4\r\n\xff\x00\r\n\r\n").await;
+        roundtrip(&[b"RPUSH", b"l", b"\xfe", b"\xff"], b":2\r\n").await;
+        roundtrip(&[b"LSET", b"l", b"0", b"\xfd"], b"+OK\r\n").await;
+        roundtrip(&[b"LINSERT", b"l", b"AFTER", b"\xfd", b"\xfc"], b":3\r\n").await;
+        roundtrip(
+            &[b"LRANGE", b"l", b"0", b"-1"],
+            b"*3\r\n$1\r\n\xfd\r\n$1\r\n\xfc\r\n$1\r\n\xff\r\n",
+        )
+        .await;
+        roundtrip(&[b"XADD", b"s", b"1-1", b"f", b"\xff"], b"$3\r\n1-1\r\n").await;
+        roundtrip(
+            &[b"XRANGE", b"s", b"-", b"+"],
+            b"*1\r\n*2\r\n+1-1\r\n*2\r\n$1\r\nf\r\n$1\r\n\xff\r\n",
+        )
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_binary_keys() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+
+        let mut roundtrip = async |cmd: &[&[u8]], expected: &[u8]| {
+            let cmd = cmd
+                .iter()
+                .map(|x| Value::BulkString(BulkString::new(*x)))
+                .collect::<Array>();
+            stream
+                .write_all(&serde_redis::to_vec(&cmd).unwrap())
+                .await
+                .unwrap();
+            let mut buf = vec![0; expected.len()];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, expected);
+        };
+        // Keys differing only in invalid UTF-8 bytes are different keys.
+        roundtrip(&[b"RPUSH", b"\xff", b"a"], b":1\r\n").await;
+        roundtrip(&[b"RPUSH", b"\xfe", b"b"], b":1\r\n").await;
+        roundtrip(&[b"LRANGE", b"\xff", b"0", b"-1"], b"*1\r\n$1\r\na\r\n").await;
+        roundtrip(&[b"TYPE", b"\xff"], b"+list\r\n").await;
+        roundtrip(
+            &[b"BLPOP", b"\xfd", b"\xfe", b"0"],
+            b"*2\r\n$1\r\n\xfe\r\n$1\r\nb\r\n",
+        )
+        .await;
+        roundtrip(&[b"SET", b"k\xff", b"v"], b"+OK\r\n").await;
+        roundtrip(&[b"GET", b"k\xff"], b"$1\r\nv\r\n").await;
+        roundtrip(&[b"DEL", b"\xff", b"k\xff", b"k"], b":2\r\n").await;
+        roundtrip(&[b"TYPE", b"\xff"], b"+none\r\n").await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_list_integer_args() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+        let error = b"-ERR value is not an integer or out of range\r\n";
+
+        roundtrip(stream, &["RPUSH", "l", "a", "b"], b":2\r\n").await;
+        roundtrip(stream, &["LINDEX", "l", "abc"], error).await;
+        roundtrip(stream, &["LSET", "l", "abc", "v"], error).await;
+        roundtrip(stream, &["LREM", "l", "1.5", "a"], error).await;
+        roundtrip(stream, &["LTRIM", "l", "0", "abc"], error).await;
+        roundtrip(
+            stream,
+            &["LINSERT", "l", "ABOUT", "a", "v"],
+            b"-ERR syntax error\r\n",
+        )
+        .await;
+        roundtrip(stream, &["LINDEX", "l", "-1"], b"$1\r\nb\r\n").await;
+
+        let error = b"-ERR value is out of range, must be positive\r\n";
+        roundtrip(stream, &["RPOP", "l", "-1"], error).await;
+        roundtrip(stream, &["LPOP", "l", "abc"], error).await;
+        roundtrip(stream, &["RPOP", "l", "1"], b"*1\r\n$1\r\nb\r\n").await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_zpop_count() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+        let error = b"-ERR value is out of range, must be positive\r\n";
+
+        roundtrip(stream, &["ZINCRBY", "z", "1", "a"], b"$1\r\n1\r\n").await;
+        roundtrip(stream, &["ZINCRBY", "z", "2", "b"], b"$1\r\n2\r\n").await;
+        roundtrip(stream, &["ZPOPMIN", "z", "-1"], error).await;
+        roundtrip(stream, &["ZPOPMAX", "z", "abc"], error).await;
+        roundtrip(
+            stream,
+            &["ZPOPMAX", "z", "1"],
+            b"*2\r\n$1\r\nb\r\n$1\r\n2\r\n",
+        )
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_string_range() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+
+        // Padded with zero bytes before the offset.
+        roundtrip(stream, &["SETRANGE", "s", "3", "ab"], b":5\r\n").await;
+        roundtrip(stream, &["GET", "s"], b"$5\r\n\0\0\0ab\r\n").await;
+        roundtrip(stream, &["SETRANGE", "s", "1", "x"], b":5\r\n").await;
+        roundtrip(stream, &["SETRANGE", "s", "6", "y"], b":7\r\n").await;
+        roundtrip(stream, &["GET", "s"], b"$7\r\n\0x\0ab\0y\r\n").await;
+        roundtrip(stream, &["STRLEN", "s"], b":7\r\n").await;
+        roundtrip(stream, &["GETRANGE", "s", "-4", "-2"], b"$3\r\nab\0\r\n").await;
+        roundtrip(stream, &["APPEND", "s", "z"], b":8\r\n").await;
+
+        // Nothing written creates no key.
+        roundtrip(stream, &["SETRANGE", "e", "10", ""], b":0\r\n").await;
+        roundtrip(stream, &["TYPE", "e"], b"+none\r\n").await;
+        roundtrip(
+            stream,
+            &["SETRANGE", "s", "-1", "v"],
+            b"-ERR offset is out of range\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["SETRANGE", "s", "536870912", "v"],
+            b"-ERR string exceeds maximum allowed size (proto-max-bulk-len)\r\n",
+        )
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_set_options() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+        let syntax = b"-ERR syntax error\r\n";
+
+        roundtrip(stream, &["SET", "k", "v", "NX"], b"+OK\r\n").await;
+        roundtrip(stream, &["SET", "k", "w", "NX"], b"$-1\r\n").await;
+        roundtrip(stream, &["SET", "k", "w", "XX"], b"+OK\r\n").await;
+        roundtrip(stream, &["SET", "m", "v", "XX"], b"$-1\r\n").await;
+        roundtrip(stream, &["GET", "m"], b"$-1\r\n").await;
+
+        // GET replies the old value, whether set or not.
+        roundtrip(stream, &["SET", "k", "x", "GET"], b"$1\r\nw\r\n").await;
+        roundtrip(stream, &["SET", "k", "y", "NX", "GET"], b"$1\r\nx\r\n").await;
+        roundtrip(stream, &["GET", "k"], b"$1\r\nx\r\n").await;
+        roundtrip(stream, &["SET", "n", "v", "GET"], b"$-1\r\n").await;
+        roundtrip(stream, &["GET", "n"], b"$1\r\nv\r\n").await;
+        roundtrip(stream, &["RPUSH", "l", "a"], b":1\r\n").await;
+        roundtrip(
+            stream,
+            &["SET", "l", "v", "GET"],
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+        )
+        .await;
+
+        roundtrip(stream, &["SET", "k", "v", "NX", "XX"], syntax).await;
+        roundtrip(stream, &["SET", "k", "v", "EX", "10", "PX", "10"], syntax).await;
+        roundtrip(stream, &["SET", "k", "v", "EX", "10", "KEEPTTL"], syntax).await;
+        roundtrip(stream, &["SET", "k", "v", "EX"], syntax).await;
+        roundtrip(stream, &["SET", "k", "v", "BAD"], syntax).await;
+        roundtrip(
+            stream,
+            &["SET", "k", "v", "EX", "0"],
+            b"-ERR invalid expire time in 'set' command\r\n",
+        )
+        .await;
+
+        // Unix times passed expire at once.
+        roundtrip(stream, &["SET", "p", "v", "EXAT", "1"], b"+OK\r\n").await;
+        roundtrip(stream, &["GET", "p"], b"$-1\r\n").await;
+        roundtrip(stream, &["SET", "p", "v", "PXAT", "1000"], b"+OK\r\n").await;
+        roundtrip(stream, &["GET", "p"], b"$-1\r\n").await;
+
+        // KEEPTTL keeps the expiration, otherwise it is dropped.
+        roundtrip(stream, &["SET", "t", "a", "PX", "100"], b"+OK\r\n").await;
+        roundtrip(stream, &["SET", "t", "b", "KEEPTTL"], b"+OK\r\n").await;
+        roundtrip(stream, &["SET", "u", "a", "PX", "100"], b"+OK\r\n").await;
+        roundtrip(stream, &["SET", "u", "b"], b"+OK\r\n").await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        roundtrip(stream, &["GET", "t"], b"$-1\r\n").await;
+        roundtrip(stream, &["GET", "u"], b"$1\r\nb\r\n").await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_get_set_variants() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+
+        roundtrip(stream, &["SETNX", "k", "a"], b":1\r\n").await;
+        roundtrip(stream, &["SETNX", "k", "b"], b":0\r\n").await;
+        roundtrip(stream, &["GETSET", "k", "c"], b"$1\r\na\r\n").await;
+        roundtrip(stream, &["GETSET", "n", "c"], b"$-1\r\n").await;
+
+        roundtrip(stream, &["GETDEL", "k"], b"$1\r\nc\r\n").await;
+        roundtrip(stream, &["GETDEL", "k"], b"$-1\r\n").await;
+        roundtrip(stream, &["TYPE", "k"], b"+none\r\n").await;
+        roundtrip(stream, &["RPUSH", "l", "a"], b":1\r\n").await;
+        roundtrip(
+            stream,
+            &["GETDEL", "l"],
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+        )
+        .await;
+
+        roundtrip(
+            stream,
+            &["SETEX", "e", "0", "v"],
+            b"-ERR invalid expire time in 'setex' command\r\n",
+        )
+        .await;
+        roundtrip(stream, &["SETEX", "e", "100", "v"], b"+OK\r\n").await;
+        roundtrip(stream, &["PSETEX", "p", "100", "v"], b"+OK\r\n").await;
+
+        // GETEX without option keeps the expiration, PERSIST drops it.
+        roundtrip(stream, &["GETEX", "p"], b"$1\r\nv\r\n").await;
+        roundtrip(stream, &["GETEX", "e", "PX", "100"], b"$1\r\nv\r\n").await;
+        roundtrip(stream, &["SET", "q", "v", "PX", "100"], b"+OK\r\n").await;
+        roundtrip(stream, &["GETEX", "q", "PERSIST"], b"$1\r\nv\r\n").await;
+        roundtrip(
+            stream,
+            &["GETEX", "q", "PERSIST", "EX", "1"],
+            b"-ERR syntax error\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["GETEX", "q", "EX", "-1"],
+            b"-ERR invalid expire time in 'getex' command\r\n",
+        )
+        .await;
+        roundtrip(stream, &["GETEX", "missing", "EX", "1"], b"$-1\r\n").await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        roundtrip(stream, &["GET", "p"], b"$-1\r\n").await;
+        roundtrip(stream, &["GET", "e"], b"$-1\r\n").await;
+        roundtrip(stream, &["GET", "q"], b"$1\r\nv\r\n").await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lmove() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let mut blocked = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+
+        roundtrip(stream, &["RPUSH", "s", "a", "b", "c"], b":3\r\n").await;
+        roundtrip(
+            stream,
+            &["LMOVE", "s", "d", "LEFT", "RIGHT"],
+            b"$1\r\na\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["LMOVE", "s", "d", "RIGHT", "LEFT"],
+            b"$1\r\nc\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["LRANGE", "d", "0", "-1"],
+            b"*2\r\n$1\r\nc\r\n$1\r\na\r\n",
+        )
+        .await;
+        // Moving within the same list rotates it.
+        roundtrip(
+            stream,
+            &["LMOVE", "d", "d", "RIGHT", "LEFT"],
+            b"$1\r\na\r\n",
+        )
+        .await;
+        roundtrip(stream, &["LINDEX", "d", "0"], b"$1\r\na\r\n").await;
+        roundtrip(stream, &["RPOPLPUSH", "s", "d"], b"$1\r\nb\r\n").await;
+        roundtrip(stream, &["LMOVE", "s", "d", "LEFT", "LEFT"], b"$-1\r\n").await;
+        roundtrip(stream, &["LLEN", "d"], b":3\r\n").await;
+        roundtrip(
+            stream,
+            &["LMOVE", "d", "s", "UP", "LEFT"],
+            b"-ERR syntax error\r\n",
+        )
+        .await;
+
+        // Nothing popped if the destination is not a list.
+        roundtrip(stream, &["SET", "str", "v"], b"+OK\r\n").await;
+        roundtrip(
+            stream,
+            &["LMOVE", "d", "str", "LEFT", "LEFT"],
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+        )
+        .await;
+        roundtrip(stream, &["LLEN", "d"], b":3\r\n").await;
+
+        roundtrip(
+            stream,
+            &["BLMOVE", "e", "d", "LEFT", "LEFT", "-1"],
+            b"-ERR timeout is not a float or out of range\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["BLMOVE", "e", "d", "LEFT", "LEFT", "0.1"],
+            b"$-1\r\n",
+        )
+        .await;
+        // Woken up by a push to the source.
+        tokio::join!(
+            roundtrip(
+                &mut blocked,
+                &["BLMOVE", "e", "d", "LEFT", "LEFT", "0"],
+                b"$1\r\nv\r\n"
+            ),
+            async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                roundtrip(stream, &["RPUSH", "e", "v"], b":1\r\n").await;
+            }
+        );
+        roundtrip(stream, &["LLEN", "e"], b":0\r\n").await;
+        roundtrip(stream, &["LINDEX", "d", "0"], b"$1\r\nv\r\n").await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_lmpop() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let mut blocked = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+
+        // Popped from the first list not empty.
+        roundtrip(stream, &["RPUSH", "b", "1", "2", "3"], b":3\r\n").await;
+        roundtrip(
+            stream,
+            &["LMPOP", "2", "a", "b", "LEFT"],
+            b"*2\r\n$1\r\nb\r\n*1\r\n$1\r\n1\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["LMPOP", "2", "a", "b", "RIGHT", "COUNT", "5"],
+            b"*2\r\n$1\r\nb\r\n*2\r\n$1\r\n3\r\n$1\r\n2\r\n",
+        )
+        .await;
+        roundtrip(stream, &["TYPE", "b"], b"+none\r\n").await;
+        roundtrip(stream, &["LMPOP", "2", "a", "b", "LEFT"], b"*-1\r\n").await;
+
+        let syntax = b"-ERR syntax error\r\n";
+        roundtrip(stream, &["LMPOP", "3", "a", "b", "LEFT"], syntax).await;
+        roundtrip(stream, &["LMPOP", "1", "a", "UP"], syntax).await;
+        roundtrip(stream, &["LMPOP", "1", "a", "LEFT", "COUNT"], syntax).await;
+        roundtrip(
+            stream,
+            &["LMPOP", "0", "a", "LEFT"],
+            b"-ERR numkeys should be greater than 0\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["LMPOP", "1", "a", "LEFT", "COUNT", "0"],
+            b"-ERR count should be greater than 0\r\n",
+        )
+        .await;
+
+        roundtrip(stream, &["BLMPOP", "0.1", "1", "a", "LEFT"], b"*-1\r\n").await;
+        roundtrip(
+            stream,
+            &["BLMPOP", "abc", "1", "a", "LEFT"],
+            b"-ERR timeout is not a float or out of range\r\n",
+        )
+        .await;
+        // Woken up by a push to any of the lists, taking up to count elements.
+        tokio::join!(
+            roundtrip(
+                &mut blocked,
+                &["BLMPOP", "0", "2", "a", "c", "LEFT", "COUNT", "2"],
+                b"*2\r\n$1\r\nc\r\n*2\r\n$1\r\nx\r\n$1\r\ny\r\n"
+            ),
+            async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                roundtrip(stream, &["RPUSH", "c", "x", "y", "z"], b":3\r\n").await;
+            }
+        );
+        roundtrip(stream, &["LRANGE", "c", "0", "-1"], b"*1\r\n$1\r\nz\r\n").await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_xinfo() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+        let bulk = |x: &str| format!("${}\r\n{x}\r\n", x.len());
+        let field = |name: &str, value: String| format!("{}{value}", bulk(name));
+        let entry =
+            |id: &str, value: &str| format!("*2\r\n{}*2\r\n{}{}", bulk(id), bulk("f"), bulk(value));
+
+        roundtrip(stream, &["XADD", "s", "1-1", "f", "v"], b"$3\r\n1-1\r\n").await;
+        roundtrip(stream, &["XADD", "s", "2-1", "f", "w"], b"$3\r\n2-1\r\n").await;
+        roundtrip(stream, &["XGROUP", "CREATE", "s", "g", "0"], b"+OK\r\n").await;
+        let expected = format!("*1\r\n*2\r\n{}*1\r\n{}", bulk("s"), entry("1-1", "v"));
+        roundtrip(
+            stream,
+            &[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "c",
+                "COUNT",
+                "1",
+                "STREAMS",
+                "s",
+                ">",
+            ],
+            expected.as_bytes(),
+        )
+        .await;
+
+        let expected = [
+            "*18\r\n".to_string(),
+            field("length", ":2\r\n".into()),
+            field("last-generated-id", bulk("2-1")),
+            field("max-deleted-entry-id", bulk("0-0")),
+            field("entries-added", ":2\r\n".into()),
+            field("max-len", ":-1\r\n".into()),
+            field("entries-trimmed", ":0\r\n".into()),
+            field("groups", ":1\r\n".into()),
+            field("first-entry", entry("1-1", "v")),
+            field("last-entry", entry("2-1", "w")),
+        ]
+        .concat();
+        roundtrip(stream, &["XINFO", "STREAM", "s"], expected.as_bytes()).await;
+
+        let expected = [
+            "*1\r\n*12\r\n".to_string(),
+            field("name", bulk("g")),
+            field("consumers", ":1\r\n".into()),
+            field("pending", ":1\r\n".into()),
+            field("last-delivered-id", bulk("1-1")),
+            field("entries-read", ":1\r\n".into()),
+            field("lag", ":1\r\n".into()),
+        ]
+        .concat();
+        roundtrip(stream, &["XINFO", "GROUPS", "s"], expected.as_bytes()).await;
+
+        roundtrip(stream, &["XINFO", "STREAM", "e"], b"-ERR no such key\r\n").await;
+        roundtrip(stream, &["SET", "str", "v"], b"+OK\r\n").await;
+        roundtrip(
+            stream,
+            &["XINFO", "GROUPS", "str"],
+            b"-WRONGTYPE Operation against a key holding the wrong kind of value\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["XINFO", "FOO", "s"],
+            b"-ERR unknown subcommand 'FOO'\r\n",
+        )
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_idle_timeout() {
+        use tokio::io::AsyncReadExt;
+
+        let handle = ServerBuilder::new()
+            .port(0)
+            .timeout(1)
+            .start()
+            .await
+            .unwrap();
+        let mut idle = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let mut subscriber = TcpStream::connect(handle.local_addr()).await.unwrap();
+        roundtrip(
+            &mut subscriber,
+            &["SUBSCRIBE", "c"],
+            b"*3\r\n$9\r\nsubscribe\r\n$1\r\nc\r\n:1\r\n",
+        )
+        .await;
+        let mut busy = TcpStream::connect(handle.local_addr()).await.unwrap();
+        for _ in 0..3 {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            roundtrip(&mut busy, &["PING"], b"+PONG\r\n").await;
+        }
+
+        // Closed after idle for a second.
+        let mut buf = [0u8; 16];
+        assert_eq!(idle.read(&mut buf).await.unwrap(), 0);
+
+        // Subscribers are kept.
+        handle.execute(["PUBLISH", "c", "m"]).await.unwrap();
+        roundtrip(
+            &mut subscriber,
+            &[],
+            b"*3\r\n$7\r\nmessage\r\n$1\r\nc\r\n$1\r\nm\r\n",
+        )
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_maxclients() {
+        use tokio::io::AsyncReadExt;
+
+        let handle = ServerBuilder::new()
+            .port(0)
+            .maxclients(2)
+            .start()
+            .await
+            .unwrap();
+        let mut a = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let mut b = TcpStream::connect(handle.local_addr()).await.unwrap();
+        roundtrip(&mut a, &["PING"], b"+PONG\r\n").await;
+        roundtrip(&mut b, &["PING"], b"+PONG\r\n").await;
+
+        let mut c = TcpStream::connect(handle.local_addr()).await.unwrap();
+        roundtrip(&mut c, &[], b"-ERR max number of clients reached\r\n").await;
+        let mut buf = [0u8; 16];
+        assert_eq!(c.read(&mut buf).await.unwrap(), 0);
+        let Value::BulkString(v) = handle.execute(["INFO", "stats"]).await.unwrap() else {
+            panic!("INFO replies bulk string");
+        };
+        let stats = String::from_utf8_lossy(v.value().unwrap()).to_string();
+        assert!(stats.contains("rejected_connections:1\n"));
+
+        // Accepted again once a client left.
+        drop(a);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut c = TcpStream::connect(handle.local_addr()).await.unwrap();
+        roundtrip(&mut c, &["PING"], b"+PONG\r\n").await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_blocked_order() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let execute = |delay: u64, cmd: Vec<&'static str>| {
+            let handle = &handle;
+            async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                handle.execute(cmd).await.unwrap()
+            }
+        };
+        // Element popped by BLPOP.
+        let popped = |value: Value| match value {
+            Value::Array(arr) => match arr.value().unwrap().as_slice() {
+                [_, Value::BulkString(v)] => {
+                    String::from_utf8_lossy(v.value().unwrap()).to_string()
+                }
+                [_, Value::SimpleString(v)] => v.value().to_string(),
+                v => panic!("unexpected reply {v:?}"),
+            },
+            v => panic!("unexpected reply {v:?}"),
+        };
+
+        // Served in the order blocked, all elements pushed at once are handed out.
+        let (first, second, third, ..) = tokio::join!(
+            execute(0, vec!["BLPOP", "l", "0"]),
+            execute(50, vec!["BLPOP", "l", "0"]),
+            execute(100, vec!["BLPOP", "l", "0"]),
+            execute(200, vec!["RPUSH", "l", "a", "b"]),
+            execute(300, vec!["RPUSH", "l", "c", "d"]),
+        );
+        assert_eq!(
+            (popped(first), popped(second), popped(third)),
+            ("a".to_string(), "b".to_string(), "c".to_string())
+        );
+        assert_eq!(
+            handle.execute(["LLEN", "l"]).await.unwrap(),
+            Value::Integer(Integer::new(1))
+        );
+
+        // Every XREAD waiting gets the entry.
+        let xread = vec!["XREAD", "BLOCK", "0", "STREAMS", "s", "$"];
+        let (first, second, ..) = tokio::join!(
+            execute(0, xread.clone()),
+            execute(50, xread),
+            execute(100, vec!["XADD", "s", "*", "k", "v"]),
+        );
+        assert_eq!(first, second);
+        assert_ne!(first, Value::Array(Array::null()));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_blpop_keys() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        // Key of the list popped from.
+        let popped_key = |value: Value| match value {
+            Value::Array(arr) => arr.value().unwrap()[0].clone(),
+            v => panic!("unexpected reply {v:?}"),
+        };
+        let key = |key: &str| Value::BulkString(BulkString::new(key));
+
+        // Lists are checked in order.
+        handle.execute(["RPUSH", "b", "1"]).await.unwrap();
+        handle.execute(["RPUSH", "c", "2"]).await.unwrap();
+        let popped = handle.execute(["BLPOP", "a", "b", "c", "1"]).await.unwrap();
+        assert_eq!(popped_key(popped), key("b"));
+        let popped = handle.execute(["BRPOP", "a", "b", "c", "1"]).await.unwrap();
+        assert_eq!(popped_key(popped), key("c"));
+
+        // Any of the lists wakes the client up.
+        let (popped, _) = tokio::join!(handle.execute(["BLPOP", "a", "b", "c", "0"]), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            handle.execute(["RPUSH", "c", "3"]).await.unwrap()
+        });
+        assert_eq!(popped_key(popped.unwrap()), key("c"));
+        assert_eq!(
+            handle.execute(["BLPOP", "a", "b", "0.1"]).await.unwrap(),
+            Value::Array(Array::null())
+        );
+
+        handle.execute(["SET", "s", "v"]).await.unwrap();
+        assert_eq!(
+            handle.execute(["BLPOP", "a", "s", "1"]).await.unwrap(),
+            Value::SimpleError(SimpleError::with_prefix(
+                "WRONGTYPE",
+                "Operation against a key holding the wrong kind of value"
+            ))
+        );
+        assert_eq!(
+            handle.execute(["BLPOP", "a", "-1"]).await.unwrap(),
+            Value::SimpleError(SimpleError::with_prefix(
+                "ERR",
+                "timeout is not a float or out of range"
+            ))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_blocked_disconnect() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let blocked_clients = || async {
+            let Value::BulkString(v) = handle.execute(["INFO", "clients"]).await.unwrap() else {
+                panic!("INFO replies bulk string");
+            };
+            let info = String::from_utf8_lossy(v.value().unwrap()).to_string();
+            info.lines()
+                .find_map(|x| x.strip_prefix("blocked_clients:"))
+                .unwrap()
+                .parse::<usize>()
+                .unwrap()
+        };
+
+        for cmd in [
+            vec!["BLPOP", "l", "0"],
+            vec!["BZPOPMIN", "z", "0"],
+            vec!["XREAD", "BLOCK", "0", "STREAMS", "s", "$"],
+        ] {
+            let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+            roundtrip(&mut stream, &["PING"], b"+PONG\r\n").await;
+            let cmd = cmd
+                .into_iter()
+                .map(|x| Value::BulkString(BulkString::new(x)))
+                .collect::<Array>();
+            let data = serde_redis::to_vec(&cmd).unwrap();
+            tokio::io::AsyncWriteExt::write_all(&mut stream, &data)
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert_eq!(blocked_clients().await, 1);
+
+            // The command ends with the connection, nothing is fed to it later.
+            drop(stream);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert_eq!(blocked_clients().await, 0);
+        }
+        handle.execute(["RPUSH", "l", "a"]).await.unwrap();
+        handle.execute(["ZADD", "z", "1", "a"]).await.unwrap();
+        handle.execute(["XADD", "s", "*", "k", "v"]).await.unwrap();
+        assert_eq!(
+            handle.execute(["LLEN", "l"]).await.unwrap(),
+            Value::Integer(Integer::new(1))
+        );
+        assert_ne!(
+            handle.execute(["ZSCORE", "z", "a"]).await.unwrap(),
+            Value::BulkString(BulkString::null())
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_client_output_buffer_limit() {
+        let handle = ServerBuilder::new()
+            .port(0)
+            .client_output_buffer_limit(1024 * 1024)
+            .start()
+            .await
+            .unwrap();
+        let mut slow = TcpStream::connect(handle.local_addr()).await.unwrap();
+        roundtrip(
+            &mut slow,
+            &["SUBSCRIBE", "c"],
+            b"*3\r\n$9\r\nsubscribe\r\n$1\r\nc\r\n:1\r\n",
+        )
+        .await;
+
+        // Messages pile up for the subscriber not reading, beyond what socket buffers
+        // take.
+        let value = "x".repeat(64 * 1024);
+        let mut receivers = 1;
+        for _ in 0..1024 {
+            let Value::Integer(n) = handle.execute(["PUBLISH", "c", &value]).await.unwrap() else {
+                panic!("PUBLISH replies integer");
+            };
+            receivers = n.value();
+            if receivers == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(receivers, 0);
+
+        // Others are kept.
+        let mut fast = TcpStream::connect(handle.local_addr()).await.unwrap();
+        roundtrip(&mut fast, &["PING"], b"+PONG\r\n").await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_client_list() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+
+        roundtrip(stream, &["CLIENT", "GETNAME"], b"$-1\r\n").await;
+        roundtrip(
+            stream,
+            &["CLIENT", "SETNAME", "a b"],
+            b"-ERR Client names cannot contain spaces, newlines or special characters.\r\n",
+        )
+        .await;
+        roundtrip(stream, &["CLIENT", "SETNAME", "worker"], b"+OK\r\n").await;
+        roundtrip(stream, &["CLIENT", "GETNAME"], b"$6\r\nworker\r\n").await;
+        roundtrip(stream, &["CLIENT", "NO-EVICT", "on"], b"+OK\r\n").await;
+        roundtrip(stream, &["MULTI"], b"+OK\r\n").await;
+        roundtrip(stream, &["GET", "a"], b"+QUEUED\r\n").await;
+        let Value::BulkString(list) = handle.execute(["CLIENT", "LIST"]).await.unwrap() else {
+            panic!("CLIENT LIST replies bulk string");
+        };
+        let list = String::from_utf8_lossy(list.value().unwrap()).to_string();
+        let line = list
+            .lines()
+            .find(|x| x.contains("name=worker"))
+            .expect("client listed");
+        assert!(line.contains(&format!("addr={}", stream.local_addr().unwrap())));
+        assert!(line.contains(" flags=xe "));
+        assert!(line.contains(" multi=1 "));
+        assert!(line.ends_with(" cmd=get"));
+
+        let Value::BulkString(list) = handle
+            .execute(["CLIENT", "LIST", "TYPE", "pubsub"])
+            .await
+            .unwrap()
+        else {
+            panic!("CLIENT LIST replies bulk string");
+        };
+        assert_eq!(list.value().unwrap(), b"");
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_client_reply() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+
+        // Nothing replied, wait till each command is read so commands are read in
+        // separate messages.
+        let wait_command = |cmd: &'static str| {
+            let handle = &handle;
+            async move {
+                loop {
+                    let Value::BulkString(list) = handle.execute(["CLIENT", "LIST"]).await.unwrap()
+                    else {
+                        panic!("CLIENT LIST replies bulk string");
+                    };
+                    let list = String::from_utf8_lossy(list.value().unwrap()).to_string();
+                    if list.contains(&format!(" cmd={cmd}\n")) {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+            }
+        };
+        for (cmd, name) in [
+            (&["CLIENT", "REPLY", "OFF"][..], "client|reply"),
+            (&["SET", "a", "1"], "set"),
+            (&["CLIENT", "REPLY", "SKIP"], "client|reply"),
+            (&["GET", "a"], "get"),
+        ] {
+            roundtrip(stream, cmd, b"").await;
+            wait_command(name).await;
+        }
+        roundtrip(stream, &["CLIENT", "REPLY", "ON"], b"+OK\r\n").await;
+        roundtrip(stream, &["GET", "a"], b"$1\r\n1\r\n").await;
+
+        // Only the next command is skipped.
+        for (cmd, name) in [
+            (&["CLIENT", "REPLY", "SKIP"][..], "client|reply"),
+            (&["GET", "a"], "get"),
+        ] {
+            roundtrip(stream, cmd, b"").await;
+            wait_command(name).await;
+        }
+        roundtrip(stream, &["PING"], b"+PONG\r\n").await;
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_monitor() {
+        use tokio::io::AsyncReadExt;
+
+        async fn read_line(stream: &mut TcpStream) -> String {
+            let mut line = vec![];
+            while !line.ends_with(b"\r\n") {
+                line.push(stream.read_u8().await.unwrap());
+            }
+            String::from_utf8(line).unwrap()
+        }
+
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut monitor = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let addr = stream.local_addr().unwrap();
+        roundtrip(&mut monitor, &["MONITOR"], b"+OK\r\n").await;
+
+        roundtrip(&mut stream, &["SET", "foo", "a b"], b"+OK\r\n").await;
+        let line = read_line(&mut monitor).await;
+        assert!(line.starts_with('+'));
+        assert!(line.ends_with(&format!(" [0 {addr}] \"SET\" \"foo\" \"a b\"\r\n")));
+
+        // Admin commands are not fed.
+        roundtrip(
+            &mut stream,
+            &["CONFIG", "GET", "maxmemory"],
+            b"*2\r\n$9\r\nmaxmemory\r\n$1\r\n0\r\n",
+        )
+        .await;
+        roundtrip(&mut stream, &["PING"], b"+PONG\r\n").await;
+        let line = read_line(&mut monitor).await;
+        assert!(line.ends_with(&format!(" [0 {addr}] \"PING\"\r\n")));
+
+        roundtrip(&mut stream, &["MULTI"], b"+OK\r\n").await;
+        roundtrip(
+            &mut stream,
+            &["MONITOR"],
+            b"-ERR Command not allowed inside a transaction\r\n",
+        )
+        .await;
+        roundtrip(&mut stream, &["DISCARD"], b"+OK\r\n").await;
+
+        // Fed as push data in RESP3.
+        let mut monitor = TcpStream::connect(handle.local_addr()).await.unwrap();
+        roundtrip(&mut monitor, &["HELLO", "3"], b"%").await;
+        while read_line(&mut monitor).await != "modules\r\n" {}
+        roundtrip(&mut monitor, &["MONITOR"], b"*0\r\n+OK\r\n").await;
+        roundtrip(&mut stream, &["GET", "foo"], b"$3\r\na b\r\n").await;
+        assert_eq!(read_line(&mut monitor).await, ">2\r\n");
+        assert_eq!(read_line(&mut monitor).await, "$7\r\n");
+        assert_eq!(read_line(&mut monitor).await, "monitor\r\n");
+        let line = read_line(&mut monitor).await;
+        assert!(line.starts_with('+'));
+        assert!(line.ends_with(&format!(" [0 {addr}] \"GET\" \"foo\"\r\n")));
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_client_kill() {
+        use tokio::io::AsyncReadExt;
+
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let mut other = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let addr = other.local_addr().unwrap().to_string();
+        roundtrip(&mut other, &["PING"], b"+PONG\r\n").await;
+
+        roundtrip(
+            &mut stream,
+            &["CLIENT", "KILL", "127.0.0.1:1"],
+            b"-ERR No such client\r\n",
+        )
+        .await;
+        roundtrip(
+            &mut stream,
+            &["CLIENT", "KILL", "TYPE", "pubsub"],
+            b":0\r\n",
+        )
+        .await;
+        roundtrip(&mut stream, &["CLIENT", "KILL", "ADDR", &addr], b":1\r\n").await;
+        // Closed by server.
+        let mut buf = [0; 16];
+        assert_eq!(other.read(&mut buf).await.unwrap(), 0);
+
+        // Skip the client itself by default.
+        roundtrip(
+            &mut stream,
+            &["CLIENT", "KILL", "USER", "default"],
+            b":0\r\n",
+        )
+        .await;
+        roundtrip(
+            &mut stream,
+            &["CLIENT", "KILL", "USER", "default", "SKIPME", "no"],
+            b":1\r\n",
+        )
+        .await;
+        assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_acl() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let stream = &mut stream;
+
+        roundtrip(stream, &["ACL", "WHOAMI"], b"$7\r\ndefault\r\n").await;
+        roundtrip(
+            stream,
+            &[
+                "ACL",
+                "SETUSER",
+                "alice",
+                "on",
+                ">pw",
+                "~cache:*",
+                "&news",
+                "+get",
+                "+set",
+                "+publish",
+                "+@transaction",
+            ],
+            b"+OK\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["ACL", "SETUSER", "alice", "+nosuch"],
+            b"-ERR Error in ACL SETUSER modifier '+nosuch': Unknown command or category name in ACL\r\n",
+        )
+        .await;
+        roundtrip(stream, &["AUTH", "alice", "pw"], b"+OK\r\n").await;
+        roundtrip(
+            stream,
+            &["ACL", "WHOAMI"],
+            b"-NOPERM User alice has no permissions to run the 'acl' command\r\n",
+        )
+        .await;
+        roundtrip(stream, &["SET", "cache:1", "v"], b"+OK\r\n").await;
+        roundtrip(
+            stream,
+            &["GET", "other"],
+            b"-NOPERM No permissions to access a key\r\n",
+        )
+        .await;
+        roundtrip(stream, &["PUBLISH", "news", "hi"], b":0\r\n").await;
+        roundtrip(
+            stream,
+            &["PUBLISH", "sports", "hi"],
+            b"-NOPERM No permissions to access a channel\r\n",
+        )
+        .await;
+
+        // Rejected commands abort the transaction.
+        roundtrip(stream, &["MULTI"], b"+OK\r\n").await;
+        roundtrip(
+            stream,
+            &["GET", "other"],
+            b"-NOPERM No permissions to access a key\r\n",
+        )
+        .await;
+        roundtrip(
+            stream,
+            &["EXEC"],
+            b"-EXECABORT Transaction discarded because of previous errors.\r\n",
+        )
+        .await;
+
+        // Disabled users can not authenticate.
+        handle
+            .execute(["ACL", "SETUSER", "alice", "off"])
+            .await
+            .unwrap();
+        roundtrip(
+            stream,
+            &["AUTH", "alice", "pw"],
+            b"-WRONGPASS invalid username-password pair or user is disabled.\r\n",
+        )
+        .await;
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_command_timeout() {
+        let handle = ServerBuilder::new()
+            .port(0)
+            .command_timeout(1)
+            .start()
+            .await
+            .unwrap();
+        let timeout = Value::SimpleError(SimpleError::with_prefix(
+            "TIMEOUT",
+            "command exceeded the max execution time of 1 ms",
+        ));
+        let values = (0..1_000_000).map(|x| (x % 10).to_string().into_bytes());
+        handle
+            .state
+            .storage()
+            .insert_list("l".into(), values.collect(), true, false)
+            .unwrap();
+
+        assert_eq!(
+            handle.execute(["LRANGE", "l", "0", "-1"]).await.unwrap(),
+            timeout
+        );
+        // Nothing is removed if aborted.
+        assert_eq!(
+            handle.execute(["LREM", "l", "0", "3"]).await.unwrap(),
+            timeout
+        );
+        assert_eq!(
+            handle.execute(["LLEN", "l"]).await.unwrap(),
+            Value::Integer(Integer::new(1_000_000))
+        );
+        assert_eq!(handle.execute(["LPOS", "l", "x"]).await.unwrap(), timeout);
+        // Short walks finish in time.
+        assert_eq!(
+            handle.execute(["LRANGE", "l", "0", "1"]).await.unwrap(),
+            Value::Array(Array::with_values(vec![
+                Value::BulkString(BulkString::new("0")),
+                Value::BulkString(BulkString::new("1")),
+            ]))
+        );
+        assert_eq!(
+            handle.execute(["LREM", "l", "1", "1"]).await.unwrap(),
+            Value::Integer(Integer::new(1))
+        );
+
+        // No part of the aborted reply is sent before the error.
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        roundtrip(
+            &mut stream,
+            &["LRANGE", "l", "0", "-1"],
+            b"-TIMEOUT command exceeded the max execution time of 1 ms\r\n",
+        )
+        .await;
+        roundtrip(&mut stream, &["PING"], b"+PONG\r\n").await;
+        assert_eq!(handle.state.load().info().timed_out_commands, 4);
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_unblock() {
+        let handle = ServerBuilder::new().port(0).start().await.unwrap();
+        let unblock = |reason: &'static str| {
+            let handle = &handle;
+            let id = handle.next_id.load(Ordering::Relaxed).to_string();
+            async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                let mut cmd = vec!["CLIENT", "UNBLOCK", id.as_str()];
+                cmd.extend(Some(reason).filter(|x| !x.is_empty()));
+                handle.execute(cmd).await.unwrap()
+            }
+        };
+
+        let (blocked, unblocked) =
+            tokio::join!(handle.execute(["BLPOP", "list", "0"]), unblock(""));
+        assert_eq!(blocked.unwrap(), Value::Array(Array::null()));
+        assert_eq!(unblocked, Value::Integer(Integer::new(1)));
+
+        let (blocked, unblocked) = tokio::join!(
+            handle.execute(["XREAD", "BLOCK", "0", "STREAMS", "s", "$"]),
+            unblock("ERROR")
+        );
+        assert_eq!(
+            blocked.unwrap(),
+            Value::SimpleError(SimpleError::with_prefix(
+                "UNBLOCKED",
+                "client unblocked via CLIENT UNBLOCK"
+            ))
+        );
+        assert_eq!(unblocked, Value::Integer(Integer::new(1)));
+        assert_eq!(
+            handle.execute(["CLIENT", "UNBLOCK", "0"]).await.unwrap(),
+            Value::Integer(Integer::new(0))
+        );
+
+        // Blocked commands end when shutting down.
+        let (blocked, _) = tokio::join!(handle.execute(["BZPOPMIN", "zset", "0"]), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            handle.state.lifecycle().shut_down();
+        });
+        assert_eq!(
+            blocked.unwrap(),
+            Value::SimpleError(SimpleError::with_prefix(
+                "UNBLOCKED",
+                "server is shutting down"
+            ))
+        );
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_flush() {
+        let hook = RecordHook::default();
+        let handle = ServerBuilder::new()
+            .port(0)
+            .storage_hook(hook.clone())
+            .start()
+            .await
+            .unwrap();
+        let ok = Value::SimpleString(SimpleString::new("OK"));
+        for cmd in [
+            vec!["SET", "k", "v"],
+            vec!["RPUSH", "list", "a"],
+            vec!["GEOADD", "geo", "13.36", "38.11", "p"],
+            vec!["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"],
+        ] {
+            handle.execute(cmd).await.unwrap();
+        }
+        hook.0.lock().unwrap().clear();
+
+        let (blocked, flushed) = tokio::join!(
+            handle.execute([
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "c",
+                "BLOCK",
+                "0",
+                "STREAMS",
+                "s",
+                ">"
+            ]),
+            async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                handle.execute(["FLUSHALL", "ASYNC"]).await.unwrap()
+            }
+        );
+        assert_eq!(flushed, ok);
+        assert_eq!(
+            blocked.unwrap(),
+            Value::SimpleError(SimpleError::with_prefix(
+                "UNBLOCKED",
+                "the stream key no longer exists"
+            ))
+        );
+        // XREADGROUP also writes the stream to create the consumer.
+        let mut written = hook.0.lock().unwrap().clone();
+        written.sort();
+        written.dedup();
+        assert_eq!(written, [b"geo".as_slice(), b"k", b"list", b"s"]);
+        for key in ["k", "list", "geo", "s"] {
+            assert_eq!(
+                handle.execute(["TYPE", key]).await.unwrap(),
+                Value::SimpleString(SimpleString::new("none"))
+            );
+        }
+        assert_eq!(handle.state.storage().used_memory(), 0);
+
+        handle.execute(["SET", "k", "v"]).await.unwrap();
+        assert_eq!(handle.execute(["FLUSHDB"]).await.unwrap(), ok);
+        assert_eq!(
+            handle.execute(["GET", "k"]).await.unwrap(),
+            Value::BulkString(BulkString::null())
+        );
+        assert_eq!(
+            handle.execute(["FLUSHALL", "NOW"]).await.unwrap(),
+            Value::SimpleError(SimpleError::with_prefix("ERR", "syntax error"))
+        );
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_save() {
+        let dir = std::env::temp_dir();
+        let name = format!("test-save-{}.rdb", std::process::id());
+        let path = dir.join(&name);
+        let handle = ServerBuilder::new()
+            .port(0)
+            .dir(&dir)
+            .dbfilename(&name)
+            .start()
+            .await
+            .unwrap();
+        let info = || async {
+            match handle.execute(["INFO", "persistence"]).await.unwrap() {
+                Value::BulkString(s) => String::from_utf8(s.value().unwrap().to_vec()).unwrap(),
+                v => panic!("unexpected INFO reply {v:?}"),
+            }
+        };
+        handle.execute(["SET", "k", "v"]).await.unwrap();
+        assert!(info().await.contains("rdb_changes_since_last_save:1\n"));
+
+        assert_eq!(
+            handle.execute(["BGSAVE"]).await.unwrap(),
+            Value::SimpleString(SimpleString::new("Background saving started"))
+        );
+        while info().await.contains("rdb_bgsave_in_progress:1\n") {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let text = info().await;
+        assert!(text.contains("rdb_changes_since_last_save:0\n"), "{text}");
+        assert!(text.contains("rdb_last_bgsave_status:ok\n"), "{text}");
+        assert!(std::fs::read(&path).unwrap().starts_with(b"REDIS0011"));
+        assert!(matches!(
+            handle.execute(["LASTSAVE"]).await.unwrap(),
+            Value::Integer(..)
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            handle.execute(["SAVE"]).await.unwrap(),
+            Value::SimpleString(SimpleString::new("OK"))
+        );
+        assert!(path.exists());
+        handle.shutdown().await;
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_config() {
+        let dir = std::env::temp_dir();
+        let name = format!("test-config-{}.rdb", std::process::id());
+        let aof_name = format!("test-config-{}.aof", std::process::id());
+        let handle = ServerBuilder::new()
+            .port(0)
+            .dir(&dir)
+            .appendfilename(&aof_name)
+            .start()
+            .await
+            .unwrap();
+        let bulk = |x: &str| Value::BulkString(BulkString::new(x));
+        let ok = Value::SimpleString(SimpleString::new("OK"));
+        assert_eq!(
+            handle
+                .execute(["CONFIG", "GET", "db*", "DBFILENAME"])
+                .await
+                .unwrap(),
+            Value::Array(Array::with_values(vec![
+                bulk("dbfilename"),
+                bulk("dump.rdb")
+            ]))
+        );
+        assert_eq!(
+            handle
+                .execute(["CONFIG", "SET", "dbfilename", &name, "save", "0 1"])
+                .await
+                .unwrap(),
+            ok
+        );
+        assert!(matches!(
+            handle
+                .execute(["CONFIG", "SET", "maxmemory", "-1"])
+                .await
+                .unwrap(),
+            Value::SimpleError(..)
+        ));
+
+        // Saved by the rule in background.
+        let path = dir.join(&name);
+        handle.execute(["SET", "k", "v"]).await.unwrap();
+        while !path.exists() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        // Turned on, the AOF is rewritten with the dataset.
+        let aof_path = dir.join(&aof_name);
+        assert_eq!(
+            handle
+                .execute(["CONFIG", "SET", "appendonly", "yes", "save", ""])
+                .await
+                .unwrap(),
+            ok
+        );
+        handle.execute(["SET", "k2", "v"]).await.unwrap();
+        let aof = || std::fs::read(&aof_path).unwrap_or_default();
+        while !aof().windows(2).any(|x| x == b"k\r") || !aof().windows(2).any(|x| x == b"k2") {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            handle
+                .execute(["CONFIG", "SET", "appendonly", "no"])
+                .await
+                .unwrap(),
+            ok
+        );
+        let len = aof().len();
+        handle.execute(["SET", "k3", "v"]).await.unwrap();
+        assert_eq!(aof().len(), len);
+
+        handle.shutdown().await;
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&aof_path).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_config_file() {
+        let path = std::env::temp_dir().join(format!("test-{}.conf", std::process::id()));
+        std::fs::write(&path, "port 0\ntcp-keepalive 60\nsave 3600 1\n").unwrap();
+        let handle = ServerBuilder::new()
+            .config_file(&path)
+            .unwrap()
+            .set_param("TCP-KEEPALIVE", "30")
+            .unwrap()
+            .start()
+            .await
+            .unwrap();
+        let bulk = |x: &str| Value::BulkString(BulkString::new(x));
+        assert_eq!(
+            handle
+                .execute(["CONFIG", "GET", "tcp-keepalive", "save"])
+                .await
+                .unwrap(),
+            Value::Array(Array::with_values(vec![
+                bulk("tcp-keepalive"),
+                bulk("30"),
+                bulk("save"),
+                bulk("3600 1"),
+            ]))
+        );
+        handle.shutdown().await;
+
+        std::fs::write(&path, "port 0\nmaxmemory 1x\n").unwrap();
+        assert!(ServerBuilder::new().config_file(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(ServerBuilder::new().set_param("foo", "1").is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_eviction() {
+        let handle = ServerBuilder::new()
+            .port(0)
+            .maxmemory_policy(MaxMemoryPolicy::AllKeysLru)
+            .start()
+            .await
+            .unwrap();
+        let ok = Value::SimpleString(SimpleString::new("OK"));
+        let value = "v".repeat(100);
+        for key in ["a", "b", "c"] {
+            handle.execute(["SET", key, &value]).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        handle.execute(["GET", "a"]).await.unwrap();
+
+        // Room for two keys only, "b" is the least recently used.
+        let maxmemory = handle.state.storage().used_memory() - 1;
+        assert_eq!(
+            handle
+                .execute(["CONFIG", "SET", "maxmemory", &maxmemory.to_string()])
+                .await
+                .unwrap(),
+            ok
+        );
+        assert_eq!(handle.execute(["SET", "d", &value]).await.unwrap(), ok);
+        for (key, exists) in [("a", true), ("b", false), ("c", true), ("d", true)] {
+            let expected = if exists {
+                Value::BulkString(BulkString::new(value.as_str()))
+            } else {
+                Value::BulkString(BulkString::null())
+            };
+            assert_eq!(handle.execute(["GET", key]).await.unwrap(), expected);
+        }
+        let Value::BulkString(info) = handle.execute(["INFO", "stats"]).await.unwrap() else {
+            panic!("INFO replies bulk string");
+        };
+        assert!(String::from_utf8_lossy(info.value().unwrap()).contains("evicted_keys:1\n"));
+
+        // Nothing evicted without a policy, deleting is still allowed.
+        handle
+            .execute(["CONFIG", "SET", "maxmemory-policy", "noeviction"])
+            .await
+            .unwrap();
+        assert!(matches!(
+            handle.execute(["SET", "e", &value]).await.unwrap(),
+            Value::SimpleError(..)
+        ));
+        assert_eq!(
+            handle.execute(["DEL", "a", "b"]).await.unwrap(),
+            Value::Integer(Integer::new(1))
+        );
+        assert_eq!(handle.execute(["SET", "e", &value]).await.unwrap(), ok);
+        handle.shutdown().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_aof_rewrite() {
+        let dir = std::env::temp_dir();
+        let name = format!("test-aof-{}.aof", std::process::id());
+        let path = dir.join(&name);
+        let start = || {
+            ServerBuilder::new()
+                .port(0)
+                .dir(&dir)
+                .appendonly(true)
+                .appendfilename(&name)
+                .start()
+        };
+        let handle = start().await.unwrap();
+        for i in 0..100 {
+            handle.execute(["INCR", "counter"]).await.unwrap();
+            handle
+                .execute(["RPUSH", "list", &i.to_string()])
+                .await
+                .unwrap();
+        }
+        handle.execute(["LPOP", "list", "50"]).await.unwrap();
+        handle
+            .execute(["XADD", "s", "1-1", "f", "v"])
+            .await
+            .unwrap();
+        handle
+            .execute(["XGROUP", "CREATE", "s", "g", "0"])
+            .await
+            .unwrap();
+        handle
+            .execute(["XREADGROUP", "GROUP", "g", "c", "STREAMS", "s", ">"])
+            .await
+            .unwrap();
+        let size = std::fs::metadata(&path).unwrap().len();
+
+        assert_eq!(
+            handle.execute(["BGREWRITEAOF"]).await.unwrap(),
+            Value::SimpleString(SimpleString::new(
+                "Background append only file rewriting started"
+            ))
+        );
+        // Appended while rewriting, or after.
+        handle.execute(["SET", "k", "v"]).await.unwrap();
+        let in_progress = || async {
+            match handle.execute(["INFO", "persistence"]).await.unwrap() {
+                Value::BulkString(s) => s
+                    .value()
+                    .unwrap()
+                    .windows(25)
+                    .any(|x| x == b"aof_rewrite_in_progress:1"),
+                v => panic!("unexpected INFO reply {v:?}"),
+            }
+        };
+        while in_progress().await {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(std::fs::metadata(&path).unwrap().len() < size);
+        handle.execute(["RPUSH", "list", "last"]).await.unwrap();
+        handle.shutdown().await;
+
+        let handle = start().await.unwrap();
+        assert_eq!(
+            handle.execute(["GET", "counter"]).await.unwrap(),
+            Value::BulkString(BulkString::new("100"))
+        );
+        assert_eq!(
+            handle.execute(["LLEN", "list"]).await.unwrap(),
+            Value::Integer(Integer::new(51))
+        );
+        assert_eq!(
+            handle.execute(["GET", "k"]).await.unwrap(),
+            Value::BulkString(BulkString::new("v"))
+        );
+        assert_eq!(
+            handle.execute(["XPENDING", "s", "g"]).await.unwrap(),
+            Value::Array(Array::with_values(vec![
+                Value::Integer(Integer::new(1)),
+                Value::BulkString(BulkString::new("1-1")),
+                Value::BulkString(BulkString::new("1-1")),
+                Value::Array(Array::with_values(vec![Value::Array(Array::with_values(
+                    vec![
+                        Value::BulkString(BulkString::new("c")),
+                        Value::BulkString(BulkString::new("1")),
+                    ]
+                ))])),
+            ]))
+        );
+        handle.shutdown().await;
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_large_value_aof_rewrite() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = std::env::temp_dir();
+        let name = format!("test-large-value-{}.aof", std::process::id());
+        let path = dir.join(&name);
+        let start = || {
+            ServerBuilder::new()
+                .port(0)
+                .dir(&dir)
+                .appendonly(true)
+                .appendfilename(&name)
+                .start()
+        };
+        let handle = start().await.unwrap();
+        handle.execute(["SET", "a", "1"]).await.unwrap();
+        handle.execute(["PFADD", "h", "x"]).await.unwrap();
+        let hll = match handle.execute(["GET", "h"]).await.unwrap() {
+            Value::BulkString(v) => v.value().unwrap().clone(),
+            v => panic!("unexpected GET reply {v:?}"),
+        };
+        assert!(hll.len() > 10_000);
+
+        // Bulk strings longer than 9999 bytes go through the codec.
+        let mut stream = TcpStream::connect(handle.local_addr()).await.unwrap();
+        let cmd = [b"SET".as_slice(), b"h2", &hll]
+            .into_iter()
+            .map(|x| Value::BulkString(BulkString::new(x)))
+            .collect::<Array>();
+        stream
+            .write_all(&serde_redis::to_vec(&cmd).unwrap())
+            .await
+            .unwrap();
+        roundtrip(&mut stream, &[], b"+OK\r\n").await;
+        roundtrip(&mut stream, &["PFCOUNT", "h2"], b":1\r\n").await;
+        roundtrip(
+            &mut stream,
+            &["GET", "h2"],
+            format!("${}\r\n", hll.len()).as_bytes(),
+        )
+        .await;
+        let mut value = vec![0; hll.len() + 2];
+        stream.read_exact(&mut value).await.unwrap();
+        assert_eq!(&value[..hll.len()], hll.as_slice());
+
+        handle.execute(["BGREWRITEAOF"]).await.unwrap();
+        let in_progress = || async {
+            match handle.execute(["INFO", "persistence"]).await.unwrap() {
+                Value::BulkString(s) => s
+                    .value()
+                    .unwrap()
+                    .windows(25)
+                    .any(|x| x == b"aof_rewrite_in_progress:1"),
+                v => panic!("unexpected INFO reply {v:?}"),
+            }
+        };
+        while in_progress().await {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        handle.shutdown().await;
+
+        let handle = start().await.unwrap();
+        for key in ["h", "h2"] {
+            assert_eq!(
+                handle.execute(["GET", key]).await.unwrap(),
+                Value::BulkString(BulkString::new(hll.clone()))
+            );
+        }
+        assert_eq!(
+            handle.execute(["PFCOUNT", "h"]).await.unwrap(),
+            Value::Integer(Integer::new(1))
+        );
+        handle.shutdown().await;
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_loading() {
+        let path = std::env::temp_dir().join(format!("test-loading-{}.json", std::process::id()));
+        let keys = (0..2000)
+            .map(|i| format!(r#"{{"key":"k{i}","ttl":null,"type":"string","value":"{i}"}}"#))
+            .collect::<Vec<_>>();
+        let json = format!(r#"{{"version":1,"keys":[{}]}}"#, keys.join(","));
+        std::fs::write(&path, &json).unwrap();
+
+        let handle = ServerBuilder::new()
+            .port(0)
+            .load_dump(&path)
+            .key_load_delay(500)
+            .start()
+            .await
+            .unwrap();
+        assert_eq!(
+            handle.execute(["GET", "k0"]).await.unwrap(),
+            Value::SimpleError(SimpleError::with_prefix(
+                "LOADING",
+                "Redis is loading the dataset in memory"
+            ))
+        );
+        assert_eq!(
+            handle.execute(["PING"]).await.unwrap(),
+            Value::SimpleString(SimpleString::new("PONG"))
+        );
+        match handle.execute(["INFO", "persistence"]).await.unwrap() {
+            Value::BulkString(s) => {
+                let text = String::from_utf8(s.value().unwrap().to_vec()).unwrap();
+                assert!(text.contains("loading:1\n"), "{text}");
+                let total = format!("loading_total_bytes:{}\n", json.len());
+                assert!(text.contains(&total), "{text}");
+            }
+            v => panic!("unexpected INFO reply {v:?}"),
+        }
+
+        handle.wait_loaded().await;
+        assert_eq!(
+            handle.execute(["GET", "k1999"]).await.unwrap(),
+            Value::BulkString(BulkString::new("1999"))
+        );
+        handle.shutdown().await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(ServerBuilder::new()
+            .port(0)
+            .load_dump(&path)
+            .start()
+            .await
+            .is_err());
+        assert!(ServerBuilder::new()
+            .port(7000)
+            .replicaof(Some((Ipv4Addr::LOCALHOST, 7000)))
+            .start()
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,334 @@
+//! State of the server shared by all connections, besides the keyspace.
+//!
+//! The keyspace lives in [`Storage`], which reports what concerns the rest of the
+//! server through [`KeyspaceEvents`], e.g. writes invalidating tracked keys. Each
+//! connection works on its own clone of the state, all clones share the same data.
+
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    acl::AclState,
+    blocking::{BlockKind, BlockingState, Unblock},
+    clients::ClientRegistry,
+    cluster::ClusterState,
+    config::SharedConfig,
+    info::{PersistenceInfo, StatsInfo},
+    lifecycle::Lifecycle,
+    load::LoadState,
+    log::log,
+    monitor::MonitorState,
+    pause::PauseState,
+    persistence::PersistenceState,
+    pubsub::PubSubState,
+    stats::StatsState,
+    storage::{write_rdb, AofLog, KeyspaceEvents, OpResult, Storage, StorageHook},
+    tracking::TrackingState,
+};
+
+#[derive(Clone)]
+pub(crate) struct ServerState {
+    storage: Storage,
+    pause: PauseState,
+    load: LoadState,
+    lifecycle: Lifecycle,
+    tracking: TrackingState,
+    pubsub: PubSubState,
+    acl: AclState,
+    clients: ClientRegistry,
+    monitor: MonitorState,
+    blocking: BlockingState,
+    stats: StatsState,
+    cluster: ClusterState,
+
+    /// Runtime configuration.
+    config: SharedConfig,
+    persistence: PersistenceState,
+    aof: AofLog,
+}
+
+/// Forwards keyspace events of the storage to the states depending on them.
+struct KeyspaceListener {
+    blocking: BlockingState,
+    tracking: TrackingState,
+    persistence: PersistenceState,
+    stats: StatsState,
+}
+
+impl KeyspaceEvents for KeyspaceListener {
+    fn written(&self, key: &[u8]) {
+        self.persistence.record_writes(1);
+        self.tracking.invalidate(key);
+    }
+
+    fn flushed(&self, writes: u64) {
+        // Consumer groups are removed with their streams, other blocked clients keep
+        // waiting for the keys to be written again.
+        self.blocking.unblock_all(
+            &[BlockKind::StreamGroup],
+            Unblock::error("the stream key no longer exists"),
+        );
+        self.tracking.invalidate_all();
+        self.persistence.record_writes(writes);
+    }
+
+    fn expired(&self) {
+        self.stats.count_expired();
+    }
+
+    fn looked_up(&self, hits: u64, misses: u64) {
+        self.stats.count_lookups(hits, misses);
+    }
+}
+
+impl ServerState {
+    /// Build the state of a server, its storage notifies all `hooks` on changes.
+    pub(crate) fn new(hooks: Vec<Arc<dyn StorageHook>>) -> Self {
+        let blocking = BlockingState::new();
+        let tracking = TrackingState::new();
+        let persistence = PersistenceState::new();
+        let stats = StatsState::new();
+        let listener = KeyspaceListener {
+            blocking: blocking.clone(),
+            tracking: tracking.clone(),
+            persistence: persistence.clone(),
+            stats: stats.clone(),
+        };
+        Self {
+            storage: Storage::with_hooks(hooks, Arc::new(listener)),
+            pause: PauseState::new(),
+            load: LoadState::new(),
+            lifecycle: Lifecycle::new(),
+            tracking,
+            pubsub: PubSubState::new(),
+            acl: AclState::new(),
+            clients: ClientRegistry::new(),
+            monitor: MonitorState::new(),
+            blocking,
+            stats,
+            cluster: ClusterState::new(),
+            config: SharedConfig::default(),
+            persistence,
+            aof: AofLog::new(),
+        }
+    }
+
+    /// The keyspace.
+    pub(crate) fn storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    pub(crate) fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+
+    /// Client pause state shared by all connections.
+    pub(crate) fn pause(&self) -> &PauseState {
+        &self.pause
+    }
+
+    /// Pending commands shared by all connections.
+    pub(crate) fn load(&self) -> &LoadState {
+        &self.load
+    }
+
+    /// Lifecycle of the server.
+    pub(crate) fn lifecycle(&self) -> &Lifecycle {
+        &self.lifecycle
+    }
+
+    /// Push channels and key tracking of all connections.
+    pub(crate) fn tracking(&self) -> &TrackingState {
+        &self.tracking
+    }
+
+    /// Message channels and subscriptions of all connections.
+    pub(crate) fn pubsub(&self) -> &PubSubState {
+        &self.pubsub
+    }
+
+    /// Users and their permissions.
+    pub(crate) fn acl(&self) -> &AclState {
+        &self.acl
+    }
+
+    /// States of all connected clients.
+    pub(crate) fn clients(&self) -> &ClientRegistry {
+        &self.clients
+    }
+
+    /// Feed of commands to connections in MONITOR mode.
+    pub(crate) fn monitor(&self) -> &MonitorState {
+        &self.monitor
+    }
+
+    /// Connections blocked by commands.
+    pub(crate) fn blocking(&self) -> &BlockingState {
+        &self.blocking
+    }
+
+    /// Counters reported in the stats section of INFO.
+    pub(crate) fn stats(&self) -> &StatsState {
+        &self.stats
+    }
+
+    /// Nodes and slots in cluster mode.
+    pub(crate) fn cluster(&self) -> &ClusterState {
+        &self.cluster
+    }
+
+    /// Runtime configuration.
+    pub(crate) fn config(&self) -> &SharedConfig {
+        &self.config
+    }
+
+    /// State of saving the RDB file.
+    pub(crate) fn persistence(&self) -> &PersistenceState {
+        &self.persistence
+    }
+
+    /// The AOF.
+    pub(crate) fn aof(&self) -> &AofLog {
+        &self.aof
+    }
+
+    /// Apply the configuration to the storage and the load state, call after it
+    /// changes.
+    ///
+    /// The AOF is left as is, as it is replayed before being opened at startup. See
+    /// [`ServerState::set_config`] for turning it on and off at runtime.
+    pub(crate) fn apply_config(&self) {
+        let config = self.config.get();
+        self.storage
+            .configure_lfu(config.maxmemory_policy, config.lfu);
+        self.load.set_max_pending(config.replica_max_pending);
+        self.load
+            .set_command_timeout(Duration::from_millis(config.command_timeout));
+    }
+
+    /// Set parameters in `params`, pairs of name and value, for CONFIG SET.
+    ///
+    /// Turning `appendonly` on opens the AOF and rewrites it in background, so it holds
+    /// the full dataset. Turning it off closes the AOF. Setting `requirepass` replaces
+    /// the passwords of the default user.
+    ///
+    /// Return the error message without prefix if any parameter is invalid, nothing is
+    /// set then.
+    pub(crate) fn set_config(&self, params: &[(String, String)]) -> Result<(), String> {
+        self.config.set_params(params)?;
+        self.apply_config();
+        let config = self.config.get();
+        if params
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("requirepass"))
+        {
+            self.acl.set_default_password(&config.requirepass);
+        }
+        match (config.appendonly, self.aof.is_enabled()) {
+            (true, false) => {
+                if let Err(e) = self.aof.open(config.aof_path()) {
+                    log!("[server] failed to open AOF: {e}");
+                    self.config.update(|x| x.appendonly = false);
+                    return Err("CONFIG SET failed (possibly related to argument 'appendonly') - Unable to turn on AOF. Check server logs.".to_string());
+                }
+                self.bgrewriteaof();
+            }
+            (false, true) => self.aof.close(),
+            (false, false) => self.aof.set_path(config.aof_path()),
+            (true, true) => {}
+        }
+        Ok(())
+    }
+
+    /// Write all keys to the RDB file in the foreground.
+    pub(crate) fn save(&self) -> std::io::Result<()> {
+        let path = self.config.get().rdb_path();
+        let ticket = self.persistence.start_save();
+        let result = write_rdb(&path, &self.storage.rdb_snapshot());
+        self.persistence.finish_save(ticket, result.is_ok());
+        result
+    }
+
+    /// Write all keys to the RDB file in background.
+    ///
+    /// Serializing and writing the snapshot run on a blocking task.
+    ///
+    /// Return false if another background save is in progress.
+    pub(crate) fn bgsave(&self) -> bool {
+        let path = self.config.get().rdb_path();
+        let Some((ticket, snapshot)) = self.storage.snapshot(|| self.persistence.start_bgsave())
+        else {
+            return false;
+        };
+        let persistence = self.persistence.clone();
+        tokio::task::spawn_blocking(move || {
+            let result = write_rdb(&path, &snapshot.to_rdb());
+            if let Err(e) = &result {
+                log!("[server] failed to save RDB to {}: {e}", path.display());
+            }
+            persistence.finish_bgsave(ticket, result.is_ok());
+        });
+        true
+    }
+
+    /// Rewrite the AOF in background, writes appended meanwhile are buffered and
+    /// appended to the rewritten file.
+    ///
+    /// The file is written even if AOF is disabled.
+    ///
+    /// Return false if another rewrite is in progress.
+    pub(crate) fn bgrewriteaof(&self) -> bool {
+        let Some(((), snapshot)) = self
+            .storage
+            .snapshot(|| self.aof.start_rewrite().then_some(()))
+        else {
+            return false;
+        };
+        let aof = self.aof.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = snapshot.write_aof(&aof) {
+                log!("[server] failed to rewrite AOF: {e}");
+            }
+        });
+        true
+    }
+
+    /// Persistence section of INFO, loading and saving.
+    pub(crate) fn persistence_info(&self) -> PersistenceInfo {
+        PersistenceInfo {
+            rdb: Some(self.persistence.info()),
+            aof: Some(self.aof.info()),
+            ..self.lifecycle.info()
+        }
+    }
+
+    /// Evict keys by `maxmemory-policy` till the used memory is within `maxmemory`,
+    /// see [`Storage::evict`].
+    pub(crate) fn evict(&self) -> Vec<Vec<u8>> {
+        self.storage.evict(self.maxmemory())
+    }
+
+    /// Check whether a write is allowed before running it, see [`Storage::check_oom`].
+    pub(crate) fn check_oom(&self) -> OpResult<()> {
+        self.storage.check_oom(self.maxmemory())
+    }
+
+    fn maxmemory(&self) -> usize {
+        self.config.read(|x| x.maxmemory) as usize
+    }
+
+    /// Zero all counters in INFO stats and commandstats, for CONFIG RESETSTAT.
+    pub(crate) fn reset_stats(&self) {
+        self.stats.reset();
+        self.load.reset_counters();
+        self.storage.reset_evicted_keys();
+    }
+
+    /// Stats section of INFO.
+    pub(crate) fn stats_info(&self) -> StatsInfo {
+        StatsInfo {
+            counters: Some(self.stats.snapshot()),
+            evicted_keys: self.storage.evicted_keys(),
+            ..self.load.info()
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! Counters of the server reported in the stats and commandstats sections of INFO.
+//!
+//! Counters are shared by all connections and only grow till CONFIG RESETSTAT,
+//! updated where the event happens: connections when accepted or rejected, commands when
+//! dispatched, keyspace hits and misses when a command reads keys, expired keys when
+//! removed on access.
+//!
+//! Each command is also recorded by its name, subcommands of container commands
+//! separately like "client|list", with the count of calls and the time spent.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone)]
+pub(crate) struct StatsState {
+    inner: Arc<StatsInner>,
+}
+
+#[derive(Debug)]
+struct StatsInner {
+    /// When the server started.
+    started: Instant,
+
+    connections_received: AtomicU64,
+    rejected_connections: AtomicU64,
+    commands_processed: AtomicU64,
+    expired_keys: AtomicU64,
+    keyspace_hits: AtomicU64,
+    keyspace_misses: AtomicU64,
+
+    /// Stats of commands by name.
+    commands: Mutex<BTreeMap<String, CommandStats>>,
+}
+
+/// How a command call ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CallOutcome {
+    /// Ran and replied without error.
+    Ok,
+
+    /// Ran and replied an error.
+    Failed,
+
+    /// Rejected before running, e.g. wrong arity or not permitted.
+    Rejected,
+}
+
+/// Stats of one command, same as a line in INFO commandstats of redis.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct CommandStats {
+    /// Count of calls ran, rejected ones not included.
+    pub(crate) calls: u64,
+
+    /// Microseconds spent in calls ran.
+    pub(crate) usec: u64,
+    pub(crate) rejected_calls: u64,
+    pub(crate) failed_calls: u64,
+}
+
+/// Values of counters at some point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct StatsSnapshot {
+    pub(crate) total_connections_received: u64,
+    pub(crate) total_commands_processed: u64,
+    pub(crate) expired_keys: u64,
+    pub(crate) keyspace_hits: u64,
+    pub(crate) keyspace_misses: u64,
+    pub(crate) rejected_connections: u64,
+}
+
+impl StatsState {
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: Arc::new(StatsInner {
+                started: Instant::now(),
+                connections_received: AtomicU64::new(0),
+                rejected_connections: AtomicU64::new(0),
+                commands_processed: AtomicU64::new(0),
+                expired_keys: AtomicU64::new(0),
+                keyspace_hits: AtomicU64::new(0),
+                keyspace_misses: AtomicU64::new(0),
+                commands: Mutex::new(BTreeMap::new()),
+            }),
+        }
+    }
+
+    /// Count a connection accepted.
+    pub(crate) fn count_connection(&self) {
+        self.inner
+            .connections_received
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a connection rejected by `maxclients`.
+    pub(crate) fn count_rejected_connection(&self) {
+        self.inner
+            .rejected_connections
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a command processed.
+    pub(crate) fn count_command(&self) {
+        self.inner
+            .commands_processed
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a key removed as expired.
+    pub(crate) fn count_expired(&self) {
+        self.inner.expired_keys.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count keys looked up by a read command, `hits` of them found.
+    pub(crate) fn count_lookups(&self, hits: u64, misses: u64) {
+        self.inner.keyspace_hits.fetch_add(hits, Ordering::Relaxed);
+        self.inner
+            .keyspace_misses
+            .fetch_add(misses, Ordering::Relaxed);
+    }
+
+    /// Record a call of command `name` taking `elapsed`, ended in `outcome`.
+    pub(crate) fn record_call(&self, name: &str, elapsed: Duration, outcome: CallOutcome) {
+        let mut commands = self.inner.commands.lock().unwrap();
+        if !commands.contains_key(name) {
+            commands.insert(name.to_string(), CommandStats::default());
+        }
+        let stats = commands.get_mut(name).unwrap();
+        if outcome == CallOutcome::Rejected {
+            stats.rejected_calls += 1;
+            return;
+        }
+        stats.calls += 1;
+        stats.usec += elapsed.as_micros() as u64;
+        if outcome == CallOutcome::Failed {
+            stats.failed_calls += 1;
+        }
+    }
+
+    /// Stats of commands ever called, ordered by name.
+    pub(crate) fn command_stats(&self) -> Vec<(String, CommandStats)> {
+        let commands = self.inner.commands.lock().unwrap();
+        commands.iter().map(|(k, v)| (k.clone(), *v)).collect()
+    }
+
+    /// Zero all counters, for CONFIG RESETSTAT.
+    pub(crate) fn reset(&self) {
+        let inner = &self.inner;
+        for counter in [
+            &inner.connections_received,
+            &inner.rejected_connections,
+            &inner.commands_processed,
+            &inner.expired_keys,
+            &inner.keyspace_hits,
+            &inner.keyspace_misses,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        inner.commands.lock().unwrap().clear();
+    }
+
+    /// Time since the server started.
+    pub(crate) fn uptime(&self) -> Duration {
+        self.inner.started.elapsed()
+    }
+
+    pub(crate) fn snapshot(&self) -> StatsSnapshot {
+        let inner = &self.inner;
+        StatsSnapshot {
+            total_connections_received: inner.connections_received.load(Ordering::Relaxed),
+            total_commands_processed: inner.commands_processed.load(Ordering::Relaxed),
+            expired_keys: inner.expired_keys.load(Ordering::Relaxed),
+            keyspace_hits: inner.keyspace_hits.load(Ordering::Relaxed),
+            keyspace_misses: inner.keyspace_misses.load(Ordering::Relaxed),
+            rejected_connections: inner.rejected_connections.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_counters() {
+        let stats = StatsState::new();
+        stats.count_connection();
+        stats.count_rejected_connection();
+        stats.clone().count_command();
+        stats.count_command();
+        stats.count_expired();
+        stats.count_lookups(2, 1);
+        assert_eq!(
+            stats.snapshot(),
+            StatsSnapshot {
+                total_connections_received: 1,
+                total_commands_processed: 2,
+                expired_keys: 1,
+                keyspace_hits: 2,
+                keyspace_misses: 1,
+                rejected_connections: 1,
+            }
+        );
+
+        stats.record_call("get", Duration::from_micros(3), CallOutcome::Ok);
+        stats.record_call("get", Duration::from_micros(5), CallOutcome::Failed);
+        stats.record_call("client|list", Duration::ZERO, CallOutcome::Rejected);
+        assert_eq!(
+            stats.command_stats(),
+            [
+                (
+                    "client|list".to_string(),
+                    CommandStats {
+                        calls: 0,
+                        usec: 0,
+                        rejected_calls: 1,
+                        failed_calls: 0,
+                    }
+                ),
+                (
+                    "get".to_string(),
+                    CommandStats {
+                        calls: 2,
+                        usec: 8,
+                        rejected_calls: 0,
+                        failed_calls: 1,
+                    }
+                ),
+            ]
+        );
+
+        stats.reset();
+        assert_eq!(stats.snapshot().total_commands_processed, 0);
+        assert!(stats.command_stats().is_empty());
+    }
+}
@@ -0,0 +1,398 @@
+//! Append-only file (AOF): every write command is appended to the file, and replayed
+//! to rebuild the dataset at startup.
+//!
+//! The file only grows, BGREWRITEAOF compacts it in background:
+//!
+//! 1. Under the storage lock, clone the keyspace and start buffering appended writes.
+//! 2. On a blocking task, write the commands rebuilding the clone to a temporary file.
+//! 3. Under the AOF lock, append the buffered writes to the temporary file and rename
+//!    it to the AOF, so the file always holds the full dataset.
+//!
+//! Rebuilding commands are the minimal ones in the protocol: SET with PXAT for
+//! strings, RPUSH for lists, ZINCRBY for sorted sets, XADD, XSETID, XGROUP CREATE and
+//! XCLAIM for streams, the first XADD setting the length cap of stream by CAP. Lists with expire time are written as IMPORT, as there is no
+//! command to set expire time on lists. Consumers without pending records and the
+//! read counter of consumer groups are not kept.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::UNIX_EPOCH,
+};
+
+use serde_redis::{Array, BulkString, Value};
+
+use crate::{
+    info::AofInfo,
+    log::log,
+    storage::{
+        dump::Dump, rdb, sorted_set::format_score, stream::Stream, string_bytes, Object,
+        StorageInner,
+    },
+};
+
+/// Max count of elements in one rebuilding command, same as redis.
+const AOF_REWRITE_ITEMS_PER_CMD: usize = 64;
+
+fn command<T: Into<Vec<u8>>>(parts: impl IntoIterator<Item = T>) -> Array {
+    parts
+        .into_iter()
+        .map(|x| Value::BulkString(BulkString::new(x)))
+        .collect()
+}
+
+fn format_id((ms, seq): (u64, u64)) -> String {
+    format!("{ms}-{seq}")
+}
+
+fn encode(messages: &[Array]) -> Vec<u8> {
+    messages
+        .iter()
+        .flat_map(|x| serde_redis::to_vec(x).unwrap_or_default())
+        .collect()
+}
+
+/// Commands rebuilding `stream` at `key`.
+fn stream_commands(key: &[u8], stream: &Stream, commands: &mut Vec<Array>) {
+    let last_id = format_id(stream.last_generated_id());
+    let mut cap = stream
+        .max_len()
+        .map(|x| vec![b"CAP".to_vec(), x.to_string().into()]);
+    let mut records = stream.records().peekable();
+    if records.peek().is_none() {
+        // Create the stream with a record trimmed at once.
+        let mut parts = vec![b"XADD".to_vec(), key.into()];
+        parts.extend(cap.take().unwrap_or_default());
+        parts.extend([
+            b"MAXLEN".to_vec(),
+            b"0".to_vec(),
+            last_id.clone().into(),
+            b"x".to_vec(),
+            b"y".to_vec(),
+        ]);
+        commands.push(command(parts));
+    }
+    for (ms, seq, values) in records {
+        let mut parts = vec![b"XADD".to_vec(), key.into()];
+        parts.extend(cap.take().unwrap_or_default());
+        parts.push(format_id((ms, seq)).into());
+        parts.extend(values.iter().map(|x| string_bytes(x).unwrap_or_default()));
+        commands.push(command(parts));
+    }
+    commands.push(command::<Vec<u8>>([
+        "XSETID".into(),
+        key.into(),
+        last_id.into(),
+        "ENTRIESADDED".into(),
+        stream.entries_added().to_string().into(),
+        "MAXDELETEDID".into(),
+        format_id(stream.max_deleted_id()).into(),
+    ]));
+
+    for (name, group) in stream.groups() {
+        commands.push(command::<Vec<u8>>([
+            "XGROUP".into(),
+            "CREATE".into(),
+            key.into(),
+            name.clone().into(),
+            format_id(group.last_delivered_id()).into(),
+        ]));
+        for (id, entry) in group.pending() {
+            commands.push(command::<Vec<u8>>([
+                "XCLAIM".into(),
+                key.into(),
+                name.clone().into(),
+                entry.consumer.clone().into(),
+                "0".into(),
+                format_id(*id).into(),
+                "TIME".into(),
+                entry.delivery_time.to_string().into(),
+                "RETRYCOUNT".into(),
+                entry.delivery_count.to_string().into(),
+                "FORCE".into(),
+                "JUSTID".into(),
+            ]));
+        }
+    }
+}
+
+/// Commands rebuilding all live keys in `storage`.
+pub(super) fn rewrite(storage: &StorageInner) -> Vec<Array> {
+    let mut commands = vec![];
+    for (key, cell) in storage.data.iter() {
+        let Some(value) = cell.live_value_ref() else {
+            continue;
+        };
+        match (value, cell.expiration) {
+            (Object::List(..), Some(..)) => {
+                let json = Dump::export(storage, std::slice::from_ref(key)).to_json();
+                commands.push(command(["IMPORT", &json, "REPLACE"]));
+            }
+            (Object::List(list), None) => {
+                for chunk in list
+                    .iter()
+                    .collect::<Vec<_>>()
+                    .chunks(AOF_REWRITE_ITEMS_PER_CMD)
+                {
+                    let mut parts = vec![b"RPUSH".to_vec(), key.clone()];
+                    parts.extend(chunk.iter().map(|x| x.to_vec()));
+                    commands.push(command(parts));
+                }
+            }
+            (Object::Stream(..) | Object::ZSet(..), Some(..))
+            | (Object::Hash(..) | Object::Set(..), _) => {
+                // XADD and ZINCRBY can not set the expiration, and no command writes
+                // hashes or sets, restore the whole value at once.
+                let millis = cell.expiration.map_or(0, |x| {
+                    x.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+                });
+                commands.push(command([
+                    b"RESTORE".to_vec(),
+                    key.clone(),
+                    millis.to_string().into_bytes(),
+                    rdb::dump(storage, key).unwrap_or_default(),
+                    b"REPLACE".to_vec(),
+                    b"ABSTTL".to_vec(),
+                ]));
+            }
+            (Object::Stream(stream), None) => stream_commands(key, stream, &mut commands),
+            (Object::ZSet(zset), None) => {
+                for (member, score) in zset.iter() {
+                    commands.push(command([
+                        b"ZINCRBY".as_slice(),
+                        key,
+                        format_score(score).as_bytes(),
+                        member.as_bytes(),
+                    ]));
+                }
+            }
+            (v, expiration) => {
+                let mut parts = vec![
+                    b"SET".to_vec(),
+                    key.clone(),
+                    v.string().unwrap_or_default().into_owned(),
+                ];
+                if let Some(expiration) = expiration {
+                    let millis = expiration.duration_since(UNIX_EPOCH).unwrap_or_default();
+                    parts.push(b"PXAT".to_vec());
+                    parts.push(millis.as_millis().to_string().into_bytes());
+                }
+                commands.push(command(parts));
+            }
+        }
+    }
+    commands
+}
+
+#[derive(Debug)]
+struct AofInner {
+    /// Path of the AOF.
+    path: PathBuf,
+
+    /// The AOF opened for appending, `None` if AOF is disabled.
+    file: Option<File>,
+
+    /// Writes appended since the running rewrite took its snapshot, `None` if no
+    /// rewrite is running.
+    rewrite_buffer: Option<Vec<u8>>,
+
+    /// Whether the last rewrite succeeded, true if never run.
+    last_rewrite_ok: bool,
+}
+
+/// The AOF and the state of rewriting it.
+#[derive(Debug, Clone)]
+pub(crate) struct AofLog {
+    inner: Arc<Mutex<AofInner>>,
+}
+
+impl AofLog {
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(AofInner {
+                path: PathBuf::from("appendonly.aof"),
+                file: None,
+                rewrite_buffer: None,
+                last_rewrite_ok: true,
+            })),
+        }
+    }
+
+    /// Enable AOF, append writes to the file at `path` from now on.
+    pub(crate) fn open(&self, path: PathBuf) -> std::io::Result<()> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let mut inner = self.inner.lock().unwrap();
+        inner.path = path;
+        inner.file = Some(file);
+        Ok(())
+    }
+
+    /// Disable AOF, writes are no longer appended.
+    pub(crate) fn close(&self) {
+        self.inner.lock().unwrap().file = None;
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.inner.lock().unwrap().file.is_some()
+    }
+
+    /// Set the path of AOF without enabling it, for rewriting while disabled.
+    pub(crate) fn set_path(&self, path: PathBuf) {
+        self.inner.lock().unwrap().path = path;
+    }
+
+    /// Append write commands in `messages` to the AOF, if enabled.
+    pub(crate) fn append(&self, messages: &[Array]) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.file.is_none() || messages.is_empty() {
+            return;
+        }
+        let bytes = encode(messages);
+        if let Some(buffer) = inner.rewrite_buffer.as_mut() {
+            buffer.extend(&bytes);
+        }
+        if let Some(Err(e)) = inner.file.as_mut().map(|x| x.write_all(&bytes)) {
+            log!("[storage] failed to append to AOF: {e}");
+        }
+    }
+
+    /// Start buffering appended writes for a rewrite, call when taking the snapshot.
+    ///
+    /// Return false if another rewrite is running.
+    pub(crate) fn start_rewrite(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.rewrite_buffer.is_some() {
+            return false;
+        }
+        inner.rewrite_buffer = Some(vec![]);
+        true
+    }
+
+    /// Replace the AOF with `commands` rebuilding the snapshot, and the writes appended
+    /// since the snapshot.
+    pub(super) fn finish_rewrite(&self, commands: &[Array]) -> std::io::Result<()> {
+        let path = self.inner.lock().unwrap().path.clone();
+        let tmp_path = path.with_file_name(format!("temp-rewriteaof-{}.aof", std::process::id()));
+        let result = std::fs::write(&tmp_path, encode(commands))
+            .and_then(|_| self.swap_rewritten(&path, &tmp_path));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&tmp_path);
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.rewrite_buffer = None;
+        inner.last_rewrite_ok = result.is_ok();
+        result
+    }
+
+    /// Append buffered writes to the rewritten file at `tmp_path`, then rename it to
+    /// `path`. No write is appended meanwhile.
+    fn swap_rewritten(&self, path: &Path, tmp_path: &Path) -> std::io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut tmp_file = OpenOptions::new().append(true).open(tmp_path)?;
+        tmp_file.write_all(inner.rewrite_buffer.as_deref().unwrap_or_default())?;
+        tmp_file.sync_all()?;
+        std::fs::rename(tmp_path, path)?;
+        if inner.file.is_some() {
+            inner.file = Some(tmp_file);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn info(&self) -> AofInfo {
+        let inner = self.inner.lock().unwrap();
+        AofInfo {
+            aof_enabled: inner.file.is_some() as u8,
+            aof_rewrite_in_progress: inner.rewrite_buffer.is_some() as u8,
+            aof_last_bgrewrite_status: if inner.last_rewrite_ok { "ok" } else { "err" },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, sync::Arc};
+
+    use super::*;
+    use crate::storage::{list::List, sorted_set::SortedSet, ValueCell};
+
+    #[test]
+    fn test_rewrite() {
+        let s = |x: &str| Value::BulkString(BulkString::new(x));
+        let mut zset = SortedSet::default();
+        zset.insert("m".into(), 1.5);
+        let mut stream = Stream::new();
+        stream.add_entry(1, 1, vec![s("f"), s("v")]).unwrap();
+        stream.set_max_len(5);
+        let storage = StorageInner {
+            data: HashMap::from([
+                (
+                    b"l".to_vec(),
+                    ValueCell {
+                        value: Arc::new(Object::List(List::from_iter([
+                            b"a".to_vec(),
+                            b"b".to_vec(),
+                        ]))),
+                        expiration: None,
+                    },
+                ),
+                (
+                    b"s".to_vec(),
+                    ValueCell {
+                        value: Arc::new(Object::Stream(stream)),
+                        expiration: None,
+                    },
+                ),
+                (
+                    b"z".to_vec(),
+                    ValueCell {
+                        value: Arc::new(Object::ZSet(zset)),
+                        expiration: None,
+                    },
+                ),
+                (
+                    b"h".to_vec(),
+                    ValueCell {
+                        value: Arc::new(Object::Hash(HashMap::from([(
+                            b"f".to_vec(),
+                            b"v".to_vec(),
+                        )]))),
+                        expiration: None,
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+        let list = vec![command(["RPUSH", "l", "a", "b"])];
+        let stream = vec![
+            command(["XADD", "s", "CAP", "5", "1-1", "f", "v"]),
+            command([
+                "XSETID",
+                "s",
+                "1-1",
+                "ENTRIESADDED",
+                "1",
+                "MAXDELETEDID",
+                "0-0",
+            ]),
+        ];
+        let zset = vec![command(["ZINCRBY", "z", "1.5", "m"])];
+        let hash = vec![command([
+            b"RESTORE".to_vec(),
+            b"h".to_vec(),
+            b"0".to_vec(),
+            rdb::dump(&storage, b"h").unwrap(),
+            b"REPLACE".to_vec(),
+            b"ABSTTL".to_vec(),
+        ])];
+        let commands = rewrite(&storage);
+        // Keys are rebuilt in any order, commands of each key are kept together.
+        assert_eq!(commands.len(), 5);
+        for key_commands in [list, stream, zset, hash] {
+            assert!(commands
+                .windows(key_commands.len())
+                .any(|x| x == key_commands));
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Bit operations on string values.
+//!
+//! Bits are addressed from the most significant bit of the first byte, so bit 0
+//! is `0x80` of byte 0 and bit 9 is `0x40` of byte 1, same as redis.
+
+/// Unit of offsets in BITCOUNT and BITPOS ranges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitUnit {
+    Byte,
+    Bit,
+}
+
+/// Get the bit at `offset`, bits beyond the end of `bytes` are 0.
+pub(super) fn get_bit(bytes: &[u8], offset: usize) -> bool {
+    bytes
+        .get(offset / 8)
+        .is_some_and(|b| b & (0x80 >> (offset % 8)) != 0)
+}
+
+/// Set the bit at `offset` to `bit`, `bytes` is zero-extended if too short.
+///
+/// Return the original bit.
+pub(super) fn set_bit(bytes: &mut Vec<u8>, offset: usize, bit: bool) -> bool {
+    let index = offset / 8;
+    if bytes.len() <= index {
+        bytes.resize(index + 1, 0);
+    }
+    let mask = 0x80 >> (offset % 8);
+    let old = bytes[index] & mask != 0;
+    if bit {
+        bytes[index] |= mask;
+    } else {
+        bytes[index] &= !mask;
+    }
+    old
+}
+
+/// Convert range `start..=end` in `unit` to an inclusive range of bit offsets
+/// inside a string of `len` bytes.
+///
+/// Negative offsets count from the end, out of range offsets are limited to
+/// the actual length. Return `None` if the range is empty.
+pub(super) fn bit_range(len: usize, start: i64, end: i64, unit: BitUnit) -> Option<(usize, usize)> {
+    let total = match unit {
+        BitUnit::Byte => len as i64,
+        BitUnit::Bit => len as i64 * 8,
+    };
+    let start = if start < 0 {
+        (total + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 {
+        (total + end).max(0)
+    } else {
+        end.min(total - 1)
+    };
+    if total == 0 || start > end {
+        return None;
+    }
+    let (start, end) = (start as usize, end as usize);
+    match unit {
+        BitUnit::Byte => Some((start * 8, end * 8 + 7)),
+        BitUnit::Bit => Some((start, end)),
+    }
+}
+
+/// Count the set bits in bit range `first..=last`.
+pub(super) fn count(bytes: &[u8], (first, last): (usize, usize)) -> usize {
+    let mut n = 0;
+    let mut offset = first;
+    while offset <= last {
+        if offset % 8 == 0 && offset + 7 <= last {
+            n += bytes[offset / 8].count_ones() as usize;
+            offset += 8;
+        } else {
+            n += get_bit(bytes, offset) as usize;
+            offset += 1;
+        }
+    }
+    n
+}
+
+/// Find the first bit equals to `bit` in bit range `first..=last`.
+pub(super) fn position(bytes: &[u8], bit: bool, (first, last): (usize, usize)) -> Option<usize> {
+    // Whole bytes without the wanted bit are skipped at once.
+    let skip = if bit { 0x00 } else { 0xff };
+    let mut offset = first;
+    while offset <= last {
+        if offset % 8 == 0 && offset + 7 <= last && bytes[offset / 8] == skip {
+            offset += 8;
+            continue;
+        }
+        if get_bit(bytes, offset) == bit {
+            return Some(offset);
+        }
+        offset += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bits() {
+        let mut bytes = vec![];
+        assert!(!set_bit(&mut bytes, 9, true));
+        assert_eq!(bytes, vec![0x00, 0x40]);
+        assert!(set_bit(&mut bytes, 9, false));
+        assert!(!get_bit(&bytes, 9));
+        assert!(!get_bit(&bytes, 100));
+
+        let bytes = b"foobar";
+        assert_eq!(
+            count(bytes, bit_range(6, 0, -1, BitUnit::Byte).unwrap()),
+            26
+        );
+        assert_eq!(count(bytes, bit_range(6, 1, 1, BitUnit::Byte).unwrap()), 6);
+        assert_eq!(count(bytes, bit_range(6, 5, 30, BitUnit::Bit).unwrap()), 17);
+        assert_eq!(bit_range(6, 3, 2, BitUnit::Byte), None);
+        assert_eq!(bit_range(0, 0, -1, BitUnit::Byte), None);
+
+        let bytes = [0xff, 0xf0, 0x00];
+        assert_eq!(position(&bytes, false, (0, 23)), Some(12));
+        assert_eq!(position(&bytes, true, (2, 23)), Some(2));
+        assert_eq!(position(&bytes, true, (16, 23)), None);
+        assert_eq!(position(&[0xff], false, (0, 7)), None);
+    }
+}
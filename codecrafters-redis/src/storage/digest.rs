@@ -0,0 +1,115 @@
+//! Digest of the whole keyspace, used by DEBUG DIGEST.
+//!
+//! Like redis, the digest of each key is mixed into the result with XOR, so it does
+//! not depend on the order of keys, and an empty keyspace has a digest of all zeros.
+//! Two instances holding the same keys, values and expirations have the same digest.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    time::UNIX_EPOCH,
+};
+
+use crate::storage::{sorted_set::format_score, string_bytes, Object, StorageInner};
+
+/// Length of digest in bytes, rendered as 40 hex chars.
+const DIGEST_LEN: usize = 20;
+
+/// Mix the hash of one key into `digest`.
+///
+/// The 64 bits hash is spread over `DIGEST_LEN` bytes by hashing it again with
+/// the index of each 8 bytes chunk.
+fn mix(digest: &mut [u8; DIGEST_LEN], hash: u64) {
+    for (i, chunk) in digest.chunks_mut(8).enumerate() {
+        let mut hasher = DefaultHasher::new();
+        (i, hash).hash(&mut hasher);
+        for (d, x) in chunk.iter_mut().zip(hasher.finish().to_be_bytes()) {
+            *d ^= x;
+        }
+    }
+}
+
+/// Compute the digest of all live keys in `storage`, as hex string.
+pub(super) fn digest(storage: &StorageInner) -> String {
+    let mut digest = [0u8; DIGEST_LEN];
+
+    for (key, cell) in storage.data.iter() {
+        let value = match cell.live_value_ref() {
+            Some(v) => v,
+            None => continue,
+        };
+        let expiration = cell
+            .expiration
+            .map(|x| x.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis());
+        let mut h = DefaultHasher::new();
+        key.hash(&mut h);
+        expiration.hash(&mut h);
+        match value {
+            Object::List(list) => {
+                "list".hash(&mut h);
+                for element in list.iter() {
+                    element.hash(&mut h);
+                }
+            }
+            Object::ZSet(zset) => {
+                "zset".hash(&mut h);
+                for (member, score) in zset.iter() {
+                    member.hash(&mut h);
+                    format_score(score).hash(&mut h);
+                }
+            }
+            Object::Hash(hash) => {
+                "hash".hash(&mut h);
+                // Fields are in any order.
+                let mut fields = hash.iter().collect::<Vec<_>>();
+                fields.sort();
+                fields.hash(&mut h);
+            }
+            Object::Set(set) => {
+                "set".hash(&mut h);
+                let mut members = set.iter().collect::<Vec<_>>();
+                members.sort();
+                members.hash(&mut h);
+            }
+            Object::Stream(stream) => {
+                "stream".hash(&mut h);
+                stream.last_generated_id().hash(&mut h);
+                for (time_id, seq_id, values) in stream.records() {
+                    (time_id, seq_id).hash(&mut h);
+                    for v in values {
+                        string_bytes(v).unwrap_or_default().hash(&mut h);
+                    }
+                }
+            }
+            v => {
+                "string".hash(&mut h);
+                v.string().unwrap_or_default().hash(&mut h);
+            }
+        }
+        mix(&mut digest, h.finish());
+    }
+
+    digest.iter().map(|x| format!("{x:02x}")).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::Storage;
+
+    #[test]
+    fn test_digest() {
+        let mut a = Storage::new();
+        let mut b = Storage::new();
+        assert_eq!(a.digest(), "0".repeat(40));
+
+        a.string_append("x".into(), b"1".to_vec()).unwrap();
+        a.string_append("y".into(), b"2".to_vec()).unwrap();
+        b.string_append("y".into(), b"2".to_vec()).unwrap();
+        assert_ne!(a.digest(), b.digest());
+        b.string_append("x".into(), b"1".to_vec()).unwrap();
+        assert_eq!(a.digest(), b.digest());
+
+        b.string_append("x".into(), b"0".to_vec()).unwrap();
+        assert_ne!(a.digest(), b.digest());
+    }
+}
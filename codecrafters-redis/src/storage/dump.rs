@@ -0,0 +1,297 @@
+//! Dump keys to JSON and import them back, used by EXPORT and IMPORT.
+//!
+//! The document looks like:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "keys": [
+//!     { "key": "greeting", "type": "string", "ttl": 1500, "value": "hello" },
+//!     { "key": "queue", "type": "list", "ttl": null, "value": ["a", "b"] },
+//!     { "key": "rank", "type": "zset", "ttl": null, "value": [{ "member": "m", "score": "1.5" }] },
+//!     { "key": "user", "type": "hash", "ttl": null, "value": { "name": "alice" } },
+//!     { "key": "tags", "type": "set", "ttl": null, "value": ["a", "b"] },
+//!     { "key": "events", "type": "stream", "ttl": null, "value": [{ "id": "1-1", "fields": ["f", "v"] }] }
+//!   ]
+//! }
+//! ```
+//!
+//! * `ttl` is the remaining time to live in milliseconds, `null` if the key never
+//!   expires.
+//! * `score` is a string so that `inf` and `-inf` are representable.
+//! * `fields` of a stream record are the field-value pairs in order.
+//! * `max_len` of a stream is its length cap set by XADD CAP, omitted if not set.
+//! * All contents are UTF-8 strings, invalid bytes are replaced when exported.
+
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_redis::{BulkString, Value};
+
+use crate::storage::{
+    sorted_set::{format_score, SortedSet},
+    stream::Stream,
+    string_bytes, string_object, Object, StorageInner, ValueCell,
+};
+
+/// Version of the document format.
+const DUMP_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct Dump {
+    version: u32,
+    keys: Vec<DumpKey>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpKey {
+    key: String,
+    ttl: Option<u64>,
+    #[serde(flatten)]
+    value: DumpValue,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_len: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+enum DumpValue {
+    String(String),
+    List(Vec<String>),
+    Zset(Vec<DumpMember>),
+    Hash(BTreeMap<String, String>),
+    Set(Vec<String>),
+    Stream(Vec<DumpRecord>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpMember {
+    member: String,
+    score: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DumpRecord {
+    id: String,
+    fields: Vec<String>,
+}
+
+/// Content of string `value` in UTF-8.
+fn dump_string(value: &Value) -> String {
+    dump_bytes(&string_bytes(value).unwrap_or_default())
+}
+
+/// `bytes` in UTF-8.
+fn dump_bytes(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+impl Dump {
+    /// Dump `keys` in `storage`, keys not present are skipped.
+    pub(super) fn export(storage: &StorageInner, keys: &[Vec<u8>]) -> Self {
+        let now = SystemTime::now();
+        let keys = keys
+            .iter()
+            .filter_map(|key| {
+                let cell = storage.data.get(key)?;
+                let value = match cell.live_value_ref()? {
+                    Object::List(list) => {
+                        DumpValue::List(list.iter().map(|x| dump_bytes(x)).collect())
+                    }
+                    Object::ZSet(zset) => DumpValue::Zset(
+                        zset.iter()
+                            .map(|(member, score)| DumpMember {
+                                member: member.to_string(),
+                                score: format_score(score),
+                            })
+                            .collect(),
+                    ),
+                    Object::Hash(hash) => DumpValue::Hash(
+                        hash.iter()
+                            .map(|(field, value)| (dump_bytes(field), dump_bytes(value)))
+                            .collect(),
+                    ),
+                    Object::Set(set) => {
+                        let mut members = set.iter().map(|x| dump_bytes(x)).collect::<Vec<_>>();
+                        members.sort();
+                        DumpValue::Set(members)
+                    }
+                    Object::Stream(stream) => DumpValue::Stream(
+                        stream
+                            .records()
+                            .map(|(time_id, seq_id, values)| DumpRecord {
+                                id: format!("{time_id}-{seq_id}"),
+                                fields: values.iter().map(dump_string).collect(),
+                            })
+                            .collect(),
+                    ),
+                    v => DumpValue::String(dump_bytes(&v.string().ok()?)),
+                };
+                let ttl = cell
+                    .expiration
+                    .map(|x| x.duration_since(now).unwrap_or_default().as_millis() as u64);
+                let max_len = match cell.live_value_ref()? {
+                    Object::Stream(stream) => stream.max_len(),
+                    _ => None,
+                };
+                Some(DumpKey {
+                    key: dump_bytes(key),
+                    ttl,
+                    value,
+                    max_len,
+                })
+            })
+            .collect();
+        Self {
+            version: DUMP_VERSION,
+            keys,
+        }
+    }
+
+    /// Parse the document in `json`.
+    ///
+    /// Return the error message if the document is invalid.
+    pub(super) fn parse(json: &[u8]) -> Result<Self, String> {
+        let dump: Self = serde_json::from_slice(json).map_err(|e| e.to_string())?;
+        if dump.version != DUMP_VERSION {
+            return Err(format!("unsupported version {}", dump.version));
+        }
+        Ok(dump)
+    }
+
+    /// Names of all keys in the document.
+    pub(super) fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.keys.iter().map(|x| x.key.as_bytes())
+    }
+
+    /// Split into documents of at most `size` keys, keys keep their order.
+    pub(super) fn into_batches(self, size: usize) -> Vec<Self> {
+        let mut batches = vec![];
+        let mut keys = self.keys;
+        while !keys.is_empty() {
+            let rest = keys.split_off(keys.len().min(size));
+            batches.push(Self {
+                version: self.version,
+                keys,
+            });
+            keys = rest;
+        }
+        batches
+    }
+
+    /// Save all keys in the document to `storage`, existing keys are replaced.
+    ///
+    /// Nothing is saved if any key in the document is invalid.
+    pub(super) fn import(self, storage: &mut StorageInner) -> Result<(), String> {
+        let now = SystemTime::now();
+        let mut cells = vec![];
+        for DumpKey {
+            key,
+            ttl,
+            value,
+            max_len,
+        } in self.keys
+        {
+            let expiration = ttl.map(|x| now + Duration::from_millis(x));
+            let value = match value {
+                DumpValue::String(s) => string_object(s.into_bytes()),
+                DumpValue::List(elements) => {
+                    Object::List(elements.into_iter().map(String::into_bytes).collect())
+                }
+                DumpValue::Zset(members) => {
+                    let mut zset = SortedSet::default();
+                    for DumpMember { member, score } in members {
+                        let score = score
+                            .parse::<f64>()
+                            .map_err(|_| format!("invalid score {score} in key {key}"))?;
+                        zset.incr(member, score)
+                            .map_err(|_| format!("invalid score in key {key}"))?;
+                    }
+                    Object::ZSet(zset)
+                }
+                DumpValue::Hash(hash) => Object::Hash(
+                    hash.into_iter()
+                        .map(|(field, value)| (field.into_bytes(), value.into_bytes()))
+                        .collect(),
+                ),
+                DumpValue::Set(members) => {
+                    Object::Set(members.into_iter().map(String::into_bytes).collect())
+                }
+                DumpValue::Stream(records) => {
+                    let mut stream = Stream::new();
+                    for DumpRecord { id, fields } in records {
+                        let (time_id, seq_id) = id
+                            .split_once('-')
+                            .and_then(|(t, s)| Some((t.parse().ok()?, s.parse().ok()?)))
+                            .ok_or_else(|| format!("invalid stream id {id} in key {key}"))?;
+                        let values = fields
+                            .into_iter()
+                            .map(|x| Value::BulkString(BulkString::new(x)))
+                            .collect();
+                        stream
+                            .add_entry(time_id, seq_id, values)
+                            .map_err(|_| format!("stream id {id} out of order in key {key}"))?;
+                    }
+                    if let Some(max_len) = max_len {
+                        stream.set_max_len(max_len);
+                    }
+                    Object::Stream(stream)
+                }
+            };
+            let value = Arc::new(value);
+            cells.push((key.into_bytes(), ValueCell { value, expiration }));
+        }
+
+        storage.data.extend(cells);
+        Ok(())
+    }
+
+    pub(super) fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::{OpError, SetCondition, SetExpire, Storage};
+
+    #[test]
+    fn test_export_import() {
+        let storage = Storage::new();
+        storage
+            .set(
+                "s".into(),
+                b"hello".to_vec(),
+                SetExpire::Never,
+                SetCondition::Always,
+                false,
+            )
+            .unwrap();
+        let list = vec![b"a".to_vec(), b"b".to_vec()];
+        storage.insert_list("l".into(), list, true, false).unwrap();
+
+        let keys = ["s", "l", "missing"].map(Vec::from);
+        let json = storage.export_json(&keys);
+        assert_eq!(
+            json,
+            r#"{"version":1,"keys":[{"key":"s","ttl":null,"type":"string","value":"hello"},{"key":"l","ttl":null,"type":"list","value":["a","b"]}]}"#
+        );
+
+        assert!(matches!(
+            storage.import_json(json.as_bytes(), false),
+            Err(OpError::BusyKey)
+        ));
+        let other = Storage::new();
+        assert_eq!(other.import_json(json.as_bytes(), false).unwrap(), 2);
+        assert_eq!(other.export_json(&keys), json);
+
+        // Sorted sets keep their ttl like other types.
+        let json = r#"{"version":1,"keys":[{"key":"z","ttl":100000,"type":"zset","value":[{"member":"m","score":"1"}]}]}"#;
+        assert_eq!(other.import_json(json.as_bytes(), false).unwrap(), 1);
+        assert!(other.export_json(&[b"z".to_vec()]).contains(r#""ttl":99"#));
+    }
+}
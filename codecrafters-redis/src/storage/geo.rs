@@ -0,0 +1,193 @@
+//! Geospatial index on sorted sets, used by GEOADD, GEOPOS, GEODIST and GEOSEARCH.
+//!
+//! Like redis, a location is a member of sorted set scored by the 52 bits geohash of
+//! its coordinates: 26 bits of latitude in even positions interleaved with 26 bits of
+//! longitude in odd positions. The score is exact in `f64`, and decodes to the center
+//! of the cell containing the location.
+
+/// Bits per coordinate.
+const STEP: u32 = 26;
+
+const LON_MIN: f64 = -180.0;
+const LON_MAX: f64 = 180.0;
+
+/// Limits of latitude in the Web Mercator projection, same as redis.
+const LAT_MIN: f64 = -85.05112878;
+const LAT_MAX: f64 = 85.05112878;
+
+/// Earth radius in meters used by redis.
+const EARTH_RADIUS: f64 = 6372797.560856;
+
+/// Unit of distances in GEO commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeoUnit {
+    Meter,
+    Kilometer,
+    Mile,
+    Foot,
+}
+
+impl GeoUnit {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "m" => Some(Self::Meter),
+            "km" => Some(Self::Kilometer),
+            "mi" => Some(Self::Mile),
+            "ft" => Some(Self::Foot),
+            _ => None,
+        }
+    }
+
+    /// Meters in one unit.
+    pub fn meters(self) -> f64 {
+        match self {
+            Self::Meter => 1.0,
+            Self::Kilometer => 1000.0,
+            Self::Mile => 1609.34,
+            Self::Foot => 0.3048,
+        }
+    }
+}
+
+/// Where a search starts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoCenter {
+    /// Location of an existing member.
+    Member(String),
+
+    /// Longitude and latitude.
+    LonLat(f64, f64),
+}
+
+/// Area of a search, in meters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeoShape {
+    Radius(f64),
+
+    /// Width and height of a box aligned with the meridians.
+    Box(f64, f64),
+}
+
+/// A member found by a search.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoMatch {
+    pub member: String,
+
+    /// Distance to the center in meters.
+    pub distance: f64,
+    pub hash: u64,
+    pub lon: f64,
+    pub lat: f64,
+}
+
+/// Check whether the coordinates can be indexed.
+pub(crate) fn is_valid(lon: f64, lat: f64) -> bool {
+    (LON_MIN..=LON_MAX).contains(&lon) && (LAT_MIN..=LAT_MAX).contains(&lat)
+}
+
+/// Spread the low 32 bits of `x` to even positions.
+fn spread(x: u64) -> u64 {
+    let mut x = x & 0xffff_ffff;
+    x = (x | (x << 16)) & 0x0000_ffff_0000_ffff;
+    x = (x | (x << 8)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+
+/// Reverse of `spread`, collect bits in even positions.
+fn squash(x: u64) -> u64 {
+    let mut x = x & 0x5555_5555_5555_5555;
+    x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    x = (x | (x >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x >> 4)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x >> 8)) & 0x0000_ffff_0000_ffff;
+    (x | (x >> 16)) & 0xffff_ffff
+}
+
+/// Geohash of valid coordinates, see `is_valid`.
+pub(super) fn encode(lon: f64, lat: f64) -> u64 {
+    let cells = (1u64 << STEP) as f64;
+    let lat_offset = ((lat - LAT_MIN) / (LAT_MAX - LAT_MIN) * cells) as u64;
+    let lon_offset = ((lon - LON_MIN) / (LON_MAX - LON_MIN) * cells) as u64;
+    // The max coordinates fall in the last cell.
+    let max = (1 << STEP) - 1;
+    spread(lat_offset.min(max)) | (spread(lon_offset.min(max)) << 1)
+}
+
+/// Coordinates of the center of the cell `hash` represents.
+pub(super) fn decode(hash: u64) -> (f64, f64) {
+    let cells = (1u64 << STEP) as f64;
+    let center = |offset: u64, min: f64, max: f64| {
+        let low = min + offset as f64 / cells * (max - min);
+        let high = min + (offset + 1) as f64 / cells * (max - min);
+        ((low + high) / 2.0).clamp(min, max)
+    };
+    (
+        center(squash(hash >> 1), LON_MIN, LON_MAX),
+        center(squash(hash), LAT_MIN, LAT_MAX),
+    )
+}
+
+/// Great-circle distance in meters, with the haversine formula.
+pub(super) fn distance((lon1, lat1): (f64, f64), (lon2, lat2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2.to_radians() - lon1.to_radians()) / 2.0).sin();
+    let a = u * u + lat1.cos() * lat2.cos() * v * v;
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+/// Distance of `point` to `center` if inside `shape`.
+pub(super) fn distance_in_shape(
+    center: (f64, f64),
+    shape: GeoShape,
+    point: (f64, f64),
+) -> Option<f64> {
+    let d = distance(center, point);
+    match shape {
+        GeoShape::Radius(radius) => (d <= radius).then_some(d),
+        GeoShape::Box(width, height) => {
+            // Measure along the meridian of the point, then along its parallel.
+            let lat_distance = EARTH_RADIUS * (point.1.to_radians() - center.1.to_radians()).abs();
+            let lon_distance = distance((center.0, point.1), point);
+            (lat_distance <= height / 2.0 && lon_distance <= width / 2.0).then_some(d)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_geohash() {
+        // Scores and decoded coordinates of redis.
+        let palermo = encode(13.361389, 38.115556);
+        assert_eq!(palermo, 3479099956230698);
+        let (lon, lat) = decode(palermo);
+        assert!((lon - 13.361389338970184).abs() < 1e-9);
+        assert!((lat - 38.1155563954963).abs() < 1e-9);
+        let catania = encode(15.087269, 37.502669);
+        assert_eq!(catania, 3479447370796909);
+
+        let d = distance(decode(palermo), decode(catania));
+        assert!((d - 166274.1516).abs() < 1e-3, "distance {d}");
+
+        assert!(!is_valid(181.0, 0.0));
+        assert!(!is_valid(0.0, 86.0));
+        assert_eq!(decode(encode(LON_MAX, LAT_MAX)).0.round(), 180.0);
+
+        let center = (15.0, 37.0);
+        assert!(distance_in_shape(center, GeoShape::Radius(200_000.0), decode(catania)).is_some());
+        assert!(distance_in_shape(center, GeoShape::Radius(100_000.0), decode(palermo)).is_none());
+        assert!(
+            distance_in_shape(center, GeoShape::Box(400_000.0, 400_000.0), decode(palermo))
+                .is_some()
+        );
+        assert!(
+            distance_in_shape(center, GeoShape::Box(100_000.0, 400_000.0), decode(palermo))
+                .is_none()
+        );
+    }
+}
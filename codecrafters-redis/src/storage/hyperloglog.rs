@@ -0,0 +1,275 @@
+//! HyperLogLog stored in string values, used by PFADD, PFCOUNT and PFMERGE.
+//!
+//! The layout follows redis so that values can be exchanged with it:
+//!
+//! ```text
+//! +------+---+-----+----------+-------------------------------+
+//! | HYLL | E | N/U | Cardin.  | 16384 registers of 6 bits ... |
+//! +------+---+-----+----------+-------------------------------+
+//! ```
+//!
+//! * `E` is the encoding, 0 for dense and 1 for sparse. Values are always written
+//!   dense, sparse ones written by redis are readable.
+//! * `Cardin.` is the cached cardinality in little endian, invalid if the most
+//!   significant bit is set. It is always written invalid.
+//! * Registers are packed from the least significant bit of each byte.
+
+/// Count of bits of hash used to select the register.
+const P: u32 = 14;
+
+/// Count of registers.
+const REGISTERS: usize = 1 << P;
+
+/// Count of bits of hash used to count the leading pattern.
+const Q: u32 = 64 - P;
+
+/// Bits per register in dense encoding.
+const BITS: usize = 6;
+
+const HEADER_SIZE: usize = 16;
+
+const DENSE_SIZE: usize = HEADER_SIZE + (REGISTERS * BITS).div_ceil(8);
+
+const MAGIC: &[u8] = b"HYLL";
+
+const ENCODING_DENSE: u8 = 0;
+
+const ENCODING_SPARSE: u8 = 1;
+
+/// Seed of MurmurHash64A, same as redis.
+const HASH_SEED: u64 = 0xadc83b19;
+
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+/// MurmurHash64A by Austin Appleby, as redis uses.
+fn murmur_hash64a(data: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (data.len() as u64).wrapping_mul(M);
+    let chunks = data.chunks_exact(8);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    if !tail.is_empty() {
+        for (i, b) in tail.iter().enumerate() {
+            h ^= (*b as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+/// Register index of `element`, and the length of the pattern `000..1` in its hash.
+fn pattern(element: &[u8]) -> (usize, u8) {
+    let hash = murmur_hash64a(element, HASH_SEED);
+    let index = (hash & (REGISTERS as u64 - 1)) as usize;
+    // Set the bit after the used ones, so the count is at most `Q + 1`.
+    let hash = (hash >> P) | (1 << Q);
+    (index, hash.trailing_zeros() as u8 + 1)
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct HyperLogLog {
+    /// One register per byte, packed only when encoded.
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub(super) fn new() -> Self {
+        Self {
+            registers: vec![0; REGISTERS],
+        }
+    }
+
+    /// Decode from the content of a string value.
+    ///
+    /// Return `None` if `bytes` is not a valid HyperLogLog.
+    pub(super) fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_SIZE || &bytes[..4] != MAGIC {
+            return None;
+        }
+        let data = &bytes[HEADER_SIZE..];
+        match bytes[4] {
+            ENCODING_DENSE if bytes.len() == DENSE_SIZE => {
+                let registers = (0..REGISTERS)
+                    .map(|i| {
+                        let offset = i * BITS;
+                        let (byte, bit) = (offset / 8, offset % 8);
+                        let lo = data[byte] as u16;
+                        let hi = data.get(byte + 1).copied().unwrap_or(0) as u16;
+                        (((lo | hi << 8) >> bit) & 0x3f) as u8
+                    })
+                    .collect();
+                Some(Self { registers })
+            }
+            ENCODING_SPARSE => Self::from_sparse(data),
+            _ => None,
+        }
+    }
+
+    /// Decode the opcodes of sparse encoding:
+    ///
+    /// * `00xxxxxx`: `xxxxxx + 1` registers set to 0.
+    /// * `01xxxxxx yyyyyyyy`: `xxxxxxyyyyyyyy + 1` registers set to 0.
+    /// * `1vvvvvxx`: `xx + 1` registers set to `vvvvv + 1`.
+    fn from_sparse(mut data: &[u8]) -> Option<Self> {
+        let mut registers = Vec::with_capacity(REGISTERS);
+        while let Some((&op, rest)) = data.split_first() {
+            data = rest;
+            let (len, value) = match op >> 6 {
+                0b00 => ((op & 0x3f) as usize + 1, 0),
+                0b01 => {
+                    let (&low, rest) = data.split_first()?;
+                    data = rest;
+                    ((((op & 0x3f) as usize) << 8 | low as usize) + 1, 0)
+                }
+                _ => ((op & 0x03) as usize + 1, ((op >> 2) & 0x1f) + 1),
+            };
+            if registers.len() + len > REGISTERS {
+                return None;
+            }
+            registers.resize(registers.len() + len, value);
+        }
+        (registers.len() == REGISTERS).then_some(Self { registers })
+    }
+
+    /// Encode in dense format, with an invalid cached cardinality.
+    pub(super) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; DENSE_SIZE];
+        bytes[..4].copy_from_slice(MAGIC);
+        bytes[4] = ENCODING_DENSE;
+        bytes[15] = 0x80;
+        let data = &mut bytes[HEADER_SIZE..];
+        for (i, value) in self.registers.iter().enumerate() {
+            let offset = i * BITS;
+            let (byte, bit) = (offset / 8, offset % 8);
+            let v = (*value as u16) << bit;
+            data[byte] |= v as u8;
+            if let Some(b) = data.get_mut(byte + 1) {
+                *b |= (v >> 8) as u8;
+            }
+        }
+        bytes
+    }
+
+    /// Add `element`, return true if any register changed.
+    pub(super) fn add(&mut self, element: &[u8]) -> bool {
+        let (index, count) = pattern(element);
+        if self.registers[index] < count {
+            self.registers[index] = count;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Merge `other` into self, so self estimates the union of both.
+    pub(super) fn merge(&mut self, other: &Self) {
+        for (r, o) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *r = (*r).max(*o);
+        }
+    }
+
+    /// Estimate the cardinality, with the improved estimator by Otmar Ertl as redis does.
+    pub(super) fn count(&self) -> u64 {
+        let mut histogram = [0u32; Q as usize + 2];
+        for r in self.registers.iter() {
+            histogram[*r as usize] += 1;
+        }
+        let m = REGISTERS as f64;
+        let mut z = m * tau((m - histogram[Q as usize + 1] as f64) / m);
+        for j in (1..=Q as usize).rev() {
+            z += histogram[j] as f64;
+            z *= 0.5;
+        }
+        z += m * sigma(histogram[0] as f64 / m);
+        (ALPHA_INF * m * m / z).round() as u64
+    }
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let prev = z;
+        z += x * y;
+        y += y;
+        if prev == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let prev = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if prev == z {
+            return z / 3.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hyperloglog() {
+        let mut hll = HyperLogLog::new();
+        assert_eq!(hll.count(), 0);
+        assert!(hll.add(b"a"));
+        assert!(!hll.add(b"a"));
+        assert_eq!(hll.count(), 1);
+
+        for i in 0..10000 {
+            hll.add(format!("e{i}").as_bytes());
+        }
+        let count = hll.count();
+        assert!((9800..=10200).contains(&count), "count {count}");
+
+        let bytes = hll.to_bytes();
+        assert_eq!(bytes.len(), 12304);
+        let decoded = HyperLogLog::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.registers, hll.registers);
+        assert!(HyperLogLog::from_bytes(b"HYLL").is_none());
+
+        let mut other = HyperLogLog::new();
+        for i in 5000..15000 {
+            other.add(format!("e{i}").as_bytes());
+        }
+        other.merge(&hll);
+        let count = other.count();
+        assert!((14700..=15300).contains(&count), "count {count}");
+
+        // An empty HyperLogLog in sparse encoding, 16384 zero registers.
+        let mut sparse = b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        sparse.extend([0x7f, 0xff]);
+        assert_eq!(HyperLogLog::from_bytes(&sparse).unwrap().count(), 0);
+        // Register 2 set to 3.
+        let mut sparse = b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        sparse.extend([0x01, 0x88, 0x7f, 0xfc]);
+        assert_eq!(HyperLogLog::from_bytes(&sparse).unwrap().registers[2], 3);
+    }
+}
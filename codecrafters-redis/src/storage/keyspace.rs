@@ -0,0 +1,188 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex, MutexGuard,
+};
+
+use crate::{cluster::key_slot, storage::StorageInner};
+
+/// Count of shards in [`Keyspace`].
+const SHARDS: usize = 16;
+
+/// All keys in storage, split into shards by hash slot, each behind its own lock.
+///
+/// Commands on keys in different shards do not wait for each other. Keys sharing a
+/// hash tag are in the same shard, like in cluster mode.
+///
+/// When locking multiple shards, they are always locked in the order of their index,
+/// so commands on multiple keys never deadlock each other. Blocked list tasks are
+/// locked before the shards, as feeding BLMOVE pushes to lists in other shards. Other
+/// blocked tasks only read the key fed, and are locked after its shard.
+pub(super) struct Keyspace {
+    shards: Box<[Mutex<StorageInner>]>,
+
+    /// Index of the shard [`Keyspace::lock_next_expire`] locks next.
+    next_expire: AtomicUsize,
+}
+
+impl Keyspace {
+    pub(super) fn new() -> Self {
+        Self {
+            shards: (0..SHARDS)
+                .map(|_| Mutex::new(StorageInner::default()))
+                .collect(),
+            next_expire: AtomicUsize::new(0),
+        }
+    }
+
+    /// Count of shards.
+    pub(super) fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Index of the shard holding `key`.
+    fn shard_index(key: &[u8]) -> usize {
+        key_slot(key) as usize % SHARDS
+    }
+
+    /// Lock the shard holding `key`.
+    pub(super) fn lock(&self, key: &[u8]) -> MutexGuard<'_, StorageInner> {
+        self.shards[Self::shard_index(key)].lock().unwrap()
+    }
+
+    /// Lock the shards holding any of `keys`.
+    pub(super) fn lock_keys<'a>(&self, keys: impl IntoIterator<Item = &'a [u8]>) -> Shards<'_> {
+        let mut indexes = keys.into_iter().map(Self::shard_index).collect::<Vec<_>>();
+        indexes.sort_unstable();
+        indexes.dedup();
+        Shards {
+            guards: indexes
+                .into_iter()
+                .map(|index| (index, self.shards[index].lock().unwrap()))
+                .collect(),
+        }
+    }
+
+    /// Lock the next shard to remove expired keys from, each shard in turn.
+    pub(super) fn lock_next_expire(&self) -> MutexGuard<'_, StorageInner> {
+        let index = self.next_expire.fetch_add(1, Ordering::Relaxed) % self.shards.len();
+        self.shards[index].lock().unwrap()
+    }
+
+    /// Lock all shards.
+    pub(super) fn lock_all(&self) -> Shards<'_> {
+        Shards {
+            guards: self
+                .shards
+                .iter()
+                .enumerate()
+                .map(|(index, shard)| (index, shard.lock().unwrap()))
+                .collect(),
+        }
+    }
+}
+
+/// Shards locked together by [`Keyspace::lock_keys`] or [`Keyspace::lock_all`], in the
+/// order of index.
+pub(super) struct Shards<'a> {
+    guards: Vec<(usize, MutexGuard<'a, StorageInner>)>,
+}
+
+impl<'a> Shards<'a> {
+    /// Position of the shard holding `key` in `guards`.
+    ///
+    /// Panic if the shard is not locked, which is a bug of the caller.
+    fn position(&self, key: &[u8]) -> usize {
+        let index = Keyspace::shard_index(key);
+        self.guards
+            .binary_search_by_key(&index, |(x, _)| *x)
+            .unwrap_or_else(|_| {
+                panic!(
+                    "shard of key {:?} is not locked",
+                    String::from_utf8_lossy(key)
+                )
+            })
+    }
+
+    /// The shard holding `key`.
+    pub(super) fn get(&self, key: &[u8]) -> &StorageInner {
+        &self.guards[self.position(key)].1
+    }
+
+    /// The shard holding `key`, for modifying.
+    pub(super) fn get_mut(&mut self, key: &[u8]) -> &mut StorageInner {
+        let pos = self.position(key);
+        &mut self.guards[pos].1
+    }
+
+    /// All shards locked.
+    pub(super) fn iter(&self) -> impl Iterator<Item = &StorageInner> {
+        self.guards.iter().map(|(_, x)| &**x)
+    }
+
+    /// All shards locked, for modifying.
+    pub(super) fn iter_mut(&mut self) -> impl Iterator<Item = &mut StorageInner> + use<'_, 'a> {
+        self.guards.iter_mut().map(|(_, x)| &mut **x)
+    }
+
+    /// Copy keys in all shards locked into one.
+    ///
+    /// Values are shared instead of copied.
+    pub(super) fn merged(&self) -> StorageInner {
+        let mut merged = StorageInner::default();
+        for shard in self.iter() {
+            merged.data.extend(shard.data.clone());
+        }
+        merged
+    }
+
+    /// Move all keys in `other` to their shards, existing keys of the same name in any
+    /// type are replaced.
+    pub(super) fn insert_all(&mut self, other: StorageInner) {
+        for (key, cell) in other.data {
+            self.get_mut(&key).insert_cell(key, cell);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::storage::{Object, ValueCell};
+
+    fn cell(value: &str) -> ValueCell {
+        ValueCell {
+            value: Arc::new(Object::Str(value.as_bytes().to_vec())),
+            expiration: None,
+        }
+    }
+
+    #[test]
+    fn test_shards() {
+        let keyspace = Keyspace::new();
+        let mut other = StorageInner::default();
+        for key in ["a", "b", "{a}b", "c"] {
+            other.data.insert(key.into(), cell(key));
+        }
+        keyspace.lock_all().insert_all(other);
+
+        // Keys sharing a hash tag are in the same shard.
+        let [a, b, ab, c] = ["a", "b", "{a}b", "c"].map(str::as_bytes);
+        assert!(keyspace.lock(a).data.contains_key(ab));
+        let shards = keyspace.lock_keys([a, ab]);
+        assert_eq!(shards.iter().count(), 1);
+        assert!(shards.get(ab).data.contains_key(ab));
+        drop(shards);
+
+        let shards = keyspace.lock_keys([c, b, a, b]);
+        assert!(shards.get(b).data.contains_key(b));
+        assert!(shards.get(c).data.contains_key(c));
+        drop(shards);
+
+        let merged = keyspace.lock_all().merged();
+        let mut keys = merged.data.keys().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, [a, b, c, ab]);
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::{vec_deque, VecDeque};
+
+/// A list saved in storage.
+///
+/// Elements are pushed and popped at both ends in constant time. Converted to an
+/// array only when replying to clients.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct List {
+    elements: VecDeque<Vec<u8>>,
+}
+
+impl FromIterator<Vec<u8>> for List {
+    fn from_iter<T: IntoIterator<Item = Vec<u8>>>(iter: T) -> Self {
+        Self {
+            elements: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl List {
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// Push `value` to the tail if `tail` is true, otherwise to the head.
+    pub fn push(&mut self, value: Vec<u8>, tail: bool) {
+        if tail {
+            self.elements.push_back(value);
+        } else {
+            self.elements.push_front(value);
+        }
+    }
+
+    /// Pop from the tail if `tail` is true, otherwise from the head.
+    pub fn pop(&mut self, tail: bool) -> Option<Vec<u8>> {
+        if tail {
+            self.elements.pop_back()
+        } else {
+            self.elements.pop_front()
+        }
+    }
+
+    pub fn get(&self, pos: usize) -> Option<&Vec<u8>> {
+        self.elements.get(pos)
+    }
+
+    /// Replace the element at `pos`, which must be in range.
+    pub fn set(&mut self, pos: usize, value: Vec<u8>) {
+        self.elements[pos] = value;
+    }
+
+    /// Insert `value` at `pos`, shifting the elements after.
+    pub fn insert(&mut self, pos: usize, value: Vec<u8>) {
+        self.elements.insert(pos, value);
+    }
+
+    pub fn iter(&self) -> vec_deque::Iter<'_, Vec<u8>> {
+        self.elements.iter()
+    }
+
+    /// Elements in positions `range`, which must be in range.
+    pub fn range(&self, range: std::ops::Range<usize>) -> vec_deque::Iter<'_, Vec<u8>> {
+        self.elements.range(range)
+    }
+
+    /// Only keep the elements in positions `range`.
+    pub fn keep_range(&mut self, range: std::ops::Range<usize>) {
+        self.elements.truncate(range.end);
+        self.elements.drain(..range.start.min(range.end));
+    }
+
+    /// Remove elements at `positions`, which are in ascending order.
+    pub fn remove_positions(&mut self, positions: &[usize]) {
+        let mut positions = positions.iter().peekable();
+        let mut pos = 0;
+        self.elements.retain(|_| {
+            let removed = positions.next_if_eq(&&pos).is_some();
+            pos += 1;
+            !removed
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn list(values: &[&str]) -> List {
+        values.iter().map(|x| x.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn test_list() {
+        let mut l = list(&["b"]);
+        l.push(list(&["c"]).pop(true).unwrap(), true);
+        l.push(list(&["a"]).pop(false).unwrap(), false);
+        assert_eq!(l, list(&["a", "b", "c"]));
+        assert_eq!(l.pop(true), list(&["c"]).pop(true));
+        assert_eq!(l.len(), 2);
+
+        let mut l = list(&["a", "b", "c", "d", "e"]);
+        l.remove_positions(&[0, 2, 3]);
+        assert_eq!(l, list(&["b", "e"]));
+
+        let mut l = list(&["a", "b", "c", "d", "e"]);
+        l.keep_range(1..3);
+        assert_eq!(l, list(&["b", "c"]));
+        l.keep_range(0..0);
+        assert!(l.is_empty());
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde_redis::Value;
+
+use crate::storage::Object;
+
+/// Estimate the memory used by `value`, in bytes.
+///
+/// This is not the accurate size in memory, only for comparing between keys.
+pub(super) fn estimate_value_size(value: &Value) -> usize {
+    match value {
+        Value::SimpleString(s) => s.value().len(),
+        Value::SimpleError(e) => e.message().len(),
+        Value::Integer(..) => 8,
+        Value::BulkString(s) => s.value().map(|x| x.len()).unwrap_or_default(),
+        Value::Array(arr) => {
+            if arr.is_null() {
+                0
+            } else {
+                arr.iter().map(estimate_value_size).sum::<usize>() + arr.len() * 8
+            }
+        }
+        // Never stored.
+        Value::Null(..) | Value::Map(..) | Value::Push(..) | Value::VerbatimString(..) => 0,
+    }
+}
+
+/// Estimate the memory used by `object`, see [`estimate_value_size`].
+pub(super) fn estimate_object_size(object: &Object) -> usize {
+    match object {
+        Object::Str(v) => v.len(),
+        Object::Int(..) => 8,
+        Object::List(list) => list.iter().map(Vec::len).sum::<usize>() + list.len() * 8,
+        Object::Stream(stream) => stream.estimate_size(),
+        Object::ZSet(zset) => zset.estimate_size(),
+        Object::Hash(hash) => hash.iter().map(|(k, v)| k.len() + v.len() + 8).sum(),
+        Object::Set(set) => set.iter().map(|x| x.len() + 8).sum(),
+    }
+}
+
+/// Statistics of keys in storage.
+///
+/// Updated every time a key is written, so that no scan is needed when reading
+/// the metrics.
+#[derive(Debug, Default)]
+pub(crate) struct StorageMetrics {
+    /// Type and estimated size of each key.
+    keys: HashMap<Vec<u8>, (&'static str, usize)>,
+
+    /// Count of keys in each type.
+    type_count: BTreeMap<&'static str, usize>,
+
+    /// All keys ordered by estimated size.
+    by_size: BTreeSet<(usize, Vec<u8>)>,
+
+    /// Sum of estimated size of all keys.
+    used: usize,
+}
+
+impl StorageMetrics {
+    /// Update the statistics of `key`.
+    ///
+    /// `stat` is the type and estimated size of the key, `None` if key is removed.
+    pub fn update(&mut self, key: &[u8], stat: Option<(&'static str, usize)>) {
+        if let Some((ty, size)) = self.keys.remove(key) {
+            self.by_size.remove(&(size, key.to_vec()));
+            self.used -= size;
+            if let Some(count) = self.type_count.get_mut(ty) {
+                *count -= 1;
+            }
+        }
+
+        if let Some((ty, size)) = stat {
+            self.keys.insert(key.to_vec(), (ty, size));
+            self.by_size.insert((size, key.to_vec()));
+            self.used += size;
+            *self.type_count.entry(ty).or_default() += 1;
+        }
+    }
+
+    /// Count of keys in type `ty`.
+    pub fn type_count(&self, ty: &str) -> usize {
+        self.type_count.get(ty).copied().unwrap_or_default()
+    }
+
+    /// Estimated memory used by all keys, in bytes.
+    pub fn used_memory(&self) -> usize {
+        self.used
+    }
+
+    /// Get at most `count` keys with the largest estimated size, largest first.
+    ///
+    /// Return the name, type and estimated size of each key.
+    pub fn biggest_keys(&self, count: usize) -> Vec<(Vec<u8>, &'static str, usize)> {
+        self.by_size
+            .iter()
+            .rev()
+            .take(count)
+            .map(|(size, key)| (key.clone(), self.keys[key].0, *size))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_storage_metrics() {
+        let mut metrics = StorageMetrics::default();
+        metrics.update(b"a", Some(("string", 10)));
+        metrics.update(b"b", Some(("list", 30)));
+        metrics.update(b"c", Some(("string", 20)));
+        assert_eq!(metrics.type_count("string"), 2);
+        assert_eq!(metrics.type_count("list"), 1);
+        assert_eq!(metrics.used_memory(), 60);
+        assert_eq!(
+            metrics.biggest_keys(2),
+            vec![(b"b".to_vec(), "list", 30), (b"c".to_vec(), "string", 20)]
+        );
+
+        // Key changed type and size.
+        metrics.update(b"b", Some(("string", 5)));
+        assert_eq!(metrics.type_count("list"), 0);
+        assert_eq!(metrics.type_count("string"), 3);
+
+        metrics.update(b"c", None);
+        assert_eq!(metrics.type_count("string"), 2);
+        assert_eq!(metrics.used_memory(), 15);
+        assert_eq!(
+            metrics.biggest_keys(5),
+            vec![(b"a".to_vec(), "string", 10), (b"b".to_vec(), "string", 5)]
+        );
+    }
+}
@@ -1,20 +1,66 @@
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    borrow::Cow,
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    ops::Bound,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use serde_redis::{Array, Integer, SimpleError, SimpleString, Value};
+use serde_redis::{Array, SimpleError, SimpleString, Value};
 use tokio::sync::oneshot;
 
+use crate::{
+    blocking::{feed_in_order, BlockedTasks, Fed, TaskGuard},
+    cluster::key_slot,
+    info::{BiggestKey, DbInfo, KeysizesInfo, KeyspaceInfo},
+    load::Budget,
+    log::log,
+};
+
+pub(crate) use aof::AofLog;
+use dump::Dump;
+use hyperloglog::HyperLogLog;
+use keyspace::{Keyspace, Shards};
+use list::List;
+use metrics::{estimate_object_size, StorageMetrics};
+pub(crate) use object::ObjectInfo;
+use object::{value_encoding, ObjectTable};
+use oom::OomInjection;
+pub(crate) use rdb::write_file as write_rdb;
+use sorted_set::SortedSet;
 use stream::Stream;
 
+mod aof;
+mod bitmap;
+mod digest;
+mod dump;
+mod geo;
+mod hyperloglog;
+mod keyspace;
+mod list;
+mod metrics;
+mod object;
+mod oom;
+mod rdb;
+mod sorted_set;
 mod stream;
 
-pub use stream::StreamId;
+pub use bitmap::BitUnit;
+pub(crate) use geo::is_valid as geo_is_valid;
+pub use geo::{GeoCenter, GeoMatch, GeoShape, GeoUnit};
+pub use object::{LfuConfig, MaxMemoryPolicy};
+pub use sorted_set::format_score;
+pub use stream::{
+    ClaimOptions, ClaimedRecord, GroupRecord, PendingEntry, RecordId, StreamId, StreamIdSpec,
+    TrimOptions, TrimStrategy,
+};
 
 pub(crate) type OpResult<T> = Result<T, OpError>;
 
+#[derive(Debug)]
 pub(crate) enum OpError {
     /// No such key in storage.
     KeyAbsent,
@@ -28,10 +74,69 @@ pub(crate) enum OpError {
     /// Stream id should be greater than "0-0".
     InvalidStreamId,
 
+    /// Stream id in command arguments is malformed or not allowed there.
+    MalformedStreamId,
+
+    /// Exclusive bound of stream range overflows, "start" or "end".
+    InvalidIntervalId(&'static str),
+
     /// Not a valid integer in storage, or the value is out of range.
     ///
     /// Similar to `TypeMismatch` but more specific to integer related process.
     InvalidInteger,
+
+    /// The score of sorted set member became NaN after operation.
+    NanScore,
+
+    /// String value will exceed the max allowed length after operation.
+    StringTooLong,
+
+    /// The key to modify in place is not present.
+    ///
+    /// Different from `KeyAbsent`, this error is returned to client as is.
+    NoSuchKey,
+
+    /// Index of element is out of range.
+    IndexOutOfRange,
+
+    /// Used memory exceeds the limit, writes are not allowed.
+    OutOfMemory,
+
+    /// The key to create already exists.
+    BusyKey,
+
+    /// The dump to import is invalid, with the reason.
+    InvalidDump(String),
+
+    /// The RDB file to load is invalid or unsupported, with the reason.
+    InvalidRdb(String),
+
+    /// The payload to restore is written by a newer version, or corrupted.
+    InvalidPayload,
+
+    /// The payload to restore has a valid checksum but can not be read.
+    BadDataFormat,
+
+    /// Consumer group to create already exists.
+    BusyGroup,
+
+    /// Stream `key` or its consumer group `group` to read not exists.
+    NoGroup { key: Vec<u8>, group: String },
+
+    /// Stream `key` or its consumer group `group` to inspect or claim records not exists.
+    NoSuchGroup { key: Vec<u8>, group: String },
+
+    /// Arguments of XSETID conflict with the stream, with the reason.
+    InvalidSetId(&'static str),
+
+    /// The string value is not a valid HyperLogLog.
+    InvalidHll,
+
+    /// The member to search from is not in the sorted set.
+    NoGeoMember,
+
+    /// The command ran longer than the command timeout, with the timeout.
+    Timeout(Duration),
 }
 
 impl OpError {
@@ -48,6 +153,13 @@ impl OpError {
             OpError::InvalidStreamId => {
                 SimpleError::with_prefix("ERR", "The ID specified in XADD must be greater than 0-0")
             }
+            OpError::MalformedStreamId => SimpleError::with_prefix(
+                "ERR",
+                "Invalid stream ID specified as stream command argument",
+            ),
+            OpError::InvalidIntervalId(bound) => {
+                SimpleError::with_prefix("ERR", format!("invalid {bound} ID for the interval"))
+            }
             OpError::TooSmallStreamId => SimpleError::with_prefix(
                 "ERR",
                 "The ID specified in XADD is equal or smaller than the target stream top item",
@@ -55,6 +167,64 @@ impl OpError {
             OpError::InvalidInteger => {
                 SimpleError::with_prefix("ERR", "value is not an integer or out of range")
             }
+            OpError::NanScore => {
+                SimpleError::with_prefix("ERR", "resulting score is not a number (NaN)")
+            }
+            OpError::StringTooLong => SimpleError::with_prefix(
+                "ERR",
+                "string exceeds maximum allowed size (proto-max-bulk-len)",
+            ),
+            OpError::NoSuchKey => SimpleError::with_prefix("ERR", "no such key"),
+            OpError::IndexOutOfRange => SimpleError::with_prefix("ERR", "index out of range"),
+            OpError::BusyKey => {
+                SimpleError::with_prefix("BUSYKEY", "Target key name already exists.")
+            }
+            OpError::InvalidDump(reason) => {
+                SimpleError::with_prefix("ERR", format!("invalid dump: {reason}"))
+            }
+            OpError::InvalidRdb(reason) => {
+                SimpleError::with_prefix("ERR", format!("invalid RDB: {reason}"))
+            }
+            OpError::InvalidPayload => {
+                SimpleError::with_prefix("ERR", "DUMP payload version or checksum are wrong")
+            }
+            OpError::BadDataFormat => SimpleError::with_prefix("ERR", "Bad data format"),
+            OpError::BusyGroup => {
+                SimpleError::with_prefix("BUSYGROUP", "Consumer Group name already exists")
+            }
+            OpError::NoGroup { key, group } => SimpleError::with_prefix(
+                "NOGROUP",
+                format!(
+                    "No such key '{}' or consumer group '{group}' in XREADGROUP with GROUP option",
+                    String::from_utf8_lossy(&key)
+                ),
+            ),
+            OpError::NoSuchGroup { key, group } => SimpleError::with_prefix(
+                "NOGROUP",
+                format!(
+                    "No such key '{}' or consumer group '{group}'",
+                    String::from_utf8_lossy(&key)
+                ),
+            ),
+            OpError::InvalidSetId(reason) => SimpleError::with_prefix("ERR", reason),
+            OpError::InvalidHll => SimpleError::with_prefix(
+                "WRONGTYPE",
+                "Key is not a valid HyperLogLog string value.",
+            ),
+            OpError::Timeout(timeout) => SimpleError::with_prefix(
+                "TIMEOUT",
+                format!(
+                    "command exceeded the max execution time of {} ms",
+                    timeout.as_millis()
+                ),
+            ),
+            OpError::NoGeoMember => {
+                SimpleError::with_prefix("ERR", "could not decode requested zset member")
+            }
+            OpError::OutOfMemory => SimpleError::with_prefix(
+                "OOM",
+                "command not allowed when used memory > 'maxmemory'.",
+            ),
         };
 
         Value::SimpleError(e)
@@ -63,7 +233,7 @@ impl OpError {
 
 enum LiveValue {
     /// Value exists and is alive.
-    Live(Value),
+    Live(Arc<Object>),
 
     /// Value exists but is expired.
     Expired,
@@ -74,16 +244,74 @@ enum LiveValue {
 
 enum LiveValueRef<'a> {
     /// Value exists and is alive.
-    Live(&'a mut Value),
+    Live(&'a mut Object),
 
     /// Value exists but is expired.
     Expired,
 }
 
+/// A value saved in [`ValueCell`].
+///
+/// Only converted from and to RESP values when received from and replied to clients.
+#[derive(Debug, Clone)]
+enum Object {
+    /// String.
+    Str(Vec<u8>),
+
+    /// String of exactly the decimal representation of an integer, see [`string_object`].
+    Int(i64),
+
+    /// List.
+    List(List),
+
+    /// Stream.
+    Stream(Stream),
+
+    /// Sorted set.
+    ZSet(SortedSet),
+
+    /// Hash of fields to values.
+    ///
+    /// No command writes it yet, only loaded from RDB files, RESTORE and IMPORT.
+    Hash(HashMap<Vec<u8>, Vec<u8>>),
+
+    /// Set of members, loaded like [`Object::Hash`].
+    Set(HashSet<Vec<u8>>),
+}
+
+impl Object {
+    /// Borrow the content of string, integers are formatted.
+    ///
+    /// Return `Err(OpError::TypeMismatch)` if not a string.
+    fn string(&self) -> OpResult<Cow<'_, [u8]>> {
+        match self {
+            Object::Str(v) => Ok(Cow::Borrowed(v)),
+            Object::Int(v) => Ok(Cow::Owned(v.to_string().into_bytes())),
+            _ => Err(OpError::TypeMismatch),
+        }
+    }
+
+    /// Type name, as TYPE command reports.
+    fn type_name(&self) -> &'static str {
+        match self {
+            Object::Str(..) | Object::Int(..) => "string",
+            Object::List(..) => "list",
+            Object::Stream(..) => "stream",
+            Object::ZSet(..) => "zset",
+            Object::Hash(..) => "hash",
+            Object::Set(..) => "set",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct ValueCell {
     /// Value content.
-    value: Value,
+    ///
+    /// Shared so that reads on a large value can take it and scan after releasing the
+    /// storage lock, keeping other keys available meanwhile. Writes copy the value if
+    /// such a read is still in progress, see `live_value_mut`.
+    value: Arc<Object>,
 
     /// When will the value expire.
     expiration: Option<SystemTime>,
@@ -104,31 +332,252 @@ impl ValueCell {
         }
     }
 
+    /// Borrow the value if it is alive, return `None` if expired.
+    fn live_value_ref(&self) -> Option<&Object> {
+        match self.expiration {
+            Some(d) if d <= SystemTime::now() => None,
+            _ => Some(self.value.as_ref()),
+        }
+    }
+
     fn live_value_mut(&mut self) -> LiveValueRef<'_> {
         match self.expiration {
             Some(d) => {
                 if d > SystemTime::now() {
-                    LiveValueRef::Live(&mut self.value)
+                    LiveValueRef::Live(Arc::make_mut(&mut self.value))
                 } else {
                     // Expired.
                     LiveValueRef::Expired
                 }
             }
-            None => LiveValueRef::Live(&mut self.value),
+            None => LiveValueRef::Live(Arc::make_mut(&mut self.value)),
         }
     }
 }
 
-pub(crate) struct LpopBlockedTask {
-    key: String,
-    sender: oneshot::Sender<Value>,
+/// Expiration option when setting a value.
+#[derive(Clone, Copy)]
+pub(crate) enum SetExpire {
+    /// Never expire, drop any existing expiration.
+    Never,
+
+    /// Expire at the specified time.
+    At(SystemTime),
+
+    /// Keep the expiration of the existing value.
+    Keep,
+}
+
+/// Condition to satisfy when setting a value.
+#[derive(Clone, Copy)]
+pub(crate) enum SetCondition {
+    /// Always set.
+    Always,
+
+    /// Only set when key not present.
+    NotExists,
+
+    /// Only set when key present.
+    Exists,
+}
+
+/// Convert list index `index`, which may be negative, into the position in
+/// list of length `len`.
+///
+/// Return `None` if out of range.
+fn list_position(len: usize, index: i64) -> Option<usize> {
+    let pos = if index < 0 { len as i64 + index } else { index };
+    if pos < 0 || pos >= len as i64 {
+        None
+    } else {
+        Some(pos as usize)
+    }
+}
+
+/// Pop at most `count` elements from `list`, in the order popped.
+fn list_pop(list: &mut List, tail: bool, count: usize) -> Vec<Vec<u8>> {
+    (0..count).map_while(|_| list.pop(tail)).collect()
+}
+
+/// Max length of string values, same as the default `proto-max-bulk-len` in redis.
+const MAX_STRING_LENGTH: usize = 512 * 1024 * 1024;
+
+/// Count of keys saved under one lock when loading the dataset at startup.
+const LOAD_BATCH: usize = 1024;
+
+/// Count of keys with expiration sampled in each shard by one cycle of active
+/// expiration, same as redis.
+const EXPIRE_SAMPLES: usize = 20;
+
+/// Get the content of string `value` in RESP as bytes, e.g. a field of stream entry.
+///
+/// Integers are converted to their decimal representation.
+fn string_bytes(value: &Value) -> OpResult<Vec<u8>> {
+    match value {
+        Value::BulkString(s) => Ok(s.value().map(|x| x.to_vec()).unwrap_or_default()),
+        Value::SimpleString(s) => Ok(s.value().as_bytes().to_vec()),
+        Value::Integer(i) => Ok(i.value().to_string().into_bytes()),
+        _ => Err(OpError::TypeMismatch),
+    }
+}
+
+/// Build the object to save for string content `bytes`.
+///
+/// Save as integer if `bytes` is exactly the decimal representation of an integer,
+/// like redis does.
+fn string_object(bytes: Vec<u8>) -> Object {
+    match std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok().filter(|v| v.to_string() == s))
+    {
+        Some(v) => Object::Int(v),
+        None => Object::Str(bytes),
+    }
+}
+
+/// String value read by [`Storage::get`].
+///
+/// The value is shared with the storage till dropped.
+pub(crate) struct StoredString(Arc<Object>);
+
+impl StoredString {
+    /// Content of the string, integers are formatted.
+    pub fn bytes(&self) -> Cow<'_, [u8]> {
+        self.0.string().unwrap_or_default()
+    }
+}
+
+/// Elements in a range of list, read by [`Storage::lrange`].
+///
+/// The list is shared with the storage till dropped.
+pub(crate) struct ListRange {
+    list: Option<Arc<Object>>,
+    range: std::ops::Range<usize>,
+}
+
+impl ListRange {
+    pub fn elements(&self) -> impl ExactSizeIterator<Item = &[u8]> {
+        let elements = match self.list.as_deref() {
+            Some(Object::List(list)) => list.range(self.range.clone()),
+            _ => Default::default(),
+        };
+        elements.map(Vec::as_slice)
+    }
+}
+
+/// Key of a list and the elements popped from it.
+pub(crate) type ListPopped = (Vec<u8>, Vec<Vec<u8>>);
+
+/// Key of the list feeding a [`ListBlockedTask`] and the popped elements.
+type ListFed = (Vec<u8>, OpResult<Vec<Vec<u8>>>);
+
+/// Receiver of [`ListBlockedTask`], see [`ListBlockedTask::sender`].
+pub(crate) type ListReceiver = oneshot::Receiver<ListFed>;
+
+/// A blocked BLPOP, BRPOP, BLMOVE or BLMPOP task.
+///
+/// Waiting for any of the lists specified by `keys` to have elements.
+pub(crate) struct ListBlockedTask {
+    keys: Vec<Vec<u8>>,
+
+    /// Pop from the tail of list if true, otherwise pop from the head.
+    tail: bool,
+
+    /// For BLMPOP, pop at most `count` elements and send them back in an array.
+    ///
+    /// Pop a single element if `None`.
+    count: Option<usize>,
+
+    /// For BLMOVE, the list to push the popped element to, and push to the tail
+    /// of it or not.
+    destination: Option<(Vec<u8>, bool)>,
+
+    /// Send back the key of list feeding the task and the popped elements, or the
+    /// error if failed to move them.
+    sender: oneshot::Sender<ListFed>,
+}
+
+impl ListBlockedTask {
+    pub fn new(key: Vec<u8>, tail: bool) -> (Self, ListReceiver) {
+        Self::new_multi(vec![key], tail, None)
+    }
+
+    /// Build a task that pops from the first list in `keys` having elements.
+    pub fn new_multi(keys: Vec<Vec<u8>>, tail: bool, count: Option<usize>) -> (Self, ListReceiver) {
+        let (sender, recver) = oneshot::channel();
+
+        let s = Self {
+            keys,
+            tail,
+            count,
+            destination: None,
+            sender,
+        };
+        (s, recver)
+    }
+
+    /// Build a task that moves the element popped from `key` to `destination`.
+    ///
+    /// The receiver gets the moved element, or the error if failed to move.
+    pub fn new_move(
+        key: Vec<u8>,
+        tail: bool,
+        destination: Vec<u8>,
+        to_tail: bool,
+    ) -> (Self, ListReceiver) {
+        let (mut s, recver) = Self::new(key, tail);
+        s.destination = Some((destination, to_tail));
+        (s, recver)
+    }
+}
+
+/// An element taken from a list by blocked tasks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ListFeed {
+    /// Popped from list `key` by BLPOP, BRPOP or BLMPOP.
+    ///
+    /// `count` is the count of elements popped by BLMPOP.
+    Pop {
+        key: Vec<u8>,
+        tail: bool,
+        count: Option<usize>,
+    },
+
+    /// Moved from list `source` to list `destination` by BLMOVE.
+    Move {
+        source: Vec<u8>,
+        destination: Vec<u8>,
+        from_tail: bool,
+        to_tail: bool,
+    },
+}
+
+/// A member popped from sorted set `key` by a blocked BZPOPMIN, or BZPOPMAX if `max`
+/// is true.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ZpopFeed {
+    pub(crate) key: Vec<u8>,
+    pub(crate) max: bool,
+}
+
+/// A blocked BZPOPMIN or BZPOPMAX task.
+///
+/// Waiting for any of the sorted sets specified by `keys` to have members.
+pub(crate) struct ZpopBlockedTask {
+    keys: Vec<Vec<u8>>,
+
+    /// Pop the member with highest score if true, otherwise pop the lowest.
+    max: bool,
+
+    /// Send back the key, member and score.
+    sender: oneshot::Sender<(Vec<u8>, String, f64)>,
 }
 
-impl LpopBlockedTask {
-    pub fn new(key: String) -> (Self, oneshot::Receiver<Value>) {
-        let (sender, recver) = oneshot::channel::<Value>();
+impl ZpopBlockedTask {
+    pub fn new(keys: Vec<Vec<u8>>, max: bool) -> (Self, oneshot::Receiver<(Vec<u8>, String, f64)>) {
+        let (sender, recver) = oneshot::channel();
 
-        let s = Self { key, sender };
+        let s = Self { keys, max, sender };
         (s, recver)
     }
 }
@@ -137,7 +586,7 @@ impl LpopBlockedTask {
 #[derive(Debug)]
 pub(crate) struct XreadBlockedTarget {
     /// Key of the string.
-    key: String,
+    key: Vec<u8>,
 
     start_time_id: u64,
 
@@ -148,7 +597,7 @@ pub(crate) struct XreadBlockedTarget {
 
 impl XreadBlockedTarget {
     /// Build a target that specified with entry id.
-    pub fn with_id(key: String, start_time_id: u64, start_seq_id: u64) -> Self {
+    pub fn with_id(key: Vec<u8>, start_time_id: u64, start_seq_id: u64) -> Self {
         Self {
             key,
             start_time_id,
@@ -158,7 +607,7 @@ impl XreadBlockedTarget {
     }
 
     /// Build a target that only excepting new entries.
-    pub fn with_new_entry(key: String) -> Self {
+    pub fn with_new_entry(key: Vec<u8>) -> Self {
         Self {
             key,
             start_time_id: 0,
@@ -183,13 +632,13 @@ pub(crate) struct XreadBlockedTask {
     /// The channel to send data back once any of the `targets` are feeded.
     ///
     /// Send back the target name and the corresponding value.
-    sender: oneshot::Sender<(Vec<String>, Value)>,
+    sender: oneshot::Sender<(Vec<Vec<u8>>, Value)>,
 }
 
 impl XreadBlockedTask {
     pub fn new(
         targets: Vec<XreadBlockedTarget>,
-        sender: oneshot::Sender<(Vec<String>, Value)>,
+        sender: oneshot::Sender<(Vec<Vec<u8>>, Value)>,
     ) -> Self {
         Self { targets, sender }
     }
@@ -200,16 +649,15 @@ impl XreadBlockedTask {
     /// Return the name of all those streams.
     fn extract_target_waiting_for_id(
         &mut self,
-        key: &str,
+        key: &[u8],
         start_time_id: u64,
         start_seq_id: u64,
-    ) -> Vec<String> {
+    ) -> Vec<Vec<u8>> {
         self.targets
             .extract_if(.., |task| {
                 !task.only_new_entry
                     && task.key == key
-                    && task.start_time_id <= start_time_id
-                    && task.start_seq_id <= start_seq_id
+                    && (task.start_time_id, task.start_seq_id) <= (start_time_id, start_seq_id)
             })
             .map(|x| x.key.clone())
             .collect::<Vec<_>>()
@@ -218,7 +666,7 @@ impl XreadBlockedTask {
     /// Find all streams in current task that only accept data saved in new entry.
     ///
     /// Return the name of all those streams.
-    fn extract_target_waiting_for_new_entry(&mut self, key: &str) -> Vec<String> {
+    fn extract_target_waiting_for_new_entry(&mut self, key: &[u8]) -> Vec<Vec<u8>> {
         self.targets
             .extract_if(.., |task| task.only_new_entry && task.key == key)
             .map(|x| x.key.clone())
@@ -226,390 +674,3122 @@ impl XreadBlockedTask {
     }
 }
 
+/// A blocked XREADGROUP task waiting for new records in any of the streams `keys`.
+///
+/// Fed when adding records so that records are delivered to the group in the order
+/// of blocked consumers.
+pub(crate) struct XreadGroupBlockedTask {
+    keys: Vec<Vec<u8>>,
+
+    group: String,
+
+    consumer: String,
+
+    count: Option<usize>,
+
+    noack: bool,
+
+    /// Send back the key of stream and records delivered.
+    sender: oneshot::Sender<(Vec<u8>, Vec<GroupRecord>)>,
+}
+
+impl XreadGroupBlockedTask {
+    pub fn new(
+        keys: Vec<Vec<u8>>,
+        group: String,
+        consumer: String,
+        count: Option<usize>,
+        noack: bool,
+    ) -> (Self, XreadGroupReceiver) {
+        let (sender, recver) = oneshot::channel();
+
+        let s = Self {
+            keys,
+            group,
+            consumer,
+            count,
+            noack,
+            sender,
+        };
+        (s, recver)
+    }
+}
+
+/// Receiver of [`XreadGroupBlockedTask`], gets the key of stream and records delivered.
+pub(crate) type XreadGroupReceiver = oneshot::Receiver<(Vec<u8>, Vec<GroupRecord>)>;
+
+/// Records delivered to a blocked XREADGROUP task when adding records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StreamGroupFeed {
+    pub key: Vec<u8>,
+    pub group: String,
+    pub consumer: String,
+    pub count: Option<usize>,
+    pub noack: bool,
+}
+
+/// Hook to observe changes in storage.
+///
+/// Register hooks with `ServerBuilder::storage_hook` when embedding the server.
+pub trait StorageHook: Send + Sync {
+    /// Called after the value specified by `key` is written.
+    fn on_write(&self, key: &[u8]);
+}
+
+/// Listener of keyspace events that concern the rest of the server, e.g. key
+/// tracking and the counters in INFO.
+///
+/// Unlike [`StorageHook`], a flush is reported once instead of per key.
+pub(crate) trait KeyspaceEvents: Send + Sync {
+    /// Called after the value of `key` is written.
+    fn written(&self, key: &[u8]);
+
+    /// Called when all keys are removed, `writes` is the count of changes it makes.
+    fn flushed(&self, writes: u64);
+
+    /// Called after an expired key is removed.
+    fn expired(&self);
+
+    /// Called after a command reads keys, `hits` of them present and `misses` not.
+    fn looked_up(&self, hits: u64, misses: u64);
+}
+
+/// Copy of all keys taken at one point, for saving the RDB file or rewriting the AOF.
+///
+/// Values are shared with the storage till the copy is dropped.
+pub(crate) struct Snapshot {
+    data: StorageInner,
+    used_memory: usize,
+}
+
+impl Snapshot {
+    /// Serialize all keys to a RDB file, see [`rdb`] for the supported types.
+    pub fn to_rdb(&self) -> Vec<u8> {
+        rdb::save(&self.data, self.used_memory)
+    }
+
+    /// Finish the rewrite of `aof` started by [`AofLog::start_rewrite`] with all keys.
+    pub fn write_aof(&self, aof: &AofLog) -> std::io::Result<()> {
+        aof.finish_rewrite(&aof::rewrite(&self.data))
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct Storage {
-    inner: Arc<Mutex<StorageInner>>,
-    lpop_blocked_task: Arc<Mutex<Vec<LpopBlockedTask>>>,
-    xread_blocked_task: Arc<Mutex<Vec<XreadBlockedTask>>>,
+    inner: Arc<Keyspace>,
+    list_blocked_task: BlockedTasks<ListBlockedTask>,
+    xread_blocked_task: BlockedTasks<XreadBlockedTask>,
+    xreadgroup_blocked_task: BlockedTasks<XreadGroupBlockedTask>,
+    zpop_blocked_task: BlockedTasks<ZpopBlockedTask>,
+    hooks: Arc<Vec<Arc<dyn StorageHook>>>,
+    metrics: Arc<Mutex<StorageMetrics>>,
+    objects: Arc<Mutex<ObjectTable>>,
+    oom: Arc<Mutex<OomInjection>>,
+
+    /// Listener of keyspace events, `None` if the storage is not served.
+    events: Option<Arc<dyn KeyspaceEvents>>,
+
+    /// Count of keys evicted by `maxmemory`.
+    evicted_keys: Arc<AtomicU64>,
+
+    /// Whether expired keys are removed in background, see [`Storage::expire_cycle`].
+    active_expire: Arc<AtomicBool>,
+
+    /// Writes made through this handle, see [`Storage::handle_writes`].
+    handle_writes: HandleWrites,
 }
 
-struct StorageInner {
-    data: HashMap<String, ValueCell>,
-    stream: HashMap<String, Stream>,
+/// Count of writes made through one [`Storage`] handle.
+///
+/// Every connection works on its own clone of storage, so a clone starts counting
+/// from zero instead of sharing the count.
+#[derive(Default)]
+struct HandleWrites(AtomicU64);
+
+impl Clone for HandleWrites {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
 }
 
-impl StorageInner {
-    fn get_next_seq_id(&self, key: impl AsRef<str>, time_id: u64) -> u64 {
-        self.stream
-            .get(key.as_ref())
-            .map_or_else(|| 0, |s| s.get_next_seq_id(time_id))
+impl HandleWrites {
+    fn record(&self, count: u64) {
+        self.0.fetch_add(count, Ordering::Relaxed);
     }
 }
 
-impl Storage {
-    pub fn new() -> Self {
-        Self {
-            inner: Arc::new(Mutex::new(StorageInner {
-                data: HashMap::new(),
-                stream: HashMap::new(),
-            })),
-            lpop_blocked_task: Arc::new(Mutex::new(vec![])),
-            xread_blocked_task: Arc::new(Mutex::new(vec![])),
-        }
-    }
+#[derive(Clone, Default)]
+struct StorageInner {
+    data: HashMap<Vec<u8>, ValueCell>,
+
+    /// Keys given an expiration, sampled by [`Storage::expire_cycle`].
+    ///
+    /// Keys removed or persisted are left here till sampled, so some of them may not
+    /// expire any more.
+    expires: BTreeSet<Vec<u8>>,
+
+    /// Last key in `expires` sampled, the next sample continues after it.
+    expire_cursor: Option<Vec<u8>>,
+}
 
-    /// Duration is the live duration till value expire.
-    pub fn insert(&self, key: String, value: Value, duration: Option<Duration>) {
-        let mut lock = self.inner.lock().unwrap();
-        let expiration = duration.map(|d| SystemTime::now().checked_add(d).unwrap());
-        let cell = ValueCell { value, expiration };
-        if lock.data.insert(key, cell).is_some() {
-            println!("[storage] override");
+impl StorageInner {
+    /// Insert `cell` as `key`, recording it in the expires index if it expires.
+    fn insert_cell(&mut self, key: Vec<u8>, cell: ValueCell) -> Option<ValueCell> {
+        if cell.expiration.is_some() {
+            self.expires.insert(key.clone());
         }
+        self.data.insert(key, cell)
     }
 
-    pub fn get(&self, key: &str) -> Option<Value> {
-        let mut lock = self.inner.lock().unwrap();
-        match lock
-            .data
-            .get(key)
-            .map(|c| c.live_value())
-            .unwrap_or_else(|| LiveValue::Absent)
-        {
-            LiveValue::Live(value) => Some(value),
-            LiveValue::Expired => {
-                // Value exists but expired, clean up.
-                lock.data.remove(key);
-                println!("[storage] get {key}: expired");
-                None
+    /// Remove keys expired at `now` among at most `samples` keys in the expires index,
+    /// continuing from where the last sample stopped, and stop after `limit` removed.
+    ///
+    /// Return the keys removed.
+    fn expire_sample(&mut self, samples: usize, limit: usize, now: SystemTime) -> Vec<Vec<u8>> {
+        let start = match &self.expire_cursor {
+            Some(cursor) => Bound::Excluded(cursor.as_slice()),
+            None => Bound::Unbounded,
+        };
+        let mut sampled = self
+            .expires
+            .range::<[u8], _>((start, Bound::Unbounded))
+            .take(samples)
+            .cloned()
+            .collect::<Vec<_>>();
+        // Start over next time once reaching the end.
+        self.expire_cursor = None;
+        let mut expired = vec![];
+        for key in sampled.iter() {
+            match self.data.get(key).and_then(|cell| cell.expiration) {
+                Some(t) if t <= now => {
+                    self.data.remove(key);
+                    self.expires.remove(key);
+                    expired.push(key.clone());
+                }
+                Some(_) => {}
+                None => {
+                    self.expires.remove(key);
+                }
             }
-            LiveValue::Absent => {
-                // No value related to key
-                None
+            if expired.len() >= limit {
+                self.expire_cursor = Some(key.clone());
+                return expired;
             }
         }
+        if sampled.len() == samples {
+            self.expire_cursor = sampled.pop();
+        }
+        expired
     }
 
-    /// Insert elements to the list specified by `key`.
-    ///
-    /// If key not present and `create` is true, create a new list.
-    ///
-    /// Set `prepend` to true if want to prepend `value` before the head of current element.
+    /// Get the list specified by `key`.
     ///
-    /// ## Returns
+    /// * `Ok(None)` if `key` not present or expired.
+    /// * `Err(OpError::TypeMismatch)` if the value is not a list.
+    fn list_mut(&mut self, key: &[u8]) -> OpResult<Option<&mut List>> {
+        match self.data.get_mut(key) {
+            Some(cell) => match cell.live_value_mut() {
+                LiveValueRef::Live(Object::List(list)) => Ok(Some(list)),
+                LiveValueRef::Live(..) => Err(OpError::TypeMismatch),
+                LiveValueRef::Expired => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Get the list specified by `key` for inspecting.
     ///
-    /// * `Some(v)` if saved successfully, return the current count of elements.
-    /// * `None` if list not exists and `create` is false, nothing performed in this situaion.
-    pub fn insert_list(
-        &self,
-        key: String,
-        mut value: Array,
-        create: bool,
-        prepend: bool,
-    ) -> OpResult<usize> {
-        let mut lock = self.inner.lock().unwrap();
-
-        // Count of elements that gave to BLPOP tasks.
-        // Elements are sent to those tasks first, then save in list.
-        // But we should return the orignal count of elements to the
-        // client gives us `value`, use this count to balance it.
-        let mut interupted_count = 0;
-        let mut lpop_lock = self.lpop_blocked_task.lock().unwrap();
-        loop {
-            if value.is_empty() {
-                break;
-            }
-            match lpop_lock.iter().position(|task| task.key == key) {
-                Some(pos) => {
-                    // Find a task waiting for current list.
-                    let v = value.pop_front().unwrap(); // Not empty for sure.
-                    let task_to_feed = lpop_lock.remove(pos);
-                    task_to_feed.sender.send(v).unwrap();
-                    interupted_count += 1;
-                }
-                None => {
-                    // No one in the blocked task queue is waiting for
-                    // current `key` list, break and go ahead.
-                    break;
-                }
-            }
+    /// * `Ok(None)` if `key` not present or expired.
+    /// * `Err(OpError::TypeMismatch)` if the value is not a list.
+    fn list_ref(&self, key: &[u8]) -> OpResult<Option<&List>> {
+        match self.data.get(key).and_then(|cell| cell.live_value_ref()) {
+            Some(Object::List(list)) => Ok(Some(list)),
+            Some(..) => Err(OpError::TypeMismatch),
+            None => Ok(None),
         }
+    }
 
-        match lock.data.get_mut(key.as_str()) {
-            Some(v) => {
-                if let Value::Array(arr) = &mut v.value {
-                    if prepend {
-                        arr.prepend(value);
-                    } else {
-                        arr.append(value);
-                    }
-                    Ok(arr.len() + interupted_count)
-                } else {
-                    Err(OpError::TypeMismatch)
-                }
-            }
+    /// Push `value` to the head or tail of list `key`, create the list if not present.
+    fn list_push(&mut self, key: &[u8], value: Vec<u8>, tail: bool) -> OpResult<()> {
+        match self.list_mut(key)? {
+            Some(list) => list.push(value, tail),
             None => {
-                if !create {
-                    return Err(OpError::KeyAbsent);
-                }
-
-                let count = value.len();
                 let cell = ValueCell {
-                    value: Value::Array(value),
+                    value: Arc::new(Object::List(List::from_iter([value]))),
                     expiration: None,
                 };
-
-                lock.data.insert(key, cell);
-                Ok(count + interupted_count)
+                self.data.insert(key.to_vec(), cell);
             }
         }
+        Ok(())
     }
 
-    pub fn lrange(&self, key: String, start: i32, end: i32) -> OpResult<Value> {
-        let lock = self.inner.lock().unwrap();
-        if let Some(ValueCell {
-            value: Value::Array(arr),
-            ..
-        }) = lock.data.get(key.as_str())
-        {
-            if arr.is_null_or_empty() {
-                return Ok(Value::Array(Array::new_empty()));
-            }
-
-            let start2 = if start >= 0 {
-                start as usize
-            } else {
-                let s = start.abs();
-                if arr.len() < (s as usize) {
-                    // [a, b, c] => start=-5, reset start to 0
-                    0
-                } else {
-                    arr.len() - (-1 * start) as usize
-                }
-            };
-
-            let end2 = if end >= 0 {
-                end as usize
-            } else {
-                arr.len() - (-1 * end) as usize
-            };
+    /// Put the `values` popped by [`list_pop`] back to list `key`.
+    fn list_unpop(&mut self, key: &[u8], values: Vec<Vec<u8>>, tail: bool) {
+        // The last popped element goes back first.
+        for value in values.into_iter().rev() {
+            self.list_push(key, value, tail).ok();
+        }
+    }
 
-            if end2 < start2 {
-                return Ok(Value::Array(Array::new_empty()));
-            }
+    /// Remove the list specified by `key` if it has no element left.
+    fn remove_empty_list(&mut self, key: &[u8]) {
+        if matches!(
+            self.data.get(key).map(|cell| cell.value.as_ref()),
+            Some(Object::List(list)) if list.is_empty()
+        ) {
+            self.data.remove(key);
+        }
+    }
 
-            let arr2 = arr
-                .iter()
-                .skip(start2)
-                .take(end2 - start2 + 1)
-                .map(|x| x.to_owned())
-                .collect::<Array>();
-            Ok(Value::Array(arr2))
-        } else {
-            Ok(Value::Array(Array::new_empty()))
+    /// Get the stream specified by `key` for inspecting.
+    ///
+    /// * `Err(OpError::NoSuchKey)` if `key` not present.
+    /// * `Err(OpError::TypeMismatch)` if `key` holds other type.
+    fn stream_ref(&self, key: &[u8]) -> OpResult<&Stream> {
+        match self.data.get(key).and_then(|cell| cell.live_value_ref()) {
+            Some(Object::Stream(s)) => Ok(s),
+            Some(..) => Err(OpError::TypeMismatch),
+            None => Err(OpError::NoSuchKey),
         }
     }
 
-    /// Get the count of elements in an array specified by `key`.
+    /// Decode the HyperLogLog specified by `key`.
     ///
-    /// * If `key` not present in storage, return `Err(OpError::KeyAbsent)`.
-    /// * If the value corresponded to `key` is not an array, return `Err(OpError::TypeMismatch)`.
-    pub fn array_get_length(&self, key: impl AsRef<str>) -> OpResult<usize> {
-        let lock = self.inner.lock().unwrap();
+    /// * `Ok(None)` if `key` not present.
+    /// * `Err(OpError::TypeMismatch)` if `key` holds other type.
+    /// * `Err(OpError::InvalidHll)` if the string is not a valid HyperLogLog.
+    fn hll_ref(&self, key: &[u8]) -> OpResult<Option<HyperLogLog>> {
+        match self.data.get(key).and_then(|cell| cell.live_value_ref()) {
+            Some(value) => HyperLogLog::from_bytes(&value.string()?)
+                .map(Some)
+                .ok_or(OpError::InvalidHll),
+            None => Ok(None),
+        }
+    }
 
-        if let Some(ValueCell { value, .. }) = lock.data.get(key.as_ref()) {
-            if let Value::Array(arr) = value {
-                Ok(arr.len())
+    /// Save `hll` as the string value of `key`, the expiration is kept if any.
+    fn hll_save(&mut self, key: &[u8], hll: &HyperLogLog) {
+        let value = Arc::new(string_object(hll.to_bytes()));
+        match self.data.get_mut(key) {
+            Some(cell) if cell.live_value_ref().is_some() => cell.value = value,
+            _ => {
+                self.data.insert(
+                    key.to_vec(),
+                    ValueCell {
+                        value,
+                        expiration: None,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Get the sorted set specified by `key` for inspecting.
+    ///
+    /// * `Ok(None)` if `key` not present or expired.
+    /// * `Err(OpError::TypeMismatch)` if `key` holds other type.
+    fn zset_ref(&self, key: &[u8]) -> OpResult<Option<&SortedSet>> {
+        match self.data.get(key).and_then(|cell| cell.live_value_ref()) {
+            Some(Object::ZSet(zset)) => Ok(Some(zset)),
+            Some(..) => Err(OpError::TypeMismatch),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the sorted set specified by `key` for modifying.
+    ///
+    /// * `Ok(None)` if `key` not present or expired.
+    /// * `Err(OpError::TypeMismatch)` if `key` holds other type.
+    fn zset_mut(&mut self, key: &[u8]) -> OpResult<Option<&mut SortedSet>> {
+        match self.data.get_mut(key) {
+            Some(cell) => match cell.live_value_mut() {
+                LiveValueRef::Live(Object::ZSet(zset)) => Ok(Some(zset)),
+                LiveValueRef::Live(..) => Err(OpError::TypeMismatch),
+                LiveValueRef::Expired => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Get the sorted set specified by `key` for adding members, create an empty one
+    /// if not present or expired.
+    ///
+    /// Call [`StorageInner::remove_empty_zset`] after modifying in case no member is
+    /// added.
+    fn zset_entry(&mut self, key: &[u8]) -> OpResult<&mut SortedSet> {
+        if self.zset_mut(key)?.is_none() {
+            let cell = ValueCell {
+                value: Arc::new(Object::ZSet(SortedSet::default())),
+                expiration: None,
+            };
+            self.data.insert(key.to_vec(), cell);
+        }
+        Ok(self.zset_mut(key)?.expect("sorted set inserted"))
+    }
+
+    /// Remove the sorted set specified by `key` if it has no member left.
+    fn remove_empty_zset(&mut self, key: &[u8]) {
+        if matches!(
+            self.data.get(key).map(|cell| cell.value.as_ref()),
+            Some(Object::ZSet(zset)) if zset.is_empty()
+        ) {
+            self.data.remove(key);
+        }
+    }
+
+    /// Get the stream specified by `key` for modifying.
+    ///
+    /// * `Ok(None)` if `key` not present.
+    /// * `Err(OpError::TypeMismatch)` if `key` holds other type.
+    fn stream_mut(&mut self, key: &[u8]) -> OpResult<Option<&mut Stream>> {
+        match self.data.get_mut(key) {
+            Some(cell) => match cell.live_value_mut() {
+                LiveValueRef::Live(Object::Stream(s)) => Ok(Some(s)),
+                LiveValueRef::Live(..) => Err(OpError::TypeMismatch),
+                LiveValueRef::Expired => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Save new `stream` as `key`, replacing the expired value if any.
+    fn stream_insert(&mut self, key: &[u8], stream: Stream) {
+        let cell = ValueCell {
+            value: Arc::new(Object::Stream(stream)),
+            expiration: None,
+        };
+        self.data.insert(key.to_vec(), cell);
+    }
+
+    /// Remove `key` in any type.
+    fn remove_key(&mut self, key: &[u8]) {
+        self.data.remove(key);
+    }
+
+    /// Check whether `key` present and not expired, in any type.
+    fn key_exists(&self, key: &[u8]) -> bool {
+        self.data
+            .get(key)
+            .is_some_and(|cell| cell.live_value_ref().is_some())
+    }
+
+    /// Get the type name and estimated size of value specified by `key`.
+    ///
+    /// Return `None` if `key` not present or expired.
+    fn key_stat(&self, key: &[u8]) -> Option<(&'static str, usize)> {
+        let value = self.data.get(key)?.live_value_ref()?;
+        Some((value.type_name(), key.len() + estimate_object_size(value)))
+    }
+
+    /// Get the encoding name of value specified by `key`, as OBJECT ENCODING reports.
+    ///
+    /// Return `None` if `key` not present or expired.
+    fn key_encoding(&self, key: &[u8]) -> Option<&'static str> {
+        self.data
+            .get(key)
+            .and_then(|cell| cell.live_value_ref())
+            .map(value_encoding)
+    }
+
+    fn get_next_seq_id(&self, key: impl AsRef<[u8]>, time_id: u64) -> u64 {
+        self.stream_ref(key.as_ref())
+            .map_or(0, |s| s.get_next_seq_id(time_id))
+    }
+}
+
+impl Shards<'_> {
+    /// Feed elements in list `key` to blocked `tasks`.
+    ///
+    /// Tasks are served in the order they started waiting, each task takes one
+    /// element from the end of list it is waiting on. Elements moved by BLMOVE
+    /// tasks are fed to the tasks waiting on the destination list, too.
+    ///
+    /// Return all elements taken, in order. Lists BLMOVE tasks push to must be locked,
+    /// see [`Storage::lock_list_feed`].
+    fn feed_list_blocked_tasks(
+        &mut self,
+        tasks: &mut Vec<(usize, ListBlockedTask)>,
+        key: &[u8],
+    ) -> Vec<ListFeed> {
+        let mut feeds = vec![];
+        let mut ready = VecDeque::from([key.to_vec()]);
+        while let Some(key) = ready.pop_front() {
+            let waits = |task: &ListBlockedTask| task.keys.contains(&key);
+            feed_in_order(tasks, waits, |task| {
+                let values = match self.get_mut(&key).list_mut(&key) {
+                    Ok(Some(list)) => list_pop(list, task.tail, task.count.unwrap_or(1)),
+                    _ => vec![],
+                };
+                if values.is_empty() {
+                    return Fed::Exhausted(task);
+                }
+                let Some((destination, to_tail)) = task.destination else {
+                    let count = task.count.map(|_| values.len());
+                    match task.sender.send((key.clone(), Ok(values))) {
+                        Ok(..) => feeds.push(ListFeed::Pop {
+                            key: key.clone(),
+                            tail: task.tail,
+                            count,
+                        }),
+                        Err((_, values)) => {
+                            // The task is gone (timeout or disconnected), put the elements back.
+                            self.get_mut(&key).list_unpop(
+                                &key,
+                                values.unwrap_or_default(),
+                                task.tail,
+                            );
+                        }
+                    }
+                    return Fed::Done;
+                };
+                if let Err(e) = self.get_mut(&destination).list_mut(&destination) {
+                    self.get_mut(&key).list_unpop(&key, values, task.tail);
+                    let _ = task.sender.send((key.clone(), Err(e)));
+                    return Fed::Done;
+                }
+                match task.sender.send((key.clone(), Ok(values.clone()))) {
+                    Ok(..) => {
+                        for value in values {
+                            self.get_mut(&destination)
+                                .list_push(&destination, value, to_tail)
+                                .ok();
+                        }
+                        feeds.push(ListFeed::Move {
+                            source: key.clone(),
+                            destination: destination.clone(),
+                            from_tail: task.tail,
+                            to_tail,
+                        });
+                        ready.push_back(destination);
+                    }
+                    Err(..) => {
+                        self.get_mut(&key).list_unpop(&key, values, task.tail);
+                    }
+                }
+                Fed::Done
+            });
+        }
+        feeds
+    }
+}
+
+impl Storage {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Keyspace::new()),
+            list_blocked_task: BlockedTasks::new(),
+            xread_blocked_task: BlockedTasks::new(),
+            xreadgroup_blocked_task: BlockedTasks::new(),
+            zpop_blocked_task: BlockedTasks::new(),
+            hooks: Arc::new(vec![]),
+            metrics: Arc::new(Mutex::new(StorageMetrics::default())),
+            objects: Arc::new(Mutex::new(ObjectTable::default())),
+            oom: Arc::new(Mutex::new(OomInjection::default())),
+            events: None,
+            evicted_keys: Arc::new(AtomicU64::new(0)),
+            active_expire: Arc::new(AtomicBool::new(true)),
+            handle_writes: HandleWrites::default(),
+        }
+    }
+
+    /// Count of writes made through this handle of storage.
+    ///
+    /// Unlike the writes counted for saving the RDB file, writes by other connections
+    /// are not counted, comparing it around a command tells whether the command itself
+    /// changed the dataset.
+    pub fn handle_writes(&self) -> u64 {
+        self.handle_writes.0.load(Ordering::Relaxed)
+    }
+
+    /// Remove all keys, for FLUSHALL and FLUSHDB.
+    ///
+    /// If `lazy`, the removed keys are dropped in background instead of before
+    /// returning, so flushing a large dataset does not hold the caller.
+    ///
+    /// Blocked XREADGROUP tasks are dropped as their groups are removed, the listener
+    /// of keyspace events unblocks their clients.
+    pub fn flush(&self, lazy: bool) {
+        let shards = self
+            .inner
+            .lock_all()
+            .iter_mut()
+            .map(std::mem::take)
+            .collect::<Vec<_>>();
+        let metrics = std::mem::take(&mut *self.metrics.lock().unwrap());
+        let objects = self.objects.lock().unwrap().take();
+
+        // Flushing counts as a change itself like redis, even if nothing to drop.
+        let keys = shards.iter().flat_map(|x| x.data.keys());
+        let writes = keys.clone().count() as u64 + 1;
+        if let Some(events) = &self.events {
+            events.flushed(writes);
+        }
+        self.xreadgroup_blocked_task.lock().clear();
+        self.handle_writes.record(writes);
+        if !self.hooks.is_empty() {
+            for key in keys {
+                for hook in self.hooks.iter() {
+                    hook.on_write(key);
+                }
+            }
+        }
+
+        let dropped = move || drop((shards, metrics, objects));
+        if lazy {
+            tokio::task::spawn_blocking(dropped);
+        } else {
+            dropped();
+        }
+    }
+
+    /// Build a storage that notifies all `hooks` on changes, and reports keyspace
+    /// events to `events`.
+    pub fn with_hooks(hooks: Vec<Arc<dyn StorageHook>>, events: Arc<dyn KeyspaceEvents>) -> Self {
+        Self {
+            hooks: Arc::new(hooks),
+            events: Some(events),
+            ..Self::new()
+        }
+    }
+
+    /// Notify all registered hooks that value of `key` is written.
+    ///
+    /// Also update metrics of `key`.
+    fn notify_write(&self, key: &[u8]) {
+        self.update_metrics(key);
+        self.handle_writes.record(1);
+        if let Some(events) = &self.events {
+            events.written(key);
+        }
+        for hook in self.hooks.iter() {
+            hook.on_write(key);
+        }
+    }
+
+    /// Refresh the metrics and object metadata of `key` according to its current value.
+    fn update_metrics(&self, key: &[u8]) {
+        let lock = self.inner.lock(key);
+        let (stat, encoding) = (lock.key_stat(key), lock.key_encoding(key));
+        drop(lock);
+        self.metrics.lock().unwrap().update(key, stat);
+        self.objects.lock().unwrap().update(key, encoding);
+    }
+
+    /// Record the access to `keys` read by a command, for OBJECT IDLETIME and FREQ,
+    /// and keyspace hits and misses in INFO.
+    pub fn touch_keys(&self, keys: &[Vec<u8>]) {
+        if keys.is_empty() {
+            return;
+        }
+        let shards = self.inner.lock_keys(keys.iter().map(Vec::as_slice));
+        let hits = keys.iter().filter(|x| shards.get(x).key_exists(x)).count();
+        drop(shards);
+        if let Some(events) = &self.events {
+            events.looked_up(hits as u64, (keys.len() - hits) as u64);
+        }
+        let mut lock = self.objects.lock().unwrap();
+        for key in keys {
+            lock.touch(key);
+        }
+    }
+
+    /// Get the metadata of `key` reported by OBJECT, without counting as access.
+    ///
+    /// Return `None` if `key` not present or expired.
+    pub fn object(&self, key: &[u8]) -> Option<ObjectInfo> {
+        if !self.inner.lock(key).key_exists(key) {
+            return None;
+        }
+        self.objects.lock().unwrap().get(key)
+    }
+
+    /// Get the estimated size of the value of `key` in bytes, reported by DEBUG OBJECT
+    /// as serialized length.
+    ///
+    /// Return `None` if `key` not present or expired.
+    pub fn serialized_length(&self, key: &[u8]) -> Option<usize> {
+        let lock = self.inner.lock(key);
+        lock.key_stat(key).map(|(_, size)| size - key.len())
+    }
+
+    /// Turn the background removal of expired keys on or off, for DEBUG
+    /// SET-ACTIVE-EXPIRE. Expired keys are still removed when accessed if off.
+    pub fn set_active_expire(&self, enabled: bool) {
+        self.active_expire.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Remove at most `limit` expired keys, the active expiration of redis.
+    ///
+    /// Like redis, only a few keys with expiration are sampled in each shard, and one
+    /// shard is locked at a time. Keys not sampled are left to later cycles.
+    ///
+    /// Do nothing if turned off by [`Storage::set_active_expire`]. Return the count of
+    /// keys removed.
+    pub fn expire_cycle(&self, limit: usize) -> usize {
+        if !self.active_expire.load(Ordering::Relaxed) {
+            return 0;
+        }
+        let now = SystemTime::now();
+        let mut expired = vec![];
+        for _ in 0..self.inner.shard_count() {
+            if expired.len() >= limit {
+                break;
+            }
+            let mut lock = self.inner.lock_next_expire();
+            expired.extend(lock.expire_sample(EXPIRE_SAMPLES, limit - expired.len(), now));
+        }
+        for key in expired.iter() {
+            if let Some(events) = &self.events {
+                events.expired();
+            }
+            self.notify_write(key);
+            log!(
+                "[storage] expire cycle {}: expired",
+                String::from_utf8_lossy(key)
+            );
+        }
+        expired.len()
+    }
+
+    /// Current `maxmemory-policy`.
+    pub fn maxmemory_policy(&self) -> MaxMemoryPolicy {
+        self.objects.lock().unwrap().policy()
+    }
+
+    /// Set `maxmemory-policy` and the tuning of LFU access counters.
+    pub fn configure_lfu(&self, policy: MaxMemoryPolicy, lfu: LfuConfig) {
+        self.objects.lock().unwrap().configure(policy, lfu);
+    }
+
+    /// Count of keys in each type, ordered by type name.
+    pub fn key_count_by_type(&self) -> Vec<(&'static str, usize)> {
+        let lock = self.metrics.lock().unwrap();
+        ["hash", "list", "set", "stream", "string", "zset"]
+            .into_iter()
+            .map(|ty| (ty, lock.type_count(ty)))
+            .collect()
+    }
+
+    /// Build the keyspace section in INFO.
+    pub(crate) fn keyspace_info(&self) -> KeyspaceInfo {
+        let shards = self.inner.lock_all();
+        let now = SystemTime::now();
+        let mut keys = 0;
+        let (mut expires, mut ttl) = (0, 0);
+        for cell in shards.iter().flat_map(|x| x.data.values()) {
+            match cell.expiration.map(|x| x.duration_since(now)) {
+                Some(Ok(v)) => {
+                    keys += 1;
+                    expires += 1;
+                    ttl += v.as_millis() as u64;
+                }
+                // Expired but not removed yet.
+                Some(Err(..)) => {}
+                None => keys += 1,
+            }
+        }
+        KeyspaceInfo {
+            db0: (keys > 0).then(|| DbInfo {
+                keys,
+                expires,
+                avg_ttl: ttl.checked_div(expires as u64).unwrap_or(0),
+            }),
+        }
+    }
+
+    /// Build the keysizes section in INFO.
+    pub(crate) fn info(&self) -> KeysizesInfo {
+        KeysizesInfo {
+            keys: self.key_count_by_type().into_iter().collect(),
+            biggest_key: self
+                .biggest_keys(1)
+                .pop()
+                .map(|(key, ty, size)| BiggestKey {
+                    key: String::from_utf8_lossy(&key).into_owned(),
+                    ty,
+                    size,
+                }),
+        }
+    }
+
+    /// Digest of all keys and their values, same on instances holding the same dataset.
+    ///
+    /// All zeros if there is no key.
+    pub fn digest(&self) -> String {
+        digest::digest(&self.inner.lock_all().merged())
+    }
+
+    /// Get at most `count` keys with the largest estimated memory usage, largest first.
+    ///
+    /// Return the name, type and estimated size in bytes of each key.
+    pub fn biggest_keys(&self, count: usize) -> Vec<(Vec<u8>, &'static str, usize)> {
+        self.metrics.lock().unwrap().biggest_keys(count)
+    }
+
+    /// Dump `keys` to a JSON document, keys not present are skipped.
+    ///
+    /// See [`dump`] for the format of document.
+    pub fn export_json(&self, keys: &[Vec<u8>]) -> String {
+        let shards = self.inner.lock_keys(keys.iter().map(Vec::as_slice));
+        Dump::export(&shards.merged(), keys).to_json()
+    }
+
+    /// Serialize all keys to a RDB file, see [`rdb`] for the supported types.
+    pub fn rdb_snapshot(&self) -> Vec<u8> {
+        let used_memory = self.used_memory();
+        let snapshot = self.inner.lock_all().merged();
+        rdb::save(&snapshot, used_memory)
+    }
+
+    /// Copy all keys if `start` returns `Some`, called under the lock of all keys so
+    /// no write happens between it and the copy.
+    ///
+    /// The keyspace is cloned, values are shared with the storage till the copy is
+    /// dropped, so serializing it is left to the caller without holding the lock.
+    pub fn snapshot<T>(&self, start: impl FnOnce() -> Option<T>) -> Option<(T, Snapshot)> {
+        let used_memory = self.used_memory();
+        let shards = self.inner.lock_all();
+        let started = start()?;
+        let data = shards.merged();
+        drop(shards);
+        Some((started, Snapshot { data, used_memory }))
+    }
+
+    /// Import all keys in JSON document `json` exported by [`Storage::export_json`].
+    ///
+    /// If `replace` is false and any key in the document already exists, nothing is
+    /// imported and `Err(OpError::BusyKey)` is returned.
+    ///
+    /// Blocked clients are not woken by imported keys.
+    ///
+    /// Return the count of imported keys.
+    pub fn import_json(&self, json: &[u8], replace: bool) -> OpResult<usize> {
+        let dump = Dump::parse(json).map_err(OpError::InvalidDump)?;
+        let keys = dump.keys().map(|x| x.to_vec()).collect::<Vec<_>>();
+        let mut shards = self.inner.lock_keys(keys.iter().map(Vec::as_slice));
+        if !replace && keys.iter().any(|key| shards.get(key).key_exists(key)) {
+            return Err(OpError::BusyKey);
+        }
+        let mut imported = StorageInner::default();
+        dump.import(&mut imported).map_err(OpError::InvalidDump)?;
+        shards.insert_all(imported);
+        drop(shards);
+        for key in keys.iter() {
+            self.notify_write(key);
+        }
+        Ok(keys.len())
+    }
+
+    /// Serialize the value of `key` as the payload of DUMP, see [`rdb`] for the format.
+    ///
+    /// Return the payload and the expiration of `key`, `None` if not present.
+    pub fn dump(&self, key: &[u8]) -> Option<(Vec<u8>, Option<SystemTime>)> {
+        let lock = self.inner.lock(key);
+        let payload = rdb::dump(&lock, key)?;
+        Some((payload, lock.data.get(key).and_then(|x| x.expiration)))
+    }
+
+    /// Save the value in `payload` of DUMP as `key`, expiring at `expiration`, for
+    /// RESTORE.
+    ///
+    /// If `replace` is false and `key` already exists, `Err(OpError::BusyKey)` is
+    /// returned. Nothing is saved if `expiration` is already passed, while the
+    /// existing key is still removed if `replace`.
+    pub fn restore(
+        &self,
+        key: &[u8],
+        payload: &[u8],
+        expiration: Option<SystemTime>,
+        replace: bool,
+    ) -> OpResult<()> {
+        let object = rdb::payload_object(payload).ok_or(OpError::InvalidPayload)?;
+        let object = rdb::restore(object).map_err(|_| OpError::BadDataFormat)?;
+        let mut lock = self.inner.lock(key);
+        if !replace && lock.key_exists(key) {
+            return Err(OpError::BusyKey);
+        }
+        if expiration.is_some_and(|x| x <= SystemTime::now()) {
+            lock.remove_key(key);
+        } else {
+            let cell = ValueCell {
+                value: Arc::new(object),
+                expiration,
+            };
+            lock.insert_cell(key.to_vec(), cell);
+        }
+        drop(lock);
+        self.notify_write(key);
+        Ok(())
+    }
+
+    /// Get at most `count` keys in hash `slot`, for CLUSTER GETKEYSINSLOT.
+    ///
+    /// Slots are not indexed, all keys are scanned.
+    pub(crate) fn keys_in_slot(&self, slot: u16, count: usize) -> Vec<Vec<u8>> {
+        let shards = self.inner.lock_all();
+        shards
+            .iter()
+            .flat_map(|x| {
+                x.data
+                    .iter()
+                    .filter(|(_, cell)| cell.live_value_ref().is_some())
+                    .map(|(key, _)| key)
+            })
+            .filter(|key| key_slot(key) == slot)
+            .take(count)
+            .cloned()
+            .collect()
+    }
+
+    /// Replace all keys with those in RDB file `rdb`, as replica in full
+    /// resynchronization with master node.
+    ///
+    /// Return the count of keys loaded. Nothing changes if `rdb` is invalid.
+    pub fn load_rdb(&self, rdb: &[u8]) -> OpResult<usize> {
+        let loaded = rdb::load(rdb).map_err(OpError::InvalidRdb)?;
+        let keys = loaded.data.keys().cloned().collect::<Vec<_>>();
+        self.flush(false);
+        self.inner.lock_all().insert_all(loaded);
+        for key in keys.iter() {
+            self.notify_write(key);
+        }
+        Ok(keys.len())
+    }
+
+    /// Load the document in `json` at startup, existing keys are replaced.
+    ///
+    /// Keys are saved in batches of `LOAD_BATCH`, the lock is released between batches
+    /// so connections are served while loading. `on_progress` is called after each
+    /// batch with the count of keys loaded and the count of all keys.
+    ///
+    /// Return the count of keys loaded. Unlike IMPORT, keys in batches before an
+    /// invalid one are kept.
+    pub fn load_json(
+        &self,
+        json: &[u8],
+        mut on_progress: impl FnMut(usize, usize),
+    ) -> OpResult<usize> {
+        let dump = Dump::parse(json).map_err(OpError::InvalidDump)?;
+        let total = dump.keys().count();
+        on_progress(0, total);
+        let mut loaded = 0;
+        for batch in dump.into_batches(LOAD_BATCH) {
+            let keys = batch.keys().map(|x| x.to_vec()).collect::<Vec<_>>();
+            let mut imported = StorageInner::default();
+            batch.import(&mut imported).map_err(OpError::InvalidDump)?;
+            self.inner
+                .lock_keys(keys.iter().map(Vec::as_slice))
+                .insert_all(imported);
+            for key in keys.iter() {
+                self.notify_write(key);
+            }
+            loaded += keys.len();
+            on_progress(loaded, total);
+        }
+        Ok(loaded)
+    }
+
+    /// Estimated memory used by all keys, in bytes.
+    pub fn used_memory(&self) -> usize {
+        self.metrics.lock().unwrap().used_memory()
+    }
+
+    /// Evict keys by `maxmemory-policy` till the used memory is within `maxmemory`
+    /// bytes, call before running a write. Nothing is evicted if `maxmemory` is 0.
+    ///
+    /// Return the evicted keys, in the order evicted.
+    pub fn evict(&self, maxmemory: usize) -> Vec<Vec<u8>> {
+        let mut evicted = vec![];
+        while maxmemory > 0 && self.used_memory() > maxmemory {
+            // Evicted keys are removed from the table, so this ends.
+            let Some(key) = self.objects.lock().unwrap().eviction_candidate() else {
+                break;
+            };
+            self.inner.lock(&key).remove_key(&key);
+            self.notify_write(&key);
+            evicted.push(key);
+        }
+        self.evicted_keys
+            .fetch_add(evicted.len() as u64, Ordering::Relaxed);
+        evicted
+    }
+
+    /// Check whether a write is allowed before running it, after [`Storage::evict`].
+    ///
+    /// Return `Err(OpError::OutOfMemory)` if the used memory still exceeds `maxmemory`
+    /// bytes, or rejected by the simulated OOM.
+    pub fn check_oom(&self, maxmemory: usize) -> OpResult<()> {
+        let used = self.used_memory();
+        if self.oom.lock().unwrap().check(used) || (maxmemory > 0 && used > maxmemory) {
+            Err(OpError::OutOfMemory)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Count of keys evicted by `maxmemory`, reported in INFO stats.
+    pub fn evicted_keys(&self) -> u64 {
+        self.evicted_keys.load(Ordering::Relaxed)
+    }
+
+    /// Zero the count of evicted keys, for CONFIG RESETSTAT.
+    pub fn reset_evicted_keys(&self) {
+        self.evicted_keys.store(0, Ordering::Relaxed);
+    }
+
+    /// Cap the estimated used memory to `limit` bytes, writes exceeding it are
+    /// rejected.
+    ///
+    /// `None` removes the cap.
+    #[cfg(debug_assertions)]
+    pub fn set_oom_limit(&self, limit: Option<usize>) {
+        self.oom.lock().unwrap().set_limit(limit);
+    }
+
+    /// Reject the next `count` writes as out of memory.
+    #[cfg(debug_assertions)]
+    pub fn inject_oom_failures(&self, count: usize) {
+        self.oom.lock().unwrap().fail_next(count);
+    }
+
+    #[cfg(debug_assertions)]
+    pub fn oom_status(&self) -> String {
+        self.oom.lock().unwrap().status()
+    }
+
+    /// Set `key` to hold string `value`, the storage part of SET command.
+    ///
+    /// The value is only written when `condition` is satisfied, checked atomically
+    /// with the write.
+    ///
+    /// ## Returns
+    ///
+    /// * `Ok((written, old_value))` where `written` indicates whether the value is
+    ///   written, and `old_value` is the value before SET if `get` is true.
+    /// * `Err(OpError::TypeMismatch)` if `get` is true and the old value is not a string.
+    pub fn set(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expire: SetExpire,
+        condition: SetCondition,
+        get: bool,
+    ) -> OpResult<(bool, Option<Vec<u8>>)> {
+        let mut lock = self.inner.lock(&key);
+        let old_cell = lock
+            .data
+            .get(key.as_slice())
+            .filter(|cell| cell.live_value_ref().is_some());
+        let exists = old_cell.is_some();
+
+        let old_value = if get {
+            match old_cell.map(|cell| cell.value.string()) {
+                Some(v) => Some(v?.into_owned()),
+                None => None,
+            }
+        } else {
+            None
+        };
+
+        let satisfied = match condition {
+            SetCondition::Always => true,
+            SetCondition::NotExists => !exists,
+            SetCondition::Exists => exists,
+        };
+        if !satisfied {
+            return Ok((false, old_value));
+        }
+
+        let expiration = match expire {
+            SetExpire::Never => None,
+            SetExpire::At(t) => Some(t),
+            SetExpire::Keep => old_cell.and_then(|cell| cell.expiration),
+        };
+        let cell = ValueCell {
+            value: Arc::new(string_object(value)),
+            expiration,
+        };
+        if lock.insert_cell(key.clone(), cell).is_some() {
+            log!("[storage] override");
+        }
+        drop(lock);
+        self.notify_write(&key);
+        Ok((true, old_value))
+    }
+
+    /// Get the string value of `key`, shared with the storage instead of copied.
+    ///
+    /// * `Ok(None)` if key not present or expired.
+    /// * `Err(OpError::TypeMismatch)` if the value is not a string.
+    pub fn get(&self, key: &[u8]) -> OpResult<Option<StoredString>> {
+        match self.get_object(key) {
+            Some(value) => {
+                value.string()?;
+                Ok(Some(StoredString(value)))
+            }
+            None if self.get_value_type(key).is_ok() => Err(OpError::TypeMismatch),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the value of `key` in string or list, shared with the storage.
+    ///
+    /// Return `None` if key not present or expired.
+    fn get_object(&self, key: &[u8]) -> Option<Arc<Object>> {
+        let mut lock = self.inner.lock(key);
+        match lock
+            .data
+            .get(key)
+            .map(|c| c.live_value())
+            .unwrap_or_else(|| LiveValue::Absent)
+        {
+            LiveValue::Live(value) => Some(value),
+            LiveValue::Expired => {
+                // Value exists but expired, clean up.
+                lock.data.remove(key);
+                drop(lock);
+                if let Some(events) = &self.events {
+                    events.expired();
+                }
+                self.update_metrics(key);
+                log!("[storage] get {}: expired", String::from_utf8_lossy(key));
+                None
+            }
+            LiveValue::Absent => {
+                // No value related to key
+                None
+            }
+        }
+    }
+
+    /// Get the string value of `key` and delete it.
+    ///
+    /// ## Returns
+    ///
+    /// * `Ok(Some(v))` if the value is removed.
+    /// * `Ok(None)` if key not present or already expired.
+    /// * `Err(OpError::TypeMismatch)` if the value is not a string, nothing removed.
+    pub fn get_del(&self, key: &[u8]) -> OpResult<Option<Vec<u8>>> {
+        let mut lock = self.inner.lock(key);
+        let value = match lock.data.get(key) {
+            Some(cell) => match cell.live_value_ref() {
+                Some(value) => Some(value.string()?.into_owned()),
+                None => None,
+            },
+            None => return Ok(None),
+        };
+        lock.data.remove(key);
+        drop(lock);
+        self.notify_write(key);
+        Ok(value)
+    }
+
+    /// Remove `keys` in any type, the storage part of DEL command.
+    ///
+    /// Return the count of keys removed.
+    pub fn delete(&self, keys: &[Vec<u8>]) -> usize {
+        let mut removed = vec![];
+        let mut shards = self.inner.lock_keys(keys.iter().map(Vec::as_slice));
+        for key in keys {
+            let shard = shards.get_mut(key);
+            if shard.key_exists(key) {
+                removed.push(key);
+            }
+            shard.remove_key(key);
+        }
+        drop(shards);
+        for key in removed.iter() {
+            self.notify_write(key);
+        }
+        removed.len()
+    }
+
+    /// Get the string value of `key` and update its expiration.
+    ///
+    /// `SetExpire::Keep` leaves the expiration untouched, `SetExpire::Never` removes it.
+    ///
+    /// ## Returns
+    ///
+    /// * `Ok(Some(v))` if the value is alive.
+    /// * `Ok(None)` if key not present or already expired.
+    /// * `Err(OpError::TypeMismatch)` if the value is not a string.
+    pub fn get_ex(&self, key: &[u8], expire: SetExpire) -> OpResult<Option<Vec<u8>>> {
+        let mut lock = self.inner.lock(key);
+        let cell = match lock.data.get_mut(key) {
+            Some(cell) => cell,
+            None => return Ok(None),
+        };
+        let value = match cell.live_value_ref() {
+            Some(v) => v.string()?.into_owned(),
+            None => {
+                // Value exists but expired, clean up.
+                lock.data.remove(key);
+                drop(lock);
+                self.update_metrics(key);
+                return Ok(None);
+            }
+        };
+        let changed = match expire {
+            SetExpire::Keep => false,
+            SetExpire::Never => {
+                cell.expiration = None;
+                true
+            }
+            SetExpire::At(t) => {
+                cell.expiration = Some(t);
+                lock.expires.insert(key.to_vec());
+                true
+            }
+        };
+        drop(lock);
+        if changed {
+            self.notify_write(key);
+        }
+        Ok(Some(value))
+    }
+
+    /// Insert elements to the list specified by `key`.
+    ///
+    /// If key not present and `create` is true, create a new list.
+    ///
+    /// Set `prepend` to true if want to prepend `value` before the head of current element.
+    ///
+    /// Elements may be taken by blocked BLPOP and BRPOP tasks right after saved.
+    ///
+    /// ## Returns
+    ///
+    /// * `Ok((count, feeds))` if saved successfully, return the count of elements
+    ///   after saving and the elements taken by blocked tasks.
+    /// * `Err(OpError::KeyAbsent)` if list not exists and `create` is false, nothing
+    ///   performed in this situaion.
+    pub fn insert_list(
+        &self,
+        key: Vec<u8>,
+        value: Vec<Vec<u8>>,
+        create: bool,
+        prepend: bool,
+    ) -> OpResult<(usize, Vec<ListFeed>)> {
+        let mut tasks = self.list_blocked_task.lock();
+        let mut shards = self.lock_list_feed(&tasks, &[&key]);
+        let lock = shards.get_mut(&key);
+
+        let ret = match lock.list_mut(&key) {
+            Ok(Some(list)) => {
+                for element in value {
+                    list.push(element, !prepend);
+                }
+                Ok(list.len())
+            }
+            Ok(None) if !create => return Err(OpError::KeyAbsent),
+            Ok(None) => {
+                let mut list = List::default();
+                for element in value {
+                    list.push(element, !prepend);
+                }
+                let count = list.len();
+                let cell = ValueCell {
+                    value: Arc::new(Object::List(list)),
+                    expiration: None,
+                };
+
+                lock.data.insert(key.clone(), cell);
+                Ok(count)
+            }
+            Err(e) => Err(e),
+        };
+
+        // Elements are saved in list first, then given to BLPOP and BRPOP tasks.
+        // The returned count is the length before any task takes elements, that is
+        // what the client pushed `value` expects.
+        let ret = ret.map(|count| (count, shards.feed_list_blocked_tasks(&mut tasks, &key)));
+
+        drop(shards);
+        drop(tasks);
+        if ret.is_ok() {
+            self.notify_write(&key);
+        }
+        ret
+    }
+
+    /// Get elements in list `key` from index `start` to `end`, both inclusive.
+    ///
+    /// Negative index counts from the tail. The list is shared with the storage, so
+    /// other keys are not blocked by a large range and no element is copied.
+    pub fn lrange(&self, key: &[u8], start: i64, end: i64) -> OpResult<ListRange> {
+        let list = self.get_object(key);
+        let len = match list.as_deref() {
+            Some(Object::List(list)) => list.len() as i64,
+            Some(..) => return Err(OpError::TypeMismatch),
+            None => 0,
+        };
+        let start = if start < 0 { len + start } else { start }.max(0);
+        let end = if end < 0 { len + end } else { end }.min(len - 1);
+        let range = if start > end {
+            0..0
+        } else {
+            start as usize..end as usize + 1
+        };
+        Ok(ListRange { list, range })
+    }
+
+    /// Get the elements of list, set or sorted set `key` to sort, for SORT.
+    ///
+    /// Lists keep their order and sorted sets are ordered by score. Set members are
+    /// ordered lexicographically, so the result does not depend on hashing.
+    ///
+    /// * Return empty if `key` not present or expired.
+    /// * `Err(OpError::TypeMismatch)` if `key` holds other type.
+    pub fn sort_elements(&self, key: &[u8]) -> OpResult<Vec<Vec<u8>>> {
+        Ok(match self.get_object(key).as_deref() {
+            Some(Object::List(list)) => list.iter().cloned().collect(),
+            Some(Object::Set(set)) => {
+                let mut members = set.iter().cloned().collect::<Vec<_>>();
+                members.sort();
+                members
+            }
+            Some(Object::ZSet(zset)) => zset
+                .iter()
+                .map(|(member, _)| member.as_bytes().to_vec())
+                .collect(),
+            Some(..) => return Err(OpError::TypeMismatch),
+            None => vec![],
+        })
+    }
+
+    /// Look up the value for `element` by `pattern` of SORT BY and GET.
+    ///
+    /// The first `*` in `pattern` is replaced by `element` to build the key, and
+    /// `key->field` reads `field` of a hash instead of a string value.
+    ///
+    /// Return `None` if `pattern` has no `*`, the key or field not present, or the
+    /// value in another type.
+    pub fn sort_lookup(&self, pattern: &[u8], element: &[u8]) -> Option<Vec<u8>> {
+        let star = pattern.iter().position(|x| *x == b'*')?;
+        let rest = &pattern[star + 1..];
+        // Like redis, "->" at the end is part of the key.
+        let (suffix, field) = match rest.windows(2).position(|x| x == b"->") {
+            Some(pos) if pos + 2 < rest.len() => (&rest[..pos], Some(&rest[pos + 2..])),
+            _ => (rest, None),
+        };
+        let key = [&pattern[..star], element, suffix].concat();
+        let value = self.get_object(&key)?;
+        match (value.as_ref(), field) {
+            (Object::Hash(hash), Some(field)) => hash.get(field).cloned(),
+            (value, None) => value.string().ok().map(Cow::into_owned),
+            _ => None,
+        }
+    }
+
+    /// Replace `key` in any type with a list of `elements`, the storage part of SORT
+    /// STORE. The key is removed if `elements` is empty.
+    ///
+    /// Elements may be taken by blocked tasks right after saved, like
+    /// [`Storage::insert_list`].
+    ///
+    /// Return the elements taken by blocked tasks, or `None` if nothing changed as
+    /// `key` is not present and `elements` is empty.
+    pub fn list_replace(&self, key: Vec<u8>, elements: Vec<Vec<u8>>) -> Option<Vec<ListFeed>> {
+        let mut tasks = self.list_blocked_task.lock();
+        let mut shards = self.lock_list_feed(&tasks, &[&key]);
+        let lock = shards.get_mut(&key);
+        let existed = lock.key_exists(&key);
+        lock.remove_key(&key);
+        let stored = !elements.is_empty();
+        let feeds = if stored {
+            let mut list = List::default();
+            for element in elements {
+                list.push(element, true);
+            }
+            let cell = ValueCell {
+                value: Arc::new(Object::List(list)),
+                expiration: None,
+            };
+            lock.data.insert(key.clone(), cell);
+            shards.feed_list_blocked_tasks(&mut tasks, &key)
+        } else {
+            vec![]
+        };
+
+        drop(shards);
+        drop(tasks);
+        if !(existed || stored) {
+            return None;
+        }
+        self.notify_write(&key);
+        Some(feeds)
+    }
+
+    /// Get the count of elements in an array specified by `key`.
+    ///
+    /// * If `key` not present in storage, return `Err(OpError::KeyAbsent)`.
+    /// * If the value corresponded to `key` is not an array, return `Err(OpError::TypeMismatch)`.
+    pub fn array_get_length(&self, key: impl AsRef<[u8]>) -> OpResult<usize> {
+        let lock = self.inner.lock(key.as_ref());
+        lock.list_ref(key.as_ref())?
+            .map(List::len)
+            .ok_or(OpError::KeyAbsent)
+    }
+
+    /// Remove at most `count` elements from array with `key`.
+    ///
+    /// Remove from the tail if `tail` is true, otherwise from the head.
+    ///
+    /// The list is removed once it has no element left.
+    ///
+    /// * If `key` not present in storage or expired, return `Ok(None)`.
+    /// * If the value corresponded to `key` is not an array, return `Err(OpError::TypeMismatch)`.
+    pub fn array_pop(
+        &self,
+        key: impl AsRef<[u8]>,
+        count: usize,
+        tail: bool,
+    ) -> OpResult<Option<Vec<Vec<u8>>>> {
+        let key = key.as_ref();
+        let mut lock = self.inner.lock(key);
+        let values = match lock.list_mut(key)? {
+            Some(list) => list_pop(list, tail, count),
+            None => return Ok(None),
+        };
+        lock.remove_empty_list(key);
+        drop(lock);
+        self.notify_write(key);
+        Ok(Some(values))
+    }
+
+    /// Pop at most `count` elements from the first list in `keys` having elements.
+    ///
+    /// Return the key of list and popped elements, or `None` if all lists are empty.
+    pub fn list_mpop(
+        &self,
+        keys: &[Vec<u8>],
+        tail: bool,
+        count: usize,
+    ) -> OpResult<Option<ListPopped>> {
+        let mut shards = self.inner.lock_keys(keys.iter().map(Vec::as_slice));
+        for key in keys {
+            let shard = shards.get_mut(key);
+            let values = match shard.list_mut(key)? {
+                Some(list) => list_pop(list, tail, count),
+                None => vec![],
+            };
+            if !values.is_empty() {
+                shard.remove_empty_list(key);
+                drop(shards);
+                self.notify_write(key);
+                return Ok(Some((key.clone(), values)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get the element at `index` in list `key`, negative index counts from the tail.
+    ///
+    /// Return `Ok(None)` if key not present or index out of range.
+    pub fn list_index(&self, key: &[u8], index: i64) -> OpResult<Option<Vec<u8>>> {
+        let lock = self.inner.lock(key);
+        let list = match lock.list_ref(key)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        Ok(list_position(list.len(), index).and_then(|pos| list.get(pos).cloned()))
+    }
+
+    /// Replace the element at `index` in list `key` with `element`.
+    pub fn list_set(&self, key: &[u8], index: i64, element: Vec<u8>) -> OpResult<()> {
+        let mut lock = self.inner.lock(key);
+        let list = lock.list_mut(key)?.ok_or(OpError::NoSuchKey)?;
+        let pos = list_position(list.len(), index).ok_or(OpError::IndexOutOfRange)?;
+        list.set(pos, element);
+        drop(lock);
+        self.notify_write(key);
+        Ok(())
+    }
+
+    /// Remove elements equal to `element` in list `key`.
+    ///
+    /// * `count > 0`: Remove at most `count` elements from head to tail.
+    /// * `count < 0`: Remove at most `-count` elements from tail to head.
+    /// * `count = 0`: Remove all.
+    ///
+    /// Return the count of removed elements.
+    pub fn list_remove(
+        &self,
+        key: &[u8],
+        count: i64,
+        element: &[u8],
+        budget: &mut Budget,
+    ) -> OpResult<usize> {
+        let mut lock = self.inner.lock(key);
+        let list = match lock.list_ref(key)? {
+            Some(v) => v,
+            None => return Ok(0),
+        };
+        let limit = if count == 0 {
+            usize::MAX
+        } else {
+            count.unsigned_abs() as usize
+        };
+        // Find all elements to remove before changing the list, so it is left as is if
+        // the budget runs out.
+        let len = list.len();
+        let mut positions = vec![];
+        for index in 0..len {
+            if positions.len() == limit {
+                break;
+            }
+            budget.check()?;
+            let pos = if count < 0 { len - 1 - index } else { index };
+            if list.get(pos).is_some_and(|x| x == element) {
+                positions.push(pos);
+            }
+        }
+        if positions.is_empty() {
+            return Ok(0);
+        }
+        positions.sort_unstable();
+        let list = lock.list_mut(key)?.ok_or(OpError::KeyAbsent)?;
+        list.remove_positions(&positions);
+        lock.remove_empty_list(key);
+        drop(lock);
+        self.notify_write(key);
+        Ok(positions.len())
+    }
+
+    /// Find positions of elements equal to `element` in list `key`.
+    ///
+    /// * `rank`: Skip the first `|rank| - 1` matches, search from tail to head if negative.
+    ///   Must not be zero.
+    /// * `count`: Return at most `count` positions, 0 means all matches.
+    /// * `max_len`: Compare at most `max_len` elements, 0 means the whole list.
+    ///
+    /// Positions are always counted from the head of list.
+    pub fn list_positions(
+        &self,
+        key: &[u8],
+        element: &[u8],
+        rank: i64,
+        count: usize,
+        max_len: usize,
+        budget: &mut Budget,
+    ) -> OpResult<Vec<usize>> {
+        let lock = self.inner.lock(key);
+        let values = match lock.list_ref(key)? {
+            Some(v) => v,
+            None => return Ok(vec![]),
+        };
+        let len = values.len();
+        let limit = if max_len == 0 { len } else { max_len.min(len) };
+        let count = if count == 0 { usize::MAX } else { count };
+        let skip = rank.unsigned_abs() as usize - 1;
+        let order: Box<dyn Iterator<Item = usize>> = if rank < 0 {
+            Box::new((0..len).rev().take(limit))
+        } else {
+            Box::new((0..len).take(limit))
+        };
+        let (mut positions, mut skipped) = (vec![], 0);
+        for pos in order {
+            if positions.len() == count {
+                break;
+            }
+            budget.check()?;
+            if values.get(pos).is_none_or(|x| x != element) {
+                continue;
+            }
+            if skipped < skip {
+                skipped += 1;
             } else {
-                Err(OpError::TypeMismatch)
+                positions.push(pos);
+            }
+        }
+        Ok(positions)
+    }
+
+    /// Insert `element` before or after the first element equal to `pivot` in list `key`.
+    ///
+    /// Set `after` to true to insert after `pivot`.
+    ///
+    /// ## Returns
+    ///
+    /// * `Ok(Some(len))` if inserted, return the length of list.
+    /// * `Ok(None)` if `pivot` not found.
+    /// * `Err(OpError::KeyAbsent)` if list not present.
+    pub fn list_insert(
+        &self,
+        key: &[u8],
+        after: bool,
+        pivot: &[u8],
+        element: Vec<u8>,
+    ) -> OpResult<Option<usize>> {
+        let mut lock = self.inner.lock(key);
+        let list = lock.list_mut(key)?.ok_or(OpError::KeyAbsent)?;
+        let pos = list.iter().position(|value| value == pivot);
+        if let Some(pos) = pos {
+            list.insert(if after { pos + 1 } else { pos }, element);
+        }
+        let len = list.len();
+        drop(lock);
+        if pos.is_none() {
+            return Ok(None);
+        }
+        self.notify_write(key);
+        Ok(Some(len))
+    }
+
+    /// Trim list `key` to only keep the elements in range `start..=stop`.
+    ///
+    /// Negative index counts from the tail, remove the list if range is empty.
+    pub fn list_trim(&self, key: &[u8], start: i64, stop: i64) -> OpResult<()> {
+        let mut lock = self.inner.lock(key);
+        let list = match lock.list_mut(key)? {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        let len = list.len() as i64;
+        let start = if start < 0 { len + start } else { start }.max(0);
+        let stop = if stop < 0 { len + stop } else { stop }.min(len - 1);
+        if start > stop {
+            list.keep_range(0..0);
+        } else {
+            list.keep_range(start as usize..stop as usize + 1);
+        }
+        lock.remove_empty_list(key);
+        drop(lock);
+        self.notify_write(key);
+        Ok(())
+    }
+
+    /// Pop an element from list `source` and push it to list `destination`, atomically.
+    ///
+    /// Pop from the tail of `source` if `from_tail` is true, push to the tail of
+    /// `destination` if `to_tail` is true.
+    ///
+    /// ## Returns
+    ///
+    /// * `Ok((Some(v), feeds))` if moved, return the element and elements taken by blocked
+    ///   tasks waiting on `destination`.
+    /// * `Ok((None, _))` if `source` not present or empty, nothing changed.
+    pub fn list_move(
+        &self,
+        source: &[u8],
+        destination: &[u8],
+        from_tail: bool,
+        to_tail: bool,
+    ) -> OpResult<(Option<Vec<u8>>, Vec<ListFeed>)> {
+        let mut tasks = self.list_blocked_task.lock();
+        let mut shards = self.lock_list_feed(&tasks, &[source, destination]);
+        // Check the type of destination before changing anything.
+        shards.get_mut(destination).list_mut(destination)?;
+        let value = match shards.get_mut(source).list_mut(source)? {
+            Some(list) => list.pop(from_tail),
+            None => None,
+        };
+        let value = match value {
+            Some(v) => v,
+            None => return Ok((None, vec![])),
+        };
+        shards
+            .get_mut(destination)
+            .list_push(destination, value.clone(), to_tail)?;
+        let feeds = shards.feed_list_blocked_tasks(&mut tasks, destination);
+        drop(shards);
+        drop(tasks);
+        self.notify_write(source);
+        if source != destination {
+            self.notify_write(destination);
+        }
+        Ok((Some(value), feeds))
+    }
+
+    /// Lock the shards of `keys`, and of lists BLMOVE `tasks` push to, so elements
+    /// pushed to `keys` can be fed to `tasks`.
+    fn lock_list_feed(&self, tasks: &[(usize, ListBlockedTask)], keys: &[&[u8]]) -> Shards<'_> {
+        let destinations = tasks.iter().filter_map(|(_, task)| {
+            task.destination
+                .as_ref()
+                .map(|(destination, _)| destination.as_slice())
+        });
+        self.inner
+            .lock_keys(keys.iter().copied().chain(destinations))
+    }
+
+    /// Add the blocked task of connection `id`, removed once the returned guard is
+    /// dropped.
+    pub fn list_add_block_task(
+        &mut self,
+        id: usize,
+        task: ListBlockedTask,
+    ) -> TaskGuard<ListBlockedTask> {
+        self.list_blocked_task.add(id, task)
+    }
+
+    /// Get the type of value specified by `key`
+    ///
+    /// If key not present, return `OpError::KeyAbsent`.
+    pub fn get_value_type(&self, key: impl AsRef<[u8]>) -> OpResult<&'static str> {
+        let lock = self.inner.lock(key.as_ref());
+        match lock.data.get(key.as_ref()).map(|cell| cell.live_value()) {
+            Some(LiveValue::Live(v)) => Ok(v.type_name()),
+            Some(LiveValue::Expired) | Some(LiveValue::Absent) | None => Err(OpError::KeyAbsent),
+        }
+    }
+
+    /// Add record `value` to stream `key`, then trim the stream by `trim` if set.
+    ///
+    /// `max_len` sets the hard cap on length of stream, kept for all later additions.
+    ///
+    /// Return the id added, the records delivered to blocked XREADGROUP tasks, and the
+    /// length of stream after trimming if it has a hard cap. Return
+    /// `Err(OpError::KeyAbsent)` if `key` not present and `nomkstream` is true.
+    #[allow(clippy::too_many_arguments)]
+    pub fn stream_add_value(
+        &mut self,
+        key: Vec<u8>,
+        stream_id: StreamId,
+        value: Vec<Value>,
+        max_len: Option<usize>,
+        nomkstream: bool,
+        trim: Option<TrimOptions>,
+    ) -> OpResult<(StreamId, Vec<StreamGroupFeed>, Option<usize>)> {
+        let mut lock = self.inner.lock(&key);
+        if nomkstream
+            && lock
+                .stream_ref(&key)
+                .is_err_and(|e| matches!(e, OpError::NoSuchKey))
+        {
+            return Err(OpError::KeyAbsent);
+        }
+        let (time_id, seq_id) = match stream_id {
+            StreamId::Value { time_id, seq_id } => (time_id, seq_id),
+            StreamId::Auto => lock
+                .stream_ref(&key)
+                .map_or((unix_millis(), 0), |s| s.next_auto_id(unix_millis())),
+            StreamId::PartialAuto(time_id) => {
+                let mut seq_id = lock.get_next_seq_id(key.as_slice(), time_id);
+                if time_id == 0 && seq_id == 0 {
+                    seq_id = 1;
+                }
+                (time_id, seq_id)
+            }
+        };
+
+        let ret = match lock.stream_mut(&key)? {
+            Some(s) => {
+                if let Some(max_len) = max_len {
+                    s.set_max_len(max_len);
+                }
+                let ret = s.add_entry(time_id, seq_id, value.clone());
+                if let (Ok(..), Some(trim)) = (&ret, &trim) {
+                    s.trim(trim);
+                }
+                ret
+            }
+            None => {
+                let mut s = Stream::new();
+                if let Some(max_len) = max_len {
+                    s.set_max_len(max_len);
+                }
+                let ret = s.add_entry(time_id, seq_id, value.clone());
+                if let (Ok(..), Some(trim)) = (&ret, &trim) {
+                    s.trim(trim);
+                }
+                lock.stream_insert(&key, s);
+                ret
+            }
+        };
+
+        if let Ok((ret, saved_in_new_entry)) = ret {
+            // Feed all waiting XREAD tasks.
+            // Return the value to all XREAD tasks.
+            // ref: https://redis.io/docs/latest/commands/xread/#how-multiple-clients-blocked-on-a-single-stream-are-served
+            let mut feed_lock = self.xread_blocked_task.lock();
+            let values_with_id = Value::Array(Array::with_values(vec![
+                Value::SimpleString(SimpleString::new(format!("{}-{}", time_id, seq_id))),
+                Value::Array(Array::with_values(value.clone())),
+            ]));
+            // Nothing is taken from the stream, every task waiting gets the entry.
+            let waits = |task: &XreadBlockedTask| task.targets.iter().any(|x| x.key == key);
+            feed_in_order(&mut feed_lock, waits, |mut task| {
+                let mut target_tasks = task.extract_target_waiting_for_id(&key, time_id, seq_id);
+                if saved_in_new_entry {
+                    target_tasks.append(&mut task.extract_target_waiting_for_new_entry(&key));
+                }
+                if target_tasks.is_empty() {
+                    return Fed::Skipped(task);
+                }
+                // Fine if the task is gone meanwhile.
+                let _ = task.sender.send((target_tasks, values_with_id.clone()));
+                Fed::Done
+            });
+            drop(feed_lock);
+
+            // Deliver new records to blocked XREADGROUP tasks, the earliest blocked first.
+            let mut group_feeds = vec![];
+            let mut group_lock = self.xreadgroup_blocked_task.lock();
+            if let Ok(Some(stream)) = lock.stream_mut(&key) {
+                // Records read are delivered for good, skip tasks ending meanwhile.
+                let waits = |task: &XreadGroupBlockedTask| {
+                    task.keys.contains(&key) && !task.sender.is_closed()
+                };
+                feed_in_order(&mut group_lock, waits, |task| {
+                    match stream.read_group(
+                        &task.group,
+                        &task.consumer,
+                        None,
+                        task.count,
+                        task.noack,
+                        unix_millis(),
+                    ) {
+                        Some(records) if !records.is_empty() => {
+                            group_feeds.push(StreamGroupFeed {
+                                key: key.clone(),
+                                group: task.group,
+                                consumer: task.consumer,
+                                count: task.count,
+                                noack: task.noack,
+                            });
+                            let _ = task.sender.send((key.clone(), records));
+                            Fed::Done
+                        }
+                        // Nothing new for the group, tasks of other groups may still
+                        // read.
+                        _ => Fed::Skipped(task),
+                    }
+                });
             }
+            drop(group_lock);
+            let capped_len = lock
+                .stream_ref(&key)
+                .ok()
+                .and_then(|stream| stream.max_len().map(|_| stream.len()));
+            drop(lock);
+            self.notify_write(&key);
+            Ok((ret, group_feeds, capped_len))
         } else {
-            Err(OpError::KeyAbsent)
+            Err(ret.unwrap_err())
+        }
+    }
+
+    /// Get records in stream `key` in range, see [`Stream::get_range`].
+    ///
+    /// Empty array if `key` not present.
+    pub fn stream_get_range(
+        &self,
+        key: &[u8],
+        start: RecordId,
+        end: RecordId,
+        count: Option<usize>,
+        rev: bool,
+    ) -> OpResult<Value> {
+        let lock = self.inner.lock(key);
+        match lock.stream_ref(key) {
+            Ok(s) => Ok(s.get_range(start, end, count, rev)),
+            Err(OpError::NoSuchKey) => Ok(Value::Array(Array::new_empty())),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the last id in stream `key`, `(0, 0)` if `key` not present.
+    pub fn stream_last_id(&self, key: &[u8]) -> OpResult<RecordId> {
+        let lock = self.inner.lock(key);
+        match lock.stream_ref(key) {
+            Ok(s) => Ok(s.last_generated_id()),
+            Err(OpError::NoSuchKey) => Ok((0, 0)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Force the last id of stream `key`, and optionally its count of added records and the
+    /// greatest deleted id.
+    pub fn stream_set_id(
+        &self,
+        key: &[u8],
+        last_id: RecordId,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<RecordId>,
+    ) -> OpResult<()> {
+        let mut lock = self.inner.lock(key);
+        lock.stream_mut(key)?.ok_or(OpError::NoSuchKey)?.set_id(
+            last_id,
+            entries_added,
+            max_deleted_id,
+        )?;
+        drop(lock);
+        self.notify_write(key);
+        Ok(())
+    }
+
+    /// Build the reply of XINFO STREAM for stream `key`.
+    pub fn stream_info(&self, key: &[u8]) -> OpResult<Value> {
+        let lock = self.inner.lock(key);
+        Ok(lock.stream_ref(key)?.info())
+    }
+
+    /// Build the reply of XINFO GROUPS for stream `key`.
+    pub fn stream_groups_info(&self, key: &[u8]) -> OpResult<Array> {
+        let lock = self.inner.lock(key);
+        Ok(lock.stream_ref(key)?.groups_info())
+    }
+
+    /// Create consumer group `group` in stream `key`, see [`Stream::create_group`].
+    ///
+    /// Create an empty stream if `key` not present and `mkstream` is true, otherwise
+    /// return `Err(OpError::NoSuchKey)`.
+    pub fn stream_create_group(
+        &self,
+        key: &[u8],
+        group: String,
+        start: StreamId,
+        mkstream: bool,
+    ) -> OpResult<()> {
+        let mut lock = self.inner.lock(key);
+        match lock.stream_mut(key)? {
+            Some(s) => s.create_group(group, start)?,
+            None if mkstream => {
+                let mut s = Stream::new();
+                s.create_group(group, start)?;
+                lock.stream_insert(key, s);
+            }
+            None => return Err(OpError::NoSuchKey),
+        }
+        drop(lock);
+        self.notify_write(key);
+        Ok(())
+    }
+
+    /// Remove consumer group `group` in stream `key`.
+    ///
+    /// Return false if group not exists.
+    pub fn stream_destroy_group(&self, key: &[u8], group: &str) -> OpResult<bool> {
+        let mut lock = self.inner.lock(key);
+        let destroyed = match lock.stream_mut(key)? {
+            Some(s) => s.destroy_group(group),
+            None => return Err(OpError::NoSuchKey),
+        };
+        drop(lock);
+        if destroyed {
+            self.notify_write(key);
+        }
+        Ok(destroyed)
+    }
+
+    /// Read records in stream `key` as `consumer` in `group`, see [`Stream::read_group`].
+    pub fn stream_read_group(
+        &self,
+        key: &[u8],
+        group: &str,
+        consumer: &str,
+        after: Option<RecordId>,
+        count: Option<usize>,
+        noack: bool,
+    ) -> OpResult<Vec<GroupRecord>> {
+        let mut lock = self.inner.lock(key);
+        let records = lock
+            .stream_mut(key)?
+            .and_then(|s| s.read_group(group, consumer, after, count, noack, unix_millis()))
+            .ok_or_else(|| OpError::NoGroup {
+                key: key.to_vec(),
+                group: group.to_string(),
+            })?;
+        drop(lock);
+        self.notify_write(key);
+        Ok(records)
+    }
+
+    /// Acknowledge records `ids` in `group` of stream `key`.
+    ///
+    /// Return the count of acknowledged records.
+    pub fn stream_ack(&self, key: &[u8], group: &str, ids: &[RecordId]) -> OpResult<usize> {
+        let mut lock = self.inner.lock(key);
+        let acked = match lock.stream_mut(key)? {
+            Some(s) => s.ack(group, ids),
+            None => 0,
+        };
+        drop(lock);
+        if acked > 0 {
+            self.notify_write(key);
         }
+        Ok(acked)
+    }
+
+    /// Summary of pending records in `group` of stream `key`, see
+    /// [`Stream::pending_summary`].
+    #[allow(clippy::type_complexity)]
+    pub fn stream_pending_summary(
+        &self,
+        key: &[u8],
+        group: &str,
+    ) -> OpResult<(usize, Option<(RecordId, RecordId)>, Vec<(String, usize)>)> {
+        let lock = self.inner.lock(key);
+        lock.stream_ref(key)?
+            .pending_summary(group)
+            .ok_or_else(|| OpError::NoSuchGroup {
+                key: key.to_vec(),
+                group: group.to_string(),
+            })
+    }
+
+    /// Pending records in `group` of stream `key`, see [`Stream::pending_range`].
+    ///
+    /// Return the idle time in milliseconds along with each record.
+    #[allow(clippy::too_many_arguments)]
+    pub fn stream_pending_range(
+        &self,
+        key: &[u8],
+        group: &str,
+        start: RecordId,
+        end: RecordId,
+        count: usize,
+        min_idle: u64,
+        consumer: Option<&str>,
+    ) -> OpResult<Vec<(RecordId, PendingEntry, u64)>> {
+        let lock = self.inner.lock(key);
+        let now = unix_millis();
+        let records = lock
+            .stream_ref(key)?
+            .pending_range(group, start, end, count, min_idle, consumer, now)
+            .ok_or_else(|| OpError::NoSuchGroup {
+                key: key.to_vec(),
+                group: group.to_string(),
+            })?;
+        Ok(records
+            .into_iter()
+            .map(|(id, x)| {
+                let idle = now.saturating_sub(x.delivery_time);
+                (id, x, idle)
+            })
+            .collect())
+    }
+
+    /// Claim pending records `ids` in `group` of stream `key` to `consumer`, see
+    /// [`Stream::claim`].
+    #[allow(clippy::type_complexity)]
+    pub fn stream_claim(
+        &self,
+        key: &[u8],
+        group: &str,
+        consumer: &str,
+        min_idle: u64,
+        ids: &[RecordId],
+        options: &ClaimOptions,
+    ) -> OpResult<(Vec<ClaimedRecord>, Vec<RecordId>)> {
+        let mut lock = self.inner.lock(key);
+        let claimed = lock
+            .stream_mut(key)?
+            .and_then(|s| s.claim(group, consumer, min_idle, ids, options, unix_millis()))
+            .ok_or_else(|| OpError::NoSuchGroup {
+                key: key.to_vec(),
+                group: group.to_string(),
+            })?;
+        drop(lock);
+        self.notify_write(key);
+        Ok(claimed)
     }
 
-    /// Remove the first `count` elements from array with `key`.
-    ///
-    /// * If `key` not present in storage, return `Err(OpError::KeyAbsent)`.
-    /// * If the value corresponded to `key` is not an array, return `Err(OpError::TypeMismatch)`.
-    pub fn array_pop_front(
+    /// Scan and claim idle pending records in `group` of stream `key` to `consumer`, see
+    /// [`Stream::auto_claim`].
+    #[allow(clippy::too_many_arguments, clippy::type_complexity)]
+    pub fn stream_auto_claim(
         &self,
-        key: impl AsRef<str>,
-        count: Option<usize>,
-    ) -> OpResult<Option<Value>> {
-        let mut lock = self.inner.lock().unwrap();
+        key: &[u8],
+        group: &str,
+        consumer: &str,
+        min_idle: u64,
+        start: RecordId,
+        count: usize,
+        justid: bool,
+    ) -> OpResult<(RecordId, Vec<ClaimedRecord>, Vec<RecordId>)> {
+        let mut lock = self.inner.lock(key);
+        let ret = lock
+            .stream_mut(key)?
+            .and_then(|s| {
+                s.auto_claim(
+                    group,
+                    consumer,
+                    min_idle,
+                    start,
+                    count,
+                    justid,
+                    unix_millis(),
+                )
+            })
+            .ok_or_else(|| OpError::NoSuchGroup {
+                key: key.to_vec(),
+                group: group.to_string(),
+            })?;
+        drop(lock);
+        self.notify_write(key);
+        Ok(ret)
+    }
 
-        if let Some(ValueCell { value, .. }) = lock.data.get_mut(key.as_ref()) {
-            if let Value::Array(arr) = value {
-                if arr.is_empty() {
-                    return Ok(None);
-                }
+    /// Add the blocked task of connection `id`, removed once the returned guard is
+    /// dropped.
+    pub fn xread_add_block_task(
+        &mut self,
+        id: usize,
+        task: XreadBlockedTask,
+    ) -> TaskGuard<XreadBlockedTask> {
+        self.xread_blocked_task.add(id, task)
+    }
 
-                match count {
-                    Some(c) => {
-                        // Take amount of elements.
-                        let mut ret = Array::new_empty();
-                        for _ in 0..c {
-                            match arr.pop_front() {
-                                Some(v) => {
-                                    ret.push_back(v);
-                                }
-                                None => {
-                                    /* No element left */
-                                    break;
-                                }
-                            }
-                        }
-                        Ok(Some(Value::Array(ret)))
-                    }
-                    None => {
-                        // Take the first element.
-                        Ok(Some(arr.pop_front().unwrap()))
-                    }
+    /// Add the blocked task of connection `id`, removed once the returned guard is
+    /// dropped.
+    pub fn xreadgroup_add_block_task(
+        &mut self,
+        id: usize,
+        task: XreadGroupBlockedTask,
+    ) -> TaskGuard<XreadGroupBlockedTask> {
+        self.xreadgroup_blocked_task.add(id, task)
+    }
+
+    pub fn integer_increase(&mut self, key: Vec<u8>) -> OpResult<i64> {
+        let mut lock = self.inner.lock(&key);
+        match lock
+            .data
+            .get_mut(key.as_slice())
+            .map(|cell| cell.live_value_mut())
+        {
+            Some(LiveValueRef::Live(value)) => match value {
+                Object::Int(v) => {
+                    *v += 1;
+                    let value = *v;
+                    drop(lock);
+                    self.notify_write(&key);
+                    Ok(value)
                 }
-            } else {
-                Err(OpError::TypeMismatch)
+                Object::Str(..) => Err(OpError::InvalidInteger),
+                _ => Err(OpError::TypeMismatch),
+            },
+            Some(LiveValueRef::Expired) | None => {
+                let value = 1;
+                // Insert new value.
+                lock.data.insert(
+                    key.clone(),
+                    ValueCell {
+                        value: Arc::new(Object::Int(value)),
+                        expiration: None,
+                    },
+                );
+                drop(lock);
+                self.notify_write(&key);
+
+                Ok(value)
             }
-        } else {
-            Err(OpError::KeyAbsent)
         }
     }
 
-    pub fn lpop_add_block_task(&mut self, task: LpopBlockedTask) {
-        let mut lock = self.lpop_blocked_task.lock().unwrap();
-        lock.push(task);
+    /// Increase the score of `member` in sorted set `key` by `increment`.
+    ///
+    /// Create the sorted set if not present. Members added are fed to blocked
+    /// BZPOPMIN and BZPOPMAX tasks first.
+    ///
+    /// Return the score of `member` after increase, and the pops made by blocked tasks.
+    pub fn zset_incr(
+        &mut self,
+        key: Vec<u8>,
+        member: String,
+        increment: f64,
+    ) -> OpResult<(f64, Vec<ZpopFeed>)> {
+        let mut lock = self.inner.lock(&key);
+        let zset = lock.zset_entry(&key)?;
+        let result = zset
+            .incr(member, increment)
+            .map(|score| (score, self.feed_zpop_tasks(&key, zset)));
+        // Not left empty if the score became NaN.
+        lock.remove_empty_zset(&key);
+        let (score, feeds) = result?;
+
+        drop(lock);
+        self.notify_write(&key);
+        Ok((score, feeds))
     }
 
-    /// Get the type of value specified by `key`
+    /// Feed blocked BZPOPMIN and BZPOPMAX tasks waiting for sorted set `key` with
+    /// members in `zset`.
     ///
-    /// If key not present, return `OpError::KeyAbsent`.
-    pub fn get_value_type(&self, key: impl AsRef<str>) -> OpResult<&'static str> {
-        let lock = self.inner.lock().unwrap();
-        match lock.data.get(key.as_ref()).map(|cell| cell.live_value()) {
-            Some(LiveValue::Live(v)) => Ok(v.simple_name()),
-            Some(LiveValue::Expired) | Some(LiveValue::Absent) | None => {
-                if lock.stream.contains_key(key.as_ref()) {
-                    Ok("stream")
-                } else {
-                    // Expired.
-                    Err(OpError::KeyAbsent)
+    /// Return the pops made by tasks fed.
+    fn feed_zpop_tasks(&self, key: &[u8], zset: &mut SortedSet) -> Vec<ZpopFeed> {
+        let mut feeds = vec![];
+        let mut zpop_lock = self.zpop_blocked_task.lock();
+        let waits = |task: &ZpopBlockedTask| task.keys.iter().any(|x| x == key);
+        feed_in_order(&mut zpop_lock, waits, |task| {
+            let Some((member, score)) = zset.pop(1, task.max).pop() else {
+                return Fed::Exhausted(task);
+            };
+            match task.sender.send((key.to_vec(), member, score)) {
+                Ok(()) => feeds.push(ZpopFeed {
+                    key: key.to_vec(),
+                    max: task.max,
+                }),
+                // Receiver gone, put the member back.
+                Err((_, member, score)) => {
+                    zset.insert(member, score);
                 }
             }
-        }
+            Fed::Done
+        });
+        feeds
     }
 
-    pub fn stream_add_value(
+    /// Add locations in `items` to sorted set `key`, as `(longitude, latitude, member)`.
+    ///
+    /// Coordinates must be valid, see `geo_is_valid`. `condition` applies to each
+    /// member: `NotExists` only adds new members and `Exists` only updates existing ones.
+    ///
+    /// Return the count of members added, or the count of members added or moved if
+    /// `changed` is true, and the pops made by blocked tasks.
+    pub fn geo_add(
         &mut self,
-        key: String,
-        stream_id: StreamId,
-        value: Vec<Value>,
-    ) -> OpResult<StreamId> {
-        let mut lock = self.inner.lock().unwrap();
-        let (time_id, seq_id) = match stream_id {
-            StreamId::Value { time_id, seq_id } => (time_id, seq_id),
-            StreamId::Auto => (
-                SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as u64,
-                0,
-            ),
-            StreamId::PartialAuto(time_id) => {
-                let mut seq_id = lock.get_next_seq_id(key.as_str(), time_id);
-                if time_id == 0 && seq_id == 0 {
-                    seq_id = 1;
-                }
-                (time_id, seq_id)
+        key: Vec<u8>,
+        items: Vec<(f64, f64, String)>,
+        condition: SetCondition,
+        changed: bool,
+    ) -> OpResult<(usize, Vec<ZpopFeed>)> {
+        let mut lock = self.inner.lock(&key);
+        let zset = lock.zset_entry(&key)?;
+        let mut count = 0;
+        for (lon, lat, member) in items {
+            let score = geo::encode(lon, lat) as f64;
+            let old = zset.score(&member);
+            match (condition, old) {
+                (SetCondition::NotExists, Some(_)) | (SetCondition::Exists, None) => continue,
+                _ => {}
+            }
+            zset.insert(member, score);
+            match old {
+                None => count += 1,
+                Some(old) if changed && old != score => count += 1,
+                Some(_) => {}
             }
+        }
+        let feeds = self.feed_zpop_tasks(&key, zset);
+        lock.remove_empty_zset(&key);
+
+        drop(lock);
+        self.notify_write(&key);
+        Ok((count, feeds))
+    }
+
+    /// Get the scores of `members` in sorted set `key`, `None` for members not present.
+    pub fn zset_scores(&self, key: &[u8], members: &[String]) -> OpResult<Vec<Option<f64>>> {
+        let lock = self.inner.lock(key);
+        Ok(match lock.zset_ref(key)? {
+            Some(zset) => members.iter().map(|x| zset.score(x)).collect(),
+            None => vec![None; members.len()],
+        })
+    }
+
+    /// Get the coordinates of `members` in sorted set `key` as `(longitude, latitude)`,
+    /// `None` for members not present.
+    pub fn geo_pos(&self, key: &[u8], members: &[String]) -> OpResult<Vec<Option<(f64, f64)>>> {
+        Ok(self
+            .zset_scores(key, members)?
+            .into_iter()
+            .map(|x| x.map(|score| geo::decode(score as u64)))
+            .collect())
+    }
+
+    /// Distance in meters between `member1` and `member2` in sorted set `key`.
+    ///
+    /// Return `None` if any of them not present.
+    pub fn geo_dist(&self, key: &[u8], member1: String, member2: String) -> OpResult<Option<f64>> {
+        match self.geo_pos(key, &[member1, member2])?.as_slice() {
+            [Some(a), Some(b)] => Ok(Some(geo::distance(*a, *b))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Find members of sorted set `key` located in `shape` around `center`.
+    ///
+    /// All members are checked, matches are in no particular order.
+    ///
+    /// * Return empty if `key` not present.
+    /// * If `center` is a member not present, return `Err(OpError::NoGeoMember)`.
+    pub fn geo_search(
+        &self,
+        key: &[u8],
+        center: GeoCenter,
+        shape: GeoShape,
+    ) -> OpResult<Vec<GeoMatch>> {
+        let lock = self.inner.lock(key);
+        let zset = match lock.zset_ref(key)? {
+            Some(v) => v,
+            None => return Ok(vec![]),
+        };
+        let center = match center {
+            GeoCenter::Member(member) => zset
+                .score(&member)
+                .map(|x| geo::decode(x as u64))
+                .ok_or(OpError::NoGeoMember)?,
+            GeoCenter::LonLat(lon, lat) => (lon, lat),
         };
+        Ok(zset
+            .iter()
+            .filter_map(|(member, score)| {
+                let hash = score as u64;
+                let (lon, lat) = geo::decode(hash);
+                let distance = geo::distance_in_shape(center, shape, (lon, lat))?;
+                Some(GeoMatch {
+                    member: member.to_string(),
+                    distance,
+                    hash,
+                    lon,
+                    lat,
+                })
+            })
+            .collect())
+    }
 
-        let ret = match lock.stream.get_mut(key.as_str()) {
-            Some(s) => s.add_entry(time_id, seq_id, value.clone()),
-            None => {
-                let mut s = Stream::new();
-                let ret = s.add_entry(time_id, seq_id, value.clone());
-                lock.stream.insert(key.clone(), s);
-                ret
+    /// Replace `key` in any type with a sorted set of `members`, the storage part of
+    /// GEORADIUS STORE and STOREDIST. The key is removed if `members` is empty.
+    ///
+    /// Members are fed to blocked BZPOPMIN and BZPOPMAX tasks first.
+    ///
+    /// Return the pops made by blocked tasks, or `None` if nothing changed as `key` is
+    /// not present and `members` is empty.
+    pub fn zset_replace(
+        &mut self,
+        key: Vec<u8>,
+        members: Vec<(String, f64)>,
+    ) -> Option<Vec<ZpopFeed>> {
+        let mut lock = self.inner.lock(&key);
+        let existed = lock.key_exists(&key);
+        lock.remove_key(&key);
+        let written = existed || !members.is_empty();
+        let mut zset = SortedSet::default();
+        for (member, score) in members {
+            zset.insert(member, score);
+        }
+        let feeds = self.feed_zpop_tasks(&key, &mut zset);
+        if !zset.is_empty() {
+            let cell = ValueCell {
+                value: Arc::new(Object::ZSet(zset)),
+                expiration: None,
+            };
+            lock.data.insert(key.clone(), cell);
+        }
+
+        drop(lock);
+        if !written {
+            return None;
+        }
+        self.notify_write(&key);
+        Some(feeds)
+    }
+
+    /// Pop members with lowest scores from sorted set `key`, or highest scores if `max` is true.
+    ///
+    /// Pop one member if `count` is `None`.
+    ///
+    /// * If `key` not present in storage, return `Err(OpError::KeyAbsent)`.
+    /// * If the value corresponded to `key` is not a sorted set, return `Err(OpError::TypeMismatch)`.
+    pub fn zset_pop(
+        &mut self,
+        key: impl AsRef<[u8]>,
+        count: Option<usize>,
+        max: bool,
+    ) -> OpResult<Vec<(String, f64)>> {
+        let mut lock = self.inner.lock(key.as_ref());
+        let zset = lock.zset_mut(key.as_ref())?.ok_or(OpError::KeyAbsent)?;
+        let ret = zset.pop(count.unwrap_or(1), max);
+        lock.remove_empty_zset(key.as_ref());
+        drop(lock);
+        if !ret.is_empty() {
+            self.notify_write(key.as_ref());
+        }
+        Ok(ret)
+    }
+
+    /// Add the blocked task of connection `id`, removed once the returned guard is
+    /// dropped.
+    pub fn zpop_add_block_task(
+        &mut self,
+        id: usize,
+        task: ZpopBlockedTask,
+    ) -> TaskGuard<ZpopBlockedTask> {
+        self.zpop_blocked_task.add(id, task)
+    }
+
+    /// Append `bytes` to the string value of `key`.
+    ///
+    /// Create the value if `key` not present.
+    ///
+    /// Return the length of string after append.
+    pub fn string_append(&mut self, key: Vec<u8>, bytes: Vec<u8>) -> OpResult<usize> {
+        let mut lock = self.inner.lock(&key);
+        let len = match lock
+            .data
+            .get_mut(key.as_slice())
+            .map(|cell| cell.live_value_mut())
+        {
+            Some(LiveValueRef::Live(value)) => {
+                let mut content = value.string()?.into_owned();
+                if content.len() + bytes.len() > MAX_STRING_LENGTH {
+                    return Err(OpError::StringTooLong);
+                }
+                content.extend(bytes);
+                let len = content.len();
+                *value = string_object(content);
+                len
+            }
+            Some(LiveValueRef::Expired) | None => {
+                let len = bytes.len();
+                lock.data.insert(
+                    key.clone(),
+                    ValueCell {
+                        value: Arc::new(string_object(bytes)),
+                        expiration: None,
+                    },
+                );
+                len
             }
         };
+        drop(lock);
+        self.notify_write(&key);
+        Ok(len)
+    }
 
-        if let Ok((ret, saved_in_new_entry)) = ret {
-            // Feed all waiting XREAD tasks.
-            // Return the value to all XREAD tasks.
-            // ref: https://redis.io/docs/latest/commands/xread/#how-multiple-clients-blocked-on-a-single-stream-are-served
-            let mut feed_lock = self.xread_blocked_task.lock().unwrap();
-            let mut removed_id = None;
-            for (idx, task) in feed_lock.iter_mut().rev().enumerate() {
-                let mut target_tasks = task.extract_target_waiting_for_id(&key, time_id, seq_id);
-                if saved_in_new_entry {
-                    println!(
-                        "[storage] stream: checking data in new entry for key {} in task {:?}",
-                        key, task.targets
-                    );
-                    target_tasks.append(&mut task.extract_target_waiting_for_new_entry(&key));
-                }
-                if target_tasks.is_empty() {
-                    continue;
-                }
+    /// Get the length of string value of `key`, in bytes.
+    ///
+    /// * If `key` not present in storage, return `Err(OpError::KeyAbsent)`.
+    /// * If the value corresponded to `key` is not a string, return `Err(OpError::TypeMismatch)`.
+    pub fn string_len(&self, key: impl AsRef<[u8]>) -> OpResult<usize> {
+        match self.get_object(key.as_ref()) {
+            Some(value) => value.string().map(|x| x.len()),
+            None => Err(OpError::KeyAbsent),
+        }
+    }
 
-                removed_id = Some((idx, target_tasks));
-                break;
-            }
+    /// Get the substring of string value of `key`, in bytes offset `start..=end`.
+    ///
+    /// Negative offsets count from the end of the string, out of range offsets
+    /// are limited to the actual length.
+    pub fn string_get_range(
+        &self,
+        key: impl AsRef<[u8]>,
+        start: i64,
+        end: i64,
+    ) -> OpResult<Vec<u8>> {
+        let Some(value) = self.get_object(key.as_ref()) else {
+            return Err(OpError::KeyAbsent);
+        };
+        let content = value.string()?;
 
-            if let Some((idx, target_tasks)) = removed_id {
-                let task = feed_lock.remove(idx);
-                let values_with_id = Value::Array(Array::with_values(vec![
-                    Value::SimpleString(SimpleString::new(format!("{}-{}", time_id, seq_id))),
-                    Value::Array(Array::with_values(value.clone())),
-                ]));
-                task.sender.send((target_tasks, values_with_id)).unwrap();
-            }
-            Ok(ret)
+        let len = content.len() as i64;
+        let start = if start < 0 {
+            (len + start).max(0)
         } else {
-            Err(ret.unwrap_err())
+            start
+        };
+        let end = if end < 0 {
+            (len + end).max(0)
+        } else {
+            end.min(len - 1)
+        };
+        if len == 0 || start > end {
+            return Ok(vec![]);
         }
+        Ok(content[start as usize..=end as usize].to_vec())
     }
 
-    pub fn stream_get_range(&self, key: String, start: StreamId, end: StreamId) -> OpResult<Value> {
-        let lock = self.inner.lock().unwrap();
-        match lock.stream.get(key.as_str()) {
-            Some(s) => s.get_range(start, end),
-            None => Err(OpError::KeyAbsent),
+    /// Overwrite the string value of `key` with `bytes` starting at `offset`.
+    ///
+    /// Zero bytes are padded if the string is shorter than `offset`. Create the
+    /// value if `key` not present and `bytes` is not empty.
+    ///
+    /// Return the length of string after overwrite.
+    pub fn string_set_range(
+        &mut self,
+        key: Vec<u8>,
+        offset: usize,
+        bytes: Vec<u8>,
+    ) -> OpResult<usize> {
+        let mut lock = self.inner.lock(&key);
+        let (cell_value, mut content) = match lock
+            .data
+            .get_mut(key.as_slice())
+            .map(|cell| cell.live_value_mut())
+        {
+            Some(LiveValueRef::Live(value)) => {
+                let content = value.string()?.into_owned();
+                (Some(value), content)
+            }
+            Some(LiveValueRef::Expired) | None => (None, vec![]),
+        };
+
+        if bytes.is_empty() {
+            // Nothing to write, do not create the key.
+            return Ok(content.len());
         }
-    }
+        if offset + bytes.len() > MAX_STRING_LENGTH {
+            return Err(OpError::StringTooLong);
+        }
+
+        if content.len() < offset + bytes.len() {
+            content.resize(offset + bytes.len(), 0);
+        }
+        content[offset..offset + bytes.len()].copy_from_slice(&bytes);
+        let len = content.len();
 
-    pub fn xread_add_block_task(&mut self, task: XreadBlockedTask) {
-        let mut lock = self.xread_blocked_task.lock().unwrap();
-        lock.push(task);
+        match cell_value {
+            Some(value) => *value = string_object(content),
+            None => {
+                lock.data.insert(
+                    key.clone(),
+                    ValueCell {
+                        value: Arc::new(string_object(content)),
+                        expiration: None,
+                    },
+                );
+            }
+        }
+        drop(lock);
+        self.notify_write(&key);
+        Ok(len)
     }
 
-    pub fn integer_increase(&mut self, key: String) -> OpResult<Value> {
-        let mut lock = self.inner.lock().unwrap();
-        match lock
+    /// Set the bit at `offset` in string value of `key` to `bit`.
+    ///
+    /// The string is zero-extended if shorter than `offset`, and created if `key` not present.
+    ///
+    /// Return the original bit.
+    pub fn string_set_bit(&mut self, key: Vec<u8>, offset: usize, bit: bool) -> OpResult<bool> {
+        let mut lock = self.inner.lock(&key);
+        let old = match lock
             .data
-            .get_mut(key.as_str())
+            .get_mut(key.as_slice())
             .map(|cell| cell.live_value_mut())
         {
-            Some(LiveValueRef::Live(value)) => match value {
-                Value::Integer(integer) => {
-                    integer.increase(1);
-                    Ok(Value::Integer(integer.to_owned()))
-                }
-                _ => Err(OpError::InvalidInteger),
-            },
+            Some(LiveValueRef::Live(value)) => {
+                let mut content = value.string()?.into_owned();
+                let old = bitmap::set_bit(&mut content, offset, bit);
+                *value = string_object(content);
+                old
+            }
             Some(LiveValueRef::Expired) | None => {
-                let value = Value::Integer(Integer::new(1));
-                // Insert new value.
+                let mut content = vec![];
+                bitmap::set_bit(&mut content, offset, bit);
                 lock.data.insert(
-                    key,
+                    key.clone(),
                     ValueCell {
-                        value: value.clone(),
+                        value: Arc::new(string_object(content)),
                         expiration: None,
                     },
                 );
+                false
+            }
+        };
+        drop(lock);
+        self.notify_write(&key);
+        Ok(old)
+    }
 
-                Ok(value)
+    /// Get the bit at `offset` in string value of `key`.
+    ///
+    /// Bits beyond the end of string, or of `key` not present, are 0.
+    pub fn string_get_bit(&self, key: impl AsRef<[u8]>, offset: usize) -> OpResult<bool> {
+        match self.get_object(key.as_ref()) {
+            Some(value) => Ok(bitmap::get_bit(&value.string()?, offset)),
+            None => Ok(false),
+        }
+    }
+
+    /// Count the set bits in string value of `key`, in `range` if any.
+    ///
+    /// `range` is the start and end offset in `unit`, both inclusive.
+    pub fn string_bit_count(
+        &self,
+        key: impl AsRef<[u8]>,
+        range: Option<(i64, i64, BitUnit)>,
+    ) -> OpResult<usize> {
+        let Some(value) = self.get_object(key.as_ref()) else {
+            return Ok(0);
+        };
+        let content = value.string()?;
+        let (start, end, unit) = range.unwrap_or((0, -1, BitUnit::Byte));
+        Ok(bitmap::bit_range(content.len(), start, end, unit)
+            .map_or(0, |range| bitmap::count(&content, range)))
+    }
+
+    /// Find the first bit equals to `bit` in string value of `key`, between offset
+    /// `start` and `end` in `unit`.
+    ///
+    /// Return the position of bit from the beginning of string, or -1 if not found.
+    /// When looking for 0 without `end`, the string is treated as padded with zeros.
+    pub fn string_bit_pos(
+        &self,
+        key: impl AsRef<[u8]>,
+        bit: bool,
+        start: i64,
+        end: Option<i64>,
+        unit: BitUnit,
+    ) -> OpResult<i64> {
+        let Some(value) = self.get_object(key.as_ref()) else {
+            return Ok(if bit { -1 } else { 0 });
+        };
+        let content = value.string()?;
+        let range = match bitmap::bit_range(content.len(), start, end.unwrap_or(-1), unit) {
+            Some(v) => v,
+            None => return Ok(-1),
+        };
+        match bitmap::position(&content, bit, range) {
+            Some(v) => Ok(v as i64),
+            None if !bit && end.is_none() => Ok(range.1 as i64 + 1),
+            None => Ok(-1),
+        }
+    }
+
+    /// Add `elements` to HyperLogLog `key`, create it if not present.
+    ///
+    /// Return true if any register changed or the key is created.
+    pub fn hll_add(&self, key: &[u8], elements: &[Vec<u8>]) -> OpResult<bool> {
+        let mut lock = self.inner.lock(key);
+        let (mut hll, mut changed) = match lock.hll_ref(key)? {
+            Some(v) => (v, false),
+            None => (HyperLogLog::new(), true),
+        };
+        for element in elements {
+            changed |= hll.add(element);
+        }
+        if !changed {
+            return Ok(false);
+        }
+        lock.hll_save(key, &hll);
+        drop(lock);
+        self.notify_write(key);
+        Ok(true)
+    }
+
+    /// Estimate the cardinality of the union of HyperLogLog `keys`.
+    ///
+    /// Keys not present are treated as empty.
+    pub fn hll_count(&self, keys: &[Vec<u8>]) -> OpResult<u64> {
+        let shards = self.inner.lock_keys(keys.iter().map(Vec::as_slice));
+        let mut union = HyperLogLog::new();
+        for key in keys {
+            if let Some(hll) = shards.get(key).hll_ref(key)? {
+                union.merge(&hll);
+            }
+        }
+        Ok(union.count())
+    }
+
+    /// Merge HyperLogLog `sources` into `dest`, `dest` is created if not present.
+    pub fn hll_merge(&self, dest: &[u8], sources: &[Vec<u8>]) -> OpResult<()> {
+        let keys = std::iter::once(dest).chain(sources.iter().map(Vec::as_slice));
+        let mut shards = self.inner.lock_keys(keys);
+        let mut merged = shards
+            .get(dest)
+            .hll_ref(dest)?
+            .unwrap_or_else(HyperLogLog::new);
+        for key in sources {
+            if let Some(hll) = shards.get(key).hll_ref(key)? {
+                merged.merge(&hll);
             }
         }
+        shards.get_mut(dest).hll_save(dest, &merged);
+        drop(shards);
+        self.notify_write(dest);
+        Ok(())
+    }
+}
+
+/// Current unix time in milliseconds.
+pub(crate) fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_list_position() {
+        assert_eq!(list_position(3, 0), Some(0));
+        assert_eq!(list_position(3, 2), Some(2));
+        assert_eq!(list_position(3, 3), None);
+        assert_eq!(list_position(3, -1), Some(2));
+        assert_eq!(list_position(3, -3), Some(0));
+        assert_eq!(list_position(3, -4), None);
+        assert_eq!(list_position(0, 0), None);
+    }
+
+    #[test]
+    fn test_list_positions() {
+        let storage = Storage::new();
+        let values = ["c", "a", "c", "b", "c"]
+            .iter()
+            .map(|x| x.as_bytes().to_vec())
+            .collect();
+        storage
+            .insert_list("list".into(), values, true, false)
+            .unwrap();
+
+        let positions = |rank, count, max_len| {
+            storage
+                .list_positions(b"list", b"c", rank, count, max_len, &mut Budget::default())
+                .unwrap()
+        };
+        assert_eq!(positions(1, 1, 0), vec![0]);
+        assert_eq!(positions(2, 0, 0), vec![2, 4]);
+        assert_eq!(positions(-1, 2, 0), vec![4, 2]);
+        assert_eq!(positions(1, 0, 2), vec![0]);
+        assert_eq!(positions(-3, 0, 0), vec![0]);
+        assert_eq!(positions(4, 0, 0), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_lrange() {
+        let storage = Storage::new();
+        let values = ["a", "b", "c"]
+            .iter()
+            .map(|x| x.as_bytes().to_vec())
+            .collect();
+        storage
+            .insert_list("list".into(), values, true, false)
+            .unwrap();
+
+        let range = |start, end| {
+            let range = storage.lrange(b"list", start, end).unwrap();
+            range
+                .elements()
+                .map(|x| String::from_utf8(x.to_vec()).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(range(0, -1), vec!["a", "b", "c"]);
+        assert_eq!(range(-2, 10), vec!["b", "c"]);
+        assert_eq!(range(-10, -3), vec!["a"]);
+        assert_eq!(range(2, 1), Vec::<String>::new());
+        assert_eq!(range(0, -10), Vec::<String>::new());
+        assert_eq!(range(5, 10), Vec::<String>::new());
+        assert_eq!(storage.lrange(b"none", 0, -1).unwrap().elements().len(), 0);
+
+        // Reads share the value in storage.
+        let list = storage.get_object(b"list").unwrap();
+        assert!(Arc::ptr_eq(&list, &storage.get_object(b"list").unwrap()));
+        storage.list_index(b"list", 0).unwrap();
+        storage
+            .list_positions(b"list", b"a", 1, 0, 0, &mut Budget::default())
+            .unwrap();
+        assert!(Arc::ptr_eq(&list, &storage.get_object(b"list").unwrap()));
+    }
+
+    #[test]
+    fn test_handle_writes() {
+        let storage = Storage::new();
+        let other = storage.clone();
+        let set = |storage: &Storage, key: &str| {
+            storage
+                .set(
+                    key.into(),
+                    b"v".to_vec(),
+                    SetExpire::Never,
+                    SetCondition::Always,
+                    false,
+                )
+                .unwrap();
+        };
+        set(&storage, "a");
+        set(&other, "b");
+        set(&other, "c");
+        assert_eq!(storage.handle_writes(), 1);
+        assert_eq!(other.handle_writes(), 2);
+        assert_eq!(storage.clone().handle_writes(), 0);
+    }
+
+    #[test]
+    fn test_list_drain() {
+        let storage = Storage::new();
+        let values = ["a", "b", "c"]
+            .iter()
+            .map(|x| x.as_bytes().to_vec())
+            .collect();
+        storage
+            .insert_list("list".into(), values, true, false)
+            .unwrap();
+
+        assert_eq!(
+            storage.array_pop("list", 2, false).unwrap().unwrap().len(),
+            2
+        );
+        assert_eq!(storage.get_value_type("list").unwrap(), "list");
+        assert_eq!(
+            storage.array_pop("list", 2, true).unwrap(),
+            Some(vec![b"c".to_vec()])
+        );
+
+        // Draining the list removes the key.
+        assert!(matches!(
+            storage.get_value_type("list"),
+            Err(OpError::KeyAbsent)
+        ));
+        assert!(!storage.inner.lock(b"list").key_exists(b"list"));
+        assert_eq!(storage.array_pop("list", 1, false).unwrap(), None);
+        assert!(matches!(
+            storage.array_get_length("list"),
+            Err(OpError::KeyAbsent)
+        ));
+
+        // Expired lists are absent as well.
+        storage
+            .insert_list("list".into(), vec![b"a".to_vec()], true, false)
+            .unwrap();
+        assert_eq!(storage.array_get_length("list").unwrap(), 1);
+        storage
+            .inner
+            .lock(b"list")
+            .data
+            .get_mut(b"list".as_slice())
+            .unwrap()
+            .expiration = Some(SystemTime::now());
+        assert!(matches!(
+            storage.array_get_length("list"),
+            Err(OpError::KeyAbsent)
+        ));
+        assert_eq!(storage.array_pop("list", 1, false).unwrap(), None);
+    }
+
+    #[test]
+    fn test_feed_list_blocked_tasks() {
+        let elements = |values: &[&str]| {
+            values
+                .iter()
+                .map(|x| x.as_bytes().to_vec())
+                .collect::<Vec<_>>()
+        };
+
+        let storage = Storage::new();
+        let (head, mut head_recver) = ListBlockedTask::new("list".into(), false);
+        let (other, mut other_recver) = ListBlockedTask::new("other".into(), false);
+        let (gone, gone_recver) = ListBlockedTask::new("list".into(), true);
+        let (tail, mut tail_recver) = ListBlockedTask::new("list".into(), true);
+        let (mv, mut mv_recver) =
+            ListBlockedTask::new_move("list".into(), false, "dst".into(), true);
+        let (dst, mut dst_recver) =
+            ListBlockedTask::new_multi(vec!["none".into(), "dst".into()], false, Some(2));
+        drop(gone_recver);
+        for (id, task) in [head, other, gone, tail, mv, dst].into_iter().enumerate() {
+            storage.list_blocked_task.lock().push((id, task));
+        }
+
+        let (count, feeds) = storage
+            .insert_list("list".into(), elements(&["a", "b", "c", "d"]), true, false)
+            .unwrap();
+        assert_eq!(count, 4);
+        assert_eq!(
+            feeds,
+            vec![
+                ListFeed::Pop {
+                    key: "list".into(),
+                    tail: false,
+                    count: None
+                },
+                ListFeed::Pop {
+                    key: "list".into(),
+                    tail: true,
+                    count: None
+                },
+                ListFeed::Move {
+                    source: "list".into(),
+                    destination: "dst".into(),
+                    from_tail: false,
+                    to_tail: true
+                },
+                ListFeed::Pop {
+                    key: "dst".into(),
+                    tail: false,
+                    count: Some(1)
+                },
+            ]
+        );
+
+        let recv = |recver: &mut ListReceiver| {
+            let (key, values) = recver.try_recv().unwrap();
+            (key, values.unwrap())
+        };
+        assert_eq!(recv(&mut head_recver), ("list".into(), elements(&["a"])));
+        assert_eq!(recv(&mut tail_recver), ("list".into(), elements(&["d"])));
+        assert_eq!(recv(&mut mv_recver), ("list".into(), elements(&["b"])));
+        assert_eq!(recv(&mut dst_recver), ("dst".into(), elements(&["b"])));
+        assert!(other_recver.try_recv().is_err());
+        assert_eq!(storage.list_blocked_task.lock().len(), 1);
+        assert!(storage
+            .lrange(b"list", 0, -1)
+            .unwrap()
+            .elements()
+            .eq([b"c".as_slice()]));
+    }
+
+    #[test]
+    fn test_stream_keyspace() {
+        let mut storage = Storage::new();
+        let add = |storage: &mut Storage, key: &str| {
+            let fields = ["f", "v"]
+                .map(|x| Value::BulkString(serde_redis::BulkString::new(x)))
+                .to_vec();
+            storage.stream_add_value(key.into(), StreamId::Auto, fields, None, false, None)
+        };
+        storage
+            .set(
+                "str".into(),
+                b"v".to_vec(),
+                SetExpire::Never,
+                SetCondition::Always,
+                false,
+            )
+            .unwrap();
+        assert!(matches!(
+            add(&mut storage, "str"),
+            Err(OpError::TypeMismatch)
+        ));
+        add(&mut storage, "s").unwrap();
+        assert_eq!(storage.get_value_type("s").unwrap(), "stream");
+        assert!(matches!(storage.get(b"s"), Err(OpError::TypeMismatch)));
+
+        // Streams expire like other types.
+        let expiration = SystemTime::now();
+        storage
+            .inner
+            .lock(b"s")
+            .data
+            .get_mut(b"s".as_slice())
+            .unwrap()
+            .expiration = Some(expiration);
+        assert!(matches!(
+            storage.get_value_type("s"),
+            Err(OpError::KeyAbsent)
+        ));
+        add(&mut storage, "s").unwrap();
+        assert_eq!(storage.get_value_type("s").unwrap(), "stream");
+        assert_eq!(storage.delete(&["s".into(), "str".into()]), 2);
+    }
+
+    #[test]
+    fn test_zset_expire() {
+        let mut storage = Storage::new();
+        storage.zset_incr("z".into(), "m".into(), 1.0).unwrap();
+        let (payload, expiration) = storage.dump(b"z").unwrap();
+        assert_eq!(expiration, None);
+
+        // Sorted sets expire like other types.
+        let expiration = SystemTime::now() + Duration::from_secs(100);
+        storage
+            .restore(b"z", &payload, Some(expiration), true)
+            .unwrap();
+        assert_eq!(storage.dump(b"z").unwrap().1, Some(expiration));
+        assert_eq!(storage.keyspace_info().db0.unwrap().expires, 1);
+        storage
+            .inner
+            .lock(b"z")
+            .data
+            .get_mut(b"z".as_slice())
+            .unwrap()
+            .expiration = Some(SystemTime::now());
+        assert!(matches!(
+            storage.get_value_type("z"),
+            Err(OpError::KeyAbsent)
+        ));
+        assert_eq!(storage.zset_scores(b"z", &["m".into()]).unwrap(), [None]);
+        assert!(matches!(
+            storage.zset_pop("z", None, false),
+            Err(OpError::KeyAbsent)
+        ));
+        assert_eq!(storage.delete(&["z".into()]), 0);
+
+        // Written again as a new sorted set without expiration.
+        storage.zset_incr("z".into(), "n".into(), 2.0).unwrap();
+        assert_eq!(storage.zset_scores(b"z", &["m".into()]).unwrap(), [None]);
+        assert_eq!(storage.dump(b"z").unwrap().1, None);
+
+        // Removed by the active expiration.
+        storage
+            .restore(b"z", &payload, Some(SystemTime::now()), true)
+            .unwrap();
+        assert!(storage.dump(b"z").is_none());
+        storage
+            .restore(b"z", &payload, Some(expiration), true)
+            .unwrap();
+        storage
+            .inner
+            .lock(b"z")
+            .data
+            .get_mut(b"z".as_slice())
+            .unwrap()
+            .expiration = Some(SystemTime::now());
+        assert_eq!(storage.expire_cycle(10), 1);
+        assert!(storage.inner.lock(b"z").data.is_empty());
+    }
+
+    #[test]
+    fn test_expire_cycle_sample() {
+        let storage = Storage::new();
+        let past = SetExpire::At(SystemTime::now() - Duration::from_secs(1));
+        // All in the same shard by hash tag.
+        for i in 0..50 {
+            let key = format!("{{a}}{i:02}").into_bytes();
+            let (written, _) = storage
+                .set(key, b"v".to_vec(), past, SetCondition::Always, false)
+                .unwrap();
+            assert!(written);
+        }
+        let future = SetExpire::At(SystemTime::now() + Duration::from_secs(60));
+        storage
+            .set(
+                b"b".to_vec(),
+                b"v".to_vec(),
+                future,
+                SetCondition::Always,
+                false,
+            )
+            .unwrap();
+        storage
+            .set(
+                b"c".to_vec(),
+                b"v".to_vec(),
+                future,
+                SetCondition::Always,
+                false,
+            )
+            .unwrap();
+        storage
+            .set(
+                b"c".to_vec(),
+                b"v".to_vec(),
+                SetExpire::Never,
+                SetCondition::Always,
+                false,
+            )
+            .unwrap();
+
+        // Only a bounded count of keys sampled in the shard each cycle.
+        assert_eq!(storage.expire_cycle(1000), EXPIRE_SAMPLES);
+        assert_eq!(storage.expire_cycle(5), 5);
+        assert_eq!(storage.expire_cycle(1000), EXPIRE_SAMPLES);
+        assert_eq!(storage.expire_cycle(1000), 50 - EXPIRE_SAMPLES * 2 - 5);
+        assert_eq!(storage.expire_cycle(1000), 0);
+        assert!(storage.inner.lock(b"{a}").data.is_empty());
+
+        // Keys not expiring any more are dropped from the index when sampled.
+        assert!(storage.inner.lock(b"c").expires.is_empty());
+        assert!(storage.inner.lock(b"b").expires.contains(b"b".as_slice()));
+    }
+
+    #[test]
+    fn test_shared_read() {
+        let storage = Storage::new();
+        let elements = (0..100).map(|x| x.to_string().into_bytes()).collect();
+        storage.list_replace(b"l".to_vec(), elements);
+        let set = |value: &str| {
+            storage
+                .set(
+                    b"s".to_vec(),
+                    value.into(),
+                    SetExpire::Never,
+                    SetCondition::Always,
+                    false,
+                )
+                .unwrap()
+        };
+        set("a");
+
+        // Reads keep the values without holding the storage, writes go on meanwhile.
+        let range = storage.lrange(b"l", 0, -1).unwrap();
+        let string = storage.get(b"s").unwrap().unwrap();
+        storage.list_set(b"l", 0, b"x".to_vec()).unwrap();
+        storage.list_trim(b"l", 0, 9).unwrap();
+        set("b");
+
+        // Values written are copied, the reads still see the values when read.
+        assert_eq!(range.elements().len(), 100);
+        assert_eq!(range.elements().next(), Some(b"0".as_slice()));
+        assert_eq!(string.bytes(), b"a".as_slice());
+        let current = storage.lrange(b"l", 0, 1).unwrap();
+        assert_eq!(current.elements().collect::<Vec<_>>(), [b"x", b"1"]);
+        assert_eq!(storage.get(b"s").unwrap().unwrap().bytes(), b"b".as_slice());
+        drop((range, current));
+
+        // Not shared once the reads end.
+        let lock = storage.inner.lock(b"l");
+        assert_eq!(Arc::strong_count(&lock.data[b"l".as_slice()].value), 1);
+    }
+
+    #[test]
+    fn test_hash_set_keyspace() {
+        let mut storage = Storage::new();
+        let mut loaded = StorageInner::default();
+        let hash = HashMap::from([(b"f".to_vec(), b"v".to_vec())]);
+        let set = HashSet::from([b"1".to_vec(), b"2".to_vec()]);
+        for (key, value) in [("h", Object::Hash(hash)), ("t", Object::Set(set))] {
+            let cell = ValueCell {
+                value: Arc::new(value),
+                expiration: None,
+            };
+            loaded.data.insert(key.into(), cell);
+        }
+        assert_eq!(storage.load_rdb(&rdb::save(&loaded, 0)).unwrap(), 2);
+
+        assert_eq!(storage.get_value_type("h").unwrap(), "hash");
+        assert_eq!(storage.get_value_type("t").unwrap(), "set");
+        assert_eq!(storage.object(b"h").unwrap().encoding, "listpack");
+        assert_eq!(storage.object(b"t").unwrap().encoding, "intset");
+        assert!(matches!(storage.get(b"h"), Err(OpError::TypeMismatch)));
+        assert!(matches!(
+            storage.zset_incr("t".into(), "m".into(), 1.0),
+            Err(OpError::TypeMismatch)
+        ));
+        assert_eq!(
+            storage.key_count_by_type()[..3],
+            [("hash", 1), ("list", 0), ("set", 1)]
+        );
+        assert_eq!(storage.delete(&["h".into(), "t".into()]), 2);
+    }
+
+    #[test]
+    fn test_sort_lookup() {
+        let storage = Storage::new();
+        let mut loaded = StorageInner::default();
+        let hash = HashMap::from([(b"f".to_vec(), b"v".to_vec())]);
+        let set = HashSet::from([b"b".to_vec(), b"a".to_vec()]);
+        for (key, value) in [
+            ("h_1", Object::Hash(hash)),
+            ("t", Object::Set(set)),
+            ("s_1", Object::Int(7)),
+        ] {
+            let cell = ValueCell {
+                value: Arc::new(value),
+                expiration: None,
+            };
+            loaded.data.insert(key.into(), cell);
+        }
+        storage.load_rdb(&rdb::save(&loaded, 0)).unwrap();
+
+        assert_eq!(storage.sort_lookup(b"s_*", b"1"), Some(b"7".to_vec()));
+        assert_eq!(storage.sort_lookup(b"h_*->f", b"1"), Some(b"v".to_vec()));
+        assert_eq!(storage.sort_lookup(b"h_*->g", b"1"), None);
+        assert_eq!(storage.sort_lookup(b"h_*", b"1"), None);
+        assert_eq!(storage.sort_lookup(b"s_*->f", b"1"), None);
+        assert_eq!(storage.sort_lookup(b"s_1", b"1"), None);
+        // "->" without field is part of the key.
+        assert_eq!(storage.sort_lookup(b"s_*->", b"1"), None);
+
+        // Set members are ordered for a stable result.
+        assert_eq!(
+            storage.sort_elements(b"t").unwrap(),
+            vec![b"a".to_vec(), b"b".to_vec()]
+        );
+        assert!(storage.sort_elements(b"none").unwrap().is_empty());
+        assert!(matches!(
+            storage.sort_elements(b"s_1"),
+            Err(OpError::TypeMismatch)
+        ));
+
+        assert!(storage.list_replace(b"none".to_vec(), vec![]).is_none());
+        assert!(storage
+            .list_replace(b"t".to_vec(), vec![b"x".to_vec()])
+            .is_some());
+        assert_eq!(storage.get_value_type("t").unwrap(), "list");
     }
 }
@@ -0,0 +1,436 @@
+//! Metadata of keys reported by OBJECT: encoding, idle time and access frequency.
+//!
+//! Like `StorageMetrics`, the metadata is refreshed every time a key is written, and
+//! keys read by commands are touched to record the access. The encoding names follow
+//! redis, though values here are never stored in those encodings.
+
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+};
+
+use tokio::time::Instant;
+
+use crate::storage::{string_object, Object};
+
+/// Max length of strings in "embstr" encoding.
+const EMBSTR_MAX_LEN: usize = 44;
+
+/// Max count of entries and max length of each entry in "listpack" encoding,
+/// same as `zset-max-listpack-entries` and `zset-max-listpack-value` in redis, and
+/// the `hash-` and `set-` ones.
+const LISTPACK_MAX_ENTRIES: usize = 128;
+const LISTPACK_MAX_VALUE: usize = 64;
+
+/// Max count of members of sets in "intset" encoding, as `set-max-intset-entries`.
+const INTSET_MAX_ENTRIES: usize = 512;
+
+/// Max total size of lists in "listpack" encoding, as `list-max-listpack-size -2`.
+const LIST_LISTPACK_MAX_SIZE: usize = 8 * 1024;
+
+/// Initial access frequency of new keys, same as redis.
+const LFU_INIT_VAL: u8 = 5;
+
+/// Policy to pick keys to evict when out of memory, same as `maxmemory-policy` in redis.
+///
+/// Also decides which of OBJECT FREQ and IDLETIME is available.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MaxMemoryPolicy {
+    /// Never evict keys, writes fail once out of memory.
+    #[default]
+    NoEviction,
+
+    /// Evict the least recently used keys.
+    AllKeysLru,
+
+    /// Evict the least frequently used keys, tracked by the access counter.
+    AllKeysLfu,
+
+    /// Evict random keys.
+    AllKeysRandom,
+}
+
+impl MaxMemoryPolicy {
+    pub const ALL: &[MaxMemoryPolicy] = &[
+        Self::NoEviction,
+        Self::AllKeysLru,
+        Self::AllKeysLfu,
+        Self::AllKeysRandom,
+    ];
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|x| x.name().eq_ignore_ascii_case(s))
+    }
+
+    /// Name of the policy in `maxmemory-policy`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NoEviction => "noeviction",
+            Self::AllKeysLru => "allkeys-lru",
+            Self::AllKeysLfu => "allkeys-lfu",
+            Self::AllKeysRandom => "allkeys-random",
+        }
+    }
+
+    pub fn is_lfu(self) -> bool {
+        self == Self::AllKeysLfu
+    }
+}
+
+/// Tuning of the logarithmic access counter, same as `lfu-log-factor` and
+/// `lfu-decay-time` in redis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LfuConfig {
+    /// How slow the counter grows: the greater, the more accesses are needed to
+    /// reach the max.
+    pub log_factor: u32,
+
+    /// Minutes of idle time to decay the counter by one, 0 never decays.
+    pub decay_time: u64,
+}
+
+impl Default for LfuConfig {
+    fn default() -> Self {
+        Self {
+            log_factor: 10,
+            decay_time: 1,
+        }
+    }
+}
+
+/// Encoding name of `value`.
+pub(super) fn value_encoding(value: &Object) -> &'static str {
+    match value {
+        Object::List(list) => {
+            let size = list.iter().map(Vec::len).sum::<usize>();
+            if size <= LIST_LISTPACK_MAX_SIZE {
+                "listpack"
+            } else {
+                "quicklist"
+            }
+        }
+        Object::Stream(..) => "stream",
+        Object::ZSet(zset) => {
+            if fits_listpack(zset.iter().map(|(member, _)| member.len())) {
+                "listpack"
+            } else {
+                "skiplist"
+            }
+        }
+        Object::Hash(hash) => {
+            if fits_listpack(
+                hash.iter()
+                    .map(|(field, value)| field.len().max(value.len())),
+            ) {
+                "listpack"
+            } else {
+                "hashtable"
+            }
+        }
+        Object::Set(set) => {
+            let is_int = |x: &Vec<u8>| matches!(string_object(x.clone()), Object::Int(..));
+            if set.len() <= INTSET_MAX_ENTRIES && set.iter().all(is_int) {
+                "intset"
+            } else if fits_listpack(set.iter().map(Vec::len)) {
+                "listpack"
+            } else {
+                "hashtable"
+            }
+        }
+        Object::Int(..) => "int",
+        Object::Str(bytes) => {
+            if bytes.len() <= EMBSTR_MAX_LEN {
+                "embstr"
+            } else {
+                "raw"
+            }
+        }
+    }
+}
+
+/// Whether entries of `lens` fit in "listpack" encoding.
+fn fits_listpack(lens: impl Iterator<Item = usize>) -> bool {
+    let mut count = 0;
+    for len in lens {
+        count += 1;
+        if count > LISTPACK_MAX_ENTRIES || len > LISTPACK_MAX_VALUE {
+            return false;
+        }
+    }
+    true
+}
+
+/// Metadata of one key.
+#[derive(Debug, Clone)]
+struct ObjectMeta {
+    encoding: &'static str,
+    last_access: Instant,
+
+    /// Logarithmic access counter of redis LFU, decays over idle time.
+    counter: u8,
+}
+
+impl ObjectMeta {
+    fn new(encoding: &'static str) -> Self {
+        Self {
+            encoding,
+            last_access: Instant::now(),
+            counter: LFU_INIT_VAL,
+        }
+    }
+
+    /// The access counter after decay over idle time.
+    fn freq(&self, lfu: LfuConfig) -> u8 {
+        if lfu.decay_time == 0 {
+            return self.counter;
+        }
+        let periods = self.last_access.elapsed().as_secs() / 60 / lfu.decay_time;
+        self.counter
+            .saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+
+    /// Record an access: decay the counter, then increase it with a probability
+    /// decreasing as the counter grows.
+    fn touch(&mut self, lfu: LfuConfig) {
+        let mut counter = self.freq(lfu);
+        if counter < u8::MAX {
+            let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+            let r = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+            if r < 1.0 / (base * lfu.log_factor as f64 + 1.0) {
+                counter += 1;
+            }
+        }
+        self.counter = counter;
+        self.last_access = Instant::now();
+    }
+}
+
+/// Metadata of a key reported by OBJECT.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ObjectInfo {
+    pub(crate) encoding: &'static str,
+
+    /// Seconds since the key was accessed.
+    pub(crate) idle_time: u64,
+
+    /// The access counter after decay.
+    pub(crate) freq: u8,
+}
+
+/// Metadata of all keys.
+#[derive(Debug, Default)]
+pub(crate) struct ObjectTable {
+    objects: HashMap<Vec<u8>, ObjectMeta>,
+    policy: MaxMemoryPolicy,
+    lfu: LfuConfig,
+}
+
+impl ObjectTable {
+    /// Update `key` after written, `encoding` is `None` if key is removed.
+    ///
+    /// Writes count as access.
+    pub fn update(&mut self, key: &[u8], encoding: Option<&'static str>) {
+        match encoding {
+            Some(encoding) => {
+                let meta = self
+                    .objects
+                    .entry(key.to_vec())
+                    .or_insert_with(|| ObjectMeta::new(encoding));
+                meta.encoding = encoding;
+                meta.touch(self.lfu);
+            }
+            None => {
+                self.objects.remove(key);
+            }
+        }
+    }
+
+    /// Record an access to `key` by a read.
+    pub fn touch(&mut self, key: &[u8]) {
+        if let Some(meta) = self.objects.get_mut(key) {
+            meta.touch(self.lfu);
+        }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<ObjectInfo> {
+        self.objects.get(key).map(|meta| ObjectInfo {
+            encoding: meta.encoding,
+            idle_time: meta.last_access.elapsed().as_secs(),
+            freq: meta.freq(self.lfu),
+        })
+    }
+
+    /// Take out metadata of all keys, keep the configuration.
+    pub fn take(&mut self) -> Self {
+        Self {
+            objects: std::mem::take(&mut self.objects),
+            policy: self.policy,
+            lfu: self.lfu,
+        }
+    }
+
+    pub fn policy(&self) -> MaxMemoryPolicy {
+        self.policy
+    }
+
+    /// Pick the key to evict next under the policy, `None` if nothing to evict.
+    ///
+    /// Unlike redis sampling a few keys, all keys are checked so the pick is exact.
+    pub fn eviction_candidate(&self) -> Option<Vec<u8>> {
+        let objects = self.objects.iter();
+        let key = match self.policy {
+            MaxMemoryPolicy::NoEviction => None,
+            MaxMemoryPolicy::AllKeysLru => objects.min_by_key(|(_, meta)| meta.last_access),
+            MaxMemoryPolicy::AllKeysLfu => {
+                objects.min_by_key(|(_, meta)| (meta.freq(self.lfu), meta.last_access))
+            }
+            MaxMemoryPolicy::AllKeysRandom => {
+                let r = RandomState::new().build_hasher().finish() as usize;
+                objects.clone().nth(r % self.objects.len().max(1))
+            }
+        };
+        key.map(|(key, _)| key.clone())
+    }
+
+    /// Set the eviction policy and the tuning of access counter.
+    ///
+    /// Counters of existing keys are kept, only the following accesses and decays
+    /// follow the new tuning.
+    pub fn configure(&mut self, policy: MaxMemoryPolicy, lfu: LfuConfig) {
+        self.policy = policy;
+        self.lfu = lfu;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, time::Duration};
+
+    use super::*;
+    use crate::storage::sorted_set::SortedSet;
+
+    #[test]
+    fn test_encoding() {
+        let string = |x: &str| string_object(x.as_bytes().to_vec());
+        assert_eq!(value_encoding(&string("12345")), "int");
+        assert_eq!(value_encoding(&string("-1")), "int");
+        assert_eq!(value_encoding(&string("012")), "embstr");
+        assert_eq!(value_encoding(&string("hello")), "embstr");
+        assert_eq!(value_encoding(&string(&"a".repeat(45))), "raw");
+
+        let list = |n: usize| Object::List((0..n).map(|_| vec![b'a'; 100]).collect());
+        assert_eq!(value_encoding(&list(3)), "listpack");
+        assert_eq!(value_encoding(&list(100)), "quicklist");
+
+        let mut zset = SortedSet::default();
+        zset.insert("a".into(), 1.0);
+        assert_eq!(value_encoding(&Object::ZSet(zset.clone())), "listpack");
+        zset.insert("b".repeat(65), 1.0);
+        assert_eq!(value_encoding(&Object::ZSet(zset)), "skiplist");
+
+        let set =
+            |members: &[&str]| Object::Set(members.iter().map(|x| x.as_bytes().to_vec()).collect());
+        assert_eq!(value_encoding(&set(&["1", "-2"])), "intset");
+        assert_eq!(value_encoding(&set(&["1", "a"])), "listpack");
+        assert_eq!(
+            value_encoding(&set(&["a".repeat(65).as_str()])),
+            "hashtable"
+        );
+        let ints = (0..600).map(|x| x.to_string().into_bytes());
+        assert_eq!(
+            value_encoding(&Object::Set(HashSet::from_iter(ints))),
+            "hashtable"
+        );
+    }
+
+    #[test]
+    fn test_object_table() {
+        let mut table = ObjectTable::default();
+        table.update(b"k", Some("embstr"));
+        let info = table.get(b"k").unwrap();
+        assert_eq!(info.encoding, "embstr");
+        assert_eq!(info.idle_time, 0);
+        assert!((LFU_INIT_VAL..=LFU_INIT_VAL + 1).contains(&info.freq));
+
+        // Counter grows slower as it grows.
+        for _ in 0..1000 {
+            table.touch(b"k");
+        }
+        let freq = table.get(b"k").unwrap().freq;
+        assert!((10..30).contains(&freq), "freq {freq}");
+
+        table.update(b"k", None);
+        assert!(table.get(b"k").is_none());
+        table.touch(b"k");
+        assert!(table.get(b"k").is_none());
+    }
+
+    #[test]
+    fn test_lfu_config() {
+        assert_eq!(
+            MaxMemoryPolicy::parse("ALLKEYS-LFU"),
+            Some(MaxMemoryPolicy::AllKeysLfu)
+        );
+        assert_eq!(MaxMemoryPolicy::parse("foo"), None);
+        for policy in MaxMemoryPolicy::ALL {
+            assert_eq!(MaxMemoryPolicy::parse(policy.name()), Some(*policy));
+        }
+
+        // Every access counts without log factor.
+        let mut table = ObjectTable::default();
+        let lfu = LfuConfig {
+            log_factor: 0,
+            decay_time: 0,
+        };
+        table.configure(MaxMemoryPolicy::AllKeysLfu, lfu);
+        assert_eq!(table.policy(), MaxMemoryPolicy::AllKeysLfu);
+        table.update(b"k", Some("embstr"));
+        for _ in 0..300 {
+            table.touch(b"k");
+        }
+        assert_eq!(table.get(b"k").unwrap().freq, u8::MAX);
+
+        // Decay by one per period of idle time.
+        let meta = ObjectMeta {
+            encoding: "embstr",
+            last_access: Instant::now() - std::time::Duration::from_secs(10 * 60),
+            counter: 20,
+        };
+        assert_eq!(meta.freq(LfuConfig::default()), 10);
+        assert_eq!(
+            meta.freq(LfuConfig {
+                log_factor: 10,
+                decay_time: 4
+            }),
+            18
+        );
+        assert_eq!(meta.freq(lfu), 20);
+    }
+
+    #[test]
+    fn test_eviction_candidate() {
+        let mut table = ObjectTable::default();
+        assert_eq!(table.eviction_candidate(), None);
+        table.update(b"a", Some("embstr"));
+        table.update(b"b", Some("embstr"));
+        table.objects.get_mut(b"a".as_slice()).unwrap().last_access -= Duration::from_secs(10);
+        assert_eq!(table.eviction_candidate(), None);
+
+        table.configure(MaxMemoryPolicy::AllKeysLru, LfuConfig::default());
+        assert_eq!(table.eviction_candidate().as_deref(), Some(b"a".as_slice()));
+
+        // The least frequently used one, though accessed recently.
+        table.configure(MaxMemoryPolicy::AllKeysLfu, LfuConfig::default());
+        table.objects.get_mut(b"a".as_slice()).unwrap().counter = 100;
+        assert_eq!(table.eviction_candidate().as_deref(), Some(b"b".as_slice()));
+
+        table.configure(MaxMemoryPolicy::AllKeysRandom, LfuConfig::default());
+        assert!(table.eviction_candidate().is_some());
+        table.update(b"a", None);
+        table.update(b"b", None);
+        assert_eq!(table.eviction_candidate(), None);
+    }
+}
@@ -0,0 +1,69 @@
+/// Simulated out of memory state, injected by DEBUG OOM in debug builds.
+///
+/// Let the error paths of writes be tested deterministically without actually
+/// exhausting memory.
+#[derive(Debug, Default)]
+pub(crate) struct OomInjection {
+    /// Cap of the estimated memory used by keys, in bytes.
+    ///
+    /// Writes are rejected once the used memory exceeds the cap.
+    limit: Option<usize>,
+
+    /// Count of following writes to reject regardless of the used memory.
+    fail_count: usize,
+}
+
+impl OomInjection {
+    #[cfg(debug_assertions)]
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    #[cfg(debug_assertions)]
+    pub fn fail_next(&mut self, count: usize) {
+        self.fail_count = count;
+    }
+
+    /// Check whether a write shall be rejected when `used` bytes are used.
+    ///
+    /// Consumes one injected failure if any.
+    pub fn check(&mut self, used: usize) -> bool {
+        if self.fail_count > 0 {
+            self.fail_count -= 1;
+            return true;
+        }
+        self.limit.is_some_and(|limit| used > limit)
+    }
+
+    /// Describe current state, for DEBUG OOM STATUS.
+    #[cfg(debug_assertions)]
+    pub fn status(&self) -> String {
+        let limit = match self.limit {
+            Some(v) => v.to_string(),
+            None => "off".to_string(),
+        };
+        format!("limit:{limit} fail_count:{}", self.fail_count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_oom_injection() {
+        let mut oom = OomInjection::default();
+        assert!(!oom.check(usize::MAX));
+
+        oom.fail_next(2);
+        assert!(oom.check(0));
+        assert!(oom.check(0));
+        assert!(!oom.check(0));
+
+        oom.set_limit(Some(100));
+        assert!(!oom.check(100));
+        assert!(oom.check(101));
+        oom.set_limit(None);
+        assert!(!oom.check(101));
+    }
+}
@@ -0,0 +1,1492 @@
+//! Write the dataset as RDB file, used by SAVE and full resynchronization of replicas,
+//! and load it back on replicas.
+//!
+//! The file follows RDB version 11 written by redis 7.2, so it can be loaded by redis
+//! and by replicas of this server:
+//!
+//! ```text
+//! "REDIS0011" | aux fields | SELECTDB 0 | RESIZEDB | keys ... | EOF | CRC64
+//! ```
+//!
+//! Each key is written as an optional expire time, the value type, the key and the
+//! value. All types in the storage are supported, in the plain types redis still
+//! loads: lists are written as `RDB_TYPE_LIST` instead of quicklists, while streams
+//! use the listpack nodes of `RDB_TYPE_STREAM_LISTPACKS_3` as there is no plain
+//! alternative. Expired keys are skipped.
+//!
+//! The hard cap on length of a stream, set by XADD CAP, has no place in the stream
+//! type. It is written as aux field `stream-max-len` right after the stream, which
+//! redis ignores.
+//!
+//! Loading accepts files of redis 7 and earlier as long as the value types are
+//! supported by the storage: strings in all encodings including LZF compressed ones,
+//! lists as plain lists or quicklists of listpacks, sets as plain, intset or
+//! listpack, sorted sets and hashes as plain or listpack, and streams. The ziplist
+//! encodings of redis 6 and earlier, modules and functions are rejected.
+//!
+//! DUMP serializes a single value the same way, as the payload RESTORE takes:
+//!
+//! ```text
+//! type | value | RDB version u16 | CRC64
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_redis::{BulkString, Value};
+
+use crate::storage::{
+    list::List,
+    sorted_set::SortedSet,
+    stream::{PendingEntry, RecordId, Stream},
+    string_bytes, string_object, Object, StorageInner, ValueCell,
+};
+
+const RDB_VERSION: &[u8] = b"0011";
+
+/// Version reported in the `redis-ver` aux field.
+const REDIS_VERSION: &str = "7.2.0";
+
+/// [`RDB_VERSION`] in the footer of DUMP payloads.
+const PAYLOAD_VERSION: u16 = 11;
+
+/// Aux field of the hard cap on length of the stream before it.
+const AUX_STREAM_MAX_LEN: &str = "stream-max-len";
+
+const OPCODE_IDLE: u8 = 0xF8;
+const OPCODE_FREQ: u8 = 0xF9;
+const OPCODE_AUX: u8 = 0xFA;
+const OPCODE_RESIZEDB: u8 = 0xFB;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EXPIRETIME: u8 = 0xFD;
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EOF: u8 = 0xFF;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+/// Special string encodings, in the lower 6 bits of a length starting with `0b11`.
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+
+/// Containers of quicklist nodes.
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+const QUICKLIST_NODE_PACKED: u64 = 2;
+
+/// Max count of records in one listpack node of stream, same as the default
+/// `stream-node-max-entries` in redis.
+const STREAM_NODE_MAX_ENTRIES: usize = 100;
+
+/// Flag of stream record deleted but still kept in node.
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+
+/// Flag of stream record having the same fields as the master entry of node.
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+
+/// Polynomial of CRC-64/Jones used by redis, reflected.
+const CRC64_POLY: u64 = 0x95ac9329ac4bc9b5;
+
+/// CRC-64/Jones checksum of `data`, the footer of RDB file.
+fn crc64(data: &[u8]) -> u64 {
+    let mut crc = 0u64;
+    for byte in data {
+        crc ^= *byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC64_POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// Parse `bytes` as integer if it is exactly the decimal form of one.
+///
+/// Such strings are stored as integers in RDB and listpacks, like redis does.
+fn parse_int(bytes: &[u8]) -> Option<i64> {
+    let s = std::str::from_utf8(bytes).ok()?;
+    s.parse::<i64>().ok().filter(|x| x.to_string() == s)
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Listpack, the compact list of strings and integers used in stream nodes.
+///
+/// ```text
+/// <total bytes: u32> <count: u16> <entry> ... <0xFF>
+/// ```
+///
+/// Each entry is its encoding and data, followed by the length of them encoded
+/// backwards so that the list can be iterated from the tail.
+#[derive(Debug, Default)]
+struct Listpack {
+    entries: Vec<u8>,
+    count: usize,
+}
+
+impl Listpack {
+    fn push_entry(&mut self, entry: &[u8]) {
+        self.entries.extend(entry);
+        // Backlen: 7 bits each byte, the most significant first, all bytes but the
+        // first have the high bit set.
+        let mut len = entry.len();
+        let mut groups = vec![];
+        loop {
+            groups.push((len & 127) as u8);
+            len >>= 7;
+            if len == 0 {
+                break;
+            }
+        }
+        for (i, x) in groups.iter().rev().enumerate() {
+            self.entries.push(if i == 0 { *x } else { x | 128 });
+        }
+        self.count += 1;
+    }
+
+    fn push_int(&mut self, v: i64) {
+        let entry = match v {
+            0..=127 => vec![v as u8],
+            -4096..=4095 => {
+                let u = (v & 0x1FFF) as u16;
+                vec![0xC0 | (u >> 8) as u8, u as u8]
+            }
+            _ if i16::try_from(v).is_ok() => [&[0xF1], &(v as i16).to_le_bytes()[..]].concat(),
+            -0x80_0000..=0x7F_FFFF => [&[0xF2], &(v as i32).to_le_bytes()[..3]].concat(),
+            _ if i32::try_from(v).is_ok() => [&[0xF3], &(v as i32).to_le_bytes()[..]].concat(),
+            _ => [&[0xF4], &v.to_le_bytes()[..]].concat(),
+        };
+        self.push_entry(&entry);
+    }
+
+    fn push_string(&mut self, s: &[u8]) {
+        if let Some(v) = parse_int(s) {
+            return self.push_int(v);
+        }
+        let len = s.len();
+        let mut entry = if len < 64 {
+            vec![0x80 | len as u8]
+        } else if len < 4096 {
+            vec![0xE0 | (len >> 8) as u8, len as u8]
+        } else {
+            [&[0xF0], &(len as u32).to_le_bytes()[..]].concat()
+        };
+        entry.extend(s);
+        self.push_entry(&entry);
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let total = 6 + self.entries.len() + 1;
+        let mut buf = Vec::with_capacity(total);
+        buf.extend((total as u32).to_le_bytes());
+        buf.extend((self.count.min(u16::MAX as usize) as u16).to_le_bytes());
+        buf.extend(self.entries);
+        buf.push(0xFF);
+        buf
+    }
+}
+
+/// Stream id as 16 bytes in big endian, the key of stream nodes and the ids in PEL.
+fn raw_id(id: RecordId) -> [u8; 16] {
+    let mut buf = [0u8; 16];
+    buf[..8].copy_from_slice(&id.0.to_be_bytes());
+    buf[8..].copy_from_slice(&id.1.to_be_bytes());
+    buf
+}
+
+#[derive(Debug, Default)]
+struct RdbWriter {
+    buf: Vec<u8>,
+}
+
+impl RdbWriter {
+    /// Write length `len` in 1, 2, 5 or 9 bytes.
+    fn write_len(&mut self, len: u64) {
+        if len < 1 << 6 {
+            self.buf.push(len as u8);
+        } else if len < 1 << 14 {
+            self.buf.push(0x40 | (len >> 8) as u8);
+            self.buf.push(len as u8);
+        } else if len <= u32::MAX as u64 {
+            self.buf.push(0x80);
+            self.buf.extend((len as u32).to_be_bytes());
+        } else {
+            self.buf.push(0x81);
+            self.buf.extend(len.to_be_bytes());
+        }
+    }
+
+    /// Write string `s`, in the integer encodings if it is a small integer.
+    fn write_string(&mut self, s: &[u8]) {
+        match parse_int(s) {
+            Some(v) if i8::try_from(v).is_ok() => {
+                self.buf.push(0xC0);
+                self.buf.extend((v as i8).to_le_bytes());
+            }
+            Some(v) if i16::try_from(v).is_ok() => {
+                self.buf.push(0xC1);
+                self.buf.extend((v as i16).to_le_bytes());
+            }
+            Some(v) if i32::try_from(v).is_ok() => {
+                self.buf.push(0xC2);
+                self.buf.extend((v as i32).to_le_bytes());
+            }
+            _ => {
+                self.write_len(s.len() as u64);
+                self.buf.extend(s);
+            }
+        }
+    }
+
+    fn write_millis(&mut self, millis: u64) {
+        self.buf.extend(millis.to_le_bytes());
+    }
+
+    fn write_aux(&mut self, key: &str, value: &str) {
+        self.buf.push(OPCODE_AUX);
+        self.write_string(key.as_bytes());
+        self.write_string(value.as_bytes());
+    }
+
+    /// Write `object` as its type and value, with `key` in between if any.
+    fn write_object(&mut self, key: Option<&[u8]>, object: &Object) {
+        self.buf.push(match object {
+            Object::Str(..) | Object::Int(..) => TYPE_STRING,
+            Object::List(..) => TYPE_LIST,
+            Object::Stream(..) => TYPE_STREAM_LISTPACKS_3,
+            Object::ZSet(..) => TYPE_ZSET_2,
+            Object::Hash(..) => TYPE_HASH,
+            Object::Set(..) => TYPE_SET,
+        });
+        if let Some(key) = key {
+            self.write_string(key);
+        }
+        match object {
+            Object::List(list) => self.write_list(list),
+            Object::Stream(stream) => self.write_stream(stream),
+            Object::ZSet(zset) => self.write_zset(zset),
+            Object::Hash(hash) => self.write_hash(hash),
+            Object::Set(set) => self.write_set(set),
+            v => self.write_string(&v.string().unwrap_or_default()),
+        }
+        if let Object::Stream(stream) = object {
+            if let Some(max_len) = stream.max_len() {
+                self.write_aux(AUX_STREAM_MAX_LEN, &max_len.to_string());
+            }
+        }
+    }
+
+    fn write_list(&mut self, list: &List) {
+        self.write_len(list.len() as u64);
+        for element in list.iter() {
+            self.write_string(element);
+        }
+    }
+
+    fn write_zset(&mut self, zset: &SortedSet) {
+        let members = zset.iter().collect::<Vec<_>>();
+        self.write_len(members.len() as u64);
+        for (member, score) in members {
+            self.write_string(member.as_bytes());
+            self.buf.extend(score.to_le_bytes());
+        }
+    }
+
+    fn write_hash(&mut self, hash: &HashMap<Vec<u8>, Vec<u8>>) {
+        self.write_len(hash.len() as u64);
+        for (field, value) in hash {
+            self.write_string(field);
+            self.write_string(value);
+        }
+    }
+
+    fn write_set(&mut self, set: &HashSet<Vec<u8>>) {
+        self.write_len(set.len() as u64);
+        for member in set {
+            self.write_string(member);
+        }
+    }
+
+    fn write_stream(&mut self, stream: &Stream) {
+        let records = stream.records().collect::<Vec<_>>();
+        let nodes = records.chunks(STREAM_NODE_MAX_ENTRIES).collect::<Vec<_>>();
+        self.write_len(nodes.len() as u64);
+        for node in &nodes {
+            let (master_ms, master_seq, master_values) = node[0];
+            let fields = |values: &[Value]| {
+                values
+                    .iter()
+                    .step_by(2)
+                    .map(|x| string_bytes(x).unwrap_or_default())
+                    .collect::<Vec<_>>()
+            };
+            let master_fields = fields(master_values);
+
+            // Master entry: count | deleted | num-fields | field ... | 0
+            let mut lp = Listpack::default();
+            lp.push_int(node.len() as i64);
+            lp.push_int(0);
+            lp.push_int(master_fields.len() as i64);
+            for field in &master_fields {
+                lp.push_string(field);
+            }
+            lp.push_int(0);
+
+            // Records: flags | ms-diff | seq-diff | [num-fields | field value ...] or
+            // [value ...] if same fields as master | lp-count
+            for (ms, seq, values) in node.iter() {
+                let same_fields = fields(values) == master_fields;
+                let num_fields = values.len() / 2;
+                lp.push_int(if same_fields {
+                    STREAM_ITEM_FLAG_SAMEFIELDS
+                } else {
+                    0
+                });
+                lp.push_int((ms - master_ms) as i64);
+                lp.push_int(seq.wrapping_sub(master_seq) as i64);
+                if same_fields {
+                    for value in values.iter().skip(1).step_by(2) {
+                        lp.push_string(&string_bytes(value).unwrap_or_default());
+                    }
+                    lp.push_int(num_fields as i64 + 3);
+                } else {
+                    lp.push_int(num_fields as i64);
+                    for x in values.iter() {
+                        lp.push_string(&string_bytes(x).unwrap_or_default());
+                    }
+                    lp.push_int(num_fields as i64 * 2 + 4);
+                }
+            }
+
+            self.write_string(&raw_id((master_ms, master_seq)));
+            self.write_string(&lp.into_bytes());
+        }
+
+        let first_id = records
+            .first()
+            .map(|(ms, seq, _)| (*ms, *seq))
+            .unwrap_or_default();
+        self.write_len(records.len() as u64);
+        for (ms, seq) in [
+            stream.last_generated_id(),
+            first_id,
+            stream.max_deleted_id(),
+        ] {
+            self.write_len(ms);
+            self.write_len(seq);
+        }
+        self.write_len(stream.entries_added());
+
+        // Consumers are not tracked by time, they are written as seen just now.
+        let now = unix_millis(SystemTime::now());
+        let groups = stream.groups().collect::<Vec<_>>();
+        self.write_len(groups.len() as u64);
+        for (name, group) in groups {
+            self.write_string(name.as_bytes());
+            let (ms, seq) = group.last_delivered_id();
+            self.write_len(ms);
+            self.write_len(seq);
+            // Unknown is -1 in redis.
+            self.write_len(group.entries_read().unwrap_or(u64::MAX));
+
+            let pending = group.pending().collect::<Vec<_>>();
+            self.write_len(pending.len() as u64);
+            for (id, entry) in pending {
+                self.buf.extend(raw_id(*id));
+                self.write_millis(entry.delivery_time);
+                self.write_len(entry.delivery_count);
+            }
+
+            let consumers = group.consumers().collect::<Vec<_>>();
+            self.write_len(consumers.len() as u64);
+            for (name, ids) in consumers {
+                self.write_string(name.as_bytes());
+                self.write_millis(now);
+                self.write_millis(now);
+                self.write_len(ids.len() as u64);
+                for id in ids {
+                    self.buf.extend(raw_id(*id));
+                }
+            }
+        }
+    }
+}
+
+/// Serialize all live keys in `storage` to a RDB file.
+///
+/// `used_memory` is reported in the `used-mem` aux field.
+pub(super) fn save(storage: &StorageInner, used_memory: usize) -> Vec<u8> {
+    let mut w = RdbWriter::default();
+    w.buf.extend(b"REDIS");
+    w.buf.extend(RDB_VERSION);
+
+    let now = SystemTime::now();
+    w.write_aux("redis-ver", REDIS_VERSION);
+    w.write_aux("redis-bits", "64");
+    w.write_aux("ctime", &(unix_millis(now) / 1000).to_string());
+    w.write_aux("used-mem", &used_memory.to_string());
+    w.write_aux("aof-base", "0");
+
+    let data = storage
+        .data
+        .iter()
+        .filter_map(|(key, cell)| Some((key, cell.live_value_ref()?, cell.expiration)))
+        .collect::<Vec<_>>();
+    let db_size = data.len();
+    let expires_size = data.iter().filter(|(_, _, exp)| exp.is_some()).count();
+
+    w.buf.push(OPCODE_SELECTDB);
+    w.write_len(0);
+    w.buf.push(OPCODE_RESIZEDB);
+    w.write_len(db_size as u64);
+    w.write_len(expires_size as u64);
+
+    for (key, value, expiration) in data {
+        if let Some(expiration) = expiration {
+            w.buf.push(OPCODE_EXPIRETIME_MS);
+            w.write_millis(unix_millis(expiration));
+        }
+        w.write_object(Some(key), value);
+    }
+
+    w.buf.push(OPCODE_EOF);
+    let crc = crc64(&w.buf);
+    w.buf.extend(crc.to_le_bytes());
+    w.buf
+}
+
+/// Write `rdb` to file at `path`.
+///
+/// The content is written to a temporary file in the same directory first, then
+/// renamed to `path`, so the file is never left partially written.
+pub(crate) fn write_file(path: &Path, rdb: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+    let result = std::fs::write(&tmp_path, rdb).and_then(|_| std::fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Decompress `input` of LZF, the compression of long strings in RDB.
+///
+/// Return `None` if `input` is corrupted or not decompressed to `len` bytes.
+fn lzf_decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 1 << 5 {
+            // Literal run of `ctrl + 1` bytes.
+            out.extend_from_slice(input.get(i..i + ctrl + 1)?);
+            i += ctrl + 1;
+            continue;
+        }
+        // Back reference: length in the high 3 bits, extended by the next byte if all
+        // set, followed by the low byte of offset.
+        let mut run = ctrl >> 5;
+        if run == 7 {
+            run += *input.get(i)? as usize;
+            i += 1;
+        }
+        let back = ((ctrl & 0x1F) << 8) + *input.get(i)? as usize + 1;
+        i += 1;
+        let start = out.len().checked_sub(back)?;
+        for k in 0..run + 2 {
+            out.push(out[start + k]);
+        }
+    }
+    (out.len() == len).then_some(out)
+}
+
+/// An entry in listpack.
+#[derive(Debug, Clone, PartialEq)]
+enum ListpackEntry {
+    Int(i64),
+    Str(Vec<u8>),
+}
+
+impl ListpackEntry {
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            ListpackEntry::Int(v) => v.to_string().into_bytes(),
+            ListpackEntry::Str(s) => s,
+        }
+    }
+}
+
+/// Parse all entries in listpack `lp`, see [`Listpack`].
+fn listpack_entries(lp: &[u8]) -> Result<Vec<ListpackEntry>, String> {
+    let invalid = || "invalid listpack".to_string();
+    let mut r = RdbReader::new(lp.get(6..).ok_or_else(invalid)?);
+    let mut entries = vec![];
+    loop {
+        let start = r.pos;
+        let entry = match r.read_u8()? {
+            0xFF => return Ok(entries),
+            v @ 0x00..=0x7F => ListpackEntry::Int(v as i64),
+            v @ 0x80..=0xBF => ListpackEntry::Str(r.read_bytes((v & 0x3F) as usize)?.to_vec()),
+            v @ 0xC0..=0xDF => {
+                let u = ((v as i64 & 0x1F) << 8) | r.read_u8()? as i64;
+                ListpackEntry::Int(if u & 0x1000 != 0 { u - 0x2000 } else { u })
+            }
+            v @ 0xE0..=0xEF => {
+                let len = ((v as usize & 0x0F) << 8) | r.read_u8()? as usize;
+                ListpackEntry::Str(r.read_bytes(len)?.to_vec())
+            }
+            0xF0 => {
+                let len = u32::from_le_bytes(r.read_array()?) as usize;
+                ListpackEntry::Str(r.read_bytes(len)?.to_vec())
+            }
+            0xF1 => ListpackEntry::Int(i16::from_le_bytes(r.read_array()?) as i64),
+            0xF2 => {
+                let [a, b, c] = r.read_array()?;
+                ListpackEntry::Int((i32::from_le_bytes([0, a, b, c]) >> 8) as i64)
+            }
+            0xF3 => ListpackEntry::Int(i32::from_le_bytes(r.read_array()?) as i64),
+            0xF4 => ListpackEntry::Int(i64::from_le_bytes(r.read_array()?)),
+            _ => return Err(invalid()),
+        };
+        // Skip the backlen, 7 bits each byte.
+        let mut len = r.pos - start;
+        loop {
+            r.read_u8()?;
+            len >>= 7;
+            if len == 0 {
+                break;
+            }
+        }
+        entries.push(entry);
+    }
+}
+
+/// Take the next entry of listpack as integer.
+fn next_int(entries: &mut impl Iterator<Item = ListpackEntry>) -> Result<i64, String> {
+    match entries.next() {
+        Some(ListpackEntry::Int(v)) => Ok(v),
+        _ => Err("invalid stream listpack".to_string()),
+    }
+}
+
+/// Take the next entry of listpack as string.
+fn next_bytes(entries: &mut impl Iterator<Item = ListpackEntry>) -> Result<Vec<u8>, String> {
+    entries
+        .next()
+        .map(ListpackEntry::into_bytes)
+        .ok_or_else(|| "invalid stream listpack".to_string())
+}
+
+/// Parse stream id of 16 bytes in big endian, see [`raw_id`].
+fn parse_raw_id(bytes: &[u8]) -> Result<RecordId, String> {
+    let bytes: [u8; 16] = bytes
+        .try_into()
+        .map_err(|_| "invalid stream id".to_string())?;
+    let (ms, seq) = bytes.split_at(8);
+    Ok((
+        u64::from_be_bytes(ms.try_into().unwrap()),
+        u64::from_be_bytes(seq.try_into().unwrap()),
+    ))
+}
+
+fn bulk_string(bytes: Vec<u8>) -> Value {
+    Value::BulkString(BulkString::new(bytes))
+}
+
+/// Parse all members in intset `blob`, the set of integers in one of the widths.
+///
+/// ```text
+/// <width: u32> <count: u32> <int> ...
+/// ```
+fn intset_members(blob: &[u8]) -> Result<HashSet<Vec<u8>>, String> {
+    let mut r = RdbReader::new(blob);
+    let width = u32::from_le_bytes(r.read_array()?);
+    let count = u32::from_le_bytes(r.read_array()?);
+    let mut set = HashSet::new();
+    for _ in 0..count {
+        let v = match width {
+            2 => i16::from_le_bytes(r.read_array()?) as i64,
+            4 => i32::from_le_bytes(r.read_array()?) as i64,
+            8 => i64::from_le_bytes(r.read_array()?),
+            v => return Err(format!("invalid intset width {v}")),
+        };
+        set.insert(v.to_string().into_bytes());
+    }
+    Ok(set)
+}
+
+#[derive(Debug)]
+struct RdbReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> RdbReader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .pos
+            .checked_add(n)
+            .and_then(|end| self.buf.get(self.pos..end))
+            .ok_or_else(|| "unexpected end of file".to_string())?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.read_bytes(N)?.try_into().unwrap())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_millis(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    /// Read length written by [`RdbWriter::write_len`].
+    ///
+    /// Return `Err` of the special encoding if it is a string in special encoding.
+    fn read_len_or_encoding(&mut self) -> Result<Result<u64, u8>, String> {
+        let first = self.read_u8()?;
+        let len = match first >> 6 {
+            0 => (first & 0x3F) as u64,
+            1 => ((first as u64 & 0x3F) << 8) | self.read_u8()? as u64,
+            3 => return Ok(Err(first & 0x3F)),
+            _ if first == 0x80 => u32::from_be_bytes(self.read_array()?) as u64,
+            _ if first == 0x81 => u64::from_be_bytes(self.read_array()?),
+            _ => return Err(format!("invalid length encoding {first:#x}")),
+        };
+        Ok(Ok(len))
+    }
+
+    fn read_len(&mut self) -> Result<u64, String> {
+        self.read_len_or_encoding()?
+            .map_err(|_| "unexpected string encoding".to_string())
+    }
+
+    /// Read length of something to iterate.
+    ///
+    /// Unlike [`RdbReader::read_len`], it is capped by the bytes left so corrupted
+    /// lengths do not allocate much.
+    fn read_count(&mut self) -> Result<usize, String> {
+        let len = self.read_len()?;
+        if len > (self.buf.len() - self.pos) as u64 {
+            return Err(format!("invalid length {len}"));
+        }
+        Ok(len as usize)
+    }
+
+    /// Read string written by [`RdbWriter::write_string`], or compressed by LZF.
+    fn read_string(&mut self) -> Result<Vec<u8>, String> {
+        let s = match self.read_len_or_encoding()? {
+            Ok(len) => {
+                let len = usize::try_from(len).map_err(|_| format!("invalid length {len}"))?;
+                self.read_bytes(len)?.to_vec()
+            }
+            Err(ENC_INT8) => (self.read_u8()? as i8).to_string().into_bytes(),
+            Err(ENC_INT16) => i16::from_le_bytes(self.read_array()?)
+                .to_string()
+                .into_bytes(),
+            Err(ENC_INT32) => i32::from_le_bytes(self.read_array()?)
+                .to_string()
+                .into_bytes(),
+            Err(ENC_LZF) => {
+                let compressed = self.read_count()?;
+                let len = self.read_len()? as usize;
+                let compressed = self.read_bytes(compressed)?;
+                lzf_decompress(compressed, len)
+                    .ok_or_else(|| "invalid LZF compressed string".to_string())?
+            }
+            Err(v) => return Err(format!("unknown string encoding {v}")),
+        };
+        Ok(s)
+    }
+
+    /// Read name of stream consumer group or consumer, invalid UTF-8 is replaced.
+    fn read_name(&mut self) -> Result<String, String> {
+        Ok(String::from_utf8_lossy(&self.read_string()?).to_string())
+    }
+
+    /// Read score of `TYPE_ZSET`, a string of length in one byte with special lengths
+    /// for NaN and infinities.
+    fn read_double_string(&mut self) -> Result<f64, String> {
+        match self.read_u8()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => {
+                let s = self.read_bytes(len as usize)?;
+                std::str::from_utf8(s)
+                    .ok()
+                    .and_then(|x| x.parse().ok())
+                    .ok_or_else(|| "invalid score".to_string())
+            }
+        }
+    }
+
+    /// Read the value of an object in type `kind`.
+    fn read_object(&mut self, kind: u8) -> Result<Object, String> {
+        let object = match kind {
+            TYPE_STRING => string_object(self.read_string()?),
+            TYPE_LIST | TYPE_LIST_QUICKLIST_2 => Object::List(self.read_list(kind)?),
+            TYPE_SET | TYPE_SET_INTSET | TYPE_SET_LISTPACK => Object::Set(self.read_set(kind)?),
+            TYPE_ZSET | TYPE_ZSET_2 | TYPE_ZSET_LISTPACK => Object::ZSet(self.read_zset(kind)?),
+            TYPE_HASH | TYPE_HASH_LISTPACK => Object::Hash(self.read_hash(kind)?),
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                Object::Stream(self.read_stream(kind)?)
+            }
+            v => return Err(format!("unsupported RDB type {v}")),
+        };
+        Ok(object)
+    }
+
+    fn read_list(&mut self, kind: u8) -> Result<List, String> {
+        let mut list = List::default();
+        if kind == TYPE_LIST {
+            for _ in 0..self.read_count()? {
+                list.push(self.read_string()?, true);
+            }
+            return Ok(list);
+        }
+        for _ in 0..self.read_count()? {
+            match self.read_len()? {
+                QUICKLIST_NODE_PLAIN => list.push(self.read_string()?, true),
+                QUICKLIST_NODE_PACKED => {
+                    for entry in listpack_entries(&self.read_string()?)? {
+                        list.push(entry.into_bytes(), true);
+                    }
+                }
+                v => return Err(format!("unknown quicklist container {v}")),
+            }
+        }
+        Ok(list)
+    }
+
+    fn read_set(&mut self, kind: u8) -> Result<HashSet<Vec<u8>>, String> {
+        match kind {
+            TYPE_SET_INTSET => intset_members(&self.read_string()?),
+            TYPE_SET_LISTPACK => Ok(listpack_entries(&self.read_string()?)?
+                .into_iter()
+                .map(ListpackEntry::into_bytes)
+                .collect()),
+            _ => (0..self.read_count()?)
+                .map(|_| self.read_string())
+                .collect(),
+        }
+    }
+
+    fn read_hash(&mut self, kind: u8) -> Result<HashMap<Vec<u8>, Vec<u8>>, String> {
+        let mut hash = HashMap::new();
+        if kind == TYPE_HASH_LISTPACK {
+            let mut entries = listpack_entries(&self.read_string()?)?.into_iter();
+            while let Some(field) = entries.next() {
+                let value = entries
+                    .next()
+                    .ok_or_else(|| "invalid hash listpack".to_string())?;
+                hash.insert(field.into_bytes(), value.into_bytes());
+            }
+            return Ok(hash);
+        }
+        for _ in 0..self.read_count()? {
+            let field = self.read_string()?;
+            hash.insert(field, self.read_string()?);
+        }
+        Ok(hash)
+    }
+
+    fn read_zset(&mut self, kind: u8) -> Result<SortedSet, String> {
+        let mut zset = SortedSet::default();
+        let member = |x: Vec<u8>| String::from_utf8_lossy(&x).to_string();
+        if kind == TYPE_ZSET_LISTPACK {
+            let mut entries = listpack_entries(&self.read_string()?)?.into_iter();
+            while let Some(m) = entries.next() {
+                let score = match entries.next() {
+                    Some(ListpackEntry::Int(v)) => v as f64,
+                    Some(ListpackEntry::Str(s)) => std::str::from_utf8(&s)
+                        .ok()
+                        .and_then(|x| x.parse().ok())
+                        .ok_or_else(|| "invalid score".to_string())?,
+                    None => return Err("invalid sorted set listpack".to_string()),
+                };
+                zset.insert(member(m.into_bytes()), score);
+            }
+            return Ok(zset);
+        }
+        for _ in 0..self.read_count()? {
+            let m = member(self.read_string()?);
+            let score = if kind == TYPE_ZSET {
+                self.read_double_string()?
+            } else {
+                f64::from_le_bytes(self.read_array()?)
+            };
+            zset.insert(m, score);
+        }
+        Ok(zset)
+    }
+
+    /// Read stream written by [`RdbWriter::write_stream`], or the older versions of it.
+    fn read_stream(&mut self, kind: u8) -> Result<Stream, String> {
+        let mut stream = Stream::new();
+        for _ in 0..self.read_count()? {
+            let (master_ms, master_seq) = parse_raw_id(&self.read_string()?)?;
+            let mut lp = listpack_entries(&self.read_string()?)?.into_iter();
+            let count = next_int(&mut lp)? + next_int(&mut lp)?;
+            let master_fields = (0..next_int(&mut lp)?)
+                .map(|_| next_bytes(&mut lp))
+                .collect::<Result<Vec<_>, _>>()?;
+            next_int(&mut lp)?;
+            for _ in 0..count {
+                let flags = next_int(&mut lp)?;
+                let ms = master_ms.wrapping_add(next_int(&mut lp)? as u64);
+                let seq = master_seq.wrapping_add(next_int(&mut lp)? as u64);
+                let mut values = vec![];
+                if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+                    for field in master_fields.iter() {
+                        values.push(bulk_string(field.clone()));
+                        values.push(bulk_string(next_bytes(&mut lp)?));
+                    }
+                } else {
+                    for _ in 0..next_int(&mut lp)? * 2 {
+                        values.push(bulk_string(next_bytes(&mut lp)?));
+                    }
+                }
+                next_int(&mut lp)?;
+                if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+                    stream
+                        .add_entry(ms, seq, values)
+                        .map_err(|_| format!("stream id {ms}-{seq} out of order"))?;
+                }
+            }
+        }
+
+        self.read_len()?;
+        let last_id = (self.read_len()?, self.read_len()?);
+        let (entries_added, max_deleted_id) = if kind >= TYPE_STREAM_LISTPACKS_2 {
+            // The first id is known from records.
+            self.read_len()?;
+            self.read_len()?;
+            let max_deleted_id = (self.read_len()?, self.read_len()?);
+            (Some(self.read_len()?), Some(max_deleted_id))
+        } else {
+            (None, None)
+        };
+        stream
+            .set_id(last_id, entries_added, max_deleted_id)
+            .map_err(|_| "invalid stream metadata".to_string())?;
+
+        for _ in 0..self.read_count()? {
+            let name = self.read_name()?;
+            let last_delivered_id = (self.read_len()?, self.read_len()?);
+            let entries_read = if kind >= TYPE_STREAM_LISTPACKS_2 {
+                Some(self.read_len()?).filter(|x| *x != u64::MAX)
+            } else {
+                None
+            };
+            let mut pending = BTreeMap::new();
+            for _ in 0..self.read_count()? {
+                let id = parse_raw_id(self.read_bytes(16)?)?;
+                let delivery_time = self.read_millis()?;
+                let delivery_count = self.read_len()?;
+                pending.insert(id, (delivery_time, delivery_count));
+            }
+            let mut consumers = vec![];
+            let mut owned = BTreeMap::new();
+            for _ in 0..self.read_count()? {
+                let consumer = self.read_name()?;
+                // Seen time, and active time since version 3.
+                self.read_millis()?;
+                if kind >= TYPE_STREAM_LISTPACKS_3 {
+                    self.read_millis()?;
+                }
+                for _ in 0..self.read_count()? {
+                    let id = parse_raw_id(self.read_bytes(16)?)?;
+                    let (delivery_time, delivery_count) = pending
+                        .get(&id)
+                        .copied()
+                        .ok_or_else(|| "consumer pending record not in group PEL".to_string())?;
+                    owned.insert(
+                        id,
+                        PendingEntry {
+                            consumer: consumer.clone(),
+                            delivery_time,
+                            delivery_count,
+                        },
+                    );
+                }
+                consumers.push(consumer);
+            }
+            stream.restore_group(name, last_delivered_id, entries_read, owned, consumers);
+        }
+        Ok(stream)
+    }
+}
+
+/// Load all keys in RDB file `rdb`.
+///
+/// Only one database is supported, keys in other databases are skipped.
+pub(super) fn load(rdb: &[u8]) -> Result<StorageInner, String> {
+    let mut r = RdbReader::new(rdb);
+    if r.read_bytes(5)? != b"REDIS" {
+        return Err("invalid RDB header".to_string());
+    }
+    let version = std::str::from_utf8(r.read_bytes(4)?)
+        .ok()
+        .and_then(|x| x.parse::<u32>().ok())
+        .ok_or_else(|| "invalid RDB version".to_string())?;
+    if version > 11 {
+        return Err(format!("unsupported RDB version {version}"));
+    }
+
+    let mut storage = StorageInner::default();
+    let mut db = 0;
+    let mut expiration = None;
+    // The key just loaded, aux fields of a value follow it.
+    let mut last_key = None;
+    loop {
+        let kind = r.read_u8()?;
+        match kind {
+            OPCODE_EOF => break,
+            OPCODE_AUX => {
+                let name = r.read_string()?;
+                let value = r.read_string()?;
+                let object = last_key
+                    .as_ref()
+                    .and_then(|x| storage.data.get_mut(x))
+                    .and_then(|x| Arc::get_mut(&mut x.value));
+                if let Some(object) = object {
+                    load_aux(object, &name, &value)?;
+                }
+            }
+            OPCODE_SELECTDB => db = r.read_len()?,
+            OPCODE_RESIZEDB => {
+                r.read_len()?;
+                r.read_len()?;
+            }
+            OPCODE_IDLE => {
+                r.read_len()?;
+            }
+            OPCODE_FREQ => {
+                r.read_u8()?;
+            }
+            OPCODE_EXPIRETIME_MS => {
+                expiration = Some(UNIX_EPOCH + Duration::from_millis(r.read_millis()?))
+            }
+            OPCODE_EXPIRETIME => {
+                let secs = u32::from_le_bytes(r.read_array()?);
+                expiration = Some(UNIX_EPOCH + Duration::from_secs(secs as u64));
+            }
+            kind => {
+                let key = r.read_string()?;
+                let value = Arc::new(r.read_object(kind)?);
+                let expiration = expiration.take();
+                last_key = None;
+                if db == 0 {
+                    storage
+                        .data
+                        .insert(key.clone(), ValueCell { value, expiration });
+                    last_key = Some(key);
+                }
+            }
+        }
+    }
+
+    // Checksum is added since version 5, zero if disabled.
+    if version >= 5 {
+        let content = &rdb[..r.pos];
+        let crc = u64::from_le_bytes(r.read_array()?);
+        if crc != 0 && crc != crc64(content) {
+            return Err("RDB checksum mismatch".to_string());
+        }
+    }
+    Ok(storage)
+}
+
+/// Apply aux field `name` of `object` with `value`, unknown fields are ignored.
+fn load_aux(object: &mut Object, name: &[u8], value: &[u8]) -> Result<(), String> {
+    match (object, name) {
+        (Object::Stream(stream), name) if name == AUX_STREAM_MAX_LEN.as_bytes() => {
+            let max_len = std::str::from_utf8(value)
+                .ok()
+                .and_then(|x| x.parse::<usize>().ok())
+                .ok_or_else(|| "invalid stream max length".to_string())?;
+            stream.set_max_len(max_len);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Serialize `key` in `storage` as the payload of DUMP.
+///
+/// Return `None` if `key` not present or expired.
+pub(super) fn dump(storage: &StorageInner, key: &[u8]) -> Option<Vec<u8>> {
+    let object = storage.data.get(key)?.live_value_ref()?;
+    let mut w = RdbWriter::default();
+    w.write_object(None, object);
+    w.buf.extend(PAYLOAD_VERSION.to_le_bytes());
+    let crc = crc64(&w.buf);
+    w.buf.extend(crc.to_le_bytes());
+    Some(w.buf)
+}
+
+/// Check the footer of `payload` of DUMP, return the object part of it.
+///
+/// Return `None` if the payload is written by a newer RDB version, or the checksum
+/// is wrong.
+pub(super) fn payload_object(payload: &[u8]) -> Option<&[u8]> {
+    let (content, crc) = payload.split_at_checked(payload.len().checked_sub(8)?)?;
+    let (object, version) = content.split_at_checked(content.len().checked_sub(2)?)?;
+    let version = u16::from_le_bytes(version.try_into().unwrap());
+    let crc = u64::from_le_bytes(crc.try_into().unwrap());
+    (version <= PAYLOAD_VERSION && crc == crc64(content)).then_some(object)
+}
+
+/// Read the object part of a DUMP payload, see [`payload_object`].
+pub(super) fn restore(object: &[u8]) -> Result<Object, String> {
+    let mut r = RdbReader::new(object);
+    let kind = r.read_u8()?;
+    let mut object = r.read_object(kind)?;
+    while r.pos != r.buf.len() && r.buf[r.pos] == OPCODE_AUX {
+        r.pos += 1;
+        let name = r.read_string()?;
+        let value = r.read_string()?;
+        load_aux(&mut object, &name, &value)?;
+    }
+    if r.pos != r.buf.len() {
+        return Err("trailing bytes".to_string());
+    }
+    Ok(object)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn zset_of<'a>(storage: &'a StorageInner, key: &str) -> &'a SortedSet {
+        match storage.data[key.as_bytes()].value.as_ref() {
+            Object::ZSet(zset) => zset,
+            v => panic!("not a sorted set: {v:?}"),
+        }
+    }
+
+    #[test]
+    fn test_crc64() {
+        assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+        assert_eq!(crc64(b""), 0);
+    }
+
+    #[test]
+    fn test_encoding() {
+        let mut w = RdbWriter::default();
+        w.write_len(10);
+        w.write_len(700);
+        w.write_len(20000);
+        assert_eq!(w.buf, [0x0A, 0x42, 0xBC, 0x80, 0x00, 0x00, 0x4E, 0x20]);
+
+        let mut w = RdbWriter::default();
+        w.write_string(b"64");
+        w.write_string(b"-300");
+        w.write_string(b"0123");
+        assert_eq!(
+            w.buf,
+            [0xC0, 0x40, 0xC1, 0xD4, 0xFE, 0x04, b'0', b'1', b'2', b'3']
+        );
+
+        let mut lp = Listpack::default();
+        lp.push_string(b"hello");
+        lp.push_string(b"-1");
+        lp.push_int(1000);
+        assert_eq!(
+            lp.into_bytes(),
+            [
+                0x14, 0, 0, 0, 3, 0, 0x85, b'h', b'e', b'l', b'l', b'o', 6, 0xDF, 0xFF, 2, 0xC3,
+                0xE8, 2, 0xFF
+            ]
+        );
+
+        // Long entry with 2 bytes backlen.
+        let mut lp = Listpack::default();
+        lp.push_string(&[b'a'; 200]);
+        let bytes = lp.into_bytes();
+        assert_eq!(&bytes[6..8], &[0xE0, 200]);
+        assert_eq!(&bytes[208..], &[0x01, 0xCA, 0xFF]);
+    }
+
+    #[test]
+    fn test_save() {
+        let s = |x: &str| Value::BulkString(BulkString::new(x));
+        let expiration = UNIX_EPOCH + Duration::from_millis(4_000_000_000_000);
+        let mut zset = SortedSet::default();
+        zset.insert("m".into(), 1.5);
+        let mut stream = Stream::new();
+        stream.add_entry(1, 1, vec![s("f"), s("v")]).unwrap();
+        stream.add_entry(1, 2, vec![s("f"), s("w")]).unwrap();
+        stream.add_entry(2, 0, vec![s("g"), s("x")]).unwrap();
+        let storage = StorageInner {
+            data: HashMap::from([
+                (
+                    b"n".to_vec(),
+                    ValueCell {
+                        value: Arc::new(Object::Int(12)),
+                        expiration: Some(expiration),
+                    },
+                ),
+                (
+                    b"s".to_vec(),
+                    ValueCell {
+                        value: Arc::new(Object::Stream(stream)),
+                        expiration: None,
+                    },
+                ),
+                (
+                    b"z".to_vec(),
+                    ValueCell {
+                        value: Arc::new(Object::ZSet(zset)),
+                        expiration: None,
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        let rdb = save(&storage, 1024);
+        assert!(rdb.starts_with(b"REDIS0011\xFA\x09redis-ver\x057.2.0"));
+        let (content, crc) = rdb.split_at(rdb.len() - 8);
+        assert_eq!(crc, crc64(content).to_le_bytes());
+
+        // Keys in the same map are written in any order.
+        let contains = |part: &[u8]| content.windows(part.len()).any(|x| x == part);
+        assert!(contains(&[OPCODE_SELECTDB, 0, OPCODE_RESIZEDB, 3, 1]));
+        // String with expire time.
+        let mut expected = vec![OPCODE_EXPIRETIME_MS];
+        expected.extend(4_000_000_000_000u64.to_le_bytes());
+        expected.extend([TYPE_STRING, 1, b'n', 0xC0, 12]);
+        assert!(contains(&expected));
+        // Sorted set.
+        let mut expected = vec![TYPE_ZSET_2, 1, b'z', 1, 1, b'm'];
+        expected.extend(1.5f64.to_le_bytes());
+        assert!(contains(&expected));
+        // Stream of one node.
+        let mut expected = vec![TYPE_STREAM_LISTPACKS_3, 1, b's', 1, 16];
+        expected.extend(raw_id((1, 1)));
+        expected.extend([57, 57, 0, 0, 0, 22, 0]);
+        expected.extend([3, 1, 0, 1, 1, 1, 0x81, b'f', 2, 0, 1]);
+        expected.extend([2, 1, 0, 1, 0, 1, 0x81, b'v', 2, 4, 1]);
+        expected.extend([2, 1, 0, 1, 1, 1, 0x81, b'w', 2, 4, 1]);
+        expected.extend([0, 1, 1, 1, 0xDF, 0xFF, 2, 1, 1]);
+        expected.extend([0x81, b'g', 2, 0x81, b'x', 2, 6, 1, 0xFF]);
+        expected.extend([3, 2, 0, 1, 1, 0, 0, 3, 0]);
+        assert!(contains(&expected));
+    }
+
+    #[test]
+    fn test_dump() {
+        let s = |x: &str| Value::BulkString(BulkString::new(x));
+        let mut stream = Stream::new();
+        stream.add_entry(1, 1, vec![s("f"), s("v")]).unwrap();
+        let storage = StorageInner {
+            data: HashMap::from([
+                (
+                    b"str".to_vec(),
+                    ValueCell {
+                        value: Arc::new(Object::Str(b"bar".to_vec())),
+                        expiration: None,
+                    },
+                ),
+                (
+                    b"list".to_vec(),
+                    ValueCell {
+                        value: Arc::new(Object::List(List::from_iter([
+                            b"a".to_vec(),
+                            b"1".to_vec(),
+                        ]))),
+                        expiration: None,
+                    },
+                ),
+                (
+                    b"s".to_vec(),
+                    ValueCell {
+                        value: Arc::new(Object::Stream(stream)),
+                        expiration: None,
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(dump(&storage, b"missing"), None);
+
+        // Same as DUMP of redis 7.2.
+        let payload = dump(&storage, b"str").unwrap();
+        assert!(payload.starts_with(b"\x00\x03bar\x0b\x00"));
+        let object = payload_object(&payload).unwrap();
+        assert!(matches!(restore(object), Ok(Object::Str(v)) if v == b"bar"));
+
+        let payload = dump(&storage, b"list").unwrap();
+        let object = payload_object(&payload).unwrap();
+        let Ok(Object::List(list)) = restore(object) else {
+            panic!("list not restored");
+        };
+        assert_eq!(list.len(), 2);
+
+        let payload = dump(&storage, b"s").unwrap();
+        let Ok(Object::Stream(stream)) = restore(payload_object(&payload).unwrap()) else {
+            panic!("stream not restored");
+        };
+        assert_eq!(stream.records().count(), 1);
+
+        let mut bad = payload.clone();
+        bad[1] ^= 1;
+        assert_eq!(payload_object(&bad), None);
+        let mut newer = payload[..payload.len() - 10].to_vec();
+        newer.extend(12u16.to_le_bytes());
+        newer.extend(crc64(&newer).to_le_bytes());
+        assert_eq!(payload_object(&newer), None);
+        assert_eq!(payload_object(b"\x0b"), None);
+        assert!(restore(b"\x00\x05bar").is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let s = |x: &str| Value::BulkString(BulkString::new(x));
+        let expiration = UNIX_EPOCH + Duration::from_millis(4_000_000_000_000);
+        let mut zset = SortedSet::default();
+        zset.insert("m".into(), 1.5);
+        zset.insert("n".into(), f64::NEG_INFINITY);
+        let mut stream = Stream::new();
+        stream.add_entry(1, 1, vec![s("f"), s("v")]).unwrap();
+        stream.add_entry(1, 2, vec![s("f"), s("w")]).unwrap();
+        stream.add_entry(2, 0, vec![s("g"), s("x")]).unwrap();
+        let pending = PendingEntry {
+            consumer: "c".into(),
+            delivery_time: 7,
+            delivery_count: 2,
+        };
+        stream.restore_group(
+            "g".into(),
+            (1, 2),
+            Some(2),
+            BTreeMap::from([((1, 2), pending.clone())]),
+            vec!["c".into(), "idle".into()],
+        );
+        let list = List::from_iter([b"a".to_vec(), vec![b'b'; 100]]);
+        let hash = HashMap::from([(b"f".to_vec(), b"1".to_vec()), (b"g".to_vec(), vec![])]);
+        let set = HashSet::from([b"a".to_vec(), b"-70000".to_vec()]);
+        let storage = StorageInner {
+            data: HashMap::from([
+                (
+                    b"n".to_vec(),
+                    ValueCell {
+                        value: Arc::new(Object::Int(-300)),
+                        expiration: Some(expiration),
+                    },
+                ),
+                (
+                    b"l".to_vec(),
+                    ValueCell {
+                        value: Arc::new(Object::List(list.clone())),
+                        expiration: None,
+                    },
+                ),
+                (
+                    b"s".to_vec(),
+                    ValueCell {
+                        value: Arc::new(Object::Stream(stream)),
+                        expiration: Some(expiration),
+                    },
+                ),
+                (
+                    b"z".to_vec(),
+                    ValueCell {
+                        value: Arc::new(Object::ZSet(zset)),
+                        expiration: None,
+                    },
+                ),
+                (
+                    b"h".to_vec(),
+                    ValueCell {
+                        value: Arc::new(Object::Hash(hash.clone())),
+                        expiration: None,
+                    },
+                ),
+                (
+                    b"t".to_vec(),
+                    ValueCell {
+                        value: Arc::new(Object::Set(set.clone())),
+                        expiration: None,
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+        let stream_ref = |storage: &StorageInner| match storage.data[b"s".as_slice()].value.as_ref()
+        {
+            Object::Stream(s) => s.clone(),
+            v => panic!("not a stream: {v:?}"),
+        };
+
+        let loaded = load(&save(&storage, 0)).unwrap();
+        assert!(matches!(
+            loaded.data[b"n".as_slice()].value.as_ref(),
+            Object::Int(-300)
+        ));
+        assert_eq!(loaded.data[b"n".as_slice()].expiration, Some(expiration));
+        assert!(
+            matches!(loaded.data[b"l".as_slice()].value.as_ref(), Object::List(l) if *l == list)
+        );
+        assert_eq!(
+            zset_of(&loaded, "z").iter().collect::<Vec<_>>(),
+            [("n", f64::NEG_INFINITY), ("m", 1.5)]
+        );
+        assert!(
+            matches!(loaded.data[b"h".as_slice()].value.as_ref(), Object::Hash(h) if *h == hash)
+        );
+        assert!(matches!(loaded.data[b"t".as_slice()].value.as_ref(), Object::Set(t) if *t == set));
+        // Streams expire like other types.
+        assert_eq!(loaded.data[b"s".as_slice()].expiration, Some(expiration));
+        let stream = stream_ref(&loaded);
+        assert_eq!(
+            stream.records().collect::<Vec<_>>(),
+            stream_ref(&storage).records().collect::<Vec<_>>()
+        );
+        assert_eq!(stream.last_generated_id(), (2, 0));
+        assert_eq!(stream.entries_added(), 3);
+        let (name, group) = stream.groups().next().unwrap();
+        assert_eq!(name, "g");
+        assert_eq!(group.last_delivered_id(), (1, 2));
+        assert_eq!(group.entries_read(), Some(2));
+        assert_eq!(group.pending().collect::<Vec<_>>(), [(&(1, 2), &pending)]);
+        assert_eq!(group.consumers().count(), 2);
+
+        // Corrupted content is rejected by checksum.
+        let mut rdb = save(&storage, 0);
+        let len = rdb.len();
+        rdb[len - 10] ^= 1;
+        assert!(load(&rdb).is_err());
+        assert!(load(b"REDIS0011\xFE").is_err());
+    }
+
+    #[test]
+    fn test_load_redis_encodings() {
+        // LZF: literal "abc", then back reference of 3 bytes at distance 3.
+        assert_eq!(
+            lzf_decompress(&[2, b'a', b'b', b'c', 0x20, 2], 6),
+            Some(b"abcabc".to_vec())
+        );
+        assert_eq!(lzf_decompress(&[0x20, 0], 3), None);
+
+        let mut lp = Listpack::default();
+        lp.push_string(b"x");
+        lp.push_int(-1000);
+        lp.push_int(100_000);
+        lp.push_string(&[b'y'; 70]);
+        assert_eq!(
+            listpack_entries(&lp.into_bytes()).unwrap(),
+            [
+                ListpackEntry::Str(b"x".to_vec()),
+                ListpackEntry::Int(-1000),
+                ListpackEntry::Int(100_000),
+                ListpackEntry::Str(vec![b'y'; 70]),
+            ]
+        );
+
+        // Quicklist of a packed node, sorted set in listpack and in the old plain
+        // type, with a key compressed by LZF, hash in listpack, sets in intset and
+        // listpack, and no checksum.
+        let mut w = RdbWriter::default();
+        w.buf.extend(b"REDIS0009");
+        w.buf.push(TYPE_LIST_QUICKLIST_2);
+        w.write_string(b"l");
+        w.write_len(1);
+        w.write_len(QUICKLIST_NODE_PACKED);
+        let mut lp = Listpack::default();
+        lp.push_string(b"a");
+        lp.push_int(2);
+        w.write_string(&lp.into_bytes());
+        w.buf.push(TYPE_ZSET_LISTPACK);
+        w.write_string(b"z");
+        let mut lp = Listpack::default();
+        lp.push_string(b"m");
+        lp.push_string(b"0.5");
+        w.write_string(&lp.into_bytes());
+        w.buf.push(TYPE_ZSET);
+        w.buf.extend([0xC3, 6, 6, 2, b'a', b'b', b'c', 0x20, 2]);
+        w.write_len(1);
+        w.write_string(b"m");
+        w.buf.extend([254]);
+        w.buf.push(TYPE_HASH_LISTPACK);
+        w.write_string(b"h");
+        let mut lp = Listpack::default();
+        lp.push_string(b"f");
+        lp.push_int(7);
+        w.write_string(&lp.into_bytes());
+        w.buf.push(TYPE_SET_INTSET);
+        w.write_string(b"i");
+        let mut intset = vec![];
+        for x in [4u32, 2] {
+            intset.extend(x.to_le_bytes());
+        }
+        for x in [-70000i32, 3] {
+            intset.extend(x.to_le_bytes());
+        }
+        w.write_string(&intset);
+        w.buf.push(TYPE_SET_LISTPACK);
+        w.write_string(b"t");
+        let mut lp = Listpack::default();
+        lp.push_string(b"a");
+        lp.push_int(1);
+        w.write_string(&lp.into_bytes());
+        w.buf.push(OPCODE_EOF);
+        w.buf.extend([0; 8]);
+
+        let loaded = load(&w.buf).unwrap();
+        let list = List::from_iter([b"a".to_vec(), b"2".to_vec()]);
+        assert!(
+            matches!(loaded.data[b"l".as_slice()].value.as_ref(), Object::List(l) if *l == list)
+        );
+        assert_eq!(
+            zset_of(&loaded, "z").iter().collect::<Vec<_>>(),
+            [("m", 0.5)]
+        );
+        assert_eq!(
+            zset_of(&loaded, "abcabc").iter().collect::<Vec<_>>(),
+            [("m", f64::INFINITY)]
+        );
+        let hash = HashMap::from([(b"f".to_vec(), b"7".to_vec())]);
+        assert!(
+            matches!(loaded.data[b"h".as_slice()].value.as_ref(), Object::Hash(h) if *h == hash)
+        );
+        let set = HashSet::from([b"-70000".to_vec(), b"3".to_vec()]);
+        assert!(matches!(loaded.data[b"i".as_slice()].value.as_ref(), Object::Set(s) if *s == set));
+        let set = HashSet::from([b"a".to_vec(), b"1".to_vec()]);
+        assert!(matches!(loaded.data[b"t".as_slice()].value.as_ref(), Object::Set(s) if *s == set));
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+};
+
+use crate::storage::{OpError, OpResult};
+
+/// Score of a member in sorted set.
+///
+/// Wraps `f64` to make it totally ordered, so that it can be used as key in ordered collections.
+#[derive(Debug, Clone, Copy)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Format score in the way redis does.
+pub fn format_score(score: f64) -> String {
+    if score == f64::INFINITY {
+        "inf".into()
+    } else if score == f64::NEG_INFINITY {
+        "-inf".into()
+    } else {
+        score.to_string()
+    }
+}
+
+/// Sorted set, a collection of unique members ordered by their scores.
+///
+/// Members with the same score are ordered lexicographically.
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    /// Score of each member.
+    scores: HashMap<String, f64>,
+
+    /// All members, ordered by score then member.
+    ordered: BTreeSet<(Score, String)>,
+}
+
+impl SortedSet {
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Estimate the memory used by all members, in bytes.
+    pub fn estimate_size(&self) -> usize {
+        self.scores.keys().map(|x| x.len() + 8).sum()
+    }
+
+    /// Increase the score of `member` by `increment`.
+    ///
+    /// If `member` not present, add it with score `increment`.
+    ///
+    /// Return the score after increase.
+    pub fn incr(&mut self, member: String, increment: f64) -> OpResult<f64> {
+        let score = match self.scores.get(&member) {
+            Some(v) => v + increment,
+            None => increment,
+        };
+        if score.is_nan() {
+            return Err(OpError::NanScore);
+        }
+
+        if let Some(old) = self.scores.insert(member.clone(), score) {
+            self.ordered.remove(&(Score(old), member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+        Ok(score)
+    }
+
+    /// Set the score of `member` to `score`, add it if not present.
+    ///
+    /// Return the old score, `None` if `member` is new.
+    pub fn insert(&mut self, member: String, score: f64) -> Option<f64> {
+        let old = self.scores.insert(member.clone(), score);
+        if let Some(old) = old {
+            self.ordered.remove(&(Score(old), member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+        old
+    }
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Iterate all members and their scores, ordered by score.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.ordered
+            .iter()
+            .map(|(Score(score), member)| (member.as_str(), *score))
+    }
+
+    /// Remove and return at most `count` members with the lowest scores.
+    ///
+    /// Set `max` to true to pop members with the highest scores instead.
+    pub fn pop(&mut self, count: usize, max: bool) -> Vec<(String, f64)> {
+        let mut ret = vec![];
+        for _ in 0..count {
+            let entry = if max {
+                self.ordered.pop_last()
+            } else {
+                self.ordered.pop_first()
+            };
+            match entry {
+                Some((Score(score), member)) => {
+                    self.scores.remove(&member);
+                    ret.push((member, score));
+                }
+                None => break,
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sorted_set_incr_and_pop() {
+        let mut zset = SortedSet::default();
+        assert_eq!(zset.incr("a".into(), 2.0).ok(), Some(2.0));
+        assert_eq!(zset.incr("b".into(), 1.0).ok(), Some(1.0));
+        assert_eq!(zset.incr("c".into(), 1.0).ok(), Some(1.0));
+        assert_eq!(zset.incr("a".into(), -1.5).ok(), Some(0.5));
+        assert!(zset.incr("d".into(), f64::INFINITY).is_ok());
+        assert!(matches!(
+            zset.incr("d".into(), f64::NEG_INFINITY),
+            Err(OpError::NanScore)
+        ));
+
+        assert_eq!(zset.pop(1, false), vec![("a".to_string(), 0.5)]);
+        assert_eq!(zset.pop(1, true), vec![("d".to_string(), f64::INFINITY)]);
+        // Same score, ordered lexicographically.
+        assert_eq!(
+            zset.pop(5, false),
+            vec![("b".to_string(), 1.0), ("c".to_string(), 1.0)]
+        );
+        assert!(zset.is_empty());
+
+        assert_eq!(zset.insert("a".into(), 1.0), None);
+        assert_eq!(zset.insert("a".into(), 3.0), Some(1.0));
+        assert_eq!(zset.score("a"), Some(3.0));
+        assert_eq!(zset.score("b"), None);
+        assert_eq!(zset.pop(5, false), vec![("a".to_string(), 3.0)]);
+    }
+
+    #[test]
+    fn test_format_score() {
+        assert_eq!(format_score(3.0), "3");
+        assert_eq!(format_score(1.5), "1.5");
+        assert_eq!(format_score(f64::INFINITY), "inf");
+        assert_eq!(format_score(f64::NEG_INFINITY), "-inf");
+    }
+}
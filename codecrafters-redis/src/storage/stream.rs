@@ -1,8 +1,8 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
-use serde_redis::{Array, BulkString, SimpleString, Value};
+use serde_redis::{Array, BulkString, Integer, SimpleString, Value};
 
-use crate::storage::{OpError, OpResult};
+use crate::storage::{metrics::estimate_value_size, OpError, OpResult};
 
 #[derive(Debug, Clone)]
 pub enum StreamId {
@@ -26,41 +26,935 @@ impl StreamId {
     }
 }
 
+/// Id of a record in stream, the time id and sequence id.
+pub type RecordId = (u64, u64);
+
+/// Stream id as specified in command arguments, shared by all stream commands.
+///
+/// Commands accept different forms, use the conversion suitable for the command
+/// and reply `OpError::MalformedStreamId` for forms not allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamIdSpec {
+    /// `-`, the smallest id.
+    Min,
+
+    /// `+`, the greatest id.
+    Max,
+
+    /// `$`, the last id in stream.
+    Last,
+
+    /// `*`, generate both time and sequence.
+    Auto,
+
+    /// `<time>-*`, generate sequence.
+    AutoSeq(u64),
+
+    /// `<time>`, sequence omitted.
+    Time(u64),
+
+    /// `<time>-<seq>`.
+    Id(RecordId),
+
+    /// `(<time>` or `(<time>-<seq>`, bound excluded from range.
+    Exclusive(u64, Option<u64>),
+}
+
+/// Parse decimal digits without sign.
+fn parse_id_part(value: &str) -> Option<u64> {
+    if value.is_empty() || !value.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+impl StreamIdSpec {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "-" => return Some(Self::Min),
+            "+" => return Some(Self::Max),
+            "$" => return Some(Self::Last),
+            "*" => return Some(Self::Auto),
+            _ => {}
+        }
+        if let Some(value) = value.strip_prefix('(') {
+            return match value.split_once('-') {
+                Some((time_id, seq_id)) => Some(Self::Exclusive(
+                    parse_id_part(time_id)?,
+                    Some(parse_id_part(seq_id)?),
+                )),
+                None => Some(Self::Exclusive(parse_id_part(value)?, None)),
+            };
+        }
+        match value.split_once('-') {
+            Some((time_id, "*")) => Some(Self::AutoSeq(parse_id_part(time_id)?)),
+            Some((time_id, seq_id)) => {
+                Some(Self::Id((parse_id_part(time_id)?, parse_id_part(seq_id)?)))
+            }
+            None => Some(Self::Time(parse_id_part(value)?)),
+        }
+    }
+
+    /// Convert to a complete id, sequence defaults to 0.
+    pub fn record_id(self) -> OpResult<RecordId> {
+        match self {
+            Self::Time(time_id) => Ok((time_id, 0)),
+            Self::Id(id) => Ok(id),
+            _ => Err(OpError::MalformedStreamId),
+        }
+    }
+
+    /// Convert to the id to add in XADD.
+    pub fn add_id(self) -> OpResult<StreamId> {
+        match self {
+            Self::Auto => Ok(StreamId::Auto),
+            Self::AutoSeq(time_id) => Ok(StreamId::PartialAuto(time_id)),
+            v => v
+                .record_id()
+                .map(|(time_id, seq_id)| StreamId::new(time_id, seq_id)),
+        }
+    }
+
+    /// Convert to the inclusive start of range, sequence defaults to the smallest.
+    pub fn range_start(self) -> OpResult<RecordId> {
+        match self {
+            Self::Min => Ok((0, 0)),
+            Self::Max => Ok((u64::MAX, u64::MAX)),
+            Self::Exclusive(time_id, seq_id) => match seq_id.unwrap_or(0).checked_add(1) {
+                Some(seq_id) => Ok((time_id, seq_id)),
+                None => time_id
+                    .checked_add(1)
+                    .map(|x| (x, 0))
+                    .ok_or(OpError::InvalidIntervalId("start")),
+            },
+            v => v.record_id(),
+        }
+    }
+
+    /// Convert to the inclusive end of range, sequence defaults to the greatest.
+    pub fn range_end(self) -> OpResult<RecordId> {
+        match self {
+            Self::Min => Ok((0, 0)),
+            Self::Max => Ok((u64::MAX, u64::MAX)),
+            Self::Time(time_id) => Ok((time_id, u64::MAX)),
+            Self::Exclusive(time_id, seq_id) => match seq_id.unwrap_or(u64::MAX).checked_sub(1) {
+                Some(seq_id) => Ok((time_id, seq_id)),
+                None => time_id
+                    .checked_sub(1)
+                    .map(|x| (x, u64::MAX))
+                    .ok_or(OpError::InvalidIntervalId("end")),
+            },
+            v => v.record_id(),
+        }
+    }
+}
+
+/// A record read in consumer group, the value is `None` if the record was removed.
+pub type GroupRecord = (RecordId, Option<Vec<Value>>);
+
+/// A consumer group of stream.
+///
+/// Each record is delivered to only one consumer in group, and stays in the
+/// pending entries list (PEL) till acknowledged by XACK.
 #[derive(Debug, Clone)]
-pub struct StreamEntry {
-    /// Sequence number part of name in the last entry.
+pub struct ConsumerGroup {
+    /// Id of the last record delivered to consumers.
+    last_delivered_id: RecordId,
+
+    /// Logical read counter of the group, the count of records added before and
+    /// including the last delivered one, `None` if unknown.
     ///
-    /// Should be more than zero.
-    last_entry_seq_id: u64,
+    /// Like redis, it is increased by each record delivered while known, and estimated
+    /// again otherwise. Used to calculate the lag of group.
+    entries_read: Option<u64>,
+
+    /// Pending entries list of the group, ordered by id.
+    ///
+    /// Records delivered but not acknowledged yet.
+    pending: BTreeMap<RecordId, PendingEntry>,
+
+    /// All consumers in group and the records pending on each of them.
+    consumers: BTreeMap<String, BTreeSet<RecordId>>,
+}
+
+/// How to trim stream, used by XADD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrimStrategy {
+    /// Keep at most this count of latest records.
+    MaxLen(usize),
+
+    /// Remove records having id less than this.
+    MinId(RecordId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrimOptions {
+    pub strategy: TrimStrategy,
+
+    /// Max count of records to remove in one trimming, unlimited if `None`.
+    pub limit: Option<usize>,
+}
+
+/// A record in the pending entries list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingEntry {
+    /// The consumer the record is delivered to.
+    pub consumer: String,
+
+    /// Unix time in milliseconds when the record was delivered last time.
+    pub delivery_time: u64,
+
+    /// Count of times the record was delivered.
+    pub delivery_count: u64,
+}
+
+/// Options of claiming pending records, used by XCLAIM and XAUTOCLAIM.
+#[derive(Debug, Clone, Default)]
+pub struct ClaimOptions {
+    /// Set the delivery time of claimed records, default is now.
+    pub delivery_time: Option<u64>,
+
+    /// Set the delivery count of claimed records, default is increased by one.
+    pub retry_count: Option<u64>,
+
+    /// Claim records not in the PEL but present in stream.
+    pub force: bool,
+
+    /// Do not increase the delivery count.
+    pub justid: bool,
+
+    /// Update the last delivered id of group if greater.
+    pub last_id: Option<RecordId>,
+}
+
+/// A record claimed by consumer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClaimedRecord {
+    pub id: RecordId,
+
+    /// Fields of record.
+    pub values: Vec<Value>,
+
+    pub delivery_time: u64,
+
+    pub delivery_count: u64,
+}
+
+impl ConsumerGroup {
+    pub fn last_delivered_id(&self) -> RecordId {
+        self.last_delivered_id
+    }
+
+    pub fn entries_read(&self) -> Option<u64> {
+        self.entries_read
+    }
+
+    /// Iterate the PEL, ordered by id.
+    pub fn pending(&self) -> impl Iterator<Item = (&RecordId, &PendingEntry)> {
+        self.pending.iter()
+    }
+
+    /// Iterate consumers and the records pending on each of them.
+    pub fn consumers(&self) -> impl Iterator<Item = (&String, &BTreeSet<RecordId>)> {
+        self.consumers.iter()
+    }
 
+    /// Remove record `id` from the PEL, return false if not pending.
+    fn remove_pending(&mut self, id: &RecordId) -> bool {
+        match self.pending.remove(id) {
+            Some(pending) => {
+                if let Some(ids) = self.consumers.get_mut(&pending.consumer) {
+                    ids.remove(id);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StreamEntry {
     /// All datas in stream.
     data: BTreeMap<u64, Vec<Value>>,
 }
 
 impl StreamEntry {
-    fn new(seq_id: u64, values: BTreeMap<u64, Vec<Value>>) -> Self {
-        Self {
-            last_entry_seq_id: seq_id,
-            data: values,
-        }
+    fn new(values: BTreeMap<u64, Vec<Value>>) -> Self {
+        Self { data: values }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Stream {
-    /// Timestamp part of name in the last entry.
-    last_entry_time_id: u64,
+    /// Id of the last record ever added, or set by XSETID.
+    ///
+    /// New records must have a greater id, even if the record is already deleted.
+    last_id: RecordId,
+
+    /// The greatest id of deleted records.
+    max_deleted_id: RecordId,
 
     /// All entries in stream.
     entries: BTreeMap<u64, StreamEntry>,
+
+    /// Count of records in all entries.
+    length: usize,
+
+    /// Hard cap on the count of records.
+    ///
+    /// Oldest records are trimmed automatically once the stream grows longer than it.
+    max_len: Option<usize>,
+
+    /// Count of records ever added.
+    entries_added: u64,
+
+    /// Count of records trimmed because of `max_len`.
+    entries_trimmed: u64,
+
+    /// Consumer groups of stream, by name.
+    groups: BTreeMap<String, ConsumerGroup>,
 }
 
 impl Stream {
     pub fn new() -> Self {
         Self {
-            last_entry_time_id: 0,
+            last_id: (0, 0),
+            max_deleted_id: (0, 0),
             entries: BTreeMap::new(),
+            length: 0,
+            max_len: None,
+            entries_added: 0,
+            entries_trimmed: 0,
+            groups: BTreeMap::new(),
+        }
+    }
+
+    /// Count of records in stream.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Hard cap on length of stream, set by XADD CAP.
+    pub fn max_len(&self) -> Option<usize> {
+        self.max_len
+    }
+
+    /// Set the hard cap on length of stream, trim immediately if already longer than it.
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = Some(max_len);
+        self.trim_to_max_len();
+    }
+
+    /// Remove the oldest records till the stream is not longer than `max_len`.
+    fn trim_to_max_len(&mut self) {
+        if let Some(max_len) = self.max_len {
+            self.trim(&TrimOptions {
+                strategy: TrimStrategy::MaxLen(max_len),
+                limit: None,
+            });
+        }
+    }
+
+    /// Remove the oldest records according to `options`.
+    ///
+    /// Return the count of removed records.
+    pub fn trim(&mut self, options: &TrimOptions) -> usize {
+        let mut trimmed = 0;
+        while options.limit.is_none_or(|x| trimmed < x) {
+            let mut first = match self.entries.first_entry() {
+                Some(v) => v,
+                None => break,
+            };
+            let time_id = *first.key();
+            let seq_id = match first.get().data.first_key_value() {
+                Some((seq_id, _)) => *seq_id,
+                None => {
+                    first.remove();
+                    continue;
+                }
+            };
+            let expired = match options.strategy {
+                TrimStrategy::MaxLen(max_len) => self.length > max_len,
+                TrimStrategy::MinId(min_id) => (time_id, seq_id) < min_id,
+            };
+            if !expired {
+                break;
+            }
+            first.get_mut().data.pop_first();
+            if first.get().data.is_empty() {
+                first.remove();
+            }
+            self.length -= 1;
+            self.entries_trimmed += 1;
+            trimmed += 1;
+        }
+        trimmed
+    }
+
+    /// Get the first and last record in stream.
+    fn first_last_entry(&self) -> (Option<Value>, Option<Value>) {
+        let build = |time_id: &u64, seq_id: &u64, values: &Vec<Value>| {
+            Value::Array(Array::with_values(vec![
+                Value::BulkString(StreamId::new(*time_id, *seq_id).to_bulk_string()),
+                Value::Array(Array::with_values(values.to_owned())),
+            ]))
+        };
+        let first = self.entries.iter().find_map(|(time_id, entry)| {
+            entry
+                .data
+                .first_key_value()
+                .map(|(seq_id, values)| build(time_id, seq_id, values))
+        });
+        let last = self.entries.iter().rev().find_map(|(time_id, entry)| {
+            entry
+                .data
+                .last_key_value()
+                .map(|(seq_id, values)| build(time_id, seq_id, values))
+        });
+        (first, last)
+    }
+
+    /// Id of the last record ever added.
+    pub fn last_generated_id(&self) -> RecordId {
+        self.last_id
+    }
+
+    /// The greatest id of deleted records.
+    pub fn max_deleted_id(&self) -> RecordId {
+        self.max_deleted_id
+    }
+
+    /// Count of records ever added.
+    pub fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    /// Iterate consumer groups by name.
+    pub fn groups(&self) -> impl Iterator<Item = (&String, &ConsumerGroup)> {
+        self.groups.iter()
+    }
+
+    /// Id for a record added with `*` at time `now`.
+    ///
+    /// Use the sequence after the last id if clock is not ahead of it.
+    pub fn next_auto_id(&self, now: u64) -> RecordId {
+        if now > self.last_id.0 {
+            (now, 0)
+        } else {
+            (self.last_id.0, self.last_id.1.saturating_add(1))
+        }
+    }
+
+    /// Force the last id and counters of stream, as XSETID does.
+    pub fn set_id(
+        &mut self,
+        last_id: RecordId,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<RecordId>,
+    ) -> OpResult<()> {
+        let top = self
+            .records()
+            .last()
+            .map(|(time_id, seq_id, _)| (time_id, seq_id));
+        if top.is_some_and(|x| last_id < x) {
+            return Err(OpError::InvalidSetId(
+                "The ID specified in XSETID is smaller than the target stream top item",
+            ));
+        }
+        if entries_added.is_some_and(|x| x < self.length as u64) {
+            return Err(OpError::InvalidSetId(
+                "The entries_added specified in XSETID is smaller than the target stream length",
+            ));
+        }
+        if max_deleted_id.is_some_and(|x| last_id < x) {
+            return Err(OpError::InvalidSetId(
+                "The ID specified in XSETID is smaller than the provided max_deleted_entry_id",
+            ));
+        }
+        self.last_id = last_id;
+        if let Some(v) = entries_added {
+            self.entries_added = v;
+        }
+        if let Some(v) = max_deleted_id {
+            self.max_deleted_id = v;
+        }
+        Ok(())
+    }
+
+    /// Build the reply of XINFO STREAM.
+    pub fn info(&self) -> Value {
+        let (time_id, seq_id) = self.last_generated_id();
+        let last_generated_id = StreamId::new(time_id, seq_id);
+        let max_deleted_id = StreamId::new(self.max_deleted_id.0, self.max_deleted_id.1);
+        let (first_entry, last_entry) = self.first_last_entry();
+        let null = || Value::BulkString(BulkString::null());
+        Value::Array(Array::with_values(vec![
+            Value::BulkString(BulkString::new("length")),
+            Value::Integer(Integer::new(self.length as i64)),
+            Value::BulkString(BulkString::new("last-generated-id")),
+            Value::BulkString(last_generated_id.to_bulk_string()),
+            Value::BulkString(BulkString::new("max-deleted-entry-id")),
+            Value::BulkString(max_deleted_id.to_bulk_string()),
+            Value::BulkString(BulkString::new("entries-added")),
+            Value::Integer(Integer::new(self.entries_added as i64)),
+            Value::BulkString(BulkString::new("max-len")),
+            Value::Integer(Integer::new(self.max_len.map(|x| x as i64).unwrap_or(-1))),
+            Value::BulkString(BulkString::new("entries-trimmed")),
+            Value::Integer(Integer::new(self.entries_trimmed as i64)),
+            Value::BulkString(BulkString::new("groups")),
+            Value::Integer(Integer::new(self.groups.len() as i64)),
+            Value::BulkString(BulkString::new("first-entry")),
+            first_entry.unwrap_or_else(null),
+            Value::BulkString(BulkString::new("last-entry")),
+            last_entry.unwrap_or_else(null),
+        ]))
+    }
+
+    /// Build the reply of XINFO GROUPS, one summary for each consumer group.
+    pub fn groups_info(&self) -> Array {
+        let null = || Value::BulkString(BulkString::null());
+        self.groups
+            .iter()
+            .map(|(name, group)| {
+                let (time_id, seq_id) = group.last_delivered_id;
+                let lag = self
+                    .group_lag(group)
+                    .map(|x| Value::Integer(Integer::new(x as i64)));
+                Value::Array(Array::with_values(vec![
+                    Value::BulkString(BulkString::new("name")),
+                    Value::BulkString(BulkString::new(name.as_str())),
+                    Value::BulkString(BulkString::new("consumers")),
+                    Value::Integer(Integer::new(group.consumers.len() as i64)),
+                    Value::BulkString(BulkString::new("pending")),
+                    Value::Integer(Integer::new(group.pending.len() as i64)),
+                    Value::BulkString(BulkString::new("last-delivered-id")),
+                    Value::BulkString(StreamId::new(time_id, seq_id).to_bulk_string()),
+                    Value::BulkString(BulkString::new("entries-read")),
+                    group
+                        .entries_read
+                        .map(|x| Value::Integer(Integer::new(x as i64)))
+                        .unwrap_or_else(null),
+                    Value::BulkString(BulkString::new("lag")),
+                    lag.unwrap_or_else(null),
+                ]))
+            })
+            .collect()
+    }
+
+    /// Id of the first record in stream.
+    fn first_id(&self) -> Option<RecordId> {
+        self.records()
+            .next()
+            .map(|(time_id, seq_id, _)| (time_id, seq_id))
+    }
+
+    /// Check whether any record deleted is at or after `start`, between the records
+    /// in stream.
+    fn range_has_tombstones(&self, start: RecordId) -> bool {
+        if self.length == 0 || self.max_deleted_id == (0, 0) {
+            return false;
+        }
+        if self.first_id().is_some_and(|x| x > self.max_deleted_id) {
+            return false;
+        }
+        start <= self.max_deleted_id
+    }
+
+    /// Estimate the read counter of a group having delivered record `id`, the count of
+    /// records added before and including it.
+    ///
+    /// Same as redis, return `None` if it can not be told, e.g. `id` is after records
+    /// deleted in the middle of stream.
+    fn estimate_entries_read(&self, id: RecordId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if self.length == 0 && id <= self.last_id {
+            return Some(self.entries_added);
+        }
+        match id.cmp(&self.last_id) {
+            std::cmp::Ordering::Equal => return Some(self.entries_added),
+            std::cmp::Ordering::Greater => return None,
+            std::cmp::Ordering::Less => {}
+        }
+        let first_id = self.first_id()?;
+        if self.max_deleted_id == (0, 0) || self.max_deleted_id < first_id {
+            // No records deleted after the first one.
+            let before_first = self.entries_added - self.length as u64;
+            match id.cmp(&first_id) {
+                std::cmp::Ordering::Less => return Some(before_first),
+                std::cmp::Ordering::Equal => return Some(before_first + 1),
+                std::cmp::Ordering::Greater => {}
+            }
+        }
+        None
+    }
+
+    /// Count of records not delivered to `group` yet, `None` if it can not be told.
+    fn group_lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        let entries_read = match group.entries_read {
+            Some(v) if !self.range_has_tombstones(group.last_delivered_id) => v,
+            _ => self.estimate_entries_read(group.last_delivered_id)?,
+        };
+        Some(self.entries_added.saturating_sub(entries_read))
+    }
+
+    /// Create consumer group `name` that starts delivering records after `start`.
+    ///
+    /// `StreamId::Auto` starts after the last record in stream, only delivers new records.
+    ///
+    /// Return `Err(OpError::BusyGroup)` if group already exists.
+    pub fn create_group(&mut self, name: String, start: StreamId) -> OpResult<()> {
+        if self.groups.contains_key(&name) {
+            return Err(OpError::BusyGroup);
+        }
+        // Like redis, the read counter starting from an arbitrary id is unknown till
+        // estimated by reading.
+        let (last_delivered_id, entries_read) = match start {
+            StreamId::Value { time_id, seq_id } => ((time_id, seq_id), None),
+            _ => (self.last_generated_id(), Some(self.entries_added)),
+        };
+        self.groups.insert(
+            name,
+            ConsumerGroup {
+                last_delivered_id,
+                entries_read,
+                pending: BTreeMap::new(),
+                consumers: BTreeMap::new(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Add consumer group `name` with its states saved elsewhere, as loading RDB.
+    ///
+    /// `pending` is the PEL of group, `consumers` are names of all consumers including
+    /// those having no pending records.
+    pub fn restore_group(
+        &mut self,
+        name: String,
+        last_delivered_id: RecordId,
+        entries_read: Option<u64>,
+        pending: BTreeMap<RecordId, PendingEntry>,
+        consumers: Vec<String>,
+    ) {
+        let mut group = ConsumerGroup {
+            last_delivered_id,
+            entries_read,
+            pending: BTreeMap::new(),
+            consumers: consumers
+                .into_iter()
+                .map(|x| (x, BTreeSet::new()))
+                .collect(),
+        };
+        for (id, entry) in pending {
+            group
+                .consumers
+                .entry(entry.consumer.clone())
+                .or_default()
+                .insert(id);
+            group.pending.insert(id, entry);
+        }
+        self.groups.insert(name, group);
+    }
+
+    /// Remove consumer group `name`, return false if not exists.
+    pub fn destroy_group(&mut self, name: &str) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    /// Read records as `consumer` in consumer group `group`.
+    ///
+    /// * `after` is `None`: Deliver at most `count` records never delivered to the group,
+    ///   they are added to the PEL of `consumer` unless `noack` is true.
+    /// * `after` is `Some(id)`: Read at most `count` records pending on `consumer`
+    ///   with id greater than `id`. Records already removed from stream have no value.
+    ///
+    /// The consumer is created if not exists.
+    ///
+    /// Return `None` if `group` not exists.
+    pub fn read_group(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        after: Option<RecordId>,
+        count: Option<usize>,
+        noack: bool,
+        now: u64,
+    ) -> Option<Vec<GroupRecord>> {
+        let group_name = group;
+        let group = self.groups.get_mut(group)?;
+        let count = count.unwrap_or(usize::MAX);
+        let pending_ids = group.consumers.entry(consumer.to_string()).or_default();
+
+        if let Some(after) = after {
+            // Reading history is delivering again.
+            let records = pending_ids
+                .range((after.0, after.1.saturating_add(1))..)
+                .take(count)
+                .map(|id| {
+                    if let Some(pending) = group.pending.get_mut(id) {
+                        pending.delivery_time = now;
+                        pending.delivery_count += 1;
+                    }
+                    let values = self
+                        .entries
+                        .get(&id.0)
+                        .and_then(|entry| entry.data.get(&id.1))
+                        .cloned();
+                    (*id, values)
+                })
+                .collect();
+            return Some(records);
+        }
+
+        let start = group.last_delivered_id;
+        let records = self
+            .entries
+            .range(start.0..)
+            .flat_map(|(time_id, entry)| {
+                entry
+                    .data
+                    .iter()
+                    .map(move |(seq_id, values)| ((*time_id, *seq_id), values))
+            })
+            .filter(|(id, _)| *id > start)
+            .take(count)
+            .map(|(id, values)| (id, Some(values.clone())))
+            .collect::<Vec<_>>();
+
+        let mut entries_read = group.entries_read;
+        for (id, _) in records.iter() {
+            entries_read = match entries_read {
+                Some(v) if !self.range_has_tombstones(*id) => Some(v + 1),
+                _ if self.entries_added > 0 => self.estimate_entries_read(*id),
+                v => v,
+            };
+        }
+        let group = self.groups.get_mut(group_name)?;
+        group.entries_read = entries_read;
+        if let Some((id, _)) = records.last() {
+            group.last_delivered_id = *id;
+        }
+        let pending_ids = group.consumers.entry(consumer.to_string()).or_default();
+        if !noack {
+            for (id, _) in records.iter() {
+                pending_ids.insert(*id);
+                group.pending.insert(
+                    *id,
+                    PendingEntry {
+                        consumer: consumer.to_string(),
+                        delivery_time: now,
+                        delivery_count: 1,
+                    },
+                );
+            }
+        }
+        Some(records)
+    }
+
+    /// Acknowledge records `ids` in consumer group `group`, remove them from the PEL.
+    ///
+    /// Return the count of records removed from the PEL.
+    pub fn ack(&mut self, group: &str, ids: &[RecordId]) -> usize {
+        let group = match self.groups.get_mut(group) {
+            Some(v) => v,
+            None => return 0,
+        };
+        let mut acked = 0;
+        for id in ids {
+            if group.remove_pending(id) {
+                acked += 1;
+            }
+        }
+        acked
+    }
+
+    /// Get the fields of record `id`.
+    fn record(&self, id: RecordId) -> Option<&Vec<Value>> {
+        self.entries
+            .get(&id.0)
+            .and_then(|entry| entry.data.get(&id.1))
+    }
+
+    /// Summary of the PEL of `group`: count of pending records, the smallest and
+    /// greatest id, and count of pending records on each consumer having any.
+    ///
+    /// Return `None` if `group` not exists.
+    #[allow(clippy::type_complexity)]
+    pub fn pending_summary(
+        &self,
+        group: &str,
+    ) -> Option<(usize, Option<(RecordId, RecordId)>, Vec<(String, usize)>)> {
+        let group = self.groups.get(group)?;
+        let bounds = group
+            .pending
+            .first_key_value()
+            .zip(group.pending.last_key_value())
+            .map(|((first, _), (last, _))| (*first, *last));
+        let consumers = group
+            .consumers
+            .iter()
+            .filter(|(_, ids)| !ids.is_empty())
+            .map(|(name, ids)| (name.clone(), ids.len()))
+            .collect();
+        Some((group.pending.len(), bounds, consumers))
+    }
+
+    /// Get at most `count` records in the PEL of `group` in range `start` to `end`,
+    /// both inclusive.
+    ///
+    /// Only records idle for at least `min_idle` milliseconds and pending on
+    /// `consumer` if specified.
+    ///
+    /// Return `None` if `group` not exists.
+    #[allow(clippy::too_many_arguments)]
+    pub fn pending_range(
+        &self,
+        group: &str,
+        start: RecordId,
+        end: RecordId,
+        count: usize,
+        min_idle: u64,
+        consumer: Option<&str>,
+        now: u64,
+    ) -> Option<Vec<(RecordId, PendingEntry)>> {
+        let group = self.groups.get(group)?;
+        if start > end {
+            return Some(vec![]);
+        }
+        let records = group
+            .pending
+            .range(start..=end)
+            .filter(|(_, x)| now.saturating_sub(x.delivery_time) >= min_idle)
+            .filter(|(_, x)| consumer.is_none_or(|c| x.consumer == c))
+            .take(count)
+            .map(|(id, x)| (*id, x.clone()))
+            .collect();
+        Some(records)
+    }
+
+    /// Transfer records `ids` idle for at least `min_idle` milliseconds to `consumer`
+    /// in group `group`.
+    ///
+    /// Records removed from stream are removed from the PEL and not claimed.
+    ///
+    /// Return the claimed records and records removed from the PEL, `None` if `group`
+    /// not exists.
+    pub fn claim(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        min_idle: u64,
+        ids: &[RecordId],
+        options: &ClaimOptions,
+        now: u64,
+    ) -> Option<(Vec<ClaimedRecord>, Vec<RecordId>)> {
+        let mut claimed = vec![];
+        let mut deleted = vec![];
+        let group_ref = self.groups.get(group)?;
+        for id in ids {
+            let values = match self.record(*id) {
+                Some(v) => v.clone(),
+                None => {
+                    deleted.push(*id);
+                    continue;
+                }
+            };
+            let idle = match group_ref.pending.get(id) {
+                Some(x) => now.saturating_sub(x.delivery_time),
+                None if options.force => u64::MAX,
+                None => continue,
+            };
+            if idle < min_idle {
+                continue;
+            }
+            claimed.push((*id, values));
         }
+
+        let group = self.groups.get_mut(group)?;
+        deleted.retain(|id| group.remove_pending(id));
+        if let Some(last_id) = options.last_id {
+            group.last_delivered_id = group.last_delivered_id.max(last_id);
+        }
+        group.consumers.entry(consumer.to_string()).or_default();
+        let claimed = claimed
+            .into_iter()
+            .map(|(id, values)| {
+                let delivery_count = group.pending.get(&id).map_or(0, |x| x.delivery_count);
+                let pending = PendingEntry {
+                    consumer: consumer.to_string(),
+                    delivery_time: options.delivery_time.unwrap_or(now),
+                    delivery_count: match (options.retry_count, options.justid) {
+                        (Some(v), _) => v,
+                        (None, true) => delivery_count,
+                        (None, false) => delivery_count + 1,
+                    },
+                };
+                group.remove_pending(&id);
+                group.consumers.get_mut(consumer).unwrap().insert(id);
+                let record = ClaimedRecord {
+                    id,
+                    values,
+                    delivery_time: pending.delivery_time,
+                    delivery_count: pending.delivery_count,
+                };
+                group.pending.insert(id, pending);
+                record
+            })
+            .collect();
+        Some((claimed, deleted))
+    }
+
+    /// Scan the PEL of `group` from `start`, claim at most `count` records idle for at
+    /// least `min_idle` milliseconds to `consumer`.
+    ///
+    /// Return the id to continue scanning from, `(0, 0)` if the scan finished, the
+    /// claimed records, and records removed from the PEL as they are removed from stream.
+    ///
+    /// Return `None` if `group` not exists.
+    #[allow(clippy::too_many_arguments, clippy::type_complexity)]
+    pub fn auto_claim(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        min_idle: u64,
+        start: RecordId,
+        count: usize,
+        justid: bool,
+        now: u64,
+    ) -> Option<(RecordId, Vec<ClaimedRecord>, Vec<RecordId>)> {
+        let group_ref = self.groups.get(group)?;
+        // Like redis, limit the records scanned in one call.
+        let mut attempts = count.saturating_mul(10);
+        let mut scan = group_ref.pending.range(start..).peekable();
+        let mut ids = vec![];
+        let mut claims = 0;
+        while claims < count && attempts > 0 {
+            let (id, pending) = match scan.next() {
+                Some(v) => v,
+                None => break,
+            };
+            attempts -= 1;
+            if self.record(*id).is_none() {
+                // Removed from PEL when claiming.
+                ids.push(*id);
+            } else if now.saturating_sub(pending.delivery_time) >= min_idle {
+                ids.push(*id);
+                claims += 1;
+            }
+        }
+        let next = scan.peek().map_or((0, 0), |(id, _)| **id);
+        let options = ClaimOptions {
+            justid,
+            ..Default::default()
+        };
+        let (claimed, deleted) = self.claim(group, consumer, min_idle, &ids, &options, now)?;
+        Some((next, claimed, deleted))
     }
 
     pub fn add_entry(
@@ -72,85 +966,410 @@ impl Stream {
         if time_id == 0 && seq_id == 0 {
             return Err(OpError::InvalidStreamId);
         }
-        if time_id < self.last_entry_time_id {
+        if (time_id, seq_id) <= self.last_id {
             return Err(OpError::TooSmallStreamId);
         }
 
+        self.last_id = (time_id, seq_id);
         match self.entries.get_mut(&time_id) {
             Some(entry) => {
                 // Add new record to existing entry.
-                if seq_id <= entry.last_entry_seq_id {
-                    return Err(OpError::TooSmallStreamId);
-                }
-
-                self.last_entry_time_id = time_id;
-                entry.last_entry_seq_id = seq_id;
                 let new_entry = !entry.data.contains_key(&seq_id);
                 entry.data.insert(seq_id, values);
+                self.length += 1;
+                self.entries_added += 1;
+                self.trim_to_max_len();
                 Ok((StreamId::new(time_id, seq_id), new_entry))
             }
             None => {
                 // Insert new entry.
                 self.entries.insert(
                     time_id,
-                    StreamEntry::new(seq_id, BTreeMap::from([(seq_id, values)])),
+                    StreamEntry::new(BTreeMap::from([(seq_id, values)])),
                 );
-                self.last_entry_time_id = time_id;
+                self.length += 1;
+                self.entries_added += 1;
+                self.trim_to_max_len();
                 Ok((StreamId::new(time_id, seq_id), true))
             }
         }
     }
 
-    pub fn get_next_seq_id(&self, time_id: u64) -> u64 {
+    /// Iterate all records in stream, from the oldest to the newest.
+    ///
+    /// Each record is its time id, sequence id and the field-value pairs.
+    pub fn records(&self) -> impl Iterator<Item = (u64, u64, &Vec<Value>)> {
+        self.entries.iter().flat_map(|(time_id, entry)| {
+            entry
+                .data
+                .iter()
+                .map(move |(seq_id, values)| (*time_id, *seq_id, values))
+        })
+    }
+
+    /// Estimate the memory used by all entries, in bytes.
+    pub fn estimate_size(&self) -> usize {
         self.entries
-            .get(&time_id)
-            .map_or_else(|| 0, |s| s.last_entry_seq_id + 1)
+            .values()
+            .flat_map(|entry| entry.data.values())
+            .map(|values| 16 + values.iter().map(estimate_value_size).sum::<usize>())
+            .sum()
+    }
+
+    pub fn get_next_seq_id(&self, time_id: u64) -> u64 {
+        if time_id == self.last_id.0 {
+            self.last_id.1.saturating_add(1)
+        } else {
+            0
+        }
+    }
+
+    /// Get at most `count` records in range `start` to `end`, both inclusive.
+    ///
+    /// Records are in descending order if `rev` is true.
+    pub fn get_range(
+        &self,
+        start: RecordId,
+        end: RecordId,
+        count: Option<usize>,
+        rev: bool,
+    ) -> Value {
+        if start > end {
+            return Value::Array(Array::new_empty());
+        }
+        let records = self.entries.range(start.0..=end.0);
+        let records: Box<dyn Iterator<Item = _>> = if rev {
+            Box::new(
+                records.rev().flat_map(|(time_id, entry)| {
+                    entry.data.iter().rev().map(move |x| (*time_id, x))
+                }),
+            )
+        } else {
+            Box::new(
+                records.flat_map(|(time_id, entry)| entry.data.iter().map(move |x| (*time_id, x))),
+            )
+        };
+        let array = records
+            .filter(|(time_id, (seq_id, _))| (start..=end).contains(&(*time_id, **seq_id)))
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(time_id, (seq_id, values))| {
+                Value::Array(Array::with_values(vec![
+                    Value::SimpleString(SimpleString::new(format!("{}-{}", time_id, seq_id))),
+                    Value::Array(Array::with_values(values.to_owned())),
+                ]))
+            })
+            .collect();
+        Value::Array(array)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_stream_id_spec() {
+        use StreamIdSpec::*;
+
+        let parse = StreamIdSpec::parse;
+        assert_eq!(parse("-"), Some(Min));
+        assert_eq!(parse("+"), Some(Max));
+        assert_eq!(parse("$"), Some(Last));
+        assert_eq!(parse("*"), Some(Auto));
+        assert_eq!(parse("5-*"), Some(AutoSeq(5)));
+        assert_eq!(parse("5"), Some(Time(5)));
+        assert_eq!(parse("5-3"), Some(Id((5, 3))));
+        assert_eq!(parse("(5"), Some(Exclusive(5, None)));
+        assert_eq!(parse("(5-3"), Some(Exclusive(5, Some(3))));
+        assert_eq!(
+            parse("18446744073709551615-18446744073709551615"),
+            Some(Id((u64::MAX, u64::MAX)))
+        );
+        for invalid in [
+            "",
+            "-5",
+            "+5",
+            "5-",
+            "-3",
+            "5-3-1",
+            "5-+3",
+            "a-1",
+            "1-a",
+            "*-1",
+            "(",
+            "(-",
+            "(+",
+            "($",
+            "(*",
+            "(5-*",
+            "((5",
+            "5 ",
+            "18446744073709551616",
+        ] {
+            assert_eq!(parse(invalid), None, "{invalid:?}");
+        }
+
+        assert_eq!(Time(5).record_id().ok(), Some((5, 0)));
+        assert_eq!(Id((5, 3)).record_id().ok(), Some((5, 3)));
+        for invalid in [Min, Max, Last, Auto, AutoSeq(5), Exclusive(5, None)] {
+            assert!(matches!(
+                invalid.record_id(),
+                Err(OpError::MalformedStreamId)
+            ));
+        }
+
+        assert!(matches!(Auto.add_id(), Ok(StreamId::Auto)));
+        assert!(matches!(AutoSeq(5).add_id(), Ok(StreamId::PartialAuto(5))));
+        assert!(matches!(
+            Time(5).add_id(),
+            Ok(StreamId::Value {
+                time_id: 5,
+                seq_id: 0
+            })
+        ));
+        assert!(Last.add_id().is_err());
+
+        let start = |x: StreamIdSpec| x.range_start().ok();
+        let end = |x: StreamIdSpec| x.range_end().ok();
+        assert_eq!(start(Min), Some((0, 0)));
+        assert_eq!(end(Max), Some((u64::MAX, u64::MAX)));
+        assert_eq!(start(Time(5)), Some((5, 0)));
+        assert_eq!(end(Time(5)), Some((5, u64::MAX)));
+        assert_eq!(start(Id((5, 3))), Some((5, 3)));
+        assert_eq!(end(Id((5, 3))), Some((5, 3)));
+        assert_eq!(start(Exclusive(5, None)), Some((5, 1)));
+        assert_eq!(end(Exclusive(5, None)), Some((5, u64::MAX - 1)));
+        assert_eq!(start(Exclusive(5, Some(u64::MAX))), Some((6, 0)));
+        assert_eq!(end(Exclusive(5, Some(0))), Some((4, u64::MAX)));
+        assert!(matches!(
+            Exclusive(u64::MAX, Some(u64::MAX)).range_start(),
+            Err(OpError::InvalidIntervalId("start"))
+        ));
+        assert!(matches!(
+            Exclusive(0, Some(0)).range_end(),
+            Err(OpError::InvalidIntervalId("end"))
+        ));
+        assert!(Last.range_start().is_err());
+        assert!(AutoSeq(5).range_end().is_err());
     }
 
-    pub fn get_range(&self, start: StreamId, end: StreamId) -> OpResult<Value> {
-        let mut array = Array::new_empty();
-        let (start_time_id, start_seq_id) = match start {
-            StreamId::Value { time_id, seq_id } => (time_id, seq_id),
-            StreamId::Auto => (0, 0),
-            StreamId::PartialAuto(time_id) => (time_id, 0),
+    #[test]
+    fn test_trim_to_max_len() {
+        let mut s = Stream::new();
+        s.set_max_len(2);
+        for seq_id in 1..=3 {
+            assert!(s.add_entry(1, seq_id, vec![]).is_ok());
+        }
+        assert!(s.add_entry(2, 0, vec![]).is_ok());
+        assert_eq!(s.length, 2);
+        assert_eq!(s.entries_added, 4);
+        assert_eq!(s.entries_trimmed, 2);
+        assert_eq!(s.entries.len(), 2);
+        assert_eq!(s.entries[&1].data.keys().collect::<Vec<_>>(), vec![&3]);
+    }
+
+    #[test]
+    fn test_trim() {
+        let mut s = Stream::new();
+        for (time_id, seq_id) in [(1, 1), (1, 2), (2, 1), (3, 1), (3, 2)] {
+            assert!(s.add_entry(time_id, seq_id, vec![]).is_ok());
+        }
+        let options = TrimOptions {
+            strategy: TrimStrategy::MinId((3, 0)),
+            limit: Some(2),
         };
+        assert_eq!(s.trim(&options), 2);
+        assert_eq!(s.length, 3);
+        assert!(!s.entries.contains_key(&1));
 
-        let (end_time_id, end_seq_id) = match end {
-            StreamId::Value { time_id, seq_id } => (Some(time_id), Some(seq_id)),
-            StreamId::Auto => (None, None),
-            StreamId::PartialAuto(time_id) => (Some(time_id), None),
+        let options = TrimOptions {
+            strategy: TrimStrategy::MaxLen(1),
+            limit: None,
         };
+        assert_eq!(s.trim(&options), 2);
+        assert_eq!(s.entries_trimmed, 4);
+        assert_eq!(s.entries[&3].data.keys().collect::<Vec<_>>(), vec![&2]);
+    }
 
-        let end_time_id = end_time_id.unwrap_or_else(|| self.last_entry_time_id);
+    #[test]
+    fn test_last_id() {
+        let mut s = Stream::new();
+        assert!(s.add_entry(5, 1, vec![]).is_ok());
+        assert!(s.add_entry(5, 2, vec![]).is_ok());
+        s.trim(&TrimOptions {
+            strategy: TrimStrategy::MaxLen(0),
+            limit: None,
+        });
+        assert_eq!(s.last_generated_id(), (5, 2));
+        assert_eq!(s.get_next_seq_id(5), 3);
+        assert!(matches!(
+            s.add_entry(5, 2, vec![]),
+            Err(OpError::TooSmallStreamId)
+        ));
+        assert_eq!(s.next_auto_id(4), (5, 3));
+        assert_eq!(s.next_auto_id(6), (6, 0));
 
-        for (time_id, entry) in self.entries.iter() {
-            if time_id < &start_time_id {
-                continue;
-            }
-            if &end_time_id < time_id {
-                // BTreeMap is orderd, we break the loop asap.
-                break;
-            }
+        assert!(s.add_entry(6, 0, vec![]).is_ok());
+        assert!(s.set_id((5, 9), None, None).is_err());
+        assert!(s.set_id((7, 0), Some(0), None).is_err());
+        assert!(s.set_id((7, 0), None, Some((8, 0))).is_err());
+        assert!(s.set_id((7, 0), Some(10), Some((6, 5))).is_ok());
+        assert_eq!(s.entries_added, 10);
+        assert_eq!(s.get_next_seq_id(7), 1);
+        assert!(s.add_entry(6, 1, vec![]).is_err());
+    }
 
-            for (seq_id, values) in entry.data.iter() {
-                let mut collected_values = vec![];
-                let end_seq_id = end_seq_id.unwrap_or_else(|| entry.last_entry_seq_id);
-                if seq_id < &start_seq_id {
-                    continue;
-                }
-                if &end_seq_id < seq_id {
-                    // BTreeMap is orderd, we break the loop asap.
-                    break;
-                }
+    #[test]
+    fn test_consumer_group() {
+        let mut s = Stream::new();
+        for seq_id in 1..=3 {
+            assert!(s.add_entry(1, seq_id, vec![]).is_ok());
+        }
+        assert!(s.create_group("g".into(), StreamId::new(0, 0)).is_ok());
+        assert!(matches!(
+            s.create_group("g".into(), StreamId::Auto),
+            Err(OpError::BusyGroup)
+        ));
+        assert!(s.read_group("missing", "a", None, None, false, 0).is_none());
 
-                collected_values.push(Value::SimpleString(SimpleString::new(format!(
-                    "{}-{}",
-                    time_id, seq_id
-                ))));
-                collected_values.push(Value::Array(Array::with_values(values.to_owned())));
-                array.push_back(Value::Array(Array::with_values(collected_values)));
-            }
+        let ids = |records: Vec<GroupRecord>| records.into_iter().map(|x| x.0).collect::<Vec<_>>();
+        let read = s.read_group("g", "a", None, Some(2), false, 0).unwrap();
+        assert_eq!(ids(read), vec![(1, 1), (1, 2)]);
+        let read = s.read_group("g", "b", None, None, false, 0).unwrap();
+        assert_eq!(ids(read), vec![(1, 3)]);
+        assert!(s
+            .read_group("g", "b", None, None, false, 0)
+            .unwrap()
+            .is_empty());
+
+        // Pending records of consumer.
+        let read = s
+            .read_group("g", "a", Some((1, 1)), None, false, 0)
+            .unwrap();
+        assert_eq!(ids(read), vec![(1, 2)]);
+        assert_eq!(s.ack("g", &[(1, 2), (1, 3), (9, 9)]), 2);
+        let read = s
+            .read_group("g", "a", Some((0, 0)), None, false, 0)
+            .unwrap();
+        assert_eq!(ids(read), vec![(1, 1)]);
+        assert_eq!(s.groups["g"].pending.len(), 1);
+
+        assert!(s.destroy_group("g"));
+        assert!(!s.destroy_group("g"));
+    }
+
+    #[test]
+    fn test_group_lag() {
+        let mut s = Stream::new();
+        assert!(s.create_group("empty".into(), StreamId::new(0, 0)).is_ok());
+        assert_eq!(s.group_lag(&s.groups["empty"]), Some(0));
+        for seq_id in 1..=5 {
+            assert!(s.add_entry(1, seq_id, vec![]).is_ok());
         }
-        Ok(Value::Array(array))
+        s.trim(&TrimOptions {
+            strategy: TrimStrategy::MaxLen(3),
+            limit: None,
+        });
+        assert!(s.create_group("g".into(), StreamId::new(0, 0)).is_ok());
+        assert!(s.create_group("new".into(), StreamId::Auto).is_ok());
+        assert_eq!(s.groups["g"].entries_read, None);
+        assert_eq!(s.group_lag(&s.groups["g"]), Some(3));
+        assert_eq!(s.group_lag(&s.groups["new"]), Some(0));
+
+        // Records trimmed before are counted as read.
+        s.read_group("g", "a", None, Some(1), true, 0).unwrap();
+        assert_eq!(s.groups["g"].entries_read, Some(3));
+        assert_eq!(s.group_lag(&s.groups["g"]), Some(2));
+        s.read_group("g", "a", None, None, true, 0).unwrap();
+        assert_eq!(s.groups["g"].entries_read, Some(5));
+        assert!(s.add_entry(2, 0, vec![]).is_ok());
+        assert_eq!(s.group_lag(&s.groups["g"]), Some(1));
+        assert_eq!(s.group_lag(&s.groups["new"]), Some(1));
+
+        // Unknown after records deleted in the middle.
+        assert!(s.set_id((2, 0), None, Some((1, 4))).is_ok());
+        assert_eq!(s.group_lag(&s.groups["empty"]), None);
+        s.read_group("empty", "a", None, Some(1), true, 0).unwrap();
+        assert_eq!(s.groups["empty"].entries_read, None);
+        s.read_group("empty", "a", None, None, true, 0).unwrap();
+        assert_eq!(s.groups["empty"].entries_read, Some(6));
+        assert_eq!(s.group_lag(&s.groups["empty"]), Some(0));
+    }
+
+    #[test]
+    fn test_get_range() {
+        let mut s = Stream::new();
+        for (time_id, seq_id) in [(1, 1), (1, 5), (2, 0), (3, 2)] {
+            assert!(s.add_entry(time_id, seq_id, vec![]).is_ok());
+        }
+        let ids = |value: Value| match value {
+            Value::Array(mut arr) => arr
+                .take()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|x| match x {
+                    Value::Array(mut x) => match x.pop_front() {
+                        Some(Value::SimpleString(id)) => id.value().to_string(),
+                        v => panic!("unexpected record id {v:?}"),
+                    },
+                    v => panic!("unexpected record {v:?}"),
+                })
+                .collect::<Vec<_>>(),
+            v => panic!("unexpected range {v:?}"),
+        };
+        assert_eq!(
+            ids(s.get_range((1, 2), (3, 0), None, false)),
+            vec!["1-5", "2-0"]
+        );
+        assert_eq!(
+            ids(s.get_range((0, 0), (u64::MAX, u64::MAX), Some(3), true)),
+            vec!["3-2", "2-0", "1-5"]
+        );
+        assert!(ids(s.get_range((3, 0), (1, 0), None, false)).is_empty());
+    }
+
+    #[test]
+    fn test_claim() {
+        let mut s = Stream::new();
+        for seq_id in 1..=4 {
+            assert!(s.add_entry(1, seq_id, vec![]).is_ok());
+        }
+        assert!(s.create_group("g".into(), StreamId::new(0, 0)).is_ok());
+        s.read_group("g", "a", None, Some(3), false, 100).unwrap();
+        s.read_group("g", "b", None, None, false, 200).unwrap();
+
+        let (count, bounds, consumers) = s.pending_summary("g").unwrap();
+        assert_eq!(count, 4);
+        assert_eq!(bounds, Some(((1, 1), (1, 4))));
+        assert_eq!(consumers, vec![("a".into(), 3), ("b".into(), 1)]);
+        let pending = s
+            .pending_range("g", (0, 0), (9, 9), 10, 150, None, 300)
+            .unwrap();
+        assert_eq!(pending.len(), 3);
+        let pending = s
+            .pending_range("g", (0, 0), (9, 9), 10, 0, Some("b"), 300)
+            .unwrap();
+        assert_eq!(pending[0].0, (1, 4));
+
+        // Records not idle long enough are not claimed.
+        let ids = [(1, 1), (1, 4), (1, 9)];
+        let (claimed, deleted) = s
+            .claim("g", "b", 150, &ids, &ClaimOptions::default(), 300)
+            .unwrap();
+        assert!(deleted.is_empty());
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].id, (1, 1));
+        assert_eq!(claimed[0].delivery_count, 2);
+        assert_eq!(s.groups["g"].pending[&(1, 1)].consumer, "b");
+        assert!(!s.groups["g"].consumers["a"].contains(&(1, 1)));
+
+        // Deleted records are removed from the PEL.
+        s.entries.get_mut(&1).unwrap().data.remove(&2);
+        let (next, claimed, deleted) = s.auto_claim("g", "c", 0, (1, 2), 1, true, 300).unwrap();
+        assert_eq!(next, (1, 4));
+        assert_eq!(claimed[0].id, (1, 3));
+        assert_eq!(claimed[0].delivery_count, 1);
+        assert_eq!(deleted, vec![(1, 2)]);
+        assert_eq!(s.pending_summary("g").unwrap().0, 3);
     }
 }
@@ -0,0 +1,246 @@
+//! Client side caching support, set by CLIENT TRACKING.
+//!
+//! Connections tracking keys are notified with invalidation messages when keys they
+//! are interested in are modified:
+//!
+//! * Default mode: keys read by the connection, each key is notified once after read.
+//! * Broadcasting mode: all keys starting with any of the prefixes, or all keys if no
+//!   prefix set.
+//!
+//! Messages are delivered as RESP3 push data through the push channel of the target
+//! connection, which also carries other out of band data. Connections using RESP2
+//! drop push data.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use serde_redis::{Array, BulkString, Null, Push, Value};
+use tokio::sync::mpsc;
+
+/// Options of a connection tracking keys.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct TrackingOptions {
+    /// Id of the connection to send invalidation messages to, instead of the
+    /// connection itself.
+    pub(crate) redirect: Option<usize>,
+
+    /// Broadcasting mode.
+    pub(crate) bcast: bool,
+
+    /// Key prefixes to notify in broadcasting mode.
+    pub(crate) prefixes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TrackingState {
+    inner: Arc<Mutex<TrackingInner>>,
+}
+
+#[derive(Debug, Default)]
+struct TrackingInner {
+    /// Push channels of connections, by connection id.
+    channels: HashMap<usize, mpsc::UnboundedSender<Push>>,
+
+    /// Connections tracking keys, by connection id.
+    clients: HashMap<usize, TrackingOptions>,
+
+    /// Connections that read the key in default mode.
+    keys: HashMap<Vec<u8>, HashSet<usize>>,
+}
+
+impl TrackingState {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the push channel of connection `id`.
+    ///
+    /// Return the receiver of all push data sent to the connection.
+    pub(crate) fn register(&self, id: usize) -> mpsc::UnboundedReceiver<Push> {
+        let (sender, recver) = mpsc::unbounded_channel();
+        self.inner.lock().unwrap().channels.insert(id, sender);
+        recver
+    }
+
+    /// Remove all states of connection `id`, called when the connection closed.
+    pub(crate) fn unregister(&self, id: usize) {
+        let mut lock = self.inner.lock().unwrap();
+        lock.channels.remove(&id);
+        lock.untrack(id);
+    }
+
+    /// Check whether connection `id` has a push channel.
+    pub(crate) fn is_connected(&self, id: usize) -> bool {
+        self.inner.lock().unwrap().channels.contains_key(&id)
+    }
+
+    /// Enable tracking on connection `id`, replace the options if already enabled.
+    pub(crate) fn enable(&self, id: usize, options: TrackingOptions) {
+        let mut lock = self.inner.lock().unwrap();
+        lock.untrack(id);
+        lock.clients.insert(id, options);
+    }
+
+    /// Disable tracking on connection `id`.
+    pub(crate) fn disable(&self, id: usize) {
+        self.inner.lock().unwrap().untrack(id);
+    }
+
+    /// Get the tracking options of connection `id`, `None` if tracking is off.
+    pub(crate) fn options(&self, id: usize) -> Option<TrackingOptions> {
+        self.inner.lock().unwrap().clients.get(&id).cloned()
+    }
+
+    /// Record `keys` read by connection `id`, if it's tracking keys in default mode.
+    pub(crate) fn track_keys(&self, id: usize, keys: Vec<Vec<u8>>) {
+        let mut lock = self.inner.lock().unwrap();
+        if lock.clients.get(&id).is_none_or(|x| x.bcast) {
+            return;
+        }
+        for key in keys {
+            lock.keys.entry(key).or_default().insert(id);
+        }
+    }
+
+    /// Notify connections tracking `key` that it's modified.
+    pub(crate) fn invalidate(&self, key: &[u8]) {
+        let mut lock = self.inner.lock().unwrap();
+        if lock.clients.is_empty() {
+            return;
+        }
+        let mut ids = lock.keys.remove(key).unwrap_or_default();
+        ids.extend(
+            lock.clients
+                .iter()
+                .filter(|(_, x)| {
+                    x.bcast
+                        && (x.prefixes.is_empty()
+                            || x.prefixes.iter().any(|p| key.starts_with(p.as_bytes())))
+                })
+                .map(|(id, _)| *id),
+        );
+        for id in ids {
+            let target = match lock.clients.get(&id) {
+                Some(options) => options.redirect.unwrap_or(id),
+                None => continue,
+            };
+            let message = Push::new(vec![
+                Value::BulkString(BulkString::new("invalidate")),
+                Value::Array(Array::with_values(vec![Value::BulkString(
+                    BulkString::new(key),
+                )])),
+            ]);
+            lock.push(target, message);
+        }
+    }
+
+    /// Notify all connections tracking keys that all keys are modified, when the
+    /// keyspace is flushed.
+    ///
+    /// Like redis, the message carries null instead of keys.
+    pub(crate) fn invalidate_all(&self) {
+        let mut lock = self.inner.lock().unwrap();
+        lock.keys.clear();
+        let targets = lock
+            .clients
+            .iter()
+            .map(|(id, options)| options.redirect.unwrap_or(*id))
+            .collect::<HashSet<_>>();
+        for target in targets {
+            let message = Push::new(vec![
+                Value::BulkString(BulkString::new("invalidate")),
+                Value::Null(Null),
+            ]);
+            lock.push(target, message);
+        }
+    }
+}
+
+impl TrackingInner {
+    fn push(&self, id: usize, value: Push) -> bool {
+        match self.channels.get(&id) {
+            Some(sender) => sender.send(value).is_ok(),
+            None => false,
+        }
+    }
+
+    fn untrack(&mut self, id: usize) {
+        if self.clients.remove(&id).is_none() {
+            return;
+        }
+        self.keys.retain(|_, ids| {
+            ids.remove(&id);
+            !ids.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn invalidated(recver: &mut mpsc::UnboundedReceiver<Push>) -> Vec<String> {
+        let mut keys = vec![];
+        while let Ok(push) = recver.try_recv() {
+            match push.into_values().pop() {
+                Some(Value::Array(mut arr)) => keys.push(arr.pop_front_bulk_string().unwrap()),
+                v => panic!("unexpected invalidation message {v:?}"),
+            }
+        }
+        keys
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let tracking = TrackingState::new();
+        let mut recver1 = tracking.register(1);
+        let mut recver2 = tracking.register(2);
+
+        // Default mode, redirect to connection 2.
+        tracking.enable(
+            1,
+            TrackingOptions {
+                redirect: Some(2),
+                ..Default::default()
+            },
+        );
+        tracking.track_keys(1, vec![b"a".to_vec(), b"b".to_vec()]);
+        tracking.invalidate(b"a");
+        tracking.invalidate(b"a");
+        assert!(invalidated(&mut recver1).is_empty());
+        assert_eq!(invalidated(&mut recver2), vec!["a"]);
+
+        // Broadcasting mode with prefix.
+        tracking.enable(
+            1,
+            TrackingOptions {
+                bcast: true,
+                prefixes: vec!["user:".into()],
+                ..Default::default()
+            },
+        );
+        tracking.invalidate(b"b");
+        tracking.invalidate(b"user:1");
+        tracking.invalidate(b"user:1");
+        assert_eq!(invalidated(&mut recver1), vec!["user:1", "user:1"]);
+
+        // Flushed, connections in both modes are notified once.
+        tracking.enable(2, TrackingOptions::default());
+        tracking.track_keys(2, vec![b"a".to_vec()]);
+        tracking.invalidate_all();
+        for recver in [&mut recver1, &mut recver2] {
+            let values = recver.try_recv().unwrap().into_values();
+            assert_eq!(values.get(1), Some(&Value::Null(Null)));
+            assert!(recver.try_recv().is_err());
+        }
+        tracking.invalidate(b"a");
+        assert!(invalidated(&mut recver2).is_empty());
+
+        tracking.unregister(1);
+        tracking.invalidate(b"user:1");
+        assert!(tracking.options(1).is_none());
+        assert!(invalidated(&mut recver2).is_empty());
+    }
+}
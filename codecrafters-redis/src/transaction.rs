@@ -29,7 +29,13 @@ pub(crate) enum Transaction {
     /// Inside a transaction process, now it's recording
     /// all incoming `TransactionEvent`s and waiting for
     /// submit, which usually an `EXEC` command.
-    Pending(Vec<TransactionEvent>),
+    Pending {
+        events: Vec<TransactionEvent>,
+
+        /// A command failed when queueing, e.g. unknown command, the transaction
+        /// shall be discarded by `EXEC`.
+        dirty: bool,
+    },
 
     /// Excuting commands. This state only occurs when submitting a transaction.
     ///
@@ -56,28 +62,33 @@ impl Transaction {
     pub fn is_pending(&self) -> bool {
         match self {
             Transaction::None | Transaction::Executing(..) => false,
-            Transaction::Pending(..) => true,
+            Transaction::Pending { .. } => true,
         }
     }
 
     pub fn is_executing(&self) -> bool {
         match self {
-            Transaction::None | Transaction::Pending(..) => false,
+            Transaction::None | Transaction::Pending { .. } => false,
             Transaction::Executing(..) => true,
         }
     }
 
     pub fn start(&mut self) {
         match self {
-            Transaction::None => *self = Transaction::Pending(vec![]),
+            Transaction::None => {
+                *self = Transaction::Pending {
+                    events: vec![],
+                    dirty: false,
+                }
+            }
             _ => unreachable!("only start a transaction when it's inactive"),
         }
     }
 
     pub fn commit(&mut self) -> Vec<TransactionEvent> {
         match self {
-            Transaction::Pending(cmdlines) => {
-                let events = std::mem::replace(cmdlines, vec![]);
+            Transaction::Pending { events, .. } => {
+                let events = std::mem::take(events);
                 *self = Transaction::Executing(vec![]);
                 events
             }
@@ -88,7 +99,7 @@ impl Transaction {
     pub fn finish(&mut self) -> Vec<Value> {
         match self {
             Transaction::Executing(result) => {
-                let result = std::mem::replace(result, vec![]);
+                let result = std::mem::take(result);
                 *self = Transaction::None;
                 result
             }
@@ -99,12 +110,31 @@ impl Transaction {
     pub fn record_result(&mut self, value: Value) {
         match self {
             Transaction::Executing(buf) => buf.push(value),
-            Transaction::None | Transaction::Pending(..) => {
+            Transaction::None | Transaction::Pending { .. } => {
                 unreachable!("only record result when executing")
             }
         }
     }
 
+    /// Mark the pending transaction as failed when queueing.
+    pub fn flag_dirty(&mut self) {
+        if let Transaction::Pending { dirty, .. } = self {
+            *dirty = true;
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        matches!(self, Transaction::Pending { dirty: true, .. })
+    }
+
+    /// Count of commands queued, `None` if not pending.
+    pub fn queued_count(&self) -> Option<usize> {
+        match self {
+            Transaction::Pending { events, .. } => Some(events.len()),
+            Transaction::None | Transaction::Executing(..) => None,
+        }
+    }
+
     pub fn abort(&mut self) {
         *self = Transaction::None
     }
@@ -0,0 +1,154 @@
+//! Chaos tests of replication.
+//!
+//! Start a master and two replicas in process, apply a concurrent write workload
+//! on master, kill and restart nodes in the middle, then check all nodes end with
+//! the same keyspace by comparing DEBUG DIGEST.
+//!
+//! These tests take seconds and bind local ports, run them with:
+//!
+//! ```shell
+//! cargo test -p codecrafters-redis --features chaos-test --test chaos
+//! ```
+#![cfg(feature = "chaos-test")]
+
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use codecrafters_redis::{Handle, ServerBuilder, Value};
+use tokio::task::JoinSet;
+
+/// Count of concurrent writers in workload.
+const WRITERS: usize = 4;
+
+/// How long to wait for replicas to catch up with master.
+const CONVERGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn start_master(port: u16) -> Handle {
+    ServerBuilder::new().port(port).start().await.unwrap()
+}
+
+async fn start_replica(master: SocketAddr) -> Handle {
+    ServerBuilder::new()
+        .port(0)
+        .replicaof(Some((Ipv4Addr::LOCALHOST, master.port())))
+        .start()
+        .await
+        .unwrap()
+}
+
+async fn digest(handle: &Handle) -> String {
+    match handle.execute(["DEBUG", "DIGEST"]).await.unwrap() {
+        Value::SimpleString(s) => s.value().to_string(),
+        v => panic!("unexpected DEBUG DIGEST reply {v:?}"),
+    }
+}
+
+/// Write `ops` rounds on `master` from `WRITERS` clients concurrently.
+///
+/// Each writer owns its keys, except a counter shared by all of them, so the final
+/// keyspace does not depend on how writes interleave.
+async fn workload(master: &Handle, round: usize, ops: usize) {
+    let mut writers = JoinSet::new();
+    for w in 0..WRITERS {
+        let client = master.client();
+        writers.spawn(async move {
+            for i in 0..ops {
+                let value = format!("{round}-{i}");
+                client
+                    .set(format!("w{w}:k{}", i % 16), &value)
+                    .await
+                    .unwrap();
+                client.rpush(format!("w{w}:list"), [&value]).await.unwrap();
+                client.incr("counter").await.unwrap();
+                if i % 8 == 0 {
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
+    }
+    while let Some(v) = writers.join_next().await {
+        v.unwrap();
+    }
+}
+
+/// Wait till all `replicas` have the same digest as `master`.
+async fn assert_converged(master: &Handle, replicas: &[Handle]) {
+    let expected = digest(master).await;
+    assert_ne!(expected, "0".repeat(40), "workload wrote nothing");
+    let deadline = tokio::time::Instant::now() + CONVERGE_TIMEOUT;
+    for (i, replica) in replicas.iter().enumerate() {
+        loop {
+            let actual = digest(replica).await;
+            if actual == expected {
+                break;
+            }
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "replica {i} not converged: digest {actual}, master {expected}"
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_converge_under_concurrent_writes() {
+    let master = start_master(0).await;
+    let replicas = vec![
+        start_replica(master.local_addr()).await,
+        start_replica(master.local_addr()).await,
+    ];
+
+    workload(&master, 0, 200).await;
+    assert_converged(&master, &replicas).await;
+
+    for replica in replicas {
+        replica.shutdown().await;
+    }
+    master.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_restart_replica_during_writes() {
+    let master = start_master(0).await;
+    let mut replicas = vec![
+        start_replica(master.local_addr()).await,
+        start_replica(master.local_addr()).await,
+    ];
+
+    let kill = async {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        replicas.remove(0).shutdown().await;
+        replicas.push(start_replica(master.local_addr()).await);
+    };
+    tokio::join!(workload(&master, 0, 500), kill);
+    workload(&master, 1, 100).await;
+    assert_converged(&master, &replicas).await;
+
+    for replica in replicas {
+        replica.shutdown().await;
+    }
+    master.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_restart_master_during_writes() {
+    let master = start_master(0).await;
+    let addr = master.local_addr();
+    let replicas = vec![start_replica(addr).await, start_replica(addr).await];
+
+    workload(&master, 0, 200).await;
+    master.shutdown().await;
+
+    // Come back on the same port, replicas shall follow the new dataset.
+    let master = start_master(addr.port()).await;
+    workload(&master, 1, 200).await;
+    assert_converged(&master, &replicas).await;
+
+    for replica in replicas {
+        replica.shutdown().await;
+    }
+    master.shutdown().await;
+}
@@ -1,9 +1,12 @@
 use serde::{de::Visitor, Deserialize, Serialize};
 
-use crate::utils::bytes_to_num;
-
 pub(super) const KEY_BULK_STRING_NULL: &'static str = "serde_redis::BulkString::Null";
 
+/// Size of the big endian length the decoder prefixes to bulk string bytes.
+///
+/// Bytes shorter than the prefix are a null bulk string.
+pub(crate) const BULK_STRING_LENGTH_PREFIX: usize = std::mem::size_of::<u64>();
+
 /// Bulk string in RESP.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BulkString(Option<Vec<u8>>);
@@ -43,19 +46,20 @@ impl<'de> Visitor<'de> for BulkStringVisitor {
     where
         E: serde::de::Error,
     {
-        if v.len() < 4 {
+        if v.len() < BULK_STRING_LENGTH_PREFIX {
             // Null
             Ok(BulkString::null())
         } else {
-            let len = bytes_to_num(&v[..4]) as usize;
-            if v.len() != len + 4 {
+            let (prefix, value) = v.split_at(BULK_STRING_LENGTH_PREFIX);
+            let len = u64::from_be_bytes(prefix.try_into().unwrap()) as usize;
+            if value.len() != len {
                 Err(serde::de::Error::custom(format!(
                     "invalid bulk string length produced by deserializer: expected {}, got {}",
                     len,
-                    v.len() - 4
+                    value.len()
                 )))
             } else {
-                Ok(BulkString::new(v.into_iter().skip(4).collect::<Vec<u8>>()))
+                Ok(BulkString::new(value))
             }
         }
     }
@@ -106,6 +110,10 @@ mod test {
 
         let v6: BulkString = from_bytes(b"$-1\r\n").unwrap();
         assert!(v6.is_null());
+
+        let v7 = BulkString::new(vec![b'x'; 12345]);
+        let v8: BulkString = from_bytes(&to_vec(&v7).unwrap()).unwrap();
+        assert_eq!(v7, v8);
     }
 
     #[test]
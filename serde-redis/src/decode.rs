@@ -4,6 +4,7 @@ use bytes::Buf;
 use serde::de::SeqAccess;
 
 use crate::{
+    bulk_string::BULK_STRING_LENGTH_PREFIX,
     error::{RdError, RdResult},
     utils::bytes_to_num,
     KEY_VALUE_ENUM,
@@ -245,22 +246,13 @@ impl<'de> Decoder<'de> {
             });
         }
 
-        let mut length = self.cursor.collect_over_crlf();
+        let length = self.cursor.collect_over_crlf();
 
         // Null
         if length.len() == 2 && length[0] == b'-' && length[1] == b'1' {
             return Ok(vec![]);
         }
 
-        // Empty
-        if length.len() == 1 && length[0] == b'0' {
-            return Ok(vec![0, 0, 0, 0]);
-        }
-
-        while length.len() < 4 {
-            length.insert(0, 0);
-        }
-
         let mut buf = vec![0u8; bytes_to_num(length.as_slice()) as usize];
         self.cursor
             .read_exact(&mut buf)
@@ -273,8 +265,9 @@ impl<'de> Decoder<'de> {
             });
         }
 
-        let mut ret = Vec::with_capacity(4 + buf.len());
-        ret.append(&mut length);
+        // Prefix the length so that empty is told from null, see `BulkStringVisitor`.
+        let mut ret = Vec::with_capacity(BULK_STRING_LENGTH_PREFIX + buf.len());
+        ret.extend_from_slice(&(buf.len() as u64).to_be_bytes());
         ret.append(&mut buf);
         Ok(ret)
     }
@@ -1,5 +1,6 @@
 use crate::{
-    bulk_string::KEY_BULK_STRING_NULL, simple_error::KEY_SIMPLE_ERROR, utils::num_to_bytes,
+    bulk_string::KEY_BULK_STRING_NULL, push::KEY_PUSH, simple_error::KEY_SIMPLE_ERROR,
+    utils::num_to_bytes, verbatim_string::KEY_VERBATIM_STRING,
 };
 
 use super::error::{RdError, RdResult};
@@ -60,6 +61,18 @@ impl Encoder {
         self.append_crlf();
     }
 
+    fn encode_push_prefix(&mut self, len: usize) {
+        self.output.push(b'>');
+        self.output.append(&mut num_to_bytes(len as i64));
+        self.append_crlf();
+    }
+
+    fn encode_map_prefix(&mut self, len: usize) {
+        self.output.push(b'%');
+        self.output.append(&mut num_to_bytes(len as i64));
+        self.append_crlf();
+    }
+
     fn encode_simple_error_prefix(&mut self) {
         self.output.push(b'-');
     }
@@ -190,6 +203,12 @@ impl<'a> serde::ser::Serializer for &'a mut Encoder {
             // Null bulk string.
             self.encode_bulk_string(None);
             Ok(())
+        } else if name == KEY_VERBATIM_STRING {
+            // Framed the same as bulk string, only the type byte differs.
+            let start = self.output.len();
+            _value.serialize(&mut *self)?;
+            self.output[start] = b'=';
+            Ok(())
         } else {
             todo!()
         }
@@ -220,10 +239,15 @@ impl<'a> serde::ser::Serializer for &'a mut Encoder {
 
     fn serialize_tuple_struct(
         self,
-        _name: &'static str,
-        _len: usize,
+        name: &'static str,
+        len: usize,
     ) -> Result<Self::SerializeTupleStruct, Self::Error> {
-        todo!()
+        if name == KEY_PUSH {
+            self.encode_push_prefix(len);
+            Ok(self)
+        } else {
+            todo!()
+        }
     }
 
     fn serialize_tuple_variant(
@@ -236,8 +260,10 @@ impl<'a> serde::ser::Serializer for &'a mut Encoder {
         todo!()
     }
 
-    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
-        todo!()
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        // Map.
+        self.encode_map_prefix(len.unwrap_or_default());
+        Ok(self)
     }
 
     fn serialize_struct(
@@ -305,15 +331,16 @@ impl<'a> serde::ser::SerializeTupleStruct for &'a mut Encoder {
 
     type Error = RdError;
 
-    fn serialize_field<T>(&mut self, _value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + serde::Serialize,
     {
-        todo!()
+        // Element in push.
+        value.serialize(&mut **self)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        todo!()
+        Ok(())
     }
 }
 
@@ -339,22 +366,22 @@ impl<'a> serde::ser::SerializeMap for &'a mut Encoder {
 
     type Error = RdError;
 
-    fn serialize_key<T>(&mut self, _key: &T) -> Result<(), Self::Error>
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + serde::Serialize,
     {
-        todo!()
+        key.serialize(&mut **self)
     }
 
-    fn serialize_value<T>(&mut self, _value: &T) -> Result<(), Self::Error>
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
         T: ?Sized + serde::Serialize,
     {
-        todo!()
+        value.serialize(&mut **self)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        todo!()
+        Ok(())
     }
 }
 
@@ -4,10 +4,13 @@ mod decode;
 mod encode;
 mod error;
 mod integer;
+mod map;
 mod null;
+mod push;
 mod simple_error;
 mod simple_string;
 mod utils;
+mod verbatim_string;
 
 const KEY_VALUE_ENUM: &'static str = "serde_redis::Value";
 
@@ -19,10 +22,13 @@ pub use decode::{from_bytes, from_bytes_len};
 pub use encode::to_vec;
 pub use error::RdError;
 pub use integer::Integer;
+pub use map::Map;
 pub use null::Null;
+pub use push::Push;
 pub use simple_error::SimpleError;
 pub use simple_string::SimpleString;
 pub use utils::num_to_bytes;
+pub use verbatim_string::VerbatimString;
 
 use crate::{
     array::ArrayVisitor, bulk_string::BulkStringVisitor, integer::IntegerVisitor,
@@ -42,6 +48,15 @@ pub enum Value {
     BulkString(BulkString),
     Array(Array),
     Null(Null),
+
+    /// Only used in RESP3.
+    Map(Map),
+
+    /// Only used in RESP3.
+    Push(Push),
+
+    /// Only used in RESP3.
+    VerbatimString(VerbatimString),
 }
 
 impl Value {
@@ -53,6 +68,33 @@ impl Value {
             Value::BulkString(..) => "string",
             Value::Array(..) => "list",
             Value::Null(..) => "null",
+            Value::Map(..) => "map",
+            Value::Push(..) => "push",
+            Value::VerbatimString(..) => "string",
+        }
+    }
+
+    /// Convert RESP3 types to the RESP2 equivalent, for clients using RESP2.
+    ///
+    /// Maps are flattened into arrays of keys and values, push data becomes arrays, and
+    /// verbatim strings become bulk strings without the format.
+    pub fn into_resp2(self) -> Value {
+        match self {
+            Value::Array(mut v) => match v.take() {
+                Some(values) => Value::Array(values.into_iter().map(Value::into_resp2).collect()),
+                None => Value::Array(v),
+            },
+            Value::Map(v) => Value::Array(
+                v.into_entries()
+                    .into_iter()
+                    .flat_map(|(key, value)| [key.into_resp2(), value.into_resp2()])
+                    .collect(),
+            ),
+            Value::Push(v) => {
+                Value::Array(v.into_values().into_iter().map(Value::into_resp2).collect())
+            }
+            Value::VerbatimString(v) => Value::BulkString(BulkString::new(v.into_data())),
+            v => v,
         }
     }
 }
@@ -158,6 +200,9 @@ impl Serialize for Value {
             Value::BulkString(v) => v.serialize(serializer),
             Value::Array(v) => v.serialize(serializer),
             Value::Null(v) => v.serialize(serializer),
+            Value::Map(v) => v.serialize(serializer),
+            Value::Push(v) => v.serialize(serializer),
+            Value::VerbatimString(v) => v.serialize(serializer),
         }
     }
 }
@@ -0,0 +1,81 @@
+use serde::{ser::SerializeMap, Serialize};
+
+use crate::Value;
+
+/// Map in RESP3, an ordered sequence of key-value pairs.
+///
+/// Only serializing is supported.
+///
+/// ## Format
+///
+/// `%<number-of-entries>\r\n<key-1><value-1>...<key-n><value-n>`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Map(Vec<(Value, Value)>);
+
+impl Map {
+    pub fn new() -> Self {
+        Self(vec![])
+    }
+
+    pub fn with_entries(entries: impl Into<Vec<(Value, Value)>>) -> Self {
+        Self(entries.into())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Append an entry, existing entry with the same key is not replaced.
+    pub fn push(&mut self, key: Value, value: Value) {
+        self.0.push((key, value));
+    }
+
+    pub fn entries(&self) -> &Vec<(Value, Value)> {
+        &self.0
+    }
+
+    pub fn into_entries(self) -> Vec<(Value, Value)> {
+        self.0
+    }
+}
+
+impl Serialize for Map {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut s = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in self.0.iter() {
+            s.serialize_entry(key, value)?;
+        }
+        s.end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{to_vec, BulkString, Integer};
+
+    use super::*;
+
+    #[test]
+    fn test_encode_map() {
+        let mut v1 = Map::new();
+        v1.push(
+            Value::BulkString(BulkString::new("proto")),
+            Value::Integer(Integer::new(3)),
+        );
+        v1.push(
+            Value::BulkString(BulkString::new("mode")),
+            Value::BulkString(BulkString::new("standalone")),
+        );
+        assert_eq!(
+            to_vec(&v1).unwrap(),
+            b"%2\r\n$5\r\nproto\r\n:3\r\n$4\r\nmode\r\n$10\r\nstandalone\r\n"
+        );
+    }
+}
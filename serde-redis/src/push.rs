@@ -0,0 +1,71 @@
+use serde::{ser::SerializeTupleStruct, Serialize};
+
+use crate::Value;
+
+pub(crate) const KEY_PUSH: &str = "serde_redis::Push";
+
+/// Push data in RESP3, sent by server out of band of replies.
+///
+/// Only serializing is supported, clients never send push data.
+///
+/// ## Format
+///
+/// `><number-of-elements>\r\n<element-1>...<element-n>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Push(Vec<Value>);
+
+impl Push {
+    pub fn new(values: impl Into<Vec<Value>>) -> Self {
+        Self(values.into())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn values(&self) -> &Vec<Value> {
+        &self.0
+    }
+
+    pub fn into_values(self) -> Vec<Value> {
+        self.0
+    }
+}
+
+impl Serialize for Push {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut s = serializer.serialize_tuple_struct(KEY_PUSH, self.0.len())?;
+        for ele in self.0.iter() {
+            s.serialize_field(ele)?;
+        }
+        s.end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{to_vec, Array, BulkString};
+
+    use super::*;
+
+    #[test]
+    fn test_encode_push() {
+        let v1 = Push::new(vec![
+            Value::BulkString(BulkString::new("invalidate")),
+            Value::Array(Array::with_values(vec![Value::BulkString(
+                BulkString::new("foo"),
+            )])),
+        ]);
+        assert_eq!(
+            to_vec(&v1).unwrap(),
+            b">2\r\n$10\r\ninvalidate\r\n*1\r\n$3\r\nfoo\r\n"
+        );
+    }
+}
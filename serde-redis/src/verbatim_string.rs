@@ -0,0 +1,92 @@
+use serde::Serialize;
+
+pub(crate) const KEY_VERBATIM_STRING: &str = "serde_redis::VerbatimString";
+
+/// Verbatim string in RESP3, a bulk string with the format of its content.
+///
+/// Only serializing is supported. RESP2 clients receive the content as a bulk string.
+///
+/// ## Format
+///
+/// `=<length>\r\n<format>:<data>\r\n`
+///
+/// `format` is three bytes, "txt" for plain text or "mkd" for markdown, and `length`
+/// counts the format and the colon.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerbatimString {
+    format: [u8; 3],
+    data: Vec<u8>,
+}
+
+impl VerbatimString {
+    /// Create a verbatim string of plain text.
+    pub fn text(data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            format: *b"txt",
+            data: data.into(),
+        }
+    }
+
+    /// Create a verbatim string of markdown.
+    pub fn markdown(data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            format: *b"mkd",
+            data: data.into(),
+        }
+    }
+
+    pub fn format(&self) -> &[u8; 3] {
+        &self.format
+    }
+
+    pub fn data(&self) -> &Vec<u8> {
+        &self.data
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Content of verbatim string, serialized as bytes.
+struct Content<'a>(&'a [u8]);
+
+impl Serialize for Content<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+impl Serialize for VerbatimString {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut content = Vec::with_capacity(self.data.len() + 4);
+        content.extend_from_slice(&self.format);
+        content.push(b':');
+        content.extend_from_slice(&self.data);
+        serializer.serialize_newtype_struct(KEY_VERBATIM_STRING, &Content(&content))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{to_vec, Value};
+
+    use super::*;
+
+    #[test]
+    fn test_encode_verbatim_string() {
+        let v1 = VerbatimString::text("Some string");
+        assert_eq!(to_vec(&v1).unwrap(), b"=15\r\ntxt:Some string\r\n");
+        let v2 = VerbatimString::markdown("");
+        assert_eq!(to_vec(&v2).unwrap(), b"=4\r\nmkd:\r\n");
+
+        let v3 = Value::VerbatimString(VerbatimString::text("Some string")).into_resp2();
+        assert_eq!(to_vec(&v3).unwrap(), b"$11\r\nSome string\r\n");
+    }
+}